@@ -0,0 +1,276 @@
+//! 结算热点路径的基准测试：keccak、secp 恢复和建树各占多少时间
+//!
+//! cargo bench --bench settlement
+//!
+//! 所有数据由 EthAddressGen::from_seed 和 keypair_from_seed 确定性生成，不同机器上的结果可以直接比较。
+//! 运行结束后把各项的中位数和均值汇总到 target/criterion/summary.md
+
+use alloy_primitives::{B256, U256};
+use criterion::{criterion_group, BatchSize, BenchmarkId, Criterion, Throughput};
+use serde_json::Value;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::path::{Path, PathBuf};
+use zkpay_lib::ethaddr_gen::{keypair_from_seed, EthAddressGen};
+use zkpay_lib::models::u256_to_key;
+use zkpay_lib::receipts::{PaymentsGrouper, SettledReceiptBuilder};
+use zkpay_lib::{
+    get_ethereum_address, EthAddress, PayIdInfo, PaymentSettledByProxy, ReceiptsOverpayChecker, SegmentVC,
+};
+
+const SENDER_SEED: u64 = 1;
+const PROXY_SEED: u64 = 2;
+const PAY_IDS: u64 = 16;
+
+// receiver 分布：均匀、90% 集中在一个 receiver、全部同一个 receiver
+#[derive(Clone, Copy)]
+enum Skew {
+    Uniform,
+    Hot,
+    Single,
+}
+
+impl Skew {
+    const ALL: [Skew; 3] = [Skew::Uniform, Skew::Hot, Skew::Single];
+
+    fn name(self) -> &'static str {
+        match self {
+            Skew::Uniform => "uniform",
+            Skew::Hot => "hot90",
+            Skew::Single => "single",
+        }
+    }
+
+    fn receiver(self, index: usize) -> EthAddress {
+        let seed = match self {
+            Skew::Uniform => index % 100,
+            Skew::Hot if index % 10 != 0 => 0,
+            Skew::Hot => 1 + (index / 10) % 99,
+            Skew::Single => 0,
+        };
+        EthAddressGen::from_seed(1000 + seed as u64)
+    }
+}
+
+fn sorted_entries(count: usize) -> Vec<(B256, B256)> {
+    (0..count as u64)
+        .map(|i| (u256_to_key(U256::from(i)), B256::from(zkpay_lib::keccak256(&i.to_be_bytes()))))
+        .collect()
+}
+
+// 未签名的收据，只用于分组；serv_id 各不相同，保证 to_key() 不重复
+fn unsigned_payments(count: usize, skew: Skew) -> Vec<PaymentSettledByProxy> {
+    (0..count)
+        .map(|i| {
+            PaymentSettledByProxy::new(U256::from(i as u64 % PAY_IDS), i as u32, U256::from(i as u64 + 1), skew.receiver(i))
+                .with_settled(true)
+        })
+        .collect()
+}
+
+// 由发送者和代理签名的收据，以及对应的 PayIdInfo
+fn signed_receipts(count: usize) -> (EthAddress, Vec<PayIdInfo>, Vec<PaymentSettledByProxy>) {
+    let (sender_key, sender_public) = keypair_from_seed(SENDER_SEED);
+    let (proxy_key, proxy_public) = keypair_from_seed(PROXY_SEED);
+    let sender = get_ethereum_address(&sender_public);
+    let proxy = get_ethereum_address(&proxy_public);
+
+    let pay_id_infos = (0..PAY_IDS)
+        .map(|id| PayIdInfo {
+            id: U256::from(id),
+            amount: U256::from(u64::MAX),
+            sender,
+            proxy,
+            state: 1,
+            created_at: 0,
+            closing_time: 0,
+            token: [0u8; 20],
+            sig_sender: None,
+        })
+        .collect();
+
+    let receipts = (0..count)
+        .map(|i| {
+            let amount = U256::from(i as u64 + 1);
+            SettledReceiptBuilder::new(U256::from(i as u64 % PAY_IDS), i as u32, amount, Skew::Uniform.receiver(i))
+                .sign_sender(&sender_key)
+                .settle(amount, true)
+                .sign_proxy(&proxy_key)
+                .build()
+                .expect("sign receipt")
+        })
+        .collect();
+
+    (proxy, pay_id_infos, receipts)
+}
+
+fn bench_insert_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("segment_vc_insert_batch");
+    group.sample_size(10);
+    for count in [1_000usize, 10_000, 100_000] {
+        let entries = sorted_entries(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &entries, |b, entries| {
+            b.iter_batched(
+                || entries.clone(),
+                |entries| {
+                    let mut vc = SegmentVC::new_hash_only(entries.len());
+                    black_box(vc.insert_batch(entries).expect("insert_batch"))
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_group_by_receiver(c: &mut Criterion) {
+    let mut group = c.benchmark_group("group_by_receiver");
+    let count = 10_000;
+    group.throughput(Throughput::Elements(count as u64));
+    for skew in Skew::ALL {
+        let payments = unsigned_payments(count, skew);
+        group.bench_with_input(BenchmarkId::from_parameter(skew.name()), &payments, |b, payments| {
+            b.iter(|| black_box(PaymentsGrouper::group_by_receiver(payments).expect("group_by_receiver")))
+        });
+    }
+    group.finish();
+}
+
+fn bench_signature_recovery(c: &mut Criterion) {
+    let mut group = c.benchmark_group("receipt_signature_recovery");
+    let (_, _, receipts) = signed_receipts(1_000);
+    for batch in [10usize, 100, 1_000] {
+        group.throughput(Throughput::Elements(batch as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(batch), &receipts[..batch], |b, receipts| {
+            b.iter(|| {
+                for receipt in receipts {
+                    black_box(receipt.get_sender_address().expect("sender"));
+                    black_box(receipt.get_proxy_address().expect("proxy"));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_merkle_proof_verify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merkle_proof_verify");
+    for count in [16usize, 1_000, 100_000] {
+        let entries = sorted_entries(count);
+        let (key, value) = entries[count / 2];
+        let mut vc = SegmentVC::new_hash_only(count);
+        vc.insert_batch(entries).expect("insert_batch");
+        let mut proof = vc.generate_proof(key).expect("proof");
+        proof.value_proof.value = value;
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &proof, |b, proof| {
+            b.iter(|| black_box(proof.verify().expect("verify")))
+        });
+    }
+    group.finish();
+}
+
+fn bench_overpay_check(c: &mut Criterion) {
+    let mut group = c.benchmark_group("overpay_checker_process");
+    group.sample_size(10);
+    for count in [100usize, 1_000] {
+        let (proxy, pay_id_infos, receipts) = signed_receipts(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter_batched(
+                || (pay_id_infos.clone(), receipts.clone()),
+                |(pay_id_infos, receipts)| {
+                    let checker = ReceiptsOverpayChecker::new(proxy, pay_id_infos, receipts)
+                        .with_proxy_signature_check()
+                        .with_sender_signature_check();
+                    black_box(checker.process().expect("process"))
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// 与 criterion 使用同一个输出目录
+fn criterion_home() -> PathBuf {
+    if let Some(home) = std::env::var_os("CRITERION_HOME") {
+        return PathBuf::from(home);
+    }
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+    target.join("criterion")
+}
+
+// 每个基准的 new/ 目录下有 benchmark.json（名称）和 estimates.json（纳秒）
+fn collect_rows(dir: &Path, rows: &mut Vec<(String, f64, f64)>) -> io::Result<()> {
+    let latest = dir.join("new");
+    if let (Ok(benchmark), Ok(estimates)) = (
+        fs::read_to_string(latest.join("benchmark.json")),
+        fs::read_to_string(latest.join("estimates.json")),
+    ) {
+        let benchmark: Value = serde_json::from_str(&benchmark).map_err(io::Error::other)?;
+        let estimates: Value = serde_json::from_str(&estimates).map_err(io::Error::other)?;
+        let id = benchmark["full_id"].as_str().unwrap_or_default().to_string();
+        let median = estimates["median"]["point_estimate"].as_f64().unwrap_or_default();
+        let mean = estimates["mean"]["point_estimate"].as_f64().unwrap_or_default();
+        rows.push((id, median, mean));
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path.file_name().is_none_or(|name| name != "report") {
+            collect_rows(&path, rows)?;
+        }
+    }
+    Ok(())
+}
+
+fn format_ns(ns: f64) -> String {
+    if ns >= 1e9 {
+        format!("{:.2} s", ns / 1e9)
+    } else if ns >= 1e6 {
+        format!("{:.2} ms", ns / 1e6)
+    } else if ns >= 1e3 {
+        format!("{:.2} µs", ns / 1e3)
+    } else {
+        format!("{:.0} ns", ns)
+    }
+}
+
+fn write_summary() -> io::Result<PathBuf> {
+    let home = criterion_home();
+    let mut rows = Vec::new();
+    collect_rows(&home, &mut rows)?;
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut table = String::from("| benchmark | median | mean |\n|---|---|---|\n");
+    for (id, median, mean) in rows {
+        table.push_str(&format!("| {} | {} | {} |\n", id, format_ns(median), format_ns(mean)));
+    }
+    let path = home.join("summary.md");
+    fs::write(&path, table)?;
+    Ok(path)
+}
+
+criterion_group!(
+    benches,
+    bench_insert_batch,
+    bench_group_by_receiver,
+    bench_signature_recovery,
+    bench_merkle_proof_verify,
+    bench_overpay_check
+);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    match write_summary() {
+        Ok(path) => println!("summary written to {}", path.display()),
+        Err(err) => eprintln!("failed to write summary: {}", err),
+    }
+}
@@ -0,0 +1,60 @@
+//! EthAddress（`[u8; 20]`）与 alloy `Address` 之间的转换
+//!
+//! 两种表示长度相同，转换不会失败。不要用 `Address::from_slice` 或 `as_slice().try_into().expect(..)`，
+//! 长度错误只能在运行时发现；crate 根目录的 clippy.toml 禁止了 `Address::from_slice`
+use alloy_primitives::Address;
+
+use crate::EthAddress;
+
+#[inline]
+pub fn to_alloy(addr: EthAddress) -> Address {
+    Address::new(addr)
+}
+
+#[inline]
+pub fn from_alloy(addr: Address) -> EthAddress {
+    addr.into_array()
+}
+
+/// `addr.to_alloy()`
+pub trait EthAddressExt {
+    fn to_alloy(&self) -> Address;
+}
+
+impl EthAddressExt for EthAddress {
+    #[inline]
+    fn to_alloy(&self) -> Address {
+        to_alloy(*self)
+    }
+}
+
+/// `address.to_eth()`
+pub trait AlloyAddressExt {
+    fn to_eth(&self) -> EthAddress;
+}
+
+impl AlloyAddressExt for Address {
+    #[inline]
+    fn to_eth(&self) -> EthAddress {
+        from_alloy(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::address;
+
+    #[test]
+    fn test_round_trip() {
+        let eth: EthAddress = core::array::from_fn(|i| i as u8 * 13 + 1);
+        assert_eq!(from_alloy(to_alloy(eth)), eth);
+        assert_eq!(eth.to_alloy().to_eth(), eth);
+        assert_eq!(to_alloy(eth).as_slice(), &eth[..]);
+
+        let alloy = address!("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        assert_eq!(to_alloy(from_alloy(alloy)), alloy);
+        assert_eq!(alloy.to_eth().to_alloy(), alloy);
+        assert_eq!(alloy.to_eth()[0], 0x5a);
+    }
+}
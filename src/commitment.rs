@@ -0,0 +1,268 @@
+//! 根的承诺方案版本
+//!
+//! 哈希布局会继续演进（域分隔、填充的分段、nonce 字段），链上验证方需要知道一个根是按哪种方案计算的。
+//! 对外的 bytes32 表示中最高字节为版本号，其余 31 字节为根的低 31 字节：
+//!
+//! ```text
+//! packed = version(1) ‖ root[1..32]
+//! ```
+//!
+//! Legacy（0）表示引入版本之前的根，打包时原样输出 32 字节，与旧的 public values 相同。
+//! 其他版本打包后丢失根的最高字节，比较时只比较版本和低 31 字节。
+use alloy_primitives::B256;
+use core::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::HashScheme;
+
+/// 承诺方案版本，写入打包后的根的最高字节
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum CommitmentVersion {
+    /// 未标记版本的根，旧数据反序列化时的默认值
+    #[default]
+    Legacy = 0,
+    /// HashScheme::V1：原像不带域标签
+    V1 = 1,
+    /// HashScheme::V2：原像以 HashDomain 标签开头
+    V2 = 2,
+}
+
+impl CommitmentVersion {
+    /// 当前代码计算的根的版本，与 HashScheme::ACTIVE 对应
+    pub const CURRENT: CommitmentVersion = CommitmentVersion::from_scheme(HashScheme::ACTIVE);
+
+    pub const fn from_scheme(scheme: HashScheme) -> Self {
+        match scheme {
+            HashScheme::V1 => CommitmentVersion::V1,
+            HashScheme::V2 => CommitmentVersion::V2,
+        }
+    }
+
+    /// 该版本记录的哈希方案，Legacy 没有记录方案
+    pub fn scheme(self) -> Option<HashScheme> {
+        match self {
+            CommitmentVersion::Legacy => None,
+            CommitmentVersion::V1 => Some(HashScheme::V1),
+            CommitmentVersion::V2 => Some(HashScheme::V2),
+        }
+    }
+
+    /// 计算该版本的根实际使用的哈希方案：Legacy 的根在引入域标签之前计算，与 V1 相同
+    pub fn hash_scheme(self) -> HashScheme {
+        self.scheme().unwrap_or(HashScheme::V1)
+    }
+
+    pub fn as_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, CommitmentError> {
+        match byte {
+            0 => Ok(CommitmentVersion::Legacy),
+            1 => Ok(CommitmentVersion::V1),
+            2 => Ok(CommitmentVersion::V2),
+            other => Err(CommitmentError::UnknownVersion(other)),
+        }
+    }
+
+    /// 所有版本的根必须按同一哈希方案计算（见 hash_scheme），返回共同的版本；
+    /// Legacy 与 V1 同时出现时返回 V1，空迭代器返回 None
+    pub fn common<I: IntoIterator<Item = CommitmentVersion>>(versions: I) -> Result<Option<Self>, CommitmentError> {
+        let mut versions = versions.into_iter();
+        let Some(expected) = versions.next() else {
+            return Ok(None);
+        };
+        let mut common = expected;
+        for actual in versions {
+            if actual.hash_scheme() != expected.hash_scheme() {
+                return Err(CommitmentError::MixedVersions { expected, actual });
+            }
+            if common == CommitmentVersion::Legacy {
+                common = actual;
+            }
+        }
+        Ok(Some(common))
+    }
+}
+
+impl fmt::Display for CommitmentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommitmentVersion::Legacy => write!(f, "legacy"),
+            CommitmentVersion::V1 => write!(f, "v1"),
+            CommitmentVersion::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// 版本号无法识别，或同一次聚合中出现不同版本的根
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitmentError {
+    UnknownVersion(u8),
+    MixedVersions { expected: CommitmentVersion, actual: CommitmentVersion },
+}
+
+impl fmt::Display for CommitmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommitmentError::UnknownVersion(byte) => write!(f, "Unknown commitment version {}", byte),
+            CommitmentError::MixedVersions { expected, actual } => {
+                write!(f, "Mixed commitment versions: expected {}, got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl core::error::Error for CommitmentError {}
+
+/// 带版本的根，序列化为打包后的 bytes32
+///
+/// 相等和哈希按打包后的值计算，Legacy 比较完整的 32 字节，其他版本忽略根的最高字节
+#[derive(Debug, Clone, Copy)]
+pub struct VersionedRoot {
+    pub version: CommitmentVersion,
+    pub root: B256,
+}
+
+impl VersionedRoot {
+    pub fn new(version: CommitmentVersion, root: B256) -> Self {
+        Self { version, root }
+    }
+
+    /// 标记为当前代码计算的根
+    pub fn current(root: B256) -> Self {
+        Self::new(CommitmentVersion::CURRENT, root)
+    }
+
+    pub fn legacy(root: B256) -> Self {
+        Self::new(CommitmentVersion::Legacy, root)
+    }
+
+    /// 未打包的根，用于仍按 32 字节根计算的旧路径（默克尔证明、settlement_id 等）
+    pub fn raw(&self) -> B256 {
+        self.root
+    }
+
+    pub fn version(&self) -> CommitmentVersion {
+        self.version
+    }
+
+    /// version ‖ root[1..32]；Legacy 为原始的根
+    pub fn pack(&self) -> B256 {
+        let mut packed = self.root;
+        if self.version != CommitmentVersion::Legacy {
+            packed.0[0] = self.version.as_byte();
+        }
+        packed
+    }
+
+    /// 从打包后的 bytes32 还原，最高字节必须是 Legacy 以外的已知版本；还原出的根最高字节为 0
+    ///
+    /// Legacy 的根没有版本字节，不能从打包值识别，由调用方用 VersionedRoot::legacy 构造
+    pub fn unpack(packed: B256) -> Result<Self, CommitmentError> {
+        let version = CommitmentVersion::from_byte(packed[0])?;
+        if version == CommitmentVersion::Legacy {
+            return Err(CommitmentError::UnknownVersion(0));
+        }
+        let mut root = packed;
+        root.0[0] = 0;
+        Ok(Self { version, root })
+    }
+
+    /// 还原同一个结果中的多个根，所有根必须带有同一个版本
+    pub fn unpack_all<const N: usize>(packed: [B256; N]) -> Result<[VersionedRoot; N], CommitmentError> {
+        let mut roots = [VersionedRoot::legacy(B256::ZERO); N];
+        for (root, packed) in roots.iter_mut().zip(packed) {
+            *root = VersionedRoot::unpack(packed)?;
+        }
+        CommitmentVersion::common(roots.iter().map(|root| root.version))?;
+        Ok(roots)
+    }
+
+    /// 与按 32 字节计算出的根比较，规则与相等相同
+    pub fn matches(&self, raw: B256) -> bool {
+        *self == VersionedRoot::new(self.version, raw)
+    }
+}
+
+impl PartialEq for VersionedRoot {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version && self.pack() == other.pack()
+    }
+}
+
+impl Eq for VersionedRoot {}
+
+impl core::hash::Hash for VersionedRoot {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.pack().hash(state);
+    }
+}
+
+impl fmt::Display for VersionedRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pack())
+    }
+}
+
+impl Serialize for VersionedRoot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.pack().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionedRoot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let packed = B256::deserialize(deserializer)?;
+        VersionedRoot::unpack(packed).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack() {
+        let root = B256::repeat_byte(0xab);
+        let versioned = VersionedRoot::new(CommitmentVersion::V2, root);
+        let packed = versioned.pack();
+        assert_eq!(packed[0], 2);
+        assert_eq!(packed[1..], root[1..]);
+
+        let unpacked = VersionedRoot::unpack(packed).unwrap();
+        assert_eq!(unpacked, versioned);
+        assert_eq!(unpacked.version(), CommitmentVersion::V2);
+        assert_eq!(unpacked.raw()[0], 0);
+        assert!(unpacked.matches(root));
+        assert!(!unpacked.matches(B256::repeat_byte(0xac)));
+
+        // Legacy 原样输出，不能从打包值还原
+        assert_eq!(VersionedRoot::legacy(root).pack(), root);
+        assert_eq!(VersionedRoot::unpack(B256::ZERO), Err(CommitmentError::UnknownVersion(0)));
+        let mut unknown = root;
+        unknown.0[0] = 0x7f;
+        assert_eq!(VersionedRoot::unpack(unknown), Err(CommitmentError::UnknownVersion(0x7f)));
+        assert_eq!(VersionedRoot::current(root).version(), CommitmentVersion::from_scheme(HashScheme::ACTIVE));
+    }
+
+    #[test]
+    fn test_common_version() {
+        use CommitmentVersion::*;
+        assert_eq!(CommitmentVersion::common(core::iter::empty()), Ok(None));
+        assert_eq!(CommitmentVersion::common([V1, V1]), Ok(Some(V1)));
+        assert_eq!(
+            CommitmentVersion::common([V1, V2, V1]),
+            Err(CommitmentError::MixedVersions { expected: V1, actual: V2 })
+        );
+
+        // Legacy 的哈希与 V1 相同
+        assert_eq!(CommitmentVersion::common([Legacy, V1, Legacy]), Ok(Some(V1)));
+        assert_eq!(CommitmentVersion::common([Legacy, Legacy]), Ok(Some(Legacy)));
+        assert_eq!(
+            CommitmentVersion::common([Legacy, V2]),
+            Err(CommitmentError::MixedVersions { expected: Legacy, actual: V2 })
+        );
+    }
+}
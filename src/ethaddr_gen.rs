@@ -1,6 +1,8 @@
 use crate::models::EthAddress;
-use rand::{Rng, thread_rng};
+use libsecp256k1::{PublicKey, SecretKey};
+use rand::{thread_rng, Rng, RngCore, SeedableRng};
 use sha3::{Digest, Keccak256};
+#[cfg(not(target_arch = "wasm32"))]
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// 通过随机数创建以太坊地址的工具函数集合
@@ -9,7 +11,11 @@ pub struct EthAddressGen;
 impl EthAddressGen {
     /// 使用完全随机数生成地址
     pub fn random() -> EthAddress {
-        let mut rng = thread_rng();
+        Self::random_with(&mut thread_rng())
+    }
+
+    /// 由 rng 生成地址，传入 DeterministicRng 时结果可复现
+    pub fn random_with<R: Rng + ?Sized>(rng: &mut R) -> EthAddress {
         let mut addr = [0u8; 20];
         rng.fill(&mut addr);
         addr
@@ -26,6 +32,9 @@ impl EthAddressGen {
     }
 
     /// 使用当前时间戳作为种子生成地址
+    ///
+    /// wasm32-unknown-unknown 上 SystemTime::now 会 panic，因此不提供
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn from_timestamp() -> EthAddress {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -46,14 +55,12 @@ impl EthAddressGen {
 
     /// 生成一系列不同的地址
     pub fn generate_batch(count: usize) -> Vec<EthAddress> {
-        let mut addresses = Vec::with_capacity(count);
-        let  rng = thread_rng();
-        
-        for _ in 0..count {
-            addresses.push(Self::random());
-        }
-        
-        addresses
+        Self::generate_batch_with(count, &mut thread_rng())
+    }
+
+    /// 与 generate_batch 相同，地址依次由 rng 生成
+    pub fn generate_batch_with<R: Rng + ?Sized>(count: usize, rng: &mut R) -> Vec<EthAddress> {
+        (0..count).map(|_| Self::random_with(rng)).collect()
     }
 
     /// 生成一个有特定前缀的地址（用于测试）
@@ -64,6 +71,92 @@ impl EthAddressGen {
     }
 }
 
+/// 由种子确定性生成密钥对，测试和基准测试中代替 SecretKey::random，保证各机器上的数据相同
+///
+/// 私钥为 keccak256(seed ‖ counter)，不是合法私钥时递增 counter 重试
+pub fn keypair_from_seed(seed: u64) -> (SecretKey, PublicKey) {
+    let mut counter: u32 = 0;
+    loop {
+        let mut hasher = Keccak256::new();
+        hasher.update(seed.to_be_bytes());
+        hasher.update(counter.to_be_bytes());
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&hasher.finalize());
+        if let Ok(secret_key) = SecretKey::parse(&bytes) {
+            let public_key = PublicKey::from_secret_key(&secret_key);
+            return (secret_key, public_key);
+        }
+        counter += 1;
+    }
+}
+
+/// 可复现的随机数源：第 i 个 32 字节块为 keccak256(seed ‖ i)，i 为 u64 大端序
+///
+/// 不依赖 rand 内部的算法，同一种子在任何平台和 rand 版本下产生相同的序列。不是安全的随机源，
+/// 只用于测试、基准测试和生成 fixture 数据
+#[derive(Debug, Clone)]
+pub struct DeterministicRng {
+    seed: [u8; 32],
+    counter: u64,
+    block: [u8; 32],
+    offset: usize,
+}
+
+impl DeterministicRng {
+    fn refill(&mut self) {
+        let mut hasher = Keccak256::new();
+        hasher.update(self.seed);
+        hasher.update(self.counter.to_be_bytes());
+        self.block.copy_from_slice(&hasher.finalize());
+        self.counter += 1;
+        self.offset = 0;
+    }
+}
+
+impl RngCore for DeterministicRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            if self.offset == self.block.len() {
+                self.refill();
+            }
+            *byte = self.block[self.offset];
+            self.offset += 1;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for DeterministicRng {
+    type Seed = [u8; 32];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Self { seed, counter: 0, block: [0u8; 32], offset: 32 }
+    }
+
+    /// 种子为 keccak256(state)，state 为大端序，不使用 rand 默认的 PCG 展开
+    fn seed_from_u64(state: u64) -> Self {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&Keccak256::digest(state.to_be_bytes()));
+        Self::from_seed(seed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,6 +186,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(target_arch = "wasm32"))]
     fn test_timestamp_address() {
         let addr1 = EthAddressGen::from_timestamp();
         thread::sleep(std::time::Duration::from_millis(1));
@@ -102,6 +196,18 @@ mod tests {
         assert_ne!(addr1, addr2);
     }
 
+    #[test]
+    fn test_keypair_from_seed() {
+        let (secret1, public1) = keypair_from_seed(7);
+        let (secret2, public2) = keypair_from_seed(7);
+        assert_eq!(secret1.serialize(), secret2.serialize());
+        assert_eq!(public1.serialize(), public2.serialize());
+        assert_eq!(PublicKey::from_secret_key(&secret1).serialize(), public1.serialize());
+
+        let (secret3, _) = keypair_from_seed(8);
+        assert_ne!(secret1.serialize(), secret3.serialize());
+    }
+
     #[test]
     fn test_data_address() {
         let data1 = b"test data 1";
@@ -131,6 +237,29 @@ mod tests {
         assert_eq!(unique_addresses.len(), count);
     }
 
+    #[test]
+    fn test_deterministic_rng() {
+        let mut rng = DeterministicRng::seed_from_u64(42);
+        let mut copy = DeterministicRng::seed_from_u64(42);
+        let batch = EthAddressGen::generate_batch_with(100, &mut rng);
+        assert_eq!(batch, EthAddressGen::generate_batch_with(100, &mut copy));
+        assert_eq!(batch.iter().collect::<HashSet<_>>().len(), 100);
+        assert_ne!(batch, EthAddressGen::generate_batch_with(100, &mut DeterministicRng::seed_from_u64(43)));
+
+        // 输出按块定义，与读取的粒度无关
+        let seed = [9u8; 32];
+        let mut bytes = [0u8; 40];
+        DeterministicRng::from_seed(seed).fill_bytes(&mut bytes);
+        let block = |counter: u64| Keccak256::new().chain_update(seed).chain_update(counter.to_be_bytes()).finalize();
+        assert_eq!(&bytes[..32], &block(0)[..]);
+        assert_eq!(&bytes[32..], &block(1)[..8]);
+
+        let mut rng = DeterministicRng::from_seed(seed);
+        let words: Vec<u64> = (0..5).map(|_| rng.next_u64()).collect();
+        let expected: Vec<u64> = bytes.chunks(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())).collect();
+        assert_eq!(words, expected);
+    }
+
     #[test]
     fn test_prefixed_address() {
         let prefix = 0xAB;
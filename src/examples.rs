@@ -0,0 +1,45 @@
+//! SP1 模板留下的示例：fibonacci 和它的公开输出 PublicValuesStruct
+//!
+//! 与收据结算无关，只为仍在使用模板程序的 guest 保留，`zkpay_lib::examples` 路径需要开启 examples 特性。
+//! crate 根目录的同名导出总是存在但已弃用，请改为 `zkpay_lib::examples::{fibonacci, PublicValuesStruct}`
+use alloy_sol_types::sol;
+
+sol! {
+    /// The public values encoded as a struct that can be easily deserialized inside Solidity.
+    struct PublicValuesStruct {
+        uint32 n;
+        uint32 a;
+        uint32 b;
+    }
+}
+
+/// Compute the n'th fibonacci number (wrapping around on overflows), using normal Rust code.
+pub fn fibonacci(n: u32) -> (u32, u32) {
+    let mut a = 0u32;
+    let mut b = 1u32;
+    for _ in 0..n {
+        let c = a.wrapping_add(b);
+        a = b;
+        b = c;
+    }
+    (a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_sol_types::SolType;
+
+    #[test]
+    fn test_fibonacci_public_values() {
+        assert_eq!(fibonacci(0), (0, 1));
+        assert_eq!(fibonacci(10), (55, 89));
+        // 超出 u32 时回绕
+        assert_eq!(fibonacci(48).0, 512559680);
+
+        let (a, b) = fibonacci(20);
+        let encoded = PublicValuesStruct::abi_encode(&PublicValuesStruct { n: 20, a, b });
+        let decoded = PublicValuesStruct::abi_decode(&encoded, true).unwrap();
+        assert_eq!((decoded.n, decoded.a, decoded.b), (20, 6765, 10946));
+    }
+}
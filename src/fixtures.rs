@@ -0,0 +1,485 @@
+//! 结算场景的确定性测试数据
+//!
+//! 同一个种子在任何机器上生成相同的密钥、地址、签名收据和 PayIdInfo。默认生成的场景能通过
+//! overpay 检查、利润计算和聚合，用 with_violation 注入特定的错误。
+//! crate 内的测试直接使用，下游 crate 通过 test-utils 特性使用。
+//!
+//! ```ignore
+//! let scenario = ScenarioBuilder::new(7)
+//!     .with_receivers(2)
+//!     .with_payment(1, 1, 0, 1000)
+//!     .with_payment(1, 2, 1, 500)
+//!     .build()?;
+//! let overpay_result = scenario.overpay_checker().process()?;
+//! ```
+
+use alloy_primitives::{B256, U256};
+use libsecp256k1::SecretKey;
+use rand::{RngCore, SeedableRng};
+use std::collections::HashMap;
+
+use crate::ethaddr_gen::{keypair_from_seed, DeterministicRng, EthAddressGen};
+use crate::models::segment_vc::MerkleProof;
+use crate::models::{PayIdInfo, PayIdState, ServiceFeeConfig};
+use crate::proxy_settler::ProxySettlementAggregator;
+use crate::receipts::profit_calculator::ReceiptsProfitCalculator;
+use crate::receipts::{MultiReceiverProfitCalculator, PaymentsGrouper, SettledReceiptBuilder};
+use crate::{
+    get_ethereum_address, BoxError, EthAddress, OverpayCheckResult, PaymentSettledByProxy, ReceiptsOverpayChecker, NATIVE_TOKEN,
+};
+
+/// 场景中的当前时间，PayIdInfo.created_at 早于它
+pub const SCENARIO_NOW: u64 = 10_000;
+const CREATED_AT: u64 = 1_000;
+
+/// 没有单独配置的服务使用的费率：系统 5%，代理 10%
+pub const DEFAULT_SYSTEM_FEE_RATE: u16 = 500;
+pub const DEFAULT_PROXY_FEE_RATE: u16 = 1000;
+
+// 种子空间按角色划分，不同角色、不同下标的密钥和地址互不相同
+const ROLE_PROXY: u64 = 1;
+const ROLE_SENDER: u64 = 2;
+const ROLE_RECEIVER: u64 = 3;
+const ROLE_PAYMENTS: u64 = 4;
+
+fn derive_seed(seed: u64, role: u64, index: u64) -> u64 {
+    (seed << 24) ^ (role << 16) ^ index
+}
+
+/// 注入到场景中的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// 该 pay_id 的存款比收据总额少 1
+    Overpay { pay_id: u64 },
+    /// 该 pay_id 的收据由另一个代理签名
+    WrongProxy { pay_id: u64 },
+    /// 该 pay_id 已关闭：state 为 Closed，closing_time 早于 SCENARIO_NOW
+    ExpiredChannel { pay_id: u64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PaymentSpec {
+    pay_id: u64,
+    serv_id: u32,
+    receiver: usize,
+    amount: u64,
+}
+
+pub struct ScenarioBuilder {
+    seed: u64,
+    senders: usize,
+    receivers: usize,
+    epoch: u64,
+    payments: Vec<PaymentSpec>,
+    deposits: HashMap<u64, U256>,
+    service_configs: Vec<ServiceFeeConfig>,
+    violations: Vec<Violation>,
+    tokens: HashMap<u64, EthAddress>,
+}
+
+/// 构建好的场景，字段可以直接传给各个检查器，也可以在测试中修改
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub epoch: u64,
+    pub proxy: EthAddress,
+    pub proxy_key: SecretKey,
+    pub senders: Vec<EthAddress>,
+    pub sender_keys: Vec<SecretKey>,
+    pub receivers: Vec<EthAddress>,
+    pub pay_id_infos: Vec<PayIdInfo>, // 按 pay_id 升序
+    pub receipts: Vec<PaymentSettledByProxy>, // 按 with_payment 的顺序
+    pub service_configs: Vec<ServiceFeeConfig>, // 按 serv_id 升序
+}
+
+impl ScenarioBuilder {
+    /// 默认 1 个发送者、1 个接收者、轮次 0，没有收据
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            senders: 1,
+            receivers: 1,
+            epoch: 0,
+            payments: Vec::new(),
+            deposits: HashMap::new(),
+            service_configs: Vec::new(),
+            violations: Vec::new(),
+            tokens: HashMap::new(),
+        }
+    }
+
+    /// pay_id 的发送者为 senders[pay_id % count]
+    pub fn with_senders(mut self, count: usize) -> Self {
+        self.senders = count.max(1);
+        self
+    }
+
+    pub fn with_receivers(mut self, count: usize) -> Self {
+        self.receivers = count.max(1);
+        self
+    }
+
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 添加一张由发送者和代理签名的已结算收据，receiver 为接收者下标（对 receivers 取模）
+    pub fn with_payment(mut self, pay_id: u64, serv_id: u32, receiver: usize, amount: u64) -> Self {
+        self.payments.push(PaymentSpec { pay_id, serv_id, receiver, amount });
+        self
+    }
+
+    /// 添加 count 张随机收据：pay_id 在 [1, count + 已有收据数] 内，serv_id 在 [1, 3] 内，金额在 [1, max_amount] 内，
+    /// 接收者为任意下标，(pay_id, serv_id, receiver) 不与已有的收据重复
+    ///
+    /// 由种子和已有的收据数决定，只用取模而不用 gen_range，在任何平台和 rand 版本下得到相同的收据
+    pub fn with_random_payments(mut self, count: usize, max_amount: u64) -> Self {
+        let mut rng = DeterministicRng::seed_from_u64(derive_seed(self.seed, ROLE_PAYMENTS, self.payments.len() as u64));
+        let mut below = |bound: u64| rng.next_u64() % bound;
+        let pay_ids = (count + self.payments.len()) as u64;
+        for _ in 0..count {
+            let spec = loop {
+                let spec = PaymentSpec {
+                    pay_id: 1 + below(pay_ids),
+                    serv_id: 1 + below(3) as u32,
+                    receiver: below(self.receivers as u64) as usize,
+                    amount: 1 + below(max_amount.max(1)),
+                };
+                let taken = self.payments.iter().any(|other| {
+                    (other.pay_id, other.serv_id, other.receiver % self.receivers)
+                        == (spec.pay_id, spec.serv_id, spec.receiver)
+                });
+                if !taken {
+                    break spec;
+                }
+            };
+            self.payments.push(spec);
+        }
+        self
+    }
+
+    /// 指定 pay_id 的存款，默认等于该 pay_id 下收据的总额
+    pub fn with_deposit(mut self, pay_id: u64, amount: U256) -> Self {
+        self.deposits.insert(pay_id, amount);
+        self
+    }
+
+    /// 指定服务费率，收据中用到但未指定的服务使用默认费率
+    pub fn with_fee_config(mut self, serv_id: u32, system_fee_rate: u16, proxy_fee_rate: u16) -> Self {
+        self.service_configs.retain(|config| config.serv_id != serv_id);
+        self.service_configs.push(ServiceFeeConfig::flat(serv_id, system_fee_rate, proxy_fee_rate));
+        self
+    }
+
+    /// 指定 pay_id 的代币，该 pay_id 的 PayIdInfo 和收据都使用它，默认为 NATIVE_TOKEN
+    pub fn with_token(mut self, pay_id: u64, token: EthAddress) -> Self {
+        self.tokens.insert(pay_id, token);
+        self
+    }
+
+    pub fn with_violation(mut self, violation: Violation) -> Self {
+        self.violations.push(violation);
+        self
+    }
+
+    pub fn build(self) -> Result<Scenario, BoxError> {
+        let (proxy_key, proxy_public) = keypair_from_seed(derive_seed(self.seed, ROLE_PROXY, 0));
+        let (rogue_proxy_key, _) = keypair_from_seed(derive_seed(self.seed, ROLE_PROXY, 1));
+        let sender_keys: Vec<SecretKey> = (0..self.senders as u64)
+            .map(|i| keypair_from_seed(derive_seed(self.seed, ROLE_SENDER, i)).0)
+            .collect();
+        let senders: Vec<EthAddress> = (0..self.senders as u64)
+            .map(|i| get_ethereum_address(&keypair_from_seed(derive_seed(self.seed, ROLE_SENDER, i)).1))
+            .collect();
+        let receivers: Vec<EthAddress> = (0..self.receivers as u64)
+            .map(|i| EthAddressGen::from_seed(derive_seed(self.seed, ROLE_RECEIVER, i)))
+            .collect();
+        let proxy = get_ethereum_address(&proxy_public);
+        let sender_index = |pay_id: u64| (pay_id % self.senders as u64) as usize;
+        let token_of = |pay_id: u64| self.tokens.get(&pay_id).copied().unwrap_or(NATIVE_TOKEN);
+
+        // 1. 收据
+        let mut receipts = Vec::with_capacity(self.payments.len());
+        let mut totals: HashMap<u64, U256> = HashMap::new();
+        for spec in &self.payments {
+            let signer = if self.violations.contains(&Violation::WrongProxy { pay_id: spec.pay_id }) {
+                &rogue_proxy_key
+            } else {
+                &proxy_key
+            };
+            receipts.push(signed_token_receipt(
+                spec.pay_id,
+                spec.serv_id,
+                spec.amount,
+                receivers[spec.receiver % receivers.len()],
+                token_of(spec.pay_id),
+                &sender_keys[sender_index(spec.pay_id)],
+                signer,
+            )?);
+            *totals.entry(spec.pay_id).or_default() += U256::from(spec.amount);
+        }
+
+        // 2. PayIdInfo，存款默认与收据总额相同
+        let mut pay_ids: Vec<u64> = totals.keys().chain(self.deposits.keys()).copied().collect();
+        pay_ids.sort_unstable();
+        pay_ids.dedup();
+        let mut pay_id_infos = Vec::with_capacity(pay_ids.len());
+        for pay_id in pay_ids {
+            let amount = self
+                .deposits
+                .get(&pay_id)
+                .copied()
+                .unwrap_or_else(|| totals.get(&pay_id).copied().unwrap_or_default());
+            let mut info = PayIdInfo {
+                id: U256::from(pay_id),
+                amount,
+                sender: senders[sender_index(pay_id)],
+                proxy,
+                state: PayIdState::Active.into(),
+                created_at: CREATED_AT,
+                closing_time: 0,
+                token: token_of(pay_id),
+                sig_sender: None,
+            };
+            for violation in &self.violations {
+                match *violation {
+                    Violation::Overpay { pay_id: id } if id == pay_id => {
+                        info.amount = totals
+                            .get(&pay_id)
+                            .and_then(|total| total.checked_sub(U256::from(1)))
+                            .ok_or_else(|| format!("Overpay violation needs payments for pay_id {}", pay_id))?;
+                    }
+                    Violation::ExpiredChannel { pay_id: id } if id == pay_id => {
+                        info.state = PayIdState::Closed.into();
+                        info.closing_time = SCENARIO_NOW - 1;
+                    }
+                    _ => {}
+                }
+            }
+            pay_id_infos.push(info);
+        }
+
+        // 3. 服务费率
+        let mut service_configs = self.service_configs;
+        for spec in &self.payments {
+            if !service_configs.iter().any(|config| config.serv_id == spec.serv_id) {
+                service_configs.push(ServiceFeeConfig {
+                    serv_id: spec.serv_id,
+                    system_fee_rate: DEFAULT_SYSTEM_FEE_RATE,
+                    proxy_fee_rate: DEFAULT_PROXY_FEE_RATE,
+                    tiers: Vec::new(),
+                });
+            }
+        }
+        service_configs.sort_by_key(|config| config.serv_id);
+
+        Ok(Scenario {
+            epoch: self.epoch,
+            proxy,
+            proxy_key,
+            senders,
+            sender_keys,
+            receivers,
+            pay_id_infos,
+            receipts,
+            service_configs,
+        })
+    }
+}
+
+impl Scenario {
+    pub fn receiver(&self, index: usize) -> EthAddress {
+        self.receivers[index]
+    }
+
+    /// 该接收者的收据，保持原有顺序
+    pub fn receipts_for(&self, receiver: &EthAddress) -> Vec<PaymentSettledByProxy> {
+        self.receipts
+            .iter()
+            .filter(|receipt| &receipt.receiver == receiver)
+            .cloned()
+            .collect()
+    }
+
+    /// 用各自发送者的私钥签名所有 PayIdInfo
+    pub fn sign_pay_id_infos(&mut self) -> Result<(), BoxError> {
+        for info in &mut self.pay_id_infos {
+            let index = self.senders.iter().position(|sender| *sender == info.sender).ok_or("Unknown sender")?;
+            info.sign(&self.sender_keys[index])?;
+        }
+        Ok(())
+    }
+
+    /// 以场景的代理为 channel，开启发送者和代理签名验证
+    pub fn overpay_checker(&self) -> ReceiptsOverpayChecker {
+        ReceiptsOverpayChecker::new(self.proxy, self.pay_id_infos.clone(), self.receipts.clone())
+            .with_epoch(self.epoch)
+            .with_proxy_signature_check()
+            .with_sender_signature_check()
+    }
+
+    /// 单个接收者的利润计算，proof 通常来自 overpay 结果的 get_merkle_proof
+    pub fn profit_calculator(&self, receiver: EthAddress, proof: MerkleProof) -> ReceiptsProfitCalculator {
+        ReceiptsProfitCalculator::new(
+            B256::ZERO,
+            receiver,
+            self.proxy,
+            self.receipts_for(&receiver),
+            proof,
+            self.pay_id_infos.clone(),
+            self.service_configs.clone(),
+        )
+        .with_epoch(self.epoch)
+    }
+
+    pub fn multi_profit_calculator(&self, overpay_result: OverpayCheckResult) -> MultiReceiverProfitCalculator {
+        MultiReceiverProfitCalculator::new(
+            B256::ZERO,
+            self.proxy,
+            overpay_result,
+            PaymentsGrouper::group_payments(&self.receipts),
+            self.pay_id_infos.clone(),
+            self.service_configs.clone(),
+        )
+    }
+
+    pub fn aggregator(&self) -> ProxySettlementAggregator {
+        ProxySettlementAggregator::new().with_epoch(self.epoch)
+    }
+}
+
+/// 发送者签名、代理按原金额结算并签名的收据
+pub fn signed_receipt(
+    pay_id: u64,
+    serv_id: u32,
+    amount: u64,
+    receiver: EthAddress,
+    sender_key: &SecretKey,
+    proxy_key: &SecretKey,
+) -> Result<PaymentSettledByProxy, BoxError> {
+    signed_token_receipt(pay_id, serv_id, amount, receiver, NATIVE_TOKEN, sender_key, proxy_key)
+}
+
+/// 与 signed_receipt 相同，收据的代币为 token
+pub fn signed_token_receipt(
+    pay_id: u64,
+    serv_id: u32,
+    amount: u64,
+    receiver: EthAddress,
+    token: EthAddress,
+    sender_key: &SecretKey,
+    proxy_key: &SecretKey,
+) -> Result<PaymentSettledByProxy, BoxError> {
+    Ok(SettledReceiptBuilder::new(U256::from(pay_id), serv_id, U256::from(amount), receiver)
+        .with_token(token)
+        .sign_sender(sender_key)
+        .settle(U256::from(amount), true)
+        .sign_proxy(proxy_key)
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_receivers(seed: u64) -> ScenarioBuilder {
+        ScenarioBuilder::new(seed)
+            .with_senders(2)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(1, 2, 1, 500)
+            .with_payment(2, 1, 1, 2000)
+    }
+
+    #[test]
+    fn test_deterministic() -> Result<(), BoxError> {
+        let first = two_receivers(7).build()?;
+        let second = two_receivers(7).build()?;
+        assert_eq!(first.proxy, second.proxy);
+        assert_eq!(first.receivers, second.receivers);
+        let hashes = |scenario: &Scenario| scenario.receipts.iter().map(|r| r.hash()).collect::<Vec<_>>();
+        assert_eq!(hashes(&first), hashes(&second));
+
+        let other = two_receivers(8).build()?;
+        assert_ne!(first.proxy, other.proxy);
+        assert_ne!(first.receivers, other.receivers);
+        Ok(())
+    }
+
+    // 场景的全部内容，私钥按字节序列化
+    fn serialize(scenario: &Scenario) -> Result<String, BoxError> {
+        let keys: Vec<[u8; 32]> = scenario.sender_keys.iter().map(SecretKey::serialize).collect();
+        Ok(serde_json::to_string(&(
+            scenario.epoch,
+            (scenario.proxy, scenario.proxy_key.serialize(), &scenario.senders, keys, &scenario.receivers),
+            &scenario.pay_id_infos,
+            &scenario.receipts,
+            &scenario.service_configs,
+        ))?)
+    }
+
+    #[test]
+    fn test_random_payments_reproducible() -> Result<(), BoxError> {
+        let builder = |seed: u64| {
+            ScenarioBuilder::new(seed)
+                .with_senders(3)
+                .with_receivers(4)
+                .with_payment(1, 1, 0, 100)
+                .with_random_payments(40, 1_000_000)
+        };
+        let first = builder(21).build()?;
+        assert_eq!(first.receipts.len(), 41);
+        assert_eq!(serialize(&first)?, serialize(&builder(21).build()?)?);
+        assert_ne!(serialize(&first)?, serialize(&builder(22).build()?)?);
+
+        // 随机的场景同样能通过 overpay 检查
+        let result = first.overpay_checker().process()?;
+        for receiver in &first.receivers {
+            if !first.receipts_for(receiver).is_empty() {
+                first.profit_calculator(*receiver, result.get_merkle_proof(*receiver)?).calculate()?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_consistent_scenario_settles() -> Result<(), BoxError> {
+        let scenario = two_receivers(1).with_epoch(2).build()?;
+        assert_eq!(scenario.pay_id_infos[0].amount, U256::from(1500));
+        assert_eq!(scenario.pay_id_infos[1].amount, U256::from(2000));
+        assert_eq!(scenario.pay_id_infos[1].sender, scenario.senders[0]);
+        assert_eq!(scenario.service_configs.len(), 2);
+
+        let overpay_result = scenario.overpay_checker().process()?;
+        let receiver = scenario.receiver(0);
+        let profit = scenario
+            .profit_calculator(receiver, overpay_result.get_merkle_proof(receiver)?)
+            .calculate()?;
+        assert_eq!(profit.system_profit + profit.proxy_profit + profit.receiver_profit, U256::from(1000));
+
+        let multi = scenario.multi_profit_calculator(overpay_result.clone()).calculate()?;
+        let settlement = scenario
+            .aggregator()
+            .aggregate_with_deposits(multi.profit_results, overpay_result, &scenario.pay_id_infos)?;
+        assert_eq!(settlement.amount, U256::from(3500));
+        Ok(())
+    }
+
+    #[test]
+    fn test_violations() -> Result<(), BoxError> {
+        let overpaid = two_receivers(1).with_violation(Violation::Overpay { pay_id: 2 }).build()?;
+        assert_eq!(overpaid.pay_id_infos[1].amount, U256::from(1999));
+        let err = overpaid.overpay_checker().process().unwrap_err();
+        assert!(err.to_string().contains("Overpayment detected for pay_id 2"));
+
+        let wrong_proxy = two_receivers(1).with_violation(Violation::WrongProxy { pay_id: 1 }).build()?;
+        let err = wrong_proxy.overpay_checker().process().unwrap_err();
+        assert!(err.to_string().contains("Invalid proxy signature"));
+
+        let expired = two_receivers(1).with_violation(Violation::ExpiredChannel { pay_id: 1 }).build()?;
+        let info = &expired.pay_id_infos[0];
+        assert_eq!(PayIdState::try_from(info.state), Ok(PayIdState::Closed));
+        assert!(info.closing_time < SCENARIO_NOW);
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! 整个结算流水线的黄金兼容性测试
+//!
+//! tests/fixtures/golden_settlement.json 记录一批固定的输入（50 个收据、10 个 pay_id、3 个费率配置，
+//! 密钥来自 keypair_from_seed）和流水线的全部输出。测试用记录的输入重新运行
+//! ReceiptsOverpayChecker → MultiReceiverProfitCalculator → ProxySettlementAggregator，逐字节比较每个输出，
+//! 并检查 ScenarioBuilder 仍然生成相同的输入。
+//!
+//! 有意修改哈希或签名布局时重新生成并提交 fixture：
+//!
+//! ```text
+//! ZKPAY_REGENERATE_GOLDEN=1 cargo test golden
+//! ```
+//!
+//! 没有设置该变量时 fixture 必须存在，缺失时测试失败。
+use alloy_primitives::{B256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::fixtures::{Scenario, ScenarioBuilder};
+use crate::models::{PayIdInfo, ServiceFeeConfig};
+use crate::proxy_settler::ProxySettlementAggregator;
+use crate::receipts::{MultiReceiverProfitCalculator, PaymentsGrouper};
+use crate::{BoxError, EthAddress, PaymentSettledByProxy, ProfitResult, ReceiptsOverpayChecker};
+
+const FIXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/golden_settlement.json");
+const REGENERATE_ENV: &str = "ZKPAY_REGENERATE_GOLDEN";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GoldenInputs {
+    #[serde(with = "crate::serde_hex")]
+    proxy: EthAddress,
+    epoch: u64,
+    pay_id_infos: Vec<PayIdInfo>,
+    receipts: Vec<PaymentSettledByProxy>,
+    service_configs: Vec<ServiceFeeConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct GoldenOutputs {
+    payments_root: B256,
+    pay_ids_root: B256,
+    serv_ids_root: B256,
+    profit_results: Vec<ProfitResult>, // 按 receiver 升序
+    settlement_id: B256,
+    settlement_amount: U256,
+    system_profits: U256,
+    proxy_profits: U256,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Golden {
+    inputs: GoldenInputs,
+    expected: GoldenOutputs,
+}
+
+// 4 个接收者，每个 (pay_id, serv_id, receiver) 只出现一次；pay_id 10 的存款多于收据总额
+fn scenario() -> Result<Scenario, BoxError> {
+    let mut builder = ScenarioBuilder::new(1896)
+        .with_senders(3)
+        .with_receivers(4)
+        .with_epoch(3)
+        .with_fee_config(1, 500, 1000)
+        .with_fee_config(2, 250, 750)
+        .with_fee_config(3, 0, 1200)
+        .with_deposit(10, U256::from(1_000_000));
+    for i in 0..50u64 {
+        let round = (i / 10) as usize;
+        builder = builder.with_payment(i % 10 + 1, (round % 3) as u32 + 1, round % 4, 100 + 37 * i);
+    }
+    builder.build()
+}
+
+fn inputs(scenario: &Scenario) -> GoldenInputs {
+    GoldenInputs {
+        proxy: scenario.proxy,
+        epoch: scenario.epoch,
+        pay_id_infos: scenario.pay_id_infos.clone(),
+        receipts: scenario.receipts.clone(),
+        service_configs: scenario.service_configs.clone(),
+    }
+}
+
+fn run(inputs: &GoldenInputs) -> Result<GoldenOutputs, BoxError> {
+    let overpay_result = ReceiptsOverpayChecker::new(inputs.proxy, inputs.pay_id_infos.clone(), inputs.receipts.clone())
+        .with_epoch(inputs.epoch)
+        .with_proxy_signature_check()
+        .with_sender_signature_check()
+        .process()?;
+
+    let mut profit_results = MultiReceiverProfitCalculator::new(
+        B256::ZERO,
+        inputs.proxy,
+        overpay_result.clone(),
+        PaymentsGrouper::group_payments(&inputs.receipts),
+        inputs.pay_id_infos.clone(),
+        inputs.service_configs.clone(),
+    )
+    .calculate()?
+    .profit_results;
+    profit_results.sort_by_key(|result| result.receiver);
+
+    let settlement = ProxySettlementAggregator::new()
+        .with_epoch(inputs.epoch)
+        .aggregate(profit_results.clone(), overpay_result.clone())?;
+
+    Ok(GoldenOutputs {
+        payments_root: overpay_result.payments_root,
+        pay_ids_root: overpay_result.pay_ids_root,
+        serv_ids_root: settlement.serv_ids_root,
+        profit_results,
+        settlement_id: settlement.settlement_id,
+        settlement_amount: settlement.amount,
+        system_profits: settlement.system_profits,
+        proxy_profits: settlement.proxy_profits,
+    })
+}
+
+#[test]
+fn test_golden_settlement() -> Result<(), BoxError> {
+    let scenario = scenario()?;
+    assert_eq!(scenario.receipts.len(), 50);
+    assert_eq!(scenario.pay_id_infos.len(), 10);
+    assert_eq!(scenario.service_configs.len(), 3);
+
+    let inputs = inputs(&scenario);
+    let current = Golden { expected: run(&inputs)?, inputs };
+    let json = serde_json::to_string_pretty(&current)?;
+
+    if std::env::var_os(REGENERATE_ENV).is_some() {
+        std::fs::write(FIXTURE_PATH, json + "\n")?;
+        return Ok(());
+    }
+    let recorded = std::fs::read_to_string(FIXTURE_PATH)
+        .map_err(|e| format!("missing golden fixture {}: {}, generate it with {}=1", FIXTURE_PATH, e, REGENERATE_ENV))?;
+    let golden: Golden = serde_json::from_str(&recorded)?;
+
+    // 1. 记录的输入仍然得到记录的输出
+    let outputs = run(&golden.inputs)?;
+    let expected = &golden.expected;
+    assert_eq!(outputs.payments_root, expected.payments_root, "payments_root changed");
+    assert_eq!(outputs.pay_ids_root, expected.pay_ids_root, "pay_ids_root changed");
+    assert_eq!(outputs.serv_ids_root, expected.serv_ids_root, "serv_ids_root changed");
+    assert_eq!(outputs.profit_results.len(), expected.profit_results.len());
+    for (actual, expected) in outputs.profit_results.iter().zip(&expected.profit_results) {
+        assert_eq!(actual, expected, "ProfitResult for receiver {:?} changed", expected.receiver);
+    }
+    assert_eq!(outputs.settlement_id, expected.settlement_id, "settlement_id changed");
+    assert_eq!(outputs, *expected);
+
+    // 2. ScenarioBuilder 生成的输入（含签名）与记录的相同，序列化结果逐字节一致
+    assert_eq!(json, recorded.trim_end(), "fixture builder output changed, regenerate with {}=1", REGENERATE_ENV);
+    Ok(())
+}
@@ -0,0 +1,479 @@
+//! guest 中使用的检查：失败时返回编码的 GuestError，而不是带格式化字符串的 panic
+//!
+//! GuestError 只有两个 u32，guest 中不格式化任何字符串，主机端用 GuestError::describe 转换为日志文本。
+//! 错误码一旦发布就不再改变，新的原因只能追加新的错误码
+use core::error::Error as StdError;
+use core::fmt;
+
+/// 失败原因的稳定编码，0 保留不用
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum ErrorCode {
+    /// 没有对应错误码的错误
+    Unclassified = 1,
+    /// 重新计算的根与承诺的根不同
+    RootMismatch = 2,
+    SignatureInvalid = 3,
+    /// 收据总额超过存款
+    Overpay = 4,
+    /// 重复的收据、PayIdInfo、键或结算
+    Duplicate = 5,
+    InvalidProof = 6,
+    EpochMismatch = 7,
+    Overflow = 8,
+    Expired = 9,
+    /// 结果中的接收者集合与承诺的不同
+    ReceiverMismatch = 10,
+    InvalidInput = 11,
+    /// 结果的 vks_hash 与其他结果或期望的不同
+    VksMismatch = 12,
+    /// 结果的代理地址与其他结果或期望的不同
+    ProxyMismatch = 13,
+}
+
+impl ErrorCode {
+    /// 所有错误码，按编码升序
+    pub const ALL: [ErrorCode; 13] = [
+        ErrorCode::Unclassified,
+        ErrorCode::RootMismatch,
+        ErrorCode::SignatureInvalid,
+        ErrorCode::Overpay,
+        ErrorCode::Duplicate,
+        ErrorCode::InvalidProof,
+        ErrorCode::EpochMismatch,
+        ErrorCode::Overflow,
+        ErrorCode::Expired,
+        ErrorCode::ReceiverMismatch,
+        ErrorCode::InvalidInput,
+        ErrorCode::VksMismatch,
+        ErrorCode::ProxyMismatch,
+    ];
+
+    pub const fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|error_code| error_code.code() == code)
+    }
+
+    pub const fn description(self) -> &'static str {
+        match self {
+            ErrorCode::Unclassified => "unclassified error",
+            ErrorCode::RootMismatch => "root mismatch",
+            ErrorCode::SignatureInvalid => "invalid signature",
+            ErrorCode::Overpay => "overpayment",
+            ErrorCode::Duplicate => "duplicate entry",
+            ErrorCode::InvalidProof => "invalid merkle proof",
+            ErrorCode::EpochMismatch => "epoch mismatch",
+            ErrorCode::Overflow => "amount overflow",
+            ErrorCode::Expired => "expired receipt",
+            ErrorCode::ReceiverMismatch => "receiver set mismatch",
+            ErrorCode::InvalidInput => "invalid input",
+            ErrorCode::VksMismatch => "verification key hash mismatch",
+            ErrorCode::ProxyMismatch => "proxy address mismatch",
+        }
+    }
+}
+
+/// GuestError 的 detail：失败的阶段，与 PayModelError 的变体对应
+pub const STAGE_OVERPAY_CHECK: u32 = 1;
+pub const STAGE_PROFIT_CALCULATION: u32 = 2;
+pub const STAGE_AGGREGATION: u32 = 3;
+pub const STAGE_RECEIVER_SETTLEMENT: u32 = 4;
+
+/// (错误码, detail)；detail 的含义由产生错误的地方决定，没有时为 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GuestError(pub u32, pub u32);
+
+impl GuestError {
+    pub const fn new(code: ErrorCode, detail: u32) -> Self {
+        Self(code.code(), detail)
+    }
+
+    pub const fn code(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn detail(&self) -> u32 {
+        self.1
+    }
+
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        ErrorCode::from_code(self.0)
+    }
+
+    /// 主机端日志使用的说明，未知的错误码返回 "unknown error code"
+    pub fn describe(code: u32) -> &'static str {
+        ErrorCode::from_code(code).map_or("unknown error code", ErrorCode::description)
+    }
+
+    /// 8 字节大端序 code ‖ detail，guest 可以作为公开输出提交
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&self.0.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.1.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 8]) -> Self {
+        let [a, b, c, d, e, f, g, h] = bytes;
+        Self(u32::from_be_bytes([a, b, c, d]), u32::from_be_bytes([e, f, g, h]))
+    }
+}
+
+impl fmt::Display for GuestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Guest error {} ({}), detail {}", self.0, Self::describe(self.0), self.1)
+    }
+}
+
+impl StdError for GuestError {}
+
+/// condition 不成立时返回 code，detail 为 0
+#[inline]
+pub fn ensure(condition: bool, code: ErrorCode) -> Result<(), GuestError> {
+    ensure_with(condition, code, 0)
+}
+
+#[inline]
+pub fn ensure_with(condition: bool, code: ErrorCode, detail: u32) -> Result<(), GuestError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(GuestError::new(code, detail))
+    }
+}
+
+/// 由错误的类型决定错误码，字符串错误为 Unclassified
+#[cfg(feature = "std")]
+pub fn classify(error: &(dyn std::error::Error + 'static)) -> ErrorCode {
+    use crate::models::segment_vc::{Error as TreeError, ProofTooLarge};
+    use crate::proxy_settler::{
+        AggregationError, DuplicateSettlementError, InconsistentProfitResult, PayIdsRootMismatch, ReceiptsRootMismatch,
+        ReceiverCoverageError, SettlementEpochMismatch,
+    };
+    use crate::receipts::overpay_checker::{OverpayDetected, OverpayError, TokenMismatch};
+    use crate::receipts::{
+        AmountOverflow, DuplicatePayIdInfo, DuplicateReceipt, InvalidReceiptSignature, ReceiptExpired, SettledExceedsAuthorized,
+    };
+    use crate::host::InputError;
+    use crate::receiver_settler::StaleSettlement;
+    use crate::CommitmentError;
+
+    if let Some(error) = error.downcast_ref::<OverpayError>() {
+        return match error {
+            OverpayError::InvalidInputs(inner) | OverpayError::ChannelRejected { error: inner, .. } => {
+                classify(inner.as_ref())
+            }
+            OverpayError::UnknownPayId(_) => ErrorCode::InvalidInput,
+            OverpayError::PayIdsRootMismatch { .. }
+            | OverpayError::PaymentsRootMismatch { .. }
+            | OverpayError::ReferencedPayIdsMismatch { .. } => ErrorCode::RootMismatch,
+            OverpayError::ReceiverMismatch { .. } => ErrorCode::ReceiverMismatch,
+            OverpayError::InvalidReceiverProof(_)
+            | OverpayError::MissingPayIdProof(_)
+            | OverpayError::InvalidPayIdProof(_) => ErrorCode::InvalidProof,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<AggregationError>() {
+        return match error {
+            AggregationError::Overflow { .. } => ErrorCode::Overflow,
+            AggregationError::InconsistentProfit { .. } | AggregationError::MissingExpectedAmount(_) => {
+                ErrorCode::InvalidInput
+            }
+        };
+    }
+    if let Some(error) = error.downcast_ref::<InconsistentProfitResult>() {
+        return match error.field {
+            "epoch" => ErrorCode::EpochMismatch,
+            "vks_hash" => ErrorCode::VksMismatch,
+            "proxy address" => ErrorCode::ProxyMismatch,
+            "pay_ids_root" | "receipts_root" | "serv_ids_root" => ErrorCode::RootMismatch,
+            _ => ErrorCode::InvalidInput,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<CommitmentError>() {
+        return match error {
+            CommitmentError::UnknownVersion(_) => ErrorCode::InvalidInput,
+            CommitmentError::MixedVersions { .. } => ErrorCode::RootMismatch,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<TreeError>() {
+        return match error {
+            TreeError::KeyExists => ErrorCode::Duplicate,
+            TreeError::ZeroValue => ErrorCode::InvalidInput,
+            _ => ErrorCode::InvalidProof,
+        };
+    }
+
+    if error.is::<OverpayDetected>() {
+        ErrorCode::Overpay
+    } else if error.is::<InvalidReceiptSignature>()
+        || error.is::<crate::SignatureError>()
+        || error.is::<crate::models::UnauthorizedPayIdInfo>()
+    {
+        ErrorCode::SignatureInvalid
+    } else if error.is::<DuplicateReceipt>() || error.is::<DuplicatePayIdInfo>() || error.is::<DuplicateSettlementError>() {
+        ErrorCode::Duplicate
+    } else if error.is::<AmountOverflow>() {
+        ErrorCode::Overflow
+    } else if error.is::<ReceiptExpired>() || error.is::<StaleSettlement>() {
+        ErrorCode::Expired
+    } else if error.is::<SettlementEpochMismatch>() {
+        ErrorCode::EpochMismatch
+    } else if error.is::<PayIdsRootMismatch>() || error.is::<ReceiptsRootMismatch>() {
+        ErrorCode::RootMismatch
+    } else if error.is::<ReceiverCoverageError>() {
+        ErrorCode::ReceiverMismatch
+    } else if error.is::<ProofTooLarge>() {
+        ErrorCode::InvalidProof
+    } else if error.is::<TokenMismatch>() || error.is::<InputError>() || error.is::<SettledExceedsAuthorized>() {
+        ErrorCode::InvalidInput
+    } else if let Some(source) = error.source() {
+        // WithContext 等只附加上下文的包装按内部错误分类
+        classify(source)
+    } else {
+        ErrorCode::Unclassified
+    }
+}
+
+/// guest 入口：ReceiptsOverpayChecker::process，失败时返回 STAGE_OVERPAY_CHECK 阶段的错误码
+#[cfg(feature = "std")]
+pub fn check_overpay(
+    checker: &crate::ReceiptsOverpayChecker,
+) -> Result<crate::OverpayCheckResult, GuestError> {
+    checker
+        .process()
+        .map_err(|e| crate::pipeline::PayModelError::OverpayCheck(e).to_guest_code())
+}
+
+/// guest 入口：ReceiptsProfitCalculator::calculate
+#[cfg(feature = "std")]
+pub fn calculate_profit(
+    calculator: &crate::receipts::profit_calculator::ReceiptsProfitCalculator,
+) -> Result<crate::ProfitResult, GuestError> {
+    calculator
+        .calculate()
+        .map_err(|e| crate::pipeline::PayModelError::ProfitCalculation(e).to_guest_code())
+}
+
+/// guest 入口：ProxySettlementAggregator::aggregate
+#[cfg(feature = "std")]
+pub fn aggregate(
+    aggregator: &crate::proxy_settler::ProxySettlementAggregator,
+    profit_results: alloc::vec::Vec<crate::ProfitResult>,
+    overpay_result: crate::OverpayCheckResult,
+) -> Result<crate::ProxySettlementResult, GuestError> {
+    aggregator
+        .aggregate(profit_results, overpay_result)
+        .map_err(|e| crate::pipeline::PayModelError::Aggregation(e).to_guest_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_stable_and_unique() {
+        // 错误码已经发布，修改这里的数字会让旧的主机端错误解析出错
+        let pinned = [
+            (ErrorCode::Unclassified, 1),
+            (ErrorCode::RootMismatch, 2),
+            (ErrorCode::SignatureInvalid, 3),
+            (ErrorCode::Overpay, 4),
+            (ErrorCode::Duplicate, 5),
+            (ErrorCode::InvalidProof, 6),
+            (ErrorCode::EpochMismatch, 7),
+            (ErrorCode::Overflow, 8),
+            (ErrorCode::Expired, 9),
+            (ErrorCode::ReceiverMismatch, 10),
+            (ErrorCode::InvalidInput, 11),
+            (ErrorCode::VksMismatch, 12),
+            (ErrorCode::ProxyMismatch, 13),
+        ];
+        assert_eq!(pinned.len(), ErrorCode::ALL.len());
+        for ((error_code, code), listed) in pinned.into_iter().zip(ErrorCode::ALL) {
+            assert_eq!(error_code, listed);
+            assert_eq!(error_code.code(), code);
+            assert_eq!(ErrorCode::from_code(code), Some(error_code));
+            assert_eq!(GuestError::describe(code), error_code.description());
+        }
+
+        let mut descriptions: Vec<&str> = ErrorCode::ALL.iter().map(|code| code.description()).collect();
+        descriptions.sort_unstable();
+        descriptions.dedup();
+        assert_eq!(descriptions.len(), ErrorCode::ALL.len());
+
+        assert_eq!(ErrorCode::from_code(0), None);
+        assert_eq!(GuestError::describe(0), "unknown error code");
+        assert_eq!(GuestError::describe(14), "unknown error code");
+    }
+
+    #[test]
+    fn test_ensure() {
+        assert_eq!(ensure(true, ErrorCode::Overpay), Ok(()));
+        assert_eq!(ensure(false, ErrorCode::Overpay), Err(GuestError(4, 0)));
+        assert_eq!(ensure_with(false, ErrorCode::Duplicate, 7), Err(GuestError(5, 7)));
+
+        let error = GuestError::new(ErrorCode::RootMismatch, STAGE_AGGREGATION);
+        assert_eq!(error.error_code(), Some(ErrorCode::RootMismatch));
+        assert_eq!(GuestError::from_be_bytes(error.to_be_bytes()), error);
+        assert_eq!(error.to_be_bytes(), [0, 0, 0, 2, 0, 0, 0, 3]);
+        assert_eq!(error.to_string(), "Guest error 2 (root mismatch), detail 3");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_classify_typed_errors() {
+        use crate::models::segment_vc::Error as TreeError;
+        use crate::proxy_settler::{
+            AggregationError, InconsistentProfitResult, PayIdsRootMismatch, ReceiptsRootMismatch, SettlementEpochMismatch,
+        };
+        use crate::receipts::overpay_checker::{OverpayDetected, OverpayError};
+        use crate::receipts::{AmountOverflow, DuplicateReceipt, ReceiptExpired, SettledExceedsAuthorized};
+        use crate::BoxError;
+        use alloy_primitives::{B256, U256};
+
+        let overpay: BoxError = Box::new(OverpayDetected { pay_id: U256::from(1), total: U256::from(2), deposit: U256::from(1) });
+        let cases: Vec<(BoxError, ErrorCode)> = vec![
+            (overpay, ErrorCode::Overpay),
+            (Box::new(OverpayError::PayIdsRootMismatch { expected: B256::ZERO, actual: B256::ZERO }), ErrorCode::RootMismatch),
+            (Box::new(OverpayError::InvalidReceiverProof([1u8; 20])), ErrorCode::InvalidProof),
+            (
+                Box::new(OverpayError::InvalidInputs(Box::new(DuplicateReceipt {
+                    key: B256::ZERO,
+                    first: B256::ZERO,
+                    second: B256::ZERO,
+                }))),
+                ErrorCode::Duplicate,
+            ),
+            (Box::new(AmountOverflow::PayId(U256::from(1))), ErrorCode::Overflow),
+            (Box::new(AggregationError::Overflow { field: "amount" }), ErrorCode::Overflow),
+            (Box::new(InconsistentProfitResult { index: 1, field: "epoch" }), ErrorCode::EpochMismatch),
+            (Box::new(InconsistentProfitResult { index: 1, field: "serv_ids_root" }), ErrorCode::RootMismatch),
+            (Box::new(InconsistentProfitResult { index: 1, field: "vks_hash" }), ErrorCode::VksMismatch),
+            (Box::new(InconsistentProfitResult { index: 1, field: "proxy address" }), ErrorCode::ProxyMismatch),
+            (Box::new(SettlementEpochMismatch { input: "Overpay check", epoch: 2, expected: 1 }), ErrorCode::EpochMismatch),
+            (Box::new(PayIdsRootMismatch { overpay: B256::ZERO, profit: B256::repeat_byte(1) }), ErrorCode::RootMismatch),
+            (Box::new(ReceiptsRootMismatch { overpay: B256::ZERO, profit: B256::repeat_byte(1) }), ErrorCode::RootMismatch),
+            (
+                Box::new(crate::CommitmentError::MixedVersions {
+                    expected: crate::CommitmentVersion::V1,
+                    actual: crate::CommitmentVersion::V2,
+                }),
+                ErrorCode::RootMismatch,
+            ),
+            (Box::new(TreeError::StaleRoot), ErrorCode::InvalidProof),
+            (Box::new(TreeError::KeyExists), ErrorCode::Duplicate),
+            (Box::new(TreeError::ZeroValue), ErrorCode::InvalidInput),
+            (Box::new(crate::SignatureError::InvalidPublicKey), ErrorCode::SignatureInvalid),
+            (
+                Box::new(crate::models::UnauthorizedPayIdInfo { id: U256::from(1), sender: [1u8; 20], signer: None }),
+                ErrorCode::SignatureInvalid,
+            ),
+            (
+                Box::new(ReceiptExpired {
+                    pay_id: U256::from(1),
+                    serv_id: 1,
+                    receiver: [1u8; 20],
+                    valid_until: 1,
+                    current_time: 2,
+                }),
+                ErrorCode::Expired,
+            ),
+            (
+                Box::new(crate::receiver_settler::StaleSettlement { receipts_root: B256::ZERO }),
+                ErrorCode::Expired,
+            ),
+            (
+                Box::new(SettledExceedsAuthorized {
+                    pay_id: U256::from(1),
+                    serv_id: 1,
+                    receiver: [1u8; 20],
+                    authorized: U256::from(1),
+                    settled: U256::from(2),
+                }),
+                ErrorCode::InvalidInput,
+            ),
+            (
+                Box::new(crate::receipts::WithContext {
+                    index: Some(3),
+                    ..crate::receipts::WithContext::new(AmountOverflow::PayId(U256::from(1)))
+                }),
+                ErrorCode::Overflow,
+            ),
+            ("Empty profit results".into(), ErrorCode::Unclassified),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(classify(error.as_ref()), expected, "{}", error);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_guest_entry_points() -> Result<(), crate::BoxError> {
+        use crate::fixtures::{ScenarioBuilder, Violation};
+
+        let builder = || ScenarioBuilder::new(31).with_payment(1, 1, 0, 1000);
+
+        // 超付在 overpay 阶段报告为 Overpay
+        let overpaid = builder().with_violation(Violation::Overpay { pay_id: 1 }).build()?;
+        assert_eq!(
+            check_overpay(&overpaid.overpay_checker()).unwrap_err(),
+            GuestError::new(ErrorCode::Overpay, STAGE_OVERPAY_CHECK)
+        );
+
+        // 伪造的代理签名在利润计算阶段报告为 SignatureInvalid
+        let forged = builder().with_violation(Violation::WrongProxy { pay_id: 1 }).build()?;
+        let receiver = forged.receiver(0);
+        let proof = crate::ReceiptsOverpayChecker::new(forged.proxy, forged.pay_id_infos.clone(), forged.receipts.clone())
+            .process()?
+            .get_merkle_proof(receiver)?;
+        assert_eq!(
+            calculate_profit(&forged.profit_calculator(receiver, proof)).unwrap_err(),
+            GuestError::new(ErrorCode::SignatureInvalid, STAGE_PROFIT_CALCULATION)
+        );
+
+        // 正常场景三个入口都成功
+        let scenario = builder().build()?;
+        let overpay_result = check_overpay(&scenario.overpay_checker())?;
+        let receiver = scenario.receiver(0);
+        let proof = overpay_result.get_merkle_proof(receiver)?;
+        let profit_result = calculate_profit(&scenario.profit_calculator(receiver, proof))?;
+        aggregate(&scenario.aggregator(), vec![profit_result.clone()], overpay_result.clone())?;
+
+        // 聚合阶段的不一致各自报告对应的错误码
+        let mut stale = profit_result.clone();
+        stale.epoch = scenario.epoch + 1;
+        assert_eq!(
+            aggregate(&scenario.aggregator(), vec![stale], overpay_result.clone()).unwrap_err(),
+            GuestError::new(ErrorCode::EpochMismatch, STAGE_AGGREGATION)
+        );
+        let mut stale_overpay = overpay_result.clone();
+        stale_overpay.epoch = scenario.epoch + 1;
+        assert_eq!(
+            aggregate(&scenario.aggregator(), vec![profit_result.clone()], stale_overpay).unwrap_err(),
+            GuestError::new(ErrorCode::EpochMismatch, STAGE_AGGREGATION)
+        );
+        let mut other_pay_ids = overpay_result.clone();
+        other_pay_ids.pay_ids_root = alloy_primitives::B256::repeat_byte(7);
+        assert_eq!(
+            aggregate(&scenario.aggregator(), vec![profit_result.clone()], other_pay_ids).unwrap_err(),
+            GuestError::new(ErrorCode::RootMismatch, STAGE_AGGREGATION)
+        );
+        let mut other_vks = profit_result.clone();
+        other_vks.vks_hash = alloy_primitives::B256::repeat_byte(8);
+        assert_eq!(
+            aggregate(&scenario.aggregator(), vec![profit_result.clone(), other_vks], overpay_result.clone()).unwrap_err(),
+            GuestError::new(ErrorCode::VksMismatch, STAGE_AGGREGATION)
+        );
+        let mut other_proxy = profit_result.clone();
+        other_proxy.proxy = [0xee; 20];
+        assert_eq!(
+            aggregate(&scenario.aggregator(), vec![profit_result, other_proxy], overpay_result).unwrap_err(),
+            GuestError::new(ErrorCode::ProxyMismatch, STAGE_AGGREGATION)
+        );
+        Ok(())
+    }
+}
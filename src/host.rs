@@ -0,0 +1,722 @@
+//! 供运维脚本调用的粗粒度函数：把 JSON 场景编码为 guest 输入，检查 guest 提交的 public values
+//!
+//! 每个 guest 程序对应一对函数：
+//! - prepare_*_json：解析 JSON，返回规范的输入 JSON 字节（输入结构的 serde_json 编码，字段顺序固定，
+//!   地址、哈希和签名为 0x 开头的小写 hex，U256 为 0x hex，缺省字段已填充），用于存档和比较输入，
+//!   不是 guest 从 stdin 读取的布局，不能直接写入 SP1Stdin
+//! - verify_*_output：解码 public values 并做不依赖原始输入的检查，返回摘要
+//!
+//! 输入结构的 *_stdin_frames 方法按 wire 布局（models::wire、receipts::wire）编码 guest 从 stdin 读取的帧，
+//! 每一帧用 SP1Stdin::write_slice 写入
+//!
+//! JSON 中的地址、哈希和签名为 hex 字符串（0x 前缀可选，大小写不敏感）；U256 为十进制或 0x 开头的十六进制字符串，
+//! 不超过 u64 时也可以是 JSON 数字。解析错误带有字段路径，例如 `payments[1].amount: expected ...`。
+//! 错误包在对应阶段的 PayModelError 中，guest_checks::classify 把输入错误归为 InvalidInput
+use alloy_primitives::{B256, U256};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+use crate::models::segment_vc::MerkleProof;
+use crate::models::WireError;
+use crate::models::{FeeTier, PayIdInfo, ServiceFeeConfig};
+use crate::pipeline::PayModelError;
+use crate::proxy_settler::ProxySettlementAggregator;
+use crate::receipts::overpay_checker::OverpayError;
+use crate::receipts::partial_authorized_amount;
+use crate::receipts::profit_calculator::ReceiptsProfitCalculator;
+use crate::receipts::receipt_stdin_frames;
+use crate::serde_hex::decode_fixed;
+use crate::{
+    format_eth_address, BoxError, EthAddress, EthSignature, OverpayCheckResult, PaymentSettledByProxy, ProfitResult,
+    ProxySettlementResult, ReceiptsOverpayChecker, ReceiverSetCommitment, NATIVE_TOKEN,
+};
+
+/// JSON 输入中 path 处的字段缺失或格式错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// 带路径的 JSON 节点，根节点的路径为 `$`
+struct Node<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    fn root(value: &'a Value) -> Self {
+        Self { value, path: "$".into() }
+    }
+
+    fn error(&self, message: impl Into<String>) -> InputError {
+        InputError { path: self.path.clone(), message: message.into() }
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        if self.path == "$" {
+            name.into()
+        } else {
+            format!("{}.{}", self.path, name)
+        }
+    }
+
+    /// 缺失或为 null 时返回 None
+    fn optional(&self, name: &str) -> Result<Option<Node<'a>>, InputError> {
+        let Some(object) = self.value.as_object() else {
+            return Err(self.error("expected an object"));
+        };
+        Ok(object
+            .get(name)
+            .filter(|value| !value.is_null())
+            .map(|value| Node { value, path: self.child_path(name) }))
+    }
+
+    fn field(&self, name: &str) -> Result<Node<'a>, InputError> {
+        self.optional(name)?.ok_or_else(|| InputError {
+            path: self.child_path(name),
+            message: "missing field".into(),
+        })
+    }
+
+    fn elements(&self) -> Result<Vec<Node<'a>>, InputError> {
+        let Some(array) = self.value.as_array() else {
+            return Err(self.error("expected an array"));
+        };
+        Ok(array
+            .iter()
+            .enumerate()
+            .map(|(index, value)| Node { value, path: format!("{}[{}]", self.path, index) })
+            .collect())
+    }
+
+    fn list<T>(&self, parse: impl Fn(&Node<'a>) -> Result<T, InputError>) -> Result<Vec<T>, InputError> {
+        self.elements()?.iter().map(parse).collect()
+    }
+
+    fn str(&self) -> Result<&'a str, InputError> {
+        self.value.as_str().ok_or_else(|| self.error("expected a string"))
+    }
+
+    fn bytes<const N: usize>(&self) -> Result<[u8; N], InputError> {
+        decode_fixed::<N>(self.str()?).map_err(|message| self.error(message))
+    }
+
+    fn address(&self) -> Result<EthAddress, InputError> {
+        self.bytes::<20>()
+    }
+
+    fn hash(&self) -> Result<B256, InputError> {
+        self.bytes::<32>().map(B256::from)
+    }
+
+    fn signature(&self) -> Result<EthSignature, InputError> {
+        self.bytes::<65>()
+    }
+
+    fn bool(&self) -> Result<bool, InputError> {
+        self.value.as_bool().ok_or_else(|| self.error("expected a boolean"))
+    }
+
+    fn u256(&self) -> Result<U256, InputError> {
+        match self.value {
+            Value::Number(number) => number
+                .as_u64()
+                .map(U256::from)
+                .ok_or_else(|| self.error(format!("expected a non-negative integer, got {}; use a string for values above u64", number))),
+            Value::String(text) => parse_u256(text).map_err(|message| self.error(message)),
+            _ => Err(self.error("expected an integer or a decimal/hex string")),
+        }
+    }
+
+    /// 整数字段，取值范围由 T 决定
+    fn uint<T: TryFrom<U256>>(&self) -> Result<T, InputError> {
+        let value = self.u256()?;
+        T::try_from(value).map_err(|_| self.error(format!("{} is out of range for {}", value, core::any::type_name::<T>())))
+    }
+
+    /// 库中已有 serde 表示的类型（MerkleProof、结果结构）按其 JSON 表示解析
+    fn decode<T: DeserializeOwned>(&self) -> Result<T, InputError> {
+        serde_json::from_value(self.value.clone()).map_err(|err| self.error(err.to_string()))
+    }
+}
+
+/// 十进制或 0x 开头的十六进制
+fn parse_u256(text: &str) -> Result<U256, String> {
+    let (digits, radix) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (text, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix as u32)) {
+        return Err(format!("expected a decimal or 0x-prefixed hex integer, got {:?}", text));
+    }
+    U256::from_str_radix(digits, radix).map_err(|err| format!("invalid integer {:?}: {}", text, err))
+}
+
+fn parse_json(json: &str) -> Result<Value, InputError> {
+    serde_json::from_str(json).map_err(|err| InputError { path: "$".into(), message: format!("invalid JSON: {}", err) })
+}
+
+fn parse_pay_id_info(node: &Node) -> Result<PayIdInfo, InputError> {
+    Ok(PayIdInfo {
+        id: node.field("id")?.u256()?,
+        amount: node.field("amount")?.u256()?,
+        sender: node.field("sender")?.address()?,
+        proxy: node.field("proxy")?.address()?,
+        state: node.field("state")?.uint()?,
+        created_at: node.field("created_at")?.uint()?,
+        closing_time: node.field("closing_time")?.uint()?,
+        token: node.optional("token")?.map(|token| token.address()).transpose()?.unwrap_or(NATIVE_TOKEN),
+        sig_sender: node.optional("sig_sender")?.map(|signature| signature.signature()).transpose()?,
+    })
+}
+
+fn parse_payment(node: &Node) -> Result<PaymentSettledByProxy, InputError> {
+    let amount = node.field("amount")?.u256()?;
+    Ok(PaymentSettledByProxy {
+        pay_id: node.field("pay_id")?.u256()?,
+        serv_id: node.field("serv_id")?.uint()?,
+        amount,
+        receiver: node.field("receiver")?.address()?,
+        sig_sender: node.field("sig_sender")?.signature()?,
+        settled: node.field("settled")?.bool()?,
+        sig_proxy: node.field("sig_proxy")?.signature()?,
+        nonce: node.optional("nonce")?.map(|nonce| nonce.uint()).transpose()?,
+        valid_until: node.optional("valid_until")?.map(|valid_until| valid_until.uint()).transpose()?,
+        token: node.optional("token")?.map(|token| token.address()).transpose()?.unwrap_or(NATIVE_TOKEN),
+        authorized_amount: partial_authorized_amount(
+            amount,
+            node.optional("authorized_amount")?.map(|authorized| authorized.u256()).transpose()?,
+        ),
+    })
+}
+
+fn parse_fee_config(node: &Node) -> Result<ServiceFeeConfig, InputError> {
+    let tiers = match node.optional("tiers")? {
+        Some(tiers) => tiers.list(|tier| {
+            Ok(FeeTier {
+                threshold: tier.field("threshold")?.u256()?,
+                system_fee_rate: tier.field("system_fee_rate")?.uint()?,
+                proxy_fee_rate: tier.field("proxy_fee_rate")?.uint()?,
+            })
+        })?,
+        None => Vec::new(),
+    };
+    Ok(ServiceFeeConfig {
+        serv_id: node.field("serv_id")?.uint()?,
+        system_fee_rate: node.field("system_fee_rate")?.uint()?,
+        proxy_fee_rate: node.field("proxy_fee_rate")?.uint()?,
+        tiers,
+    })
+}
+
+fn optional_epoch(node: &Node) -> Result<u64, InputError> {
+    Ok(node.optional("epoch")?.map(|epoch| epoch.uint()).transpose()?.unwrap_or(0))
+}
+
+fn parse_expected_address(value: &str, name: &str) -> Result<EthAddress, InputError> {
+    decode_fixed::<20>(value).map_err(|message| InputError { path: name.into(), message })
+}
+
+fn encode<T: Serialize>(input: &T) -> Vec<u8> {
+    serde_json::to_vec(input).expect("guest inputs always serialize")
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+/// overpay guest 的输入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverpayInput {
+    #[serde(with = "crate::serde_hex")]
+    pub channel: EthAddress,
+    pub epoch: u64,
+    pub pay_id_infos: Vec<PayIdInfo>,
+    pub payments: Vec<PaymentSettledByProxy>,
+}
+
+impl OverpayInput {
+    /// JSON：`{ channel, epoch?, pay_id_infos: [...], payments: [...] }`
+    pub fn from_json(json: &str) -> Result<Self, InputError> {
+        let value = parse_json(json)?;
+        let root = Node::root(&value);
+        Ok(Self {
+            channel: root.field("channel")?.address()?,
+            epoch: optional_epoch(&root)?,
+            pay_id_infos: root.field("pay_id_infos")?.list(parse_pay_id_info)?,
+            payments: root.field("payments")?.list(parse_payment)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BoxError> {
+        decode(bytes)
+    }
+
+    /// payments 的 stdin 帧：布局一帧，之后每张收据一帧
+    pub fn receipt_stdin_frames(&self) -> Result<Vec<Vec<u8>>, WireError> {
+        receipt_stdin_frames(&self.payments)
+    }
+
+    /// guest 中使用的检查器，轮次与输入相同
+    pub fn checker(&self) -> ReceiptsOverpayChecker {
+        ReceiptsOverpayChecker::new(self.channel, self.pay_id_infos.clone(), self.payments.clone()).with_epoch(self.epoch)
+    }
+}
+
+/// profit guest 的输入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfitInput {
+    pub vks_hash: B256,
+    #[serde(with = "crate::serde_hex")]
+    pub proxy: EthAddress,
+    #[serde(with = "crate::serde_hex")]
+    pub receiver: EthAddress,
+    pub epoch: u64,
+    pub receipts: Vec<PaymentSettledByProxy>,
+    pub merkle_proof: MerkleProof,
+    pub pay_id_infos: Vec<PayIdInfo>,
+    pub fee_configs: Vec<ServiceFeeConfig>,
+}
+
+impl ProfitInput {
+    /// JSON：`{ vks_hash?, proxy, receiver, epoch?, receipts: [...], merkle_proof, pay_id_infos: [...], fee_configs: [...] }`，
+    /// merkle_proof 为 MerkleProof 的 JSON 表示
+    pub fn from_json(json: &str) -> Result<Self, InputError> {
+        let value = parse_json(json)?;
+        let root = Node::root(&value);
+        Ok(Self {
+            vks_hash: root.optional("vks_hash")?.map(|vks_hash| vks_hash.hash()).transpose()?.unwrap_or_default(),
+            proxy: root.field("proxy")?.address()?,
+            receiver: root.field("receiver")?.address()?,
+            epoch: optional_epoch(&root)?,
+            receipts: root.field("receipts")?.list(parse_payment)?,
+            merkle_proof: root.field("merkle_proof")?.decode()?,
+            pay_id_infos: root.field("pay_id_infos")?.list(parse_pay_id_info)?,
+            fee_configs: root.field("fee_configs")?.list(parse_fee_config)?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BoxError> {
+        decode(bytes)
+    }
+
+    /// receipts 的 stdin 帧：布局一帧，之后每张收据一帧
+    pub fn receipt_stdin_frames(&self) -> Result<Vec<Vec<u8>>, WireError> {
+        receipt_stdin_frames(&self.receipts)
+    }
+
+    /// merkle_proof 的 stdin 帧
+    pub fn merkle_proof_stdin_frame(&self) -> Vec<u8> {
+        self.merkle_proof.to_stdin_bytes()
+    }
+
+    /// 每个费率配置一帧；stdin 布局只有基础费率，有 tiers 的配置返回 WireError::NotInLayout
+    pub fn fee_config_stdin_frames(&self) -> Result<Vec<Vec<u8>>, WireError> {
+        self.fee_configs.iter().map(ServiceFeeConfig::to_stdin_bytes).collect()
+    }
+
+    pub fn calculator(&self) -> ReceiptsProfitCalculator {
+        ReceiptsProfitCalculator::new(
+            self.vks_hash,
+            self.receiver,
+            self.proxy,
+            self.receipts.clone(),
+            self.merkle_proof.clone(),
+            self.pay_id_infos.clone(),
+            self.fee_configs.clone(),
+        )
+        .with_epoch(self.epoch)
+    }
+}
+
+/// 聚合 guest 的输入
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregationInput {
+    pub epoch: u64,
+    pub profit_results: Vec<ProfitResult>,
+    pub overpay_result: OverpayCheckResult,
+    /// 非空时同时验证结算总额不超过存款总额
+    pub pay_id_infos: Vec<PayIdInfo>,
+}
+
+impl AggregationInput {
+    /// JSON：`{ epoch?, profit_results: [...], overpay_result, pay_id_infos? }`，
+    /// profit_results 和 overpay_result 为 ProfitResult 和 OverpayCheckResult 的 JSON 表示
+    pub fn from_json(json: &str) -> Result<Self, InputError> {
+        let value = parse_json(json)?;
+        let root = Node::root(&value);
+        Ok(Self {
+            epoch: optional_epoch(&root)?,
+            profit_results: root.field("profit_results")?.list(|result| result.decode())?,
+            overpay_result: root.field("overpay_result")?.decode()?,
+            pay_id_infos: match root.optional("pay_id_infos")? {
+                Some(infos) => infos.list(parse_pay_id_info)?,
+                None => Vec::new(),
+            },
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BoxError> {
+        decode(bytes)
+    }
+
+    pub fn aggregate(&self) -> Result<ProxySettlementResult, BoxError> {
+        let aggregator = ProxySettlementAggregator::new().with_epoch(self.epoch);
+        if self.pay_id_infos.is_empty() {
+            aggregator.aggregate(self.profit_results.clone(), self.overpay_result.clone())
+        } else {
+            aggregator.aggregate_with_deposits(self.profit_results.clone(), self.overpay_result.clone(), &self.pay_id_infos)
+        }
+    }
+}
+
+/// overpay guest 输出的摘要
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverpaySummary {
+    pub payments_root: B256,
+    pub pay_ids_root: B256,
+    pub receivers_root: B256,
+    pub receivers: Vec<EthAddress>, // 按地址升序
+    pub epoch: u64,
+    pub excluded_root: Option<B256>, // 被排除收据的承诺，没有收据被排除时为 None
+}
+
+/// profit guest 输出的摘要
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfitSummary {
+    pub receiver: EthAddress,
+    pub proxy: EthAddress,
+    pub epoch: u64,
+    pub receipts_root: B256,
+    pub pay_ids_root: B256,
+    pub serv_ids_root: B256,
+    pub system_profit: U256,
+    pub proxy_profit: U256,
+    pub receiver_profit: U256,
+    pub total: U256, // 三项利润之和，即该接收者的收据总额
+}
+
+/// 聚合 guest 输出的摘要
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettlementSummary {
+    pub proxy: EthAddress,
+    pub epoch: u64,
+    pub settlement_id: B256,
+    pub pay_ids_root: B256,
+    pub serv_ids_root: B256,
+    pub system_profits: U256,
+    pub proxy_profits: U256,
+    pub amount: U256,
+}
+
+/// 解析 JSON 场景，返回 overpay guest 输入的规范 JSON 字节
+pub fn prepare_overpay_json(json: &str) -> Result<Vec<u8>, PayModelError> {
+    OverpayInput::from_json(json).map(|input| input.to_bytes()).map_err(|err| PayModelError::OverpayCheck(Box::new(err)))
+}
+
+/// 检查 overpay guest 的 public values：必须是规范编码（receiver_proofs 按接收者升序、receivers_root
+/// 与接收者集合一致），每个接收者的证明都以 payments_root 为根且有效
+///
+/// public values 不包含通道地址，无法检查通道，通道由 guest 的输入决定
+pub fn verify_overpay_output(public_values: &[u8]) -> Result<OverpaySummary, PayModelError> {
+    check_overpay_output(public_values).map_err(PayModelError::OverpayCheck)
+}
+
+fn check_overpay_output(public_values: &[u8]) -> Result<OverpaySummary, BoxError> {
+    let result = OverpayCheckResult::from_public_values(public_values)?;
+    if result.to_public_values() != public_values {
+        return Err("Overpay public values are not canonically encoded".into());
+    }
+    for proof in &result.receiver_proofs {
+        if !proof.verify(result.payments_root)? {
+            return Err(Box::new(OverpayError::InvalidReceiverProof(proof.receiver)));
+        }
+    }
+    Ok(OverpaySummary {
+        payments_root: result.payments_root,
+        pay_ids_root: result.pay_ids_root,
+        receivers_root: ReceiverSetCommitment::from_overpay_result(&result).root(),
+        receivers: result.receiver_proofs.iter().map(|proof| proof.receiver).collect(),
+        epoch: result.epoch,
+        excluded_root: result.excluded_root,
+    })
+}
+
+/// 解析 JSON 场景，返回 profit guest 输入的规范 JSON 字节
+pub fn prepare_profit_json(json: &str) -> Result<Vec<u8>, PayModelError> {
+    ProfitInput::from_json(json).map(|input| input.to_bytes()).map_err(|err| PayModelError::ProfitCalculation(Box::new(err)))
+}
+
+/// 检查 profit guest 的 public values：proxy 必须是 expected_proxy，三项利润之和不能溢出
+pub fn verify_profit_output(public_values: &[u8], expected_proxy: &str) -> Result<ProfitSummary, PayModelError> {
+    check_profit_output(public_values, expected_proxy).map_err(PayModelError::ProfitCalculation)
+}
+
+fn check_profit_output(public_values: &[u8], expected_proxy: &str) -> Result<ProfitSummary, BoxError> {
+    let proxy = parse_expected_address(expected_proxy, "expected_proxy")?;
+    let result = ProfitResult::from_public_values(public_values)?;
+    check_proxy(proxy, result.proxy)?;
+    let total = result
+        .system_profit
+        .checked_add(result.proxy_profit)
+        .and_then(|sum| sum.checked_add(result.receiver_profit))
+        .ok_or("Profit result total overflows")?;
+    Ok(ProfitSummary {
+        receiver: result.receiver,
+        proxy: result.proxy,
+        epoch: result.epoch,
+        receipts_root: result.receipts_root,
+        pay_ids_root: result.pay_ids_root,
+        serv_ids_root: result.serv_ids_root,
+        system_profit: result.system_profit,
+        proxy_profit: result.proxy_profit,
+        receiver_profit: result.receiver_profit,
+        total,
+    })
+}
+
+/// 解析 JSON 场景，返回聚合 guest 输入的规范 JSON 字节
+pub fn prepare_aggregation_json(json: &str) -> Result<Vec<u8>, PayModelError> {
+    AggregationInput::from_json(json).map(|input| input.to_bytes()).map_err(|err| PayModelError::Aggregation(Box::new(err)))
+}
+
+/// 检查聚合 guest 的 public values：proxy 必须是 expected_proxy，amount 不小于系统和代理利润之和，
+/// 按 public values 重新计算的 settlement_id 必须一致
+pub fn verify_aggregation_output(public_values: &[u8], expected_proxy: &str) -> Result<SettlementSummary, PayModelError> {
+    check_aggregation_output(public_values, expected_proxy).map_err(PayModelError::Aggregation)
+}
+
+fn check_aggregation_output(public_values: &[u8], expected_proxy: &str) -> Result<SettlementSummary, BoxError> {
+    let proxy = parse_expected_address(expected_proxy, "expected_proxy")?;
+    let result = ProxySettlementResult::from_public_values(public_values)?;
+    check_proxy(proxy, result.proxy)?;
+    let fees = result.system_profits.checked_add(result.proxy_profits).ok_or("Settlement fees overflow")?;
+    if fees > result.amount {
+        return Err(format!("Settlement fees {} exceed amount {}", fees, result.amount).into());
+    }
+    if !result.verify_settlement_id(result.pay_ids_root) {
+        return Err(format!("Settlement id mismatch: {} does not commit to the settlement fields", result.settlement_id).into());
+    }
+    Ok(SettlementSummary {
+        proxy: result.proxy,
+        epoch: result.epoch,
+        settlement_id: result.settlement_id,
+        pay_ids_root: result.pay_ids_root,
+        serv_ids_root: result.serv_ids_root,
+        system_profits: result.system_profits,
+        proxy_profits: result.proxy_profits,
+        amount: result.amount,
+    })
+}
+
+fn check_proxy(expected: EthAddress, actual: EthAddress) -> Result<(), BoxError> {
+    if expected != actual {
+        return Err(format!(
+            "Proxy mismatch: expected {}, got {}",
+            format_eth_address(&expected),
+            format_eth_address(&actual)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guest_checks::ErrorCode;
+    use crate::{CommitmentVersion, ProfitResultStruct, ReceiptStdinLayout};
+    use alloy_sol_types::SolType;
+
+    const OVERPAY_JSON: &str = include_str!("../tests/fixtures/host_overpay.json");
+    const PROFIT_JSON: &str = include_str!("../tests/fixtures/host_profit.json");
+    const AGGREGATION_JSON: &str = include_str!("../tests/fixtures/host_aggregation.json");
+    const CHANNEL: &str = "0x9999999999999999999999999999999999999999";
+
+    fn input_error(err: PayModelError) -> InputError {
+        let source = std::error::Error::source(&err).expect("stage error has a source");
+        source.downcast_ref::<InputError>().expect("expected InputError").clone()
+    }
+
+    // 把 JSON 中 pointer 处的值替换为 value
+    fn with_value(json: &str, pointer: &str, value: Value) -> String {
+        let mut document: Value = serde_json::from_str(json).unwrap();
+        *document.pointer_mut(pointer).unwrap() = value;
+        document.to_string()
+    }
+
+    #[test]
+    fn test_prepare_overpay_json() -> Result<(), BoxError> {
+        let bytes = prepare_overpay_json(OVERPAY_JSON)?;
+        let input = OverpayInput::from_bytes(&bytes)?;
+        assert_eq!(input.channel, [0x99u8; 20]);
+        assert_eq!(input.epoch, 3);
+        assert_eq!(input.pay_id_infos.len(), 2);
+        assert_eq!(input.payments.len(), 3);
+
+        // 十进制、十六进制字符串和 JSON 数字得到相同的 U256
+        assert_eq!(input.pay_id_infos[0].amount, U256::from(10_000u32));
+        assert_eq!(input.pay_id_infos[1].amount, U256::from(0x1f40u32));
+        assert_eq!(input.payments[0].amount, U256::from(1000u32));
+        assert_eq!(input.payments[2].nonce, Some(7));
+        assert_eq!(input.payments[0].token, NATIVE_TOKEN);
+
+        // 规范字节与格式无关：编码后再解析得到相同的字节
+        assert_eq!(OverpayInput::from_bytes(&bytes)?.to_bytes(), bytes);
+        let reformatted = serde_json::to_string_pretty(&serde_json::from_str::<Value>(OVERPAY_JSON)?)?;
+        assert_eq!(prepare_overpay_json(&reformatted)?, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_errors_name_field() {
+        let cases = [
+            (with_value(OVERPAY_JSON, "/payments/1/amount", Value::from(-1)), "payments[1].amount"),
+            (with_value(OVERPAY_JSON, "/payments/0/receiver", Value::from("0x1234")), "payments[0].receiver"),
+            (with_value(OVERPAY_JSON, "/pay_id_infos/1/state", Value::from(256)), "pay_id_infos[1].state"),
+            (with_value(OVERPAY_JSON, "/pay_id_infos/0/id", Value::from("12abc")), "pay_id_infos[0].id"),
+            (with_value(OVERPAY_JSON, "/payments", Value::from("none")), "payments"),
+            (with_value(OVERPAY_JSON, "/channel", Value::Null), "channel"),
+            ("{".to_string(), "$"),
+        ];
+        for (json, path) in cases {
+            let err = prepare_overpay_json(&json).unwrap_err();
+            assert!(matches!(err, PayModelError::OverpayCheck(_)));
+            assert_eq!(err.to_guest_code().error_code(), Some(ErrorCode::InvalidInput));
+            assert_eq!(input_error(err).path, path);
+        }
+
+        let err = input_error(prepare_overpay_json(&with_value(OVERPAY_JSON, "/payments/0/serv_id", Value::from("0x100000000"))).unwrap_err());
+        assert_eq!(err.to_string(), "payments[0].serv_id: 4294967296 is out of range for u32");
+
+        // 嵌套的库类型保留 serde 的描述
+        let err = prepare_profit_json(&with_value(PROFIT_JSON, "/merkle_proof/root_hash", Value::from(1))).unwrap_err();
+        assert!(matches!(err, PayModelError::ProfitCalculation(_)));
+        assert_eq!(input_error(err).path, "merkle_proof");
+        let err = prepare_aggregation_json(&with_value(AGGREGATION_JSON, "/profit_results/0/receiver", Value::from("0x12")));
+        assert_eq!(input_error(err.unwrap_err()).path, "profit_results[0]");
+    }
+
+    #[test]
+    fn test_verify_overpay_output() -> Result<(), BoxError> {
+        let input = OverpayInput::from_bytes(&prepare_overpay_json(OVERPAY_JSON)?)?;
+        let result = input.checker().process()?;
+        let public_values = result.to_public_values();
+
+        let summary = verify_overpay_output(&public_values)?;
+        assert_eq!(summary.payments_root, result.payments_root);
+        assert_eq!(summary.receivers, vec![[0x11u8; 20], [0x22u8; 20]]);
+        assert_eq!(summary.receivers_root, ReceiverSetCommitment::from_overpay_result(&result).root());
+        assert_eq!(summary.epoch, 3);
+
+        // 篡改证明和非规范编码
+        let mut forged = result.clone();
+        forged.receiver_proofs[0].proof.root_hash = B256::repeat_byte(1);
+        assert!(verify_overpay_output(&forged.to_public_values()).is_err());
+        let mut reordered = crate::OverpayCheckResultStruct::from(result);
+        reordered.receiver_proofs.reverse();
+        let bytes = crate::OverpayCheckResultStruct::abi_encode(&reordered);
+        assert!(verify_overpay_output(&bytes).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profit_input_and_output() -> Result<(), BoxError> {
+        let input = ProfitInput::from_bytes(&prepare_profit_json(PROFIT_JSON)?)?;
+        assert_eq!(input.receiver, [0x11u8; 20]);
+        assert_eq!(input.merkle_proof.root_hash, B256::repeat_byte(0x66));
+        assert_eq!(input.fee_configs[1].tiers[0].threshold, U256::from(5000u32));
+        assert_eq!(input.vks_hash, B256::ZERO);
+
+        let result = ProfitResult {
+            vks_hash: B256::ZERO,
+            receiver: input.receiver,
+            proxy: input.proxy,
+            receipts_root: input.merkle_proof.root_hash,
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(50u32),
+            proxy_profit: U256::from(100u32),
+            receiver_profit: U256::from(850u32),
+            epoch: input.epoch,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+        let summary = verify_profit_output(&result.to_public_values(), CHANNEL)?;
+        assert_eq!(summary.total, U256::from(1000u32));
+        assert_eq!(summary.receipts_root, result.receipts_root);
+
+        let err = verify_profit_output(&result.to_public_values(), "0x2222222222222222222222222222222222222222").unwrap_err();
+        assert!(err.to_string().contains("Proxy mismatch"));
+        let mut overflow = result.clone().to_struct();
+        overflow.receiver_profit = U256::MAX;
+        assert!(verify_profit_output(&ProfitResultStruct::abi_encode(&overflow), CHANNEL).is_err());
+        assert!(verify_profit_output(&[0u8; 31], CHANNEL).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_profit_stdin_frames() -> Result<(), BoxError> {
+        let mut input = ProfitInput::from_bytes(&prepare_profit_json(PROFIT_JSON)?)?;
+        let frames = input.receipt_stdin_frames()?;
+        let layout = ReceiptStdinLayout::from_bits(frames[0][0]).ok_or("unknown layout")?;
+        assert_eq!(layout, ReceiptStdinLayout::for_receipts(&input.receipts));
+        for (frame, receipt) in frames[1..].iter().zip(&input.receipts) {
+            assert_eq!(PaymentSettledByProxy::from_stdin_bytes(layout, frame)?.hash(), receipt.hash());
+        }
+        assert_eq!(MerkleProof::from_stdin_bytes(&input.merkle_proof_stdin_frame())?, input.merkle_proof);
+
+        // 分级费率不能写入 stdin，只有基础费率时每个配置 8 字节
+        assert_eq!(input.fee_config_stdin_frames().unwrap_err(), WireError::NotInLayout { field: "tiers" });
+        input.fee_configs.iter_mut().for_each(|config| config.tiers.clear());
+        let frames = input.fee_config_stdin_frames()?;
+        assert_eq!(frames.len(), input.fee_configs.len());
+        assert_eq!(ServiceFeeConfig::from_stdin_bytes(&frames[1])?.serv_id, input.fee_configs[1].serv_id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation_input_and_output() -> Result<(), BoxError> {
+        let input = AggregationInput::from_bytes(&prepare_aggregation_json(AGGREGATION_JSON)?)?;
+        assert_eq!(input.profit_results.len(), 1);
+        assert_eq!(input.overpay_result.receiver_proofs[0].receiver, [0x11u8; 20]);
+        assert!(input.pay_id_infos.is_empty());
+        let settlement = input.aggregate()?;
+
+        let summary = verify_aggregation_output(&settlement.to_public_values(), CHANNEL)?;
+        assert_eq!(summary.amount, settlement.amount);
+        assert_eq!(summary.settlement_id, settlement.settlement_id);
+
+        let mut forged = settlement.clone();
+        forged.amount += U256::from(1u32);
+        let err = verify_aggregation_output(&forged.to_public_values(), CHANNEL).unwrap_err();
+        assert!(err.to_string().contains("Settlement id mismatch"));
+        forged.amount = U256::ZERO;
+        assert!(verify_aggregation_output(&forged.to_public_values(), CHANNEL).is_err());
+        assert!(verify_aggregation_output(&settlement.to_public_values(), "0x1111111111111111111111111111111111111111").is_err());
+        Ok(())
+    }
+}
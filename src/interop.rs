@@ -0,0 +1,130 @@
+//! crate 的签名、地址表示与 alloy 类型之间的转换
+//!
+//! crate 内的签名是 r ‖ s ‖ v 的 65 字节，v 为恢复 id 0/1（sign_message 的输出）；alloy 的 PrimitiveSignature
+//! 只保存 y_parity。转换到 alloy 时 v 接受 0/1 和 27/28，转换回来时 v 总是 0/1，recover_public_key 只接受这种形式。
+//! 依赖中的 alloy-primitives 没有开启 k256，alloy 自身不能恢复签名者，需要时使用这里的适配函数
+use alloy_primitives::{Address, PrimitiveSignature, U256};
+use libsecp256k1::PublicKey;
+
+use crate::signing_key::AsSecretKey;
+use crate::{BoxError, EthSignature, SerializableSignature, SignatureError};
+
+/// v 为 0/1 或 27/28，其他值（包括 libsecp256k1 的 2/3）返回 InvalidRecoveryId
+pub fn eth_signature_to_alloy(signature: &EthSignature) -> Result<PrimitiveSignature, SignatureError> {
+    let y_parity = match signature[64] {
+        0 | 27 => false,
+        1 | 28 => true,
+        v => return Err(SignatureError::InvalidRecoveryId(v)),
+    };
+    let r = U256::from_be_slice(&signature[..32]);
+    let s = U256::from_be_slice(&signature[32..64]);
+    Ok(PrimitiveSignature::new(r, s, y_parity))
+}
+
+/// v 为 0/1，与 sign_message 的输出相同
+pub fn alloy_signature_to_eth(signature: &PrimitiveSignature) -> EthSignature {
+    let mut bytes = [0u8; 65];
+    bytes[..32].copy_from_slice(&signature.r().to_be_bytes::<32>());
+    bytes[32..64].copy_from_slice(&signature.s().to_be_bytes::<32>());
+    bytes[64] = signature.v() as u8;
+    bytes
+}
+
+/// 与 get_ethereum_address 相同的地址
+pub fn public_key_to_alloy_address(public_key: &PublicKey) -> Address {
+    crate::addr::to_alloy(crate::get_ethereum_address(public_key))
+}
+
+/// sign_message，返回 alloy 的签名
+pub fn sign_message_alloy<K: AsSecretKey + ?Sized>(secret_key: &K, message: &[u8]) -> Result<PrimitiveSignature, BoxError> {
+    let signature = crate::sign_message(secret_key, message)?;
+    Ok(eth_signature_to_alloy(&signature)?)
+}
+
+/// recover_public_key，接受 alloy 的签名
+pub fn recover_public_key_alloy(signature: &PrimitiveSignature, message: &[u8]) -> Result<PublicKey, BoxError> {
+    crate::recover_public_key(&alloy_signature_to_eth(signature), message)
+}
+
+/// 签名者的 alloy 地址
+pub fn recover_address_alloy(signature: &PrimitiveSignature, message: &[u8]) -> Result<Address, BoxError> {
+    recover_public_key_alloy(signature, message).map(|public_key| public_key_to_alloy_address(&public_key))
+}
+
+impl From<PrimitiveSignature> for SerializableSignature {
+    fn from(signature: PrimitiveSignature) -> Self {
+        Self(alloy_signature_to_eth(&signature))
+    }
+}
+
+impl TryFrom<&SerializableSignature> for PrimitiveSignature {
+    type Error = SignatureError;
+
+    fn try_from(signature: &SerializableSignature) -> Result<Self, Self::Error> {
+        eth_signature_to_alloy(&signature.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::address;
+    use libsecp256k1::SecretKey;
+
+    fn secret_key(scalar: u8) -> SecretKey {
+        let mut bytes = [0u8; 32];
+        bytes[31] = scalar;
+        SecretKey::parse(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_signature_round_trip() -> Result<(), BoxError> {
+        for scalar in 1..=8 {
+            let key = secret_key(scalar);
+            let message = [scalar; 40];
+            let signature = crate::sign_message(&key, &message)?;
+
+            let alloy = eth_signature_to_alloy(&signature)?;
+            assert_eq!(alloy_signature_to_eth(&alloy), signature);
+            assert_eq!(sign_message_alloy(&key, &message)?, alloy);
+            assert_eq!(recover_public_key_alloy(&alloy, &message)?, crate::recover_public_key(&signature, &message)?);
+
+            // 27/28 形式的 v 归一化为 0/1
+            let mut legacy = signature;
+            legacy[64] += 27;
+            assert_eq!(eth_signature_to_alloy(&legacy)?, alloy);
+            assert_eq!(alloy_signature_to_eth(&eth_signature_to_alloy(&legacy)?), signature);
+
+            let wrapped = SerializableSignature::from(alloy);
+            assert_eq!(wrapped.0, signature);
+            assert_eq!(PrimitiveSignature::try_from(&wrapped)?, alloy);
+        }
+
+        let mut invalid = crate::sign_message(&secret_key(1), b"message")?;
+        for v in [2u8, 26, 29, 0xff] {
+            invalid[64] = v;
+            assert_eq!(eth_signature_to_alloy(&invalid), Err(SignatureError::InvalidRecoveryId(v)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_agrees_with_alloy() -> Result<(), BoxError> {
+        // 私钥 1 对应的地址是公开的固定值
+        let key = secret_key(1);
+        let public_key = crate::get_public_key(&key);
+        let expected = address!("7e5f4552091a69125d5dfcb7b8c2659029395bdf");
+        assert_eq!(public_key_to_alloy_address(&public_key), expected);
+        assert_eq!(Address::from_raw_public_key(&public_key.serialize()[1..]), expected);
+
+        let message = b"cross-check";
+        let alloy = sign_message_alloy(&key, message)?;
+        assert_eq!(recover_address_alloy(&alloy, message)?, expected);
+
+        // alloy 的 65 字节编码使用 27/28，r、s 与 crate 的编码相同
+        let signature = crate::sign_message(&key, message)?;
+        let bytes = alloy.as_bytes();
+        assert_eq!((&bytes[..64], bytes[64]), (&signature[..64], signature[64] + 27));
+        Ok(())
+    }
+}
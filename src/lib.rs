@@ -1,36 +1,86 @@
+//! 关闭默认的 std 特性时只编译验证子集：哈希、MerkleProof::verify、SettlementProof::verify、
+//! 签名恢复和结果结构，依赖 core + alloc。其余模块（收据处理、结算、SegmentVC 等）需要 std
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
 use alloy_primitives::ruint::aliases::U256;
 use alloy_sol_types::sol;
 use alloy_sol_types::SolType;  
 use models::segment_vc::MerkleProof;
-use serde_json; 
-use alloy_primitives::{Address, B256, U256 as AlloyU256,Bytes};
+use models::TreeHashAlgorithm;
+use alloy_primitives::{hex, B256, U256 as AlloyU256,Bytes};
+use core::fmt;
+use core::str::FromStr;
 
 use libsecp256k1::{
     Message, SecretKey, PublicKey, Signature, 
     RecoveryId, recover, sign,verify
 };
-use sp1_zkvm::io as spio;
 
 use serde::{Deserialize, Serialize};
 use tiny_keccak::{Keccak, Hasher};
+use addr::{from_alloy, to_alloy};
+
+// 开启 profiling 特性时累加 metrics 计数，否则展开为空
+macro_rules! count_op {
+    ($op:ident) => {
+        #[cfg(feature = "profiling")]
+        $crate::metrics::count($crate::metrics::Op::$op);
+    };
+}
+
+pub mod addr;
+pub mod commitment;
+pub mod guest_checks;
+#[cfg(feature = "std")]
+pub mod host;
+pub mod interop;
 pub mod models;
+#[cfg(feature = "std")]
 pub mod receipts;
+#[cfg(feature = "std")]
 pub mod ethaddr_gen;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod planner;
+#[cfg(feature = "std")]
 pub mod proxy_settler;
+#[cfg(feature = "std")]
 pub mod receiver_settler;
-pub use receipts::overpay_checker::{ReceiptsOverpayChecker,OverpayCheckResult};
-pub use receipts::{PaymentSettledByProxy,ReceiverProof};
+pub mod serde_hex;
+pub mod serde_u256_dec;
+pub mod signing_key;
+#[cfg(feature = "examples")]
+pub mod examples;
+// 没有 examples 特性时只供 crate 根的弃用导出使用
+#[cfg(not(feature = "examples"))]
+mod examples;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(all(feature = "std", any(test, feature = "test-utils")))]
+pub mod fixtures;
+#[cfg(test)]
+mod alloc_counter;
+#[cfg(all(test, feature = "std"))]
+mod golden;
+#[cfg(feature = "std")]
+pub use receipts::overpay_checker::{ReceiptsOverpayChecker,OverpayCheckResult,CrossRootWitness};
+#[cfg(feature = "std")]
+pub use receipts::{PaymentSettledByProxy,ReceiptStdinLayout,ReceiverSetCommitment,SealedReceipt,SettledReceiptBuilder};
+#[cfg(feature = "std")]
 pub use models::{segment_vc::SegmentVC,PayIdInfo};
-pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub use signing_key::{AsSecretKey, SigningKey};
+#[cfg(feature = "std")]
+pub use ethaddr_gen::DeterministicRng;
+pub use commitment::{CommitmentError, CommitmentVersion, VersionedRoot};
+pub type BoxError = Box<dyn core::error::Error + Send + Sync>;
 
 sol! {
-    /// The public values encoded as a struct that can be easily deserialized inside Solidity.
-    struct PublicValuesStruct {
-        uint32 n;
-        uint32 a;
-        uint32 b;
-    }
-
     // 首先定义 ReceiverProof 结构
     struct ReceiverProofStruct {
         address receiver;
@@ -43,6 +93,10 @@ sol! {
         bytes32 payments_root;
         ReceiverProofStruct[] receiver_proofs;  // 移除 receiver_len，因为可以从数组长度获取
         bytes32 pay_ids_root;
+        bytes32 receivers_root; // ReceiverSetCommitment 的根，布局见 receipts::receiver_set
+        uint64 epoch;           // 结算轮次
+        bytes32 excluded_root;  // 宽松模式移除的收据的承诺，没有移除时为 0
+        bytes32 referenced_pay_ids_root; // 收据引用的 pay_id 的承诺，没有收据时为 0
     }
 
     // 使用 sol! 宏定义与 Solidity 兼容的结构
@@ -64,87 +118,238 @@ sol! {
         uint256 proxy_profits;
         /// @notice 总金额
         uint256 amount;
+        /// @notice 结算轮次，非 0 时参与 settlement_id 的计算
+        uint64 epoch;
+        /// @notice 按代币的小计，只有原生代币时为空，非空时参与 settlement_id 的计算
+        TokenSubtotalStruct[] token_totals;
+    }
+
+    /// @notice 一种代币的利润小计，与 TokenSubtotal 相同
+    struct TokenSubtotalStruct {
+        address token;
+        uint256 system_profit;
+        uint256 proxy_profit;
+        uint256 receiver_profit;
+    }
+
+    struct ProfitResultStruct {
+        bytes32 vks_hash;
+        address receiver;
+        address proxy;
+        bytes32 receipts_root;
+        bytes32 pay_ids_root;
+        bytes32 serv_ids_root;
+        uint256 system_profit;
+        uint256 proxy_profit;
+        uint256 receiver_profit;
+        uint64 epoch;
+        TokenSubtotalStruct[] token_totals; // 按代币的小计，只有原生代币时为空
     }
 
 
 }
 
+/// 某个接收者的收据组在 payments_root 中的证明，由 receipts::PaymentsGrouper 生成
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReceiverProof {
+    #[serde(with = "crate::serde_hex")]
+    pub receiver: EthAddress,
+    pub proof: MerkleProof // 实际使用时替换为具体的证明类型
+}
+
+impl ReceiverProof {
+    /// 证明的根必须是 payments_root 且默克尔路径有效
+    ///
+    /// 证明中的值（收据组的哈希）由调用方根据收据重新计算后比较，这里不检查。按 Keccak 验证
+    pub fn verify(&self, payments_root: B256) -> Result<bool, BoxError> {
+        self.verify_with(payments_root, TreeHashAlgorithm::Keccak)
+    }
+
+    /// 与 verify 相同，按分组时使用的树哈希算法验证
+    pub fn verify_with(&self, payments_root: B256, hasher: TreeHashAlgorithm) -> Result<bool, BoxError> {
+        if self.proof.root_hash != payments_root {
+            return Ok(false);
+        }
+        self.proof.verify_with(hasher, false)
+    }
+}
+
+/// 从 sol 结构或 SP1 public values 转换为结果结构失败
+#[derive(Debug)]
+pub enum ConversionError {
+    /// abi 解码失败，保留 alloy 的错误
+    Abi(alloy_sol_types::Error),
+    /// public values 的长度与解码出的结构的编码长度不同，例如尾部有多余数据
+    Length { expected: usize, actual: usize },
+    /// receiver_proofs 中的证明不是合法的 MerkleProof JSON
+    Proof {
+        receiver: EthAddress,
+        source: serde_json::Error,
+    },
+    /// 打包的根带有未知版本，或各个根的版本不同
+    Commitment(CommitmentError),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::Abi(err) => write!(f, "ABI decoding failed: {}", err),
+            ConversionError::Length { expected, actual } => {
+                write!(f, "Public values length mismatch: expected {} bytes, got {}", expected, actual)
+            }
+            ConversionError::Proof { receiver, source } => {
+                write!(f, "Invalid MerkleProof for receiver {}: {}", format_eth_address(receiver), source)
+            }
+            ConversionError::Commitment(err) => write!(f, "Invalid versioned root: {}", err),
+        }
+    }
+}
+
+impl core::error::Error for ConversionError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            ConversionError::Abi(err) => Some(err),
+            ConversionError::Proof { source, .. } => Some(source),
+            ConversionError::Commitment(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// SP1 public values 必须恰好是一个 T 的 abi 编码，不接受截断或尾部多余的数据
+fn decode_public_values<T: SolType>(bytes: &[u8]) -> Result<T::RustType, ConversionError> {
+    let decoded = T::abi_decode(bytes, true).map_err(ConversionError::Abi)?;
+    let expected = T::abi_encoded_size(&decoded);
+    if expected != bytes.len() {
+        return Err(ConversionError::Length { expected, actual: bytes.len() });
+    }
+    Ok(decoded)
+}
+
 // 在 receipts_overpay_checker.rs 中的转换代码：
 // 转换实现
+#[cfg(feature = "std")]
 impl From<ReceiverProof> for ReceiverProofStruct {
     fn from(proof: ReceiverProof) -> Self {
                 // 直接序列化 MerkleProof
                 let serialized_proof = serde_json::to_vec(&proof.proof)
                 .expect("Failed to serialize MerkleProof");
         ReceiverProofStruct {
-            receiver: proof.receiver.into(),
+            receiver: to_alloy(proof.receiver),
            
             // 将 MerkleProof 序列化为 bytes
             proof: Bytes::from(serialized_proof),
         }
     }
 }
+#[cfg(feature = "std")]
 impl From<OverpayCheckResult> for OverpayCheckResultStruct {
     fn from(result: OverpayCheckResult) -> Self {
+        let receivers_root = ReceiverSetCommitment::from_overpay_result(&result).root();
         OverpayCheckResultStruct {
             payments_root: result.payments_root,
             receiver_proofs: result.receiver_proofs
                 .into_iter()
                 .map(ReceiverProofStruct::from)
                 .collect(),
-            pay_ids_root: result.pay_ids_root
+            pay_ids_root: result.pay_ids_root,
+            receivers_root,
+            epoch: result.epoch,
+            excluded_root: result.excluded_root.unwrap_or_default(),
+            referenced_pay_ids_root: result.referenced_pay_ids_root.unwrap_or_default(),
         }
     }
 }
-impl From<OverpayCheckResultStruct> for OverpayCheckResult {
-    fn from(result: OverpayCheckResultStruct) -> Self {
+#[cfg(feature = "std")]
+impl TryFrom<OverpayCheckResultStruct> for OverpayCheckResult {
+    type Error = ConversionError;
+
+    fn try_from(result: OverpayCheckResultStruct) -> Result<Self, ConversionError> {
         // 转换 receiver_proofs
         let receiver_proofs = result.receiver_proofs
             .into_iter()
             .map(|proof_struct| {
+                let receiver = from_alloy(proof_struct.receiver);
                 // 从 proof_struct.proof (Bytes) 反序列化得到 MerkleProof
-                let merkle_proof: MerkleProof = serde_json::from_slice(&proof_struct.proof)
-                    .expect("Failed to deserialize MerkleProof");
-                
-                // 创建 ReceiverProof
-                ReceiverProof {
-                    receiver: proof_struct.receiver.as_slice().try_into()
-                        .expect("Invalid receiver address length"),
-                    
-                    proof: merkle_proof,
-                }
+                let proof = serde_json::from_slice(&proof_struct.proof)
+                    .map_err(|source| ConversionError::Proof { receiver, source })?;
+                Ok(ReceiverProof { receiver, proof })
             })
-            .collect();
+            .collect::<Result<Vec<_>, ConversionError>>()?;
 
-        OverpayCheckResult {
+        let mut result = OverpayCheckResult {
             payments_root: result.payments_root,
             receiver_proofs,
             pay_ids_root: result.pay_ids_root,
-        }
+            epoch: result.epoch,
+            // 未打包的 public values 由使用同一哈希方案的 guest 生成
+            commitment_version: CommitmentVersion::CURRENT,
+            excluded_root: (result.excluded_root != B256::ZERO).then_some(result.excluded_root),
+            referenced_pay_ids_root: (result.referenced_pay_ids_root != B256::ZERO)
+                .then_some(result.referenced_pay_ids_root),
+        };
+        // 不信任外部传入的顺序，重新按 receiver 排序
+        result.canonicalize();
+        Ok(result)
     }
 }
 
 // 添加便捷方法
+#[cfg(feature = "std")]
 impl OverpayCheckResultStruct {
-    pub fn to_result(self) -> OverpayCheckResult {
-        self.into()
+    pub fn to_result(self) -> Result<OverpayCheckResult, ConversionError> {
+        self.try_into()
+    }
+
+    /// OverpayCheckResult::to_versioned_struct 的结果中根的共同版本，规则见 ProfitResultStruct::commitment_version
+    pub fn commitment_version(&self) -> Result<CommitmentVersion, ConversionError> {
+        let [payments_root, _] =
+            VersionedRoot::unpack_all([self.payments_root, self.pay_ids_root]).map_err(ConversionError::Commitment)?;
+        Ok(payments_root.version())
     }
 }
 
+#[cfg(feature = "std")]
+impl OverpayCheckResult {
+    /// 从 guest 提交的 public values 解码，布局为 OverpayCheckResultStruct 的 abi 编码
+    pub fn from_public_values(bytes: &[u8]) -> Result<Self, ConversionError> {
+        decode_public_values::<OverpayCheckResultStruct>(bytes)?.try_into()
+    }
 
-/// Compute the n'th fibonacci number (wrapping around on overflows), using normal Rust code.
-pub fn fibonacci(n: u32) -> (u32, u32) {
-    let mut a = 0u32;
-    let mut b = 1u32;
-    for _ in 0..n {
-        let c = a.wrapping_add(b);
-        a = b;
-        b = c;
+    pub fn to_public_values(&self) -> Vec<u8> {
+        OverpayCheckResultStruct::abi_encode(&OverpayCheckResultStruct::from(self.clone()))
+    }
+
+    /// payments_root 和 pay_ids_root 打包版本后的 sol 结构，receivers_root 不打包
+    pub fn to_versioned_struct(&self) -> OverpayCheckResultStruct {
+        let mut sol = OverpayCheckResultStruct::from(self.clone());
+        sol.payments_root = self.versioned_payments_root().pack();
+        sol.pay_ids_root = self.versioned_pay_ids_root().pack();
+        sol
     }
-    (a, b)
+
+    /// sol 是否正是 self.to_versioned_struct()，规则见 ProfitResult::matches_versioned_struct
+    pub fn matches_versioned_struct(&self, sol: &OverpayCheckResultStruct) -> Result<bool, ConversionError> {
+        let version = sol.commitment_version()?;
+        Ok(version == self.commitment_version
+            && OverpayCheckResultStruct::abi_encode(&self.to_versioned_struct()) == OverpayCheckResultStruct::abi_encode(sol))
+    }
+}
+
+
+// SP1 模板的旧导出，保留到使用模板的 guest 迁移到 examples 模块为止；不受 examples 特性控制，
+// 旧的 guest 不开启特性也能编译，只得到弃用警告
+#[deprecated(note = "使用 zkpay_lib::examples::fibonacci")]
+pub fn fibonacci(n: u32) -> (u32, u32) {
+    examples::fibonacci(n)
 }
 
+#[deprecated(note = "使用 zkpay_lib::examples::PublicValuesStruct")]
+pub type PublicValuesStruct = examples::PublicValuesStruct;
+
 pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    count_op!(Keccak);
     let mut keccak = Keccak::v256();
     let mut output = [0u8; 32];
     keccak.update(data);
@@ -153,63 +358,194 @@ pub fn keccak256(data: &[u8]) -> [u8; 32] {
 }
 
 pub fn keccak256_more(prev_hash:&B256,new_data:&[u8]) -> [u8; 32] {
+    keccak256_chain(prev_hash, &[new_data])
+}
+
+/// keccak256(parts[0] ‖ parts[1] ‖ ...)，多段原像不需要先拼接到临时的 Vec
+pub fn keccak256_concat(parts: &[&[u8]]) -> [u8; 32] {
+    count_op!(Keccak);
+    let mut keccak = Keccak::v256();
+    let mut output = [0u8; 32];
+    for part in parts {
+        keccak.update(part);
+    }
+    keccak.finalize(&mut output);
+    output
+}
+
+/// keccak256(start ‖ parts[0] ‖ parts[1] ‖ ...)，keccak256_more 的多段版本
+pub fn keccak256_chain(start: &B256, parts: &[&[u8]]) -> [u8; 32] {
+    count_op!(Keccak);
     let mut keccak = Keccak::v256();
     let mut output = [0u8; 32];
-    keccak.update(prev_hash.as_slice());
-    keccak.update(new_data);
+    keccak.update(start.as_slice());
+    for part in parts {
+        keccak.update(part);
+    }
     keccak.finalize(&mut output);
     output
 }
 
-// 生成新的私钥
-fn generate_private_key() -> SecretKey {
-    let mut rng = rand::thread_rng();
-    SecretKey::random(&mut rng)
+/// 承诺类型的域分隔标签
+///
+/// 开启 v2-hashing 后每种承诺的哈希原像以 1 字节标签开头，长度相同、类型不同的原像不会碰撞。
+/// 标签会改变所有的根，部署需要整体迁移；未开启时（v1）原像与之前完全相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HashDomain {
+    PaymentLeaf = 0x01,  // Payment / PaymentSettledByProxy 的 hash()
+    GroupHash = 0x02,    // 同一接收者所有收据哈希的组合哈希
+    PayIdLeaf = 0x03,    // PayIdInfo 的 hash()
+    SettlementId = 0x04, // ProxySettlementResult 的 settlement_id
+    ReceiverSet = 0x05,  // ReceiverSetCommitment 的叶子
+    ExcludedReceipts = 0x06, // OverpayCheckResult.excluded_root
+    ReferencedPayIds = 0x07, // OverpayCheckResult.referenced_pay_ids_root
+}
+
+/// 承诺的哈希方案
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    V1, // 原像不带标签
+    V2, // 原像以 HashDomain 标签开头
+}
+
+impl HashScheme {
+    /// 当前编译使用的方案，由 v2-hashing 特性决定
+    pub const ACTIVE: HashScheme = if cfg!(feature = "v2-hashing") { HashScheme::V2 } else { HashScheme::V1 };
+
+    /// 原像开头的标签字节，V1 没有标签
+    pub(crate) fn tag(self, domain: HashDomain) -> Option<u8> {
+        match self {
+            HashScheme::V1 => None,
+            HashScheme::V2 => Some(domain as u8),
+        }
+    }
+
+    /// 已写入标签的 hasher，用于流式计算
+    pub(crate) fn hasher(self, domain: HashDomain) -> Keccak {
+        count_op!(Keccak);
+        let mut keccak = Keccak::v256();
+        if let Some(tag) = self.tag(domain) {
+            keccak.update(&[tag]);
+        }
+        keccak
+    }
+
+    /// keccak256([tag ‖] parts[0] ‖ parts[1] ‖ ...)
+    pub fn hash(self, domain: HashDomain, parts: &[&[u8]]) -> B256 {
+        let mut keccak = self.hasher(domain);
+        for part in parts {
+            keccak.update(part);
+        }
+        let mut output = [0u8; 32];
+        keccak.finalize(&mut output);
+        B256::from(output)
+    }
+}
+
+/// 按当前方案计算带域分隔的哈希
+pub fn hash_with_domain(domain: HashDomain, parts: &[&[u8]]) -> B256 {
+    HashScheme::ACTIVE.hash(domain, parts)
+}
+
+/// settlement_id 的原像，合约和审计方按同样的布局重新计算
+///
+/// inner = H(proxy ‖ pay_ids_root ‖ serv_ids_root ‖ system(32) ‖ proxy_profit(32) ‖ amount(32) [‖ epoch(8)])，
+/// settlement_id = H(inner ‖ receipts_root)，两次都使用 SettlementId 域；epoch 为 0 时不追加
+#[allow(clippy::too_many_arguments)]
+pub fn settlement_id(
+    proxy: &EthAddress,
+    pay_ids_root: B256,
+    serv_ids_root: B256,
+    system: U256,
+    proxy_profit: U256,
+    amount: U256,
+    epoch: u64,
+    receipts_root: B256,
+) -> B256 {
+    let (system, proxy_profit, amount) =
+        (system.to_be_bytes::<32>(), proxy_profit.to_be_bytes::<32>(), amount.to_be_bytes::<32>());
+    let epoch_bytes = epoch.to_be_bytes();
+    let parts: [&[u8]; 7] =
+        [proxy, pay_ids_root.as_slice(), serv_ids_root.as_slice(), &system, &proxy_profit, &amount, &epoch_bytes];
+    let parts = if epoch != 0 { &parts[..] } else { &parts[..6] };
+
+    let inner = hash_with_domain(HashDomain::SettlementId, parts);
+    hash_with_domain(HashDomain::SettlementId, &[inner.as_slice(), receipts_root.as_slice()])
+}
+
+/// 结算历史链的一步：keccak256(prev ‖ settlement_id)
+pub fn settlement_history_step(prev: B256, settlement_id: B256) -> B256 {
+    B256::from(keccak256_more(&prev, settlement_id.as_slice()))
+}
+
+/// 从 start 开始按顺序对 ids 执行 settlement_history_step，得到链的末端
+pub fn settlement_chain_root(start: B256, ids: &[B256]) -> B256 {
+    ids.iter().fold(start, |prev, id| settlement_history_step(prev, *id))
+}
+
+/// ids 从 start 按顺序链接后是否得到 expected_root；顺序不同结果不同。
+/// SettlementProof 的历史哈希和 ReceiverSettleResult.settlement_root 都用它验证
+pub fn verify_settlement_chain(start: B256, ids: &[B256], expected_root: B256) -> bool {
+    settlement_chain_root(start, ids) == expected_root
+}
+
+// 生成新的私钥，释放时清零
+#[cfg(feature = "std")]
+pub fn generate_private_key() -> SigningKey {
+    SigningKey::random()
+}
+
+// 由 rng 生成私钥，传入 DeterministicRng 时可复现
+#[cfg(feature = "std")]
+pub fn generate_private_key_with<R: rand::Rng>(rng: &mut R) -> SigningKey {
+    SigningKey::random_with(rng)
+}
+
+// 从私钥获取公钥，接受 SigningKey 或 SecretKey
+pub fn get_public_key<K: AsSecretKey + ?Sized>(secret_key: &K) -> PublicKey {
+    PublicKey::from_secret_key(secret_key.secret_key())
 }
 
-// 从私钥获取公钥
-pub fn get_public_key(secret_key: &SecretKey) -> PublicKey {
-    PublicKey::from_secret_key(secret_key)
+// libsecp256k1 的错误只在 std 下实现 Error，统一转换为字符串错误
+fn secp_error(error: libsecp256k1::Error) -> BoxError {
+    format!("secp256k1 error: {:?}", error).into()
 }
 
-// 签名消息
-pub fn sign_message(secret_key: &SecretKey, message: &[u8]) -> Result<EthSignature, BoxError> {
+// 签名消息，接受 SigningKey 或 SecretKey
+pub fn sign_message<K: AsSecretKey + ?Sized>(secret_key: &K, message: &[u8]) -> Result<EthSignature, BoxError> {
     // 计算消息哈希
     let message_hash = keccak256(message);
-    let msg = Message::parse_slice(&message_hash)?;
+    let msg = Message::parse_slice(&message_hash).map_err(secp_error)?;
 
     // 签名
-    let (signature, recovery_id) = sign(&msg, secret_key);
+    let (signature, recovery_id) = sign(&msg, secret_key.secret_key());
 
     // 组装完整签名（r + s + v）
-    let mut sig_bytes = [0u8; 65];
-    sig_bytes[..32].copy_from_slice(&signature.r.b32());
-    sig_bytes[32..64].copy_from_slice(&signature.s.b32());
-    sig_bytes[64] = recovery_id.serialize();
-
-    Ok(sig_bytes)
+    Ok(signature_to_eth(&signature, &recovery_id))
 }
 
 // 从签名恢复公钥
 pub fn recover_public_key(signature: &EthSignature, message: &[u8]) -> Result<PublicKey, BoxError >{
     // 解析签名组件
-    let recovery_id = RecoveryId::parse(signature[64])?;
-    let sig = Signature::parse_standard_slice(&signature[..64])?;
+    let recovery_id = RecoveryId::parse(signature[64]).map_err(secp_error)?;
+    let sig = Signature::parse_standard_slice(&signature[..64]).map_err(secp_error)?;
 
     // 计算消息哈希
     let message_hash = keccak256(message);
-    let msg = Message::parse_slice(&message_hash)?;
+    let msg = Message::parse_slice(&message_hash).map_err(secp_error)?;
 
     // 恢复公钥
-    let public_key = recover(&msg, &sig, &recovery_id)?;
+    count_op!(SignatureRecovery);
+    let public_key = recover(&msg, &sig, &recovery_id).map_err(secp_error)?;
     Ok(public_key)
 }
 
 // 验证签名
 pub fn verify_signature(public_key: &PublicKey, signature: &EthSignature, message: &[u8]) -> Result<bool, BoxError> {
-    let sig = Signature::parse_standard_slice(&signature[..64])?;
+    let sig = Signature::parse_standard_slice(&signature[..64]).map_err(secp_error)?;
     let message_hash = keccak256(message);
-    let msg = Message::parse_slice(&message_hash)?;
+    let msg = Message::parse_slice(&message_hash).map_err(secp_error)?;
     
     Ok(verify(&msg, &sig, public_key))
 }
@@ -222,26 +558,110 @@ pub fn get_ethereum_address(public_key: &PublicKey) -> EthAddress {
     address.copy_from_slice(&hash[12..32]);
     address
 }
-// 定义以太坊签名类型（65字节）
+/// 公钥字节解析失败，或收据签名无法恢复出签名者
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    /// 只接受 33 字节压缩、64 字节裸坐标和 65 字节带 0x04 前缀的编码
+    InvalidPublicKeyLength(usize),
+    /// 长度正确但不是曲线上的点
+    InvalidPublicKey,
+    /// 私钥标量为 0 或不小于曲线的阶
+    InvalidSecretKey,
+    /// 收据的 "sender" 或 "proxy" 签名格式错误或无法恢复公钥
+    UnrecoverableSignature(&'static str),
+    /// 65 字节签名的 v 不是 0、1、27 或 28
+    InvalidRecoveryId(u8),
+    /// 需要 "sender" 等签名但没有提供
+    MissingSignature(&'static str),
+    /// "sender" 或 "proxy" 签名恢复出的地址不是期望的地址
+    UnexpectedSigner { signer: &'static str, expected: EthAddress, actual: EthAddress },
+}
 
-pub type EthSignature = [u8; 65];
-// 首先，在适当的位置添加这个辅助函数
-fn read_eth_signature() -> [u8; 65] {
-    let mut sig = [0u8; 65];
-    for i in 0..65 {
-        sig[i] = spio::read::<u8>();
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::InvalidPublicKeyLength(len) => {
+                write!(f, "Invalid public key length {}: expected 33, 64 or 65 bytes", len)
+            }
+            SignatureError::InvalidPublicKey => write!(f, "Invalid secp256k1 public key"),
+            SignatureError::InvalidSecretKey => write!(f, "Invalid secp256k1 secret key"),
+            SignatureError::UnrecoverableSignature(signer) => write!(f, "Cannot recover the {} signer", signer),
+            SignatureError::InvalidRecoveryId(v) => {
+                write!(f, "Invalid signature recovery id {}: expected 0, 1, 27 or 28", v)
+            }
+            SignatureError::MissingSignature(signer) => write!(f, "Missing {} signature", signer),
+            SignatureError::UnexpectedSigner { signer, expected, actual } => write!(
+                f,
+                "Unexpected {} signer: expected {}, got {}",
+                signer,
+                format_eth_address(expected),
+                format_eth_address(actual)
+            ),
+        }
     }
-    sig
 }
 
+impl core::error::Error for SignatureError {}
+
+/// 从公钥字节获取以太坊地址，压缩公钥会先解压，结果与 get_ethereum_address 相同
+pub fn get_ethereum_address_from_bytes(pubkey_bytes: &[u8]) -> Result<EthAddress, SignatureError> {
+    match pubkey_bytes.len() {
+        33 | 64 | 65 => {}
+        len => return Err(SignatureError::InvalidPublicKeyLength(len)),
+    }
+    let public_key =
+        PublicKey::parse_slice(pubkey_bytes, None).map_err(|_| SignatureError::InvalidPublicKey)?;
+    Ok(get_ethereum_address(&public_key))
+}
+
+/// 从 33 字节压缩公钥获取以太坊地址
+pub fn eth_address_from_compressed(pubkey: &[u8; 33]) -> Result<EthAddress, SignatureError> {
+    get_ethereum_address_from_bytes(pubkey)
+}
+// 定义以太坊签名类型（65字节）
+
+pub type EthSignature = [u8; 65];
+
 
 // 可选：你也可以为其他常用类型定义类型别名
 pub type EthAddress = [u8; 20];
 pub type EthHash = [u8; 32];
 
+/// 原生资产的代币地址；收据、PayIdInfo 的 token 为该值时使用引入代币之前的签名和哈希布局
+pub const NATIVE_TOKEN: EthAddress = [0u8; 20];
+
+/// 解析 0x 十六进制的签名
+pub fn parse_eth_signature(value: &str) -> Result<EthSignature, BoxError> {
+    serde_hex::decode_fixed(value).map_err(|e| format!("Invalid signature: {}", e).into())
+}
+
+/// 解析 0x 十六进制的哈希
+pub fn parse_eth_hash(value: &str) -> Result<EthHash, BoxError> {
+    serde_hex::decode_fixed(value).map_err(|e| format!("Invalid hash: {}", e).into())
+}
+
+/// 日志中地址统一使用 EIP-55 校验和格式
+pub fn format_eth_address(addr: &EthAddress) -> String {
+    to_alloy(*addr).to_checksum(None)
+}
+
 // 签名包装类型
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SerializableSignature(#[serde(with = "signature_serde")] pub EthSignature);
+
+impl fmt::Display for SerializableSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode_prefixed(self.0))
+    }
+}
+
+impl FromStr for SerializableSignature {
+    type Err = BoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_eth_signature(s).map(Self)
+    }
+}
 impl SerializableSignature {
     pub fn new(bytes: [u8; 65]) -> Self {
         Self(bytes)
@@ -252,43 +672,21 @@ impl SerializableSignature {
     }
 }
 
-// 签名的序列化助手
+// 签名的序列化助手，保留旧路径，实现见 serde_hex::signature
 pub mod signature_serde {
-    use super::*;
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
-    pub fn serialize<S>(signature: &EthSignature, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let bytes = signature.to_vec();
-        bytes.serialize(serializer)
-    }
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<EthSignature, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let bytes: Vec<u8> = Vec::deserialize(deserializer)?;
-        EthSignature::try_from(&bytes[..])
-            .map_err(|e| serde::de::Error::custom(format!("Invalid signature: {}", e)))
-    }
+    pub use crate::serde_hex::signature::{deserialize, serialize};
 }
-pub fn signature_to_eth(signature:Signature) -> EthSignature {
-
-    // 获取 r 和 s 的字节表示
-    let r_bytes: [u8; 32] = signature.r.b32();
-    let s_bytes: [u8; 32] = signature.s.b32();
-
-    // 序列化为以太坊格式（65字节）
+/// 把 libsecp256k1 的签名和恢复 id 编码为 r ‖ s ‖ v，v 为恢复 id 0/1，与 sign_message 的输出相同，
+/// recover_public_key 可以直接恢复；需要 27/28 形式时由调用方加 27
+pub fn signature_to_eth(signature: &Signature, recovery_id: &RecoveryId) -> EthSignature {
     let mut eth_signature = [0u8; 65];
-    eth_signature[0..32].copy_from_slice(&r_bytes);
-    eth_signature[32..64].copy_from_slice(&s_bytes);
-    eth_signature[64] = 27; // recovery id + 27
-
+    eth_signature[0..32].copy_from_slice(&signature.r.b32());
+    eth_signature[32..64].copy_from_slice(&signature.s.b32());
+    eth_signature[64] = recovery_id.serialize();
     eth_signature
 }
 
+/// 地址作为 SegmentVC 的键：高 12 字节为零，与 Solidity 的 bytes32(uint256(uint160(addr))) 相同
 pub fn eth_address_to_B256(addr: &EthAddress) -> B256 {
     let mut bytes = [0u8; 32];
     // 将地址复制到后20个字节
@@ -296,96 +694,335 @@ pub fn eth_address_to_B256(addr: &EthAddress) -> B256 {
     B256::from(bytes)
 }
 
+/// eth_address_to_B256 的逆变换，高 12 字节不为零时返回错误
+pub fn b256_to_eth_address(key: &B256) -> Result<EthAddress, BoxError> {
+    if key[..12].iter().any(|byte| *byte != 0) {
+        return Err(format!("Key {} is not a padded address", key).into());
+    }
+    let mut addr = [0u8; 20];
+    addr.copy_from_slice(&key[12..]);
+    Ok(addr)
+}
+
 // 利润计算结果
-#[derive(Debug,Clone,Serialize,Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ProfitResult {
+    pub vks_hash: B256,           // 生成该结果的 guest 验证密钥哈希
+    #[serde(with = "crate::serde_hex")]
     pub receiver: EthAddress,
+    #[serde(with = "crate::serde_hex")]
     pub proxy: EthAddress,
     pub receipts_root: B256,
     pub pay_ids_root: B256,
     pub serv_ids_root: B256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub system_profit: U256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub proxy_profit: U256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub receiver_profit: U256,
+    #[serde(default)]
+    pub epoch: u64,               // 结算轮次，旧数据没有该字段时为 0
+    /// 按代币的小计，按 token 升序；所有收据都是原生代币时为空，此时只使用上面的总额。
+    /// 上面的三项总额只包括原生代币的收据，其他代币只出现在这里
+    #[serde(default)]
+    pub token_totals: Vec<TokenSubtotal>,
+    /// 三个根的承诺方案版本，旧数据没有该字段时为 Legacy
+    #[serde(default)]
+    pub commitment_version: CommitmentVersion,
 }
 
-#[derive(Debug)]
+/// 一种代币的利润小计，ProxySettlementResult 中 receiver_profit 为所有接收者的合计
+///
+/// 不同代币的金额不能相加：结果的三项总额（以及 ProxySettlementResult.amount）只包括原生代币，
+/// 等于原生代币的小计，没有原生代币的收据时为 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenSubtotal {
+    #[serde(with = "crate::serde_hex")]
+    pub token: EthAddress,
+    #[serde(with = "crate::serde_u256_dec")]
+    pub system_profit: U256,
+    #[serde(with = "crate::serde_u256_dec")]
+    pub proxy_profit: U256,
+    #[serde(with = "crate::serde_u256_dec")]
+    pub receiver_profit: U256,
+}
+
+impl TokenSubtotal {
+    pub fn new(token: EthAddress) -> Self {
+        Self { token, system_profit: U256::ZERO, proxy_profit: U256::ZERO, receiver_profit: U256::ZERO }
+    }
+
+    /// 把 subtotal 累加到 totals 中同一代币的小计，totals 保持按 token 升序；溢出时返回溢出的字段名
+    pub fn accumulate(totals: &mut Vec<TokenSubtotal>, subtotal: &TokenSubtotal) -> Result<(), &'static str> {
+        let index = match totals.binary_search_by(|total| total.token.cmp(&subtotal.token)) {
+            Ok(index) => index,
+            Err(index) => {
+                totals.insert(index, TokenSubtotal::new(subtotal.token));
+                index
+            }
+        };
+        let total = &mut totals[index];
+        total.system_profit = total.system_profit.checked_add(subtotal.system_profit).ok_or("system_profit")?;
+        total.proxy_profit = total.proxy_profit.checked_add(subtotal.proxy_profit).ok_or("proxy_profit")?;
+        total.receiver_profit = total.receiver_profit.checked_add(subtotal.receiver_profit).ok_or("receiver_profit")?;
+        Ok(())
+    }
+
+    /// 只有原生代币时清空，与引入代币之前的结果相同
+    pub fn normalize(totals: &mut Vec<TokenSubtotal>) {
+        if totals.iter().all(|total| total.token == NATIVE_TOKEN) {
+            totals.clear();
+        }
+    }
+}
+
+/// 按代币小计的摘要：每项 token(20) | system_profit | proxy_profit | receiver_profit（各 32 字节大端序）依次拼接
+pub fn token_totals_hash(totals: &[TokenSubtotal]) -> B256 {
+    let mut keccak = HashScheme::ACTIVE.hasher(HashDomain::SettlementId);
+    for total in totals {
+        keccak.update(&total.token);
+        keccak.update(&total.system_profit.to_be_bytes::<32>());
+        keccak.update(&total.proxy_profit.to_be_bytes::<32>());
+        keccak.update(&total.receiver_profit.to_be_bytes::<32>());
+    }
+    let mut output = [0u8; 32];
+    keccak.finalize(&mut output);
+    B256::from(output)
+}
+
+/// 单行格式：key=value 以空格分隔，字段顺序固定，日志解析依赖该格式
+impl fmt::Display for ProfitResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ProfitResult vks_hash={} receiver={} proxy={} receipts_root={} pay_ids_root={} serv_ids_root={} system_profit={} proxy_profit={} receiver_profit={} epoch={}",
+            self.vks_hash,
+            format_eth_address(&self.receiver),
+            format_eth_address(&self.proxy),
+            self.receipts_root,
+            self.pay_ids_root,
+            self.serv_ids_root,
+            self.system_profit,
+            self.proxy_profit,
+            self.receiver_profit,
+            self.epoch
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ProxySettlementResult {
     pub vks_hash: B256,           // 添加验证密钥哈希
     pub settlement_id: B256,
+    #[serde(with = "crate::serde_hex")]
     pub proxy: EthAddress,
     pub pay_ids_root: B256,
     pub serv_ids_root: B256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub system_profits: U256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub proxy_profits: U256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub amount: U256,
+    pub epoch: u64,
+    /// 按代币的小计，规则与 ProfitResult.token_totals 相同
+    pub token_totals: Vec<TokenSubtotal>,
+    /// pay_ids_root 和 serv_ids_root 的承诺方案版本，取自聚合的 ProfitResult
+    pub commitment_version: CommitmentVersion,
 }
 
-// 添加 Solidity 类型定义
-sol! {
-    struct ProfitResultStruct {
-        bytes32 vks_hash;
-        address receiver;
-        address proxy;
-        bytes32 receipts_root;
-        bytes32 pay_ids_root;
-        bytes32 serv_ids_root;
-        uint256 system_profit;
-        uint256 proxy_profit;
-        uint256 receiver_profit;
+impl fmt::Display for ProxySettlementResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ProxySettlementResult vks_hash={} settlement_id={} proxy={} pay_ids_root={} serv_ids_root={} system_profits={} proxy_profits={} amount={} epoch={}",
+            self.vks_hash,
+            self.settlement_id,
+            format_eth_address(&self.proxy),
+            self.pay_ids_root,
+            self.serv_ids_root,
+            self.system_profits,
+            self.proxy_profits,
+            self.amount,
+            self.epoch
+        )
     }
 }
+
 impl ProxySettlementResult {
  /// 验证 settlement_id 是否正确
     pub fn verify_settlement_id(&self, receipts_root: B256) -> bool {
-        // 复制计算 settlement_id 的算法
-        let mut data = Vec::new();
-        data.extend_from_slice(&self.proxy);  // EthAddress -> &[u8]
-        data.extend_from_slice(self.pay_ids_root.as_slice());
-        data.extend_from_slice(self.serv_ids_root.as_slice());
-        data.extend_from_slice(&self.system_profits.to_be_bytes::<32>());
-        data.extend_from_slice(&self.proxy_profits.to_be_bytes::<32>());
-        data.extend_from_slice(&self.amount.to_be_bytes::<32>());
-        
-        let mut data2 = keccak256(&data).to_vec();
-        data2.extend_from_slice(receipts_root.as_slice());
-        let calculated_id = B256::from_slice(&keccak256(&data2));
-
         // 比较计算得到的 settlement_id 和存储的是否一致
-        calculated_id == self.settlement_id
+        self.calculate_settlement_id(receipts_root) == self.settlement_id
     }
 
-    /// 计算正确的 settlement_id（可选，用于调试）
+    /// 计算正确的 settlement_id
+    ///
+    /// epoch 非 0 时在 amount 之后追加 epoch(8 字节)，epoch 为 0 时与引入 epoch 之前的结果相同；
+    /// token_totals 非空时再与 token_totals_hash 组合一次
     pub fn calculate_settlement_id(&self, receipts_root: B256) -> B256 {
-        let mut data = Vec::new();
-        data.extend_from_slice(&self.proxy);
-        data.extend_from_slice(self.pay_ids_root.as_slice());
-        data.extend_from_slice(self.serv_ids_root.as_slice());
-        data.extend_from_slice(&self.system_profits.to_be_bytes::<32>());
-        data.extend_from_slice(&self.proxy_profits.to_be_bytes::<32>());
-        data.extend_from_slice(&self.amount.to_be_bytes::<32>());
-        
-        let mut data2 = keccak256(&data).to_vec();
-        data2.extend_from_slice(receipts_root.as_slice());
-        B256::from_slice(&keccak256(&data2))
+        let id = settlement_id(
+            &self.proxy,
+            self.pay_ids_root,
+            self.serv_ids_root,
+            self.system_profits,
+            self.proxy_profits,
+            self.amount,
+            self.epoch,
+            receipts_root,
+        );
+        if self.token_totals.is_empty() {
+            return id;
+        }
+        hash_with_domain(HashDomain::SettlementId, &[id.as_slice(), token_totals_hash(&self.token_totals).as_slice()])
     }
+    /// 每种代币的结算金额（三项小计之和），按 token 升序；token_totals 为空时只有原生代币的 amount。
+    /// 溢出时返回溢出的字段名
+    pub fn token_amounts(&self) -> Result<Vec<(EthAddress, U256)>, &'static str> {
+        if self.token_totals.is_empty() {
+            return Ok(vec![(NATIVE_TOKEN, self.amount)]);
+        }
+        self.token_totals
+            .iter()
+            .map(|total| {
+                let amount = total
+                    .system_profit
+                    .checked_add(total.proxy_profit)
+                    .and_then(|sum| sum.checked_add(total.receiver_profit))
+                    .ok_or("amount")?;
+                Ok((total.token, amount))
+            })
+            .collect()
+    }
+
     pub fn build_settlement_id(&mut self){
         self.settlement_id = self.calculate_settlement_id(self.pay_ids_root);
     }
+
+    /// 逐字段比较两个结算结果，settlement_id 不一致时用于找出是哪个组成部分不同
+    pub fn diff(&self, other: &Self) -> SettlementDiff {
+        let mut diff = SettlementDiff::default();
+        diff.push("vks_hash", DiffValue::Hash(self.vks_hash), DiffValue::Hash(other.vks_hash));
+        diff.push("settlement_id", DiffValue::Hash(self.settlement_id), DiffValue::Hash(other.settlement_id));
+        diff.push("proxy", DiffValue::Address(self.proxy), DiffValue::Address(other.proxy));
+        diff.push("pay_ids_root", DiffValue::Hash(self.pay_ids_root), DiffValue::Hash(other.pay_ids_root));
+        diff.push("serv_ids_root", DiffValue::Hash(self.serv_ids_root), DiffValue::Hash(other.serv_ids_root));
+        diff.push("system_profits", DiffValue::Amount(self.system_profits), DiffValue::Amount(other.system_profits));
+        diff.push("proxy_profits", DiffValue::Amount(self.proxy_profits), DiffValue::Amount(other.proxy_profits));
+        diff.push("amount", DiffValue::Amount(self.amount), DiffValue::Amount(other.amount));
+        diff.push("epoch", DiffValue::Epoch(self.epoch), DiffValue::Epoch(other.epoch));
+        diff
+    }
+}
+
+/// diff 中一个字段的值，地址以 EIP-55 格式显示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffValue {
+    Hash(B256),
+    Address(EthAddress),
+    Amount(U256),
+    Epoch(u64),
+}
+
+impl fmt::Display for DiffValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffValue::Hash(hash) => write!(f, "{}", hash),
+            DiffValue::Address(addr) => write!(f, "{}", format_eth_address(addr)),
+            DiffValue::Amount(amount) => write!(f, "{}", amount),
+            DiffValue::Epoch(epoch) => write!(f, "{}", epoch),
+        }
+    }
+}
+
+/// 不同的字段及两边的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub left: DiffValue,
+    pub right: DiffValue,
+}
+
+/// ProxySettlementResult::diff 和 ProfitResult::diff 的结果，按结构体字段顺序排列
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SettlementDiff {
+    pub fields: Vec<FieldDiff>,
+}
+
+impl SettlementDiff {
+    fn push(&mut self, field: &'static str, left: DiffValue, right: DiffValue) {
+        if left != right {
+            self.fields.push(FieldDiff { field, left, right });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// 字段是否不同
+    pub fn contains(&self, field: &str) -> bool {
+        self.get(field).is_some()
+    }
+
+    pub fn get(&self, field: &str) -> Option<&FieldDiff> {
+        self.fields.iter().find(|diff| diff.field == field)
+    }
+}
+
+/// 每个不同的字段一行：field: left != right
+impl fmt::Display for SettlementDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fields.is_empty() {
+            return write!(f, "no differences");
+        }
+        for (i, diff) in self.fields.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {} != {}", diff.field, diff.left, diff.right)?;
+        }
+        Ok(())
+    }
+}
+impl From<TokenSubtotal> for TokenSubtotalStruct {
+    fn from(subtotal: TokenSubtotal) -> Self {
+        TokenSubtotalStruct {
+            token: to_alloy(subtotal.token),
+            system_profit: subtotal.system_profit,
+            proxy_profit: subtotal.proxy_profit,
+            receiver_profit: subtotal.receiver_profit,
+        }
+    }
+}
+
+impl From<TokenSubtotalStruct> for TokenSubtotal {
+    fn from(subtotal: TokenSubtotalStruct) -> Self {
+        TokenSubtotal {
+            token: from_alloy(subtotal.token),
+            system_profit: subtotal.system_profit,
+            proxy_profit: subtotal.proxy_profit,
+            receiver_profit: subtotal.receiver_profit,
+        }
+    }
 }
+
 // ProfitResult 转换为 ProfitResultStruct
 impl From<ProfitResult> for ProfitResultStruct {
     fn from(result: ProfitResult) -> Self {
         ProfitResultStruct {
-            vks_hash: B256::ZERO,
-            receiver: Address::from_slice(&result.receiver),
-            proxy: Address::from_slice(&result.proxy),
+            vks_hash: result.vks_hash,
+            receiver: to_alloy(result.receiver),
+            proxy: to_alloy(result.proxy),
             receipts_root: result.receipts_root,
             pay_ids_root: result.pay_ids_root,
             serv_ids_root: result.serv_ids_root,
             system_profit: result.system_profit,
             proxy_profit: result.proxy_profit,
             receiver_profit: result.receiver_profit,
+            epoch: result.epoch,
+            token_totals: result.token_totals.into_iter().map(Into::into).collect(),
         }
     }
 }
@@ -393,21 +1030,20 @@ impl From<ProfitResult> for ProfitResultStruct {
 // ProfitResultStruct 转换为 ProfitResult
 impl From<ProfitResultStruct> for ProfitResult {
     fn from(result: ProfitResultStruct) -> Self {
-        let mut receiver = [0u8; 20];
-        receiver.copy_from_slice(result.receiver.as_slice());
-        
-        let mut proxy = [0u8; 20];
-        proxy.copy_from_slice(result.proxy.as_slice());
-
         ProfitResult {
-            receiver,
-            proxy,
+            vks_hash: result.vks_hash,
+            receiver: from_alloy(result.receiver),
+            proxy: from_alloy(result.proxy),
             receipts_root: result.receipts_root,
             pay_ids_root: result.pay_ids_root,
             serv_ids_root: result.serv_ids_root,
             system_profit: result.system_profit,
             proxy_profit: result.proxy_profit,
             receiver_profit: result.receiver_profit,
+            epoch: result.epoch,
+            token_totals: result.token_totals.into_iter().map(Into::into).collect(),
+            // 未打包的 public values 由使用同一哈希方案的 guest 生成
+            commitment_version: CommitmentVersion::CURRENT,
         }
     }
 }
@@ -417,12 +1053,100 @@ impl ProfitResult {
     pub fn to_struct(self) -> ProfitResultStruct {
         self.into()
     }
+
+    /// 按代币的小计；token_totals 为空时为一项原生代币的小计，即结果的三项总额。
+    /// 合并多个结果时逐项累加后用 TokenSubtotal::normalize 恢复空的约定
+    pub fn token_subtotals(&self) -> Vec<TokenSubtotal> {
+        if !self.token_totals.is_empty() {
+            return self.token_totals.clone();
+        }
+        vec![TokenSubtotal {
+            token: NATIVE_TOKEN,
+            system_profit: self.system_profit,
+            proxy_profit: self.proxy_profit,
+            receiver_profit: self.receiver_profit,
+        }]
+    }
+
+    /// 内容摘要，等价于 Solidity 中的 keccak256(abi.encode(result))
+    pub fn content_hash(&self) -> B256 {
+        let sol_result: ProfitResultStruct = self.clone().into();
+        let encoded = ProfitResultStruct::abi_encode(&sol_result);
+        B256::from(keccak256(&encoded))
+    }
+
+    /// 逐字段比较两个利润结果
+    pub fn diff(&self, other: &Self) -> SettlementDiff {
+        let mut diff = SettlementDiff::default();
+        diff.push("vks_hash", DiffValue::Hash(self.vks_hash), DiffValue::Hash(other.vks_hash));
+        diff.push("receiver", DiffValue::Address(self.receiver), DiffValue::Address(other.receiver));
+        diff.push("proxy", DiffValue::Address(self.proxy), DiffValue::Address(other.proxy));
+        diff.push("receipts_root", DiffValue::Hash(self.receipts_root), DiffValue::Hash(other.receipts_root));
+        diff.push("pay_ids_root", DiffValue::Hash(self.pay_ids_root), DiffValue::Hash(other.pay_ids_root));
+        diff.push("serv_ids_root", DiffValue::Hash(self.serv_ids_root), DiffValue::Hash(other.serv_ids_root));
+        diff.push("system_profit", DiffValue::Amount(self.system_profit), DiffValue::Amount(other.system_profit));
+        diff.push("proxy_profit", DiffValue::Amount(self.proxy_profit), DiffValue::Amount(other.proxy_profit));
+        diff.push("receiver_profit", DiffValue::Amount(self.receiver_profit), DiffValue::Amount(other.receiver_profit));
+        diff.push("epoch", DiffValue::Epoch(self.epoch), DiffValue::Epoch(other.epoch));
+        diff
+    }
 }
 
 impl ProfitResultStruct {
     pub fn to_result(self) -> ProfitResult {
         self.into()
     }
+
+    /// ProfitResult::to_versioned_struct 的结果中根的共同版本：各个根的最高字节必须是同一个 Legacy 以外的已知版本
+    ///
+    /// 打包时根的最高字节被版本替换，不能从打包的结构还原出默克尔证明和 settlement_id 使用的原始根，
+    /// 持有原始结果时用 ProfitResult::matches_versioned_struct 比较
+    pub fn commitment_version(&self) -> Result<CommitmentVersion, ConversionError> {
+        let [receipts_root, _, _] =
+            VersionedRoot::unpack_all([self.receipts_root, self.pay_ids_root, self.serv_ids_root])
+                .map_err(ConversionError::Commitment)?;
+        Ok(receipts_root.version())
+    }
+}
+
+impl ProfitResult {
+    /// 从 guest 提交的 public values 解码，布局为 ProfitResultStruct 的 abi 编码
+    pub fn from_public_values(bytes: &[u8]) -> Result<Self, ConversionError> {
+        decode_public_values::<ProfitResultStruct>(bytes).map(Into::into)
+    }
+
+    pub fn to_public_values(&self) -> Vec<u8> {
+        ProfitResultStruct::abi_encode(&ProfitResultStruct::from(self.clone()))
+    }
+
+    pub fn versioned_receipts_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.receipts_root)
+    }
+
+    pub fn versioned_pay_ids_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.pay_ids_root)
+    }
+
+    pub fn versioned_serv_ids_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.serv_ids_root)
+    }
+
+    /// 三个根打包版本后的 sol 结构；Legacy 与 to_struct 相同
+    pub fn to_versioned_struct(&self) -> ProfitResultStruct {
+        let mut sol = ProfitResultStruct::from(self.clone());
+        sol.receipts_root = self.versioned_receipts_root().pack();
+        sol.pay_ids_root = self.versioned_pay_ids_root().pack();
+        sol.serv_ids_root = self.versioned_serv_ids_root().pack();
+        sol
+    }
+
+    /// sol 是否正是 self.to_versioned_struct()：版本与 commitment_version 相同，根的低 31 字节和其他字段相等。
+    /// 打包的结构中没有版本时返回错误
+    pub fn matches_versioned_struct(&self, sol: &ProfitResultStruct) -> Result<bool, ConversionError> {
+        let version = sol.commitment_version()?;
+        Ok(version == self.commitment_version
+            && ProfitResultStruct::abi_encode(&self.to_versioned_struct()) == ProfitResultStruct::abi_encode(sol))
+    }
 }
 
 
@@ -432,12 +1156,14 @@ impl From<ProxySettlementResult> for ProxySettlementResultStruct {
         ProxySettlementResultStruct {
             vks_hash: result.vks_hash,
             settlement_id: result.settlement_id,
-            proxy: Address::from_slice(&result.proxy),
+            proxy: to_alloy(result.proxy),
             pay_ids_root: result.pay_ids_root,
             serv_ids_root: result.serv_ids_root,
             system_profits: result.system_profits,
             proxy_profits: result.proxy_profits,
             amount: result.amount,
+            epoch: result.epoch,
+            token_totals: result.token_totals.into_iter().map(Into::into).collect(),
         }
     }
 }
@@ -445,18 +1171,19 @@ impl From<ProxySettlementResult> for ProxySettlementResultStruct {
 // ProxySettlementResultStruct 转换为 ProxySettlementResult
 impl From<ProxySettlementResultStruct> for ProxySettlementResult {
     fn from(result: ProxySettlementResultStruct) -> Self {
-        let mut proxy = [0u8; 20];
-        proxy.copy_from_slice(result.proxy.as_slice());
-
         ProxySettlementResult {
             vks_hash: result.vks_hash,
             settlement_id: result.settlement_id,
-            proxy,
+            proxy: from_alloy(result.proxy),
             pay_ids_root: result.pay_ids_root,
             serv_ids_root: result.serv_ids_root,
             system_profits: result.system_profits,
             proxy_profits: result.proxy_profits,
             amount: result.amount,
+            epoch: result.epoch,
+            token_totals: result.token_totals.into_iter().map(Into::into).collect(),
+            // 未打包的 public values 由使用同一哈希方案的 guest 生成
+            commitment_version: CommitmentVersion::CURRENT,
         }
     }
 }
@@ -472,6 +1199,48 @@ impl ProxySettlementResultStruct {
     pub fn to_result(self) -> ProxySettlementResult {
         self.into()
     }
+
+    /// ProxySettlementResult::to_versioned_struct 的结果中根的共同版本，规则见 ProfitResultStruct::commitment_version
+    pub fn commitment_version(&self) -> Result<CommitmentVersion, ConversionError> {
+        let [pay_ids_root, _] =
+            VersionedRoot::unpack_all([self.pay_ids_root, self.serv_ids_root]).map_err(ConversionError::Commitment)?;
+        Ok(pay_ids_root.version())
+    }
+}
+
+impl ProxySettlementResult {
+    /// 从 guest 提交的 public values 解码，布局为 ProxySettlementResultStruct 的 abi 编码
+    pub fn from_public_values(bytes: &[u8]) -> Result<Self, ConversionError> {
+        decode_public_values::<ProxySettlementResultStruct>(bytes).map(Into::into)
+    }
+
+    pub fn to_public_values(&self) -> Vec<u8> {
+        ProxySettlementResultStruct::abi_encode(&ProxySettlementResultStruct::from(self.clone()))
+    }
+
+    pub fn versioned_pay_ids_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.pay_ids_root)
+    }
+
+    pub fn versioned_serv_ids_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.serv_ids_root)
+    }
+
+    /// pay_ids_root 和 serv_ids_root 打包版本后的 sol 结构，settlement_id 仍按未打包的根计算
+    pub fn to_versioned_struct(&self) -> ProxySettlementResultStruct {
+        let mut sol = ProxySettlementResultStruct::from(self.clone());
+        sol.pay_ids_root = self.versioned_pay_ids_root().pack();
+        sol.serv_ids_root = self.versioned_serv_ids_root().pack();
+        sol
+    }
+
+    /// sol 是否正是 self.to_versioned_struct()，规则见 ProfitResult::matches_versioned_struct
+    pub fn matches_versioned_struct(&self, sol: &ProxySettlementResultStruct) -> Result<bool, ConversionError> {
+        let version = sol.commitment_version()?;
+        Ok(version == self.commitment_version
+            && ProxySettlementResultStruct::abi_encode(&self.to_versioned_struct())
+                == ProxySettlementResultStruct::abi_encode(sol))
+    }
 }
 /******************
  
@@ -509,8 +1278,11 @@ interface IProfitResult {
 #[derive(Debug, Clone, Serialize, Deserialize)]
  pub struct ReceiverSettleResult{
     pub vk_hash:B256,
+    /// 接收者领取的各代理结算的 settlement_id 按处理顺序链接的结果，见 verify_settlement_chain
     pub settlement_root:B256,
+    #[serde(with = "crate::serde_hex")]
     pub receiver:EthAddress,
+    #[serde(with = "crate::serde_u256_dec")]
     pub profit:U256,
  }
  // 在 sol! 宏中添加 ReceiverSettleResultStruct 定义
@@ -530,7 +1302,7 @@ impl From<ReceiverSettleResult> for ReceiverSettleResultStruct {
             vk_hash: result.vk_hash,
             settlement_root: result.settlement_root,
             profit: result.profit,
-            receiver: Address::from_slice(&result.receiver),
+            receiver: to_alloy(result.receiver),
 
         }
     }
@@ -542,17 +1314,38 @@ impl From<ReceiverSettleResultStruct> for ReceiverSettleResult {
             vk_hash: result.vk_hash,
             settlement_root: result.settlement_root,
             profit: result.profit,
-            receiver: result.receiver.as_slice().try_into()
-                .expect("Invalid receiver address length"),
+            receiver: from_alloy(result.receiver),
         }
     }
 }
 
+impl fmt::Display for ReceiverSettleResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ReceiverSettleResult vk_hash={} settlement_root={} receiver={} profit={}",
+            self.vk_hash,
+            self.settlement_root,
+            format_eth_address(&self.receiver),
+            self.profit
+        )
+    }
+}
+
 // 为 ReceiverSettleResult 添加便捷方法
 impl ReceiverSettleResult {
     pub fn to_struct(self) -> ReceiverSettleResultStruct {
         self.into()
     }
+
+    /// 从 guest 提交的 public values 解码，布局为 ReceiverSettleResultStruct 的 abi 编码
+    pub fn from_public_values(bytes: &[u8]) -> Result<Self, ConversionError> {
+        decode_public_values::<ReceiverSettleResultStruct>(bytes).map(Into::into)
+    }
+
+    pub fn to_public_values(&self) -> Vec<u8> {
+        ReceiverSettleResultStruct::abi_encode(&ReceiverSettleResultStruct::from(self.clone()))
+    }
 }
 
 impl ReceiverSettleResultStruct {
@@ -561,6 +1354,495 @@ impl ReceiverSettleResultStruct {
     }
 }
 
+#[cfg(test)]
+mod test_address_keys {
+    use super::*;
+
+    #[test]
+    fn test_address_key_round_trip() -> Result<(), BoxError> {
+        let addr: EthAddress = core::array::from_fn(|i| i as u8 + 1);
+        let key = eth_address_to_B256(&addr);
+        assert_eq!(&key[..12], &[0u8; 12][..]);
+        assert_eq!(b256_to_eth_address(&key)?, addr);
+
+        let mut dirty = key;
+        dirty[0] = 1;
+        assert!(b256_to_eth_address(&dirty).is_err());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_public_key_encodings {
+    use super::*;
+
+    #[test]
+    fn test_compressed_and_uncompressed_agree() -> Result<(), BoxError> {
+        // 私钥 1 对应的公钥就是生成元 G
+        let mut secret = [0u8; 32];
+        secret[31] = 1;
+        let public_key = get_public_key(&SecretKey::parse(&secret).map_err(secp_error)?);
+        let expected: EthAddress = alloy_primitives::address!("7e5f4552091a69125d5dfcb7b8c2659029395bdf").into();
+        assert_eq!(get_ethereum_address(&public_key), expected);
+
+        let full = public_key.serialize();
+        let compressed = public_key.serialize_compressed();
+        assert_eq!(get_ethereum_address_from_bytes(&full)?, expected);
+        assert_eq!(get_ethereum_address_from_bytes(&full[1..])?, expected);
+        assert_eq!(get_ethereum_address_from_bytes(&compressed)?, expected);
+        assert_eq!(eth_address_from_compressed(&compressed)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_public_key_bytes() {
+        assert_eq!(
+            get_ethereum_address_from_bytes(&[2u8; 20]),
+            Err(SignatureError::InvalidPublicKeyLength(20))
+        );
+        let mut bad_prefix = [0u8; 33];
+        bad_prefix[0] = 5;
+        assert_eq!(eth_address_from_compressed(&bad_prefix), Err(SignatureError::InvalidPublicKey));
+    }
+}
+
+#[cfg(test)]
+mod test_signature_to_eth {
+    use super::*;
+    use alloy_primitives::b256;
+    use proptest::prelude::*;
+
+    fn sign_raw(secret: &[u8; 32], message: &[u8]) -> Result<(SecretKey, Signature, RecoveryId), BoxError> {
+        let secret_key = SecretKey::parse(secret).map_err(secp_error)?;
+        let msg = Message::parse_slice(&keccak256(message)).map_err(secp_error)?;
+        let (signature, recovery_id) = sign(&msg, &secret_key);
+        Ok((secret_key, signature, recovery_id))
+    }
+
+    #[test]
+    fn test_recovery_id_one_regression() -> Result<(), BoxError> {
+        // 私钥 0x11..11 对该消息的签名恢复 id 为 1，固定 v = 27 时会恢复出错误的地址或直接失败
+        let message = b"recovery id regression 2";
+        let (secret_key, signature, recovery_id) = sign_raw(&[0x11; 32], message)?;
+        assert_eq!(recovery_id.serialize(), 1);
+
+        let eth_signature = signature_to_eth(&signature, &recovery_id);
+        let expected_r = b256!("677144e6c293bc8672011420ec7614cf5287de988fd757424c0e366fa5463375");
+        let expected_s = b256!("4142186764011966c79c043b05ac77b217fd31184894da05e8926ff1aea16538");
+        assert_eq!(&eth_signature[..32], expected_r.as_slice());
+        assert_eq!(&eth_signature[32..64], expected_s.as_slice());
+        assert_eq!(eth_signature[64], 1);
+        assert_eq!(eth_signature, sign_message(&secret_key, message)?);
+
+        let expected: EthAddress = alloy_primitives::address!("19e7e376e7c213b7e7e7e46cc70a5dd086daff2a").into();
+        assert_eq!(get_ethereum_address(&get_public_key(&secret_key)), expected);
+        assert_eq!(get_ethereum_address(&recover_public_key(&eth_signature, message)?), expected);
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(256))]
+
+        #[test]
+        fn signatures_round_trip(secret in any::<[u8; 32]>(), message in proptest::collection::vec(any::<u8>(), 0..128)) {
+            // 不在曲线阶范围内的私钥被 SecretKey::parse 拒绝
+            let Ok((secret_key, signature, recovery_id)) = sign_raw(&secret, &message) else {
+                return Ok(());
+            };
+            let eth_signature = signature_to_eth(&signature, &recovery_id);
+            prop_assert_eq!(eth_signature[64], recovery_id.serialize());
+            let recovered = recover_public_key(&eth_signature, &message).unwrap();
+            prop_assert_eq!(get_ethereum_address(&recovered), get_ethereum_address(&get_public_key(&secret_key)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_result_diff {
+    use super::*;
+
+    fn settlement() -> ProxySettlementResult {
+        let mut result = ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::ZERO,
+            proxy: [2u8; 20],
+            pay_ids_root: B256::repeat_byte(3),
+            serv_ids_root: B256::repeat_byte(4),
+            system_profits: U256::from(10u32),
+            proxy_profits: U256::from(20u32),
+            amount: U256::from(100u32),
+            epoch: 1,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+        result.build_settlement_id();
+        result
+    }
+
+    fn profit_result() -> ProfitResult {
+        ProfitResult {
+            vks_hash: B256::repeat_byte(1),
+            receiver: [5u8; 20],
+            proxy: [2u8; 20],
+            receipts_root: B256::repeat_byte(6),
+            pay_ids_root: B256::repeat_byte(3),
+            serv_ids_root: B256::repeat_byte(4),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(70u32),
+            epoch: 1,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        }
+    }
+
+    #[test]
+    fn test_settlement_diff_each_field() {
+        let base = settlement();
+        assert!(base.diff(&base.clone()).is_empty());
+        assert_eq!(base.diff(&base).to_string(), "no differences");
+
+        // settlement_id 不包含 vks_hash，只有 vks_hash 本身不同
+        let mut other = base.clone();
+        other.vks_hash = B256::repeat_byte(9);
+        other.build_settlement_id();
+        let diff = base.diff(&other);
+        assert_eq!(diff.fields.len(), 1, "{}", diff);
+        assert!(diff.contains("vks_hash"));
+
+        let cases: [(&str, fn(&mut ProxySettlementResult)); 7] = [
+            ("proxy", |r| r.proxy = [9u8; 20]),
+            ("pay_ids_root", |r| r.pay_ids_root = B256::repeat_byte(9)),
+            ("serv_ids_root", |r| r.serv_ids_root = B256::repeat_byte(9)),
+            ("system_profits", |r| r.system_profits = U256::from(11u32)),
+            ("proxy_profits", |r| r.proxy_profits = U256::from(21u32)),
+            ("amount", |r| r.amount = U256::from(101u32)),
+            ("epoch", |r| r.epoch = 2),
+        ];
+        for (field, change) in cases {
+            let mut other = base.clone();
+            change(&mut other);
+            other.build_settlement_id();
+
+            // 组成部分不同时 settlement_id 也不同
+            let diff = base.diff(&other);
+            assert_eq!(diff.fields.len(), 2, "{}", diff);
+            assert!(diff.contains("settlement_id"));
+            assert!(diff.contains(field), "{} missing from {}", field, diff);
+        }
+    }
+
+    #[test]
+    fn test_settlement_diff_display() {
+        let base = settlement();
+        let mut other = base.clone();
+        other.proxy = [0xabu8; 20];
+        other.amount = U256::from(99u32);
+
+        let diff = base.diff(&other);
+        assert_eq!(diff.get("amount").map(|d| (d.left, d.right)), Some((DiffValue::Amount(U256::from(100u32)), DiffValue::Amount(U256::from(99u32)))));
+        assert_eq!(
+            diff.to_string(),
+            format!(
+                "proxy: {} != {}\namount: 100 != 99",
+                format_eth_address(&[2u8; 20]),
+                format_eth_address(&[0xabu8; 20])
+            )
+        );
+    }
+
+    #[test]
+    fn test_profit_result_diff_each_field() {
+        let base = profit_result();
+        assert!(base.diff(&base).is_empty());
+
+        let cases: [(&str, fn(&mut ProfitResult)); 10] = [
+            ("vks_hash", |r| r.vks_hash = B256::repeat_byte(9)),
+            ("receiver", |r| r.receiver = [9u8; 20]),
+            ("proxy", |r| r.proxy = [9u8; 20]),
+            ("receipts_root", |r| r.receipts_root = B256::repeat_byte(9)),
+            ("pay_ids_root", |r| r.pay_ids_root = B256::repeat_byte(9)),
+            ("serv_ids_root", |r| r.serv_ids_root = B256::repeat_byte(9)),
+            ("system_profit", |r| r.system_profit = U256::from(11u32)),
+            ("proxy_profit", |r| r.proxy_profit = U256::from(21u32)),
+            ("receiver_profit", |r| r.receiver_profit = U256::from(71u32)),
+            ("epoch", |r| r.epoch = 2),
+        ];
+        for (field, change) in cases {
+            let mut other = base.clone();
+            change(&mut other);
+            let diff = base.diff(&other);
+            assert_eq!(diff.fields.len(), 1);
+            assert_eq!(diff.fields[0].field, field);
+        }
+
+        let mut other = base.clone();
+        other.receiver_profit = U256::from(71u32);
+        assert_eq!(base.diff(&other).to_string(), "receiver_profit: 70 != 71");
+    }
+}
+
+#[cfg(test)]
+mod test_hash_domains {
+    use super::*;
+    use alloy_primitives::b256;
+
+    // 两种方案的期望值：(v1, v2)
+    fn golden(vectors: (B256, B256)) -> B256 {
+        match HashScheme::ACTIVE {
+            HashScheme::V1 => vectors.0,
+            HashScheme::V2 => vectors.1,
+        }
+    }
+
+    #[test]
+    fn test_scheme_vectors() {
+        let parts: [&[u8]; 2] = [b"ab", b"c"];
+        assert_eq!(
+            HashScheme::V1.hash(HashDomain::GroupHash, &parts),
+            b256!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45")
+        );
+        assert_eq!(
+            HashScheme::V2.hash(HashDomain::GroupHash, &parts),
+            b256!("e0c51c31bd1626d6c593c8713f715088997d5eef09322d7a1d4adbbb506e5f62")
+        );
+        // v1 与原来的 keccak256 相同，v2 中不同类型的同一原像得到不同的哈希
+        assert_eq!(HashScheme::V1.hash(HashDomain::PayIdLeaf, &[b"abc"]), B256::from(keccak256(b"abc")));
+        assert_ne!(
+            HashScheme::V2.hash(HashDomain::PayIdLeaf, &[b"abc"]),
+            HashScheme::V2.hash(HashDomain::PaymentLeaf, &[b"abc"])
+        );
+        assert_eq!(hash_with_domain(HashDomain::GroupHash, &parts), HashScheme::ACTIVE.hash(HashDomain::GroupHash, &parts));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_receiver_set_leaf_vectors() {
+        let set = ReceiverSetCommitment::new(&[[7u8; 20]]);
+        assert_eq!(
+            set.root(),
+            golden((
+                b256!("ec92a20c2418016d3f8c4730b5f224f3275b1f815410efd3f44a3922c148b353"),
+                b256!("034903db0411bdd859e00c33c40f199aca805b8327401d9abd96280586f15f40"),
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_payment_leaf_vectors() {
+        let payment = PaymentSettledByProxy::new(AlloyU256::from(1), 1, AlloyU256::from(100), [1u8; 20])
+            .with_settled(true)
+            .with_sig_sender([1u8; 65])
+            .with_sig_proxy([2u8; 65]);
+        assert_eq!(
+            payment.hash(),
+            golden((
+                b256!("06893d36902c7f013e43962defd5b319eb2578d93b03ff7dff8e31b6ffc122e5"),
+                b256!("1e0d2e8bde78018ef3e3a0dd1ba1631e20f0bdd81fdcb4e1609c11c6a4e84539"),
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_pay_id_leaf_vectors() {
+        let info = PayIdInfo {
+            id: AlloyU256::from(1),
+            amount: AlloyU256::from(1000),
+            sender: [2u8; 20],
+            proxy: [3u8; 20],
+            state: 1,
+            created_at: 1000,
+            closing_time: 2000,
+            token: [0u8; 20],
+            sig_sender: None,
+        };
+        assert_eq!(
+            info.hash(),
+            golden((
+                b256!("375e5ba84cb86eedc6f8ff0b4a3c1d1bee3e8779504daf993dc8c79d46119486"),
+                b256!("acaf39d9dcb1ff4d6290201912d428e8c266a490c0405c7769dd7143533cbd07"),
+            ))
+        );
+    }
+
+    fn sample_settlement() -> ProxySettlementResult {
+        ProxySettlementResult {
+            vks_hash: B256::ZERO,
+            settlement_id: B256::ZERO,
+            proxy: [3u8; 20],
+            pay_ids_root: B256::repeat_byte(1),
+            serv_ids_root: B256::repeat_byte(2),
+            system_profits: U256::from(10u32),
+            proxy_profits: U256::from(20u32),
+            amount: U256::from(100u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        }
+    }
+
+    #[test]
+    fn test_settlement_id_vectors() {
+        let receipts_root = B256::repeat_byte(5);
+        let compute = |epoch| {
+            settlement_id(
+                &[3u8; 20],
+                B256::repeat_byte(1),
+                B256::repeat_byte(2),
+                U256::from(10u32),
+                U256::from(20u32),
+                U256::from(100u32),
+                epoch,
+                receipts_root,
+            )
+        };
+        assert_eq!(
+            compute(0),
+            golden((
+                b256!("95e36279e96587aec64df650eed5cb52ad83562c4bc78765c46f77a17b1eab4e"),
+                b256!("82edcffe792ffc723e7acdd4394466acf6926b8e78419cac450689e7c94d3802"),
+            ))
+        );
+        assert_eq!(
+            compute(7),
+            golden((
+                b256!("db351e3ad9638fe497368fe0cd3fd69a6cd7c0a7d5f2ad5a66be4aee2fca536d"),
+                b256!("f710c2511b3cd671cb459e77406c19f9f7a444e835bace302a49d56889502950"),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_settlement_id_method_agrees() {
+        let mut result = sample_settlement();
+        for epoch in [0, 7, u64::MAX] {
+            result.epoch = epoch;
+            for receipts_root in [B256::ZERO, B256::repeat_byte(5), result.pay_ids_root] {
+                let expected = settlement_id(
+                    &result.proxy,
+                    result.pay_ids_root,
+                    result.serv_ids_root,
+                    result.system_profits,
+                    result.proxy_profits,
+                    result.amount,
+                    result.epoch,
+                    receipts_root,
+                );
+                assert_eq!(result.calculate_settlement_id(receipts_root), expected);
+            }
+            result.build_settlement_id();
+            assert!(result.verify_settlement_id(result.pay_ids_root));
+        }
+    }
+
+    #[test]
+    fn test_keccak_variadic() {
+        let start = B256::repeat_byte(9);
+        assert_eq!(keccak256_concat(&[b"ab", b"", b"c"]), keccak256(b"abc"));
+        assert_eq!(keccak256_concat(&[]), keccak256(&[]));
+        assert_eq!(keccak256_chain(&start, &[b"xyz"]), keccak256_more(&start, b"xyz"));
+        assert_eq!(keccak256_chain(&start, &[b"x", b"yz"]), keccak256_more(&start, b"xyz"));
+        assert_eq!(keccak256_chain(&start, &[]), keccak256(start.as_slice()));
+    }
+
+    // 引入 keccak256_concat 之前拼接 Vec 的实现，重构后的结果必须逐字节相同
+    #[allow(clippy::too_many_arguments)]
+    fn settlement_id_with_vec(
+        proxy: &EthAddress,
+        pay_ids_root: B256,
+        serv_ids_root: B256,
+        system: U256,
+        proxy_profit: U256,
+        amount: U256,
+        epoch: u64,
+        receipts_root: B256,
+    ) -> B256 {
+        let mut data = Vec::new();
+        data.extend_from_slice(proxy);
+        data.extend_from_slice(pay_ids_root.as_slice());
+        data.extend_from_slice(serv_ids_root.as_slice());
+        data.extend_from_slice(&system.to_be_bytes::<32>());
+        data.extend_from_slice(&proxy_profit.to_be_bytes::<32>());
+        data.extend_from_slice(&amount.to_be_bytes::<32>());
+        if epoch != 0 {
+            data.extend_from_slice(&epoch.to_be_bytes());
+        }
+        let inner = hash_with_domain(HashDomain::SettlementId, &[&data]);
+        let mut outer = Vec::new();
+        outer.extend_from_slice(inner.as_slice());
+        outer.extend_from_slice(receipts_root.as_slice());
+        hash_with_domain(HashDomain::SettlementId, &[&outer])
+    }
+
+    fn token_totals_hash_with_vec(totals: &[TokenSubtotal]) -> B256 {
+        let mut data = Vec::new();
+        for total in totals {
+            data.extend_from_slice(&total.token);
+            data.extend_from_slice(&total.system_profit.to_be_bytes::<32>());
+            data.extend_from_slice(&total.proxy_profit.to_be_bytes::<32>());
+            data.extend_from_slice(&total.receiver_profit.to_be_bytes::<32>());
+        }
+        hash_with_domain(HashDomain::SettlementId, &[&data])
+    }
+
+    #[test]
+    fn test_refactor_preserves_hashes() {
+        let amounts = [U256::ZERO, U256::from(1u32), U256::from(u64::MAX), U256::MAX];
+        for (i, epoch) in [0, 1, 7, u64::MAX].into_iter().enumerate() {
+            let args = (
+                [i as u8; 20],
+                B256::repeat_byte(i as u8 + 1),
+                B256::repeat_byte(i as u8 + 2),
+                amounts[i],
+                amounts[3 - i],
+                amounts[(i + 1) % 4],
+                B256::repeat_byte(0xf0 | i as u8),
+            );
+            assert_eq!(
+                settlement_id(&args.0, args.1, args.2, args.3, args.4, args.5, epoch, args.6),
+                settlement_id_with_vec(&args.0, args.1, args.2, args.3, args.4, args.5, epoch, args.6)
+            );
+        }
+
+        let totals: Vec<TokenSubtotal> = (0..3u8)
+            .map(|i| TokenSubtotal {
+                token: [i; 20],
+                system_profit: amounts[i as usize],
+                proxy_profit: U256::from(i),
+                receiver_profit: amounts[3 - i as usize],
+            })
+            .collect();
+        for len in 0..=totals.len() {
+            assert_eq!(token_totals_hash(&totals[..len]), token_totals_hash_with_vec(&totals[..len]));
+        }
+    }
+
+    #[test]
+    fn test_settlement_hashing_does_not_allocate() {
+        let mut result = sample_settlement();
+        result.epoch = 3;
+        result.token_totals = vec![TokenSubtotal::new([4u8; 20]), TokenSubtotal::new([5u8; 20])];
+        let receipts_root = B256::repeat_byte(5);
+
+        let before = crate::alloc_counter::allocations();
+        let id = result.calculate_settlement_id(receipts_root);
+        let chained = settlement_chain_root(B256::ZERO, &[id, id, id]);
+        let concat = keccak256_concat(&[id.as_slice(), chained.as_slice()]);
+        assert_eq!(crate::alloc_counter::allocations() - before, 0);
+        assert_ne!(B256::from(concat), id);
+    }
+
+    #[test]
+    fn test_settlement_history_step() {
+        assert_eq!(
+            settlement_history_step(B256::repeat_byte(1), B256::repeat_byte(2)),
+            b256!("346d8c96a2454213fcc0daff3c96ad0398148181b9fa6488f7ae2c0af5b20aa0")
+        );
+    }
+}
+
 // 使用示例
 #[cfg(test)]
 mod test_receiver_settle_result_conversion{
@@ -589,6 +1871,38 @@ mod test_receiver_settle_result_conversion{
     }
 }
 
+#[cfg(test)]
+mod test_profit_result_conversion {
+    use super::*;
+
+    #[test]
+    fn test_profit_result_conversion() {
+        let result = ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            receiver: [1u8; 20],
+            proxy: [2u8; 20],
+            receipts_root: B256::repeat_byte(3),
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+
+        let sol_result: ProfitResultStruct = result.clone().into();
+        assert_eq!(sol_result.vks_hash, result.vks_hash);
+
+        let rust_result: ProfitResult = sol_result.into();
+        assert_eq!(rust_result.vks_hash, result.vks_hash);
+        assert_eq!(rust_result.receiver, result.receiver);
+        assert_eq!(rust_result.proxy, result.proxy);
+        assert_eq!(rust_result.receiver_profit, result.receiver_profit);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
  pub struct SettlementProof {
     pub proxy: EthAddress,  //Proxy的地址
@@ -598,27 +1912,426 @@ mod test_receiver_settle_result_conversion{
 }
 impl SettlementProof {
     pub fn verify(&self) -> Result<bool,BoxError> {
-        // 1. 计算最终哈希
-        let final_hash = self.calculate_final_hash();
-        if final_hash.ne(&self.proof.value_proof.value) {
+        // 1. 从 start_history_hash 依次链接每个 settlement_id，末端必须是证明中的值
+        if !verify_settlement_chain(self.start_history_hash, &self.settlement_ids, self.proof.value_proof.value) {
             return Err("Final hash mismatch".into());
         }
         // 2. 使用 MerkleProof 验证
         self.proof.verify()
     }
+}
+#[cfg(test)]
+mod test_display {
+    use super::*;
+    use alloy_primitives::address;
 
-    /// 计算最终哈希值
-    fn calculate_final_hash(&self) -> B256 {
-        // 从 start_history_hash 开始
-        let mut current_hash = self.start_history_hash;
+    // EIP-55 规范中的示例地址
+    const RECEIVER: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    const PROXY: &str = "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359";
 
-        // 针对每个 settlement_id 计算新的哈希
-        for settlement_id in &self.settlement_ids {
-            current_hash = B256::from_slice(
-                &keccak256_more(&current_hash, settlement_id.as_slice())
-            );
+    fn receiver() -> EthAddress {
+        address!("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").into_array()
+    }
+
+    fn proxy() -> EthAddress {
+        address!("fb6916095ca1df60bb79ce92ce3ea74c37c5d359").into_array()
+    }
+
+    fn hash(byte: u8) -> String {
+        format!("0x{}", format!("{:02x}", byte).repeat(32))
+    }
+
+    #[test]
+    fn test_profit_result_display() {
+        let result = ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            receiver: receiver(),
+            proxy: proxy(),
+            receipts_root: B256::repeat_byte(3),
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(1_000_000_000_000_000_000u128),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "ProfitResult vks_hash={} receiver={} proxy={} receipts_root={} pay_ids_root={} serv_ids_root={} system_profit=10 proxy_profit=20 receiver_profit=1000000000000000000 epoch=0",
+                hash(7), RECEIVER, PROXY, hash(3), hash(4), hash(5)
+            )
+        );
+    }
+
+    #[test]
+    fn test_proxy_settlement_result_display() {
+        let result = ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::repeat_byte(0xab),
+            proxy: proxy(),
+            pay_ids_root: B256::repeat_byte(2),
+            serv_ids_root: B256::repeat_byte(3),
+            system_profits: U256::from(1u32),
+            proxy_profits: U256::from(2u32),
+            amount: U256::MAX,
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "ProxySettlementResult vks_hash={} settlement_id={} proxy={} pay_ids_root={} serv_ids_root={} system_profits=1 proxy_profits=2 amount={} epoch=0",
+                hash(1), hash(0xab), PROXY, hash(2), hash(3),
+                "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+            )
+        );
+    }
+
+    #[test]
+    fn test_receiver_settle_result_display() {
+        let result = ReceiverSettleResult {
+            vk_hash: B256::ZERO,
+            settlement_root: B256::repeat_byte(9),
+            receiver: receiver(),
+            profit: U256::from(100u32),
+        };
+
+        assert_eq!(
+            result.to_string(),
+            format!(
+                "ReceiverSettleResult vk_hash={} settlement_root={} receiver={} profit=100",
+                hash(0), hash(9), RECEIVER
+            )
+        );
+    }
+
+    #[test]
+    fn test_pay_id_info_display() {
+        let info = PayIdInfo {
+            id: U256::from(42u32),
+            amount: U256::from(1000u32),
+            sender: receiver(),
+            proxy: proxy(),
+            state: 1,
+            created_at: 1000,
+            closing_time: 2000,
+            token: [0u8; 20],
+            sig_sender: None,
+        };
+
+        assert_eq!(
+            info.to_string(),
+            format!(
+                "PayIdInfo id=42 amount=1000 sender={} proxy={} state=1 created_at=1000 closing_time=2000",
+                RECEIVER, PROXY
+            )
+        );
+    }
+
+    #[test]
+    fn test_overpay_check_result_display() {
+        let result = OverpayCheckResult {
+            payments_root: B256::repeat_byte(1),
+            receiver_proofs: vec![],
+            pay_ids_root: B256::repeat_byte(2),
+            epoch: 0,
+            commitment_version: CommitmentVersion::Legacy,
+            excluded_root: None,
+            referenced_pay_ids_root: None,
+        };
+
+        assert_eq!(
+            result.to_string(),
+            format!("OverpayCheckResult payments_root={} pay_ids_root={} receivers=[] epoch=0", hash(1), hash(2))
+        );
+    }
+
+    #[test]
+    fn test_parse_helpers() {
+        let signature = [0x1bu8; 65];
+        let text = format!("0x{}", "1b".repeat(65));
+        assert_eq!(parse_eth_signature(&text).unwrap(), signature);
+        assert_eq!(SerializableSignature::from_str(&text).unwrap().0, signature);
+        assert_eq!(SerializableSignature::new(signature).to_string(), text);
+
+        // 0x 前缀可选，大小写不敏感
+        assert_eq!(parse_eth_hash(&"AB".repeat(32)).unwrap(), [0xabu8; 32]);
+
+        assert!(parse_eth_hash(&hash(1)[..64]).is_err());
+        assert!(parse_eth_signature(&hash(1)).is_err());
+        assert!(parse_eth_hash("0xzz").is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_equality {
+    use super::*;
+    use crate::models::segment_vc::{LevelProof, SegmentProof, ValueProof};
+    use std::collections::HashSet;
+
+    fn create_test_profit_result() -> ProfitResult {
+        ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            receiver: [1u8; 20],
+            proxy: [2u8; 20],
+            receipts_root: B256::repeat_byte(3),
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        }
+    }
+
+    fn create_test_overpay_result() -> OverpayCheckResult {
+        OverpayCheckResult {
+            payments_root: B256::repeat_byte(1),
+            receiver_proofs: vec![ReceiverProof {
+                receiver: [5u8; 20],
+                proof: MerkleProof {
+                    value_proof: ValueProof {
+                        value: B256::repeat_byte(9),
+                        chunk_hash: B256::repeat_byte(8),
+                    },
+                    segment_proof: SegmentProof {
+                        chunk_index: 1,
+                        siblings: vec![B256::repeat_byte(6)],
+                    },
+                    level_proofs: vec![LevelProof {
+                        level: 0,
+                        node_index: 2,
+                        siblings: vec![B256::repeat_byte(5)],
+                    }],
+                    root_hash: B256::repeat_byte(1),
+                    hasher: Default::default(),
+                    padded: false,
+                },
+            }],
+            pay_ids_root: B256::repeat_byte(2),
+            epoch: 0,
+            commitment_version: CommitmentVersion::Legacy,
+            excluded_root: None,
+            referenced_pay_ids_root: None,
         }
+    }
+
+    #[test]
+    fn test_profit_result_equality() {
+        // sol 结构不带版本，解码为当前版本
+        let result = ProfitResult { commitment_version: CommitmentVersion::CURRENT, ..create_test_profit_result() };
+
+        let json: ProfitResult = serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(json, result);
+        let sol: ProfitResult = result.clone().to_struct().to_result();
+        assert_eq!(sol, result);
+        assert_eq!(sol.content_hash(), result.content_hash());
+
+        let mut mutated = result.clone();
+        mutated.proxy_profit += U256::from(1u32);
+        assert_ne!(mutated, result);
+        assert_ne!(mutated.content_hash(), result.content_hash());
+
+        let mut mutated = result.clone();
+        mutated.vks_hash = B256::ZERO;
+        assert_ne!(mutated.content_hash(), result.content_hash());
+
+        // 可以放入集合去重
+        let set: HashSet<ProfitResult> = vec![result.clone(), json, mutated].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_overpay_result_equality() {
+        let result = create_test_overpay_result().with_commitment_version(CommitmentVersion::CURRENT);
+
+        let json: OverpayCheckResult =
+            serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(json, result);
+        let sol: OverpayCheckResult = OverpayCheckResultStruct::from(json).try_into().unwrap();
+        assert_eq!(sol, result);
+
+        let mut mutated = result.clone();
+        mutated.receiver_proofs[0].proof.level_proofs[0].siblings[0] = B256::ZERO;
+        assert_ne!(mutated, result);
+        assert_ne!(mutated.receiver_proofs[0], result.receiver_proofs[0]);
+
+        let mut mutated = result.clone();
+        mutated.receiver_proofs[0].receiver = [6u8; 20];
+        assert_ne!(mutated, result);
+    }
+
+    #[test]
+    fn test_proxy_settlement_result_equality() {
+        let create = || ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::repeat_byte(2),
+            proxy: [3u8; 20],
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profits: U256::from(1u32),
+            proxy_profits: U256::from(2u32),
+            amount: U256::from(3u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::CURRENT,
+        };
+
+        assert_eq!(create().to_struct().to_result(), create());
+
+        let mut mutated = create();
+        mutated.amount = U256::from(4u32);
+        assert_ne!(mutated, create());
+    }
+
+    // 截断和尾部多余数据都被拒绝，截断时保留 alloy 的错误
+    fn assert_rejects_bad_lengths<T>(bytes: &[u8], decode: impl Fn(&[u8]) -> Result<T, ConversionError>) {
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), Err(ConversionError::Abi(_))));
+        assert!(matches!(decode(&bytes[..bytes.len() - 32]), Err(ConversionError::Abi(_))));
+        assert!(decode(&[]).is_err());
+
+        let mut trailing = bytes.to_vec();
+        trailing.extend_from_slice(&[0xde, 0xad]);
+        assert!(decode(&trailing).is_err());
+        trailing.extend_from_slice(&[0u8; 30]);
+        assert!(decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_public_values_round_trip() {
+        let profit = ProfitResult { commitment_version: CommitmentVersion::CURRENT, ..create_test_profit_result() };
+        let bytes = profit.to_public_values();
+        assert_eq!(bytes, ProfitResultStruct::abi_encode(&profit.clone().to_struct()));
+        assert_eq!(ProfitResult::from_public_values(&bytes).unwrap(), profit);
+        assert_rejects_bad_lengths(&bytes, ProfitResult::from_public_values);
+
+        let overpay = create_test_overpay_result().with_commitment_version(CommitmentVersion::CURRENT);
+        let bytes = overpay.to_public_values();
+        assert_eq!(OverpayCheckResult::from_public_values(&bytes).unwrap(), overpay);
+        assert_rejects_bad_lengths(&bytes, OverpayCheckResult::from_public_values);
+
+        let settlement = ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::repeat_byte(2),
+            proxy: [3u8; 20],
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profits: U256::from(1u32),
+            proxy_profits: U256::from(2u32),
+            amount: U256::MAX,
+            epoch: 9,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::CURRENT,
+        };
+        let bytes = settlement.to_public_values();
+        assert_eq!(ProxySettlementResult::from_public_values(&bytes).unwrap(), settlement);
+        assert_rejects_bad_lengths(&bytes, ProxySettlementResult::from_public_values);
+
+        let receiver = ReceiverSettleResult {
+            vk_hash: B256::repeat_byte(7),
+            settlement_root: B256::repeat_byte(8),
+            receiver: [9u8; 20],
+            profit: U256::from(1234u32),
+        };
+        let bytes = receiver.to_public_values();
+        let decoded = ReceiverSettleResult::from_public_values(&bytes).unwrap();
+        assert_eq!(
+            (decoded.vk_hash, decoded.settlement_root, decoded.receiver, decoded.profit),
+            (receiver.vk_hash, receiver.settlement_root, receiver.receiver, receiver.profit)
+        );
+        assert_rejects_bad_lengths(&bytes, ReceiverSettleResult::from_public_values);
+    }
 
-        current_hash
+    // 打包的根经过 sol 结构往返后版本和低 31 字节不变，原始结果仍能与之比较
+    #[test]
+    fn test_versioned_roots_round_trip() {
+        let mut profit = create_test_profit_result();
+        profit.commitment_version = CommitmentVersion::V2;
+        let sol = profit.to_versioned_struct();
+        assert_eq!(sol.receipts_root[0], CommitmentVersion::V2.as_byte());
+        assert_eq!(sol.serv_ids_root[1..], profit.serv_ids_root[1..]);
+        let decoded = ProfitResultStruct::abi_decode(&ProfitResultStruct::abi_encode(&sol), true).unwrap();
+        assert_eq!(decoded.commitment_version().unwrap(), CommitmentVersion::V2);
+        assert!(profit.matches_versioned_struct(&decoded).unwrap());
+        // 原始的根没有被改写
+        assert_eq!(profit.receipts_root, create_test_profit_result().receipts_root);
+
+        let mut other = profit.clone();
+        other.receiver_profit += U256::from(1u32);
+        assert!(!other.matches_versioned_struct(&decoded).unwrap());
+        let mut other = profit.clone();
+        other.commitment_version = CommitmentVersion::V1;
+        assert!(!other.matches_versioned_struct(&decoded).unwrap());
+
+        let overpay = create_test_overpay_result().with_commitment_version(CommitmentVersion::V1);
+        let sol = overpay.to_versioned_struct();
+        assert_eq!(sol.commitment_version().unwrap(), CommitmentVersion::V1);
+        assert!(overpay.matches_versioned_struct(&sol).unwrap());
+
+        let settlement = ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::repeat_byte(2),
+            proxy: [3u8; 20],
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profits: U256::from(1u32),
+            proxy_profits: U256::from(2u32),
+            amount: U256::from(3u32),
+            epoch: 9,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::CURRENT,
+        };
+        let sol = settlement.to_versioned_struct();
+        assert_eq!(sol.commitment_version().unwrap(), CommitmentVersion::CURRENT);
+        assert!(settlement.matches_versioned_struct(&sol).unwrap());
+
+        // Legacy 与未打包的 sol 结构相同，不能识别版本
+        let legacy = create_test_profit_result();
+        assert_eq!(
+            ProfitResultStruct::abi_encode(&legacy.to_versioned_struct()),
+            ProfitResultStruct::abi_encode(&legacy.clone().to_struct())
+        );
+        assert!(matches!(
+            legacy.to_versioned_struct().commitment_version(),
+            Err(ConversionError::Commitment(CommitmentError::UnknownVersion(_)))
+        ));
+
+        // 各个根的版本不同
+        let mut mixed = profit.to_versioned_struct();
+        mixed.serv_ids_root.0[0] = CommitmentVersion::V1.as_byte();
+        assert!(matches!(
+            mixed.commitment_version(),
+            Err(ConversionError::Commitment(CommitmentError::MixedVersions { .. }))
+        ));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_public_values_invalid_proof() {
+        let mut sol = OverpayCheckResultStruct::from(create_test_overpay_result());
+        sol.receiver_proofs[0].proof = Bytes::from_static(b"not a proof");
+        let bytes = OverpayCheckResultStruct::abi_encode(&sol);
+
+        let err = OverpayCheckResult::from_public_values(&bytes).unwrap_err();
+        assert!(matches!(err, ConversionError::Proof { receiver, .. } if receiver == [5u8; 20]));
+        assert!(core::error::Error::source(&err).is_some());
+        assert!(sol.to_result().is_err());
+    }
+
+    #[test]
+    fn test_signature_equality() {
+        let a = SerializableSignature::new([1u8; 65]);
+        let mut bytes = [1u8; 65];
+        bytes[64] = 2;
+        // 只有最后一个字节不同
+        assert_ne!(a, SerializableSignature::new(bytes));
+        assert_eq!(a, serde_json::from_str(&serde_json::to_string(&a).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,151 @@
+//! 结算流水线的计数点，用于在 guest 中分析 cycle 消耗
+//!
+//! 只有开启 profiling 特性时才统计：签名恢复、keccak 调用（不含树哈希）和树节点哈希在各自的位置累加到线程内计数，
+//! 带 `_with_metrics` 的入口在结束时把本次调用期间的增量和处理的收据数写入 Metrics。
+//! 未开启时计数点展开为空，Metrics 不会被调用
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+pub const RECEIPTS_PROCESSED: &str = "receipts_processed";
+pub const SIGNATURES_RECOVERED: &str = "signatures_recovered";
+pub const KECCAK_INVOCATIONS: &str = "keccak_invocations";
+pub const TREE_NODES_HASHED: &str = "tree_nodes_hashed";
+
+/// 计数的接收方，同一 label 可以被记录多次
+pub trait Metrics {
+    fn record(&self, label: &'static str, value: u64);
+}
+
+/// 丢弃所有计数，不需要统计时的默认值
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {
+    #[inline]
+    fn record(&self, _label: &'static str, _value: u64) {}
+}
+
+/// 按 label 累加的内存计数，用于测试
+#[derive(Debug, Default)]
+pub struct InMemoryMetrics {
+    counters: RefCell<BTreeMap<&'static str, u64>>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// label 的累计值，没有记录过时为 0
+    pub fn get(&self, label: &str) -> u64 {
+        self.counters.borrow().get(label).copied().unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        self.counters.borrow().clone()
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn record(&self, label: &'static str, value: u64) {
+        *self.counters.borrow_mut().entry(label).or_default() += value;
+    }
+}
+
+/// 计数点的种类，由 count_op! 宏在各处累加
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    SignatureRecovery,
+    Keccak,
+    TreeNode,
+}
+
+impl Op {
+    #[cfg(feature = "profiling")]
+    fn label(self) -> &'static str {
+        match self {
+            Op::SignatureRecovery => SIGNATURES_RECOVERED,
+            Op::Keccak => KECCAK_INVOCATIONS,
+            Op::TreeNode => TREE_NODES_HASHED,
+        }
+    }
+}
+
+#[cfg(feature = "profiling")]
+thread_local! {
+    static COUNTERS: [std::cell::Cell<u64>; 3] = Default::default();
+}
+
+#[cfg(feature = "profiling")]
+pub(crate) fn count(op: Op) {
+    COUNTERS.with(|counters| {
+        let counter = &counters[op as usize];
+        counter.set(counter.get() + 1);
+    });
+}
+
+#[cfg(feature = "profiling")]
+fn read(op: Op) -> u64 {
+    COUNTERS.with(|counters| counters[op as usize].get())
+}
+
+/// 运行 f，并把期间 ops 的计数增量写入 metrics
+pub(crate) fn measure<T>(metrics: &dyn Metrics, ops: &[Op], f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "profiling")]
+    {
+        let before: Vec<u64> = ops.iter().map(|op| read(*op)).collect();
+        let output = f();
+        for (op, before) in ops.iter().zip(before) {
+            metrics.record(op.label(), read(*op) - before);
+        }
+        output
+    }
+    #[cfg(not(feature = "profiling"))]
+    {
+        let _ = (metrics, ops);
+        f()
+    }
+}
+
+/// 直接记录一个值，未开启 profiling 时不调用 metrics
+#[inline]
+pub(crate) fn record(metrics: &dyn Metrics, label: &'static str, value: u64) {
+    #[cfg(feature = "profiling")]
+    metrics.record(label, value);
+    #[cfg(not(feature = "profiling"))]
+    let _ = (metrics, label, value);
+}
+
+/// 流水线入口统计的全部计数点
+pub(crate) const PIPELINE_OPS: [Op; 3] = [Op::SignatureRecovery, Op::Keccak, Op::TreeNode];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_memory_metrics_accumulates() {
+        let metrics = InMemoryMetrics::new();
+        metrics.record(RECEIPTS_PROCESSED, 2);
+        metrics.record(RECEIPTS_PROCESSED, 3);
+        assert_eq!(metrics.get(RECEIPTS_PROCESSED), 5);
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 0);
+        NoopMetrics.record(RECEIPTS_PROCESSED, 1);
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_measure_records_deltas() {
+        count(Op::Keccak);
+        let metrics = InMemoryMetrics::new();
+        let output = measure(&metrics, &[Op::Keccak, Op::TreeNode], || {
+            count(Op::Keccak);
+            count(Op::Keccak);
+            7
+        });
+        assert_eq!(output, 7);
+        assert_eq!(metrics.get(KECCAK_INVOCATIONS), 2);
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 0);
+        assert_eq!(metrics.snapshot().len(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use alloy_primitives::{B256, U256};
+use serde::{Deserialize, Serialize};
+
+use super::EthAddress;
+
+/// 默认保留的事件数量
+pub const DEFAULT_EVENT_RETENTION: usize = 1024;
+
+/// 带序号的事件，序号从 1 开始严格递增，快照恢复后从快照的最后序号继续
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event<E> {
+    pub seq: u64,
+    pub kind: E,
+}
+
+/// PayIdManager 的修改，每次成功修改状态的调用对应一个事件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayIdEvent {
+    Added {
+        id: U256,
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+    },
+    /// 已有的 PayId 被替换，包括换了代理的情况
+    Updated { id: U256, old_state: u8, new_state: u8 },
+    Removed {
+        id: U256,
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+    },
+    /// update_root_hash 记录的根
+    RootRecomputed {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+        root: B256,
+    },
+}
+
+/// ProxyManager::update_state 的修改，同时满足多项时取最先列出的一项
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProxyEvent {
+    /// 之前没有该代理
+    Registered {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+        staked: U256,
+    },
+    /// is_slashed 由 false 变为 true
+    Slashed {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+    },
+    /// shutdown_hash 发生变化
+    Shutdown {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+        shutdown_hash: B256,
+    },
+    /// staked 发生变化
+    Staked {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+        old_staked: U256,
+        new_staked: U256,
+    },
+    /// 其他字段的变化，或者状态没有变化
+    Updated {
+        #[serde(with = "crate::serde_hex")]
+        proxy: EthAddress,
+    },
+}
+
+/// 只追加的事件日志，最多保留 retention 个最新的事件，更早的事件被丢弃但序号不复用
+#[derive(Debug, Clone)]
+pub struct EventLog<E> {
+    events: Vec<Event<E>>,
+    last_seq: u64,
+    retention: usize,
+}
+
+impl<E> Default for EventLog<E> {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_RETENTION)
+    }
+}
+
+impl<E> EventLog<E> {
+    pub fn new(retention: usize) -> Self {
+        Self { events: Vec::new(), last_seq: 0, retention }
+    }
+
+    /// 从快照恢复：没有保留的事件，下一个事件的序号为 last_seq + 1
+    pub fn resume(last_seq: u64, retention: usize) -> Self {
+        Self { last_seq, ..Self::new(retention) }
+    }
+
+    /// 修改保留数量，超出的旧事件立即丢弃
+    pub fn set_retention(&mut self, retention: usize) {
+        self.retention = retention;
+        self.trim();
+    }
+
+    pub fn retention(&self) -> usize {
+        self.retention
+    }
+
+    /// 最近一个事件的序号，还没有事件时为 0
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    pub fn push(&mut self, kind: E) -> u64 {
+        self.last_seq += 1;
+        self.events.push(Event { seq: self.last_seq, kind });
+        self.trim();
+        self.last_seq
+    }
+
+    /// 取出所有保留的事件，之后的事件继续使用递增的序号
+    pub fn drain(&mut self) -> Vec<Event<E>> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// 序号大于 seq 的保留事件；seq 之后的事件已被丢弃时只返回仍保留的部分，调用方比较第一个事件的序号判断是否有缺口
+    pub fn since(&self, seq: u64) -> &[Event<E>] {
+        let start = self.events.partition_point(|event| event.seq <= seq);
+        &self.events[start..]
+    }
+
+    fn trim(&mut self) {
+        if self.events.len() > self.retention {
+            let excess = self.events.len() - self.retention;
+            self.events.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_and_retention() {
+        let mut log = EventLog::new(3);
+        assert_eq!(log.last_seq(), 0);
+        for i in 1..=5u64 {
+            assert_eq!(log.push(i * 10), i);
+        }
+
+        // 只保留最新的 3 个，序号不变
+        let seqs = |events: &[Event<u64>]| events.iter().map(|event| event.seq).collect::<Vec<_>>();
+        assert_eq!(seqs(log.since(0)), vec![3, 4, 5]);
+        assert_eq!(seqs(log.since(4)), vec![5]);
+        assert!(log.since(5).is_empty());
+        assert_eq!(log.since(3)[0].kind, 40);
+
+        let drained = log.drain();
+        assert_eq!(seqs(&drained), vec![3, 4, 5]);
+        assert!(log.since(0).is_empty());
+        assert_eq!(log.push(60), 6);
+
+        log.set_retention(0);
+        assert!(log.since(0).is_empty());
+        assert_eq!(log.last_seq(), 6);
+
+        let mut resumed = EventLog::resume(41, DEFAULT_EVENT_RETENTION);
+        assert_eq!(resumed.push(1u64), 42);
+    }
+}
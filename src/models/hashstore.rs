@@ -1,5 +1,23 @@
+use alloc::vec::Vec;
 use alloy_primitives::B256;
+use core::fmt;
 use super::{keccak256,keccak256_add};
+
+/// resize 和 merge 的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HashStoreError {
+    ZeroCapacity,
+}
+
+impl fmt::Display for HashStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashStoreError::ZeroCapacity => write!(f, "Hash store capacity must be greater than zero"),
+        }
+    }
+}
+
+impl core::error::Error for HashStoreError {}
 /// CircularHashStore - 简化版本
 #[derive(Debug,Clone)]
 pub struct CircularHashStore {
@@ -39,14 +57,7 @@ pub fn current_size(&self) -> usize {
 
         // 如果达到最大容量,需要更新history_hash
         if self.hashes.len() == self.capacity {
-            let old_hash = self.hashes.remove(0);
-            if self.history_hash == B256::default() {
-                self.history_hash = old_hash;
-            } else {
-                self.history_hash = keccak256_add(
-                    &self.history_hash, old_hash.as_slice()
-                ).into();
-            }
+            self.evict_oldest();
         }
 
         let position = self.hashes.len();
@@ -56,6 +67,65 @@ pub fn current_size(&self) -> usize {
         Ok(position)
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 修改容量：增大时保留所有哈希；缩小到当前数量以下时按从旧到新的顺序把多出的哈希挤入 history_hash，
+    /// 与 add_hash 挤出的规则相同
+    pub fn resize(&mut self, new_capacity: usize) -> Result<(), HashStoreError> {
+        if new_capacity == 0 {
+            return Err(HashStoreError::ZeroCapacity);
+        }
+        while self.hashes.len() > new_capacity {
+            self.evict_oldest();
+        }
+        self.capacity = new_capacity;
+        Ok(())
+    }
+
+    /// 把另一个存储追加到本存储之后，容量不变
+    ///
+    /// 组合规则：
+    /// 1. other.history_hash 非零时把它当作一个被挤出的哈希链入 history_hash：
+    ///    本存储没有历史时直接取 other.history_hash，否则为 keccak256(history_hash ‖ other.history_hash)
+    /// 2. 按顺序逐个添加 other 当前的哈希，超出容量时与 add_hash 一样挤出最旧的哈希
+    /// 3. total_added 为两者之和
+    ///
+    /// 因此 other 历史链的起点仍可用 check_hash 证明，证明为 other 原来的证明加上合并后依次挤出的哈希
+    pub fn merge(&mut self, other: &CircularHashStore) -> Result<(), HashStoreError> {
+        if self.capacity == 0 {
+            return Err(HashStoreError::ZeroCapacity);
+        }
+        if other.history_hash != Self::EMPTY_HASH {
+            self.push_history(other.history_hash);
+        }
+        for &hash in &other.hashes {
+            if self.hashes.len() == self.capacity {
+                self.evict_oldest();
+            }
+            self.hashes.push(hash);
+        }
+        self.total_added += other.total_added;
+        Ok(())
+    }
+
+    // 移除最旧的哈希并链入 history_hash
+    fn evict_oldest(&mut self) {
+        let old_hash = self.hashes.remove(0);
+        self.push_history(old_hash);
+    }
+
+    fn push_history(&mut self, hash: B256) {
+        if self.history_hash == B256::default() {
+            self.history_hash = hash;
+        } else {
+            self.history_hash = keccak256_add(
+                &self.history_hash, hash.as_slice()
+            ).into();
+        }
+    }
+
     /// 检查哈希是否存在
     pub fn check_hash(&self, hash: B256, history_proof: &[B256]) -> bool {
         // 检查当前存储
@@ -97,10 +167,29 @@ pub fn current_size(&self) -> usize {
         )
     }
 
-    /// 获取当前哈希
+    /// 获取当前哈希，即最近添加的哈希
     pub fn get_current_hash(&self) -> Option<B256> {
+        self.hashes.last().copied()
+    }
+
+    /// 最近添加的哈希，与 get_current_hash 相同
+    pub fn latest_hash(&self) -> Option<B256> {
+        self.get_current_hash()
+    }
+
+    /// 仍保留在存储中的最旧的哈希，下一次超出容量时被挤出
+    pub fn get_oldest_hash(&self) -> Option<B256> {
         self.hashes.first().copied()
     }
+
+    /// 从最新往回数的第 index_from_latest 个哈希，0 为最新；超出保留的数量时为 None
+    pub fn get_hash_at(&self, index_from_latest: usize) -> Option<B256> {
+        let len = self.hashes.len();
+        index_from_latest
+            .checked_add(1)
+            .and_then(|offset| len.checked_sub(offset))
+            .map(|index| self.hashes[index])
+    }
 }
 
 #[cfg(test)]
@@ -175,4 +264,120 @@ mod tests {
         let (_, _, _, current_hashes, _) = store.get_full_state();
         assert_eq!(current_hashes.len(), CircularHashStore::STORE_SIZE);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hash_accessors() {
+        let mut store = CircularHashStore::new(4);
+        assert_eq!(store.get_current_hash(), None);
+        assert_eq!(store.get_oldest_hash(), None);
+        assert_eq!(store.get_hash_at(0), None);
+
+        for i in 1..=3u8 {
+            store.add_hash(B256::repeat_byte(i)).unwrap();
+            assert_eq!(store.get_current_hash(), Some(B256::repeat_byte(i)));
+            assert_eq!(store.get_oldest_hash(), Some(B256::repeat_byte(1)));
+        }
+        assert_eq!(store.get_hash_at(0), Some(B256::repeat_byte(3)));
+        assert_eq!(store.get_hash_at(2), Some(B256::repeat_byte(1)));
+        assert_eq!(store.get_hash_at(3), None);
+
+        // 挤出之后：当前为 7，最旧为 4
+        for i in 4..=7u8 {
+            store.add_hash(B256::repeat_byte(i)).unwrap();
+        }
+        assert_eq!(store.get_current_hash(), Some(B256::repeat_byte(7)));
+        assert_eq!(store.latest_hash(), store.get_current_hash());
+        assert_eq!(store.get_oldest_hash(), Some(B256::repeat_byte(4)));
+        let walk: Vec<B256> = (0..store.current_size()).filter_map(|i| store.get_hash_at(i)).collect();
+        assert_eq!(walk, [7u8, 6, 5, 4].map(B256::repeat_byte).to_vec());
+        assert_eq!(store.get_hash_at(4), None);
+        assert_eq!(store.get_hash_at(usize::MAX), None);
+    }
+
+    fn filled(capacity: usize, bytes: core::ops::RangeInclusive<u8>) -> CircularHashStore {
+        let mut store = CircularHashStore::new(capacity);
+        for i in bytes {
+            store.add_hash(B256::repeat_byte(i)).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_resize_grow() -> Result<(), HashStoreError> {
+        let mut store = filled(4, 1..=6);
+        let history = store.get_full_state().2;
+        store.resize(8)?;
+        assert_eq!(store.capacity(), 8);
+        assert_eq!(store.current_size(), 4);
+        assert_eq!(store.get_full_state().2, history);
+
+        // 增大后不再挤出，直到新的容量
+        for i in 7..=10u8 {
+            store.add_hash(B256::repeat_byte(i)).unwrap();
+        }
+        assert_eq!(store.current_size(), 8);
+        assert_eq!(store.get_full_state().2, history);
+        for i in 3..=10u8 {
+            assert!(store.check_hash(B256::repeat_byte(i), &[]));
+        }
+        assert!(store.check_hash(B256::repeat_byte(1), &[B256::repeat_byte(2)]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_shrink() -> Result<(), HashStoreError> {
+        let mut store = filled(8, 1..=5);
+        store.resize(2)?;
+        assert_eq!(store.current_size(), 2);
+        assert_eq!(store.total_added(), 5);
+        assert_eq!(store.get_oldest_hash(), Some(B256::repeat_byte(4)));
+        assert_eq!(store.get_current_hash(), Some(B256::repeat_byte(5)));
+
+        // 与逐个 add_hash 挤出的结果相同
+        let expected = filled(2, 1..=5);
+        assert_eq!(store.get_full_state(), expected.get_full_state());
+
+        // 被挤出的哈希按顺序链接
+        let proof: Vec<B256> = (2..=3u8).map(B256::repeat_byte).collect();
+        assert!(store.check_hash(B256::repeat_byte(1), &proof));
+        assert!(store.check_hash(B256::repeat_byte(5), &[]));
+
+        assert_eq!(store.resize(0), Err(HashStoreError::ZeroCapacity));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), HashStoreError> {
+        // other 已有历史：1, 2 被挤出
+        let other = filled(3, 1..=5);
+        let mut store = filled(4, 11..=13);
+        store.merge(&other)?;
+
+        // other 的历史先链入，之后 11、12 被挤出
+        assert_eq!(store.current_size(), 4);
+        assert_eq!(store.total_added(), 8);
+        let (_, _, _, hashes, _) = store.get_full_state();
+        assert_eq!(hashes, [13u8, 3, 4, 5].map(B256::repeat_byte).to_vec());
+
+        // other 历史链的起点：other 原来的证明，加上合并后挤出的哈希
+        let proof = [2u8, 11, 12].map(B256::repeat_byte);
+        assert!(store.check_hash(B256::repeat_byte(1), &proof));
+        for i in [13u8, 3, 4, 5] {
+            assert!(store.check_hash(B256::repeat_byte(i), &[]));
+        }
+
+        // 继续添加后，原有的证明延长即可
+        store.add_hash(B256::repeat_byte(6)).unwrap();
+        let proof = [2u8, 11, 12, 13].map(B256::repeat_byte);
+        assert!(store.check_hash(B256::repeat_byte(1), &proof));
+
+        // 双方都有历史：本存储的历史在前，other.history_hash 作为一个元素链入
+        let mut both = filled(2, 21..=23);
+        both.merge(&other)?;
+        let other_history = other.get_full_state().2;
+        let proof = [other_history, B256::repeat_byte(22), B256::repeat_byte(23), B256::repeat_byte(3)];
+        assert!(both.check_hash(B256::repeat_byte(21), &proof));
+        assert_eq!(both.get_full_state().3, [4u8, 5].map(B256::repeat_byte).to_vec());
+        Ok(())
+    }
+}
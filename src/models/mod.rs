@@ -2,26 +2,72 @@
 
 
 pub mod hashstore;
+pub mod proof_compression;
 // pub mod mmr;
 // pub mod settlement;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
 pub mod pay_id_infos;
+#[cfg(feature = "std")]
 pub mod proof;
+#[cfg(feature = "std")]
+pub mod proxy;
+// 不开启 std 时只保留 MerkleProof 及其验证
 pub mod segment_vc;
+#[cfg(feature = "std")]
+pub mod service_fee_registry;
+pub mod settlement_tracker;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod tree_hasher;
+pub mod wire;
 
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use alloy_primitives::{U256,B256};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "zkvm")]
 use sp1_zkvm::io as spio;
-use std::collections::BTreeMap;
 // use crate::receipts::{PaymentSettledByProxy, };
 
 pub use crate::{keccak256,keccak256_more as keccak256_add,EthAddress};
 // pub use proof::Proof;
-pub use hashstore::CircularHashStore;
+pub use hashstore::{CircularHashStore, HashStoreError};
+pub use proof_compression::{CompressedProofError, COMPRESSED_PROOF_VERSION};
+pub use settlement_tracker::{CircularSettlementTracker, InMemorySettlementTracker};
+pub use tree_hasher::{KeccakHasher, Sha256Hasher, TreeHashAlgorithm, TreeHasher};
+pub use wire::{Wire, WireError};
 // pub use mmr::MerkleRangeWithDCCH;
 // pub use settlement::{ProxySettlement, ReceiverSettlement, SettlementManager};
-pub use pay_id_infos::{PayIdInfo,PayIdManager};
+#[cfg(feature = "std")]
+pub use events::{Event, EventLog, PayIdEvent, ProxyEvent, DEFAULT_EVENT_RETENTION};
+#[cfg(feature = "std")]
+pub use pay_id_infos::{PayIdInfo,PayIdManager,PayIdState,UnauthorizedPayIdInfo};
+#[cfg(feature = "std")]
+pub use proxy::{verify_proxy_state, ProxyManager, ProxyState};
+#[cfg(feature = "std")]
+pub use service_fee_registry::{ServiceFeeProof, ServiceFeeRegistry, FEE_RATE_BASE};
+#[cfg(feature = "std")]
+pub use snapshot::{SnapshotError, StateSnapshot, SNAPSHOT_VERSION};
 
+pub use segment_vc::{compute_root_from_values, compute_root_from_values_padded, render_proof};
+#[cfg(feature = "std")]
+#[allow(deprecated)]
 pub use segment_vc::print_proof;
+
+/// U256 作为 SegmentVC 的键：32 字节大端序，pay_id 1 对应 0x00..01
+///
+/// 所有以 U256 为键的树都必须经过这里，字节序不同会让根悄悄改变
+pub fn u256_to_key(value: U256) -> B256 {
+    B256::from(value.to_be_bytes::<32>())
+}
+
+/// u256_to_key 的逆变换
+pub fn key_to_u256(key: B256) -> U256 {
+    U256::from_be_bytes(key.0)
+}
+
 // 首先定义 trait
 pub trait SettlementTracker {
     /// 记录新的结算记录
@@ -54,20 +100,68 @@ pub struct ServiceFeeConfig {
     pub serv_id: u32,
     pub system_fee_rate: u16,  // 基数为10000
     pub proxy_fee_rate: u16,   // 基数为10000
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,   // 按 threshold 严格递增，为空时所有金额使用上面的费率
 }
-// 为 ServiceFeeConfig 实现读取方法
+
+/// 按金额分级的费率：金额不小于 threshold 的收据使用该级费率
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub threshold: U256,
+    pub system_fee_rate: u16,
+    pub proxy_fee_rate: u16,
+}
+
 impl ServiceFeeConfig {
-    pub fn read_from_stdin() -> Self {
-        Self {
-            serv_id: spio::read::<u32>(),
-            system_fee_rate: spio::read::<u16>(),
-            proxy_fee_rate: spio::read::<u16>(),
+    /// 单一费率的配置
+    pub fn flat(serv_id: u32, system_fee_rate: u16, proxy_fee_rate: u16) -> Self {
+        Self { serv_id, system_fee_rate, proxy_fee_rate, tiers: Vec::new() }
+    }
+
+    pub fn with_tiers(mut self, tiers: Vec<FeeTier>) -> Self {
+        self.tiers = tiers;
+        self
+    }
+
+    /// 金额对应的 (system_fee_rate, proxy_fee_rate)：threshold 不大于 amount 的最高一级，
+    /// 低于所有 threshold 时为基础费率。要求 tiers 已按 threshold 严格递增
+    pub fn rates_for(&self, amount: U256) -> (u16, u16) {
+        match self.tiers.partition_point(|tier| tier.threshold <= amount) {
+            0 => (self.system_fee_rate, self.proxy_fee_rate),
+            index => (self.tiers[index - 1].system_fee_rate, self.tiers[index - 1].proxy_fee_rate),
         }
     }
 }
+
+// 为 ServiceFeeConfig 实现读取方法；stdin 协议只包含基础费率，布局见 wire 模块
+#[cfg(feature = "zkvm")]
+impl ServiceFeeConfig {
+    /// 读取 host 用 to_stdin_bytes 写入的一帧
+    pub fn read_from_stdin() -> Result<Self, WireError> {
+        Self::from_stdin_bytes(&spio::read_vec())
+    }
+}
 pub(crate) const SEGMENT_SIZE: usize = 16;    // 每段128个元素
 pub(crate) const CHUNK_SIZE: usize = 16;      // 每chunk16个元素
 pub(crate) const NODE_WIDTH: usize = 16;      // 节点宽度
 pub(crate) const TREE_DEPTH: usize = 10;      // 树的深度
 pub(crate) const LEFT_LEAF_INDEX: usize = 1431655765; // 预计算值：(16^10 - 1) / 15
-//pub(crate) const LEFT_LEAF_INDEX: usize = (NODE_WIDTH.pow(TREE_DEPTH as u32) - 1) / (NODE_WIDTH - 1);
\ No newline at end of file
+//pub(crate) const LEFT_LEAF_INDEX: usize = (NODE_WIDTH.pow(TREE_DEPTH as u32) - 1) / (NODE_WIDTH - 1);
+
+#[cfg(test)]
+mod test_keys {
+    use super::*;
+
+    #[test]
+    fn test_u256_key_byte_order() {
+        let mut expected = [0u8; 32];
+        expected[31] = 1;
+        assert_eq!(u256_to_key(U256::from(1)), B256::from(expected));
+
+        let value = U256::from(0x0102_0304u64) << 200;
+        let key = u256_to_key(value);
+        assert_eq!(&key[..7], &[0u8, 0, 1, 2, 3, 4, 0][..]);
+        assert_eq!(key_to_u256(key), value);
+        assert_eq!(key_to_u256(u256_to_key(U256::MAX)), U256::MAX);
+    }
+}
@@ -1,21 +1,87 @@
 use alloy_sol_types::abi::Token;
-use alloy_primitives::{ B256, U256,keccak256};
-use std::collections::HashMap;
+use alloy_primitives::{ B256, U256};
+use std::collections::{BTreeSet, HashMap};
 use serde::{Serialize, Deserialize};
-use super::{EthAddress};
-use sp1_zkvm::io as spio;
+use super::{u256_to_key, EthAddress, CircularHashStore};
+use super::events::{Event, EventLog, PayIdEvent, DEFAULT_EVENT_RETENTION};
+use super::segment_vc::SegmentVC;
+use super::snapshot::{SnapshotError, StateSnapshot};
+use crate::receipts::{PayIdsProcessor, SigningDomain};
+use crate::signing_key::AsSecretKey;
+use crate::{hash_with_domain, BoxError, EthSignature, HashDomain, SignatureError};
+use std::fmt;
 
 #[derive(Debug, Clone,Serialize, Deserialize)]
 pub struct PayIdInfo {
+    #[serde(with = "crate::serde_u256_dec")]
     pub id: U256,
+    #[serde(with = "crate::serde_u256_dec")]
     pub amount: U256,
+    #[serde(with = "crate::serde_hex")]
     pub sender: EthAddress,
+    #[serde(with = "crate::serde_hex")]
     pub proxy: EthAddress,
     pub state: u8,
     pub created_at: u64,
     pub closing_time: u64,
+    /// 存款的代币，零地址（NATIVE_TOKEN）为原生资产，旧数据没有该字段
+    #[serde(default, with = "crate::serde_hex")]
+    pub token: EthAddress,
+    /// 发送者对通道参数的签名（见 sign），旧数据没有该字段；不计入 hash()，签名前后 pay_ids_root 不变
+    #[serde(default, with = "crate::serde_hex::option_signature")]
+    pub sig_sender: Option<EthSignature>,
 }
+impl fmt::Display for PayIdInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PayIdInfo id={} amount={} sender={} proxy={} state={} created_at={} closing_time={}",
+            self.id,
+            self.amount,
+            crate::format_eth_address(&self.sender),
+            crate::format_eth_address(&self.proxy),
+            self.state,
+            self.created_at,
+            self.closing_time
+        )
+    }
+}
+
+/// PayIdInfo.state 的取值，1 为活跃
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PayIdState {
+    Inactive = 0,
+    Active = 1,
+    Closing = 2,
+    Closed = 3,
+}
+
+impl From<PayIdState> for u8 {
+    fn from(state: PayIdState) -> Self {
+        state as u8
+    }
+}
+
+impl TryFrom<u8> for PayIdState {
+    type Error = String;
+
+    fn try_from(state: u8) -> Result<Self, Self::Error> {
+        match state {
+            0 => Ok(PayIdState::Inactive),
+            1 => Ok(PayIdState::Active),
+            2 => Ok(PayIdState::Closing),
+            3 => Ok(PayIdState::Closed),
+            _ => Err(format!("Unknown pay_id state {}", state)),
+        }
+    }
+}
+
 impl PayIdInfo {
+    pub fn is_in_state(&self, state: PayIdState) -> bool {
+        self.state == u8::from(state)
+    }
+
     // 使用encodePacked方式计算PayIdInfo的哈希值
     pub fn hash(&self) -> B256 {
         // 准备编码数据
@@ -37,50 +103,473 @@ impl PayIdInfo {
         
         let closing_time_bytes = self.closing_time.to_be_bytes();
         packed.extend_from_slice(&closing_time_bytes);         // uint64 closing_time
-        // 计算keccak256哈希
-     
-       let result = keccak256(&packed);
-  
-        
-        B256::from_slice(&result[..])
+        // 非原生代币时追加 address token；原生代币保持旧的 121 字节布局，长度不同不会混淆
+        if self.token != crate::NATIVE_TOKEN {
+            packed.extend_from_slice(&self.token);
+        }
+        // 计算keccak256哈希，v2-hashing 下以 PayIdLeaf 标签开头
+        hash_with_domain(HashDomain::PayIdLeaf, &[&packed])
+    }
+
+    /// 发送者签名的载荷：标签 | id | amount | proxy | created_at | closing_time，非原生代币时追加 token。
+    /// 标签区分收据签名；state 和 sig_sender 不在其中
+    pub fn signing_payload(&self) -> Vec<u8> {
+        self.signing_payload_in(None)
+    }
+
+    /// 带签名域的载荷：与收据签名相同，chain_id | contract 在 signing_payload 之前，
+    /// 一个部署上的存款授权在其他链或合约上无效
+    pub fn signing_payload_with_domain(&self, domain: &SigningDomain) -> Vec<u8> {
+        self.signing_payload_in(Some(domain))
+    }
+
+    pub(crate) fn signing_payload_in(&self, domain: Option<&SigningDomain>) -> Vec<u8> {
+        let mut packed = Vec::with_capacity(PAY_ID_INFO_SIGNING_TAG.len() + 120);
+        packed.extend_from_slice(PAY_ID_INFO_SIGNING_TAG);
+        packed.extend_from_slice(&self.id.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.amount.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.proxy);
+        packed.extend_from_slice(&self.created_at.to_be_bytes());
+        packed.extend_from_slice(&self.closing_time.to_be_bytes());
+        if self.token != crate::NATIVE_TOKEN {
+            packed.extend_from_slice(&self.token);
+        }
+        match domain {
+            Some(domain) => domain.wrap(&packed),
+            None => packed,
+        }
+    }
+
+    /// 用发送者的私钥签名通道参数，写入 sig_sender
+    pub fn sign<K: AsSecretKey + ?Sized>(&mut self, key: &K) -> Result<(), BoxError> {
+        self.sign_in(key, None)
     }
 
- 
+    /// 在指定签名域下签名
+    pub fn sign_with_domain<K: AsSecretKey + ?Sized>(&mut self, key: &K, domain: &SigningDomain) -> Result<(), BoxError> {
+        self.sign_in(key, Some(domain))
+    }
+
+    pub(crate) fn sign_in<K: AsSecretKey + ?Sized>(&mut self, key: &K, domain: Option<&SigningDomain>) -> Result<(), BoxError> {
+        self.sig_sender = Some(crate::sign_message(key, &self.signing_payload_in(domain))?);
+        Ok(())
+    }
+
+    /// 恢复签名者地址，没有签名时返回 MissingSignature；调用方比较结果与 sender
+    pub fn verify_sender(&self) -> Result<EthAddress, SignatureError> {
+        self.verify_sender_in(None)
+    }
+
+    /// 在指定签名域下恢复签名者地址
+    pub fn verify_sender_with_domain(&self, domain: &SigningDomain) -> Result<EthAddress, SignatureError> {
+        self.verify_sender_in(Some(domain))
+    }
+
+    pub(crate) fn verify_sender_in(&self, domain: Option<&SigningDomain>) -> Result<EthAddress, SignatureError> {
+        let signature = self.sig_sender.ok_or(SignatureError::MissingSignature("sender"))?;
+        let public_key = crate::recover_public_key(&signature, &self.signing_payload_in(domain))
+            .map_err(|_| SignatureError::UnrecoverableSignature("sender"))?;
+        Ok(crate::get_ethereum_address(&public_key))
+    }
+
+    /// 签名有效且签名者是 sender
+    pub fn check_authorized(&self) -> Result<(), UnauthorizedPayIdInfo> {
+        self.check_authorized_in(None)
+    }
+
+    /// 在指定签名域下检查授权
+    pub fn check_authorized_with_domain(&self, domain: &SigningDomain) -> Result<(), UnauthorizedPayIdInfo> {
+        self.check_authorized_in(Some(domain))
+    }
+
+    /// domain 为 None 时与 check_authorized 相同；配置了签名域的检查器用它验证存款授权
+    pub(crate) fn check_authorized_in(&self, domain: Option<&SigningDomain>) -> Result<(), UnauthorizedPayIdInfo> {
+        match self.verify_sender_in(domain) {
+            Ok(signer) if signer == self.sender => Ok(()),
+            signer => Err(UnauthorizedPayIdInfo { id: self.id, sender: self.sender, signer: signer.ok() }),
+        }
+    }
 }
 
+/// PayIdInfo::signing_payload 开头的标签
+pub const PAY_ID_INFO_SIGNING_TAG: &[u8] = b"zkpay:PayIdInfo";
+
+/// PayIdInfo 没有有效的发送者签名，或签名者不是 sender
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnauthorizedPayIdInfo {
+    pub id: U256,
+    pub sender: EthAddress,
+    pub signer: Option<EthAddress>, // 没有签名或无法恢复时为 None
+}
+
+impl fmt::Display for UnauthorizedPayIdInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PayIdInfo {} is not authorized by sender {}: ", self.id, crate::format_eth_address(&self.sender))?;
+        match &self.signer {
+            Some(signer) => write!(f, "signed by {}", crate::format_eth_address(signer)),
+            None => f.write_str("missing or invalid sender signature"),
+        }
+    }
+}
+
+impl std::error::Error for UnauthorizedPayIdInfo {}
+
 
 
 #[derive(Debug)]
 pub struct PayIdManager {
-    // 每个代理的PayId列表
+    // 每个代理的PayId列表，按id从小到大排序，每个id只保留最新状态
     pay_ids: HashMap<EthAddress, Vec<PayIdInfo>>,
     // 每个代理的当前根哈希
     root_hashes: HashMap<EthAddress, B256>,
     // 每个PayId的最新状态
     id_states: HashMap<U256, PayIdInfo>,
+    // 每个代理的 pay_ids SegmentVC，与 PayIdsProcessor 的布局相同，增量维护
+    vcs: HashMap<EthAddress, SegmentVC>,
+    // 发送者到其 PayId 的索引
+    sender_ids: HashMap<EthAddress, BTreeSet<U256>>,
+    // 修改事件，供主机端索引
+    events: EventLog<PayIdEvent>,
+}
+
+// 快照中的状态，HashMap 按键排序后存为列表，相同状态得到相同字节
+#[derive(Serialize, Deserialize)]
+struct PayIdManagerState {
+    pay_ids: Vec<ProxyPayIds>,
+    root_hashes: Vec<ProxyRootHash>,
+    id_states: Vec<PayIdInfo>,
+    #[serde(default)]
+    last_event_seq: u64, // 旧快照没有该字段时为 0
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProxyPayIds {
+    #[serde(with = "crate::serde_hex")]
+    proxy: EthAddress,
+    pay_ids: Vec<PayIdInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProxyRootHash {
+    #[serde(with = "crate::serde_hex")]
+    proxy: EthAddress,
+    root: B256,
 }
 
 impl PayIdManager {
+    pub const SNAPSHOT_KIND: &'static str = "pay_id_manager";
+
     pub fn new() -> Self {
         Self {
             pay_ids: HashMap::new(),
             root_hashes: HashMap::new(),
             id_states: HashMap::new(),
+            vcs: HashMap::new(),
+            sender_ids: HashMap::new(),
+            events: EventLog::default(),
         }
     }
 
+    /// 最多保留的事件数量，默认 DEFAULT_EVENT_RETENTION
+    pub fn with_event_retention(mut self, retention: usize) -> Self {
+        self.events.set_retention(retention);
+        self
+    }
+
+    /// 取出所有保留的事件
+    pub fn drain_events(&mut self) -> Vec<Event<PayIdEvent>> {
+        self.events.drain()
+    }
+
+    /// 序号大于 seq 的保留事件
+    pub fn events_since(&self, seq: u64) -> &[Event<PayIdEvent>] {
+        self.events.since(seq)
+    }
+
+    /// 最近一个事件的序号，保存在快照中
+    pub fn last_event_seq(&self) -> u64 {
+        self.events.last_seq()
+    }
+
+    /// 添加或更新 PayId，与 upsert_pay_id 相同但不返回错误
+    ///
+    /// # Panics
+    /// 列表与 SegmentVC 不同步时 panic，正常使用中不会发生
     pub fn update_pay_id(&mut self, pay_id: PayIdInfo) {
+        self.upsert_pay_id(pay_id).expect("PayId list and SegmentVC out of sync");
+    }
+
+    /// 添加或更新 PayId，并增量更新该代理的 SegmentVC 和 root_hashes
+    ///
+    /// 更新已有 id 只重新计算所在段；插入时用 SegmentVC::insert_at，id 大于该代理已有的所有 id 时
+    /// 直接追加，插入到中间时重新计算插入位置所在段及之后的段，上层节点整体重建。
+    /// 成功时记录一个 Added 或 Updated 事件
+    pub fn upsert_pay_id(&mut self, pay_id: PayIdInfo) -> Result<(), BoxError> {
         // 更新PayId状态
         let proxy = pay_id.proxy;
         let id =pay_id.id;
-        
+        let key = u256_to_key(id);
+        let value = pay_id.hash();
+        let event = match self.id_states.get(&id) {
+            Some(previous) => PayIdEvent::Updated { id, old_state: previous.state, new_state: pay_id.state },
+            None => PayIdEvent::Added { id, proxy },
+        };
+
+        // id 换了代理时先从原代理移除
+        if let Some(previous) = self.id_states.get(&id) {
+            if previous.proxy != proxy {
+                self.remove_entry(&id)?;
+            }
+        }
+
         // 更新或添加到代理的PayId列表
-        self.pay_ids.entry(proxy)
-            .or_insert_with(Vec::new)
-            .push(pay_id.clone());
+        let list = self.pay_ids.entry(proxy).or_default();
+        let vc = self
+            .vcs
+            .entry(proxy)
+            .or_insert_with(|| SegmentVC::new_hash_only(CircularHashStore::STORE_SIZE));
+        match list.binary_search_by(|info| info.id.cmp(&id)) {
+            Ok(position) => {
+                list[position] = pay_id.clone();
+                vc.update(key, value)?;
+            }
+            Err(position) => {
+                list.insert(position, pay_id.clone());
+                vc.insert_at(position, key, value)?;
+            }
+        }
+        self.refresh_root(&proxy)?;
+
+        // 更新发送者索引，发送者变化时从原发送者移除
+        if let Some(previous) = self.id_states.get(&id) {
+            if previous.sender != pay_id.sender {
+                Self::unindex_sender(&mut self.sender_ids, &previous.sender, &id);
+            }
+        }
+        self.sender_ids.entry(pay_id.sender).or_default().insert(id);
 
         // 更新PayId状态映射
         self.id_states.insert(id, pay_id);
+        self.events.push(event);
+        Ok(())
+    }
+
+    /// 移除 PayId（例如已关闭的通道），同时从 SegmentVC 中删除；id 存在时记录一个 Removed 事件
+    pub fn remove_pay_id(&mut self, id: &U256) -> Result<Option<PayIdInfo>, BoxError> {
+        let removed = self.remove_entry(id)?;
+        if let Some(pay_id) = &removed {
+            self.events.push(PayIdEvent::Removed { id: *id, proxy: pay_id.proxy });
+        }
+        Ok(removed)
+    }
+
+    // 不记录事件的移除，upsert_pay_id 换代理时也使用
+    fn remove_entry(&mut self, id: &U256) -> Result<Option<PayIdInfo>, BoxError> {
+        let pay_id = match self.id_states.remove(id) {
+            Some(pay_id) => pay_id,
+            None => return Ok(None),
+        };
+        if let Some(list) = self.pay_ids.get_mut(&pay_id.proxy) {
+            list.retain(|info| info.id != *id);
+        }
+        Self::unindex_sender(&mut self.sender_ids, &pay_id.sender, id);
+        if let Some(vc) = self.vcs.get_mut(&pay_id.proxy) {
+            vc.remove(u256_to_key(*id))?;
+        }
+        self.refresh_root(&pay_id.proxy)?;
+        Ok(Some(pay_id))
+    }
+
+    // 树变化后同步 root_hashes，get_root_hash 返回当前树的根
+    fn refresh_root(&mut self, proxy: &EthAddress) -> Result<(), BoxError> {
+        if let Some(vc) = self.vcs.get(proxy) {
+            self.root_hashes.insert(*proxy, vc.get_root_hash()?);
+        }
+        Ok(())
+    }
+
+    /// 增量维护的 pay_ids_root，没有重建开销；该代理的 PayId 全部移除后为零，从未有过 PayId 时为 None
+    ///
+    /// 树的根不可用时（例如 Error::StaleRoot）返回错误，而不是与“没有树”混为 None
+    pub fn current_root(&self, proxy: &EthAddress) -> Result<Option<B256>, BoxError> {
+        self.vcs.get(proxy).map(|vc| vc.get_root_hash()).transpose()
+    }
+
+    /// 用 PayIdsProcessor 从头计算 pay_ids_root，用于检查 current_root
+    pub fn rebuild_root(&self, proxy: &EthAddress) -> Result<B256, BoxError> {
+        match self.pay_ids.get(proxy) {
+            Some(list) if !list.is_empty() => PayIdsProcessor::get_root_hash(list),
+            _ => Ok(B256::default()),
+        }
+    }
+
+    /// 保存为带版本的快照，只保存 pay_ids、root_hashes、id_states 和最后的事件序号，
+    /// SegmentVC 和发送者索引加载时重建，事件本身不保存
+    pub fn save_to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut pay_ids: Vec<ProxyPayIds> = self
+            .pay_ids
+            .iter()
+            .map(|(proxy, list)| ProxyPayIds {
+                proxy: *proxy,
+                pay_ids: list.clone(),
+            })
+            .collect();
+        pay_ids.sort_by(|a, b| a.proxy.cmp(&b.proxy));
+
+        let mut root_hashes: Vec<ProxyRootHash> = self
+            .root_hashes
+            .iter()
+            .map(|(proxy, root)| ProxyRootHash {
+                proxy: *proxy,
+                root: *root,
+            })
+            .collect();
+        root_hashes.sort_by(|a, b| a.proxy.cmp(&b.proxy));
+
+        let mut id_states: Vec<PayIdInfo> = self.id_states.values().cloned().collect();
+        id_states.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let state = PayIdManagerState {
+            pay_ids,
+            root_hashes,
+            id_states,
+            last_event_seq: self.events.last_seq(),
+        };
+        StateSnapshot::new(Self::SNAPSHOT_KIND, state).to_bytes()
+    }
+
+    /// 从快照恢复并重新检查不变量：
+    /// 列表中的 PayId 属于所在代理、按 id 严格递增，且与 id_states 一一对应
+    pub fn load_from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let state: PayIdManagerState = StateSnapshot::from_bytes(Self::SNAPSHOT_KIND, bytes)?;
+        let mut manager = Self::new();
+        manager.events = EventLog::resume(state.last_event_seq, DEFAULT_EVENT_RETENTION);
+
+        for entry in state.id_states {
+            let id = entry.id;
+            if manager.id_states.insert(id, entry).is_some() {
+                return Err(SnapshotError::Inconsistent(format!("Duplicate id_state {}", id)));
+            }
+        }
+
+        let mut listed = 0usize;
+        for entry in state.pay_ids {
+            let proxy = entry.proxy;
+            for (position, pay_id) in entry.pay_ids.iter().enumerate() {
+                if pay_id.proxy != proxy {
+                    return Err(SnapshotError::Inconsistent(format!(
+                        "PayId {} listed under proxy {:?} but belongs to {:?}",
+                        pay_id.id, proxy, pay_id.proxy
+                    )));
+                }
+                if position > 0 && entry.pay_ids[position - 1].id >= pay_id.id {
+                    return Err(SnapshotError::Inconsistent(format!(
+                        "PayIds of proxy {:?} not strictly sorted at id {}",
+                        proxy, pay_id.id
+                    )));
+                }
+                match manager.id_states.get(&pay_id.id) {
+                    Some(state) if state.hash() == pay_id.hash() => {}
+                    _ => {
+                        return Err(SnapshotError::Inconsistent(format!(
+                            "PayId {} does not match id_states",
+                            pay_id.id
+                        )))
+                    }
+                }
+            }
+            listed += entry.pay_ids.len();
+
+            if !entry.pay_ids.is_empty() {
+                let (vc, _) = PayIdsProcessor::create_segment_vc(&entry.pay_ids)
+                    .map_err(|e| SnapshotError::Inconsistent(e.to_string()))?;
+                manager.vcs.insert(proxy, vc);
+            }
+            for pay_id in &entry.pay_ids {
+                manager.sender_ids.entry(pay_id.sender).or_default().insert(pay_id.id);
+            }
+            if manager.pay_ids.insert(proxy, entry.pay_ids).is_some() {
+                return Err(SnapshotError::Inconsistent(format!("Duplicate proxy {:?}", proxy)));
+            }
+        }
+        // 每个 id 只属于一个代理，所以列表总长度相等即一一对应
+        if listed != manager.id_states.len() {
+            return Err(SnapshotError::Inconsistent(format!(
+                "{} id_states but {} listed PayIds",
+                manager.id_states.len(),
+                listed
+            )));
+        }
+
+        for entry in state.root_hashes {
+            if manager.root_hashes.insert(entry.proxy, entry.root).is_some() {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "Duplicate root hash for proxy {:?}",
+                    entry.proxy
+                )));
+            }
+        }
+
+        Ok(manager)
+    }
+
+    fn unindex_sender(sender_ids: &mut HashMap<EthAddress, BTreeSet<U256>>, sender: &EthAddress, id: &U256) {
+        if let Some(ids) = sender_ids.get_mut(sender) {
+            ids.remove(id);
+            if ids.is_empty() {
+                sender_ids.remove(sender);
+            }
+        }
+    }
+
+    /// 某个发送者的所有 PayId，按 id 从小到大排序
+    pub fn get_pay_ids_by_sender(&self, sender: &EthAddress) -> Vec<&PayIdInfo> {
+        self.sender_ids
+            .get(sender)
+            .map(|ids| ids.iter().filter_map(|id| self.id_states.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// 代理下处于某个状态的 PayId，按 id 从小到大排序
+    pub fn get_by_state(&self, proxy: &EthAddress, state: PayIdState) -> Vec<&PayIdInfo> {
+        self.pay_ids
+            .get(proxy)
+            .map(|pay_ids| pay_ids.iter().filter(|pay_id| pay_id.is_in_state(state)).collect())
+            .unwrap_or_default()
+    }
+
+    /// 按 id 从小到大分页遍历代理下的 PayId
+    pub fn iter_pay_ids(
+        &self,
+        proxy: &EthAddress,
+        offset: usize,
+        limit: usize,
+    ) -> impl Iterator<Item = &PayIdInfo> + '_ {
+        self.pay_ids
+            .get(proxy)
+            .map(|pay_ids| pay_ids.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .skip(offset)
+            .take(limit)
+    }
+
+    pub fn count_active(&self, proxy: &EthAddress) -> usize {
+        self.pay_ids
+            .get(proxy)
+            .map(|pay_ids| pay_ids.iter().filter(|pay_id| pay_id.is_in_state(PayIdState::Active)).count())
+            .unwrap_or(0)
+    }
+
+    /// 代理下所有 PayId 的存款总额，溢出时报错
+    pub fn total_deposits(&self, proxy: &EthAddress) -> Result<U256, BoxError> {
+        let mut total = U256::ZERO;
+        for pay_id in self.pay_ids.get(proxy).into_iter().flatten() {
+            total = total
+                .checked_add(pay_id.amount)
+                .ok_or_else(|| format!("Deposit total overflow for proxy {:?}", proxy))?;
+        }
+        Ok(total)
     }
 
     pub fn get_pay_ids(&self, proxy: &EthAddress) -> Option<&Vec<PayIdInfo>> {
@@ -91,8 +580,10 @@ impl PayIdManager {
         self.id_states.get(id)
     }
 
+    /// 记录一个 RootRecomputed 事件；该代理的树下次变化时 root_hashes 被当前根覆盖
     pub fn update_root_hash(&mut self, proxy: EthAddress, root: B256) {
         self.root_hashes.insert(proxy, root);
+        self.events.push(PayIdEvent::RootRecomputed { proxy, root });
     }
 
     pub fn get_root_hash(&self, proxy: &EthAddress) -> Option<B256> {
@@ -107,10 +598,426 @@ impl PayIdManager {
         self.pay_ids.get(proxy)
             .map(|pay_ids| {
                 pay_ids.iter()
-                    .filter(|pay_id| pay_id.state == 1) // 假设1表示活跃状态
+                    .filter(|pay_id| pay_id.is_in_state(PayIdState::Active))
                     .cloned()
                     .collect()
             })
             .unwrap_or_default()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_pay_id(id: u64, amount: u64, proxy: EthAddress) -> PayIdInfo {
+        PayIdInfo {
+            id: U256::from(id),
+            amount: U256::from(amount),
+            sender: [1u8; 20],
+            proxy,
+            state: 1,
+            created_at: 1000,
+            closing_time: 2000,
+            token: [0u8; 20],
+            sig_sender: None,
+        }
+    }
+
+    fn assert_fresh(manager: &PayIdManager, proxy: &EthAddress) -> Result<(), BoxError> {
+        assert_eq!(manager.current_root(proxy)?.unwrap_or_default(), manager.rebuild_root(proxy)?);
+        // root_hashes 随树一起更新
+        assert_eq!(manager.get_root_hash(proxy), manager.current_root(proxy)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_root() -> Result<(), BoxError> {
+        let proxy = [2u8; 20];
+        let other_proxy = [3u8; 20];
+        let mut manager = PayIdManager::new();
+
+        // 按 id 递增追加
+        for id in 1..=20 {
+            manager.upsert_pay_id(create_test_pay_id(id * 10, id * 100, proxy))?;
+            assert_fresh(&manager, &proxy)?;
+        }
+
+        // 更新已有 id、插入到中间、移除，交替进行
+        enum Step {
+            Update(PayIdInfo),
+            Remove(u64),
+        }
+        let steps = vec![
+            Step::Update(create_test_pay_id(50, 1, proxy)),
+            Step::Update(create_test_pay_id(55, 555, proxy)),
+            Step::Remove(10),
+            Step::Update(create_test_pay_id(300, 3000, proxy)),
+            Step::Remove(160),
+            Step::Update(create_test_pay_id(5, 5, proxy)),
+            Step::Update(create_test_pay_id(70, 7, other_proxy)),
+            Step::Remove(300),
+        ];
+        for step in steps {
+            match step {
+                Step::Update(pay_id) => manager.upsert_pay_id(pay_id)?,
+                Step::Remove(id) => {
+                    assert!(manager.remove_pay_id(&U256::from(id))?.is_some());
+                }
+            }
+            assert_fresh(&manager, &proxy)?;
+            assert_fresh(&manager, &other_proxy)?;
+        }
+
+        // id 70 已经换到另一个代理
+        assert!(manager.get_pay_ids(&proxy).unwrap().iter().all(|info| info.id != U256::from(70)));
+        assert_eq!(manager.get_pay_ids(&other_proxy).unwrap().len(), 1);
+        assert_eq!(manager.get_pay_id(&U256::from(50)).unwrap().amount, U256::from(1));
+
+        // 移除不存在的 id
+        assert!(manager.remove_pay_id(&U256::from(12345))?.is_none());
+
+        // 不返回错误的 update_pay_id 与 upsert_pay_id 相同
+        manager.update_pay_id(create_test_pay_id(15, 150, proxy));
+        assert_eq!(manager.get_pay_id(&U256::from(15)).unwrap().amount, U256::from(150));
+        assert_fresh(&manager, &proxy)?;
+
+        // 发送者索引与 id_states 一致
+        for proxy in [proxy, other_proxy] {
+            for pay_id in manager.get_pay_ids(&proxy).unwrap() {
+                assert!(manager.get_pay_ids_by_sender(&pay_id.sender).iter().any(|info| info.id == pay_id.id));
+            }
+        }
+
+        // 全部移除后根为零
+        manager.remove_pay_id(&U256::from(70))?;
+        assert_eq!(manager.current_root(&other_proxy)?, Some(B256::default()));
+        assert_eq!(manager.current_root(&[9u8; 20])?, None);
+        assert_fresh(&manager, &other_proxy)?;
+
+        // 根不可用时返回错误，不与没有树混为 None
+        let vc = manager.vcs.get_mut(&proxy).unwrap();
+        vc.start_building();
+        vc.insert(B256::repeat_byte(0xEE), B256::repeat_byte(1))?;
+        assert!(manager.current_root(&proxy).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sender_index_and_state_queries() -> Result<(), BoxError> {
+        let proxy = [2u8; 20];
+        let alice = [0xA1u8; 20];
+        let bob = [0xB0u8; 20];
+        let mut manager = PayIdManager::new();
+
+        for id in 1..=6u64 {
+            let mut pay_id = create_test_pay_id(id, id * 100, proxy);
+            pay_id.sender = if id % 2 == 0 { bob } else { alice };
+            manager.upsert_pay_id(pay_id)?;
+        }
+        fn ids(infos: Vec<&PayIdInfo>) -> Vec<u64> {
+            infos.iter().map(|info| info.id.to::<u64>()).collect()
+        }
+        assert_eq!(ids(manager.get_pay_ids_by_sender(&alice)), vec![1, 3, 5]);
+        assert_eq!(ids(manager.get_pay_ids_by_sender(&bob)), vec![2, 4, 6]);
+
+        // 状态变化和发送者变化后索引保持一致
+        let mut closed = manager.get_pay_id(&U256::from(3)).unwrap().clone();
+        closed.state = PayIdState::Closed.into();
+        manager.upsert_pay_id(closed)?;
+        let mut moved = manager.get_pay_id(&U256::from(5)).unwrap().clone();
+        moved.sender = bob;
+        manager.upsert_pay_id(moved)?;
+
+        assert_eq!(ids(manager.get_pay_ids_by_sender(&alice)), vec![1, 3]);
+        assert_eq!(ids(manager.get_pay_ids_by_sender(&bob)), vec![2, 4, 5, 6]);
+        assert_eq!(ids(manager.get_by_state(&proxy, PayIdState::Closed)), vec![3]);
+        assert_eq!(ids(manager.get_by_state(&proxy, PayIdState::Active)), vec![1, 2, 4, 5, 6]);
+        assert_eq!(manager.count_active(&proxy), 5);
+        assert_eq!(manager.get_active_pay_ids(&proxy).len(), 5);
+
+        manager.remove_pay_id(&U256::from(1))?;
+        assert_eq!(ids(manager.get_pay_ids_by_sender(&alice)), vec![3]);
+        manager.remove_pay_id(&U256::from(3))?;
+        assert!(manager.get_pay_ids_by_sender(&alice).is_empty());
+
+        // 存款总额
+        assert_eq!(manager.total_deposits(&proxy)?, U256::from(200 + 400 + 500 + 600));
+        assert_eq!(manager.total_deposits(&[9u8; 20])?, U256::ZERO);
+        let mut huge = create_test_pay_id(100, 0, proxy);
+        huge.amount = U256::MAX;
+        manager.upsert_pay_id(huge)?;
+        assert!(manager.total_deposits(&proxy).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pagination() -> Result<(), BoxError> {
+        let proxy = [2u8; 20];
+        let mut manager = PayIdManager::new();
+        // 乱序插入，分页按 id 排序
+        for id in [7u64, 3, 9, 1, 5] {
+            manager.upsert_pay_id(create_test_pay_id(id, 100, proxy))?;
+        }
+
+        let page = |offset, limit| {
+            manager
+                .iter_pay_ids(&proxy, offset, limit)
+                .map(|info| info.id.to::<u64>())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(page(0, 2), vec![1, 3]);
+        assert_eq!(page(2, 2), vec![5, 7]);
+        assert_eq!(page(4, 2), vec![9]);
+        assert!(page(5, 2).is_empty());
+        assert!(page(100, 2).is_empty());
+        assert!(page(0, 0).is_empty());
+        assert_eq!(page(0, usize::MAX).len(), 5);
+        assert_eq!(manager.iter_pay_ids(&[9u8; 20], 0, 10).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_id_state_conversion() {
+        for state in [PayIdState::Inactive, PayIdState::Active, PayIdState::Closing, PayIdState::Closed] {
+            assert_eq!(PayIdState::try_from(u8::from(state)), Ok(state));
+        }
+        assert!(PayIdState::try_from(4).is_err());
+    }
+
+    #[test]
+    fn test_token_in_hash() {
+        let native = create_test_pay_id(1, 100, [2u8; 20]);
+        let mut token = native.clone();
+        token.token = [0xaau8; 20];
+        assert_ne!(token.hash(), native.hash());
+
+        // 旧的 JSON 没有 token 字段，哈希不变
+        let mut object = serde_json::to_value(&native).unwrap().as_object().unwrap().clone();
+        object.remove("token");
+        let decoded: PayIdInfo = serde_json::from_value(object.into()).unwrap();
+        assert_eq!(decoded.token, crate::NATIVE_TOKEN);
+        assert_eq!(decoded.hash(), native.hash());
+    }
+
+    fn snapshot_fixture() -> Result<PayIdManager, BoxError> {
+        let mut manager = PayIdManager::new();
+        for id in [5u64, 1, 9, 3] {
+            manager.upsert_pay_id(create_test_pay_id(id, id * 100, [2u8; 20]))?;
+        }
+        let mut other = create_test_pay_id(4, 400, [3u8; 20]);
+        other.sender = [0xB0u8; 20];
+        manager.upsert_pay_id(other)?;
+        manager.update_root_hash([2u8; 20], B256::repeat_byte(7));
+        Ok(manager)
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() -> Result<(), BoxError> {
+        let manager = snapshot_fixture()?;
+        let bytes = manager.save_to_bytes()?;
+        let mut restored = PayIdManager::load_from_bytes(&bytes)?;
+
+        // 保存结果与 HashMap 遍历顺序无关
+        assert_eq!(restored.save_to_bytes()?, bytes);
+        for proxy in [[2u8; 20], [3u8; 20]] {
+            assert_eq!(restored.current_root(&proxy)?, manager.current_root(&proxy)?);
+            assert_eq!(
+                restored.get_pay_ids(&proxy).map(|list| list.len()),
+                manager.get_pay_ids(&proxy).map(|list| list.len())
+            );
+        }
+        assert_eq!(restored.get_root_hash(&[2u8; 20]), Some(B256::repeat_byte(7)));
+        assert_eq!(restored.get_pay_ids_by_sender(&[0xB0u8; 20]).len(), 1);
+
+        // 恢复后的状态可以继续增量更新
+        restored.upsert_pay_id(create_test_pay_id(20, 2000, [2u8; 20]))?;
+        restored.upsert_pay_id(create_test_pay_id(2, 200, [2u8; 20]))?;
+        restored.remove_pay_id(&U256::from(9))?;
+        assert_fresh(&restored, &[2u8; 20])?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_rejects_bad_blobs() -> Result<(), BoxError> {
+        let bytes = snapshot_fixture()?.save_to_bytes()?;
+
+        // 截断
+        assert!(matches!(
+            PayIdManager::load_from_bytes(&bytes[..bytes.len() - 3]),
+            Err(SnapshotError::Malformed(_))
+        ));
+
+        // 未知版本
+        let text = String::from_utf8(bytes.clone())?;
+        let future = text.replacen("\"version\":1", "\"version\":99", 1);
+        assert_eq!(
+            PayIdManager::load_from_bytes(future.as_bytes()).err(),
+            Some(SnapshotError::UnsupportedVersion(99))
+        );
+
+        // 类型不匹配
+        assert!(matches!(
+            crate::models::ProxyManager::load_from_bytes(&bytes),
+            Err(SnapshotError::WrongKind { .. })
+        ));
+
+        // 解析成功但列表与 id_states 不一致：修改列表中第一个 PayId 的金额
+        let mut value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        value["state"]["pay_ids"][0]["pay_ids"][0]["amount"] = serde_json::json!("0x1");
+        let tampered = serde_json::to_vec(&value)?;
+        assert!(matches!(
+            PayIdManager::load_from_bytes(&tampered),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+
+        // 列表乱序
+        let mut value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        let list = value["state"]["pay_ids"][0]["pay_ids"].as_array_mut().unwrap();
+        list.swap(0, 1);
+        let tampered = serde_json::to_vec(&value)?;
+        assert!(matches!(
+            PayIdManager::load_from_bytes(&tampered),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+
+        // 缺少 id_states
+        let mut value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        value["state"]["id_states"].as_array_mut().unwrap().pop();
+        let tampered = serde_json::to_vec(&value)?;
+        assert!(matches!(
+            PayIdManager::load_from_bytes(&tampered),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_log() -> Result<(), BoxError> {
+        let proxy = [2u8; 20];
+        let mut manager = PayIdManager::new();
+        manager.upsert_pay_id(create_test_pay_id(1, 100, proxy))?;
+        manager.upsert_pay_id(create_test_pay_id(2, 200, proxy))?;
+        let mut closed = create_test_pay_id(1, 100, proxy);
+        closed.state = 2;
+        manager.upsert_pay_id(closed)?;
+        // 换代理只记录一次更新
+        manager.upsert_pay_id(create_test_pay_id(2, 200, [3u8; 20]))?;
+        manager.update_root_hash(proxy, B256::repeat_byte(7));
+        assert!(manager.remove_pay_id(&U256::from(1))?.is_some());
+        // 不存在的 id 没有修改，不记录事件
+        assert!(manager.remove_pay_id(&U256::from(1))?.is_none());
+
+        let events = manager.events_since(0);
+        assert_eq!(events.iter().map(|event| event.seq).collect::<Vec<_>>(), (1..=6).collect::<Vec<_>>());
+        let kinds: Vec<PayIdEvent> = events.iter().map(|event| event.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                PayIdEvent::Added { id: U256::from(1), proxy },
+                PayIdEvent::Added { id: U256::from(2), proxy },
+                PayIdEvent::Updated { id: U256::from(1), old_state: 1, new_state: 2 },
+                PayIdEvent::Updated { id: U256::from(2), old_state: 1, new_state: 1 },
+                PayIdEvent::RootRecomputed { proxy, root: B256::repeat_byte(7) },
+                PayIdEvent::Removed { id: U256::from(1), proxy },
+            ]
+        );
+        assert_eq!(manager.events_since(4).len(), 2);
+        assert_eq!(manager.last_event_seq(), 6);
+
+        // 快照保存最后的序号，恢复后从该序号继续
+        let bytes = manager.save_to_bytes()?;
+        let mut restored = PayIdManager::load_from_bytes(&bytes)?;
+        assert_eq!(restored.last_event_seq(), 6);
+        assert!(restored.events_since(0).is_empty());
+        restored.upsert_pay_id(create_test_pay_id(5, 500, proxy))?;
+        assert_eq!(restored.drain_events().iter().map(|event| event.seq).collect::<Vec<_>>(), vec![7]);
+        assert!(restored.drain_events().is_empty());
+
+        // 旧快照没有序号
+        let mut value: serde_json::Value = serde_json::from_slice(&bytes)?;
+        value["state"].as_object_mut().unwrap().remove("last_event_seq");
+        assert_eq!(PayIdManager::load_from_bytes(&serde_json::to_vec(&value)?)?.last_event_seq(), 0);
+
+        let mut bounded = PayIdManager::new().with_event_retention(2);
+        for id in 1..=5 {
+            bounded.upsert_pay_id(create_test_pay_id(id, 100, proxy))?;
+        }
+        assert_eq!(bounded.events_since(0).iter().map(|event| event.seq).collect::<Vec<_>>(), vec![4, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sender_signature() -> Result<(), BoxError> {
+        let (key, public_key) = crate::ethaddr_gen::keypair_from_seed(7);
+        let mut info = create_test_pay_id(1, 100, [2u8; 20]);
+        info.sender = crate::get_ethereum_address(&public_key);
+        let hash = info.hash();
+
+        assert_eq!(info.verify_sender(), Err(SignatureError::MissingSignature("sender")));
+        assert_eq!(info.check_authorized().unwrap_err().signer, None);
+
+        // 签名不改变叶子哈希，state 不在载荷中
+        info.sign(&key)?;
+        assert_eq!(info.hash(), hash);
+        assert_eq!(info.verify_sender()?, info.sender);
+        info.check_authorized()?;
+        info.state = 2;
+        info.check_authorized()?;
+
+        // 改动通道参数后签名者不再是 sender
+        let mut tampered = info.clone();
+        tampered.amount = U256::from(101);
+        let err = tampered.check_authorized().unwrap_err();
+        assert_eq!((err.id, err.sender), (tampered.id, tampered.sender));
+        assert_ne!(err.signer, Some(tampered.sender));
+
+        // 其他人的签名
+        let mut forged = info.clone();
+        forged.sign(&crate::ethaddr_gen::keypair_from_seed(8).0)?;
+        assert!(forged.check_authorized().is_err());
+
+        // JSON 往返保留签名，旧格式没有该字段时为 None
+        let json = serde_json::to_string(&info)?;
+        let decoded: PayIdInfo = serde_json::from_str(&json)?;
+        assert_eq!((decoded.sig_sender, decoded.hash()), (info.sig_sender, hash));
+        let mut legacy: serde_json::Value = serde_json::from_str(&json)?;
+        legacy.as_object_mut().unwrap().remove("sig_sender");
+        let legacy: PayIdInfo = serde_json::from_value(legacy)?;
+        assert_eq!(legacy.sig_sender, None);
+        assert_eq!(legacy.hash(), hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sender_signature_domain() -> Result<(), BoxError> {
+        let (key, public_key) = crate::ethaddr_gen::keypair_from_seed(9);
+        let mainnet = SigningDomain::new(1, [9u8; 20]);
+        let mut info = create_test_pay_id(1, 100, [2u8; 20]);
+        info.sender = crate::get_ethereum_address(&public_key);
+
+        // 载荷与收据签名一样在前面加上 chain_id | contract
+        assert_eq!(info.signing_payload_with_domain(&mainnet), mainnet.wrap(&info.signing_payload()));
+
+        info.sign_with_domain(&key, &mainnet)?;
+        assert_eq!(info.verify_sender_with_domain(&mainnet)?, info.sender);
+        info.check_authorized_with_domain(&mainnet)?;
+
+        // 在其他链、其他合约或不带域时都不是 sender 的授权
+        assert!(info.check_authorized_with_domain(&SigningDomain::new(5, [9u8; 20])).is_err());
+        assert!(info.check_authorized_with_domain(&SigningDomain::new(1, [8u8; 20])).is_err());
+        assert!(info.check_authorized().is_err());
+
+        // 不带域的旧签名在配置了域时同样被拒绝
+        let mut unscoped = info.clone();
+        unscoped.sign(&key)?;
+        unscoped.check_authorized()?;
+        assert!(unscoped.check_authorized_with_domain(&mainnet).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+//! MerkleProof 的压缩编码，用于链下传输
+//!
+//! 布局：
+//! - 版本(1)：当前为 1
+//! - 标志(1)：bit0 为 Sha256，bit1 为 padded，其余位必须为 0
+//! - 字典：varint 数量，之后是按首次出现顺序排列、互不相同的哈希，每个 32 字节
+//! - value、chunk_hash 的字典下标，chunk_index，段内兄弟节点数量和下标
+//! - level_proofs 数量，每层的 level、node_index、兄弟节点数量和下标
+//! - root_hash 的字典下标
+//!
+//! 所有整数都是 LEB128 varint。稀疏的树中大量兄弟节点是相同的默认值，只在字典中保存一次。
+//! 编码是确定的，解码时重新编码并要求与输入完全相同，因此每个证明只有一种合法编码
+use alloc::vec::Vec;
+use alloy_primitives::B256;
+use core::error::Error as StdError;
+use core::fmt;
+
+use super::segment_vc::{
+    LevelProof, MerkleProof, ProofTooLarge, SegmentProof, ValueProof, MAX_PROOF_LEVELS,
+    MAX_PROOF_SIBLINGS_PER_LEVEL, MAX_PROOF_TOTAL_SIBLINGS,
+};
+use super::TreeHashAlgorithm;
+
+/// 压缩编码的版本
+pub const COMPRESSED_PROOF_VERSION: u8 = 1;
+
+const FLAG_SHA256: u8 = 0b01;
+const FLAG_PADDED: u8 = 0b10;
+// value、chunk_hash、root_hash 加上所有兄弟节点
+const MAX_DICTIONARY_LEN: usize = MAX_PROOF_TOTAL_SIBLINGS + 3;
+
+/// from_compressed_bytes 的失败原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressedProofError {
+    /// 输入在 offset 处提前结束
+    Truncated { offset: usize },
+    UnsupportedVersion(u8),
+    UnknownFlags(u8),
+    /// varint 超过 64 位
+    VarintOverflow { offset: usize },
+    /// 哈希下标超出字典
+    InvalidReference { index: u64, dictionary_len: usize },
+    TooLarge(ProofTooLarge),
+    /// 解码完成后还有多余的字节
+    TrailingBytes(usize),
+    /// 能够解码，但不是该证明的规范编码（varint 不是最短形式、字典有重复或顺序不对等）
+    NonCanonical,
+}
+
+impl fmt::Display for CompressedProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressedProofError::Truncated { offset } => write!(f, "Compressed proof truncated at byte {}", offset),
+            CompressedProofError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported compressed proof version {}", version)
+            }
+            CompressedProofError::UnknownFlags(flags) => write!(f, "Unknown compressed proof flags {:#04x}", flags),
+            CompressedProofError::VarintOverflow { offset } => write!(f, "Varint overflow at byte {}", offset),
+            CompressedProofError::InvalidReference { index, dictionary_len } => write!(
+                f,
+                "Hash reference {} out of range for dictionary of {} hashes",
+                index, dictionary_len
+            ),
+            CompressedProofError::TooLarge(e) => write!(f, "{}", e),
+            CompressedProofError::TrailingBytes(len) => write!(f, "{} trailing bytes after compressed proof", len),
+            CompressedProofError::NonCanonical => f.write_str("Compressed proof is not canonically encoded"),
+        }
+    }
+}
+
+impl StdError for CompressedProofError {}
+
+impl From<ProofTooLarge> for CompressedProofError {
+    fn from(e: ProofTooLarge) -> Self {
+        CompressedProofError::TooLarge(e)
+    }
+}
+
+impl MerkleProof {
+    /// 压缩编码，相同的哈希只保存一次，见模块文档
+    pub fn to_compressed_bytes(&self) -> Vec<u8> {
+        let (dictionary, references) = self.dictionary();
+        let mut out = Vec::with_capacity(self.encoded_size_estimate());
+        out.push(COMPRESSED_PROOF_VERSION);
+        out.push(self.flags());
+
+        write_varint(&mut out, dictionary.len() as u64);
+        for hash in &dictionary {
+            out.extend_from_slice(hash.as_slice());
+        }
+
+        let mut references = references.into_iter();
+        let mut reference = |out: &mut Vec<u8>| write_varint(out, references.next().unwrap_or_default() as u64);
+        reference(&mut out);
+        reference(&mut out);
+        write_varint(&mut out, self.segment_proof.chunk_index as u64);
+        write_varint(&mut out, self.segment_proof.siblings.len() as u64);
+        for _ in &self.segment_proof.siblings {
+            reference(&mut out);
+        }
+        write_varint(&mut out, self.level_proofs.len() as u64);
+        for proof in &self.level_proofs {
+            write_varint(&mut out, proof.level as u64);
+            write_varint(&mut out, proof.node_index as u64);
+            write_varint(&mut out, proof.siblings.len() as u64);
+            for _ in &proof.siblings {
+                reference(&mut out);
+            }
+        }
+        reference(&mut out);
+        out
+    }
+
+    /// to_compressed_bytes 的逆变换，长度在分配之前按证明的大小上限检查
+    pub fn from_compressed_bytes(bytes: &[u8]) -> Result<Self, CompressedProofError> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        let version = reader.byte()?;
+        if version != COMPRESSED_PROOF_VERSION {
+            return Err(CompressedProofError::UnsupportedVersion(version));
+        }
+        let flags = reader.byte()?;
+        if flags & !(FLAG_SHA256 | FLAG_PADDED) != 0 {
+            return Err(CompressedProofError::UnknownFlags(flags));
+        }
+
+        let dictionary_len = reader.len("dictionary", MAX_DICTIONARY_LEN)?;
+        let mut dictionary = Vec::with_capacity(dictionary_len);
+        for _ in 0..dictionary_len {
+            dictionary.push(reader.hash()?);
+        }
+        let hash = |reader: &mut Reader<'_>| -> Result<B256, CompressedProofError> {
+            let index = reader.varint()?;
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| dictionary.get(index).copied())
+                .ok_or(CompressedProofError::InvalidReference { index, dictionary_len })
+        };
+
+        let value_proof = ValueProof { value: hash(&mut reader)?, chunk_hash: hash(&mut reader)? };
+        let chunk_index = reader.usize()?;
+        let siblings_len = reader.len("segment siblings", MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+        let mut total_siblings = siblings_len;
+        let mut siblings = Vec::with_capacity(siblings_len);
+        for _ in 0..siblings_len {
+            siblings.push(hash(&mut reader)?);
+        }
+        let segment_proof = SegmentProof { chunk_index, siblings };
+
+        let levels_len = reader.len("level proofs", MAX_PROOF_LEVELS)?;
+        let mut level_proofs = Vec::with_capacity(levels_len);
+        for _ in 0..levels_len {
+            let level = reader.usize()?;
+            let node_index = reader.usize()?;
+            let siblings_len = reader.len("level siblings", MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+            total_siblings += siblings_len;
+            if total_siblings > MAX_PROOF_TOTAL_SIBLINGS {
+                return Err(ProofTooLarge { field: "total siblings", len: total_siblings, max: MAX_PROOF_TOTAL_SIBLINGS }.into());
+            }
+            let mut siblings = Vec::with_capacity(siblings_len);
+            for _ in 0..siblings_len {
+                siblings.push(hash(&mut reader)?);
+            }
+            level_proofs.push(LevelProof { level, node_index, siblings });
+        }
+        let root_hash = hash(&mut reader)?;
+
+        let remaining = bytes.len() - reader.offset;
+        if remaining != 0 {
+            return Err(CompressedProofError::TrailingBytes(remaining));
+        }
+
+        let proof = MerkleProof {
+            value_proof,
+            segment_proof,
+            level_proofs,
+            root_hash,
+            hasher: if flags & FLAG_SHA256 != 0 { TreeHashAlgorithm::Sha256 } else { TreeHashAlgorithm::Keccak },
+            padded: flags & FLAG_PADDED != 0,
+        };
+        if proof.to_compressed_bytes() != bytes {
+            return Err(CompressedProofError::NonCanonical);
+        }
+        Ok(proof)
+    }
+
+    /// to_compressed_bytes 输出的字节数，只建立字典，不分配输出
+    pub fn encoded_size_estimate(&self) -> usize {
+        let (dictionary, references) = self.dictionary();
+        let mut size = 2 + varint_len(dictionary.len() as u64) + dictionary.len() * 32;
+        size += references.iter().map(|&index| varint_len(index as u64)).sum::<usize>();
+        size += varint_len(self.segment_proof.chunk_index as u64);
+        size += varint_len(self.segment_proof.siblings.len() as u64);
+        size += varint_len(self.level_proofs.len() as u64);
+        for proof in &self.level_proofs {
+            size += varint_len(proof.level as u64);
+            size += varint_len(proof.node_index as u64);
+            size += varint_len(proof.siblings.len() as u64);
+        }
+        size
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.hasher == TreeHashAlgorithm::Sha256 {
+            flags |= FLAG_SHA256;
+        }
+        if self.padded {
+            flags |= FLAG_PADDED;
+        }
+        flags
+    }
+
+    // 按编码顺序遍历所有哈希，返回去重后的字典和每个哈希的下标；证明最多约 170 个哈希，线性查找即可
+    fn dictionary(&self) -> (Vec<B256>, Vec<usize>) {
+        let hashes = [self.value_proof.value, self.value_proof.chunk_hash]
+            .into_iter()
+            .chain(self.segment_proof.siblings.iter().copied())
+            .chain(self.level_proofs.iter().flat_map(|proof| proof.siblings.iter().copied()))
+            .chain(core::iter::once(self.root_hash));
+
+        let mut dictionary: Vec<B256> = Vec::new();
+        let mut references = Vec::new();
+        for hash in hashes {
+            let index = match dictionary.iter().position(|entry| *entry == hash) {
+                Some(index) => index,
+                None => {
+                    dictionary.push(hash);
+                    dictionary.len() - 1
+                }
+            };
+            references.push(index);
+        }
+        (dictionary, references)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, CompressedProofError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(CompressedProofError::Truncated { offset: self.offset })?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn hash(&mut self) -> Result<B256, CompressedProofError> {
+        let end = self.offset + 32;
+        let hash = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or(CompressedProofError::Truncated { offset: self.bytes.len() })?;
+        self.offset = end;
+        Ok(B256::from_slice(hash))
+    }
+
+    fn varint(&mut self) -> Result<u64, CompressedProofError> {
+        let start = self.offset;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                // 第 10 个字节只能提供最高位
+                if shift == 63 && byte > 1 {
+                    break;
+                }
+                return Ok(value);
+            }
+        }
+        Err(CompressedProofError::VarintOverflow { offset: start })
+    }
+
+    fn usize(&mut self) -> Result<usize, CompressedProofError> {
+        let start = self.offset;
+        usize::try_from(self.varint()?).map_err(|_| CompressedProofError::VarintOverflow { offset: start })
+    }
+
+    // 数量在分配之前检查
+    fn len(&mut self, field: &'static str, max: usize) -> Result<usize, CompressedProofError> {
+        let len = self.varint()?;
+        match usize::try_from(len) {
+            Ok(len) if len <= max => Ok(len),
+            _ => Err(ProofTooLarge { field, len: usize::try_from(len).unwrap_or(usize::MAX), max }.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::segment_vc::SegmentVC;
+    use alloc::vec;
+    use crate::BoxError;
+
+    // 每层都是相同默认值兄弟节点的证明，与稀疏段中的证明相同
+    fn sparse_proof() -> MerkleProof {
+        MerkleProof {
+            value_proof: ValueProof { value: B256::repeat_byte(1), chunk_hash: B256::repeat_byte(2) },
+            segment_proof: SegmentProof { chunk_index: 3, siblings: vec![B256::ZERO; MAX_PROOF_SIBLINGS_PER_LEVEL] },
+            level_proofs: (0..MAX_PROOF_LEVELS)
+                .map(|level| LevelProof {
+                    level,
+                    node_index: level,
+                    siblings: vec![B256::ZERO; MAX_PROOF_SIBLINGS_PER_LEVEL],
+                })
+                .collect(),
+            root_hash: B256::repeat_byte(3),
+            hasher: TreeHashAlgorithm::Keccak,
+            padded: true,
+        }
+    }
+
+    #[test]
+    fn test_compressed_round_trip() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(64).with_hasher(TreeHashAlgorithm::Sha256);
+        let entries: Vec<(B256, B256)> = (0..40u8).map(|i| (B256::repeat_byte(i + 1), B256::repeat_byte(i + 100))).collect();
+        vc.insert_batch(entries.clone())?;
+
+        let mut proofs = vec![sparse_proof()];
+        for (key, _) in entries.iter().step_by(7) {
+            proofs.push(vc.generate_proof(*key)?);
+        }
+        for proof in proofs {
+            let bytes = proof.to_compressed_bytes();
+            assert_eq!(bytes.len(), proof.encoded_size_estimate());
+            assert_eq!(MerkleProof::from_compressed_bytes(&bytes)?, proof);
+            // 确定性：重复编码得到相同的字节
+            assert_eq!(proof.clone().to_compressed_bytes(), bytes);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_dedupes_default_siblings() -> Result<(), BoxError> {
+        let proof = sparse_proof();
+        let compressed = proof.to_compressed_bytes();
+        let uncompressed = bincode::serialize(&proof)?;
+
+        // 165 个相同的兄弟节点只保存一次：4 个不同的哈希，每个引用 1 字节
+        assert_eq!(compressed.len(), 2 + 1 + 4 * 32 + 2 + 1 + 1 + 165 + 1 + 10 * 3 + 1);
+        assert!(compressed.len() * 10 < uncompressed.len(), "{} vs {}", compressed.len(), uncompressed.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_corrupted_input() {
+        let bytes = sparse_proof().to_compressed_bytes();
+        let decode = |bytes: &[u8]| MerkleProof::from_compressed_bytes(bytes).unwrap_err();
+
+        assert_eq!(decode(&[]), CompressedProofError::Truncated { offset: 0 });
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), CompressedProofError::Truncated { .. }));
+        assert!(matches!(decode(&bytes[..40]), CompressedProofError::Truncated { .. }));
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] = 2;
+        assert_eq!(decode(&corrupted), CompressedProofError::UnsupportedVersion(2));
+
+        let mut corrupted = bytes.clone();
+        corrupted[1] = 0x80;
+        assert_eq!(decode(&corrupted), CompressedProofError::UnknownFlags(0x80));
+
+        // root_hash 的下标超出字典
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() = 9;
+        assert_eq!(decode(&corrupted), CompressedProofError::InvalidReference { index: 9, dictionary_len: 4 });
+
+        let mut corrupted = bytes.clone();
+        corrupted.push(0);
+        assert_eq!(decode(&corrupted), CompressedProofError::TrailingBytes(1));
+
+        // 字典数量超过上限，在分配之前拒绝
+        let mut corrupted = bytes[..2].to_vec();
+        write_varint(&mut corrupted, u64::MAX);
+        assert!(matches!(decode(&corrupted), CompressedProofError::TooLarge(_)));
+
+        // 非最短的 varint：root_hash 下标 3 写成两个字节
+        let mut corrupted = bytes[..bytes.len() - 1].to_vec();
+        corrupted.extend_from_slice(&[0x83, 0x00]);
+        assert_eq!(decode(&corrupted), CompressedProofError::NonCanonical);
+
+        // 修改字典中的哈希仍能解码，但得到不同的证明
+        let mut corrupted = bytes.clone();
+        corrupted[3] ^= 1;
+        assert_ne!(MerkleProof::from_compressed_bytes(&corrupted).unwrap(), sparse_proof());
+    }
+
+    #[test]
+    fn test_varint_len() {
+        for value in [0u64, 1, 127, 128, 16_383, 16_384, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(out.len(), varint_len(value), "{}", value);
+            assert_eq!(Reader { bytes: &out, offset: 0 }.varint(), Ok(value));
+        }
+        // 第 10 个字节超过最高位
+        let overflow = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert_eq!(Reader { bytes: &overflow, offset: 0 }.varint(), Err(CompressedProofError::VarintOverflow { offset: 0 }));
+    }
+}
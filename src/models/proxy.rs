@@ -1,11 +1,17 @@
 use std::collections::HashMap;
-use primitive_types::{H160, H256, U256};
+use alloy_primitives::{B256, U256};
+use serde::{Deserialize, Serialize};
+use super::events::{Event, EventLog, ProxyEvent, DEFAULT_EVENT_RETENTION};
+use super::segment_vc::{HistoryMode, MerkleProof, SegmentVC};
+use super::snapshot::{SnapshotError, StateSnapshot};
+use super::{keccak256, EthAddress};
+use crate::{eth_address_to_B256, BoxError};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProxyState {
     pub staked: U256,
     pub block_height: u64,
-    pub shutdown_hash: H256,
+    pub shutdown_hash: B256,
     pub transfer_block: u64,
     pub is_active: bool,
     pub tags: u64,
@@ -15,9 +21,9 @@ pub struct ProxyState {
 impl Default for ProxyState {
     fn default() -> Self {
         ProxyState {
-            staked: U256::zero(),
+            staked: U256::ZERO,
             block_height: 0,
-            shutdown_hash: H256::zero(),
+            shutdown_hash: B256::ZERO,
             transfer_block: 0,
             is_active: false,
             tags: 0,
@@ -26,27 +32,383 @@ impl Default for ProxyState {
     }
 }
 
-#[derive(Default)]
+impl ProxyState {
+    /// 状态承诺的叶子编码（encodePacked，共 109 字节）：
+    /// proxy(20) ‖ staked(32) ‖ block_height(8) ‖ shutdown_hash(32) ‖ transfer_block(8) ‖ flags(1) ‖ tags(8)
+    ///
+    /// flags 的 bit0 为 is_active，bit1 为 is_slashed。证明不携带 key，所以代理地址编码进叶子
+    pub fn packed(&self, proxy: &EthAddress) -> Vec<u8> {
+        let mut packed = Vec::with_capacity(109);
+        packed.extend_from_slice(proxy);
+        packed.extend_from_slice(&self.staked.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.block_height.to_be_bytes());
+        packed.extend_from_slice(self.shutdown_hash.as_slice());
+        packed.extend_from_slice(&self.transfer_block.to_be_bytes());
+        packed.push(self.flags());
+        packed.extend_from_slice(&self.tags.to_be_bytes());
+        packed
+    }
+
+    pub fn flags(&self) -> u8 {
+        (self.is_active as u8) | ((self.is_slashed as u8) << 1)
+    }
+
+    /// SegmentVC 中存储的值：keccak256(packed)
+    pub fn leaf_hash(&self, proxy: &EthAddress) -> B256 {
+        B256::from(keccak256(&self.packed(proxy)))
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct ProxyManager {
-    proxy_states: HashMap<H160, ProxyState>,
+    proxy_states: HashMap<EthAddress, ProxyState>,
+    events: EventLog<ProxyEvent>, // 修改事件，供主机端索引
+}
+
+// 快照中的一条代理状态
+#[derive(Serialize, Deserialize)]
+struct ProxyStateEntry {
+    #[serde(with = "crate::serde_hex")]
+    proxy: EthAddress,
+    state: ProxyState,
+}
+
+// 快照中的状态；旧快照只有按地址排序的代理状态列表
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ProxyManagerState {
+    Current { proxies: Vec<ProxyStateEntry>, last_event_seq: u64 },
+    Legacy(Vec<ProxyStateEntry>),
 }
 
 impl ProxyManager {
+    pub const SNAPSHOT_KIND: &'static str = "proxy_manager";
+
     pub fn new() -> Self {
         Self {
             proxy_states: HashMap::new(),
+            events: EventLog::default(),
         }
     }
 
-    pub fn update_state(&mut self, proxy: H160, state: ProxyState) {
+    /// 最多保留的事件数量，默认 DEFAULT_EVENT_RETENTION
+    pub fn with_event_retention(mut self, retention: usize) -> Self {
+        self.events.set_retention(retention);
+        self
+    }
+
+    /// 写入代理状态，记录一个 ProxyEvent，事件类型由新旧状态决定
+    pub fn update_state(&mut self, proxy: EthAddress, state: ProxyState) {
+        let event = match self.proxy_states.get(&proxy) {
+            None => ProxyEvent::Registered { proxy, staked: state.staked },
+            Some(old) if !old.is_slashed && state.is_slashed => ProxyEvent::Slashed { proxy },
+            Some(old) if old.shutdown_hash != state.shutdown_hash => {
+                ProxyEvent::Shutdown { proxy, shutdown_hash: state.shutdown_hash }
+            }
+            Some(old) if old.staked != state.staked => {
+                ProxyEvent::Staked { proxy, old_staked: old.staked, new_staked: state.staked }
+            }
+            Some(_) => ProxyEvent::Updated { proxy },
+        };
         self.proxy_states.insert(proxy, state);
+        self.events.push(event);
+    }
+
+    /// 取出所有保留的事件
+    pub fn drain_events(&mut self) -> Vec<Event<ProxyEvent>> {
+        self.events.drain()
+    }
+
+    /// 序号大于 seq 的保留事件
+    pub fn events_since(&self, seq: u64) -> &[Event<ProxyEvent>] {
+        self.events.since(seq)
     }
 
-    pub fn get_state(&self, proxy: &H160) -> Option<&ProxyState> {
+    /// 最近一个事件的序号，保存在快照中
+    pub fn last_event_seq(&self) -> u64 {
+        self.events.last_seq()
+    }
+
+    pub fn get_state(&self, proxy: &EthAddress) -> Option<&ProxyState> {
         self.proxy_states.get(proxy)
     }
 
-    pub fn get_all_states(&self) -> &HashMap<H160, ProxyState> {
+    pub fn get_all_states(&self) -> &HashMap<EthAddress, ProxyState> {
         &self.proxy_states
     }
-}
\ No newline at end of file
+
+    /// 代理注册表的状态根，用于链上锚定
+    ///
+    /// 按代理地址升序插入 SegmentVC，key 为左补零的地址，value 为 ProxyState::leaf_hash；没有代理时为零
+    pub fn state_root(&self) -> Result<B256, BoxError> {
+        match self.build_state_vc()? {
+            Some(vc) => vc.get_root_hash(),
+            None => Ok(B256::ZERO),
+        }
+    }
+
+    /// 某个代理的状态包含在 state_root 中的证明
+    pub fn state_proof(&self, proxy: &EthAddress) -> Result<MerkleProof, BoxError> {
+        let state = self
+            .proxy_states
+            .get(proxy)
+            .ok_or_else(|| format!("Proxy {:?} not registered", proxy))?;
+        let vc = self.build_state_vc()?.ok_or("Proxy registry is empty")?;
+
+        let mut proof = vc.generate_proof(eth_address_to_B256(proxy))?;
+        proof.value_proof.value = state.leaf_hash(proxy);
+        Ok(proof)
+    }
+
+    fn build_state_vc(&self) -> Result<Option<SegmentVC>, BoxError> {
+        if self.proxy_states.is_empty() {
+            return Ok(None);
+        }
+        let mut entries: Vec<(B256, B256)> = self
+            .proxy_states
+            .iter()
+            .map(|(proxy, state)| (eth_address_to_B256(proxy), state.leaf_hash(proxy)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut vc = SegmentVC::builder().hash_only().with_history_mode(HistoryMode::Disabled).build();
+        vc.insert_batch(entries)?;
+        Ok(Some(vc))
+    }
+
+    /// 保存为带版本的快照，按代理地址排序，相同状态得到相同字节；包含最后的事件序号，不包含事件本身
+    pub fn save_to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut entries: Vec<ProxyStateEntry> = self
+            .proxy_states
+            .iter()
+            .map(|(proxy, state)| ProxyStateEntry {
+                proxy: *proxy,
+                state: state.clone(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.proxy.cmp(&b.proxy));
+        let state = ProxyManagerState::Current { proxies: entries, last_event_seq: self.events.last_seq() };
+        StateSnapshot::new(Self::SNAPSHOT_KIND, state).to_bytes()
+    }
+
+    /// 从快照恢复，拒绝未知版本和重复的代理；旧快照的事件序号从 0 开始
+    pub fn load_from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let (entries, last_event_seq) = match StateSnapshot::from_bytes(Self::SNAPSHOT_KIND, bytes)? {
+            ProxyManagerState::Current { proxies, last_event_seq } => (proxies, last_event_seq),
+            ProxyManagerState::Legacy(proxies) => (proxies, 0),
+        };
+        let mut manager = Self::new();
+        manager.events = EventLog::resume(last_event_seq, DEFAULT_EVENT_RETENTION);
+        for entry in entries {
+            if manager.proxy_states.insert(entry.proxy, entry.state).is_some() {
+                return Err(SnapshotError::Inconsistent(format!(
+                    "Duplicate proxy {:?}",
+                    entry.proxy
+                )));
+            }
+        }
+        Ok(manager)
+    }
+}
+
+/// 验证代理状态包含在 ProxyManager::state_root 中：重新计算叶子并验证证明
+pub fn verify_proxy_state(
+    root: B256,
+    proxy: &EthAddress,
+    state: &ProxyState,
+    proof: &MerkleProof,
+) -> Result<bool, BoxError> {
+    if proof.root_hash != root || proof.value_proof.value != state.leaf_hash(proxy) {
+        return Ok(false);
+    }
+    proof.verify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_manager(count: u8) -> ProxyManager {
+        let mut manager = ProxyManager::new();
+        for i in 1..=count {
+            manager.update_state(
+                [i; 20],
+                ProxyState {
+                    staked: U256::from(i as u64 * 1000),
+                    block_height: i as u64,
+                    shutdown_hash: B256::repeat_byte(i),
+                    transfer_block: 10,
+                    is_active: i != 2,
+                    tags: 7,
+                    is_slashed: i == 3,
+                },
+            );
+        }
+        manager
+    }
+
+    #[test]
+    fn test_packed_layout() {
+        let state = ProxyState {
+            staked: U256::from(0x0102u64),
+            block_height: 3,
+            shutdown_hash: B256::repeat_byte(0xAA),
+            transfer_block: 4,
+            is_active: true,
+            tags: 5,
+            is_slashed: true,
+        };
+        let packed = state.packed(&[0x11u8; 20]);
+        assert_eq!(packed.len(), 109);
+        assert_eq!(&packed[..20], &[0x11u8; 20]);
+        assert_eq!(&packed[50..52], &[0x01, 0x02]);
+        assert_eq!(&packed[52..60], &3u64.to_be_bytes());
+        assert_eq!(&packed[60..92], &[0xAAu8; 32]);
+        assert_eq!(&packed[92..100], &4u64.to_be_bytes());
+        assert_eq!(packed[100], 0b11);
+        assert_eq!(&packed[101..], &5u64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_state_root_and_proofs() -> Result<(), BoxError> {
+        assert_eq!(ProxyManager::new().state_root()?, B256::ZERO);
+        assert!(ProxyManager::new().state_proof(&[1u8; 20]).is_err());
+
+        for count in [1u8, 3, 17, 40] {
+            let manager = sample_manager(count);
+            let root = manager.state_root()?;
+            for (proxy, state) in manager.get_all_states() {
+                let proof = manager.state_proof(proxy)?;
+                assert!(verify_proxy_state(root, proxy, state, &proof)?);
+
+                // 证明不能用于其他代理或被修改的状态
+                let mut forged = state.clone();
+                forged.tags += 1;
+                assert!(!verify_proxy_state(root, proxy, &forged, &proof)?);
+                assert!(!verify_proxy_state(root, &[0xEEu8; 20], state, &proof)?);
+                assert!(!verify_proxy_state(B256::repeat_byte(9), proxy, state, &proof)?);
+            }
+            assert!(manager.state_proof(&[0xEEu8; 20]).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_root_covers_every_field() -> Result<(), BoxError> {
+        let manager = sample_manager(5);
+        let root = manager.state_root()?;
+        // 与插入顺序无关
+        let mut reversed = ProxyManager::new();
+        let mut states: Vec<_> = manager.get_all_states().iter().collect();
+        states.sort_by(|a, b| b.0.cmp(a.0));
+        for (proxy, state) in states {
+            reversed.update_state(*proxy, state.clone());
+        }
+        assert_eq!(reversed.state_root()?, root);
+
+        let mutations: Vec<fn(&mut ProxyState)> = vec![
+            |s: &mut ProxyState| s.staked += U256::from(1),
+            |s: &mut ProxyState| s.block_height += 1,
+            |s: &mut ProxyState| s.shutdown_hash = B256::repeat_byte(0xFF),
+            |s: &mut ProxyState| s.transfer_block += 1,
+            |s: &mut ProxyState| s.is_active = !s.is_active,
+            |s: &mut ProxyState| s.tags ^= 1,
+            |s: &mut ProxyState| s.is_slashed = !s.is_slashed,
+        ];
+        for proxy in manager.get_all_states().keys() {
+            for mutate in &mutations {
+                let mut changed = sample_manager(5);
+                let mut state = changed.get_state(proxy).unwrap().clone();
+                mutate(&mut state);
+                changed.update_state(*proxy, state);
+                assert_ne!(changed.state_root()?, root);
+            }
+        }
+
+        // 把状态挪到另一个地址也会改变根
+        let mut moved = sample_manager(5);
+        let state = moved.proxy_states.remove(&[5u8; 20]).unwrap();
+        moved.update_state([6u8; 20], state);
+        assert_ne!(moved.state_root()?, root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() -> Result<(), SnapshotError> {
+        let manager = sample_manager(3);
+        let bytes = manager.save_to_bytes()?;
+        let restored = ProxyManager::load_from_bytes(&bytes)?;
+        assert_eq!(restored.get_all_states(), manager.get_all_states());
+        assert_eq!(restored.save_to_bytes()?, bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_log() -> Result<(), SnapshotError> {
+        let proxy = [1u8; 20];
+        let mut manager = ProxyManager::new();
+        let mut state = ProxyState { staked: U256::from(100), is_active: true, ..ProxyState::default() };
+        manager.update_state(proxy, state.clone());
+        state.staked = U256::from(150);
+        manager.update_state(proxy, state.clone());
+        state.tags = 1;
+        manager.update_state(proxy, state.clone());
+        // 同时罚没和减少质押时记录为 Slashed
+        state.is_slashed = true;
+        state.staked = U256::from(50);
+        manager.update_state(proxy, state.clone());
+        state.shutdown_hash = B256::repeat_byte(9);
+        state.is_active = false;
+        manager.update_state(proxy, state.clone());
+
+        let drained = manager.drain_events();
+        assert_eq!(drained.iter().map(|event| event.seq).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        let kinds: Vec<ProxyEvent> = drained.into_iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ProxyEvent::Registered { proxy, staked: U256::from(100) },
+                ProxyEvent::Staked { proxy, old_staked: U256::from(100), new_staked: U256::from(150) },
+                ProxyEvent::Updated { proxy },
+                ProxyEvent::Slashed { proxy },
+                ProxyEvent::Shutdown { proxy, shutdown_hash: B256::repeat_byte(9) },
+            ]
+        );
+        assert!(manager.events_since(0).is_empty());
+
+        // 快照保存最后的序号
+        let mut restored = ProxyManager::load_from_bytes(&manager.save_to_bytes()?)?;
+        assert_eq!(restored.last_event_seq(), 5);
+        restored.update_state([2u8; 20], ProxyState::default());
+        assert_eq!(restored.events_since(5).len(), 1);
+
+        let mut bounded = ProxyManager::new().with_event_retention(1);
+        bounded.update_state(proxy, state.clone());
+        bounded.update_state([2u8; 20], state);
+        assert_eq!(bounded.events_since(0).iter().map(|event| event.seq).collect::<Vec<_>>(), vec![2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_rejects_duplicates() {
+        let entry = r#"{"proxy":"0x0101010101010101010101010101010101010101","state":{"staked":"0x1","block_height":0,"shutdown_hash":"0x0000000000000000000000000000000000000000000000000000000000000000","transfer_block":0,"is_active":true,"tags":0,"is_slashed":false}}"#;
+        let blob = format!(r#"{{"version":1,"kind":"proxy_manager","state":[{},{}]}}"#, entry, entry);
+        assert!(matches!(
+            ProxyManager::load_from_bytes(blob.as_bytes()),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+
+        // 带事件序号的格式
+        let blob = format!(
+            r#"{{"version":1,"kind":"proxy_manager","state":{{"proxies":[{},{}],"last_event_seq":3}}}}"#,
+            entry, entry
+        );
+        assert!(matches!(
+            ProxyManager::load_from_bytes(blob.as_bytes()),
+            Err(SnapshotError::Inconsistent(_))
+        ));
+    }
+}
@@ -1,13 +1,33 @@
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
 use alloy_primitives::{B256, U256};
 
-use serde::{Deserialize, Serialize};
-use sha3::{Digest, Keccak256};
-use std::collections::HashMap;
-use std::error::Error as StdError;
-use std::{fmt};
+use core::error::Error as StdError;
+use core::fmt::{self, Write as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use super::tree_hasher::{TreeHashAlgorithm, TreeHasher};
+#[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+#[cfg(feature = "zkvm")]
 use sp1_zkvm::io::{self as spio};
+#[cfg(feature = "std")]
 use super::CircularHashStore;
 use crate::BoxError;
+#[cfg(feature = "zkvm")]
+use super::wire::WireError;
+
+// 开启 trace-hashing 特性时产生 tracing 事件；默认不求值参数，也不依赖 tracing
+macro_rules! trace_hashing {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "trace-hashing")]
+        tracing::$level!($($arg)+);
+        #[cfg(not(feature = "trace-hashing"))]
+        if false {
+            let _ = ::alloc::format!($($arg)+);
+        }
+    };
+}
 
 // 常量定义
 const SEGMENT_SIZE: usize = 16; // 每段16个元素
@@ -41,6 +61,11 @@ pub enum Error {
     IndexOutOfBounds,
     InvalidProof,
     HashStoreError(String),
+    NotRetained,
+    EmptyTree,
+    TreeNotFinalized,
+    StaleRoot,
+    ZeroValue,
 }
 
 impl fmt::Display for Error {
@@ -51,110 +76,314 @@ impl fmt::Display for Error {
             Error::IndexOutOfBounds => write!(f, "Index out of bounds"),
             Error::InvalidProof => write!(f, "Invalid proof"),
             Error::HashStoreError(msg) => write!(f, "Hash store error: {}", msg),
+            Error::NotRetained => write!(f, "Value not retained in hash-only mode"),
+            Error::EmptyTree => write!(f, "Tree is empty"),
+            Error::TreeNotFinalized => write!(f, "Tree is in building mode, call finish_building first"),
+            Error::StaleRoot => write!(f, "Root is stale after changes in building mode, call finish_building first"),
+            Error::ZeroValue => write!(f, "Zero value rejected in strict mode"),
         }
     }
 }
 
 impl StdError for Error {}
-#[derive(Debug, Clone,Serialize,Deserialize)]
+
+// 证明类型的 serde 表示：人类可读的格式（JSON）使用 json 模块中固定的结构，
+// 与 TypeScript 验证器共用；bincode 等格式使用 remote = "Self" 生成的原有派生表示
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct ValueProof {
     pub value: B256,      // 原始值
     pub chunk_hash: B256, // 对应的chunk hash
 }
 
-#[derive(Debug, Clone,Serialize,Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct SegmentProof {
     pub chunk_index: usize,  // chunk在segment内的索引
     pub siblings: Vec<B256>, // 同segment内的其他chunk hashes
 }
 
-#[derive(Debug, Clone,Serialize,Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct LevelProof {
     pub level: usize,        // 当前层级
     pub node_index: usize,   // 节点在当前层的索引
     pub siblings: Vec<B256>, // 同组内的其他节点hashes
 }
 
-#[derive(Debug, Clone,Serialize,Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct MerkleProof {
     pub value_proof: ValueProof,       // 值到chunk hash的证明
     pub segment_proof: SegmentProof,   // chunk在segment内的证明
     pub level_proofs: Vec<LevelProof>, // 从Level 0到root的路径证明
     pub root_hash: B256,               // 最终的root hash
+    #[serde(default)]
+    pub hasher: TreeHashAlgorithm,     // 生成证明的树哈希算法，仅供参考，验证时由验证方指定；旧数据没有该字段时为 Keccak
+    #[serde(default)]
+    pub padded: bool,                  // 每组按 NODE_WIDTH 个位置哈希（见 SegmentVC::with_padded），仅供参考，验证时由验证方指定；旧数据为 false
 }
-impl MerkleProof {
-    pub fn read_from_stdin() -> Self {
-        // 1. 读取 ValueProof
-        let value_proof = ValueProof {
-            value: spio::read(),
-            chunk_hash: spio::read(),
-        };
 
-        // 2. 读取 SegmentProof
-        let chunk_index = spio::read::<u32>() as usize;
-        let siblings_len = spio::read::<u32>() as usize;
-        let mut segment_siblings = Vec::with_capacity(siblings_len);
-        for _ in 0..siblings_len {
-            segment_siblings.push(spio::read::<B256>());
+// 人类可读时转换为 json 模块的结构，否则调用 remote 派生的原有实现
+macro_rules! proof_serde {
+    ($($ty:ident),+) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    json::$ty::try_from(self).map_err(serde::ser::Error::custom)?.serialize(serializer)
+                } else {
+                    $ty::serialize(self, serializer)
+                }
+            }
         }
-        let segment_proof = SegmentProof {
-            chunk_index,
-            siblings: segment_siblings,
-        };
 
-        // 3. 读取 LevelProofs
-        let level_proofs_len = spio::read::<u32>() as usize;
-        let mut level_proofs = Vec::with_capacity(level_proofs_len);
-        
-        for _ in 0..level_proofs_len {
-            let level = spio::read::<u32>() as usize;
-            let node_index = spio::read::<u32>() as usize;
-            let level_siblings_len = spio::read::<u32>() as usize;
-            
-            let mut level_siblings = Vec::with_capacity(level_siblings_len);
-            for _ in 0..level_siblings_len {
-                level_siblings.push(spio::read::<B256>());
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    json::$ty::deserialize(deserializer).map(Into::into)
+                } else {
+                    $ty::deserialize(deserializer)
+                }
             }
+        }
+    )+};
+}
 
-            level_proofs.push(LevelProof {
-                level,
-                node_index,
-                siblings: level_siblings,
-            });
+proof_serde!(ValueProof, SegmentProof, LevelProof, MerkleProof);
+
+/// 证明的 JSON 结构，TypeScript 验证器按此解析，修改时需要同步更新 tests/fixtures/merkle_proof.json
+///
+/// - 哈希为 "0x" 加 64 位十六进制的字符串，长度不对时拒绝
+/// - chunk_index、level、node_index 为 u32，不随平台的 usize 宽度变化
+/// - 字段名与 Rust 结构相同，不允许未知字段；hasher 为 "Keccak" 或 "Sha256"，缺省时为 Keccak
+/// - padded 只在为 true 时输出，缺省时为 false，旧证明的 JSON 不变
+mod json {
+    use super::TreeHashAlgorithm;
+    use alloc::{format, string::String, vec::Vec};
+    use alloy_primitives::B256;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub(super) struct Hash(#[serde(with = "crate::serde_hex")] [u8; 32]);
+
+    impl From<&B256> for Hash {
+        fn from(hash: &B256) -> Self {
+            Hash(hash.0)
         }
+    }
 
-        // 4. 读取根哈希
-        let root_hash = spio::read::<B256>();
+    impl From<Hash> for B256 {
+        fn from(hash: Hash) -> Self {
+            B256::from(hash.0)
+        }
+    }
 
-        Self {
-            value_proof,
-            segment_proof,
-            level_proofs,
-            root_hash,
+    fn hashes(hashes: &[B256]) -> Vec<Hash> {
+        hashes.iter().map(Hash::from).collect()
+    }
+
+    fn index(field: &str, value: usize) -> Result<u32, String> {
+        u32::try_from(value).map_err(|_| format!("{} {} does not fit in u32", field, value))
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct ValueProof {
+        value: Hash,
+        chunk_hash: Hash,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct SegmentProof {
+        chunk_index: u32,
+        siblings: Vec<Hash>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct LevelProof {
+        level: u32,
+        node_index: u32,
+        siblings: Vec<Hash>,
+    }
+
+    // 嵌套的证明通过外层类型的 Serialize 再次进入本模块
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub(super) struct MerkleProof {
+        value_proof: super::ValueProof,
+        segment_proof: super::SegmentProof,
+        level_proofs: Vec<super::LevelProof>,
+        root_hash: Hash,
+        #[serde(default)]
+        hasher: TreeHashAlgorithm,
+        #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+        padded: bool,
+    }
+
+    impl TryFrom<&super::ValueProof> for ValueProof {
+        type Error = String;
+
+        fn try_from(proof: &super::ValueProof) -> Result<Self, String> {
+            Ok(Self { value: Hash::from(&proof.value), chunk_hash: Hash::from(&proof.chunk_hash) })
+        }
+    }
+
+    impl From<ValueProof> for super::ValueProof {
+        fn from(proof: ValueProof) -> Self {
+            Self { value: proof.value.into(), chunk_hash: proof.chunk_hash.into() }
+        }
+    }
+
+    impl TryFrom<&super::SegmentProof> for SegmentProof {
+        type Error = String;
+
+        fn try_from(proof: &super::SegmentProof) -> Result<Self, String> {
+            Ok(Self {
+                chunk_index: index("chunk_index", proof.chunk_index)?,
+                siblings: hashes(&proof.siblings),
+            })
+        }
+    }
+
+    impl From<SegmentProof> for super::SegmentProof {
+        fn from(proof: SegmentProof) -> Self {
+            Self {
+                chunk_index: proof.chunk_index as usize,
+                siblings: proof.siblings.into_iter().map(Into::into).collect(),
+            }
         }
     }
+
+    impl TryFrom<&super::LevelProof> for LevelProof {
+        type Error = String;
+
+        fn try_from(proof: &super::LevelProof) -> Result<Self, String> {
+            Ok(Self {
+                level: index("level", proof.level)?,
+                node_index: index("node_index", proof.node_index)?,
+                siblings: hashes(&proof.siblings),
+            })
+        }
+    }
+
+    impl From<LevelProof> for super::LevelProof {
+        fn from(proof: LevelProof) -> Self {
+            Self {
+                level: proof.level as usize,
+                node_index: proof.node_index as usize,
+                siblings: proof.siblings.into_iter().map(Into::into).collect(),
+            }
+        }
+    }
+
+    impl TryFrom<&super::MerkleProof> for MerkleProof {
+        type Error = String;
+
+        fn try_from(proof: &super::MerkleProof) -> Result<Self, String> {
+            Ok(Self {
+                value_proof: proof.value_proof.clone(),
+                segment_proof: proof.segment_proof.clone(),
+                level_proofs: proof.level_proofs.clone(),
+                root_hash: Hash::from(&proof.root_hash),
+                hasher: proof.hasher,
+                padded: proof.padded,
+            })
+        }
+    }
+
+    impl From<MerkleProof> for super::MerkleProof {
+        fn from(proof: MerkleProof) -> Self {
+            Self {
+                value_proof: proof.value_proof,
+                segment_proof: proof.segment_proof,
+                level_proofs: proof.level_proofs,
+                root_hash: proof.root_hash.into(),
+                hasher: proof.hasher,
+                padded: proof.padded,
+            }
+        }
+    }
+}
+/// 单层（段内或上层组内）兄弟节点数量上限
+pub const MAX_PROOF_SIBLINGS_PER_LEVEL: usize = NODE_WIDTH - 1;
+/// level_proofs 数量上限
+pub const MAX_PROOF_LEVELS: usize = TREE_DEPTH;
+/// 整个证明的兄弟节点总数上限：段内一层加上所有上层
+pub const MAX_PROOF_TOTAL_SIBLINGS: usize = (MAX_PROOF_LEVELS + 1) * MAX_PROOF_SIBLINGS_PER_LEVEL;
+
+/// 证明超出大小上限，读取时在分配内存之前返回
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofTooLarge {
+    pub field: &'static str,
+    pub len: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for ProofTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Proof too large: {} is {}, max {}", self.field, self.len, self.max)
+    }
 }
 
+impl StdError for ProofTooLarge {}
 
+pub(crate) fn check_proof_len(field: &'static str, len: usize, max: usize) -> Result<(), ProofTooLarge> {
+    if len > max {
+        return Err(ProofTooLarge { field, len, max });
+    }
+    Ok(())
+}
+
+impl MerkleProof {
+    /// 读取 host 用 to_stdin_bytes 写入的一帧，布局见 wire 模块；布局中没有 hasher 和 padded，
+    /// guest 按自己的配置用 verify_with 验证
+    #[cfg(feature = "zkvm")]
+    pub fn read_from_stdin() -> Result<Self, WireError> {
+        Self::from_stdin_bytes(&spio::read_vec())
+    }
 
+    /// 检查大小上限和索引范围，不合法的证明在哈希之前被拒绝
+    pub fn check_structure(&self) -> Result<(), BoxError> {
+        let segment_siblings = self.segment_proof.siblings.len();
+        check_proof_len("segment siblings", segment_siblings, MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+        check_proof_len("level proofs", self.level_proofs.len(), MAX_PROOF_LEVELS)?;
+        if self.segment_proof.chunk_index > segment_siblings {
+            return Err(Box::new(Error::InvalidProof));
+        }
 
+        let mut total_siblings = segment_siblings;
+        for proof in &self.level_proofs {
+            check_proof_len("level siblings", proof.siblings.len(), MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+            total_siblings += proof.siblings.len();
+            if proof.node_index > proof.siblings.len() {
+                return Err(Box::new(Error::InvalidProof));
+            }
+        }
+        check_proof_len("total siblings", total_siblings, MAX_PROOF_TOTAL_SIBLINGS)?;
+        Ok(())
+    }
+}
 impl MerkleProof {
+    /// 按默认配置（Keccak、不填充）验证，忽略证明中记录的 hasher 和 padded：这两个字段由证明方提供，
+    /// 验证方不能据此选择验证方式；树使用其他配置时用 verify_with
     pub fn verify(&self) -> Result<bool, BoxError> {
-        print_proof(&self, "---------------------- in ---------------");
-        println!("\n=== Starting Verification Process ===");
+        self.verify_with(TreeHashAlgorithm::Keccak, false)
+    }
+
+    /// 按验证方配置的树哈希算法和填充方式验证，忽略证明中记录的值，配置不同的证明验证失败
+    pub fn verify_with(&self, hasher: TreeHashAlgorithm, padded: bool) -> Result<bool, BoxError> {
+        self.check_structure()?;
 
         // 1. 验证value到chunk hash
-        let mut hasher = Keccak256::new();
-        hasher.update(self.value_proof.value.as_slice());
-        let calculated_chunk = B256::from_slice(&hasher.finalize());
-        println!("Value -> Chunk Hash:");
-        println!(
-            "  Value:           {}",
-            format_hash(&self.value_proof.value)
-        );
-        println!("  Calculated:      {}", format_hash(&calculated_chunk));
-        println!(
-            "  Expected Chunk:  {}",
+        let calculated_chunk = hash_value(hasher, &self.value_proof.value);
+        trace_hashing!(
+            trace,
+            "verify value {} -> chunk {}, expected {}",
+            format_hash(&self.value_proof.value),
+            format_hash(&calculated_chunk),
             format_hash(&self.value_proof.chunk_hash)
         );
 
@@ -162,7 +391,8 @@ impl MerkleProof {
             return Ok(false);
         }
 
-        if self.segment_proof.siblings.len() == 0 {
+        // 旧布局中只有一个元素的树根等于 chunk hash；填充模式下段根总是哈希 NODE_WIDTH 个位置，没有这种情况
+        if self.segment_proof.siblings.len() == 0 && !padded {
             if (self.root_hash == calculated_chunk &&  calculated_chunk == self.value_proof.chunk_hash) {
                 return Ok(true);
             }
@@ -183,21 +413,18 @@ impl MerkleProof {
                 sibling_idx += 1;
             }
         }
-        hasher = Keccak256::new();
         // 计算segment root
-        println!("\n chunks: {}, index:{}, siblings:{:?}",len,self.segment_proof.chunk_index,self.segment_proof.siblings);
-        for chunk in all_chunks {
-             println!("[{}],",format_hash(&chunk));
-            hasher.update(chunk.as_slice());
-        }
-        let mut current_hash = B256::from_slice(&hasher.finalize());
-        println!("\nChunk Hash -> Segment Root:");
-        println!("  Segment Root: {}", format_hash(&current_hash));
+        let mut current_hash = hash_chunks(hasher, &all_chunks, padded);
+        trace_hashing!(
+            trace,
+            "verify {} chunks (index {}) -> segment root {}",
+            len,
+            self.segment_proof.chunk_index,
+            format_hash(&current_hash)
+        );
 
         // 3. 验证从Level 0到root的路径
         for proof in &self.level_proofs {
-            println!("\nLevel {} (index {}):", proof.level, proof.node_index);
-            hasher = Keccak256::new();
             let len = proof.siblings.len() + 1;
             // 构建当前层的所有节点
             let mut level_nodes = vec![B256::default(); len];
@@ -215,119 +442,477 @@ impl MerkleProof {
             }
 
             // 计算父节点
-            for (i, node) in level_nodes.iter().enumerate() {
-                println!("  Node[{}]: {}", i, format_hash(node));
-                hasher.update(node.as_slice());
-            }
-
-            current_hash = B256::from_slice(&hasher.finalize());
-            println!("  Result: {}", format_hash(&current_hash));
+            current_hash = hash_chunks(hasher, &level_nodes, padded);
+            trace_hashing!(
+                trace,
+                "verify level {} (index {} of {}) -> {}",
+                proof.level,
+                proof.node_index,
+                len,
+                format_hash(&current_hash)
+            );
         }
 
-        println!("\nFinal Verification:");
-        println!("Calculated Root: {}", format_hash(&current_hash));
-        println!("Expected Root:   {}", format_hash(&self.root_hash));
+        trace_hashing!(
+            debug,
+            "verify root {}, expected {}",
+            format_hash(&current_hash),
+            format_hash(&self.root_hash)
+        );
 
         Ok(current_hash == self.root_hash)
     }
 }
+/// 借用 SegmentVC 内部数据的证明
+///
+/// chunks 和 nodes 是树中整组的切片（包含自身），兄弟节点只在 to_owned 时复制
+#[derive(Debug, Clone)]
+pub struct MerkleProofRef<'a> {
+    pub value: B256,
+    pub chunk_index: usize,
+    pub chunks: &'a [B256],
+    pub levels: Vec<LevelProofRef<'a>>,
+    pub root_hash: B256,
+    pub hasher: TreeHashAlgorithm,
+    pub padded: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LevelProofRef<'a> {
+    pub level: usize,
+    pub node_index: usize,
+    pub nodes: &'a [B256],
+}
+
+impl<'a> MerkleProofRef<'a> {
+    /// 转换为可序列化的 MerkleProof；字段是公开的，chunk_index 越界时 chunk_hash 为零，得到的证明验证失败
+    pub fn to_owned(&self) -> MerkleProof {
+        let value_proof = ValueProof {
+            value: self.value,
+            chunk_hash: self.chunks.get(self.chunk_index).copied().unwrap_or_default(),
+        };
+        let segment_proof = SegmentProof {
+            chunk_index: self.chunk_index,
+            siblings: siblings_of(self.chunks, self.chunk_index),
+        };
+        let level_proofs = self
+            .levels
+            .iter()
+            .map(|level| LevelProof {
+                level: level.level,
+                node_index: level.node_index,
+                siblings: siblings_of(level.nodes, level.node_index),
+            })
+            .collect();
+
+        MerkleProof {
+            value_proof,
+            segment_proof,
+            level_proofs,
+            root_hash: self.root_hash,
+            hasher: self.hasher,
+            padded: self.padded,
+        }
+    }
+
+    /// 与 MerkleProof::verify 结果相同（Keccak、不填充，忽略 hasher 和 padded 字段），
+    /// 但直接在切片上计算，不分配也不打印
+    pub fn verify(&self) -> bool {
+        self.verify_with(TreeHashAlgorithm::Keccak, false)
+    }
+
+    /// 与 MerkleProof::verify_with 结果相同；索引越界或组超过 NODE_WIDTH 的证明返回 false
+    pub fn verify_with(&self, hasher: TreeHashAlgorithm, padded: bool) -> bool {
+        if !self.is_well_formed() {
+            return false;
+        }
+
+        // 1. value 到 chunk hash
+        let chunk_hash = hash_value(hasher, &self.value);
+        if chunk_hash != self.chunks[self.chunk_index] {
+            return false;
+        }
+        if self.chunks.len() == 1 && self.root_hash == chunk_hash && !padded {
+            return true;
+        }
+
+        // 2. chunk hashes 到段根
+        let mut current_hash = hash_chunks(hasher, self.chunks, padded);
+
+        // 3. 逐层到根，自身位置用计算出的哈希代替
+        for level in &self.levels {
+            let mut state = hasher.start();
+            for (i, node) in level.nodes.iter().enumerate() {
+                if i == level.node_index {
+                    state.update(current_hash.as_slice());
+                } else {
+                    state.update(node.as_slice());
+                }
+            }
+            if padded {
+                for _ in level.nodes.len()..NODE_WIDTH {
+                    state.update(B256::ZERO.as_slice());
+                }
+            }
+            current_hash = state.finalize();
+        }
+
+        current_hash == self.root_hash
+    }
+
+    // 与 MerkleProof::check_structure 对应：每组不超过 NODE_WIDTH 个且自身位置在组内
+    fn is_well_formed(&self) -> bool {
+        let in_group = |len: usize, index: usize| index < len && len <= NODE_WIDTH;
+        in_group(self.chunks.len(), self.chunk_index)
+            && self.levels.len() <= MAX_PROOF_LEVELS
+            && self.levels.iter().all(|level| in_group(level.nodes.len(), level.node_index))
+    }
+}
+
+// 除 index 之外的所有元素
+fn siblings_of(nodes: &[B256], index: usize) -> Vec<B256> {
+    let mut siblings = Vec::with_capacity(nodes.len().saturating_sub(1));
+    for (i, node) in nodes.iter().enumerate() {
+        if i != index {
+            siblings.push(*node);
+        }
+    }
+    siblings
+}
+
 #[derive(Debug)]
 pub enum BuilderMode {
     Building,
     Built,
 }
+
+/// 根历史的记录方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryMode {
+    #[default]
+    EveryUpdate,    // 每次更新默克尔树都记录新根
+    CheckpointOnly, // 只在调用 checkpoint() 时记录当前根
+    Disabled,       // 不记录根历史，用于只计算根和证明的一次性树，was_root 总是 false
+}
+
+/// SegmentVC::builder 默认保留的根数量，与树中的条目数量无关
+#[cfg(feature = "std")]
+pub const DEFAULT_HISTORY_CAPACITY: usize = CircularHashStore::STORE_SIZE;
+
+/// SegmentVC 的构造参数，根历史的容量和记录方式与树的大小分开设置
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct SegmentVCBuilder {
+    history_capacity: usize,
+    history_mode: HistoryMode,
+    retain_values: bool,
+    hasher: TreeHashAlgorithm,
+    padded: bool,
+    strict: bool,
+}
+
+#[cfg(feature = "std")]
+impl Default for SegmentVCBuilder {
+    fn default() -> Self {
+        Self {
+            history_capacity: DEFAULT_HISTORY_CAPACITY,
+            history_mode: HistoryMode::EveryUpdate,
+            retain_values: true,
+            hasher: TreeHashAlgorithm::Keccak,
+            padded: false,
+            strict: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl SegmentVCBuilder {
+    /// 根历史最多保留的根数量，更早的根挤出到历史哈希
+    pub fn with_history_capacity(mut self, history_capacity: usize) -> Self {
+        self.history_capacity = history_capacity;
+        self
+    }
+
+    pub fn with_history_mode(mut self, history_mode: HistoryMode) -> Self {
+        self.history_mode = history_mode;
+        self
+    }
+
+    /// 与 SegmentVC::new_hash_only 相同，不保留原始值
+    pub fn hash_only(mut self) -> Self {
+        self.retain_values = false;
+        self
+    }
+
+    pub fn with_hasher(mut self, hasher: TreeHashAlgorithm) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    pub fn with_padded(mut self, padded: bool) -> Self {
+        self.padded = padded;
+        self
+    }
+
+    /// 与 SegmentVC::with_strict 相同
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn build(self) -> SegmentVC {
+        let history_capacity = match self.history_mode {
+            HistoryMode::Disabled => 0,
+            _ => self.history_capacity,
+        };
+        SegmentVC {
+            segments: vec![Segment::empty()],
+            total_size: 0,
+            root_hash: B256::default(),
+            merkle_nodes: HashMap::new(),
+            indices: HashMap::new(),
+            root_history: CircularHashStore::new(history_capacity),
+            building_mode: BuilderMode::Built,
+            dirty: false,
+            retain_values: self.retain_values,
+            history_mode: self.history_mode,
+            hasher: self.hasher,
+            padded: self.padded,
+            strict: self.strict,
+        }
+    }
+}
+#[cfg(feature = "std")]
 #[derive(Debug, Clone)]
 struct Segment {
     values: Vec<B256>,       // 值数组
     chunk_hashes: Vec<B256>, // chunk哈希数组
     root: B256,              // 段根
-    size: usize,             // 当前使用数量
+    size: usize,             // 已占用的位置数，零值也算占用
+}
+
+#[cfg(feature = "std")]
+impl Segment {
+    fn empty() -> Self {
+        Self {
+            values: Vec::new(),
+            chunk_hashes: Vec::new(),
+            root: B256::default(),
+            size: 0,
+        }
+    }
+
+    // 写入 local_index 处的值并重新计算段根
+    fn set(&mut self, local_index: usize, value: B256, retain_values: bool, hasher: TreeHashAlgorithm, padded: bool) {
+        self.size = self.size.max(local_index + 1);
+        if !retain_values {
+            // hash-only 模式：只更新对应位置的 chunk hash
+            while self.chunk_hashes.len() <= local_index {
+                self.chunk_hashes.push(hash_value(hasher, &B256::default()));
+            }
+            self.chunk_hashes[local_index] = hash_value(hasher, &value);
+            self.root = hash_chunks(hasher, &self.chunk_hashes, padded);
+            return;
+        }
+        while self.values.len() <= local_index {
+            self.values.push(B256::default());
+        }
+        self.values[local_index] = value;
+
+        // 零值与其他值一样计为 H(0)，与 hash-only 模式的 chunk hash 相同
+        self.rehash(hasher, padded);
+    }
+
+    // 按已占用的值重新计算所有 chunk hash 和段根
+    fn rehash(&mut self, hasher: TreeHashAlgorithm, padded: bool) {
+        self.chunk_hashes = self.values[..self.size].iter().map(|value| hash_value(hasher, value)).collect();
+        self.root = hash_chunks(hasher, &self.chunk_hashes, padded);
+    }
 }
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
 pub struct SegmentVC {
     segments: Vec<Segment>,                  // 所有段
     total_size: usize,                       // 总元素数量
     root_hash: B256,                         // 根哈希
     merkle_nodes: HashMap<usize, Vec<B256>>, // merkle树节点存储
-    indices: HashMap<B256, usize>,           // 键到索引的映射
+    indices: HashMap<B256, usize>,           // 键到元素索引的映射，从 0 开始（旧版本从 1 开始，见 migrate_one_based_indices）
     root_history: CircularHashStore,         // 根哈希历史
     // 新增构建模式相关字段
     building_mode: BuilderMode,
+    dirty: bool,                             // 构建模式中有未计入根的插入或更新
+    retain_values: bool,                     // 是否保留原始值，hash-only 模式下为 false
+    history_mode: HistoryMode,
+    hasher: TreeHashAlgorithm,               // 树哈希算法，默认 Keccak
+    padded: bool,                            // 段和上层组是否按 NODE_WIDTH 个位置哈希，默认 false
+    strict: bool,                            // 拒绝写入 B256::ZERO，默认 false
 }
 
+#[cfg(feature = "std")]
 impl SegmentVC {
+    /// capacity 为根历史的容量，不是条目数量
     pub fn new(capacity: usize) -> Self {
-        let mut segments = Vec::new();
-        segments.push(Segment {
-            values: Vec::new(),
-            chunk_hashes: Vec::new(),
-            root: B256::default(),
-            size: 0,
-        });
+        Self::builder().with_history_capacity(capacity).build()
+    }
 
-        Self {
-            segments,
-            total_size: 0,
-            root_hash: B256::default(),
-            merkle_nodes: HashMap::new(),
-            indices: HashMap::new(),
-            root_history: CircularHashStore::new(capacity),
-            building_mode: BuilderMode::Built,
+    /// 默认保留 DEFAULT_HISTORY_CAPACITY 个根，每次更新都记录
+    pub fn builder() -> SegmentVCBuilder {
+        SegmentVCBuilder::default()
+    }
+
+    /// hash-only 模式：段内只保存 chunk hash，不保留原始值
+    ///
+    /// 根和证明与普通模式完全相同，但 get_value 返回 Error::NotRetained，
+    /// generate_proof 得到的 value_proof.value 为零，验证前由调用方填入插入时的值
+    pub fn new_hash_only(capacity: usize) -> Self {
+        Self::builder().with_history_capacity(capacity).hash_only().build()
+    }
+
+    pub fn retains_values(&self) -> bool {
+        self.retain_values
+    }
+
+    /// 设置根历史的记录方式，默认每次更新都记录；改为 Disabled 时清空已记录的根
+    pub fn with_history_mode(mut self, history_mode: HistoryMode) -> Self {
+        if history_mode == HistoryMode::Disabled {
+            self.root_history = CircularHashStore::new(0);
         }
+        self.history_mode = history_mode;
+        self
+    }
+
+    pub fn history_mode(&self) -> HistoryMode {
+        self.history_mode
     }
-    // 获取根哈希
-    pub fn get_root_hash(&self) -> B256 {
-        self.root_hash
+
+    /// 设置树哈希算法，必须在插入之前设置；生成的证明记录该算法
+    pub fn with_hasher(mut self, hasher: TreeHashAlgorithm) -> Self {
+        self.hasher = hasher;
+        self
     }
-// 新增：开始构建模式
-pub fn start_building(&mut self) {
-    self.building_mode = BuilderMode::Building;
-}
 
-// 新增：完成构建
-pub fn finish_building(&mut self) -> Result<B256, BoxError> {
-    // 只有在构建模式下才需要重新计算
-    if matches!(self.building_mode, BuilderMode::Building) {
-        // 重新计算所有segment的chunk hashes和roots
-        for segment_index in 0..self.segments.len() {
-            let segment = &self.segments[segment_index];
-            let values = segment.values.clone();
-            for (local_index, value) in values.iter().enumerate() {
-                if *value != B256::default() {
-                    self.update_segment(segment_index, local_index, *value)?;
-                }
-            }
+    pub fn hasher(&self) -> TreeHashAlgorithm {
+        self.hasher
+    }
+
+    /// 填充模式：段根总是哈希 NODE_WIDTH 个 chunk hash，上层每组总是哈希 NODE_WIDTH 个子节点，空位为 B256::ZERO
+    ///
+    /// 原像长度固定，Solidity 验证器可以用定长循环重算；根与默认模式不同，必须在插入之前设置。
+    /// 生成的证明记录 padded；验证方用 verify_with(hasher, true) 或 verify_inclusion 验证
+    pub fn with_padded(mut self, padded: bool) -> Self {
+        self.padded = padded;
+        self
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.padded
+    }
+
+    /// 严格模式：insert 和 update 拒绝 B256::ZERO，返回 Error::ZeroValue
+    ///
+    /// 非严格模式下零值与其他值一样可以插入；严格模式适用于零值只可能来自未初始化数据的调用方
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    fn check_value(&self, value: &B256) -> Result<(), Error> {
+        if self.strict && *value == B256::ZERO {
+            return Err(Error::ZeroValue);
         }
+        Ok(())
+    }
 
-        // 更新整个Merkle树
-        if self.segments.len() > 0 {
-            self.update_merkle_tree(0)?;
+    /// 把当前根记入根历史，CheckpointOnly 模式下只有这样记录的根才能通过 was_root 和 verify_inclusion
+    ///
+    /// 当前根已经是最近一次记录的根时不重复记录，Disabled 模式下不记录；构建模式中或空树返回错误
+    pub fn checkpoint(&mut self) -> Result<B256, BoxError> {
+        self.check_provable()?;
+        if self.history_mode != HistoryMode::Disabled && self.root_history.latest_hash() != Some(self.root_hash) {
+            self.root_history.add_hash(self.root_hash)?;
         }
+        Ok(self.root_hash)
+    }
 
-        self.building_mode = BuilderMode::Built;
+    pub fn root_history(&self) -> &CircularHashStore {
+        &self.root_history
     }
 
-    Ok(self.root_hash)
-}
+    /// (当前保留的根数量, 累计记录的根数量, 是否已有根被挤出到历史哈希)
+    pub fn history_stats(&self) -> (usize, usize, bool) {
+        (
+            self.root_history.current_size(),
+            self.root_history.total_added(),
+            self.root_history.get_store_stats().2,
+        )
+    }
+
+    /// root 是否记录在根历史中；已被挤出的根需要提供 CircularHashStore::check_hash 的历史证明
+    pub fn was_root(&self, root: B256, history_proof: &[B256]) -> bool {
+        self.root_history.check_hash(root, history_proof)
+    }
+    /// 获取根哈希；构建模式中插入或更新后、finish_building 之前返回 Error::StaleRoot
+    pub fn get_root_hash(&self) -> Result<B256, BoxError> {
+        if self.dirty {
+            return Err(Box::new(Error::StaleRoot));
+        }
+        Ok(self.root_hash)
+    }
+    /// 不在构建模式中，默克尔树与插入的值一致，可以生成证明
+    pub fn is_finalized(&self) -> bool {
+        matches!(self.building_mode, BuilderMode::Built)
+    }
+
+    // 新增：开始构建模式
+    pub fn start_building(&mut self) {
+        self.building_mode = BuilderMode::Building;
+    }
+
+    // 新增：完成构建
+    pub fn finish_building(&mut self) -> Result<B256, BoxError> {
+        // 只有在构建模式下才需要重新计算
+        if matches!(self.building_mode, BuilderMode::Building) {
+            // 重新计算所有segment的chunk hashes和roots，按 size 判断占用，零值同样计入
+            // hash-only 模式下插入时已经更新过段，只需重建上层
+            if self.retain_values {
+                let (hasher, padded) = (self.hasher, self.padded);
+                for segment in self.segments.iter_mut().filter(|segment| segment.size > 0) {
+                    segment.rehash(hasher, padded);
+                }
+            }
+
+            // 更新整个Merkle树
+            if self.segments.len() > 0 {
+                self.update_merkle_tree(0)?;
+            }
+
+            self.building_mode = BuilderMode::Built;
+            self.dirty = false;
+        }
+
+        Ok(self.root_hash)
+    }
 
     pub fn insert(&mut self, key: B256, value: B256) -> Result<B256, BoxError> {
         if self.indices.contains_key(&key) {
             return Err(Box::new(Error::KeyExists));
         }
+        self.check_value(&value)?;
 
         let (current_segment, local_index) = self.get_segment_and_index(self.total_size);
 
         // 确保有足够的段
         while self.segments.len() <= current_segment {
-            self.segments.push(Segment {
-                values: Vec::new(),
-                chunk_hashes: Vec::new(),
-                root: B256::default(),
-                size: 0,
-            });
+            self.segments.push(Segment::empty());
         }
 
-        self.total_size += 1;
         self.indices.insert(key, self.total_size);
+        self.total_size += 1;
 
         // // 更新段内容
         // {
@@ -339,7 +924,7 @@ pub fn finish_building(&mut self) -> Result<B256, BoxError> {
         // // 更新merkle树
         // self.update_merkle_tree(current_segment)
           // 更新段内容
-          {
+          if self.retain_values {
             let segment = &mut self.segments[current_segment];
             // segment.size += 1;
             
@@ -348,13 +933,21 @@ pub fn finish_building(&mut self) -> Result<B256, BoxError> {
                 segment.values.push(B256::default());
             }
             segment.values[local_index] = value;
+            segment.size = segment.size.max(local_index + 1);
+        } else {
+            // hash-only 模式下值不保留，只能在插入时计算 chunk hash
+            self.update_segment(current_segment, local_index, value)?;
+        }
 
-            // 只在非构建模式下更新segment和merkle树
-            if matches!(self.building_mode, BuilderMode::Built) {
+        // 只在非构建模式下更新segment和merkle树
+        if matches!(self.building_mode, BuilderMode::Built) {
+            if self.retain_values {
                 self.update_segment(current_segment, local_index, value)?;
-                return self.update_merkle_tree(current_segment);
             }
+            return self.update_merkle_tree(current_segment);
         }
+        // 构建模式中返回的根尚未包含这次插入
+        self.dirty = true;
         Ok(self.root_hash)
     }
    // 新增：批量插入方法
@@ -367,62 +960,100 @@ pub fn finish_building(&mut self) -> Result<B256, BoxError> {
 
     self.finish_building()
 }
+
+    /// 与 insert_batch 相同，开启 profiling 时把哈希的树节点数写入 metrics
+    pub fn insert_batch_with_metrics(
+        &mut self,
+        entries: Vec<(B256, B256)>,
+        metrics: &dyn crate::metrics::Metrics,
+    ) -> Result<B256, BoxError> {
+        use crate::metrics::{measure, Op};
+        measure(metrics, &[Op::TreeNode], || self.insert_batch(entries))
+    }
     pub fn generate_proof(&self, key: B256) -> Result<MerkleProof, BoxError> {
-        let index = self.indices.get(&key).ok_or(Error::KeyNotFound)?;
-        let (segment_index, local_index) = self.get_segment_and_index(index - 1);
+        Ok(self.generate_proof_ref(key)?.to_owned())
+    }
 
-        // 1. 构建value proof
-        let value = self.segments[segment_index].values[local_index];
-        let chunk_hash = self.segments[segment_index].chunk_hashes[local_index];
-        let value_proof = ValueProof { value, chunk_hash };
+    /// 生成借用树内数据的证明，不复制兄弟节点
+    ///
+    /// 构建模式中有未计入根的修改时返回 Error::StaleRoot，没有修改时返回 Error::TreeNotFinalized，
+    /// 没有任何元素时返回 Error::EmptyTree
+    pub fn generate_proof_ref(&self, key: B256) -> Result<MerkleProofRef<'_>, BoxError> {
+        self.check_provable()?;
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        Ok(self.proof_ref_at(index))
+    }
 
-        // 2. 构建segment proof
-        let segment = &self.segments[segment_index];
-        let mut chunk_siblings = Vec::new();
-        for i in 0..segment.chunk_hashes.len() {
-            if i != local_index {
-                chunk_siblings.push(segment.chunk_hashes[i]);
-            }
-        }
-        let segment_proof = SegmentProof {
-            chunk_index: local_index,
-            siblings: chunk_siblings,
-        };
+    /// 按插入顺序依次生成所有 key 的借用证明，错误条件与 generate_proof_ref 相同
+    pub fn generate_proofs_for_all(
+        &self,
+    ) -> Result<impl Iterator<Item = (B256, MerkleProofRef<'_>)> + '_, BoxError> {
+        self.check_provable()?;
+        let mut keys: Vec<(usize, B256)> = self.indices.iter().map(|(key, index)| (*index, *key)).collect();
+        keys.sort_unstable_by_key(|(index, _)| *index);
+        Ok(keys
+            .into_iter()
+            .map(move |(index, key)| (key, self.proof_ref_at(index))))
+    }
 
-        // 3. 构建level proofs
-        let mut level_proofs = Vec::new();
+    // 构建模式中默克尔树尚未更新，空树没有默克尔节点
+    fn check_provable(&self) -> Result<(), BoxError> {
+        if self.dirty {
+            return Err(Box::new(Error::StaleRoot));
+        }
+        if !self.is_finalized() {
+            return Err(Box::new(Error::TreeNotFinalized));
+        }
+        if self.total_size == 0 || self.merkle_nodes.is_empty() {
+            return Err(Box::new(Error::EmptyTree));
+        }
+        Ok(())
+    }
+
+    // global_index 从 0 开始
+    fn proof_ref_at(&self, global_index: usize) -> MerkleProofRef<'_> {
+        let (segment_index, local_index) = self.get_segment_and_index(global_index);
+        let segment = &self.segments[segment_index];
+
+        // 1. value，hash-only 模式下值由调用方在验证前填入
+        let value = if self.retain_values {
+            segment.values[local_index]
+        } else {
+            B256::default()
+        };
+
+        // 2. 每层所在的组
+        let mut levels = Vec::new();
         let mut current_index = segment_index;
 
-        for level in 0..self.merkle_nodes.len() - 1 {
+        // 最上层只有根，不属于路径；没有默克尔节点时路径为空
+        for level in 0..self.merkle_nodes.len().saturating_sub(1) {
             let nodes = &self.merkle_nodes[&level];
             let group_start = (current_index / SEGMENT_SIZE) * SEGMENT_SIZE;
             let group_end = std::cmp::min(group_start + SEGMENT_SIZE, nodes.len());
 
-            let mut siblings = Vec::new();
-            for i in group_start..group_end {
-                if i != current_index {
-                    siblings.push(nodes[i]);
-                }
-            }
-
-            level_proofs.push(LevelProof {
+            levels.push(LevelProofRef {
                 level,
                 node_index: current_index % SEGMENT_SIZE,
-                siblings,
+                nodes: &nodes[group_start..group_end],
             });
 
             current_index /= SEGMENT_SIZE;
         }
 
-        Ok(MerkleProof {
-            value_proof,
-            segment_proof,
-            level_proofs,
+        MerkleProofRef {
+            value,
+            chunk_index: local_index,
+            chunks: &segment.chunk_hashes,
+            levels,
             root_hash: self.root_hash,
-        })
+            hasher: self.hasher,
+            padded: self.padded,
+        }
     }
     // ... 其他辅助方法保持不变
 }
+#[cfg(feature = "std")]
 impl SegmentVC {
     fn update_segment(
         &mut self,
@@ -430,39 +1061,13 @@ impl SegmentVC {
         local_index: usize,
         value: B256,
     ) -> Result<(), BoxError> {
-        {
-            let segment = &mut self.segments[segment_index];
-            while segment.values.len() <= local_index {
-                segment.values.push(B256::default());
-            }
-            segment.values[local_index] = value;
-        }
-        // 2. 计算chunk hash
-        let segment = &mut self.segments[segment_index];
-        segment.chunk_hashes.clear(); // 清除现有的chunk hashes
-
-        // 只为实际存在的值计算chunk hash
-        for i in 0..segment.values.len() {
-            let value = segment.values[i];
-            let mut hasher = Keccak256::new();
-            hasher.update(value.as_slice());
-            let chunk_hash = B256::from_slice(&hasher.finalize());
-            segment.chunk_hashes.push(chunk_hash);
-        }
-        // 3. 计算chunk root
-        let mut hasher = Keccak256::new();
-        for hash in &segment.chunk_hashes {
-            hasher.update(hash.as_slice());
-        }
-        segment.root = B256::from_slice(&hasher.finalize());
-
+        let (retain_values, hasher, padded) = (self.retain_values, self.hasher, self.padded);
+        self.segments[segment_index].set(local_index, value, retain_values, hasher, padded);
         Ok(())
     }
 
     // 更新Merkle树
     fn update_merkle_tree(&mut self, segment_index: usize) -> Result<B256, BoxError> {
-        println!("\n=== Updating Merkle Tree ===");
-
         // 清除旧的merkle nodes数据
         // self.merkle_nodes.clear();
 
@@ -473,10 +1078,7 @@ impl SegmentVC {
             .map(|seg| seg.root)
             .collect::<Vec<B256>>();
 
-        println!("\nLevel 0 (Segment Roots):");
-        for (i, node) in current_level_nodes.iter().enumerate() {
-            println!("Node[{}]: {}", i, format_hash(node));
-        }
+        trace_hashing!(trace, "update merkle tree from {} segment roots", current_level_nodes.len());
         // 存储第0层数据
         self.merkle_nodes.insert(0, current_level_nodes.clone());
 
@@ -486,22 +1088,12 @@ impl SegmentVC {
             level += 1;
             let mut next_level = Vec::new();
 
-            // println!("\nProcessing Level {}:", level);
-
             // 每SEGMENT_SIZE个节点一组
-            for (group_idx, chunk) in current_level_nodes.chunks(SEGMENT_SIZE).enumerate() {
-                // println!("\nProcessing Group {}:", group_idx);
-
-                let mut hasher = Keccak256::new();
-                for (i, node) in chunk.iter().enumerate() {
-                    // println!("  Node[{}]: {}", i, format_hash(node));
-                    hasher.update(node.as_slice());
-                }
-
-                let parent = B256::from_slice(&hasher.finalize());
-                // println!("  Group Hash: {}", format_hash(&parent));
+            for chunk in current_level_nodes.chunks(SEGMENT_SIZE) {
+                let parent = hash_chunks(self.hasher, chunk, self.padded);
                 next_level.push(parent);
             }
+            trace_hashing!(trace, "update merkle tree level {}: {} nodes", level, next_level.len());
 
             // 存储当前层的数据
             self.merkle_nodes.insert(level, next_level.clone());
@@ -510,9 +1102,11 @@ impl SegmentVC {
 
         // 3. 设置最终的root hash
         self.root_hash = current_level_nodes[0];
-        println!("\nFinal root hash: {}", format_hash(&self.root_hash));
+        trace_hashing!(debug, "update merkle tree root {}", format_hash(&self.root_hash));
 
-        self.root_history.add_hash(self.root_hash)?;
+        if self.history_mode == HistoryMode::EveryUpdate {
+            self.root_history.add_hash(self.root_hash)?;
+        }
         Ok(self.root_hash)
     }
 
@@ -552,34 +1146,549 @@ impl SegmentVC {
     }
 
     // 验证特定值
+    #[deprecated(note = "只做本地查表和根比较，不验证默克尔结构；请使用 verify_inclusion 或 lookup_matches")]
     pub fn verify(&self, key: B256, value: B256, history_root: B256) -> Result<bool, BoxError> {
-        let index = self.indices.get(&key).ok_or(Error::KeyNotFound)?;
-        let (segment_index, local_index) = self.get_segment_and_index(index - 1);
+        if !self.lookup_matches(key, value)? {
+            return Ok(false);
+        }
 
-        let segment = &self.segments[segment_index];
-        if value != segment.values[local_index] {
+        Ok(self.is_known_root(history_root))
+    }
+
+    /// 用默克尔证明验证包含关系
+    ///
+    /// 证明必须能重算出自身的 root_hash，且该根是当前根或仍保留在根历史中，
+    /// 因此旧根上生成的证明在被挤出历史之前仍然有效
+    pub fn verify_inclusion(&self, proof: &MerkleProof) -> Result<bool, BoxError> {
+        if !self.is_known_root(proof.root_hash) {
             return Ok(false);
         }
+        proof.verify_with(self.hasher, self.padded)
+    }
+
+    /// 本地查表检查 key 当前的值是否等于 value，不涉及默克尔证明
+    /// hash-only 模式下比较 chunk hash
+    pub fn lookup_matches(&self, key: B256, value: B256) -> Result<bool, BoxError> {
+        if self.retain_values {
+            return Ok(self.get_value(key)? == value);
+        }
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        let (segment_index, local_index) = self.get_segment_and_index(index);
+        Ok(self.segments[segment_index].chunk_hashes[local_index] == hash_value(self.hasher, &value))
+    }
+
+    // 当前根或根历史中的根
+    fn is_known_root(&self, root: B256) -> bool {
+        root == self.root_hash || self.root_history.check_hash(root, &[])
+    }
 
-        Ok(history_root == self.root_hash || self.root_history.check_hash(history_root, &[]))
+    /// key 的元素索引（插入顺序，从 0 开始）
+    pub fn index_of(&self, key: B256) -> Option<usize> {
+        self.indices.get(&key).copied()
     }
 
-    // 获取值
+    // 获取值，hash-only 模式下返回 Error::NotRetained
     pub fn get_value(&self, key: B256) -> Result<B256, BoxError> {
-        let index = self.indices.get(&key).ok_or(Error::KeyNotFound)?;
-        let (segment_index, local_index) = self.get_segment_and_index(index - 1);
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        if !self.retain_values {
+            return Err(Box::new(Error::NotRetained));
+        }
+        let (segment_index, local_index) = self.get_segment_and_index(index);
         Ok(self.segments[segment_index].values[local_index])
     }
 
     // 更新值
     pub fn update(&mut self, key: B256, value: B256) -> Result<B256, BoxError> {
-        let index = self.indices.get(&key).ok_or(Error::KeyNotFound)?;
-        let (segment_index, local_index) = self.get_segment_and_index(index - 1);
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        self.check_value(&value)?;
+        let (segment_index, local_index) = self.get_segment_and_index(index);
 
         self.update_segment(segment_index, local_index, value)?;
-        self.update_merkle_tree(segment_index)
+        let root = self.update_merkle_tree(segment_index)?;
+        // 构建模式中其他段可能还有未计入的插入
+        if !self.is_finalized() {
+            self.dirty = true;
+        }
+        Ok(root)
+    }
+
+    /// 一次更新多个已存在的 key：受影响的段和默克尔树各重新计算一次，根历史只增加一项
+    ///
+    /// 任何 key 不存在时在修改之前返回 Error::KeyNotFound，树保持不变；同一个 key 出现多次时以最后一次为准。
+    /// 空的 updates 不修改树，返回当前根
+    pub fn update_batch(&mut self, updates: &[(B256, B256)]) -> Result<B256, BoxError> {
+        let mut positions = Vec::with_capacity(updates.len());
+        for (key, value) in updates {
+            let index = self.index_of(*key).ok_or(Error::KeyNotFound)?;
+            self.check_value(value)?;
+            positions.push((self.get_segment_and_index(index), *value));
+        }
+        if positions.is_empty() {
+            return Ok(self.root_hash);
+        }
+
+        // 1. 写入新值，hash-only 模式下直接替换 chunk hash
+        let (hasher, padded) = (self.hasher, self.padded);
+        let mut affected = Vec::new();
+        for ((segment_index, local_index), value) in positions {
+            let segment = &mut self.segments[segment_index];
+            if self.retain_values {
+                segment.values[local_index] = value;
+            } else {
+                segment.chunk_hashes[local_index] = hash_value(hasher, &value);
+            }
+            affected.push(segment_index);
+        }
+        affected.sort_unstable();
+        affected.dedup();
+
+        // 2. 每个受影响的段重新计算一次，与 update_segment 相同
+        for segment_index in affected {
+            let segment = &mut self.segments[segment_index];
+            if self.retain_values {
+                segment.rehash(hasher, padded);
+            } else {
+                segment.root = hash_chunks(hasher, &segment.chunk_hashes, padded);
+            }
+        }
+
+        // 3. 默克尔树只重建一次
+        let root = self.update_merkle_tree(0)?;
+        if !self.is_finalized() {
+            self.dirty = true;
+        }
+        Ok(root)
+    }
+
+    /// key 已存在时更新，否则追加
+    pub fn upsert(&mut self, key: B256, value: B256) -> Result<B256, BoxError> {
+        if self.indices.contains_key(&key) {
+            self.update(key, value)
+        } else {
+            self.insert(key, value)
+        }
+    }
+
+    /// 删除 key，之后的元素依次前移，保持相对顺序
+    ///
+    /// 结果与按剩余顺序重新构建的树完全相同；只重新计算被删除元素所在段及之后的段
+    pub fn remove(&mut self, key: B256) -> Result<B256, BoxError> {
+        if !self.is_finalized() {
+            return Err(Box::new(Error::TreeNotFinalized));
+        }
+        let index = self.indices.remove(&key).ok_or(Error::KeyNotFound)?;
+        for other in self.indices.values_mut() {
+            if *other > index {
+                *other -= 1;
+            }
+        }
+
+        // 1. 把受影响的段拉平后删除该元素
+        let (first_segment, local_index) = self.get_segment_and_index(index);
+        let mut values = Vec::new();
+        let mut chunk_hashes = Vec::new();
+        for segment in self.segments.drain(first_segment..) {
+            values.extend(segment.values);
+            chunk_hashes.extend(segment.chunk_hashes);
+        }
+        if self.retain_values {
+            values.remove(local_index);
+        }
+        chunk_hashes.remove(local_index);
+        self.total_size -= 1;
+
+        // 2. 重新分段
+        self.resegment(&values, &chunk_hashes);
+
+        // 3. 层数可能减少，整棵树重建
+        self.merkle_nodes.clear();
+        if self.total_size == 0 {
+            self.root_hash = B256::default();
+            return Ok(self.root_hash);
+        }
+        self.update_merkle_tree(0)
+    }
+
+    /// 在 index 处插入 key，原来 index 及之后的元素依次后移；index 等于 len() 时与 insert 相同
+    ///
+    /// 结果与按插入后的顺序重新构建的树完全相同；与 remove 一样只重新计算 index 所在段及之后的段，
+    /// 上层节点整体重建
+    pub fn insert_at(&mut self, index: usize, key: B256, value: B256) -> Result<B256, BoxError> {
+        if !self.is_finalized() {
+            return Err(Box::new(Error::TreeNotFinalized));
+        }
+        if index > self.total_size {
+            return Err(Box::new(Error::IndexOutOfBounds));
+        }
+        if self.indices.contains_key(&key) {
+            return Err(Box::new(Error::KeyExists));
+        }
+        self.check_value(&value)?;
+        if index == self.total_size {
+            return self.insert(key, value);
+        }
+        for other in self.indices.values_mut() {
+            if *other >= index {
+                *other += 1;
+            }
+        }
+        self.indices.insert(key, index);
+
+        // 1. 把受影响的段拉平后插入该元素
+        let (first_segment, local_index) = self.get_segment_and_index(index);
+        let mut values = Vec::new();
+        let mut chunk_hashes = Vec::new();
+        for segment in self.segments.drain(first_segment..) {
+            values.extend(segment.values);
+            chunk_hashes.extend(segment.chunk_hashes);
+        }
+        if self.retain_values {
+            values.insert(local_index, value);
+        }
+        chunk_hashes.insert(local_index, hash_value(self.hasher, &value));
+        self.total_size += 1;
+
+        // 2. 重新分段，层数可能增加
+        self.resegment(&values, &chunk_hashes);
+        self.merkle_nodes.clear();
+        self.update_merkle_tree(0)
+    }
+
+    // 把拉平的值和 chunk hash 依次追加为新的段，没有元素时保留一个空段
+    fn resegment(&mut self, values: &[B256], chunk_hashes: &[B256]) {
+        for (i, hashes) in chunk_hashes.chunks(SEGMENT_SIZE).enumerate() {
+            let segment_values = if self.retain_values {
+                values[i * SEGMENT_SIZE..i * SEGMENT_SIZE + hashes.len()].to_vec()
+            } else {
+                Vec::new()
+            };
+            self.segments.push(Segment {
+                values: segment_values,
+                chunk_hashes: hashes.to_vec(),
+                root: hash_chunks(self.hasher, hashes, self.padded),
+                size: hashes.len(),
+            });
+        }
+        if self.segments.is_empty() {
+            self.segments.push(Segment::empty());
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl SegmentVC {
+    /// 基于当前树的写时复制分支，用于模拟追加或修改收据后的根
+    ///
+    /// 分支借用父树，只复制被修改的段，计算根时只重新哈希受影响的上层节点；父树保持不变，
+    /// 分支存在期间仍可生成证明。父树处于构建模式或有未计入根的修改时，分支的 root 返回 Error::StaleRoot
+    pub fn fork(&self) -> SegmentVcFork<'_> {
+        SegmentVcFork {
+            parent: self,
+            segments: BTreeMap::new(),
+            segment_count: self.segments.len(),
+            shared_segments: self.segments.len(),
+            total_size: self.total_size,
+            indices: Cow::Borrowed(&self.indices),
+            added: HashMap::new(),
+        }
+    }
+}
+
+/// SegmentVC::fork 创建的分支，insert、update、remove 的语义与 SegmentVC 相同，结果不写回父树
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct SegmentVcFork<'a> {
+    parent: &'a SegmentVC,
+    segments: BTreeMap<usize, Segment>,     // 修改过的段，覆盖父树中同一位置的段
+    segment_count: usize,
+    shared_segments: usize,                 // 该位置之前未被覆盖的段仍与父树一致，remove 之后的段不再对应
+    total_size: usize,
+    indices: Cow<'a, HashMap<B256, usize>>, // 第一次 remove 之前借用父树的索引
+    added: HashMap<B256, usize>,            // 分支中插入的 key
+}
+
+/// 分支与父树的根对比
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootDiff {
+    pub parent_root: B256,
+    pub fork_root: B256,
+    pub changed_segments: Vec<usize>, // 分支中复制过的段，升序
+    pub rehashed_nodes: usize,        // 计算 fork_root 时重新哈希的上层节点数
+}
+
+#[cfg(feature = "std")]
+impl RootDiff {
+    pub fn is_changed(&self) -> bool {
+        self.parent_root != self.fork_root
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> SegmentVcFork<'a> {
+    pub fn parent(&self) -> &'a SegmentVC {
+        self.parent
+    }
+
+    pub fn len(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_size == 0
+    }
+
+    /// 已复制的段数量，未修改的段与父树共享
+    pub fn materialized_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn index_of(&self, key: B256) -> Option<usize> {
+        self.added.get(&key).or_else(|| self.indices.get(&key)).copied()
+    }
+
+    /// 与 SegmentVC::get_value 相同，hash-only 的父树返回 Error::NotRetained
+    pub fn get_value(&self, key: B256) -> Result<B256, BoxError> {
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        if !self.parent.retain_values {
+            return Err(Box::new(Error::NotRetained));
+        }
+        Ok(self.segment(index / SEGMENT_SIZE).values[index % SEGMENT_SIZE])
+    }
+
+    pub fn insert(&mut self, key: B256, value: B256) -> Result<(), BoxError> {
+        if self.index_of(key).is_some() {
+            return Err(Box::new(Error::KeyExists));
+        }
+        self.parent.check_value(&value)?;
+        let index = self.total_size;
+        self.added.insert(key, index);
+        self.total_size += 1;
+        self.segment_count = self.segment_count.max(index / SEGMENT_SIZE + 1);
+        self.write(index, value);
+        Ok(())
+    }
+
+    pub fn update(&mut self, key: B256, value: B256) -> Result<(), BoxError> {
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        self.parent.check_value(&value)?;
+        self.write(index, value);
+        Ok(())
+    }
+
+    /// 与 SegmentVC::remove 相同，之后的元素依次前移；被删除元素所在段及之后的段都被复制，
+    /// 第一次 remove 时复制父树的索引
+    pub fn remove(&mut self, key: B256) -> Result<(), BoxError> {
+        let index = self.index_of(key).ok_or(Error::KeyNotFound)?;
+        let added = std::mem::take(&mut self.added);
+        let indices = self.indices.to_mut();
+        indices.extend(added);
+        indices.remove(&key);
+        for other in indices.values_mut() {
+            if *other > index {
+                *other -= 1;
+            }
+        }
+
+        // 1. 把受影响的段拉平后删除该元素
+        let first_segment = index / SEGMENT_SIZE;
+        let mut values = Vec::new();
+        let mut chunk_hashes = Vec::new();
+        for segment_index in first_segment..self.segment_count {
+            let segment = match self.segments.remove(&segment_index) {
+                Some(segment) => segment,
+                None => self.parent.segments[segment_index].clone(),
+            };
+            values.extend(segment.values);
+            chunk_hashes.extend(segment.chunk_hashes);
+        }
+        if self.parent.retain_values {
+            values.remove(index % SEGMENT_SIZE);
+        }
+        chunk_hashes.remove(index % SEGMENT_SIZE);
+        self.total_size -= 1;
+
+        // 2. 重新分段
+        let (hasher, padded) = (self.parent.hasher, self.parent.padded);
+        self.shared_segments = self.shared_segments.min(first_segment);
+        self.segment_count = first_segment;
+        for (i, hashes) in chunk_hashes.chunks(SEGMENT_SIZE).enumerate() {
+            let segment_values = if self.parent.retain_values {
+                values[i * SEGMENT_SIZE..i * SEGMENT_SIZE + hashes.len()].to_vec()
+            } else {
+                Vec::new()
+            };
+            self.segments.insert(
+                first_segment + i,
+                Segment {
+                    values: segment_values,
+                    chunk_hashes: hashes.to_vec(),
+                    root: hash_chunks(hasher, hashes, padded),
+                    size: hashes.len(),
+                },
+            );
+            self.segment_count += 1;
+        }
+        if self.segment_count == 0 {
+            self.segments.insert(0, Segment::empty());
+            self.segment_count = 1;
+        }
+        Ok(())
+    }
+
+    /// 分支的当前根，与在父树上执行同样的修改得到的根相同；空树为 B256::default()
+    pub fn root(&self) -> Result<B256, BoxError> {
+        self.compute_root().map(|(root, _)| root)
+    }
+
+    /// 父树的根、分支的根以及分支修改的范围
+    pub fn diff_root(&self) -> Result<RootDiff, BoxError> {
+        let (fork_root, rehashed_nodes) = self.compute_root()?;
+        Ok(RootDiff {
+            parent_root: self.parent.root_hash,
+            fork_root,
+            changed_segments: self.segments.keys().copied().collect(),
+            rehashed_nodes,
+        })
+    }
+
+    /// 丢弃分支中的修改，父树不受影响
+    pub fn discard(self) {}
+
+    fn segment(&self, segment_index: usize) -> &Segment {
+        self.segments
+            .get(&segment_index)
+            .unwrap_or_else(|| &self.parent.segments[segment_index])
+    }
+
+    // 先复制所在的段，不再与父树对应的段从空段开始
+    fn write(&mut self, index: usize, value: B256) {
+        let (parent, segment_index) = (self.parent, index / SEGMENT_SIZE);
+        let shared = segment_index < self.shared_segments;
+        let segment = self
+            .segments
+            .entry(segment_index)
+            .or_insert_with(|| if shared { parent.segments[segment_index].clone() } else { Segment::empty() });
+        segment.set(index % SEGMENT_SIZE, value, parent.retain_values, parent.hasher, parent.padded);
+    }
+
+    // 父树中未被修改的节点，第 0 层为段根
+    fn parent_node(&self, level: usize, index: usize) -> B256 {
+        if level == 0 {
+            return self.parent.segments[index].root;
+        }
+        self.parent.merkle_nodes.get(&level).and_then(|nodes| nodes.get(index)).copied().unwrap_or_default()
+    }
+
+    // (根, 重新哈希的上层节点数)：只重算修改过的节点所在的组，节点数量变化的层还要重算最后一组
+    fn compute_root(&self) -> Result<(B256, usize), BoxError> {
+        if self.parent.dirty || !self.parent.is_finalized() {
+            return Err(Box::new(Error::StaleRoot));
+        }
+        if self.total_size == 0 {
+            return Ok((B256::default(), 0));
+        }
+
+        let (hasher, padded) = (self.parent.hasher, self.parent.padded);
+        let mut changed: BTreeMap<usize, B256> = self.segments.iter().map(|(index, segment)| (*index, segment.root)).collect();
+        let mut count = self.segment_count;
+        let mut parent_count = self.parent.segments.len();
+        let mut level = 0;
+        let mut rehashed_nodes = 0;
+        while count > 1 {
+            let mut groups: BTreeSet<usize> = changed.keys().map(|index| index / SEGMENT_SIZE).collect();
+            if count != parent_count {
+                groups.insert((count - 1) / SEGMENT_SIZE);
+            }
+
+            let mut next = BTreeMap::new();
+            for group in groups {
+                let start = group * SEGMENT_SIZE;
+                let end = std::cmp::min(start + SEGMENT_SIZE, count);
+                let children: Vec<B256> = (start..end)
+                    .map(|index| changed.get(&index).copied().unwrap_or_else(|| self.parent_node(level, index)))
+                    .collect();
+                next.insert(group, hash_chunks(hasher, &children, padded));
+            }
+            rehashed_nodes += next.len();
+
+            changed = next;
+            count = count.div_ceil(SEGMENT_SIZE);
+            parent_count = parent_count.div_ceil(SEGMENT_SIZE);
+            level += 1;
+        }
+
+        let root = changed.get(&0).copied().unwrap_or_else(|| self.parent_node(level, 0));
+        Ok((root, rehashed_nodes))
+    }
+}
+/// 把旧版本持久化的从 1 开始的 indices 转换为从 0 开始
+#[cfg(feature = "std")]
+pub fn migrate_one_based_indices(
+    indices: HashMap<B256, usize>,
+) -> Result<HashMap<B256, usize>, BoxError> {
+    indices
+        .into_iter()
+        .map(|(key, index)| match index.checked_sub(1) {
+            Some(index) => Ok((key, index)),
+            None => Err(format!("Index 0 for key {} is not a one-based index", key).into()),
+        })
+        .collect()
+}
+
+/// 按插入顺序的 values 依次插入默认配置（Keccak、不填充）的 SegmentVC 后的根，不构造树
+///
+/// 承诺方案，Solidity 实现逐行对照：
+/// 1. 每个值的 chunk hash = H(value)
+/// 2. 每 SEGMENT_SIZE 个 chunk hash 为一段，段根 = H(chunk_hash_0 ‖ … ‖ chunk_hash_k)，最后一段可以不满
+/// 3. 段根为第 0 层，每 NODE_WIDTH 个相邻节点一组，父节点 = H(组内节点依次拼接)，直到只剩一个节点
+/// 4. 只有一段时根就是段根，没有值时根为零
+///
+/// H 为 TreeHashAlgorithm 的哈希，默认 Keccak；键不参与哈希，只决定插入顺序
+pub fn compute_root_from_values(values: &[B256]) -> B256 {
+    compute_root_with(TreeHashAlgorithm::Keccak, values, false)
+}
+
+/// 与 compute_root_from_values 相同，对应 with_padded(true) 的树：第 2、3 步每组不满 NODE_WIDTH 个时补零
+pub fn compute_root_from_values_padded(values: &[B256]) -> B256 {
+    compute_root_with(TreeHashAlgorithm::Keccak, values, true)
+}
+
+/// 指定哈希算法和填充方式的 compute_root_from_values
+pub fn compute_root_with(hasher: TreeHashAlgorithm, values: &[B256], padded: bool) -> B256 {
+    if values.is_empty() {
+        return B256::ZERO;
+    }
+    let chunk_hashes: Vec<B256> = values.iter().map(|value| hash_value(hasher, value)).collect();
+    let mut level: Vec<B256> = chunk_hashes
+        .chunks(SEGMENT_SIZE)
+        .map(|segment| hash_chunks(hasher, segment, padded))
+        .collect();
+    while level.len() > 1 {
+        level = level.chunks(NODE_WIDTH).map(|group| hash_chunks(hasher, group, padded)).collect();
     }
+    level[0]
 }
+
+// 值到 chunk hash
+fn hash_value(hasher: TreeHashAlgorithm, value: &B256) -> B256 {
+    hasher.hash(value.as_slice())
+}
+
+// chunk hashes 到段根
+// padded 时在 chunk_hashes 之后补零到 NODE_WIDTH 个位置
+fn hash_chunks(hasher: TreeHashAlgorithm, chunk_hashes: &[B256], padded: bool) -> B256 {
+    let mut state = hasher.start();
+    for hash in chunk_hashes {
+        state.update(hash.as_slice());
+    }
+    if padded {
+        for _ in chunk_hashes.len()..NODE_WIDTH {
+            state.update(B256::ZERO.as_slice());
+        }
+    }
+    state.finalize()
+}
+
 fn format_hash(hash: &B256) -> String {
     let bytes = hash.as_slice();
     format!(
@@ -588,98 +1697,166 @@ fn format_hash(hash: &B256) -> String {
     )
 }
 
+/// 证明的可读文本，调用方需要时自行打印
+pub fn render_proof(proof: &MerkleProof, title: &str) -> String {
+    let mut out = String::new();
+    // 写入 String 不会失败
+    let _ = write_proof(&mut out, proof, title);
+    out
+}
+
+/// 打印 render_proof 的输出
+#[cfg(feature = "std")]
+#[deprecated(note = "请使用 render_proof，由调用方决定输出位置")]
 pub fn print_proof(proof: &MerkleProof, title: &str) {
-    println!("\n=== {} ===", title);
-
-    // 打印Value Proof
-    println!("Value Proof:");
-    println!(
-        "  Original Value: {}",
-        format_hash(&proof.value_proof.value)
-    );
-    println!(
-        "  Chunk Hash:     {}",
-        format_hash(&proof.value_proof.chunk_hash)
-    );
-
-    // 打印Segment Proof
-    println!("\nSegment Proof:");
-    println!("  Chunk Index: {}", proof.segment_proof.chunk_index);
-    println!("  Siblings:");
+    print!("\n{}", render_proof(proof, title));
+}
+
+fn write_proof(out: &mut String, proof: &MerkleProof, title: &str) -> fmt::Result {
+    writeln!(out, "=== {} ===", title)?;
+
+    // Value Proof
+    writeln!(out, "Value Proof:")?;
+    writeln!(out, "  Original Value: {}", format_hash(&proof.value_proof.value))?;
+    writeln!(out, "  Chunk Hash:     {}", format_hash(&proof.value_proof.chunk_hash))?;
+
+    // Segment Proof
+    writeln!(out, "\nSegment Proof:")?;
+    writeln!(out, "  Chunk Index: {}", proof.segment_proof.chunk_index)?;
+    writeln!(out, "  Siblings:")?;
     for (i, sibling) in proof.segment_proof.siblings.iter().enumerate() {
-        println!("    {}: {}", i, format_hash(sibling));
+        writeln!(out, "    {}: {}", i, format_hash(sibling))?;
     }
 
-    // 打印Level Proofs
-    println!("\nLevel Proofs:");
+    // Level Proofs
+    writeln!(out, "\nLevel Proofs:")?;
     for level_proof in proof.level_proofs.iter() {
-        println!("\nLevel {}:", level_proof.level);
-        println!("  Node Index: {}", level_proof.node_index);
-        println!("  Siblings:");
+        writeln!(out, "\nLevel {}:", level_proof.level)?;
+        writeln!(out, "  Node Index: {}", level_proof.node_index)?;
+        writeln!(out, "  Siblings:")?;
         for (i, sibling) in level_proof.siblings.iter().enumerate() {
-            println!("    {}: {}", i, format_hash(sibling));
+            writeln!(out, "    {}: {}", i, format_hash(sibling))?;
         }
     }
 
-    println!("\nRoot Hash: {}", format_hash(&proof.root_hash));
+    if proof.padded {
+        writeln!(out, "\nPadded: {} slots per group", NODE_WIDTH)?;
+    }
+    writeln!(out, "\nRoot Hash: {}", format_hash(&proof.root_hash))
+}
+
+/// SegmentVC::stats 的结果，供监控和测试读取树的形状
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    pub total_elements: usize,
+    pub segment_count: usize,
+    pub levels: usize,                    // merkle_nodes 的层数，第 0 层为段根，空树为 0
+    pub nodes_per_level: Vec<usize>,      // 从第 0 层开始每层的节点数
+    pub fill_ratio_per_segment: Vec<f32>, // 每段已用位置占 SEGMENT_SIZE 的比例
+    pub history_len: usize,               // 根历史中当前保留的根数量
 }
+
+#[cfg(feature = "std")]
 impl SegmentVC {
+    pub fn stats(&self) -> TreeStats {
+        let levels = self.merkle_nodes.keys().max().map_or(0, |max_level| max_level + 1);
+        let nodes_per_level = (0..levels)
+            .map(|level| self.merkle_nodes.get(&level).map_or(0, |nodes| nodes.len()))
+            .collect();
+        let fill_ratio_per_segment = (0..self.segments.len())
+            .map(|segment| {
+                let used = self.total_size.saturating_sub(segment * SEGMENT_SIZE).min(SEGMENT_SIZE);
+                used as f32 / SEGMENT_SIZE as f32
+            })
+            .collect();
+
+        TreeStats {
+            total_elements: self.total_size,
+            segment_count: self.segments.len(),
+            levels,
+            nodes_per_level,
+            fill_ratio_per_segment,
+            history_len: self.root_history.current_size(),
+        }
+    }
+
+    /// 整棵树的可读文本，调用方需要时自行打印
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // 写入 String 不会失败
+        let _ = self.write_tree_structure(&mut out);
+        out
+    }
+
+    /// 与 render 相同
+    pub fn render_tree_structure(&self) -> String {
+        self.render()
+    }
+
+    /// 打印 render_tree_structure 的输出
+    #[deprecated(note = "请使用 render_tree_structure，由调用方决定输出位置")]
     pub fn print_tree_structure(&self) {
-        println!("\n=== Vector Commitment Tree Structure ===\n");
+        print!("\n{}", self.render_tree_structure());
+    }
+
+    fn write_tree_structure(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "=== Vector Commitment Tree Structure ===\n")?;
 
         // 找出最大层级
         let max_level = self.merkle_nodes.keys().max().unwrap_or(&0);
 
-        // 从上到下打印每一层
+        // 从上到下输出每一层
         // 最顶层（root）
-        println!("Root Hash: {}", format_hash(&self.root_hash));
+        writeln!(out, "Root Hash: {}", format_hash(&self.root_hash))?;
 
-        // 打印merkle_nodes中的每一层
+        // merkle_nodes中的每一层
         for level in (0..=*max_level).rev() {
             if let Some(nodes) = self.merkle_nodes.get(&level) {
-                println!("\nLevel {}:", level);
+                writeln!(out, "\nLevel {}:", level)?;
                 for (i, node) in nodes.iter().enumerate() {
-                    println!("├── Node[{}]: {}", i, format_hash(node));
+                    writeln!(out, "├── Node[{}]: {}", i, format_hash(node))?;
                 }
             }
         }
 
-        // 打印Segment Roots
-        println!("\nSegment Roots:");
+        // Segment Roots
+        writeln!(out, "\nSegment Roots:")?;
         for (i, segment) in self.segments.iter().enumerate() {
-            println!("├── Segment[{}]: {}", i, format_hash(&segment.root));
+            writeln!(out, "├── Segment[{}]: {}", i, format_hash(&segment.root))?;
         }
 
-        // 打印Chunk Hashes
-        println!("\nChunk Hashes:");
+        // Chunk Hashes
+        writeln!(out, "\nChunk Hashes:")?;
         for (seg_idx, segment) in self.segments.iter().enumerate() {
-            println!("Segment {}:", seg_idx);
+            writeln!(out, "Segment {}:", seg_idx)?;
             for (i, chunk_hash) in segment.chunk_hashes.iter().enumerate() {
-                println!("├── Chunk[{}]: {}", i, format_hash(chunk_hash));
+                writeln!(out, "├── Chunk[{}]: {}", i, format_hash(chunk_hash))?;
             }
         }
 
-        // 打印Original Values
-        println!("\nOriginal Values:");
+        // Original Values
+        writeln!(out, "\nOriginal Values:")?;
         for (seg_idx, segment) in self.segments.iter().enumerate() {
-            println!("Segment {}:", seg_idx);
+            writeln!(out, "Segment {}:", seg_idx)?;
             for (i, value) in segment.values.iter().enumerate() {
-                println!("├── Value[{}]: {}", i, format_hash(value));
+                writeln!(out, "├── Value[{}]: {}", i, format_hash(value))?;
             }
         }
 
-        println!("\nTotal size: {}", self.total_size);
+        writeln!(out, "\nTotal size: {}", self.total_size)?;
 
-        // 打印一些统计信息
-        println!("\nTree Statistics:");
-        println!("Total Levels: {}", max_level + 1);
-        println!("Total Segments: {}", self.segments.len());
-        println!("Nodes per Level:");
+        // 统计信息
+        writeln!(out, "\nTree Statistics:")?;
+        writeln!(out, "Total Levels: {}", max_level + 1)?;
+        writeln!(out, "Total Segments: {}", self.segments.len())?;
+        writeln!(out, "Nodes per Level:")?;
         for level in 0..=*max_level {
             if let Some(nodes) = self.merkle_nodes.get(&level) {
-                println!("  Level {}: {} nodes", level, nodes.len());
+                writeln!(out, "  Level {}: {} nodes", level, nodes.len())?;
             }
         }
+        Ok(())
     }
 }
 #[cfg(test)]
@@ -695,7 +1872,7 @@ mod tests {
         vc.insert(key, value)?;
 
         let proof = vc.generate_proof(key)?;
-        print_proof(&proof, "Single Node Proof");
+        assert!(render_proof(&proof, "Single Node Proof").contains("Single Node Proof"));
         assert!(proof.verify()?);
 
         Ok(())
@@ -711,73 +1888,839 @@ mod tests {
             let value = B256::repeat_byte((i * 100) as u8);
             vc.insert(key, value)?;
         }
-        // 打印整个树的结构
-        vc.print_tree_structure();
+        // 整个树的结构
+        let rendered = vc.render();
+        assert!(rendered.contains("Total Segments: 1"));
         // 验证不同位置的节点
         let test_indices = vec![1, 3];
         for &i in &test_indices {
             let key = B256::repeat_byte(i as u8);
             let proof = vc.generate_proof(key)?;
-            print_proof(&proof, &format!("Node {} Proof", i));
+            assert!(render_proof(&proof, &format!("Node {} Proof", i)).contains(&format_hash(&proof.root_hash)));
             assert!(proof.verify()?);
         }
 
         Ok(())
     }
     #[test]
-    fn test_three_nodes_tree() -> Result<(), BoxError> {
-        let mut vc = SegmentVC::new(128);
+    fn test_verify_inclusion_history() -> Result<(), BoxError> {
+        // 根历史只保留最近 2 个根
+        let mut vc = SegmentVC::new(2);
+        let key = B256::repeat_byte(1);
+        let value = B256::repeat_byte(100);
+        vc.insert(key, value)?;
+        vc.insert(B256::repeat_byte(2), B256::repeat_byte(200))?;
 
-        // 创建三个不同的值和key
-        let key1 = B256::repeat_byte(1 as u8);
-        let key2 = B256::repeat_byte(2 as u8);
-        let key3 = B256::repeat_byte(3 as u8);
+        let stale_proof = vc.generate_proof(key)?;
+        assert!(vc.verify_inclusion(&stale_proof)?);
 
-        let value1 = B256::repeat_byte(100 as u8);
-        let value2 = B256::repeat_byte(200 as u8);
-        let value3 = B256::repeat_byte((300&0xff) as u8);
+        // 根变化后，旧证明只能通过根历史验证
+        vc.insert(B256::repeat_byte(3), B256::repeat_byte(3))?;
+        assert_ne!(stale_proof.root_hash, vc.get_root_hash()?);
+        assert!(vc.verify_inclusion(&stale_proof)?);
+        assert!(vc.verify_inclusion(&vc.generate_proof(key)?)?);
 
-        println!("\n=== Inserting First Node ===");
-        vc.insert(key1, value1)?;
+        // 旧根被挤出历史后不再有效，尽管证明本身仍然自洽
+        vc.insert(B256::repeat_byte(4), B256::repeat_byte(4))?;
+        assert!(stale_proof.verify()?);
+        assert!(!vc.verify_inclusion(&stale_proof)?);
 
-        println!("\n=== Inserting Second Node ===");
-        vc.insert(key2, value2)?;
+        // 篡改过的证明即使根已知也不通过
+        let mut tampered = vc.generate_proof(key)?;
+        tampered.value_proof.value = B256::repeat_byte(0xAA);
+        assert!(!vc.verify_inclusion(&tampered)?);
 
-        println!("\n=== Inserting Third Node ===");
-        vc.insert(key3, value3)?;
+        // 其他树的证明根未知
+        let mut other = SegmentVC::new(2);
+        other.insert(key, B256::repeat_byte(0xBB))?;
+        assert!(!vc.verify_inclusion(&other.generate_proof(key)?)?);
 
-        vc.print_tree_structure();
+        Ok(())
+    }
 
-        // 生成并验证每个节点的证明
-        for (key, value) in [(key1, value1), (key2, value2), (key3, value3)] {
-            println!(
-                "\n=== Generating and Verifying Proof for key {} ===",
-                format_hash(&key)
-            );
-            let proof = vc.generate_proof(key)?;
-            print_proof(&proof, &format!("Node {} Proof", key));
-            assert!(proof.verify()?);
-        }
+    #[test]
+    fn test_checkpoint_only_history() -> Result<(), BoxError> {
+        let entries: Vec<(B256, B256)> = (1..=40u8)
+            .map(|i| (B256::repeat_byte(i), B256::repeat_byte(i.wrapping_mul(7))))
+            .collect();
+
+        let mut vc = SegmentVC::new(16).with_history_mode(HistoryMode::CheckpointOnly);
+        vc.insert_batch(entries.clone())?;
+        assert_eq!(vc.history_stats(), (0, 0, false));
+        let root = vc.checkpoint()?;
+        assert_eq!(vc.history_stats(), (1, 1, false));
+        // 根没有变化时不重复记录
+        vc.checkpoint()?;
+        assert_eq!(vc.history_stats(), (1, 1, false));
+        assert!(vc.was_root(root, &[]));
+
+        // 单个插入也不记录，直到下一次 checkpoint
+        vc.insert(B256::repeat_byte(0xF0), B256::repeat_byte(1))?;
+        vc.insert(B256::repeat_byte(0xF1), B256::repeat_byte(2))?;
+        assert_eq!(vc.root_history().total_added(), 1);
+        vc.checkpoint()?;
+        assert_eq!(vc.history_stats(), (2, 2, false));
+
+        // 默认模式下同样的批量插入也只记录一次，之后每次插入都记录
+        let mut every = SegmentVC::new(16);
+        every.insert_batch(entries)?;
+        assert_eq!(every.history_stats(), (1, 1, false));
+        every.insert(B256::repeat_byte(0xF0), B256::repeat_byte(1))?;
+        assert_eq!(every.history_stats(), (2, 2, false));
+
+        // 构建模式中不能 checkpoint
+        let mut building = SegmentVC::new(16).with_history_mode(HistoryMode::CheckpointOnly);
+        assert!(building.checkpoint().is_err());
+        building.start_building();
+        building.insert(B256::repeat_byte(1), B256::repeat_byte(1))?;
+        assert!(building.checkpoint().is_err());
 
         Ok(())
     }
 
     #[test]
-    fn test_building_mode() -> Result<(), BoxError> {
-        let mut vc = SegmentVC::new(16);
-        
-        // 开始构建模式
-        vc.start_building();
+    fn test_builder_history_policies() -> Result<(), BoxError> {
+        let insert_all = |vc: &mut SegmentVC| -> Result<Vec<B256>, BoxError> {
+            (1..=40u8).map(|i| vc.insert(B256::repeat_byte(i), B256::repeat_byte(i))).collect()
+        };
 
-        // 插入多个值
-        for i in 1..=5 {
-            let key = B256::repeat_byte(i as u8);
-            let value = B256::repeat_byte(((100* i)&0xff) as u8);
-            vc.insert(key, value)?;
-        }
+        // 默认容量与条目数量无关
+        let mut every = SegmentVC::builder().build();
+        let roots = insert_all(&mut every)?;
+        assert_eq!(every.history_stats(), (40, 40, false));
+        assert!(roots.iter().all(|root| every.was_root(*root, &[])));
+        assert_eq!(SegmentVC::builder().build().root_history().capacity(), DEFAULT_HISTORY_CAPACITY);
+
+        // 容量 8：更早的根被挤出
+        let mut small = SegmentVC::builder().with_history_capacity(8).build();
+        let roots = insert_all(&mut small)?;
+        assert_eq!(small.history_stats(), (8, 40, true));
+        assert!(!small.was_root(roots[0], &[]));
+        assert!(small.was_root(roots[39], &[]));
+
+        // CheckpointOnly：只有 checkpoint 的根
+        let mut checkpoints = SegmentVC::builder().with_history_mode(HistoryMode::CheckpointOnly).build();
+        let roots = insert_all(&mut checkpoints)?;
+        assert_eq!(checkpoints.history_stats(), (0, 0, false));
+        assert!(!checkpoints.was_root(roots[39], &[]));
+        checkpoints.checkpoint()?;
+        assert_eq!(checkpoints.history_stats(), (1, 1, false));
+        assert!(checkpoints.was_root(roots[39], &[]));
+        assert!(!checkpoints.was_root(roots[38], &[]));
+
+        // Disabled：不记录任何根，当前根仍可验证包含证明
+        let mut disabled = SegmentVC::builder()
+            .hash_only()
+            .with_history_capacity(8)
+            .with_history_mode(HistoryMode::Disabled)
+            .build();
+        let roots = insert_all(&mut disabled)?;
+        assert_eq!(disabled.checkpoint()?, roots[39]);
+        assert_eq!(disabled.history_stats(), (0, 0, false));
+        assert!(!disabled.was_root(roots[39], &[]));
+        let mut proof = disabled.generate_proof(B256::repeat_byte(3))?;
+        proof.value_proof.value = B256::repeat_byte(3);
+        assert!(disabled.verify_inclusion(&proof)?);
+
+        // 根与记录方式无关
+        assert_eq!(disabled.get_root_hash()?, every.get_root_hash()?);
+        assert_eq!(SegmentVC::new(8).with_history_mode(HistoryMode::Disabled).history_stats(), (0, 0, false));
+        Ok(())
+    }
 
-        // 完成构建
-        let root = vc.finish_building()?;
+    #[test]
+    fn test_was_root_after_update() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(2);
+        vc.insert(B256::repeat_byte(1), B256::repeat_byte(1))?;
+        let first = vc.get_root_hash()?;
+        vc.insert(B256::repeat_byte(2), B256::repeat_byte(2))?;
+        let second = vc.get_root_hash()?;
+
+        assert!(vc.was_root(first, &[]));
+        assert!(vc.was_root(second, &[]));
+        assert!(!vc.was_root(B256::repeat_byte(0xAA), &[]));
+
+        // first 被挤出后需要历史证明：之后被挤出的根依次链接
+        vc.insert(B256::repeat_byte(3), B256::repeat_byte(3))?;
+        assert!(!vc.was_root(first, &[]));
+        assert_eq!(vc.history_stats(), (2, 3, true));
+        vc.insert(B256::repeat_byte(4), B256::repeat_byte(4))?;
+        assert!(vc.was_root(first, &[second]));
+        assert!(!vc.was_root(second, &[first]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_batch() -> Result<(), BoxError> {
+        let entries = tree_entries(50);
+        let updates: Vec<(B256, B256)> = entries
+            .iter()
+            .step_by(3)
+            .map(|(key, value)| (*key, B256::from(U256::from_be_bytes(value.0) + U256::from(1u32))))
+            .collect();
+
+        for hash_only in [false, true] {
+            let build = || -> Result<SegmentVC, BoxError> {
+                let mut vc = if hash_only { SegmentVC::new_hash_only(64) } else { SegmentVC::new(64) };
+                vc.insert_batch(entries.clone())?;
+                Ok(vc)
+            };
+
+            // 与逐个 update 的最终根相同，根历史只增加一项
+            let mut sequential = build()?;
+            for (key, value) in &updates {
+                sequential.update(*key, *value)?;
+            }
+            let mut batched = build()?;
+            let added = batched.root_history().total_added();
+            let root = batched.update_batch(&updates)?;
+            assert_eq!(root, sequential.get_root_hash()?);
+            assert_eq!(batched.root_history().total_added(), added + 1);
+            assert!(batched.was_root(root, &[]));
+            for (key, value) in &updates {
+                assert!(batched.lookup_matches(*key, *value)?);
+                // hash-only 模式下证明中的值由调用方填入
+                let mut proof = batched.generate_proof(*key)?;
+                proof.value_proof.value = *value;
+                assert!(batched.verify_inclusion(&proof)?);
+            }
+
+            // 含有未知 key 时不修改树
+            let mut untouched = build()?;
+            let before = (untouched.get_root_hash()?, untouched.root_history().total_added());
+            let mut invalid = updates.clone();
+            invalid.insert(2, (B256::repeat_byte(0xEE), B256::repeat_byte(1)));
+            let err = untouched.update_batch(&invalid).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::KeyNotFound)));
+            assert_eq!((untouched.get_root_hash()?, untouched.root_history().total_added()), before);
+            for (key, value) in &entries {
+                assert!(untouched.lookup_matches(*key, *value)?);
+            }
+            assert_eq!(untouched.update_batch(&[])?, before.0);
+            assert_eq!(untouched.root_history().total_added(), before.1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_matches() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(16);
+        let key = B256::repeat_byte(1);
+        vc.insert(key, B256::repeat_byte(100))?;
+
+        assert!(vc.lookup_matches(key, B256::repeat_byte(100))?);
+        assert!(!vc.lookup_matches(key, B256::repeat_byte(101))?);
+        assert!(vc.lookup_matches(B256::repeat_byte(2), B256::repeat_byte(100)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_only_mode() -> Result<(), BoxError> {
+        let entries: Vec<(B256, B256)> = (1..=40u8)
+            .map(|i| (B256::repeat_byte(i), B256::repeat_byte(i.wrapping_mul(7))))
+            .collect();
+
+        let mut full = SegmentVC::new(16);
+        let mut hash_only = SegmentVC::new_hash_only(16);
+        assert!(!hash_only.retains_values());
+
+        // 批量插入后再逐个插入，两种模式的根始终一致
+        full.insert_batch(entries[..30].to_vec())?;
+        hash_only.insert_batch(entries[..30].to_vec())?;
+        assert_eq!(full.get_root_hash()?, hash_only.get_root_hash()?);
+        for (key, value) in &entries[30..] {
+            assert_eq!(full.insert(*key, *value)?, hash_only.insert(*key, *value)?);
+        }
+
+        for (key, value) in &entries {
+            let full_proof = full.generate_proof(*key)?;
+            let mut proof = hash_only.generate_proof(*key)?;
+            assert_eq!(proof.value_proof.value, B256::default());
+
+            // 填入调用方持有的值后与普通模式的证明相同
+            proof.value_proof.value = *value;
+            assert_eq!(proof, full_proof);
+            assert!(hash_only.verify_inclusion(&proof)?);
+            assert!(hash_only.lookup_matches(*key, *value)?);
+
+            let err = hash_only.get_value(*key).unwrap_err();
+            assert_eq!(err.downcast_ref::<Error>(), Some(&Error::NotRetained));
+        }
+
+        // update 之后仍然一致
+        let (key, _) = entries[5];
+        let value = B256::repeat_byte(0xEE);
+        assert_eq!(full.update(key, value)?, hash_only.update(key, value)?);
+        assert!(hash_only.lookup_matches(key, value)?);
+        assert!(!hash_only.lookup_matches(key, entries[5].1)?);
+
+        // 未知 key 仍然是 KeyNotFound
+        let err = hash_only.get_value(B256::repeat_byte(0xFF)).unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::KeyNotFound));
+
+        Ok(())
+    }
+
+    use crate::alloc_counter;
+
+    #[test]
+    fn test_proof_ref() -> Result<(), BoxError> {
+        let entries: Vec<(B256, B256)> = (1..=300u32)
+            .map(|i| {
+                (
+                    B256::left_padding_from(&i.to_be_bytes()),
+                    B256::left_padding_from(&(i + 1000).to_be_bytes()),
+                )
+            })
+            .collect();
+        let mut vc = SegmentVC::new(16);
+        vc.insert_batch(entries.clone())?;
+
+        // 借用证明与拥有证明一致，验证结果相同
+        let mut count = 0;
+        for ((key, proof_ref), (expected_key, value)) in vc.generate_proofs_for_all()?.zip(&entries) {
+            assert_eq!(key, *expected_key);
+            assert_eq!(proof_ref.value, *value);
+
+            let owned = vc.generate_proof(key)?;
+            assert_eq!(proof_ref.to_owned(), owned);
+            assert!(proof_ref.verify());
+            assert!(owned.verify()?);
+
+            let mut tampered = proof_ref.clone();
+            tampered.value = B256::repeat_byte(0xAA);
+            assert!(!tampered.verify());
+            assert!(!tampered.to_owned().verify()?);
+            count += 1;
+        }
+        assert_eq!(count, entries.len());
+
+        // 流式借用证明的分配次数远少于逐个生成拥有证明
+        let before = alloc_counter::allocations();
+        for (key, _) in &entries {
+            vc.generate_proof(*key)?;
+        }
+        let owned_allocations = alloc_counter::allocations() - before;
+
+        let before = alloc_counter::allocations();
+        for (_, proof_ref) in vc.generate_proofs_for_all()? {
+            assert!(proof_ref.verify());
+        }
+        let streamed_allocations = alloc_counter::allocations() - before;
+
+        assert!(streamed_allocations * 2 < owned_allocations);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_proof_ref() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(16);
+        for i in 1..=40u8 {
+            vc.insert(B256::repeat_byte(i), B256::repeat_byte(i.wrapping_add(100)))?;
+        }
+        let proof_ref = vc.generate_proof_ref(B256::repeat_byte(20))?;
+        assert!(proof_ref.verify());
+
+        // 越界的索引在 release 构建中同样返回 false 而不是 panic
+        let out_of_range = MerkleProofRef { chunk_index: proof_ref.chunks.len(), ..proof_ref.clone() };
+        assert!(!out_of_range.verify());
+        assert!(!out_of_range.to_owned().verify().unwrap_or(false));
+
+        let mut bad_level = proof_ref.clone();
+        bad_level.levels[0].node_index = bad_level.levels[0].nodes.len();
+        assert!(!bad_level.verify());
+
+        let empty = MerkleProofRef { chunks: &[], chunk_index: 0, ..proof_ref.clone() };
+        assert!(!empty.verify());
+
+        // 组超过 NODE_WIDTH
+        let wide = [B256::ZERO; NODE_WIDTH + 1];
+        let too_wide = MerkleProofRef { chunks: &wide, chunk_index: 0, ..proof_ref.clone() };
+        assert!(!too_wide.verify());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_proof() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(16);
+        for i in 1..=20u8 {
+            vc.insert(B256::repeat_byte(i), B256::repeat_byte(i.wrapping_add(100)))?;
+        }
+        let proof = vc.generate_proof(B256::repeat_byte(20))?;
+        assert!(proof.verify()?);
+
+        // chunk_index 超出范围
+        let mut bad = proof.clone();
+        bad.segment_proof.chunk_index = bad.segment_proof.siblings.len() + 1;
+        let err = bad.verify().unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::InvalidProof));
+
+        // node_index 超出范围
+        let mut bad = proof.clone();
+        bad.level_proofs[0].node_index = usize::MAX;
+        let err = bad.verify().unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::InvalidProof));
+
+        // 兄弟节点过多
+        let mut bad = proof.clone();
+        bad.level_proofs[0].siblings = vec![B256::ZERO; MAX_PROOF_SIBLINGS_PER_LEVEL + 1];
+        let err = bad.verify().unwrap_err();
+        assert_eq!(err.downcast_ref::<ProofTooLarge>().map(|e| e.field), Some("level siblings"));
+
+        // 层数过多
+        let mut bad = proof;
+        bad.level_proofs = vec![bad.level_proofs[0].clone(); MAX_PROOF_LEVELS + 1];
+        let err = bad.verify().unwrap_err();
+        assert_eq!(err.downcast_ref::<ProofTooLarge>().map(|e| e.field), Some("level proofs"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_proof_states() -> Result<(), BoxError> {
+        let key = B256::repeat_byte(1);
+        let failed_with = |result: Result<MerkleProof, BoxError>, expected: Error| {
+            result.unwrap_err().downcast_ref::<Error>() == Some(&expected)
+        };
+
+        // 新建的空树
+        let mut vc = SegmentVC::new(16);
+        assert!(vc.is_finalized());
+        let err = vc.generate_proof(key).unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::EmptyTree));
+        assert!(vc.generate_proofs_for_all().is_err());
+
+        // 构建模式中有待处理的插入
+        vc.start_building();
+        assert!(!vc.is_finalized());
+        assert!(failed_with(vc.generate_proof(key), Error::TreeNotFinalized));
+        vc.insert(key, B256::repeat_byte(100))?;
+        assert!(failed_with(vc.generate_proof(key), Error::StaleRoot));
+        assert!(vc.generate_proofs_for_all().is_err());
+
+        // 完成构建后正常生成
+        vc.finish_building()?;
+        assert!(vc.is_finalized());
+        assert!(vc.generate_proof(key)?.verify()?);
+        assert_eq!(vc.generate_proofs_for_all()?.count(), 1);
+        let err = vc.generate_proof(B256::repeat_byte(2)).unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::KeyNotFound));
+
+        // 已有数据时再次进入构建模式
+        vc.start_building();
+        vc.insert(B256::repeat_byte(2), B256::repeat_byte(200))?;
+        assert!(failed_with(vc.generate_proof(key), Error::StaleRoot));
+        vc.finish_building()?;
+        assert!(vc.generate_proof(B256::repeat_byte(2))?.verify()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_root_in_building_mode() -> Result<(), BoxError> {
+        let stale = |err: BoxError| err.downcast_ref::<Error>() == Some(&Error::StaleRoot);
+        let key = B256::repeat_byte(1);
+        let mut vc = SegmentVC::new(16);
+        vc.insert(key, B256::repeat_byte(100))?;
+        let old_root = vc.get_root_hash()?;
+
+        // 没有修改时构建模式中的根仍然有效
+        vc.start_building();
+        assert_eq!(vc.get_root_hash()?, old_root);
+
+        // 插入后根和证明都报告过期，而不是返回旧根
+        vc.insert(B256::repeat_byte(2), B256::repeat_byte(200))?;
+        assert!(stale(vc.get_root_hash().unwrap_err()));
+        assert!(stale(vc.generate_proof(key).unwrap_err()));
+        assert!(stale(vc.checkpoint().unwrap_err()));
+
+        // 更新同样使根过期，直到 finish_building
+        vc.update(key, B256::repeat_byte(101))?;
+        assert!(stale(vc.get_root_hash().unwrap_err()));
+        let root = vc.finish_building()?;
+        assert_ne!(root, old_root);
+        assert_eq!(vc.get_root_hash()?, root);
+        let proof = vc.generate_proof(B256::repeat_byte(2))?;
+        assert_eq!(proof.root_hash, root);
+        assert!(proof.verify()?);
+        assert_eq!(vc.generate_proof(key)?.value_proof.value, B256::repeat_byte(101));
+
+        // insert_batch 内部完成构建，不受影响
+        let mut batch = SegmentVC::new(16);
+        let batch_root = batch.insert_batch(vec![(key, B256::repeat_byte(101)), (B256::repeat_byte(2), B256::repeat_byte(200))])?;
+        assert_eq!(batch_root, root);
+        assert_eq!(batch.get_root_hash()?, root);
+        assert!(batch.generate_proof(key)?.verify()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_boundary_indices() -> Result<(), BoxError> {
+        let key = |i: u32| B256::left_padding_from(&i.to_be_bytes());
+        let value = |i: u32| B256::left_padding_from(&(i + 1000).to_be_bytes());
+
+        let mut vc = SegmentVC::new(16);
+        for i in 0..=17u32 {
+            vc.insert(key(i), value(i))?;
+        }
+
+        // 第 15、16、17 个元素分别位于第一个段末尾和第二个段开头
+        for i in [0u32, 15, 16, 17] {
+            assert_eq!(vc.index_of(key(i)), Some(i as usize));
+            assert_eq!(vc.get_value(key(i))?, value(i));
+            assert!(vc.lookup_matches(key(i), value(i))?);
+
+            let proof = vc.generate_proof(key(i))?;
+            assert_eq!(proof.segment_proof.chunk_index, i as usize % SEGMENT_SIZE);
+            assert_eq!(proof.value_proof.value, value(i));
+            assert!(vc.verify_inclusion(&proof)?);
+        }
+        assert_eq!(vc.index_of(key(18)), None);
+
+        // 更新边界元素只影响对应位置
+        for i in [15u32, 16, 17] {
+            let new_value = B256::repeat_byte(i as u8);
+            vc.update(key(i), new_value)?;
+            assert_eq!(vc.get_value(key(i))?, new_value);
+            assert!(vc.generate_proof(key(i))?.verify()?);
+        }
+        assert_eq!(vc.get_value(key(14))?, value(14));
+        assert_eq!(vc.get_value(key(0))?, value(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_one_based_indices() -> Result<(), BoxError> {
+        let mut old = HashMap::new();
+        old.insert(B256::repeat_byte(1), 1);
+        old.insert(B256::repeat_byte(2), 17);
+
+        let migrated = migrate_one_based_indices(old)?;
+        assert_eq!(migrated[&B256::repeat_byte(1)], 0);
+        assert_eq!(migrated[&B256::repeat_byte(2)], 16);
+
+        let mut invalid = HashMap::new();
+        invalid.insert(B256::repeat_byte(1), 0);
+        assert!(migrate_one_based_indices(invalid).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_and_remove() -> Result<(), BoxError> {
+        let key = |i: u32| B256::left_padding_from(&i.to_be_bytes());
+        let value = |i: u32| B256::left_padding_from(&(i + 1000).to_be_bytes());
+
+        for hash_only in [false, true] {
+            let new_vc = || if hash_only { SegmentVC::new_hash_only(16) } else { SegmentVC::new(16) };
+            let mut vc = new_vc();
+            let mut model: Vec<(B256, B256)> = Vec::new();
+            for i in 0..40u32 {
+                vc.upsert(key(i), value(i))?;
+                model.push((key(i), value(i)));
+            }
+
+            // upsert 已存在的 key 等同于 update
+            vc.upsert(key(3), value(3000))?;
+            model[3].1 = value(3000);
+
+            // 依次删除段首、段尾、中间和最后的元素，每一步都与重新构建的树一致
+            for i in [0u32, 15, 16, 39, 20] {
+                let root = vc.remove(key(i))?;
+                model.retain(|(k, _)| *k != key(i));
+
+                let mut rebuilt = new_vc();
+                rebuilt.insert_batch(model.clone())?;
+                assert_eq!(root, rebuilt.get_root_hash()?);
+                assert_eq!(vc.index_of(key(i)), None);
+
+                for (position, (k, v)) in model.iter().enumerate() {
+                    assert_eq!(vc.index_of(*k), Some(position));
+                    assert!(vc.lookup_matches(*k, *v)?);
+                }
+                let (k, v) = model[model.len() - 1];
+                let mut proof = vc.generate_proof(k)?;
+                proof.value_proof.value = v;
+                assert_eq!(proof, {
+                    let mut expected = rebuilt.generate_proof(k)?;
+                    expected.value_proof.value = v;
+                    expected
+                });
+                assert!(vc.verify_inclusion(&proof)?);
+            }
+
+            assert!(vc.remove(key(0)).is_err());
+        }
+
+        // 删除最后一个元素后回到空树
+        let mut vc = SegmentVC::new(16);
+        vc.insert(key(1), value(1))?;
+        assert_eq!(vc.remove(key(1))?, B256::default());
+        assert!(vc.generate_proof(key(1)).is_err());
+        vc.insert(key(2), value(2))?;
+        assert!(vc.generate_proof(key(2))?.verify()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_at() -> Result<(), BoxError> {
+        let key = |i: u32| B256::left_padding_from(&i.to_be_bytes());
+        let value = |i: u32| B256::left_padding_from(&(i + 1000).to_be_bytes());
+
+        for hash_only in [false, true] {
+            let new_vc = || if hash_only { SegmentVC::new_hash_only(16) } else { SegmentVC::new(16) };
+            let mut vc = new_vc();
+            let mut model: Vec<(B256, B256)> = Vec::new();
+            for i in 0..30u32 {
+                vc.insert(key(i), value(i))?;
+                model.push((key(i), value(i)));
+            }
+
+            // 依次插入到开头、段首、段尾、中间和末尾，第 33 个元素开始第三段
+            for (step, position) in [0usize, 16, 15, 7, 34].into_iter().enumerate() {
+                let i = 100 + step as u32;
+                let root = vc.insert_at(position, key(i), value(i))?;
+                model.insert(position, (key(i), value(i)));
+
+                let mut rebuilt = new_vc();
+                rebuilt.insert_batch(model.clone())?;
+                assert_eq!(root, rebuilt.get_root_hash()?);
+                for (index, (k, v)) in model.iter().enumerate() {
+                    assert_eq!(vc.index_of(*k), Some(index));
+                    assert!(vc.lookup_matches(*k, *v)?);
+                    let mut proof = vc.generate_proof(*k)?;
+                    proof.value_proof.value = *v;
+                    assert!(vc.verify_inclusion(&proof)?);
+                }
+            }
+
+            // 越界、重复的 key 和构建模式中的树
+            let err = vc.insert_at(model.len() + 1, key(200), value(200)).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::IndexOutOfBounds)));
+            let err = vc.insert_at(0, key(3), value(3)).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::KeyExists)));
+            vc.start_building();
+            let err = vc.insert_at(0, key(200), value(200)).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::TreeNotFinalized)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork_matches_parent_operations() -> Result<(), BoxError> {
+        let word = |n: u32| B256::from(U256::from(n).to_be_bytes::<32>());
+
+        for hash_only in [false, true] {
+            let new_vc = || if hash_only { SegmentVC::new_hash_only(16) } else { SegmentVC::new(16) };
+            // 250 个元素正好 16 段，插入后树增加一层
+            let mut parent = new_vc();
+            let parent_root = parent.insert_batch(tree_entries(250))?;
+            // hash-only 模式下由调用方填入值，tree_entries 中 word(7) 的值为 word(50)
+            let fill = |mut proof: MerkleProof| {
+                proof.value_proof.value = word(50);
+                proof
+            };
+            let proof = fill(parent.generate_proof(word(7))?);
+            let mut expected = new_vc();
+            expected.insert_batch(tree_entries(250))?;
+
+            let mut fork = parent.fork();
+            assert_eq!(fork.root()?, parent_root);
+            assert!(!fork.diff_root()?.is_changed());
+
+            fork.update(word(7), word(70_000))?;
+            expected.update(word(7), word(70_000))?;
+            for i in 0..10 {
+                fork.insert(word(10_000 + i), word(i))?;
+                expected.insert(word(10_000 + i), word(i))?;
+            }
+            assert_eq!(fork.root()?, expected.get_root_hash()?);
+
+            // 删除段中间和分支中插入的元素，之后再插入
+            for key in [word(20), word(10_003), word(249)] {
+                fork.remove(key)?;
+                expected.remove(key)?;
+                assert_eq!(fork.root()?, expected.get_root_hash()?);
+            }
+            fork.insert(word(20_000), word(1))?;
+            expected.insert(word(20_000), word(1))?;
+            assert_eq!(fork.root()?, expected.get_root_hash()?);
+            assert_eq!(fork.len(), 258);
+            for key in [word(0), word(21), word(10_004), word(20_000)] {
+                assert_eq!(fork.index_of(key), expected.index_of(key));
+            }
+            assert_eq!(fork.index_of(word(20)), None);
+            assert!(fork.insert(word(1), word(1)).is_err());
+            assert!(fork.update(word(20), word(1)).is_err());
+            if !hash_only {
+                assert_eq!(fork.get_value(word(7))?, word(70_000));
+            }
+
+            // 分支存在时父树不变，仍生成原来的证明
+            assert_eq!(parent.get_root_hash()?, parent_root);
+            assert_eq!(fill(parent.generate_proof(word(7))?), proof);
+            assert_eq!(parent.index_of(word(20)), Some(20));
+            let diff = fork.diff_root()?;
+            assert_eq!(diff.parent_root, parent_root);
+            assert!(diff.is_changed());
+            fork.discard();
+
+            assert!(parent.verify_inclusion(&proof)?);
+            parent.update(word(8), word(1))?;
+            assert_eq!(parent.fork().root()?, parent.get_root_hash()?);
+        }
+
+        // 空树分支，以及删除全部元素
+        let parent = SegmentVC::new(16);
+        let mut fork = parent.fork();
+        assert_eq!(fork.root()?, B256::default());
+        fork.insert(word(1), word(2))?;
+        let mut expected = SegmentVC::new(16);
+        assert_eq!(fork.root()?, expected.insert(word(1), word(2))?);
+        fork.remove(word(1))?;
+        assert!(fork.is_empty());
+        assert_eq!(fork.root()?, B256::default());
+
+        // 构建模式中的父树没有可用的根
+        let mut building = SegmentVC::new(16);
+        building.start_building();
+        building.insert(word(1), word(2))?;
+        assert!(building.fork().root().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork_copies_affected_segments() -> Result<(), BoxError> {
+        let word = |n: u32| B256::from(U256::from(n).to_be_bytes::<32>());
+        let mut parent = SegmentVC::builder().with_history_mode(HistoryMode::Disabled).build();
+        parent.insert_batch(tree_entries(5000))?;
+        let mut expected = SegmentVC::builder().with_history_mode(HistoryMode::Disabled).build();
+        expected.insert_batch(tree_entries(5000))?;
+
+        // 313 段，上面三层：修改一个叶子只复制一个段，每层重新哈希一个节点
+        let mut fork = parent.fork();
+        fork.update(word(1234), word(1))?;
+        let diff = fork.diff_root()?;
+        assert_eq!(diff.changed_segments, vec![1234 / SEGMENT_SIZE]);
+        assert_eq!(diff.rehashed_nodes, 3);
+        assert_eq!(fork.materialized_segments(), 1);
+        expected.update(word(1234), word(1))?;
+        assert_eq!(diff.fork_root, expected.get_root_hash()?);
+
+        // 追加的叶子落在最后一段
+        fork.insert(word(9999), word(2))?;
+        let diff = fork.diff_root()?;
+        assert_eq!(diff.changed_segments, vec![1234 / SEGMENT_SIZE, 5000 / SEGMENT_SIZE]);
+        assert!(diff.rehashed_nodes <= 6);
+        expected.insert(word(9999), word(2))?;
+        assert_eq!(diff.fork_root, expected.get_root_hash()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_three_nodes_tree() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(128);
+
+        // 创建三个不同的值和key
+        let key1 = B256::repeat_byte(1 as u8);
+        let key2 = B256::repeat_byte(2 as u8);
+        let key3 = B256::repeat_byte(3 as u8);
+
+        let value1 = B256::repeat_byte(100 as u8);
+        let value2 = B256::repeat_byte(200 as u8);
+        let value3 = B256::repeat_byte((300&0xff) as u8);
+
+        vc.insert(key1, value1)?;
+        vc.insert(key2, value2)?;
+        vc.insert(key3, value3)?;
+
+        let rendered = vc.render();
+        assert!(rendered.contains(&format_hash(&vc.get_root_hash()?)));
+        assert!(rendered.contains("Total size: 3"));
+        assert_eq!(vc.render_tree_structure(), rendered);
+        // 旧的打印接口仍然可用
+        #[allow(deprecated)]
+        {
+            vc.print_tree_structure();
+            print_proof(&vc.generate_proof(key1)?, "Deprecated Print");
+        }
+
+        // 生成并验证每个节点的证明
+        for (key, value) in [(key1, value1), (key2, value2), (key3, value3)] {
+            let proof = vc.generate_proof(key)?;
+            assert_eq!(proof.value_proof.value, value);
+            assert!(proof.verify()?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_stats() -> Result<(), BoxError> {
+        let empty = SegmentVC::new(16).stats();
+        assert_eq!((empty.total_elements, empty.segment_count, empty.levels), (0, 1, 0));
+        assert!(empty.nodes_per_level.is_empty());
+
+        // (元素数, 段数, 每层节点数, 最后一段的填充比例)
+        let cases: [(usize, usize, &[usize], f32); 4] = [
+            (1, 1, &[1], 1.0 / 16.0),
+            (16, 1, &[1], 1.0),
+            (17, 2, &[2, 1], 1.0 / 16.0),
+            (300, 19, &[19, 2, 1], 12.0 / 16.0),
+        ];
+        for (count, segment_count, nodes_per_level, last_fill) in cases {
+            let mut vc = SegmentVC::new(16);
+            for i in 0..count {
+                vc.insert(B256::from(U256::from(i + 1)), B256::from(U256::from(i + 1000)))?;
+            }
+            let stats = vc.stats();
+            assert_eq!(stats.total_elements, count);
+            assert_eq!(stats.segment_count, segment_count);
+            assert_eq!(stats.nodes_per_level, nodes_per_level);
+            assert_eq!(stats.history_len, count.min(16));
+
+            // 与 merkle_nodes 一致，最顶层只有根
+            assert_eq!(stats.levels, vc.merkle_nodes.len());
+            for (level, nodes) in stats.nodes_per_level.iter().enumerate() {
+                assert_eq!(vc.merkle_nodes[&level].len(), *nodes);
+            }
+            assert_eq!(vc.merkle_nodes[&(stats.levels - 1)], vec![vc.get_root_hash()?]);
+
+            let (last, full) = stats.fill_ratio_per_segment.split_last().unwrap();
+            assert_eq!(*last, last_fill);
+            assert!(full.iter().all(|ratio| *ratio == 1.0));
+
+            let rendered = vc.render();
+            assert!(rendered.contains(&format!("Total Levels: {}", stats.levels)));
+            assert!(rendered.contains(&format!("Total Segments: {}", segment_count)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_building_mode() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(16);
+        
+        // 开始构建模式
+        vc.start_building();
+
+        // 插入多个值
+        for i in 1..=5 {
+            let key = B256::repeat_byte(i as u8);
+            let value = B256::repeat_byte(((100* i)&0xff) as u8);
+            vc.insert(key, value)?;
+        }
+
+        // 完成构建
+        let root = vc.finish_building()?;
 
         // 验证所有值
         for i in 1..=5 {
@@ -845,4 +2788,567 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_zero_values() -> Result<(), BoxError> {
+        // 第一段中夹杂零值，第二段全部为零
+        let entries: Vec<(B256, B256)> = (0..20u8)
+            .map(|i| (B256::repeat_byte(i + 1), if i % 3 == 0 || i >= 16 { B256::ZERO } else { B256::repeat_byte(i) }))
+            .collect();
+
+        let mut sequential = SegmentVC::new(16);
+        for (key, value) in &entries {
+            sequential.insert(*key, *value)?;
+        }
+        let mut building = SegmentVC::new(16);
+        building.start_building();
+        for (key, value) in &entries {
+            building.insert(*key, *value)?;
+        }
+        let root = building.finish_building()?;
+        assert_eq!(root, sequential.get_root_hash()?);
+        assert_eq!(root, reference_keccak_root(&entries.iter().map(|(_, value)| *value).collect::<Vec<_>>()));
+
+        for (key, value) in &entries {
+            assert_eq!(building.get_value(*key)?, *value);
+            let proof = building.generate_proof(*key)?;
+            assert_eq!(proof.value_proof.value, *value);
+            assert!(proof.verify()?);
+        }
+
+        // 严格模式拒绝零值，树保持不变
+        let mut strict = SegmentVC::builder().with_strict(true).build();
+        assert!(strict.is_strict());
+        let err = strict.insert(B256::repeat_byte(1), B256::ZERO).unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::ZeroValue));
+        assert!(strict.get_value(B256::repeat_byte(1)).is_err());
+        assert!(strict.insert_batch(entries.clone()).is_err());
+
+        let mut strict = SegmentVC::new(16).with_strict(true);
+        let root = strict.insert(B256::repeat_byte(1), B256::repeat_byte(2))?;
+        assert!(strict.update(B256::repeat_byte(1), B256::ZERO).is_err());
+        assert!(strict.update_batch(&[(B256::repeat_byte(1), B256::ZERO)]).is_err());
+        assert!(strict.fork().insert(B256::repeat_byte(3), B256::ZERO).is_err());
+        assert_eq!(strict.get_root_hash()?, root);
+        assert_eq!(strict.get_value(B256::repeat_byte(1))?, B256::repeat_byte(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_values_hash_only() -> Result<(), BoxError> {
+        // 中间和段尾的零值在两种模式下都计为 H(0)，根与证明相同
+        let entries: Vec<(B256, B256)> = (0..20u8)
+            .map(|i| (B256::repeat_byte(i + 1), if i % 3 == 0 || i >= 16 { B256::ZERO } else { B256::repeat_byte(i) }))
+            .collect();
+        let expected = reference_keccak_root(&entries.iter().map(|(_, value)| *value).collect::<Vec<_>>());
+
+        let mut full = SegmentVC::new(16);
+        let mut hash_only = SegmentVC::new_hash_only(16);
+        for (key, value) in &entries {
+            assert_eq!(full.insert(*key, *value)?, hash_only.insert(*key, *value)?);
+        }
+        assert_eq!(hash_only.get_root_hash()?, expected);
+        let mut batched = SegmentVC::new_hash_only(16);
+        assert_eq!(batched.insert_batch(entries.clone())?, expected);
+
+        for (key, value) in &entries {
+            let mut proof = hash_only.generate_proof(*key)?;
+            assert_eq!(proof.value_proof.chunk_hash, full.generate_proof(*key)?.value_proof.chunk_hash);
+            proof.value_proof.value = *value;
+            assert!(hash_only.verify_inclusion(&proof)?);
+        }
+
+        // 把非零值改为零，逐个更新和批量更新的结果也相同
+        let zeroed = [(entries[1].0, B256::ZERO), (entries[17].0, B256::ZERO)];
+        for (key, value) in zeroed {
+            assert_eq!(full.update(key, value)?, hash_only.update(key, value)?);
+        }
+        assert_eq!(batched.update_batch(&zeroed)?, full.get_root_hash()?);
+
+        // 分支中追加的零值
+        let mut full_fork = full.fork();
+        let mut hash_only_fork = hash_only.fork();
+        full_fork.insert(B256::repeat_byte(0xAA), B256::ZERO)?;
+        hash_only_fork.insert(B256::repeat_byte(0xAA), B256::ZERO)?;
+        assert_eq!(full_fork.root()?, hash_only_fork.root()?);
+        Ok(())
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_insert_batch_metrics() -> Result<(), BoxError> {
+        use crate::metrics::{InMemoryMetrics, TREE_NODES_HASHED};
+
+        // hash-only：每次插入补一个空位、写入 chunk hash 并重算段根，共 3 次；两个段根再合并 1 次
+        let metrics = InMemoryMetrics::new();
+        SegmentVC::new_hash_only(16).insert_batch_with_metrics(tree_entries(17), &metrics)?;
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 17 * 3 + 1);
+
+        // 保留值：finish_building 每段重算一次，3 个 chunk hash 加 1 次段根
+        let metrics = InMemoryMetrics::new();
+        SegmentVC::new(16).insert_batch_with_metrics(tree_entries(3), &metrics)?;
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 3 + 1);
+
+        Ok(())
+    }
+
+    // 不经过 TreeHasher 的 keccak 参考实现：值 -> chunk hash -> 段根 -> 每 16 个一组向上
+    fn reference_keccak_root(values: &[B256]) -> B256 {
+        use sha3::{Digest, Keccak256};
+        let keccak = |parts: &[B256]| {
+            let mut hasher = Keccak256::new();
+            for part in parts {
+                hasher.update(part.as_slice());
+            }
+            B256::from_slice(&hasher.finalize())
+        };
+        let chunk_hashes: Vec<B256> = values.iter().map(|value| keccak(core::slice::from_ref(value))).collect();
+        let mut level: Vec<B256> = chunk_hashes.chunks(SEGMENT_SIZE).map(keccak).collect();
+        while level.len() > 1 {
+            level = level.chunks(SEGMENT_SIZE).map(keccak).collect();
+        }
+        level[0]
+    }
+
+    fn tree_entries(count: u32) -> Vec<(B256, B256)> {
+        let word = |n: u32| B256::from(U256::from(n).to_be_bytes::<32>());
+        (0..count).map(|i| (word(i), word(i * 7 + 1))).collect()
+    }
+
+    #[test]
+    fn test_default_hasher_matches_keccak_reference() -> Result<(), BoxError> {
+        let entries = tree_entries(300);
+        let values: Vec<B256> = entries.iter().map(|(_, value)| *value).collect();
+        let expected = reference_keccak_root(&values);
+
+        let mut default_vc = SegmentVC::new(16);
+        assert_eq!(default_vc.hasher(), TreeHashAlgorithm::Keccak);
+        assert_eq!(default_vc.insert_batch(entries.clone())?, expected);
+
+        let mut keccak_vc = SegmentVC::new_hash_only(16).with_hasher(TreeHashAlgorithm::Keccak);
+        assert_eq!(keccak_vc.insert_batch(entries.clone())?, expected);
+
+        let mut sha_vc = SegmentVC::new(16).with_hasher(TreeHashAlgorithm::Sha256);
+        let sha_root = sha_vc.insert_batch(entries)?;
+        assert_ne!(sha_root, expected);
+
+        // 不构造树的参考函数
+        assert_eq!(compute_root_from_values(&values), expected);
+        assert_eq!(compute_root_with(TreeHashAlgorithm::Sha256, &values, false), sha_root);
+        assert_eq!(compute_root_from_values(&[]), B256::ZERO);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proof_hasher_mismatch() -> Result<(), BoxError> {
+        let entries = tree_entries(40);
+        let key = entries[20].0;
+
+        let mut keccak_vc = SegmentVC::new(16);
+        keccak_vc.insert_batch(entries.clone())?;
+        let mut sha_vc = SegmentVC::new(16).with_hasher(TreeHashAlgorithm::Sha256);
+        sha_vc.insert_batch(entries)?;
+
+        let keccak_proof = keccak_vc.generate_proof(key)?;
+        let sha_proof = sha_vc.generate_proof(key)?;
+        assert_eq!(keccak_proof.hasher, TreeHashAlgorithm::Keccak);
+        assert_eq!(sha_proof.hasher, TreeHashAlgorithm::Sha256);
+
+        // 按树的算法验证通过，换成另一种算法失败；verify 固定为 Keccak
+        assert!(keccak_proof.verify()?);
+        assert!(!sha_proof.verify()?);
+        assert!(sha_proof.verify_with(TreeHashAlgorithm::Sha256, false)?);
+        assert!(sha_vc.verify_inclusion(&sha_proof)?);
+        assert!(sha_vc.generate_proof_ref(key)?.verify_with(TreeHashAlgorithm::Sha256, false));
+        assert!(!sha_vc.generate_proof_ref(key)?.verify());
+        assert!(!keccak_proof.verify_with(TreeHashAlgorithm::Sha256, false)?);
+        assert!(!sha_proof.verify_with(TreeHashAlgorithm::Keccak, false)?);
+
+        // 证明方改写记录的算法不影响验证方选择的算法
+        let mut relabeled = keccak_proof.clone();
+        relabeled.hasher = TreeHashAlgorithm::Sha256;
+        assert!(relabeled.verify()?);
+        assert!(!relabeled.verify_with(TreeHashAlgorithm::Sha256, false)?);
+        let mut relabeled = sha_proof.clone();
+        relabeled.hasher = TreeHashAlgorithm::Keccak;
+        assert!(!relabeled.verify()?);
+        assert!(relabeled.verify_with(TreeHashAlgorithm::Sha256, false)?);
+
+        // 旧 JSON 没有 hasher 字段时为 Keccak
+        let mut json = serde_json::to_value(&keccak_proof)?;
+        json.as_object_mut().unwrap().remove("hasher");
+        let legacy: MerkleProof = serde_json::from_value(json)?;
+        assert_eq!(legacy, keccak_proof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_padded_segment_roots() -> Result<(), BoxError> {
+        let key = |i: u32| B256::left_padding_from(&i.to_be_bytes());
+        let value = |i: u32| B256::left_padding_from(&(i + 1000).to_be_bytes());
+
+        // 单段：根总是 16 个位置的哈希，未填充的位置为零，与段的填充程度无关
+        let mut vc = SegmentVC::new(16).with_padded(true);
+        let mut hash_only = SegmentVC::new_hash_only(16).with_padded(true);
+        for i in 0..NODE_WIDTH as u32 {
+            let root = vc.insert(key(i), value(i))?;
+            assert_eq!(hash_only.insert(key(i), value(i))?, root);
+
+            let mut slots = vec![B256::ZERO; NODE_WIDTH];
+            for j in 0..=i {
+                slots[j as usize] = hash_value(TreeHashAlgorithm::Keccak, &value(j));
+            }
+            assert_eq!(root, hash_chunks(TreeHashAlgorithm::Keccak, &slots, false));
+
+            let mut batch = SegmentVC::new(16).with_padded(true);
+            assert_eq!(batch.insert_batch((0..=i).map(|j| (key(j), value(j))).collect())?, root);
+        }
+
+        // 多段多层：证明记录 padded，验证方按树的配置验证，根与默认模式不同
+        let entries: Vec<(B256, B256)> = (0..300u32).map(|i| (key(i), value(i))).collect();
+        let mut padded = SegmentVC::new(16).with_padded(true);
+        let mut legacy = SegmentVC::new(16);
+        assert_ne!(padded.insert_batch(entries.clone())?, legacy.insert_batch(entries.clone())?);
+        for (key, _) in entries.iter().step_by(37) {
+            let proof = padded.generate_proof(*key)?;
+            assert!(proof.padded);
+            assert!(proof.verify_with(TreeHashAlgorithm::Keccak, true)?);
+            assert!(padded.verify_inclusion(&proof)?);
+            assert!(padded.generate_proof_ref(*key)?.verify_with(TreeHashAlgorithm::Keccak, true));
+
+            // 验证方的填充方式不同时验证失败，证明中的 padded 不影响结果
+            assert!(!proof.verify()?);
+            let mut unpadded = proof.clone();
+            unpadded.padded = false;
+            assert!(unpadded.verify_with(TreeHashAlgorithm::Keccak, true)?);
+            assert!(!unpadded.verify()?);
+            assert!(!legacy.generate_proof(*key)?.padded);
+        }
+
+        // JSON 只在填充时输出 padded
+        let proof = padded.generate_proof(key(1))?;
+        let json = serde_json::to_value(&proof)?;
+        assert_eq!(json["padded"], serde_json::Value::Bool(true));
+        assert_eq!(serde_json::from_value::<MerkleProof>(json)?, proof);
+        assert!(serde_json::to_value(legacy.generate_proof(key(1))?)?.get("padded").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_padded_removes_single_chunk_special_case() -> Result<(), BoxError> {
+        let key = B256::repeat_byte(1);
+        let value = B256::repeat_byte(2);
+        let chunk_hash = hash_value(TreeHashAlgorithm::Keccak, &value);
+
+        // 只有一个元素时默认模式的段根是 keccak(chunk_hash)，而 verify 的特例接受根等于 chunk_hash 的证明
+        let mut legacy = SegmentVC::new(16);
+        assert_eq!(legacy.insert(key, value)?, hash_chunks(TreeHashAlgorithm::Keccak, &[chunk_hash], false));
+        let mut forged = legacy.generate_proof(key)?;
+        forged.level_proofs.clear();
+        forged.root_hash = chunk_hash;
+        assert!(forged.verify()?);
+
+        // 填充模式下段根与 chunk 级的哈希不会重合，同样的证明被拒绝
+        let mut padded = SegmentVC::new(16).with_padded(true);
+        let root = padded.insert(key, value)?;
+        assert_ne!(root, chunk_hash);
+        assert_ne!(root, hash_chunks(TreeHashAlgorithm::Keccak, &[chunk_hash], false));
+        assert!(padded.generate_proof(key)?.verify_with(TreeHashAlgorithm::Keccak, true)?);
+        assert!(!forged.verify_with(TreeHashAlgorithm::Keccak, true)?);
+        assert!(!padded.verify_inclusion(&forged)?);
+        forged.padded = true;
+        assert!(!forged.verify_with(TreeHashAlgorithm::Keccak, true)?);
+
+        // 伪造的证明声称不填充也无法绕过，验证方决定填充方式
+        let proof_ref = padded.generate_proof_ref(key)?;
+        let forged_ref =
+            MerkleProofRef { root_hash: chunk_hash, levels: Vec::new(), padded: false, ..proof_ref.clone() };
+        assert!(proof_ref.verify_with(TreeHashAlgorithm::Keccak, true));
+        assert!(!forged_ref.verify_with(TreeHashAlgorithm::Keccak, true));
+        Ok(())
+    }
+
+    const PROOF_JSON: &str = include_str!("../../tests/fixtures/merkle_proof.json");
+
+    fn json_fixture_proof() -> MerkleProof {
+        MerkleProof {
+            value_proof: ValueProof { value: B256::repeat_byte(0x11), chunk_hash: B256::repeat_byte(0x22) },
+            segment_proof: SegmentProof {
+                chunk_index: 3,
+                siblings: vec![B256::repeat_byte(0x33), B256::repeat_byte(0x44)],
+            },
+            level_proofs: vec![LevelProof { level: 0, node_index: 17, siblings: vec![B256::repeat_byte(0x55)] }],
+            root_hash: B256::repeat_byte(0x66),
+            hasher: TreeHashAlgorithm::Sha256,
+            padded: false,
+        }
+    }
+
+    #[test]
+    fn test_proof_json_schema() -> Result<(), BoxError> {
+        let proof = json_fixture_proof();
+        assert_eq!(serde_json::to_string_pretty(&proof)?, PROOF_JSON.trim_end());
+        assert_eq!(serde_json::from_str::<MerkleProof>(PROOF_JSON)?, proof);
+
+        // bincode 仍使用紧凑的派生表示
+        let bytes = bincode::serialize(&proof)?;
+        assert_eq!(bincode::deserialize::<MerkleProof>(&bytes)?, proof);
+        assert!(bytes.len() < PROOF_JSON.len() / 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proof_json_rejects_malformed() -> Result<(), BoxError> {
+        let fixture: serde_json::Value = serde_json::from_str(PROOF_JSON)?;
+        let parse = |edit: &dyn Fn(&mut serde_json::Value)| {
+            let mut json = fixture.clone();
+            edit(&mut json);
+            serde_json::from_value::<MerkleProof>(json)
+        };
+        assert!(parse(&|_| {}).is_ok());
+
+        // 哈希长度错误
+        let short = format!("0x{}", "11".repeat(31));
+        let long = format!("0x{}", "11".repeat(33));
+        let array = serde_json::to_value([0x55u8; 32])?;
+        assert!(parse(&|json| json["root_hash"] = short.clone().into()).is_err());
+        assert!(parse(&|json| json["segment_proof"]["siblings"][0] = long.clone().into()).is_err());
+        assert!(parse(&|json| json["level_proofs"][0]["siblings"][0] = array.clone()).is_err());
+
+        // 未知字段
+        assert!(parse(&|json| json["extra"] = 1.into()).is_err());
+        assert!(parse(&|json| json["value_proof"]["extra"] = 1.into()).is_err());
+        assert!(parse(&|json| json["level_proofs"][0]["extra"] = 1.into()).is_err());
+
+        // 索引必须是 u32
+        assert!(parse(&|json| json["level_proofs"][0]["node_index"] = (u64::from(u32::MAX) + 1).into()).is_err());
+        assert!(parse(&|json| json["segment_proof"]["chunk_index"] = (-1).into()).is_err());
+
+        #[cfg(target_pointer_width = "64")]
+        {
+            let mut proof = json_fixture_proof();
+            proof.level_proofs[0].node_index = u32::MAX as usize + 1;
+            assert!(serde_json::to_string(&proof).is_err());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::collection::{btree_map, vec};
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+    use std::collections::BTreeMap;
+
+    // 键生成器：小整数映射为 B256，收缩时趋向少量、较小的键，便于定位问题
+    fn key_strategy() -> impl Strategy<Value = B256> {
+        prop_oneof![
+            3 => (0u64..4096).prop_map(|i| B256::left_padding_from(&i.to_be_bytes())),
+            1 => any::<[u8; 32]>().prop_map(B256::from),
+        ]
+    }
+
+    // 零值与其他值一样插入，单独给出一定的比例
+    fn value_strategy() -> impl Strategy<Value = B256> {
+        prop_oneof![
+            1 => Just(B256::ZERO),
+            9 => (1u64..u64::MAX).prop_map(|i| B256::left_padding_from(&i.to_be_bytes())),
+        ]
+    }
+
+    // 不重复的键值对，顺序随机
+    fn entries_strategy() -> impl Strategy<Value = Vec<(B256, B256)>> {
+        btree_map(key_strategy(), value_strategy(), 1..400)
+            .prop_flat_map(|map| Just(map.into_iter().collect::<Vec<_>>()).prop_shuffle())
+    }
+
+    fn build_sequential(entries: &[(B256, B256)]) -> Result<SegmentVC, BoxError> {
+        let mut vc = SegmentVC::new(16);
+        for (key, value) in entries {
+            vc.insert(*key, *value)?;
+        }
+        Ok(vc)
+    }
+
+    fn build_batch(entries: &[(B256, B256)]) -> Result<SegmentVC, BoxError> {
+        let mut vc = SegmentVC::new(16);
+        vc.insert_batch(entries.to_vec())?;
+        Ok(vc)
+    }
+
+    // 把 entries 分成 modes.len() 段，true 的段用 insert_batch，false 的段逐个插入
+    fn build_interleaved(entries: &[(B256, B256)], modes: &[bool]) -> Result<SegmentVC, BoxError> {
+        let mut vc = SegmentVC::new(16);
+        let chunk_len = (entries.len() + modes.len() - 1) / modes.len();
+        for (chunk, batch) in entries.chunks(chunk_len.max(1)).zip(modes.iter().cycle()) {
+            if *batch {
+                vc.insert_batch(chunk.to_vec())?;
+            } else {
+                for (key, value) in chunk {
+                    vc.insert(*key, *value)?;
+                }
+            }
+        }
+        Ok(vc)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn compute_root_matches_insert_batch(
+            values in vec(any::<[u8; 32]>().prop_map(B256::from), 1..600),
+            padded in any::<bool>(),
+        ) {
+            let entries: Vec<(B256, B256)> = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (B256::left_padding_from(&(i as u64).to_be_bytes()), *value))
+                .collect();
+            let mut vc = SegmentVC::new(16).with_padded(padded);
+            let root = vc.insert_batch(entries).unwrap();
+
+            let expected = if padded { compute_root_from_values_padded(&values) } else { compute_root_from_values(&values) };
+            prop_assert_eq!(expected, root);
+            prop_assert_eq!(compute_root_with(TreeHashAlgorithm::Keccak, &values, padded), root);
+        }
+
+        #[test]
+        fn root_is_independent_of_build_mode(
+            entries in entries_strategy(),
+            modes in vec(any::<bool>(), 1..8),
+        ) {
+            let sequential = build_sequential(&entries).unwrap();
+            let batch = build_batch(&entries).unwrap();
+            let interleaved = build_interleaved(&entries, &modes).unwrap();
+
+            prop_assert_eq!(sequential.get_root_hash().unwrap(), batch.get_root_hash().unwrap());
+            prop_assert_eq!(sequential.get_root_hash().unwrap(), interleaved.get_root_hash().unwrap());
+        }
+
+        #[test]
+        fn get_value_matches_reference_model(
+            entries in entries_strategy(),
+            modes in vec(any::<bool>(), 1..8),
+            absent in key_strategy(),
+        ) {
+            let model: BTreeMap<B256, B256> = entries.iter().copied().collect();
+            let vc = build_interleaved(&entries, &modes).unwrap();
+
+            for (key, value) in &model {
+                prop_assert_eq!(vc.get_value(*key).unwrap(), *value);
+            }
+            if !model.contains_key(&absent) {
+                prop_assert!(vc.get_value(absent).is_err());
+                prop_assert!(vc.generate_proof(absent).is_err());
+            }
+        }
+
+        #[test]
+        fn proofs_are_sound(
+            entries in entries_strategy(),
+            samples in vec(any::<Index>(), 1..8),
+            tamper in any::<[u8; 32]>(),
+        ) {
+            let vc = build_batch(&entries).unwrap();
+            let tamper = B256::from(tamper);
+
+            for sample in samples {
+                let (key, value) = *sample.get(&entries);
+                let proof = vc.generate_proof(key).unwrap();
+                prop_assert_eq!(proof.value_proof.value, value);
+                prop_assert_eq!(proof.root_hash, vc.get_root_hash().unwrap());
+                prop_assert!(proof.verify().unwrap());
+
+                // 篡改值
+                if tamper != value {
+                    let mut mutated = proof.clone();
+                    mutated.value_proof.value = tamper;
+                    prop_assert!(!mutated.verify().unwrap());
+                }
+
+                // 篡改段内兄弟节点
+                if let Some(sibling) = proof.segment_proof.siblings.first() {
+                    if *sibling != tamper {
+                        let mut mutated = proof.clone();
+                        mutated.segment_proof.siblings[0] = tamper;
+                        prop_assert!(!mutated.verify().unwrap());
+                    }
+                }
+
+                // 篡改上层兄弟节点
+                if let Some(level) = proof.level_proofs.iter().position(|l| !l.siblings.is_empty()) {
+                    if proof.level_proofs[level].siblings[0] != tamper {
+                        let mut mutated = proof.clone();
+                        mutated.level_proofs[level].siblings[0] = tamper;
+                        prop_assert!(!mutated.verify().unwrap());
+                    }
+                }
+
+                // 篡改根
+                if proof.root_hash != tamper {
+                    let mut mutated = proof.clone();
+                    mutated.root_hash = tamper;
+                    prop_assert!(!mutated.verify().unwrap());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "trace-hashing"))]
+mod test_trace_hashing {
+    use super::*;
+    use crate::models::u256_to_key;
+    use std::sync::{Arc, Mutex};
+    use tracing::{span, Event, Level, Metadata, Subscriber};
+
+    // 只记录事件级别的最小 Subscriber
+    struct LevelRecorder(Arc<Mutex<Vec<Level>>>);
+
+    impl Subscriber for LevelRecorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(1)
+        }
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+        fn event(&self, event: &Event<'_>) {
+            self.0.lock().unwrap().push(*event.metadata().level());
+        }
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    #[test]
+    fn test_verify_emits_per_level_trace_events() -> Result<(), BoxError> {
+        // 300 个元素分成 19 段，证明有两层
+        let mut vc = SegmentVC::new(16);
+        for i in 1..=300u64 {
+            vc.insert(u256_to_key(U256::from(i)), B256::repeat_byte(7))?;
+        }
+        let proof = vc.generate_proof(u256_to_key(U256::from(42)))?;
+        assert_eq!(proof.level_proofs.len(), 2);
+
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let verified = tracing::subscriber::with_default(LevelRecorder(levels.clone()), || proof.verify())?;
+        assert!(verified);
+
+        let levels = levels.lock().unwrap();
+        // value 和 segment 各一条，每层一条，最后的根比较为 debug
+        let traces = levels.iter().filter(|level| **level == Level::TRACE).count();
+        assert_eq!(traces, 2 + proof.level_proofs.len());
+        assert_eq!(levels.iter().filter(|level| **level == Level::DEBUG).count(), 1);
+        // info 及以上没有任何事件
+        assert!(levels.iter().all(|level| *level == Level::TRACE || *level == Level::DEBUG));
+        Ok(())
+    }
 }
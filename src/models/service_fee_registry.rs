@@ -0,0 +1,336 @@
+use alloy_primitives::B256;
+use sha3::{Digest, Keccak256};
+use super::ServiceFeeConfig;
+use crate::BoxError;
+
+/// 费率基数，system_fee_rate 与 proxy_fee_rate 之和不能超过它
+pub const FEE_RATE_BASE: u16 = 10000;
+
+/// 服务费率表，按 serv_id 升序保存，serv_id 唯一
+///
+/// serv_ids_root = keccak256(serv_id(4) ‖ system_fee_rate(2) ‖ proxy_fee_rate(2) ‖ ...)，
+/// 按 serv_id 升序拼接所有配置，与 ReceiptsProfitCalculator 使用的哈希相同。
+///
+/// 存在分级费率时改为 keccak256(0x01 ‖ 每个配置的 serv_id ‖ system_fee_rate ‖ proxy_fee_rate ‖
+/// 级数(4) ‖ 各级 threshold(32) ‖ system_fee_rate(2) ‖ proxy_fee_rate(2))，
+/// 长度不是 8 的倍数，不会与只有单一费率的编码相同
+#[derive(Debug, Clone, Default)]
+pub struct ServiceFeeRegistry {
+    configs: Vec<ServiceFeeConfig>,
+}
+
+/// 单个配置包含在 serv_ids_root 中的证明
+///
+/// 根是所有配置的扁平哈希，所以证明就是排在它前后的其他配置
+#[derive(Debug, Clone)]
+pub struct ServiceFeeProof {
+    pub preceding: Vec<ServiceFeeConfig>,
+    pub following: Vec<ServiceFeeConfig>,
+}
+
+impl ServiceFeeRegistry {
+    pub fn new() -> Self {
+        Self { configs: Vec::new() }
+    }
+
+    /// 从一组配置构建，serv_id 重复或费率无效时报错
+    pub fn from_configs(configs: &[ServiceFeeConfig]) -> Result<Self, BoxError> {
+        let mut registry = Self::new();
+        for config in configs {
+            registry.insert(config.clone())?;
+        }
+        Ok(registry)
+    }
+
+    pub fn insert(&mut self, config: ServiceFeeConfig) -> Result<(), BoxError> {
+        config.validate()?;
+        match self.position(config.serv_id) {
+            Ok(_) => Err(format!("Duplicate serv_id: {}", config.serv_id).into()),
+            Err(position) => {
+                self.configs.insert(position, config);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn update(&mut self, config: ServiceFeeConfig) -> Result<(), BoxError> {
+        config.validate()?;
+        let position = self
+            .position(config.serv_id)
+            .map_err(|_| format!("Service config not found for serv_id: {}", config.serv_id))?;
+        self.configs[position] = config;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, serv_id: u32) -> Option<ServiceFeeConfig> {
+        self.position(serv_id)
+            .ok()
+            .map(|position| self.configs.remove(position))
+    }
+
+    pub fn get(&self, serv_id: u32) -> Option<&ServiceFeeConfig> {
+        self.position(serv_id).ok().map(|position| &self.configs[position])
+    }
+
+    /// 按 serv_id 升序
+    pub fn configs(&self) -> &[ServiceFeeConfig] {
+        &self.configs
+    }
+
+    pub fn len(&self) -> usize {
+        self.configs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    pub fn root(&self) -> B256 {
+        hash_sorted(&self.configs)
+    }
+
+    /// 任意一组配置的 serv_ids_root，先按 serv_id 排序，不检查重复
+    pub fn root_of(configs: &[ServiceFeeConfig]) -> B256 {
+        let mut sorted_configs = configs.to_vec();
+        sorted_configs.sort_by(|a, b| a.serv_id.cmp(&b.serv_id));
+        hash_sorted(&sorted_configs)
+    }
+
+    pub fn proof_for(&self, serv_id: u32) -> Result<ServiceFeeProof, BoxError> {
+        let position = self
+            .position(serv_id)
+            .map_err(|_| format!("Service config not found for serv_id: {}", serv_id))?;
+        Ok(ServiceFeeProof {
+            preceding: self.configs[..position].to_vec(),
+            following: self.configs[position + 1..].to_vec(),
+        })
+    }
+
+    /// 验证配置包含在 root 中
+    ///
+    /// 要求 serv_id 严格递增，这样同一个 serv_id 不可能在根中出现两次、得到两种费率
+    pub fn verify(root: B256, config: &ServiceFeeConfig, proof: &ServiceFeeProof) -> bool {
+        let mut all = Vec::with_capacity(proof.preceding.len() + proof.following.len() + 1);
+        all.extend_from_slice(&proof.preceding);
+        all.push(config.clone());
+        all.extend_from_slice(&proof.following);
+
+        all.windows(2).all(|pair| pair[0].serv_id < pair[1].serv_id) && hash_sorted(&all) == root
+    }
+
+    fn position(&self, serv_id: u32) -> Result<usize, usize> {
+        self.configs.binary_search_by(|config| config.serv_id.cmp(&serv_id))
+    }
+}
+
+// 分级费率编码的前缀
+const TIERED_TAG: u8 = 0x01;
+
+impl ServiceFeeConfig {
+    /// 基础费率和每一级费率之和都不能超过 FEE_RATE_BASE，threshold 必须严格递增
+    pub fn validate(&self) -> Result<(), BoxError> {
+        validate_rates(self.serv_id, self.system_fee_rate, self.proxy_fee_rate)?;
+        for tier in &self.tiers {
+            validate_rates(self.serv_id, tier.system_fee_rate, tier.proxy_fee_rate)?;
+        }
+        if let Some(pair) = self.tiers.windows(2).find(|pair| pair[0].threshold >= pair[1].threshold) {
+            return Err(format!(
+                "Fee tier thresholds for serv_id {} must be strictly increasing: {} then {}",
+                self.serv_id, pair[0].threshold, pair[1].threshold
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn validate_rates(serv_id: u32, system_fee_rate: u16, proxy_fee_rate: u16) -> Result<(), BoxError> {
+    let total = system_fee_rate as u32 + proxy_fee_rate as u32;
+    if total > FEE_RATE_BASE as u32 {
+        return Err(format!(
+            "Invalid fee rates for serv_id {}: system {} + proxy {} exceeds {}",
+            serv_id, system_fee_rate, proxy_fee_rate, FEE_RATE_BASE
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn hash_sorted(sorted_configs: &[ServiceFeeConfig]) -> B256 {
+    let tiered = sorted_configs.iter().any(|config| !config.tiers.is_empty());
+    let mut hasher = Keccak256::new();
+    if tiered {
+        hasher.update([TIERED_TAG]);
+    }
+    for config in sorted_configs {
+        // 打包服务配置数据
+        hasher.update(config.serv_id.to_be_bytes());
+        hasher.update(config.system_fee_rate.to_be_bytes());
+        hasher.update(config.proxy_fee_rate.to_be_bytes());
+        if tiered {
+            hasher.update((config.tiers.len() as u32).to_be_bytes());
+            for tier in &config.tiers {
+                hasher.update(tier.threshold.to_be_bytes::<32>());
+                hasher.update(tier.system_fee_rate.to_be_bytes());
+                hasher.update(tier.proxy_fee_rate.to_be_bytes());
+            }
+        }
+    }
+    B256::from_slice(&hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FeeTier;
+    use alloy_primitives::U256;
+
+    fn config(serv_id: u32, system_fee_rate: u16, proxy_fee_rate: u16) -> ServiceFeeConfig {
+        ServiceFeeConfig {
+            serv_id,
+            system_fee_rate,
+            proxy_fee_rate,
+            tiers: Vec::new(),
+        }
+    }
+
+    // 抽取前 ReceiptsProfitCalculator 中的实现
+    fn legacy_serv_ids_root(service_configs: &[ServiceFeeConfig]) -> B256 {
+        let mut sorted_configs = service_configs.to_vec();
+        sorted_configs.sort_by(|a, b| a.serv_id.cmp(&b.serv_id));
+
+        let mut hasher = Keccak256::new();
+        for config in &sorted_configs {
+            hasher.update(&config.serv_id.to_be_bytes());
+            hasher.update(&config.system_fee_rate.to_be_bytes());
+            hasher.update(&config.proxy_fee_rate.to_be_bytes());
+        }
+        B256::from_slice(&hasher.finalize())
+    }
+
+    #[test]
+    fn test_root_matches_legacy() -> Result<(), BoxError> {
+        let inputs = vec![
+            vec![],
+            vec![config(1, 500, 1000)],
+            vec![config(2, 300, 700), config(1, 500, 1000)],
+            (1..=20).rev().map(|i| config(i * 3, i as u16 * 10, i as u16 * 20)).collect(),
+        ];
+        for configs in inputs {
+            let registry = ServiceFeeRegistry::from_configs(&configs)?;
+            assert_eq!(registry.root(), legacy_serv_ids_root(&configs));
+            assert_eq!(ServiceFeeRegistry::root_of(&configs), legacy_serv_ids_root(&configs));
+            assert_eq!(
+                crate::receipts::profit_calculator::calculate_serv_ids_root(&configs)?,
+                legacy_serv_ids_root(&configs)
+            );
+        }
+        Ok(())
+    }
+
+    fn tier(threshold: u64, system_fee_rate: u16, proxy_fee_rate: u16) -> FeeTier {
+        FeeTier { threshold: U256::from(threshold), system_fee_rate, proxy_fee_rate }
+    }
+
+    #[test]
+    fn test_tiered_roots() -> Result<(), BoxError> {
+        let flat = vec![config(1, 500, 1000), config(2, 300, 700)];
+        let flat_root = ServiceFeeRegistry::root_of(&flat);
+        assert_eq!(flat_root, legacy_serv_ids_root(&flat));
+
+        // 分级费率改变根，即使只有一级且费率与基础费率相同
+        let mut tiered = flat.clone();
+        tiered[0].tiers = vec![tier(0, 500, 1000)];
+        let single_tier_root = ServiceFeeRegistry::root_of(&tiered);
+        assert_ne!(single_tier_root, flat_root);
+
+        tiered[0].tiers = vec![tier(1000, 500, 500), tier(5000, 500, 0)];
+        let root = ServiceFeeRegistry::from_configs(&tiered)?.root();
+        assert_ne!(root, single_tier_root);
+        assert_eq!(ServiceFeeRegistry::root_of(&tiered), root);
+
+        tiered[0].tiers[1].threshold = U256::from(5001);
+        assert_ne!(ServiceFeeRegistry::root_of(&tiered), root);
+
+        // 分级放到另一个服务上
+        let mut moved = flat.clone();
+        moved[1].tiers = vec![tier(1000, 500, 500), tier(5000, 500, 0)];
+        assert_ne!(ServiceFeeRegistry::root_of(&moved), root);
+
+        // 分级的证明与单一费率相同
+        let registry = ServiceFeeRegistry::from_configs(&moved)?;
+        let proof = registry.proof_for(2)?;
+        assert!(ServiceFeeRegistry::verify(registry.root(), &moved[1], &proof));
+        assert!(!ServiceFeeRegistry::verify(registry.root(), &flat[1], &proof));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tier_validation() {
+        let tiered = |tiers| config(1, 500, 1000).with_tiers(tiers);
+        assert!(tiered(vec![tier(10, 500, 500), tier(20, 500, 0)]).validate().is_ok());
+
+        // threshold 必须严格递增
+        assert!(tiered(vec![tier(20, 500, 500), tier(10, 500, 0)]).validate().is_err());
+        assert!(tiered(vec![tier(10, 500, 500), tier(10, 500, 0)]).validate().is_err());
+        // 每一级的费率之和不能超过基数
+        assert!(tiered(vec![tier(10, 6000, 4001)]).validate().is_err());
+
+        let mut registry = ServiceFeeRegistry::new();
+        assert!(registry.insert(tiered(vec![tier(20, 500, 500), tier(10, 500, 0)])).is_err());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_insert_update_remove() -> Result<(), BoxError> {
+        let mut registry = ServiceFeeRegistry::new();
+        registry.insert(config(5, 100, 200))?;
+        registry.insert(config(1, 100, 200))?;
+        registry.insert(config(3, 100, 200))?;
+        let ids: Vec<u32> = registry.configs().iter().map(|c| c.serv_id).collect();
+        assert_eq!(ids, vec![1, 3, 5]);
+
+        // 重复和无效费率
+        assert!(registry.insert(config(3, 1, 1)).is_err());
+        assert!(registry.insert(config(7, 6000, 4001)).is_err());
+        assert!(registry.insert(config(7, 6000, 4000)).is_ok());
+        assert!(registry.update(config(3, 10000, 1)).is_err());
+        assert!(registry.update(config(99, 1, 1)).is_err());
+
+        let before = registry.root();
+        registry.update(config(3, 111, 222))?;
+        assert_eq!(registry.get(3).unwrap().system_fee_rate, 111);
+        assert_ne!(registry.root(), before);
+
+        assert_eq!(registry.remove(1).map(|c| c.serv_id), Some(1));
+        assert!(registry.remove(1).is_none());
+        assert_eq!(registry.len(), 3);
+        assert_eq!(registry.root(), legacy_serv_ids_root(registry.configs()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_proofs() -> Result<(), BoxError> {
+        let registry = ServiceFeeRegistry::from_configs(&[config(1, 500, 1000), config(2, 300, 700), config(4, 0, 0)])?;
+        let root = registry.root();
+        for config in registry.configs() {
+            let proof = registry.proof_for(config.serv_id)?;
+            assert!(ServiceFeeRegistry::verify(root, config, &proof));
+        }
+        assert!(registry.proof_for(3).is_err());
+
+        // 修改费率、错误的根
+        let proof = registry.proof_for(2)?;
+        assert!(!ServiceFeeRegistry::verify(root, &config(2, 301, 700), &proof));
+        assert!(!ServiceFeeRegistry::verify(B256::ZERO, &config(2, 300, 700), &proof));
+
+        // 把配置放到错误的位置，即使哈希相同也拒绝
+        let misplaced = ServiceFeeProof {
+            preceding: vec![],
+            following: vec![config(1, 500, 1000)],
+        };
+        let unsorted_root = hash_sorted(&[config(2, 300, 700), config(1, 500, 1000)]);
+        assert!(!ServiceFeeRegistry::verify(unsorted_root, &config(2, 300, 700), &misplaced));
+        Ok(())
+    }
+}
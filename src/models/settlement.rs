@@ -204,7 +204,7 @@ impl SettlementManager {
         proof: &MerkleProof,
     ) -> Result<bool, BoxError> {
         let proxy_key = H256::from_slice(&proxy);
-        self.settle_of_proxy.verify(proof)
+        self.settle_of_proxy.verify_inclusion(proof)
     }
 
     pub fn verify_receiver_settlement(
@@ -240,7 +240,7 @@ impl SettlementManager {
     pub fn get_proxy_stats(&self, proxy: Address) -> Result<ProxyStats, BoxError> {
         let total_size = self.settle_of_proxy.len()?;
         let current_root = self.settle_of_proxy.get_root_hash()?;
-        let (_, history_size, has_history) = self.settle_of_proxy.get_history_stats()?;
+        let (_, history_size, has_history) = self.settle_of_proxy.history_stats();
         
         let proxy_history = self.proxy_settle_history.get(&proxy);
         
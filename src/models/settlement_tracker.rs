@@ -0,0 +1,184 @@
+//! SettlementTracker 的实现：记录一个代理已经提交过的 settlement_id
+//!
+//! track_settlement 的 id 为 settlement_id 按 u256_to_key 的逆变换得到的 U256，
+//! 其余方法直接使用 B256 形式的 settlement_id
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use alloy_primitives::{B256, U256};
+
+use super::{u256_to_key, CircularHashStore, HashStoreError, SettlementTracker};
+
+/// 保留全部记录，get_all_settlement_ids 按 settlement_id 升序
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySettlementTracker {
+    settlements: BTreeMap<B256, B256>,
+}
+
+impl InMemorySettlementTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SettlementTracker for InMemorySettlementTracker {
+    fn track_settlement(&mut self, id: U256, hash: B256) {
+        self.settlements.insert(u256_to_key(id), hash);
+    }
+
+    fn get_settlement_hash(&self, settlement_id: B256) -> Option<B256> {
+        self.settlements.get(&settlement_id).copied()
+    }
+
+    fn has_settlement(&self, settlement_id: B256) -> bool {
+        self.settlements.contains_key(&settlement_id)
+    }
+
+    fn get_all_settlement_ids(&self) -> Vec<B256> {
+        self.settlements.keys().copied().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.settlements.len()
+    }
+
+    fn clear(&mut self) {
+        self.settlements.clear();
+    }
+}
+
+/// 只保留最近 capacity 个记录，按 CircularHashStore 的规则把最旧的 settlement_id 挤入历史哈希
+///
+/// 被挤出的 settlement_id 不再被 has_settlement 识别，可以用 store().check_hash 加历史证明验证；
+/// get_all_settlement_ids 按记录顺序从旧到新。settlement_id 为零时不记录（CircularHashStore 拒绝空哈希），
+/// 重复记录同一 settlement_id 只更新哈希，不改变顺序
+#[derive(Debug, Clone)]
+pub struct CircularSettlementTracker {
+    store: CircularHashStore,
+    hashes: BTreeMap<B256, B256>, // store 中当前保留的 settlement_id 对应的哈希
+}
+
+impl CircularSettlementTracker {
+    pub fn new(capacity: usize) -> Result<Self, HashStoreError> {
+        if capacity == 0 {
+            return Err(HashStoreError::ZeroCapacity);
+        }
+        Ok(Self {
+            store: CircularHashStore::new(capacity),
+            hashes: BTreeMap::new(),
+        })
+    }
+
+    /// 保存 settlement_id 的环形存储，包括被挤出记录的历史哈希
+    pub fn store(&self) -> &CircularHashStore {
+        &self.store
+    }
+}
+
+impl SettlementTracker for CircularSettlementTracker {
+    fn track_settlement(&mut self, id: U256, hash: B256) {
+        let settlement_id = u256_to_key(id);
+        if settlement_id == CircularHashStore::EMPTY_HASH {
+            return;
+        }
+        if let Some(existing) = self.hashes.get_mut(&settlement_id) {
+            *existing = hash;
+            return;
+        }
+
+        // add_hash 会挤出最旧的 settlement_id，同时删除它的哈希
+        if self.store.current_size() == self.store.capacity() {
+            if let Some(oldest) = self.store.get_oldest_hash() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        if self.store.add_hash(settlement_id).is_ok() {
+            self.hashes.insert(settlement_id, hash);
+        }
+    }
+
+    fn get_settlement_hash(&self, settlement_id: B256) -> Option<B256> {
+        self.hashes.get(&settlement_id).copied()
+    }
+
+    fn has_settlement(&self, settlement_id: B256) -> bool {
+        self.hashes.contains_key(&settlement_id)
+    }
+
+    fn get_all_settlement_ids(&self) -> Vec<B256> {
+        self.store.get_full_state().3
+    }
+
+    fn len(&self) -> usize {
+        self.store.current_size()
+    }
+
+    fn clear(&mut self) {
+        self.store = CircularHashStore::new(self.store.capacity());
+        self.hashes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::key_to_u256;
+
+    fn track(tracker: &mut dyn SettlementTracker, byte: u8) {
+        tracker.track_settlement(key_to_u256(B256::repeat_byte(byte)), B256::repeat_byte(byte ^ 0xff));
+    }
+
+    #[test]
+    fn test_in_memory_tracker() {
+        let mut tracker = InMemorySettlementTracker::new();
+        assert!(tracker.is_empty());
+        for byte in [3u8, 1, 2] {
+            track(&mut tracker, byte);
+        }
+
+        // 按 settlement_id 升序
+        assert_eq!(tracker.get_all_settlement_ids(), [1u8, 2, 3].map(B256::repeat_byte).to_vec());
+        assert_eq!(tracker.len(), 3);
+        assert!(tracker.has_settlement(B256::repeat_byte(2)));
+        assert_eq!(tracker.get_settlement_hash(B256::repeat_byte(1)), Some(B256::repeat_byte(0xfe)));
+        assert_eq!(tracker.get_settlement_hash(B256::repeat_byte(4)), None);
+
+        tracker.clear();
+        assert!(tracker.is_empty());
+        assert!(!tracker.has_settlement(B256::repeat_byte(2)));
+    }
+
+    #[test]
+    fn test_circular_tracker_eviction() -> Result<(), HashStoreError> {
+        let mut tracker = CircularSettlementTracker::new(3)?;
+        for byte in [5u8, 1, 4, 2] {
+            track(&mut tracker, byte);
+        }
+
+        // 5 被挤出，其余按记录顺序
+        assert_eq!(tracker.len(), 3);
+        assert_eq!(tracker.get_all_settlement_ids(), [1u8, 4, 2].map(B256::repeat_byte).to_vec());
+        assert!(!tracker.has_settlement(B256::repeat_byte(5)));
+        assert_eq!(tracker.get_settlement_hash(B256::repeat_byte(5)), None);
+        assert!(tracker.has_settlement(B256::repeat_byte(1)));
+        assert_eq!(tracker.store().get_full_state().2, B256::repeat_byte(5));
+
+        // 重复记录只更新哈希
+        tracker.track_settlement(key_to_u256(B256::repeat_byte(1)), B256::ZERO);
+        assert_eq!(tracker.get_all_settlement_ids(), [1u8, 4, 2].map(B256::repeat_byte).to_vec());
+        assert_eq!(tracker.get_settlement_hash(B256::repeat_byte(1)), Some(B256::ZERO));
+
+        // 被挤出的记录可以用历史证明验证
+        track(&mut tracker, 6);
+        assert!(!tracker.has_settlement(B256::repeat_byte(1)));
+        assert!(tracker.store().check_hash(B256::repeat_byte(5), &[B256::repeat_byte(1)]));
+
+        tracker.track_settlement(U256::ZERO, B256::repeat_byte(9));
+        assert_eq!(tracker.len(), 3);
+
+        tracker.clear();
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.store().capacity(), 3);
+        assert!(CircularSettlementTracker::new(0).is_err());
+        Ok(())
+    }
+}
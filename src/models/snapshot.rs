@@ -0,0 +1,125 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 当前快照格式版本
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// 带版本的状态快照
+///
+/// kind 区分快照属于哪个管理器，防止把 PayIdManager 的快照加载为 ProxyManager
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot<T> {
+    pub version: u32,
+    pub kind: String,
+    pub state: T,
+}
+
+/// 加载快照失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// 快照版本不受支持
+    UnsupportedVersion(u32),
+    /// 快照类型不匹配
+    WrongKind { expected: String, found: String },
+    /// 字节无法解析
+    Malformed(String),
+    /// 解析成功但内部状态不一致
+    Inconsistent(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported snapshot version {} (supported: {})",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::WrongKind { expected, found } => {
+                write!(f, "Wrong snapshot kind: expected {}, found {}", expected, found)
+            }
+            SnapshotError::Malformed(msg) => write!(f, "Malformed snapshot: {}", msg),
+            SnapshotError::Inconsistent(msg) => write!(f, "Inconsistent snapshot: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// 先只读版本和类型，版本未知时不尝试解析 state
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+    kind: String,
+}
+
+impl<T> StateSnapshot<T> {
+    pub fn new(kind: &str, state: T) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            kind: kind.to_string(),
+            state,
+        }
+    }
+}
+
+impl<T: Serialize> StateSnapshot<T> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        serde_json::to_vec(self).map_err(|e| SnapshotError::Malformed(e.to_string()))
+    }
+}
+
+impl<T: DeserializeOwned> StateSnapshot<T> {
+    /// 检查版本和类型后返回 state
+    pub fn from_bytes(kind: &str, bytes: &[u8]) -> Result<T, SnapshotError> {
+        let header: SnapshotHeader =
+            serde_json::from_slice(bytes).map_err(|e| SnapshotError::Malformed(e.to_string()))?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(header.version));
+        }
+        if header.kind != kind {
+            return Err(SnapshotError::WrongKind {
+                expected: kind.to_string(),
+                found: header.kind,
+            });
+        }
+
+        let snapshot: StateSnapshot<T> =
+            serde_json::from_slice(bytes).map_err(|e| SnapshotError::Malformed(e.to_string()))?;
+        Ok(snapshot.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_and_kind_checks() {
+        let bytes = StateSnapshot::new("numbers", vec![1u32, 2, 3]).to_bytes().unwrap();
+        assert_eq!(StateSnapshot::<Vec<u32>>::from_bytes("numbers", &bytes).unwrap(), vec![1, 2, 3]);
+
+        assert!(matches!(
+            StateSnapshot::<Vec<u32>>::from_bytes("letters", &bytes),
+            Err(SnapshotError::WrongKind { .. })
+        ));
+
+        // 未知版本即使 state 结构不同也报告版本错误
+        let future = br#"{"version":2,"kind":"numbers","state":{"new":"layout"}}"#;
+        assert_eq!(
+            StateSnapshot::<Vec<u32>>::from_bytes("numbers", future),
+            Err(SnapshotError::UnsupportedVersion(2))
+        );
+
+        // 损坏的字节
+        assert!(matches!(
+            StateSnapshot::<Vec<u32>>::from_bytes("numbers", &bytes[..bytes.len() / 2]),
+            Err(SnapshotError::Malformed(_))
+        ));
+        assert!(matches!(
+            StateSnapshot::<Vec<u32>>::from_bytes("numbers", br#"{"version":1,"kind":"numbers","state":"x"}"#),
+            Err(SnapshotError::Malformed(_))
+        ));
+    }
+}
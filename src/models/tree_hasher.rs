@@ -0,0 +1,126 @@
+//! SegmentVC、收据分组和 PayIdInfo 树使用的树哈希
+//!
+//! 只影响树内部的节点哈希和分组值；签名消息、收据 hash() 和 PayIdInfo::hash() 仍然固定为 keccak256。
+//! 默认的 Keccak 与之前的实现逐字节相同，Sha256 用于 SHA-256 有预编译的 SP1 部署
+use alloy_primitives::B256;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+/// 树哈希函数
+pub trait TreeHasher {
+    fn hash(&self, data: &[u8]) -> B256 {
+        self.hash_many(&[data])
+    }
+
+    /// parts 依次拼接后的哈希
+    fn hash_many(&self, parts: &[&[u8]]) -> B256;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeccakHasher;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sha256Hasher;
+
+impl TreeHasher for KeccakHasher {
+    fn hash_many(&self, parts: &[&[u8]]) -> B256 {
+        digest::<Keccak256>(parts)
+    }
+}
+
+impl TreeHasher for Sha256Hasher {
+    fn hash_many(&self, parts: &[&[u8]]) -> B256 {
+        digest::<Sha256>(parts)
+    }
+}
+
+fn digest<D: Digest>(parts: &[&[u8]]) -> B256 {
+    count_op!(TreeNode);
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    B256::from_slice(&hasher.finalize())
+}
+
+/// 运行时选择的树哈希算法，记录在 MerkleProof 中，验证时据此选择 hasher
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TreeHashAlgorithm {
+    #[default]
+    Keccak,
+    Sha256,
+}
+
+impl TreeHashAlgorithm {
+    /// 流式计算，用于不想先收集所有部分的调用方
+    pub(crate) fn start(self) -> TreeHashState {
+        count_op!(TreeNode);
+        match self {
+            TreeHashAlgorithm::Keccak => TreeHashState::Keccak(Keccak256::new()),
+            TreeHashAlgorithm::Sha256 => TreeHashState::Sha256(Sha256::new()),
+        }
+    }
+}
+
+impl TreeHasher for TreeHashAlgorithm {
+    fn hash_many(&self, parts: &[&[u8]]) -> B256 {
+        match self {
+            TreeHashAlgorithm::Keccak => KeccakHasher.hash_many(parts),
+            TreeHashAlgorithm::Sha256 => Sha256Hasher.hash_many(parts),
+        }
+    }
+}
+
+pub(crate) enum TreeHashState {
+    Keccak(Keccak256),
+    Sha256(Sha256),
+}
+
+impl TreeHashState {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            TreeHashState::Keccak(hasher) => hasher.update(data),
+            TreeHashState::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> B256 {
+        match self {
+            TreeHashState::Keccak(hasher) => B256::from_slice(&hasher.finalize()),
+            TreeHashState::Sha256(hasher) => B256::from_slice(&hasher.finalize()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::b256;
+
+    #[test]
+    fn test_known_vectors() {
+        assert_eq!(
+            KeccakHasher.hash(b"abc"),
+            b256!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45")
+        );
+        assert_eq!(
+            Sha256Hasher.hash(b"abc"),
+            b256!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+    }
+
+    #[test]
+    fn test_hash_many_concatenates() {
+        let parts: [&[u8]; 3] = [b"ab", b"", b"c"];
+        for algorithm in [TreeHashAlgorithm::Keccak, TreeHashAlgorithm::Sha256] {
+            assert_eq!(algorithm.hash_many(&parts), algorithm.hash(b"abc"));
+
+            let mut state = algorithm.start();
+            state.update(b"a");
+            state.update(b"bc");
+            assert_eq!(state.finalize(), algorithm.hash(b"abc"));
+        }
+        assert_eq!(TreeHashAlgorithm::Keccak.hash(b"abc"), B256::from(crate::keccak256(b"abc")));
+    }
+}
@@ -0,0 +1,423 @@
+//! guest stdin 中 MerkleProof 和 ServiceFeeConfig 的字节布局，host（Rust、Go）按此写入，guest 的 read_from_stdin 按此解析；
+//! 收据的布局见 receipts::wire
+//!
+//! 每个值作为一个 stdin 帧写入（SP1Stdin::write_slice），guest 用 read_vec 读出后解码。
+//! 整数为小端序，哈希为 32 字节原始值。MerkleProof 的字段依次为：
+//!
+//! | 字段                      | 类型              |
+//! |---------------------------|-------------------|
+//! | value_proof.value         | 32 字节           |
+//! | value_proof.chunk_hash    | 32 字节           |
+//! | segment_proof.chunk_index | u32               |
+//! | segment_proof.siblings    | u32 数量 + n × 32 |
+//! | level_proofs              | u32 数量 + 每层：level u32、node_index u32、siblings（u32 数量 + n × 32） |
+//! | root_hash                 | 32 字节           |
+//!
+//! 布局中没有 hasher 和 padded，解码的证明为 Keccak、不填充。长度在分配之前按 MAX_PROOF_* 检查。
+//!
+//! ServiceFeeConfig 为 serv_id u32、system_fee_rate u16、proxy_fee_rate u16，共 8 字节；布局只包含基础费率，
+//! 有 tiers 的配置不能写入。
+//!
+//! 布局变化时必须同步更新 tests/fixtures/ 下对应的 .bin 文件，测试与这些文件逐字节比较
+use alloc::vec::Vec;
+use alloy_primitives::B256;
+use core::error::Error as StdError;
+use core::fmt;
+
+use super::segment_vc::{
+    check_proof_len, LevelProof, MerkleProof, ProofTooLarge, SegmentProof, ValueProof, MAX_PROOF_LEVELS,
+    MAX_PROOF_SIBLINGS_PER_LEVEL, MAX_PROOF_TOTAL_SIBLINGS,
+};
+use super::{ServiceFeeConfig, TreeHashAlgorithm};
+
+/// 解码 stdin 字节失败
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    UnexpectedEnd { field: &'static str }, // 读取 field 时输入已结束
+    TooLarge(ProofTooLarge),
+    TrailingBytes(usize),               // 值之后还有多余的字节
+    InvalidValue { field: &'static str }, // bool 或 Option 的标记不是 0 或 1
+    NotInLayout { field: &'static str }, // 编码时 field 有值，但布局中没有该字段
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::UnexpectedEnd { field } => write!(f, "Unexpected end of stdin frame reading {}", field),
+            WireError::TooLarge(err) => err.fmt(f),
+            WireError::TrailingBytes(len) => write!(f, "{} trailing bytes after stdin frame", len),
+            WireError::InvalidValue { field } => write!(f, "Invalid {} in stdin frame", field),
+            WireError::NotInLayout { field } => write!(f, "{} is set but not part of the stdin layout", field),
+        }
+    }
+}
+
+impl StdError for WireError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            WireError::TooLarge(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProofTooLarge> for WireError {
+    fn from(err: ProofTooLarge) -> Self {
+        WireError::TooLarge(err)
+    }
+}
+
+/// 按模块文档中的布局编码和解码，decode 从 input 开头读取并前移 input
+pub trait Wire: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut &[u8]) -> Result<Self, WireError>;
+}
+
+pub(crate) fn take<'a>(input: &mut &'a [u8], len: usize, field: &'static str) -> Result<&'a [u8], WireError> {
+    if input.len() < len {
+        return Err(WireError::UnexpectedEnd { field });
+    }
+    let (head, rest) = input.split_at(len);
+    *input = rest;
+    Ok(head)
+}
+
+pub(crate) fn read_array<const N: usize>(input: &mut &[u8], field: &'static str) -> Result<[u8; N], WireError> {
+    let mut array = [0u8; N];
+    array.copy_from_slice(take(input, N, field)?);
+    Ok(array)
+}
+
+pub(crate) fn read_u16(input: &mut &[u8], field: &'static str) -> Result<u16, WireError> {
+    Ok(u16::from_le_bytes(read_array(input, field)?))
+}
+
+pub(crate) fn read_u32(input: &mut &[u8], field: &'static str) -> Result<u32, WireError> {
+    Ok(u32::from_le_bytes(read_array(input, field)?))
+}
+
+pub(crate) fn read_u64(input: &mut &[u8], field: &'static str) -> Result<u64, WireError> {
+    Ok(u64::from_le_bytes(read_array(input, field)?))
+}
+
+/// 一个字节，0 为 false，1 为 true
+pub(crate) fn read_bool(input: &mut &[u8], field: &'static str) -> Result<bool, WireError> {
+    match take(input, 1, field)?[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(WireError::InvalidValue { field }),
+    }
+}
+
+/// 标记字节（0 为 None，1 为 Some）之后是值
+pub(crate) fn read_option<T>(
+    input: &mut &[u8],
+    field: &'static str,
+    read: impl FnOnce(&mut &[u8], &'static str) -> Result<T, WireError>,
+) -> Result<Option<T>, WireError> {
+    if read_bool(input, field)? {
+        read(input, field).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn write_option<T>(out: &mut Vec<u8>, value: Option<T>, write: impl FnOnce(&mut Vec<u8>, T)) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            write(out, value);
+        }
+        None => out.push(0),
+    }
+}
+
+/// 解码一整帧，之后有多余字节时返回 WireError::TrailingBytes
+pub(crate) fn decode_frame<T>(
+    mut bytes: &[u8],
+    decode: impl FnOnce(&mut &[u8]) -> Result<T, WireError>,
+) -> Result<T, WireError> {
+    let value = decode(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(WireError::TrailingBytes(bytes.len()));
+    }
+    Ok(value)
+}
+
+pub(crate) fn read_hash(input: &mut &[u8], field: &'static str) -> Result<B256, WireError> {
+    Ok(B256::from_slice(take(input, 32, field)?))
+}
+
+// 合法证明的索引不超过 NODE_WIDTH，超过 u32 说明证明已损坏
+fn write_u32(out: &mut Vec<u8>, value: usize, field: &'static str) {
+    let value = u32::try_from(value).unwrap_or_else(|_| panic!("{} does not fit in u32", field));
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_hashes(out: &mut Vec<u8>, hashes: &[B256], field: &'static str) {
+    write_u32(out, hashes.len(), field);
+    for hash in hashes {
+        out.extend_from_slice(hash.as_slice());
+    }
+}
+
+// 数量已检查，不超过 MAX_PROOF_SIBLINGS_PER_LEVEL
+fn read_hashes(input: &mut &[u8], len: usize, field: &'static str) -> Result<Vec<B256>, WireError> {
+    let mut hashes = Vec::with_capacity(len);
+    for _ in 0..len {
+        hashes.push(read_hash(input, field)?);
+    }
+    Ok(hashes)
+}
+
+impl Wire for ValueProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.value.as_slice());
+        out.extend_from_slice(self.chunk_hash.as_slice());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, WireError> {
+        Ok(Self { value: read_hash(input, "value")?, chunk_hash: read_hash(input, "chunk hash")? })
+    }
+}
+
+impl Wire for SegmentProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_u32(out, self.chunk_index, "chunk index");
+        write_hashes(out, &self.siblings, "segment siblings");
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, WireError> {
+        let chunk_index = read_u32(input, "chunk index")? as usize;
+        let len = read_u32(input, "segment siblings")? as usize;
+        check_proof_len("segment siblings", len, MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+        Ok(Self { chunk_index, siblings: read_hashes(input, len, "segment siblings")? })
+    }
+}
+
+impl Wire for LevelProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_u32(out, self.level, "level");
+        write_u32(out, self.node_index, "node index");
+        write_hashes(out, &self.siblings, "level siblings");
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, WireError> {
+        let level = read_u32(input, "level")? as usize;
+        let node_index = read_u32(input, "node index")? as usize;
+        let len = read_u32(input, "level siblings")? as usize;
+        check_proof_len("level siblings", len, MAX_PROOF_SIBLINGS_PER_LEVEL)?;
+        Ok(Self { level, node_index, siblings: read_hashes(input, len, "level siblings")? })
+    }
+}
+
+impl Wire for MerkleProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.value_proof.encode(out);
+        self.segment_proof.encode(out);
+        write_u32(out, self.level_proofs.len(), "level proofs");
+        for proof in &self.level_proofs {
+            proof.encode(out);
+        }
+        out.extend_from_slice(self.root_hash.as_slice());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, WireError> {
+        let value_proof = ValueProof::decode(input)?;
+        let segment_proof = SegmentProof::decode(input)?;
+
+        let len = read_u32(input, "level proofs")? as usize;
+        check_proof_len("level proofs", len, MAX_PROOF_LEVELS)?;
+        let mut total_siblings = segment_proof.siblings.len();
+        let mut level_proofs = Vec::with_capacity(len);
+        for _ in 0..len {
+            let proof = LevelProof::decode(input)?;
+            total_siblings += proof.siblings.len();
+            check_proof_len("total siblings", total_siblings, MAX_PROOF_TOTAL_SIBLINGS)?;
+            level_proofs.push(proof);
+        }
+
+        Ok(Self {
+            value_proof,
+            segment_proof,
+            level_proofs,
+            root_hash: read_hash(input, "root hash")?,
+            hasher: TreeHashAlgorithm::Keccak,
+            padded: false,
+        })
+    }
+}
+
+impl MerkleProof {
+    /// host 写入 stdin 的字节，guest 用 read_from_stdin 读取
+    pub fn to_stdin_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    /// 解码 to_stdin_bytes 的输出，之后有多余字节时返回 WireError::TrailingBytes
+    pub fn from_stdin_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        decode_frame(bytes, Self::decode)
+    }
+}
+
+impl ServiceFeeConfig {
+    /// host 写入 stdin 的 8 字节；有 tiers 时返回 WireError::NotInLayout，否则 guest 会按基础费率计算
+    pub fn to_stdin_bytes(&self) -> Result<Vec<u8>, WireError> {
+        if !self.tiers.is_empty() {
+            return Err(WireError::NotInLayout { field: "tiers" });
+        }
+        let mut out = Vec::with_capacity(8);
+        out.extend_from_slice(&self.serv_id.to_le_bytes());
+        out.extend_from_slice(&self.system_fee_rate.to_le_bytes());
+        out.extend_from_slice(&self.proxy_fee_rate.to_le_bytes());
+        Ok(out)
+    }
+
+    /// 解码 to_stdin_bytes 的输出，tiers 为空
+    pub fn from_stdin_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        decode_frame(bytes, |input| {
+            Ok(Self::flat(
+                read_u32(input, "serv id")?,
+                read_u16(input, "system fee rate")?,
+                read_u16(input, "proxy fee rate")?,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::segment_vc::SegmentVC;
+    use crate::models::FeeTier;
+    use alloy_primitives::U256;
+    use crate::BoxError;
+    use alloc::vec;
+
+    // 布局变化时重新生成这些文件，审查时可以看到
+    const PROOF_BIN: &[u8] = include_bytes!("../../tests/fixtures/merkle_proof.bin");
+    const TWO_LEVELS_BIN: &[u8] = include_bytes!("../../tests/fixtures/merkle_proof_two_levels.bin");
+    const FEE_CONFIG_BIN: &[u8] = include_bytes!("../../tests/fixtures/fee_config.bin");
+
+    // 与 merkle_proof.json 相同的证明，stdin 布局不记录 hasher
+    fn fixture_proof() -> MerkleProof {
+        MerkleProof {
+            value_proof: ValueProof { value: B256::repeat_byte(0x11), chunk_hash: B256::repeat_byte(0x22) },
+            segment_proof: SegmentProof {
+                chunk_index: 3,
+                siblings: vec![B256::repeat_byte(0x33), B256::repeat_byte(0x44)],
+            },
+            level_proofs: vec![LevelProof { level: 0, node_index: 17, siblings: vec![B256::repeat_byte(0x55)] }],
+            root_hash: B256::repeat_byte(0x66),
+            hasher: TreeHashAlgorithm::Keccak,
+            padded: false,
+        }
+    }
+
+    fn two_levels_proof() -> MerkleProof {
+        MerkleProof {
+            value_proof: ValueProof { value: B256::repeat_byte(0xaa), chunk_hash: B256::repeat_byte(0xbb) },
+            segment_proof: SegmentProof { chunk_index: 1, siblings: vec![B256::repeat_byte(0x31)] },
+            level_proofs: vec![
+                LevelProof { level: 0, node_index: 2, siblings: vec![B256::repeat_byte(0x41), B256::repeat_byte(0x42)] },
+                LevelProof { level: 1, node_index: 0, siblings: vec![B256::repeat_byte(0x51)] },
+            ],
+            root_hash: B256::repeat_byte(0x61),
+            hasher: TreeHashAlgorithm::Keccak,
+            padded: false,
+        }
+    }
+
+    #[test]
+    fn test_fixtures() -> Result<(), BoxError> {
+        for (bytes, proof) in [(PROOF_BIN, fixture_proof()), (TWO_LEVELS_BIN, two_levels_proof())] {
+            assert_eq!(MerkleProof::from_stdin_bytes(bytes)?, proof);
+            assert_eq!(proof.to_stdin_bytes(), bytes);
+        }
+
+        // 固定字段的位置：chunk_index 紧跟两个哈希，小端序
+        assert_eq!(&PROOF_BIN[64..72], &[3, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(PROOF_BIN.len(), 32 * 2 + 8 + 32 * 2 + 4 + 12 + 32 + 32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_proof_round_trip() -> Result<(), BoxError> {
+        let mut vc = SegmentVC::new(16);
+        let entries: Vec<(B256, B256)> =
+            (0..300u32).map(|i| (B256::left_padding_from(&i.to_be_bytes()), B256::repeat_byte(i as u8))).collect();
+        vc.insert_batch(entries)?;
+
+        let proof = vc.generate_proof(B256::left_padding_from(&200u32.to_be_bytes()))?;
+        let decoded = MerkleProof::from_stdin_bytes(&proof.to_stdin_bytes())?;
+        assert_eq!(decoded, proof);
+        assert!(decoded.verify()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        // 截断和多余的字节
+        for len in [0, 31, 64, 70, PROOF_BIN.len() - 1] {
+            let err = MerkleProof::from_stdin_bytes(&PROOF_BIN[..len]).unwrap_err();
+            assert!(matches!(err, WireError::UnexpectedEnd { .. }), "{}: {:?}", len, err);
+        }
+        let mut trailing = PROOF_BIN.to_vec();
+        trailing.push(0);
+        assert_eq!(MerkleProof::from_stdin_bytes(&trailing), Err(WireError::TrailingBytes(1)));
+
+        let too_large = |field: &'static str, max: usize| move |err: WireError| match err {
+            WireError::TooLarge(err) => err.field == field && err.max == max,
+            _ => false,
+        };
+        let header = |segment_siblings: u32| {
+            let mut bytes = vec![0u8; 64];
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&segment_siblings.to_le_bytes());
+            bytes
+        };
+
+        // 巨大的兄弟节点数量在分配之前被拒绝
+        let err = MerkleProof::from_stdin_bytes(&header(u32::MAX)).unwrap_err();
+        assert!(too_large("segment siblings", MAX_PROOF_SIBLINGS_PER_LEVEL)(err));
+
+        // 过多的层数
+        let mut bytes = header(0);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(too_large("level proofs", MAX_PROOF_LEVELS)(MerkleProof::from_stdin_bytes(&bytes).unwrap_err()));
+
+        // 上层兄弟节点过多
+        let mut bytes = header(1);
+        bytes.extend_from_slice(&[0x11; 32]);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        for value in [0u32, 0, (MAX_PROOF_SIBLINGS_PER_LEVEL + 1) as u32] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        let err = MerkleProof::from_stdin_bytes(&bytes).unwrap_err();
+        assert!(too_large("level siblings", MAX_PROOF_SIBLINGS_PER_LEVEL)(err));
+    }
+
+    #[test]
+    fn test_fee_config_fixture() -> Result<(), BoxError> {
+        let config = ServiceFeeConfig::from_stdin_bytes(FEE_CONFIG_BIN)?;
+        assert_eq!((config.serv_id, config.system_fee_rate, config.proxy_fee_rate), (7, 500, 1000));
+        assert!(config.tiers.is_empty());
+        assert_eq!(ServiceFeeConfig::flat(7, 500, 1000).to_stdin_bytes()?, FEE_CONFIG_BIN);
+        assert_eq!(FEE_CONFIG_BIN, &[7, 0, 0, 0, 0xf4, 0x01, 0xe8, 0x03]);
+
+        // 分级费率不在布局中，截断和多余的字节
+        let tier = FeeTier { threshold: U256::from(100), system_fee_rate: 100, proxy_fee_rate: 100 };
+        let tiered = ServiceFeeConfig::flat(7, 500, 1000).with_tiers(vec![tier]);
+        assert_eq!(tiered.to_stdin_bytes().unwrap_err(), WireError::NotInLayout { field: "tiers" });
+        assert!(matches!(
+            ServiceFeeConfig::from_stdin_bytes(&FEE_CONFIG_BIN[..7]),
+            Err(WireError::UnexpectedEnd { field: "proxy fee rate" })
+        ));
+        let mut trailing = FEE_CONFIG_BIN.to_vec();
+        trailing.push(0);
+        assert!(matches!(ServiceFeeConfig::from_stdin_bytes(&trailing), Err(WireError::TrailingBytes(1))));
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+/***
+ * 结算流程的编排
+ *
+ * 代理结算：
+ * 1. ReceiptsOverpayChecker 检查超付，得到 payments_root、每个接收者的证明和 pay_ids_root
+ * 2. PaymentsGrouper 按接收者分组，MultiReceiverProfitCalculator 计算每个接收者的 ProfitResult
+ * 3. ProxySettlementAggregator 聚合所有 ProfitResult，并验证结算总额不超过存款总额
+ *
+ * 接收者结算：
+ * 对每个代理的 (收据, ProfitResult, 默克尔证明)，验证收据包含在 receipts_root 中，累计 receiver_profit
+ */
+
+use alloy_primitives::{B256, U256};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::guest_checks::{self, GuestError};
+use crate::models::segment_vc::MerkleProof;
+use crate::models::{PayIdInfo, ServiceFeeConfig, TreeHashAlgorithm};
+use crate::proxy_settler::ProxySettlementAggregator;
+use crate::receipts::profit_calculator::{validate_receipts_proof, validate_receivers};
+use crate::receipts::{AmountOverflow, MultiReceiverProfitCalculator, PaymentsGrouper};
+use crate::{
+    keccak256_more, BoxError, EthAddress, OverpayCheckResult, PaymentSettledByProxy, ProfitResult,
+    ProxySettlementResult, ReceiptsOverpayChecker, ReceiverSettleResult,
+};
+
+/// 流程失败时记录失败的阶段
+#[derive(Debug)]
+pub enum PayModelError {
+    OverpayCheck(BoxError),
+    ProfitCalculation(BoxError),
+    Aggregation(BoxError),
+    ReceiverSettlement(BoxError),
+}
+
+impl fmt::Display for PayModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayModelError::OverpayCheck(e) => write!(f, "Overpay check failed: {}", e),
+            PayModelError::ProfitCalculation(e) => write!(f, "Profit calculation failed: {}", e),
+            PayModelError::Aggregation(e) => write!(f, "Proxy settlement aggregation failed: {}", e),
+            PayModelError::ReceiverSettlement(e) => write!(f, "Receiver settlement failed: {}", e),
+        }
+    }
+}
+
+impl PayModelError {
+    /// guest 使用的编码错误：code 由内部错误的类型决定，detail 为失败的阶段
+    pub fn to_guest_code(&self) -> GuestError {
+        let (stage, error) = match self {
+            PayModelError::OverpayCheck(e) => (guest_checks::STAGE_OVERPAY_CHECK, e),
+            PayModelError::ProfitCalculation(e) => (guest_checks::STAGE_PROFIT_CALCULATION, e),
+            PayModelError::Aggregation(e) => (guest_checks::STAGE_AGGREGATION, e),
+            PayModelError::ReceiverSettlement(e) => (guest_checks::STAGE_RECEIVER_SETTLEMENT, e),
+        };
+        GuestError::new(guest_checks::classify(error.as_ref()), stage)
+    }
+}
+
+impl std::error::Error for PayModelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PayModelError::OverpayCheck(e)
+            | PayModelError::ProfitCalculation(e)
+            | PayModelError::Aggregation(e)
+            | PayModelError::ReceiverSettlement(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// 代理结算的全部输出
+#[derive(Debug, Clone)]
+pub struct ProxyPipelineOutput {
+    pub overpay_result: OverpayCheckResult,
+    pub profit_results: Vec<ProfitResult>, // 按接收者地址升序
+    pub settlement: ProxySettlementResult,
+}
+
+/// 运行代理一轮结算的完整流程
+///
+/// 在 guest 之外运行，ProfitResult 的 vks_hash 为零
+pub fn run_proxy_settlement(
+    channel: EthAddress,
+    pay_id_infos: Vec<PayIdInfo>,
+    payments: Vec<PaymentSettledByProxy>,
+    fee_configs: Vec<ServiceFeeConfig>,
+    epoch: u64,
+) -> Result<ProxyPipelineOutput, PayModelError> {
+    // 1. 超付检查
+    let receipts_by_receiver = PaymentsGrouper::group_payments(&payments);
+    let overpay_result = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), payments)
+        .with_epoch(epoch)
+        .process()
+        .map_err(PayModelError::OverpayCheck)?;
+
+    // 2. 每个接收者的利润
+    let profit_results = MultiReceiverProfitCalculator::new(
+        B256::ZERO,
+        channel,
+        overpay_result.clone(),
+        receipts_by_receiver,
+        pay_id_infos.clone(),
+        fee_configs,
+    )
+    .calculate()
+    .map_err(PayModelError::ProfitCalculation)?
+    .profit_results;
+
+    // 3. 聚合
+    let settlement = ProxySettlementAggregator::new()
+        .with_epoch(epoch)
+        .aggregate_with_deposits(profit_results.clone(), overpay_result.clone(), &pay_id_infos)
+        .map_err(PayModelError::Aggregation)?;
+
+    Ok(ProxyPipelineOutput {
+        overpay_result,
+        profit_results,
+        settlement,
+    })
+}
+
+/// 汇总接收者在多个代理处的结算
+///
+/// 每一项为 (该接收者在某个代理处的收据, 对应的 ProfitResult, 收据在 receipts_root 中的证明)。
+/// settlement_root 从零开始依次链接每个 ProfitResult 的 content_hash：
+/// h = keccak256(h ‖ content_hash)
+pub fn run_receiver_settlement(
+    receiver: EthAddress,
+    settlements: Vec<(Vec<PaymentSettledByProxy>, ProfitResult, MerkleProof)>,
+) -> Result<ReceiverSettleResult, PayModelError> {
+    settle_receiver(receiver, settlements).map_err(PayModelError::ReceiverSettlement)
+}
+
+fn settle_receiver(
+    receiver: EthAddress,
+    settlements: Vec<(Vec<PaymentSettledByProxy>, ProfitResult, MerkleProof)>,
+) -> Result<ReceiverSettleResult, BoxError> {
+    if settlements.is_empty() {
+        return Err("No settlements for receiver".into());
+    }
+
+    let vk_hash = settlements[0].1.vks_hash;
+    let mut settlement_root = B256::ZERO;
+    let mut profit = U256::ZERO;
+    let mut seen = HashSet::new();
+
+    for (payments, profit_result, proof) in &settlements {
+        if profit_result.receiver != receiver {
+            return Err("Receiver mismatch".into());
+        }
+        if profit_result.vks_hash != vk_hash {
+            return Err("vks_hash mismatch".into());
+        }
+        if proof.root_hash != profit_result.receipts_root {
+            return Err("Merkle proof root does not match receipts_root".into());
+        }
+        validate_receivers(payments, receiver)?;
+        validate_receipts_proof(payments, proof, TreeHashAlgorithm::Keccak)?;
+
+        // 同一个 ProfitResult 只能计入一次
+        let content_hash = profit_result.content_hash();
+        if !seen.insert(content_hash) {
+            return Err(format!("Duplicate ProfitResult {}", content_hash).into());
+        }
+
+        profit = profit
+            .checked_add(profit_result.receiver_profit)
+            .ok_or(AmountOverflow::Receiver(receiver))?;
+        settlement_root = B256::from(keccak256_more(&settlement_root, content_hash.as_slice()));
+    }
+
+    Ok(ReceiverSettleResult {
+        vk_hash,
+        settlement_root,
+        receiver,
+        profit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_ethereum_address;
+    use crate::receipts::SettledReceiptBuilder;
+    use crate::DeterministicRng;
+    use libsecp256k1::{PublicKey, SecretKey};
+    use rand::SeedableRng;
+
+    struct Party {
+        key: SecretKey,
+        address: EthAddress,
+    }
+
+    fn party(seed: u64) -> Party {
+        let key = SecretKey::random(&mut DeterministicRng::seed_from_u64(seed));
+        let address = get_ethereum_address(&PublicKey::from_secret_key(&key));
+        Party { key, address }
+    }
+
+    fn create_test_payment(
+        pay_id: u64,
+        serv_id: u32,
+        amount: u64,
+        receiver: EthAddress,
+        sender: &Party,
+        proxy: &Party,
+    ) -> Result<PaymentSettledByProxy, BoxError> {
+        Ok(SettledReceiptBuilder::new(U256::from(pay_id), serv_id, U256::from(amount), receiver)
+            .sign_sender(&sender.key)
+            .settle(U256::from(amount), true)
+            .sign_proxy(&proxy.key)
+            .build()?)
+    }
+
+    fn pay_id_info(id: u64, amount: u64, sender: &Party, proxy: &Party) -> PayIdInfo {
+        PayIdInfo {
+            id: U256::from(id),
+            amount: U256::from(amount),
+            sender: sender.address,
+            proxy: proxy.address,
+            state: 1,
+            created_at: 0,
+            closing_time: 0,
+            token: [0u8; 20],
+            sig_sender: None,
+        }
+    }
+
+    fn fee_configs() -> Vec<ServiceFeeConfig> {
+        vec![
+            ServiceFeeConfig {
+                serv_id: 1,
+                system_fee_rate: 500,
+                proxy_fee_rate: 1000,
+                tiers: Vec::new(),
+            },
+            ServiceFeeConfig {
+                serv_id: 2,
+                system_fee_rate: 300,
+                proxy_fee_rate: 700,
+                tiers: Vec::new(),
+            },
+        ]
+    }
+
+    // 与 calculate_receipt_profits 相同的整数除法
+    fn expected_fees(serv_id: u32, amount: u64) -> (u64, u64, u64) {
+        let (system_rate, proxy_rate) = if serv_id == 1 { (500, 1000) } else { (300, 700) };
+        let system = amount * system_rate / 10000;
+        let proxy = amount * proxy_rate / 10000;
+        (system, proxy, amount - system - proxy)
+    }
+
+    #[test]
+    fn test_full_pipeline() -> Result<(), BoxError> {
+        let proxy = party(1);
+        let alice = party(2);
+        let bob = party(3);
+        let receivers = [[0x11u8; 20], [0x22u8; 20], [0x33u8; 20]];
+
+        // pay_id 1 属于 alice，pay_id 2 属于 bob
+        let pay_id_infos = vec![
+            pay_id_info(1, 10_000, &alice, &proxy),
+            pay_id_info(2, 8_000, &bob, &proxy),
+        ];
+        let plan: Vec<(u64, u32, u64, EthAddress)> = vec![
+            (1, 1, 1000, receivers[0]),
+            (2, 2, 2000, receivers[0]),
+            (1, 2, 1500, receivers[1]),
+            (2, 1, 333, receivers[1]),
+            (1, 1, 4321, receivers[2]),
+            (2, 2, 77, receivers[2]),
+        ];
+        let mut payments = Vec::new();
+        for (pay_id, serv_id, amount, receiver) in &plan {
+            let sender = if *pay_id == 1 { &alice } else { &bob };
+            payments.push(create_test_payment(*pay_id, *serv_id, *amount, *receiver, sender, &proxy)?);
+        }
+
+        let output = run_proxy_settlement(proxy.address, pay_id_infos, payments.clone(), fee_configs(), 5)?;
+
+        // 每个接收者的利润与逐笔计算的结果一致
+        assert_eq!(output.profit_results.len(), 3);
+        let (mut total_system, mut total_proxy, mut total_amount) = (0u64, 0u64, 0u64);
+        for profit_result in &output.profit_results {
+            let (mut system, mut proxy_fee, mut receiver_fee) = (0u64, 0u64, 0u64);
+            for (_, serv_id, amount, receiver) in &plan {
+                if *receiver == profit_result.receiver {
+                    let fees = expected_fees(*serv_id, *amount);
+                    system += fees.0;
+                    proxy_fee += fees.1;
+                    receiver_fee += fees.2;
+                    total_amount += amount;
+                }
+            }
+            assert_eq!(profit_result.system_profit, U256::from(system));
+            assert_eq!(profit_result.proxy_profit, U256::from(proxy_fee));
+            assert_eq!(profit_result.receiver_profit, U256::from(receiver_fee));
+            assert_eq!(profit_result.epoch, 5);
+            total_system += system;
+            total_proxy += proxy_fee;
+        }
+
+        // 聚合结果与各接收者的总和一致
+        let settlement = &output.settlement;
+        assert_eq!(settlement.proxy, proxy.address);
+        assert_eq!(settlement.epoch, 5);
+        assert_eq!(settlement.system_profits, U256::from(total_system));
+        assert_eq!(settlement.proxy_profits, U256::from(total_proxy));
+        assert_eq!(settlement.amount, U256::from(total_amount));
+        assert_eq!(settlement.pay_ids_root, output.overpay_result.pay_ids_root);
+        assert!(settlement.verify_settlement_id(settlement.pay_ids_root));
+
+        // 每个接收者用自己的收据和证明完成结算
+        let groups = PaymentsGrouper::group_payments(&payments);
+        let mut settled_total = U256::ZERO;
+        for profit_result in &output.profit_results {
+            let receipts = groups
+                .iter()
+                .find(|(receiver, _)| *receiver == profit_result.receiver)
+                .map(|(_, receipts)| receipts.clone())
+                .unwrap();
+            let proof = output.overpay_result.get_merkle_proof(profit_result.receiver)?;
+            let result = run_receiver_settlement(
+                profit_result.receiver,
+                vec![(receipts, profit_result.clone(), proof)],
+            )?;
+            assert_eq!(result.profit, profit_result.receiver_profit);
+            assert_eq!(
+                result.settlement_root,
+                B256::from(keccak256_more(&B256::ZERO, profit_result.content_hash().as_slice()))
+            );
+            settled_total += result.profit;
+        }
+        assert_eq!(
+            settled_total + settlement.system_profits + settlement.proxy_profits,
+            settlement.amount
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipeline_errors_name_stage() -> Result<(), BoxError> {
+        let proxy = party(1);
+        let alice = party(2);
+        let receiver = [0x11u8; 20];
+        let pay_id_infos = vec![pay_id_info(1, 1000, &alice, &proxy)];
+
+        // 超付
+        let payments = vec![create_test_payment(1, 1, 1001, receiver, &alice, &proxy)?];
+        let err = run_proxy_settlement(proxy.address, pay_id_infos.clone(), payments, fee_configs(), 0).unwrap_err();
+        assert!(matches!(err, PayModelError::OverpayCheck(_)));
+
+        // 服务配置缺失
+        let payments = vec![create_test_payment(1, 3, 100, receiver, &alice, &proxy)?];
+        let err = run_proxy_settlement(proxy.address, pay_id_infos.clone(), payments.clone(), fee_configs(), 0)
+            .unwrap_err();
+        assert!(matches!(err, PayModelError::ProfitCalculation(_)));
+        assert!(std::error::Error::source(&err).is_some());
+
+        // 接收者结算：收据与证明不符、重复的 ProfitResult
+        let payments = vec![create_test_payment(1, 1, 100, receiver, &alice, &proxy)?];
+        let output = run_proxy_settlement(proxy.address, pay_id_infos, payments.clone(), fee_configs(), 0)?;
+        let profit_result = output.profit_results[0].clone();
+        let proof = output.overpay_result.get_merkle_proof(receiver)?;
+
+        let other = vec![create_test_payment(1, 2, 100, receiver, &alice, &proxy)?];
+        let err = run_receiver_settlement(receiver, vec![(other, profit_result.clone(), proof.clone())]).unwrap_err();
+        assert!(matches!(err, PayModelError::ReceiverSettlement(_)));
+
+        let twice = vec![
+            (payments.clone(), profit_result.clone(), proof.clone()),
+            (payments, profit_result, proof),
+        ];
+        assert!(run_receiver_settlement(receiver, twice).is_err());
+        assert!(run_receiver_settlement(receiver, vec![]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_guest_code_stages() {
+        use crate::guest_checks::{ErrorCode, STAGE_RECEIVER_SETTLEMENT};
+
+        let overflow = || -> BoxError { Box::new(AmountOverflow::Receiver([1u8; 20])) };
+        let cases = [
+            (PayModelError::OverpayCheck(overflow()), guest_checks::STAGE_OVERPAY_CHECK),
+            (PayModelError::ProfitCalculation(overflow()), guest_checks::STAGE_PROFIT_CALCULATION),
+            (PayModelError::Aggregation(overflow()), guest_checks::STAGE_AGGREGATION),
+            (PayModelError::ReceiverSettlement(overflow()), STAGE_RECEIVER_SETTLEMENT),
+        ];
+        for (error, stage) in cases {
+            assert_eq!(error.to_guest_code(), GuestError::new(ErrorCode::Overflow, stage));
+        }
+        assert_eq!(
+            PayModelError::Aggregation("Empty profit results".into()).to_guest_code().error_code(),
+            Some(ErrorCode::Unclassified)
+        );
+    }
+}
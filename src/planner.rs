@@ -0,0 +1,220 @@
+//! 主机端的证明批次规划
+//!
+//! 证明的成本随批次大小超线性增长，主机把待结算的收据拆成多个批次分别证明。plan_batches 按接收者分组装箱，
+//! 同一接收者的收据尽量放在同一个批次中，不需要分页的组哈希链；单个接收者的收据超过 max_receipts 时只能拆开，
+//! 拆开的接收者记录在每个相关批次的 split_receivers 中。
+//!
+//! 每个批次单独做超付检查，同一 pay_id 的收据分布在多个批次时，结算总额不超过授权金额需要调用方跨批次检查
+use alloy_primitives::U256;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::models::PayIdInfo;
+use crate::{EthAddress, PaymentSettledByProxy, ReceiptsOverpayChecker};
+
+/// 每个批次的上限，0 按 1 处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLimits {
+    pub max_receipts: usize,
+    pub max_receivers: usize,
+}
+
+impl BatchLimits {
+    pub fn new(max_receipts: usize, max_receivers: usize) -> Self {
+        Self { max_receipts, max_receivers }
+    }
+}
+
+/// 一个批次包含的收据和预计的树大小
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchPlan {
+    pub receipt_indices: Vec<usize>,      // 收据在 plan_batches 输入中的下标，升序
+    pub receivers: Vec<EthAddress>,       // 升序
+    pub split_receivers: Vec<EthAddress>, // 收据分布在多个批次中的接收者，升序
+    pub payments_tree_size: usize,        // payments 树的叶子数，每个接收者一个
+    pub pay_ids_tree_size: usize,         // 只使用本批次的 PayIdInfo 时 pay_ids 树的叶子数
+}
+
+impl BatchPlan {
+    pub fn is_split(&self) -> bool {
+        !self.split_receivers.is_empty()
+    }
+
+    /// 本批次的收据，保持在 payments 中的顺序；payments 必须是 plan_batches 的输入
+    pub fn payments(&self, payments: &[PaymentSettledByProxy]) -> Vec<PaymentSettledByProxy> {
+        self.receipt_indices.iter().map(|&index| payments[index].clone()).collect()
+    }
+
+    /// 本批次的收据用到的 PayIdInfo
+    pub fn pay_id_infos(&self, payments: &[PaymentSettledByProxy], pay_id_infos: &[PayIdInfo]) -> Vec<PayIdInfo> {
+        let pay_ids: BTreeSet<U256> = self.receipt_indices.iter().map(|&index| payments[index].pay_id).collect();
+        pay_id_infos.iter().filter(|info| pay_ids.contains(&info.id)).cloned().collect()
+    }
+
+    /// 本批次的超付检查，签名验证等选项由调用方继续设置
+    pub fn overpay_checker(
+        &self,
+        channel: EthAddress,
+        payments: &[PaymentSettledByProxy],
+        pay_id_infos: &[PayIdInfo],
+    ) -> ReceiptsOverpayChecker {
+        ReceiptsOverpayChecker::new(channel, self.pay_id_infos(payments, pay_id_infos), self.payments(payments))
+    }
+}
+
+/// 把收据分成满足 limits 的批次，结果只由输入决定
+///
+/// 首次适应递减装箱：按接收者分组，收据多的组先放（相同数量按地址升序），放入第一个放得下的批次，
+/// 都放不下时新建批次。收据超过 max_receipts 的接收者先按原顺序切出装满的块，余下的部分与其他组一起装箱
+pub fn plan_batches(payments: &[PaymentSettledByProxy], limits: BatchLimits) -> Vec<BatchPlan> {
+    let max_receipts = limits.max_receipts.max(1);
+    let max_receivers = limits.max_receivers.max(1);
+
+    let mut groups: BTreeMap<EthAddress, Vec<usize>> = BTreeMap::new();
+    for (index, payment) in payments.iter().enumerate() {
+        groups.entry(payment.receiver).or_default().push(index);
+    }
+
+    let mut split = BTreeSet::new();
+    let mut pieces: Vec<(EthAddress, &[usize])> = Vec::with_capacity(groups.len());
+    for (receiver, indices) in &groups {
+        if indices.len() > max_receipts {
+            split.insert(*receiver);
+        }
+        pieces.extend(indices.chunks(max_receipts).map(|chunk| (*receiver, chunk)));
+    }
+    // 稳定排序，同一接收者的块保持原顺序
+    pieces.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+
+    let mut batches: Vec<(Vec<usize>, BTreeSet<EthAddress>)> = Vec::new();
+    for (receiver, indices) in pieces {
+        let fits = |(receipts, receivers): &&mut (Vec<usize>, BTreeSet<EthAddress>)| {
+            receipts.len() + indices.len() <= max_receipts
+                && (receivers.contains(&receiver) || receivers.len() < max_receivers)
+        };
+        match batches.iter_mut().find(fits) {
+            Some((receipts, receivers)) => {
+                receipts.extend_from_slice(indices);
+                receivers.insert(receiver);
+            }
+            None => batches.push((indices.to_vec(), BTreeSet::from([receiver]))),
+        }
+    }
+
+    batches
+        .into_iter()
+        .map(|(mut receipt_indices, receivers)| {
+            receipt_indices.sort_unstable();
+            let pay_ids: BTreeSet<U256> = receipt_indices.iter().map(|&index| payments[index].pay_id).collect();
+            BatchPlan {
+                split_receivers: receivers.intersection(&split).copied().collect(),
+                payments_tree_size: receivers.len(),
+                pay_ids_tree_size: pay_ids.len(),
+                receivers: receivers.into_iter().collect(),
+                receipt_indices,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ScenarioBuilder;
+    use crate::BoxError;
+
+    // receiver 为 [receiver; 20]，每个接收者的 pay_id 从 1 开始
+    fn payments_with_counts(counts: &[usize]) -> Vec<PaymentSettledByProxy> {
+        let mut payments = Vec::new();
+        for (receiver, &count) in counts.iter().enumerate() {
+            for pay_id in 1..=count as u64 {
+                payments.push(PaymentSettledByProxy::new(U256::from(pay_id), 1, U256::from(10), [receiver as u8 + 1; 20]));
+            }
+        }
+        payments
+    }
+
+    fn assert_valid(plans: &[BatchPlan], payments: &[PaymentSettledByProxy], limits: BatchLimits) {
+        let mut seen = vec![0usize; payments.len()];
+        for plan in plans {
+            assert!(!plan.receipt_indices.is_empty());
+            assert!(plan.receipt_indices.len() <= limits.max_receipts);
+            assert!(plan.receivers.len() <= limits.max_receivers);
+            assert!(plan.receipt_indices.windows(2).all(|pair| pair[0] < pair[1]));
+            for &index in &plan.receipt_indices {
+                seen[index] += 1;
+                assert!(plan.receivers.binary_search(&payments[index].receiver).is_ok());
+            }
+            assert_eq!(plan.payments_tree_size, plan.receivers.len());
+        }
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_receivers_stay_together() {
+        let payments = payments_with_counts(&[7, 5, 4, 3, 3, 2, 1]);
+        let limits = BatchLimits::new(10, 3);
+        let plans = plan_batches(&payments, limits);
+        assert_valid(&plans, &payments, limits);
+        assert_eq!(plans, plan_batches(&payments, limits));
+
+        // 25 张收据至少 3 个批次，首次适应递减恰好装满
+        assert_eq!(plans.len(), 3);
+        assert!(plans.iter().all(|plan| !plan.is_split()));
+        let total_receivers: usize = plans.iter().map(|plan| plan.receivers.len()).sum();
+        assert_eq!(total_receivers, 7);
+        assert_eq!(plans[0].pay_ids_tree_size, 7);
+
+        // 只受接收者数限制
+        let payments = payments_with_counts(&[1, 1, 1, 1, 1]);
+        let plans = plan_batches(&payments, BatchLimits::new(100, 2));
+        assert_eq!(plans.iter().map(|plan| plan.receivers.len()).collect::<Vec<_>>(), vec![2, 2, 1]);
+
+        assert!(plan_batches(&[], limits).is_empty());
+    }
+
+    #[test]
+    fn test_oversized_receiver_split() {
+        let payments = payments_with_counts(&[25, 3, 4]);
+        let limits = BatchLimits::new(10, 2);
+        let plans = plan_batches(&payments, limits);
+        assert_valid(&plans, &payments, limits);
+
+        let big = [1u8; 20];
+        let holding: Vec<&BatchPlan> = plans.iter().filter(|plan| plan.receivers.contains(&big)).collect();
+        assert_eq!(holding.len(), 3);
+        assert!(holding.iter().all(|plan| plan.split_receivers == vec![big]));
+        // 其他接收者没有被拆开，也不会被标记
+        assert!(plans.iter().filter(|plan| !plan.receivers.contains(&big)).all(|plan| !plan.is_split()));
+        for receiver in [[2u8; 20], [3u8; 20]] {
+            assert_eq!(plans.iter().filter(|plan| plan.receivers.contains(&receiver)).count(), 1);
+        }
+
+        // 切出的块保持原顺序
+        let chunks: Vec<Vec<usize>> = holding
+            .iter()
+            .map(|plan| plan.receipt_indices.iter().copied().filter(|&index| payments[index].receiver == big).collect())
+            .collect();
+        assert_eq!(chunks.concat(), (0..25).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_materialized_batches_pass_overpay_check() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(1).with_receivers(5).with_random_payments(30, 100).build()?;
+        let limits = BatchLimits::new(8, 2);
+        let plans = plan_batches(&scenario.receipts, limits);
+        assert_valid(&plans, &scenario.receipts, limits);
+
+        for plan in &plans {
+            let infos = plan.pay_id_infos(&scenario.receipts, &scenario.pay_id_infos);
+            assert_eq!(infos.len(), plan.pay_ids_tree_size);
+            let result = plan
+                .overpay_checker(scenario.proxy, &scenario.receipts, &scenario.pay_id_infos)
+                .with_epoch(scenario.epoch)
+                .with_proxy_signature_check()
+                .with_sender_signature_check()
+                .process()?;
+            assert_eq!(result.receiver_proofs.len(), plan.payments_tree_size);
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,203 @@
-use alloy_primitives::{Address, B256, U256,keccak256};
+use alloy_primitives::{B256, U256,keccak256};
+use crate::addr::to_alloy;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 
-use crate::{BoxError, EthAddress, OverpayCheckResult, ProfitResult, ProxySettlementResult};
+use crate::models::{key_to_u256, PayIdInfo, SettlementTracker};
+use crate::receipts::PayIdsProcessor;
+use crate::{
+    format_eth_address, settlement_history_step, BoxError, CommitmentVersion, EthAddress, OverpayCheckResult, ProfitResult, ProxySettlementResult, TokenSubtotal,
+};
 
+/// ProfitResult 的接收者集合与 OverpayCheckResult 中的接收者集合不一致
+#[derive(Debug, PartialEq)]
+pub struct ReceiverCoverageError {
+    pub missing: Vec<EthAddress>,     // 在 overpay 结果中但没有 ProfitResult
+    pub extra: Vec<EthAddress>,       // 有 ProfitResult 但不在 overpay 结果中
+    pub duplicated: Vec<EthAddress>,  // 出现多次的 ProfitResult 接收者
+}
+
+impl fmt::Display for ReceiverCoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_list = |addrs: &[EthAddress]| {
+            addrs
+                .iter()
+                .map(|addr| to_alloy(*addr).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(
+            f,
+            "Receiver coverage mismatch: missing [{}], extra [{}], duplicated [{}]",
+            format_list(&self.missing),
+            format_list(&self.extra),
+            format_list(&self.duplicated)
+        )
+    }
+}
+
+impl std::error::Error for ReceiverCoverageError {}
+
+/// 聚合 ProfitResult 时的金额错误
+#[derive(Debug, PartialEq)]
+pub enum AggregationError {
+    /// 累加溢出，field 为溢出的字段
+    Overflow { field: &'static str },
+    /// 严格模式下 system_profit + proxy_profit + receiver_profit 与调用方给出的总额不一致
+    InconsistentProfit { receiver: EthAddress, expected: U256, actual: U256 },
+    /// 严格模式下没有给出该接收者的总额
+    MissingExpectedAmount(EthAddress),
+}
+
+impl fmt::Display for AggregationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregationError::Overflow { field } => write!(f, "Overflow while summing {}", field),
+            AggregationError::InconsistentProfit { receiver, expected, actual } => write!(
+                f,
+                "Profit result for {} sums to {}, expected {}",
+                to_alloy(*receiver),
+                actual,
+                expected
+            ),
+            AggregationError::MissingExpectedAmount(receiver) => {
+                write!(f, "No expected amount for receiver {}", to_alloy(*receiver))
+            }
+        }
+    }
+}
+
+impl std::error::Error for AggregationError {}
+
+/// 第 index 个 ProfitResult 的 field 与第一个结果不同；serv_ids_root 等字段取自第一个结果，不一致时不能聚合
+#[derive(Debug, PartialEq)]
+pub struct InconsistentProfitResult {
+    pub index: usize,
+    pub field: &'static str,
+}
+
+impl fmt::Display for InconsistentProfitResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Profit result {} has inconsistent {}", self.index, self.field)
+    }
+}
+
+impl std::error::Error for InconsistentProfitResult {}
+
+/// input（"Profit result" 或 "Overpay check"）所属的轮次与本轮结算的轮次不同
+#[derive(Debug, PartialEq)]
+pub struct SettlementEpochMismatch {
+    pub input: &'static str,
+    pub epoch: u64,
+    pub expected: u64,
+}
+
+impl fmt::Display for SettlementEpochMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} epoch {} does not match settlement epoch {}", self.input, self.epoch, self.expected)
+    }
+}
+
+impl std::error::Error for SettlementEpochMismatch {}
+
+/// overpay 检查结果的 pay_ids_root 与利润结果的不同
+#[derive(Debug, PartialEq)]
+pub struct PayIdsRootMismatch {
+    pub overpay: B256,
+    pub profit: B256,
+}
+
+impl fmt::Display for PayIdsRootMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Overpay check pay_ids_root {} does not match profit result pay_ids_root {}", self.overpay, self.profit)
+    }
+}
 
-pub struct ProxySettlementAggregator;
+impl std::error::Error for PayIdsRootMismatch {}
+
+/// 利润结果的 receipts_root 不是 overpay 检查结果的 payments_root：两者不是同一批收据，
+/// 接收者覆盖检查对另一批收据的结果没有意义
+#[derive(Debug, PartialEq)]
+pub struct ReceiptsRootMismatch {
+    pub overpay: B256,
+    pub profit: B256,
+}
+
+impl fmt::Display for ReceiptsRootMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Overpay check payments_root {} does not match profit result receipts_root {}", self.overpay, self.profit)
+    }
+}
+
+impl std::error::Error for ReceiptsRootMismatch {}
+
+/// 聚合得到的 settlement_id 已经被 settlement tracker 记录过
+#[derive(Debug, PartialEq)]
+pub struct DuplicateSettlementError {
+    pub settlement_id: B256,
+}
+
+impl fmt::Display for DuplicateSettlementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Settlement {} already tracked", self.settlement_id)
+    }
+}
+
+impl std::error::Error for DuplicateSettlementError {}
+
+// with_settlement_tracker 的状态：已记录的结算和结算历史链的当前值
+struct SettlementGuard {
+    tracker: Box<dyn SettlementTracker>,
+    history_hash: B256,
+}
+
+pub struct ProxySettlementAggregator {
+    allow_partial: bool, // 允许只结算 overpay 结果中的部分接收者
+    epoch: u64,          // 本轮结算的轮次，所有输入必须属于该轮
+    expected_amounts: Option<HashMap<EthAddress, U256>>, // 严格模式：每个接收者的收据总额
+    settlement_guard: Option<RefCell<SettlementGuard>>,  // 拒绝重复的 settlement_id
+}
 
 impl ProxySettlementAggregator {
     pub fn new() -> Self {
-        Self
+        Self { allow_partial: false, epoch: 0, expected_amounts: None, settlement_guard: None }
+    }
+
+    /// 部分结算：允许缺少接收者，但仍拒绝多余和重复的接收者
+    pub fn new_partial() -> Self {
+        Self { allow_partial: true, epoch: 0, expected_amounts: None, settlement_guard: None }
+    }
+
+    /// 设置结算轮次，默认为 0；ProfitResult 和 OverpayCheckResult 的 epoch 必须与之相同
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 严格模式：每个 ProfitResult 的三项利润之和必须等于调用方给出的该接收者收据总额
+    pub fn with_expected_amounts(mut self, expected_amounts: HashMap<EthAddress, U256>) -> Self {
+        self.expected_amounts = Some(expected_amounts);
+        self
+    }
+
+    /// 记录每次成功聚合的 settlement_id，已记录的 settlement_id 再次出现时返回 DuplicateSettlementError
+    ///
+    /// tracker 中每个结算的哈希为加入该结算之后的历史链哈希，从 start_history_hash 开始按
+    /// settlement_history_step 链接，与 SettlementProof 的规则相同；tracker 应只用于同一个代理
+    pub fn with_settlement_tracker(mut self, tracker: Box<dyn SettlementTracker>, start_history_hash: B256) -> Self {
+        self.settlement_guard = Some(RefCell::new(SettlementGuard { tracker, history_hash: start_history_hash }));
+        self
+    }
+
+    /// 结算历史链的当前值，没有设置 settlement tracker 时为 None
+    pub fn settlement_history_hash(&self) -> Option<B256> {
+        self.settlement_guard.as_ref().map(|guard| guard.borrow().history_hash)
+    }
+
+    /// 取回 settlement tracker
+    pub fn into_settlement_tracker(self) -> Option<Box<dyn SettlementTracker>> {
+        self.settlement_guard.map(|guard| guard.into_inner().tracker)
     }
 
     pub fn aggregate(
@@ -19,7 +209,66 @@ impl ProxySettlementAggregator {
         self.pre_validate(&profit_results, &overpay_result)?;
 
         // 2. 计算聚合结果
-        self.calculate_aggregate_result(profit_results)
+        let result = self.calculate_aggregate_result(profit_results)?;
+
+        // 3. 记录结算，拒绝重复的 settlement_id
+        self.track_settlement(&result)?;
+        Ok(result)
+    }
+
+    /// 在聚合的同时验证结算总额不超过 pay_ids_root 中承诺的存款总额
+    pub fn aggregate_with_deposits(
+        &self,
+        profit_results: Vec<ProfitResult>,
+        overpay_result: OverpayCheckResult,
+        pay_id_infos: &[PayIdInfo],
+    ) -> Result<ProxySettlementResult, BoxError> {
+        self.pre_validate(&profit_results, &overpay_result)?;
+
+        // 1. 重新计算 pay_ids_root，必须与共享的 pay_ids_root 一致
+        let pay_ids_root = PayIdsProcessor::get_root_hash(pay_id_infos)?;
+        if pay_ids_root != profit_results[0].pay_ids_root {
+            return Err("PayIdInfos do not match pay_ids_root".into());
+        }
+
+        // 2. 按代币统计存款总额，不同代币的金额不能相加
+        let mut deposits: HashMap<EthAddress, U256> = HashMap::new();
+        for info in pay_id_infos {
+            let total = deposits.entry(info.token).or_default();
+            *total = total.checked_add(info.amount).ok_or("Deposit overflow")?;
+        }
+
+        // 3. 每种代币的结算总额不能超过该代币的存款总额
+        let result = self.calculate_aggregate_result(profit_results)?;
+        for (token, amount) in result.token_amounts().map_err(|field| AggregationError::Overflow { field })? {
+            let total_deposits = deposits.get(&token).copied().unwrap_or_default();
+            if amount > total_deposits {
+                return Err(format!(
+                    "Settlement amount {} of token {} exceeds total deposits {}",
+                    amount,
+                    format_eth_address(&token),
+                    total_deposits
+                )
+                .into());
+            }
+        }
+
+        self.track_settlement(&result)?;
+        Ok(result)
+    }
+
+    fn track_settlement(&self, result: &ProxySettlementResult) -> Result<(), DuplicateSettlementError> {
+        let Some(guard) = &self.settlement_guard else {
+            return Ok(());
+        };
+        let mut guard = guard.borrow_mut();
+        if guard.tracker.has_settlement(result.settlement_id) {
+            return Err(DuplicateSettlementError { settlement_id: result.settlement_id });
+        }
+        guard.history_hash = settlement_history_step(guard.history_hash, result.settlement_id);
+        let history_hash = guard.history_hash;
+        guard.tracker.track_settlement(key_to_u256(result.settlement_id), history_hash);
+        Ok(())
     }
 
     fn pre_validate(
@@ -32,28 +281,116 @@ impl ProxySettlementAggregator {
         }
 
         let first_result = &profit_results[0];
-        let proxy = first_result.proxy;
         let pay_ids_root = first_result.pay_ids_root;
-        let receipts_root = first_result.receipts_root;
 
         // 验证所有结果的一致性
+        for (index, profit_result) in profit_results.iter().enumerate() {
+            let field = if profit_result.epoch != first_result.epoch {
+                "epoch"
+            } else if profit_result.vks_hash != first_result.vks_hash {
+                "vks_hash"
+            } else if profit_result.proxy != first_result.proxy {
+                "proxy address"
+            } else if profit_result.pay_ids_root != first_result.pay_ids_root {
+                "pay_ids_root"
+            } else if profit_result.receipts_root != first_result.receipts_root {
+                "receipts_root"
+            } else if profit_result.serv_ids_root != first_result.serv_ids_root {
+                "serv_ids_root"
+            } else {
+                continue;
+            };
+            return Err(InconsistentProfitResult { index, field }.into());
+        }
+        if first_result.epoch != self.epoch {
+            return Err(SettlementEpochMismatch { input: "Profit result", epoch: first_result.epoch, expected: self.epoch }.into());
+        }
+
+        if overpay_result.pay_ids_root != pay_ids_root {
+            return Err(PayIdsRootMismatch { overpay: overpay_result.pay_ids_root, profit: pay_ids_root }.into());
+        }
+        if overpay_result.payments_root != first_result.receipts_root {
+            return Err(ReceiptsRootMismatch { overpay: overpay_result.payments_root, profit: first_result.receipts_root }.into());
+        }
+        if overpay_result.epoch != self.epoch {
+            return Err(SettlementEpochMismatch { input: "Overpay check", epoch: overpay_result.epoch, expected: self.epoch }.into());
+        }
+
+        // 同一次聚合中的所有根必须按同一承诺方案计算
+        CommitmentVersion::common(
+            std::iter::once(overpay_result.commitment_version)
+                .chain(profit_results.iter().map(|profit_result| profit_result.commitment_version)),
+        )?;
+
+        self.validate_receiver_coverage(profit_results, overpay_result)?;
+
         for profit_result in profit_results {
-            if profit_result.proxy != proxy {
-                return Err("Inconsistent proxy address".into());
-            }
-            if profit_result.pay_ids_root != pay_ids_root {
-                return Err("Inconsistent pay_ids_root".into());
+            self.validate_profit_result(profit_result)?;
+        }
+
+        Ok(())
+    }
+
+    /// 单个 ProfitResult 的三项利润之和不能溢出，严格模式下还必须等于期望的总额
+    fn validate_profit_result(&self, profit_result: &ProfitResult) -> Result<(), AggregationError> {
+        let actual = profit_result
+            .system_profit
+            .checked_add(profit_result.proxy_profit)
+            .and_then(|sum| sum.checked_add(profit_result.receiver_profit))
+            .ok_or(AggregationError::Overflow { field: "profit result" })?;
+
+        if let Some(expected_amounts) = &self.expected_amounts {
+            let expected = *expected_amounts
+                .get(&profit_result.receiver)
+                .ok_or(AggregationError::MissingExpectedAmount(profit_result.receiver))?;
+            if actual != expected {
+                return Err(AggregationError::InconsistentProfit {
+                    receiver: profit_result.receiver,
+                    expected,
+                    actual,
+                });
             }
-            if profit_result.receipts_root != receipts_root {
-                return Err("Inconsistent receipts_root".into());
+        }
+
+        Ok(())
+    }
+
+    /// 验证 ProfitResult 与 overpay 结果中的接收者一一对应
+    fn validate_receiver_coverage(
+        &self,
+        profit_results: &[ProfitResult],
+        overpay_result: &OverpayCheckResult,
+    ) -> Result<(), BoxError> {
+        let expected: BTreeSet<EthAddress> = overpay_result
+            .receiver_proofs
+            .iter()
+            .map(|proof| proof.receiver)
+            .collect();
+
+        let mut seen = BTreeSet::new();
+        let mut duplicated = Vec::new();
+        for profit_result in profit_results {
+            if !seen.insert(profit_result.receiver) {
+                duplicated.push(profit_result.receiver);
             }
         }
 
-        if overpay_result.pay_ids_root != pay_ids_root {
-            return Err("Overpay check pay_ids_root mismatch".into());
+        let missing: Vec<EthAddress> = if self.allow_partial {
+            Vec::new()
+        } else {
+            expected.difference(&seen).copied().collect()
+        };
+        let extra: Vec<EthAddress> = seen.difference(&expected).copied().collect();
+
+        if missing.is_empty() && extra.is_empty() && duplicated.is_empty() {
+            return Ok(());
         }
 
-        Ok(())
+        Err(Box::new(ReceiverCoverageError {
+            missing,
+            extra,
+            duplicated,
+        }))
     }
 
     fn calculate_aggregate_result(
@@ -61,26 +398,40 @@ impl ProxySettlementAggregator {
         profit_results: Vec<ProfitResult>,
     ) -> Result<ProxySettlementResult, BoxError> {
         let first_result = &profit_results[0].clone();
+        let vks_hash = first_result.vks_hash;
         let proxy = first_result.proxy;
         let pay_ids_root = first_result.pay_ids_root;
         let serv_ids_root = first_result.serv_ids_root;
+        // validate_inputs 已检查所有版本兼容，取共同的版本（Legacy 与 V1 混合时为 V1）
+        let commitment_version = CommitmentVersion::common(profit_results.iter().map(|result| result.commitment_version))?
+            .unwrap_or_default();
 
         // 累计所有利润
         let mut system_profits = U256::ZERO;
         let mut proxy_profits = U256::ZERO;
         let mut receiver_profits = U256::ZERO;
 
+        let checked_add = |total: U256, value: U256, field: &'static str| {
+            total.checked_add(value).ok_or(AggregationError::Overflow { field })
+        };
+        let mut token_totals = Vec::new();
         for profit_result in profit_results {
-            system_profits += profit_result.system_profit;
-            proxy_profits += profit_result.proxy_profit;
-            receiver_profits += profit_result.receiver_profit;
+            system_profits = checked_add(system_profits, profit_result.system_profit, "system_profits")?;
+            proxy_profits = checked_add(proxy_profits, profit_result.proxy_profit, "proxy_profits")?;
+            receiver_profits = checked_add(receiver_profits, profit_result.receiver_profit, "receiver_profits")?;
+            for subtotal in &profit_result.token_subtotals() {
+                TokenSubtotal::accumulate(&mut token_totals, subtotal)
+                    .map_err(|field| AggregationError::Overflow { field })?;
+            }
         }
+        TokenSubtotal::normalize(&mut token_totals);
 
         // 计算总金额
-        let amount = system_profits + proxy_profits + receiver_profits;
+        let amount = checked_add(system_profits, proxy_profits, "amount")?;
+        let amount = checked_add(amount, receiver_profits, "amount")?;
 
         let mut profit_result = ProxySettlementResult {
-            vks_hash: B256::ZERO,
+            vks_hash,
             settlement_id:B256::ZERO,
             proxy,
             pay_ids_root,
@@ -88,6 +439,9 @@ impl ProxySettlementAggregator {
             system_profits,
             proxy_profits,
             amount,
+            epoch: self.epoch,
+            token_totals,
+            commitment_version,
         };
         profit_result.build_settlement_id();
 
@@ -99,6 +453,667 @@ impl ProxySettlementAggregator {
    
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::segment_vc::{MerkleProof, SegmentProof, ValueProof};
+    use crate::ReceiverProof;
+
+    fn create_test_profit_result(receiver: EthAddress, vks_hash: B256) -> ProfitResult {
+        ProfitResult {
+            vks_hash,
+            receiver,
+            proxy: [1u8; 20],
+            receipts_root: B256::repeat_byte(2),
+            pay_ids_root: B256::repeat_byte(3),
+            serv_ids_root: B256::repeat_byte(4),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        }
+    }
+
+    fn create_test_receiver_proof(receiver: EthAddress) -> ReceiverProof {
+        ReceiverProof {
+            receiver,
+            proof: MerkleProof {
+                value_proof: ValueProof {
+                    value: B256::ZERO,
+                    chunk_hash: B256::ZERO,
+                },
+                segment_proof: SegmentProof {
+                    chunk_index: 0,
+                    siblings: vec![],
+                },
+                level_proofs: vec![],
+                root_hash: B256::repeat_byte(2),
+                hasher: Default::default(),
+                padded: false,
+            },
+        }
+    }
+
+    fn create_test_overpay_result(receivers: &[EthAddress]) -> OverpayCheckResult {
+        OverpayCheckResult {
+            payments_root: B256::repeat_byte(2),
+            receiver_proofs: receivers
+                .iter()
+                .map(|receiver| create_test_receiver_proof(*receiver))
+                .collect(),
+            pay_ids_root: B256::repeat_byte(3),
+            epoch: 0,
+            commitment_version: CommitmentVersion::Legacy,
+            excluded_root: None,
+            referenced_pay_ids_root: None,
+        }
+    }
+
+    fn coverage_error(err: BoxError) -> ReceiverCoverageError {
+        let err = err
+            .downcast::<ReceiverCoverageError>()
+            .expect("expected ReceiverCoverageError");
+        *err
+    }
+
+    #[test]
+    fn test_aggregate_propagates_vks_hash() -> Result<(), BoxError> {
+        let vks_hash = B256::repeat_byte(9);
+        let profit_results = vec![
+            create_test_profit_result([5u8; 20], vks_hash),
+            create_test_profit_result([6u8; 20], vks_hash),
+        ];
+
+        let result = ProxySettlementAggregator::new()
+            .aggregate(profit_results, create_test_overpay_result(&[[5u8; 20], [6u8; 20]]))?;
+
+        assert_eq!(result.vks_hash, vks_hash);
+        assert_eq!(result.amount, U256::from(200u32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_commitment_versions_rejected() -> Result<(), BoxError> {
+        use crate::CommitmentError;
+
+        let receivers = [[5u8; 20], [6u8; 20]];
+        let mut profit_results: Vec<ProfitResult> = receivers
+            .iter()
+            .map(|receiver| create_test_profit_result(*receiver, B256::repeat_byte(9)))
+            .collect();
+        let overpay_result = create_test_overpay_result(&receivers);
+        let aggregator = ProxySettlementAggregator::new();
+        let mixed_error = |err: BoxError| *err.downcast::<CommitmentError>().expect("expected CommitmentError");
+
+        // 同一版本可以聚合，结果带有该版本
+        let result = aggregator.aggregate(profit_results.clone(), overpay_result.clone())?;
+        assert_eq!(result.commitment_version, CommitmentVersion::Legacy);
+
+        // ProfitResult 之间的版本不同
+        profit_results[1].commitment_version = CommitmentVersion::V2;
+        let err = aggregator.aggregate(profit_results.clone(), overpay_result.clone()).unwrap_err();
+        assert_eq!(
+            mixed_error(err),
+            CommitmentError::MixedVersions { expected: CommitmentVersion::Legacy, actual: CommitmentVersion::V2 }
+        );
+
+        // ProfitResult 与 overpay 结果的版本不同
+        profit_results[0].commitment_version = CommitmentVersion::V2;
+        let err = aggregator.aggregate(profit_results.clone(), overpay_result.clone()).unwrap_err();
+        assert_eq!(
+            mixed_error(err),
+            CommitmentError::MixedVersions { expected: CommitmentVersion::Legacy, actual: CommitmentVersion::V2 }
+        );
+
+        // 按 sol 结构解码的结果与新计算的结果哈希相同，可以一起聚合
+        profit_results[0].commitment_version = CommitmentVersion::V1;
+        profit_results[1].commitment_version = CommitmentVersion::Legacy;
+        let result = aggregator.aggregate(profit_results.clone(), overpay_result.clone())?;
+        assert_eq!(result.commitment_version, CommitmentVersion::V1);
+
+        profit_results[0].commitment_version = CommitmentVersion::V2;
+        profit_results[1].commitment_version = CommitmentVersion::V2;
+        let overpay_result = overpay_result.with_commitment_version(CommitmentVersion::V2);
+        let result = aggregator.aggregate(profit_results, overpay_result)?;
+        assert_eq!(result.commitment_version, CommitmentVersion::V2);
+        assert_eq!(result.versioned_pay_ids_root().pack()[0], CommitmentVersion::V2.as_byte());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inconsistent_vks_hash() {
+        let profit_results = vec![
+            create_test_profit_result([5u8; 20], B256::repeat_byte(9)),
+            create_test_profit_result([6u8; 20], B256::repeat_byte(8)),
+        ];
+
+        let result = ProxySettlementAggregator::new()
+            .aggregate(profit_results, create_test_overpay_result(&[[5u8; 20], [6u8; 20]]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_receiver() {
+        let profit_results = vec![create_test_profit_result([5u8; 20], B256::ZERO)];
+        let overpay_result = create_test_overpay_result(&[[5u8; 20], [6u8; 20]]);
+
+        let err = ProxySettlementAggregator::new()
+            .aggregate(profit_results.clone(), create_test_overpay_result(&[[5u8; 20], [6u8; 20]]))
+            .unwrap_err();
+        let err = coverage_error(err);
+        assert_eq!(err.missing, vec![[6u8; 20]]);
+        assert!(err.extra.is_empty());
+        assert!(err.duplicated.is_empty());
+
+        // 部分结算模式允许缺少接收者
+        assert!(ProxySettlementAggregator::new_partial()
+            .aggregate(profit_results, overpay_result)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_duplicated_receiver() {
+        let profit_results = vec![
+            create_test_profit_result([5u8; 20], B256::ZERO),
+            create_test_profit_result([5u8; 20], B256::ZERO),
+        ];
+
+        let err = ProxySettlementAggregator::new_partial()
+            .aggregate(profit_results, create_test_overpay_result(&[[5u8; 20]]))
+            .unwrap_err();
+        let err = coverage_error(err);
+        assert_eq!(err.duplicated, vec![[5u8; 20]]);
+    }
+
+    #[test]
+    fn test_extra_receiver() {
+        let profit_results = vec![
+            create_test_profit_result([5u8; 20], B256::ZERO),
+            create_test_profit_result([7u8; 20], B256::ZERO),
+        ];
+
+        let err = ProxySettlementAggregator::new_partial()
+            .aggregate(profit_results, create_test_overpay_result(&[[5u8; 20]]))
+            .unwrap_err();
+        let err = coverage_error(err);
+        assert_eq!(err.extra, vec![[7u8; 20]]);
+    }
+
+    fn create_test_pay_id_info(id: u64, amount: u64) -> PayIdInfo {
+        PayIdInfo {
+            id: U256::from(id),
+            amount: U256::from(amount),
+            sender: [8u8; 20],
+            proxy: [1u8; 20],
+            state: 1,
+            created_at: 0,
+            closing_time: 0,
+            token: [0u8; 20],
+            sig_sender: None,
+        }
+    }
+
+    fn create_rooted_inputs(
+        pay_ids_root: B256,
+        receiver_profit: u64,
+    ) -> (Vec<ProfitResult>, OverpayCheckResult) {
+        let receivers = [[5u8; 20], [6u8; 20]];
+        let profit_results = receivers
+            .iter()
+            .map(|receiver| ProfitResult {
+                pay_ids_root,
+                receiver_profit: U256::from(receiver_profit),
+                ..create_test_profit_result(*receiver, B256::ZERO)
+            })
+            .collect();
+        let mut overpay_result = create_test_overpay_result(&receivers);
+        overpay_result.pay_ids_root = pay_ids_root;
+        (profit_results, overpay_result)
+    }
+
+    #[test]
+    fn test_deposits_cover_settlement() -> Result<(), BoxError> {
+        let pay_id_infos = vec![create_test_pay_id_info(1, 100), create_test_pay_id_info(2, 100)];
+        let pay_ids_root = PayIdsProcessor::get_root_hash(&pay_id_infos)?;
+
+        // 每个接收者 10 + 20 + 70 = 100，总额 200 等于存款总额
+        let (profit_results, overpay_result) = create_rooted_inputs(pay_ids_root, 70);
+        let result = ProxySettlementAggregator::new()
+            .aggregate_with_deposits(profit_results, overpay_result, &pay_id_infos)?;
+        assert_eq!(result.amount, U256::from(200u32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inflated_profit_exceeds_deposits() -> Result<(), BoxError> {
+        let pay_id_infos = vec![create_test_pay_id_info(1, 100), create_test_pay_id_info(2, 100)];
+        let pay_ids_root = PayIdsProcessor::get_root_hash(&pay_id_infos)?;
+
+        let (profit_results, overpay_result) = create_rooted_inputs(pay_ids_root, 71);
+        let result = ProxySettlementAggregator::new()
+            .aggregate_with_deposits(profit_results, overpay_result, &pay_id_infos);
+        assert!(result.unwrap_err().to_string().contains("exceeds total deposits"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatched_pay_ids_root() -> Result<(), BoxError> {
+        let pay_id_infos = vec![create_test_pay_id_info(1, 100), create_test_pay_id_info(2, 100)];
+
+        let (profit_results, overpay_result) = create_rooted_inputs(B256::repeat_byte(3), 70);
+        let result = ProxySettlementAggregator::new()
+            .aggregate_with_deposits(profit_results, overpay_result, &pay_id_infos);
+        assert!(result.unwrap_err().to_string().contains("pay_ids_root"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overpay_result_for_another_batch() -> Result<(), BoxError> {
+        use crate::fixtures::ScenarioBuilder;
+        use crate::ReceiptsOverpayChecker;
+
+        let scenario = ScenarioBuilder::new(31)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 400)
+            .with_payment(2, 1, 1, 700)
+            .with_fee_config(1, 500, 1000)
+            .build()?;
+        let overpay_result = scenario.overpay_checker().process()?;
+        let receiver = scenario.receiver(0);
+        let profit_result = scenario.profit_calculator(receiver, overpay_result.get_merkle_proof(receiver)?).calculate()?;
+
+        // 对本批收据的结果缺少 receiver 1
+        let err = scenario.aggregator().aggregate(vec![profit_result.clone()], overpay_result.clone()).unwrap_err();
+        assert_eq!(coverage_error(err).missing, vec![scenario.receiver(1)]);
+
+        // 同一组 PayIdInfo、只含 receiver 0 的另一批收据：pay_ids_root 和接收者集合都相符，payments_root 不同
+        let other_batch =
+            ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), scenario.receipts_for(&receiver))
+                .with_epoch(scenario.epoch)
+                .process()?;
+        assert_eq!(other_batch.pay_ids_root, overpay_result.pay_ids_root);
+        assert_eq!(other_batch.receiver_proofs.len(), 1);
+        let err = scenario.aggregator().aggregate(vec![profit_result.clone()], other_batch.clone()).unwrap_err();
+        let mismatch = err.downcast_ref::<ReceiptsRootMismatch>().ok_or("Expected ReceiptsRootMismatch")?;
+        assert_eq!(
+            *mismatch,
+            ReceiptsRootMismatch { overpay: other_batch.payments_root, profit: profit_result.receipts_root }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_receiver_match() {
+        let profit_results = vec![
+            create_test_profit_result([6u8; 20], B256::ZERO),
+            create_test_profit_result([5u8; 20], B256::ZERO),
+        ];
+
+        assert!(ProxySettlementAggregator::new()
+            .aggregate(profit_results, create_test_overpay_result(&[[5u8; 20], [6u8; 20]]))
+            .is_ok());
+    }
+
+    fn with_epoch(epoch: u64) -> (Vec<ProfitResult>, OverpayCheckResult) {
+        let receivers = [[5u8; 20], [6u8; 20]];
+        let profit_results = receivers
+            .iter()
+            .map(|receiver| ProfitResult {
+                epoch,
+                ..create_test_profit_result(*receiver, B256::ZERO)
+            })
+            .collect();
+        (profit_results, create_test_overpay_result(&receivers).with_epoch(epoch))
+    }
+
+    #[test]
+    fn test_epoch_scopes_settlement_id() -> Result<(), BoxError> {
+        let (profit_results, overpay_result) = with_epoch(0);
+        let round_0 = ProxySettlementAggregator::new().aggregate(profit_results, overpay_result)?;
+
+        // epoch 为 0 时与引入 epoch 之前的算法一致（v1 哈希方案）
+        if crate::HashScheme::ACTIVE == crate::HashScheme::V1 {
+            let mut data = Vec::new();
+            data.extend_from_slice(&round_0.proxy);
+            data.extend_from_slice(round_0.pay_ids_root.as_slice());
+            data.extend_from_slice(round_0.serv_ids_root.as_slice());
+            data.extend_from_slice(&round_0.system_profits.to_be_bytes::<32>());
+            data.extend_from_slice(&round_0.proxy_profits.to_be_bytes::<32>());
+            data.extend_from_slice(&round_0.amount.to_be_bytes::<32>());
+            let mut data2 = keccak256(&data).to_vec();
+            data2.extend_from_slice(round_0.pay_ids_root.as_slice());
+            assert_eq!(round_0.settlement_id, keccak256(&data2));
+        }
+
+        // 相同输入、不同轮次得到不同的 settlement_id
+        let (profit_results, overpay_result) = with_epoch(7);
+        let round_7 = ProxySettlementAggregator::new()
+            .with_epoch(7)
+            .aggregate(profit_results, overpay_result)?;
+        assert_eq!(round_7.epoch, 7);
+        assert_eq!(round_7.amount, round_0.amount);
+        assert_ne!(round_7.settlement_id, round_0.settlement_id);
+        assert!(round_7.verify_settlement_id(round_7.pay_ids_root));
+
+        // epoch 经过 sol 结构往返后仍然保留
+        let round_7_sol = round_7.to_struct().to_result();
+        assert_eq!(round_7_sol.epoch, 7);
+        assert!(round_7_sol.verify_settlement_id(round_7_sol.pay_ids_root));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_settlement_rejected() -> Result<(), BoxError> {
+        use crate::models::{CircularSettlementTracker, InMemorySettlementTracker};
+
+        let start = B256::repeat_byte(1);
+        let trackers: [Box<dyn SettlementTracker>; 2] = [
+            Box::new(InMemorySettlementTracker::new()),
+            Box::new(CircularSettlementTracker::new(4)?),
+        ];
+        for tracker in trackers {
+            let aggregator = ProxySettlementAggregator::new().with_settlement_tracker(tracker, start);
+            let (profit_results, overpay_result) = with_epoch(0);
+            let round_0 = aggregator.aggregate(profit_results.clone(), overpay_result.clone())?;
+            let history = settlement_history_step(start, round_0.settlement_id);
+            assert_eq!(aggregator.settlement_history_hash(), Some(history));
+
+            // 同一结算再次聚合被拒绝，历史链不变
+            let err = aggregator.aggregate(profit_results, overpay_result).unwrap_err();
+            assert_eq!(
+                *err.downcast::<DuplicateSettlementError>().expect("expected DuplicateSettlementError"),
+                DuplicateSettlementError { settlement_id: round_0.settlement_id }
+            );
+            assert_eq!(aggregator.settlement_history_hash(), Some(history));
+
+            // 新的轮次继续链接
+            let aggregator = aggregator.with_epoch(7);
+            let (profit_results, overpay_result) = with_epoch(7);
+            let round_7 = aggregator.aggregate(profit_results, overpay_result)?;
+            let history = settlement_history_step(history, round_7.settlement_id);
+            assert_eq!(aggregator.settlement_history_hash(), Some(history));
+
+            let tracker = aggregator.into_settlement_tracker().expect("tracker");
+            assert_eq!(tracker.len(), 2);
+            assert_eq!(tracker.get_settlement_hash(round_7.settlement_id), Some(history));
+        }
+
+        // 没有 tracker 时允许重复聚合
+        let (profit_results, overpay_result) = with_epoch(0);
+        let aggregator = ProxySettlementAggregator::new();
+        aggregator.aggregate(profit_results.clone(), overpay_result.clone())?;
+        aggregator.aggregate(profit_results, overpay_result)?;
+        assert_eq!(aggregator.settlement_history_hash(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_epoch_mismatch_rejected() {
+        // ProfitResult 属于第 7 轮，OverpayCheckResult 属于第 8 轮
+        let (profit_results, _) = with_epoch(7);
+        let (_, overpay_result) = with_epoch(8);
+        let err = ProxySettlementAggregator::new()
+            .with_epoch(7)
+            .aggregate(profit_results, overpay_result)
+            .unwrap_err();
+        assert!(err.to_string().contains("epoch"));
+
+        // 输入一致但与聚合器的轮次不同
+        let (profit_results, overpay_result) = with_epoch(7);
+        assert!(ProxySettlementAggregator::new()
+            .aggregate(profit_results, overpay_result)
+            .is_err());
+
+        // ProfitResult 之间的轮次不一致
+        let (mut profit_results, overpay_result) = with_epoch(7);
+        profit_results[1].epoch = 6;
+        assert!(ProxySettlementAggregator::new()
+            .with_epoch(7)
+            .aggregate(profit_results, overpay_result)
+            .is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_serv_ids_root() -> Result<(), BoxError> {
+        use crate::fixtures::ScenarioBuilder;
+        use crate::receipts::profit_calculator::ReceiptsProfitCalculator;
+
+        let scenario = ScenarioBuilder::new(21)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 1, 1, 2000)
+            .build()?;
+        let overpay_result = scenario.overpay_checker().process()?;
+        let profit_result = |index: usize, service_configs: Vec<_>| {
+            let receiver = scenario.receiver(index);
+            ReceiptsProfitCalculator::new(
+                B256::ZERO,
+                receiver,
+                scenario.proxy,
+                scenario.receipts_for(&receiver),
+                overpay_result.get_merkle_proof(receiver)?,
+                scenario.pay_id_infos.clone(),
+                service_configs,
+            )
+            .calculate()
+        };
+
+        let first = profit_result(0, scenario.service_configs.clone())?;
+        let second = profit_result(1, scenario.service_configs.clone())?;
+        assert!(ProxySettlementAggregator::new()
+            .aggregate(vec![first.clone(), second], overpay_result.clone())
+            .is_ok());
+
+        // 第二个接收者按另一套费率计算
+        let mut service_configs = scenario.service_configs.clone();
+        service_configs[0].proxy_fee_rate += 100;
+        let second = profit_result(1, service_configs)?;
+        assert_ne!(second.serv_ids_root, first.serv_ids_root);
+
+        let err = ProxySettlementAggregator::new()
+            .aggregate(vec![first, second], overpay_result)
+            .unwrap_err();
+        let err = err.downcast::<InconsistentProfitResult>().expect("expected InconsistentProfitResult");
+        assert_eq!(*err, InconsistentProfitResult { index: 1, field: "serv_ids_root" });
+        Ok(())
+    }
+
+    fn aggregation_error(err: BoxError) -> AggregationError {
+        *err.downcast::<AggregationError>()
+            .expect("expected AggregationError")
+    }
+
+    #[test]
+    fn test_aggregate_overflow_rejected() {
+        let receivers = [[5u8; 20], [6u8; 20]];
+        let profit_results = receivers
+            .iter()
+            .map(|receiver| ProfitResult {
+                system_profit: U256::MAX,
+                proxy_profit: U256::ZERO,
+                receiver_profit: U256::ZERO,
+                ..create_test_profit_result(*receiver, B256::ZERO)
+            })
+            .collect();
+
+        let err = ProxySettlementAggregator::new()
+            .aggregate(profit_results, create_test_overpay_result(&receivers))
+            .unwrap_err();
+        assert_eq!(aggregation_error(err), AggregationError::Overflow { field: "system_profits" });
+
+        // 单个结果内部三项之和溢出
+        let profit_results = vec![ProfitResult {
+            system_profit: U256::MAX,
+            ..create_test_profit_result([5u8; 20], B256::ZERO)
+        }];
+        let err = ProxySettlementAggregator::new_partial()
+            .aggregate(profit_results, create_test_overpay_result(&receivers))
+            .unwrap_err();
+        assert_eq!(aggregation_error(err), AggregationError::Overflow { field: "profit result" });
+    }
+
+    #[test]
+    fn test_strict_expected_amounts() -> Result<(), BoxError> {
+        let receivers = [[5u8; 20], [6u8; 20]];
+        let expected: HashMap<EthAddress, U256> =
+            receivers.iter().map(|receiver| (*receiver, U256::from(100u32))).collect();
+        let consistent = || {
+            receivers
+                .iter()
+                .map(|receiver| create_test_profit_result(*receiver, B256::ZERO))
+                .collect::<Vec<_>>()
+        };
+
+        let result = ProxySettlementAggregator::new()
+            .with_expected_amounts(expected.clone())
+            .aggregate(consistent(), create_test_overpay_result(&receivers))?;
+        assert_eq!(result.amount, U256::from(200u32));
+
+        // 接收者 6 多报了 1 的利润
+        let mut inconsistent = consistent();
+        inconsistent[1].receiver_profit = U256::from(71u32);
+        let aggregator = ProxySettlementAggregator::new().with_expected_amounts(expected);
+        let err = aggregator
+            .aggregate(inconsistent.clone(), create_test_overpay_result(&receivers))
+            .unwrap_err();
+        assert_eq!(
+            aggregation_error(err),
+            AggregationError::InconsistentProfit {
+                receiver: [6u8; 20],
+                expected: U256::from(100u32),
+                actual: U256::from(101u32),
+            }
+        );
+
+        // 非严格模式不检查
+        assert!(ProxySettlementAggregator::new()
+            .aggregate(inconsistent, create_test_overpay_result(&receivers))
+            .is_ok());
+
+        // 严格模式下缺少期望总额
+        let err = ProxySettlementAggregator::new()
+            .with_expected_amounts(HashMap::new())
+            .aggregate(consistent(), create_test_overpay_result(&receivers))
+            .unwrap_err();
+        assert_eq!(aggregation_error(err), AggregationError::MissingExpectedAmount([5u8; 20]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_subtotals() -> Result<(), BoxError> {
+        use crate::fixtures::ScenarioBuilder;
+        use crate::{TokenSubtotal, NATIVE_TOKEN};
+
+        let (token_a, token_b) = ([0xaau8; 20], [0xbbu8; 20]);
+        let scenario = ScenarioBuilder::new(22)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 1, 1, 2000)
+            .with_payment(3, 1, 0, 4000)
+            .with_payment(4, 1, 1, 10_000)
+            .with_token(2, token_a)
+            .with_token(3, token_a)
+            .with_token(4, token_b)
+            .build()?;
+        let overpay_result = scenario.overpay_checker().process()?;
+        let profit_results = (0..2)
+            .map(|index| {
+                let receiver = scenario.receiver(index);
+                scenario.profit_calculator(receiver, overpay_result.get_merkle_proof(receiver)?).calculate()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // 默认费率 5% / 10%
+        let subtotal = |token, system: u64, proxy: u64, receiver: u64| TokenSubtotal {
+            token,
+            system_profit: U256::from(system),
+            proxy_profit: U256::from(proxy),
+            receiver_profit: U256::from(receiver),
+        };
+        assert_eq!(
+            profit_results[0].token_totals,
+            vec![subtotal(NATIVE_TOKEN, 50, 100, 850), subtotal(token_a, 200, 400, 3400)]
+        );
+        assert_eq!(
+            profit_results[1].token_totals,
+            vec![subtotal(token_a, 100, 200, 1700), subtotal(token_b, 500, 1000, 8500)]
+        );
+
+        let totals = |result: &ProfitResult| (result.system_profit, result.proxy_profit, result.receiver_profit);
+        let profit_results_totals = (totals(&profit_results[0]), totals(&profit_results[1]));
+        let decoded = ProfitResult::from_public_values(&profit_results[1].to_public_values())?;
+        assert_eq!(decoded.token_totals, profit_results[1].token_totals);
+
+        // 存款按代币比较：token_b 的结算超出 token_b 的存款时拒绝，即使所有代币的存款之和足够
+        let deposits = &scenario.pay_id_infos;
+        scenario.aggregator().aggregate_with_deposits(profit_results.clone(), overpay_result.clone(), deposits)?;
+        let mut inflated = profit_results.clone();
+        inflated[1].token_totals[1].receiver_profit += U256::from(1);
+        let err = scenario.aggregator().aggregate_with_deposits(inflated, overpay_result.clone(), deposits).unwrap_err();
+        assert!(err.to_string().contains("exceeds total deposits 10000"));
+
+        let settlement = scenario.aggregator().aggregate(profit_results, overpay_result)?;
+        assert_eq!(
+            settlement.token_totals,
+            vec![
+                subtotal(NATIVE_TOKEN, 50, 100, 850),
+                subtotal(token_a, 300, 600, 5100),
+                subtotal(token_b, 500, 1000, 8500),
+            ]
+        );
+        // 总额只包括原生代币，其他代币只在小计中
+        assert_eq!(profit_results_totals.0, (U256::from(50), U256::from(100), U256::from(850)));
+        assert_eq!(profit_results_totals.1, (U256::ZERO, U256::ZERO, U256::ZERO));
+        assert_eq!(settlement.system_profits, U256::from(50));
+        assert_eq!(settlement.amount, U256::from(1000));
+        assert_eq!(
+            settlement.token_amounts(),
+            Ok(vec![(NATIVE_TOKEN, U256::from(1000)), (token_a, U256::from(6000)), (token_b, U256::from(10_000))])
+        );
+
+        // 小计随 public values 往返，可以按 public values 重新计算 settlement_id
+        let decoded = ProxySettlementResult::from_public_values(&settlement.to_public_values())?;
+        assert_eq!(decoded.token_totals, settlement.token_totals);
+        assert!(decoded.verify_settlement_id(decoded.pay_ids_root));
+
+        // settlement_id 覆盖按代币的小计
+        let mut tampered = settlement.clone();
+        tampered.token_totals[1].proxy_profit += U256::from(1);
+        assert_ne!(tampered.calculate_settlement_id(tampered.pay_ids_root), settlement.settlement_id);
+
+        // 只有原生代币时没有小计，settlement_id 与之前相同
+        let native = ScenarioBuilder::new(22).with_receivers(2).with_payment(1, 1, 0, 1000).build()?;
+        let overpay_result = native.overpay_checker().process()?;
+        let receiver = native.receiver(0);
+        let profit_result = native.profit_calculator(receiver, overpay_result.get_merkle_proof(receiver)?).calculate()?;
+        assert!(profit_result.token_totals.is_empty());
+        let settlement = native.aggregator().aggregate(vec![profit_result], overpay_result)?;
+        assert!(settlement.token_totals.is_empty());
+        assert_eq!(
+            settlement.settlement_id,
+            crate::settlement_id(
+                &settlement.proxy,
+                settlement.pay_ids_root,
+                settlement.serv_ids_root,
+                settlement.system_profits,
+                settlement.proxy_profits,
+                settlement.amount,
+                settlement.epoch,
+                settlement.pay_ids_root,
+            )
+        );
+        Ok(())
+    }
+}
+
 /********   doc
  * 创建一个聚合中验证器，其输入是多个settle_one_receiver的证据和一个overpay_check的证据。其过程是
 
@@ -108,12 +1123,12 @@ impl ProxySettlementAggregator {
      累计所有的ProfitResult system_profit，proxy_profit,receiver_profit到相应的system_profits，proxy_profits,receiver_profits
 
     4. 输出结果
-        settlement_id: keccak256(proxy||receipts_root||pay_ids_root||serv_ids_root||system_profits||proxy_profits||receiver_profits)
+        settlement_id: keccak256(proxy||receipts_root||pay_ids_root||serv_ids_root||system_profits||proxy_profits||receiver_profits[||epoch])，epoch 为 0 时不追加
         proxy
         pay_ids_root
         serv_ids_root
         system_profits
         proxy_profits
-        amount:system_profits + proxy_profits + receiver_profits
+        amount:system_profits + proxy_profits + receiver_profits（所有累加都检查溢出）
 
  */
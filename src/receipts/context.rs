@@ -0,0 +1,101 @@
+use alloy_primitives::U256;
+use std::fmt;
+
+use super::{EthAddress, PaymentSettledByProxy};
+
+/// 逐收据检查失败时附带的上下文：收据在被检查的切片中的下标、接收者和 pay_id
+///
+/// Display 在内部错误前加上 "receipt #index (receiver .., pay_id ..): "，source() 返回内部错误，
+/// guest_checks::classify 按内部错误分类。取类型化的错误时 downcast 为 WithContext<E> 再读 inner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WithContext<E> {
+    pub index: Option<usize>,
+    pub receiver: Option<EthAddress>,
+    pub pay_id: Option<U256>,
+    pub inner: E,
+}
+
+impl<E> WithContext<E> {
+    /// 不带上下文
+    pub fn new(inner: E) -> Self {
+        Self { index: None, receiver: None, pay_id: None, inner }
+    }
+
+    /// receipt 是切片中下标为 index 的收据
+    pub fn at_receipt(index: usize, receipt: &PaymentSettledByProxy, inner: E) -> Self {
+        Self { index: Some(index), receiver: Some(receipt.receiver), pay_id: Some(receipt.pay_id), inner }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for WithContext<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.index.is_none() && self.receiver.is_none() && self.pay_id.is_none() {
+            return self.inner.fmt(f);
+        }
+
+        f.write_str("receipt")?;
+        if let Some(index) = self.index {
+            write!(f, " #{}", index)?;
+        }
+        let mut separator = " (";
+        if let Some(receiver) = self.receiver {
+            write!(f, "{}receiver {}", separator, crate::addr::to_alloy(receiver))?;
+            separator = ", ";
+        }
+        if let Some(pay_id) = self.pay_id {
+            write!(f, "{}pay_id {}", separator, pay_id)?;
+            separator = ", ";
+        }
+        if separator == ", " {
+            f.write_str(")")?;
+        }
+        write!(f, ": {}", self.inner)
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for WithContext<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.inner)
+    }
+}
+
+/// 为逐收据检查的结果附加上下文
+pub trait ErrorContext<T, E> {
+    /// 失败时记录 receipt 及其在切片中的下标 index
+    fn ctx(self, index: usize, receipt: &PaymentSettledByProxy) -> Result<T, WithContext<E>>;
+}
+
+impl<T, E> ErrorContext<T, E> for Result<T, E> {
+    fn ctx(self, index: usize, receipt: &PaymentSettledByProxy) -> Result<T, WithContext<E>> {
+        self.map_err(|inner| WithContext::at_receipt(index, receipt, inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::receipts::ReceiptExpired;
+
+    #[test]
+    fn test_display_and_source() {
+        let receipt = PaymentSettledByProxy::new(U256::from(7), 1, U256::from(100), [0x11u8; 20]);
+        let expired = ReceiptExpired { pay_id: U256::from(7), serv_id: 1, receiver: [0x11u8; 20], valid_until: 5, current_time: 6 };
+
+        let err = Err::<(), _>(expired.clone()).ctx(3, &receipt).unwrap_err();
+        assert_eq!((err.index, err.receiver, err.pay_id), (Some(3), Some([0x11u8; 20]), Some(U256::from(7))));
+        let prefix = format!("receipt #3 (receiver {}, pay_id 7): ", crate::addr::to_alloy([0x11u8; 20]));
+        assert_eq!(err.to_string(), format!("{}{}", prefix, expired));
+        let source = std::error::Error::source(&err).and_then(|source| source.downcast_ref::<ReceiptExpired>());
+        assert_eq!(source, Some(&expired));
+
+        // 只有下标时不输出括号，没有上下文时与内部错误相同
+        let index_only = WithContext { index: Some(0), ..WithContext::new("Dust receipt rejected") };
+        assert_eq!(index_only.to_string(), "receipt #0: Dust receipt rejected");
+        assert_eq!(WithContext::new(expired.clone()).to_string(), expired.to_string());
+        assert_eq!(err.into_inner(), expired);
+    }
+}
@@ -0,0 +1,208 @@
+//! 选择性披露：接收者向对方出示绑定在 receipts_root 上的利润总额，而不交出每一张收据
+//!
+//! DisclosureProof 只能证明结构上的一致性：结果中的根与默克尔证明相符，证明中的值（收据哈希的组合承诺）
+//! 确实在 receipts_root 之下。默克尔证明不把叶子绑定到接收者：expected_receiver 只与未经认证的
+//! profit_result.receiver 比较，receipts_root 下任何接收者的叶子都能通过，接收者的绑定只来自 zk 证明。
+//! 它也不能证明利润数额是按这些收据算出来的——没有收据就无法重算，
+//! 结构检查也无法发现被抬高的 receiver_profit。利润数额的可信度完全取决于覆盖计算过程的 zk 证明：
+//! 对方必须验证 guest 的证明，并逐项比较 public_values 中的 vks_hash、receiver、proxy、receipts_root、
+//! pay_ids_root、serv_ids_root、三项利润和 epoch 与 profit_result 一致
+use alloy_primitives::{B256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::models::segment_vc::MerkleProof;
+use crate::models::TreeHashAlgorithm;
+use crate::{BoxError, EthAddress, ProfitResult, TokenSubtotal, NATIVE_TOKEN};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisclosureProof {
+    pub profit_result: ProfitResult,
+    pub merkle_proof: MerkleProof, // 接收者的收据在 payments 树中的证明
+    pub receipts_commitment: B256, // 按 canonical_receipt_order 排序的收据哈希的组合哈希，嵌套模式下为收据子树的根
+}
+
+impl DisclosureProof {
+    pub fn new(profit_result: ProfitResult, merkle_proof: MerkleProof) -> Self {
+        let receipts_commitment = merkle_proof.value_proof.value;
+        Self { profit_result, merkle_proof, receipts_commitment }
+    }
+
+    /// 检查结构一致性，不需要收据本身，按 Keccak、不填充的树验证；默克尔证明格式错误时返回错误，其他不一致返回 false
+    ///
+    /// 返回 true 不代表利润数额正确，也不代表叶子属于 expected_receiver，见模块文档
+    pub fn verify(&self, expected_receipts_root: B256, expected_receiver: EthAddress) -> Result<bool, BoxError> {
+        self.verify_with(expected_receipts_root, expected_receiver, TreeHashAlgorithm::Keccak, false)
+    }
+
+    /// 与 verify 相同，按验证方配置的树哈希算法和填充方式验证默克尔证明，不使用证明中记录的值
+    pub fn verify_with(
+        &self,
+        expected_receipts_root: B256,
+        expected_receiver: EthAddress,
+        hasher: TreeHashAlgorithm,
+        padded: bool,
+    ) -> Result<bool, BoxError> {
+        let result = &self.profit_result;
+        if result.receipts_root != expected_receipts_root
+            || self.merkle_proof.root_hash != result.receipts_root
+            || result.receiver != expected_receiver
+            || self.merkle_proof.value_proof.value != self.receipts_commitment
+        {
+            return Ok(false);
+        }
+        if !self.totals_consistent() {
+            return Ok(false);
+        }
+        self.merkle_proof.verify_with(hasher, padded)
+    }
+
+    /// 链上验证者需要与 zk 证明的 public values 逐字节比较的编码，即 ProfitResult::to_public_values
+    pub fn public_values(&self) -> Vec<u8> {
+        self.profit_result.to_public_values()
+    }
+
+    pub fn receiver_profit(&self) -> U256 {
+        self.profit_result.receiver_profit
+    }
+
+    // 三项利润之和不溢出；有按代币的小计时总额等于原生代币的小计，没有原生代币时为 0
+    fn totals_consistent(&self) -> bool {
+        let result = &self.profit_result;
+        if result.system_profit.checked_add(result.proxy_profit).and_then(|sum| sum.checked_add(result.receiver_profit)).is_none() {
+            return false;
+        }
+        if result.token_totals.is_empty() {
+            return true;
+        }
+        let native = result
+            .token_totals
+            .iter()
+            .find(|subtotal| subtotal.token == NATIVE_TOKEN)
+            .copied()
+            .unwrap_or(TokenSubtotal::new(NATIVE_TOKEN));
+        native.system_profit == result.system_profit
+            && native.proxy_profit == result.proxy_profit
+            && native.receiver_profit == result.receiver_profit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ScenarioBuilder;
+
+    fn disclosure() -> Result<(DisclosureProof, EthAddress), BoxError> {
+        let scenario = ScenarioBuilder::new(7)
+            .with_receivers(3)
+            .with_payment(1, 1, 1, 400)
+            .with_payment(2, 1, 1, 700)
+            .with_payment(3, 1, 0, 300)
+            .with_fee_config(1, 500, 1000)
+            .build()?;
+        let receiver = scenario.receiver(1);
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+        let disclosure = scenario.profit_calculator(receiver, proof).disclosure_proof()?;
+        Ok((disclosure, receiver))
+    }
+
+    #[test]
+    fn test_valid_disclosure() -> Result<(), BoxError> {
+        let (disclosure, receiver) = disclosure()?;
+        let root = disclosure.profit_result.receipts_root;
+        assert!(disclosure.verify(root, receiver)?);
+        assert_eq!(disclosure.public_values(), disclosure.profit_result.to_public_values());
+
+        let json = serde_json::to_string(&disclosure)?;
+        let decoded: DisclosureProof = serde_json::from_str(&json)?;
+        assert_eq!(decoded, disclosure);
+
+        // 期望的根或接收者不同
+        assert!(!disclosure.verify(B256::repeat_byte(1), receiver)?);
+        assert!(!disclosure.verify(root, [0xee; 20])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_disclosure() -> Result<(), BoxError> {
+        let (disclosure, receiver) = disclosure()?;
+        let root = disclosure.profit_result.receipts_root;
+
+        // 换成另一组收据的承诺，证明路径不再通过
+        let mut tampered = disclosure.clone();
+        tampered.receipts_commitment = B256::repeat_byte(2);
+        tampered.merkle_proof.value_proof.value = tampered.receipts_commitment;
+        assert!(!tampered.verify(root, receiver).unwrap_or(false));
+
+        // 承诺与证明中的值不一致
+        let mut tampered = disclosure.clone();
+        tampered.receipts_commitment = B256::repeat_byte(3);
+        assert!(!tampered.verify(root, receiver)?);
+
+        // 结果中的根与证明的根不一致
+        let mut tampered = disclosure.clone();
+        tampered.profit_result.receipts_root = B256::repeat_byte(4);
+        assert!(!tampered.verify(B256::repeat_byte(4), receiver)?);
+
+        // 三项利润之和溢出
+        let mut tampered = disclosure.clone();
+        tampered.profit_result.receiver_profit = U256::MAX;
+        assert!(!tampered.verify(root, receiver)?);
+
+        // 按代币的小计与总额不符
+        let mut tampered = disclosure.clone();
+        tampered.profit_result.token_totals = vec![TokenSubtotal::new([9; 20])];
+        assert!(!tampered.verify(root, receiver)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verifier_tree_config() -> Result<(), BoxError> {
+        use crate::models::segment_vc::SegmentVC;
+
+        let (disclosure, receiver) = disclosure()?;
+
+        // 同一个承诺放进 SHA-256、填充的树，验证方必须按树的配置验证
+        let mut vc = SegmentVC::new(16).with_hasher(TreeHashAlgorithm::Sha256).with_padded(true);
+        for i in 1..5u8 {
+            vc.insert(B256::repeat_byte(i), B256::repeat_byte(0x10 | i))?;
+        }
+        let root = vc.insert(B256::repeat_byte(9), disclosure.receipts_commitment)?;
+        let profit_result = ProfitResult { receipts_root: root, ..disclosure.profit_result.clone() };
+        let sha256 = DisclosureProof::new(profit_result, vc.generate_proof(B256::repeat_byte(9))?);
+
+        assert!(sha256.verify_with(root, receiver, TreeHashAlgorithm::Sha256, true)?);
+        assert!(!sha256.verify_with(root, receiver, TreeHashAlgorithm::Sha256, false).unwrap_or(false));
+        assert!(!sha256.verify_with(root, receiver, TreeHashAlgorithm::Keccak, true).unwrap_or(false));
+        assert!(!sha256.verify(root, receiver).unwrap_or(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_receiver_not_bound_by_merkle_proof() -> Result<(), BoxError> {
+        let (disclosure, receiver) = disclosure()?;
+        let root = disclosure.profit_result.receipts_root;
+
+        // 把结果中的接收者换成另一个地址，结构检查仍然通过；只有 public values 与 zk 证明比较时暴露出来
+        let other = [0xee; 20];
+        assert_ne!(other, receiver);
+        let mut relabeled = disclosure.clone();
+        relabeled.profit_result.receiver = other;
+        assert!(relabeled.verify(root, other)?);
+        assert_ne!(relabeled.public_values(), disclosure.public_values());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inflated_profit_needs_zk_proof() -> Result<(), BoxError> {
+        let (disclosure, receiver) = disclosure()?;
+        let root = disclosure.profit_result.receipts_root;
+
+        // 结构检查无法发现被抬高但不溢出的利润，只有 public values 能与 zk 证明比较时暴露出来
+        let mut inflated = disclosure.clone();
+        inflated.profit_result.receiver_profit += U256::from(1_000_000);
+        assert!(inflated.verify(root, receiver)?);
+        assert_ne!(inflated.public_values(), disclosure.public_values());
+        assert_ne!(inflated.profit_result.content_hash(), disclosure.profit_result.content_hash());
+        Ok(())
+    }
+}
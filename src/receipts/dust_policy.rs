@@ -0,0 +1,123 @@
+use alloy_primitives::U256;
+use crate::BoxError;
+use super::{PaymentSettledByProxy, WithContext};
+
+/// 金额低于 min_amount 的收据的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DustAction {
+    /// 直接报错，错误中包含 (pay_id, serv_id, receiver)
+    Reject,
+    /// 在分组前移除，因此不会出现在任何承诺的根中
+    Skip,
+}
+
+/// 零金额及小额收据策略
+///
+/// amount < min_amount 的收据视为 dust，amount == min_amount 不算。
+/// 默认 min_amount 为 0，即不过滤任何收据。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DustPolicy {
+    pub min_amount: U256,
+    pub action: DustAction,
+}
+
+impl Default for DustPolicy {
+    fn default() -> Self {
+        Self {
+            min_amount: U256::ZERO,
+            action: DustAction::Reject,
+        }
+    }
+}
+
+impl DustPolicy {
+    pub fn new(min_amount: U256, action: DustAction) -> Self {
+        Self { min_amount, action }
+    }
+
+    pub fn is_dust(&self, payment: &PaymentSettledByProxy) -> bool {
+        payment.amount < self.min_amount
+    }
+
+    /// Skip 模式下移除 dust 收据，Reject 模式下保持不变
+    pub fn filter(&self, payments: &mut Vec<PaymentSettledByProxy>) {
+        self.take_dust(payments);
+    }
+
+    /// 与 filter 相同，返回移除的 dust 收据，两者都保持输入顺序
+    pub fn take_dust(&self, payments: &mut Vec<PaymentSettledByProxy>) -> Vec<PaymentSettledByProxy> {
+        if self.action != DustAction::Skip {
+            return Vec::new();
+        }
+        let (kept, dust): (Vec<_>, Vec<_>) = std::mem::take(payments).into_iter().partition(|payment| !self.is_dust(payment));
+        *payments = kept;
+        dust
+    }
+
+    /// 检查剩余收据，Reject 模式下遇到 dust 收据时报错
+    pub fn check(&self, payments: &[PaymentSettledByProxy]) -> Result<(), BoxError> {
+        if self.action != DustAction::Reject {
+            return Ok(());
+        }
+        if let Some((index, payment)) = payments.iter().enumerate().find(|(_, payment)| self.is_dust(payment)) {
+            let rejected = format!(
+                "Dust receipt rejected: pay_id {}, serv_id {}, receiver {:?}, amount {} < {}",
+                payment.pay_id, payment.serv_id, payment.receiver, payment.amount, self.min_amount
+            );
+            return Err(WithContext::at_receipt(index, payment, rejected).to_string().into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_payment(pay_id: u64, amount: u64) -> PaymentSettledByProxy {
+        PaymentSettledByProxy::new(U256::from(pay_id), 1, U256::from(amount), [1u8; 20])
+            .with_settled(true)
+            .with_sig_sender([1u8; 65])
+            .with_sig_proxy([2u8; 65])
+    }
+
+    #[test]
+    fn test_default_keeps_everything() {
+        let policy = DustPolicy::default();
+        let mut payments = vec![create_test_payment(1, 0), create_test_payment(2, 10)];
+
+        policy.filter(&mut payments);
+        assert_eq!(payments.len(), 2);
+        assert!(policy.check(&payments).is_ok());
+    }
+
+    #[test]
+    fn test_reject() {
+        let policy = DustPolicy::new(U256::from(10), DustAction::Reject);
+
+        // 边界值：amount == min_amount 不是 dust
+        assert!(policy.check(&[create_test_payment(1, 10)]).is_ok());
+
+        let err = policy
+            .check(&[create_test_payment(1, 10), create_test_payment(2, 9)])
+            .unwrap_err();
+        assert!(err.to_string().starts_with("receipt #1 ") && err.to_string().contains("pay_id 2"));
+    }
+
+    #[test]
+    fn test_skip() {
+        let policy = DustPolicy::new(U256::from(10), DustAction::Skip);
+        let mut payments = vec![
+            create_test_payment(1, 0),
+            create_test_payment(2, 9),
+            create_test_payment(3, 10),
+        ];
+
+        let dust = policy.take_dust(&mut payments);
+        assert_eq!(payments.len(), 1);
+        assert_eq!(payments[0].pay_id, U256::from(3));
+        assert_eq!(dust.iter().map(|payment| payment.pay_id.to::<u64>()).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(policy.check(&payments).is_ok());
+        assert!(DustPolicy::new(U256::from(10), DustAction::Reject).take_dust(&mut dust.clone()).is_empty());
+    }
+}
@@ -1,21 +1,296 @@
-use crate::{keccak256, read_eth_signature, SerializableSignature};
+use crate::{hash_with_domain, keccak256, HashDomain, SerializableSignature, NATIVE_TOKEN};
 
-use super::{EthAddress, EthHash, EthSignature,signature_serde};
+use super::{EthAddress, EthHash, EthSignature};
+#[cfg(feature = "zkvm")]
 use sp1_zkvm::io as spio;
+#[cfg(feature = "zkvm")]
+use crate::models::WireError;
 use libsecp256k1::{recover, sign, verify, Message, PublicKey, RecoveryId, SecretKey, Signature};
 use alloy_primitives::{B256, U256};
 use tiny_keccak::{Hasher, Keccak};
-use crate::models::segment_vc::MerkleProof;
 use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
 use serde::{Serialize, Deserialize};
 pub mod overpay_checker;
 pub mod pay_ids_to_segvc;
 pub mod payment_grouper;
 pub mod profit_calculator;
+pub mod multi_profit_calculator;
+pub mod multi_channel;
+pub mod dust_policy;
+pub mod receiver_set;
+pub mod receipt_builder;
+pub mod sealed;
+pub mod settlement_filter;
+pub mod context;
+pub mod disclosure;
+pub mod wire;
 // mod pay_ids_to_segvc;
 // mod receipts_pay_check;
-pub use pay_ids_to_segvc::PayIdsProcessor;
-pub use payment_grouper::PaymentsGrouper;
+pub use pay_ids_to_segvc::{DuplicatePayIdInfo, PayIdsProcessor};
+pub use payment_grouper::{
+    paged_group_hash, verify_payment_inclusion, verify_payment_inclusion_with, NestedPaymentGroups, PaymentsGrouper,
+};
+pub use profit_calculator::{combine_partial_results, serv_ids_root, PartialProfitResult};
+pub use multi_profit_calculator::{MultiProfitResult, MultiReceiverProfitCalculator};
+pub use multi_channel::MultiChannelOverpayChecker;
+pub use dust_policy::{DustAction, DustPolicy};
+pub use receiver_set::ReceiverSetCommitment;
+pub use receipt_builder::{BuildError, SettledReceiptBuilder};
+pub use sealed::SealedReceipt;
+pub use settlement_filter::{SettlementFilter, UnsettledReport};
+pub use context::{ErrorContext, WithContext};
+pub use disclosure::DisclosureProof;
+pub use wire::receipt_stdin_frames;
+
+/// 金额累加溢出 U256，记录溢出发生在哪个 pay_id 或 receiver 的总额上
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmountOverflow {
+    PayId(U256),
+    Receiver(EthAddress),
+}
+
+impl std::fmt::Display for AmountOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountOverflow::PayId(pay_id) => write!(f, "Amount overflow for pay_id {}", pay_id),
+            AmountOverflow::Receiver(receiver) => write!(
+                f,
+                "Amount overflow for receiver {}",
+                crate::addr::to_alloy(*receiver)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AmountOverflow {}
+
+/// 收据已过期：current_time 晚于收据的 valid_until
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptExpired {
+    pub pay_id: U256,
+    pub serv_id: u32,
+    pub receiver: EthAddress,
+    pub valid_until: u64,
+    pub current_time: u64,
+}
+
+impl std::fmt::Display for ReceiptExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Receipt expired (pay_id {}, serv_id {}, receiver {}): valid until {}, current time {}",
+            self.pay_id,
+            self.serv_id,
+            crate::addr::to_alloy(self.receiver),
+            self.valid_until,
+            self.current_time
+        )
+    }
+}
+
+impl std::error::Error for ReceiptExpired {}
+
+/// 收据的代理或发送者签名无效：recovered 为 None 时无法恢复签名者，否则恢复出的地址不是 expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidReceiptSignature {
+    pub signer: &'static str, // "proxy" 或 "sender"
+    pub pay_id: U256,
+    pub serv_id: u32,
+    pub receiver: EthAddress,
+    pub expected: EthAddress,
+    pub recovered: Option<EthAddress>,
+}
+
+impl InvalidReceiptSignature {
+    pub(crate) fn new(signer: &'static str, receipt: &PaymentSettledByProxy, expected: EthAddress, recovered: Option<EthAddress>) -> Self {
+        Self {
+            signer,
+            pay_id: receipt.pay_id,
+            serv_id: receipt.serv_id,
+            receiver: receipt.receiver,
+            expected,
+            recovered,
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidReceiptSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid {} signature for receipt (pay_id {}, serv_id {}, receiver {}): expected {}, ",
+            self.signer,
+            self.pay_id,
+            self.serv_id,
+            crate::addr::to_alloy(self.receiver),
+            crate::addr::to_alloy(self.expected)
+        )?;
+        match self.recovered {
+            Some(recovered) => write!(f, "recovered {}", crate::addr::to_alloy(recovered)),
+            None => f.write_str("recovery failed"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidReceiptSignature {}
+
+/// 收据的结算金额超过了发送者签名授权的金额
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettledExceedsAuthorized {
+    pub pay_id: U256,
+    pub serv_id: u32,
+    pub receiver: EthAddress,
+    pub authorized: U256,
+    pub settled: U256,
+}
+
+impl std::fmt::Display for SettledExceedsAuthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Settled amount {} exceeds authorized amount {} (pay_id {}, serv_id {}, receiver {})",
+            self.settled,
+            self.authorized,
+            self.pay_id,
+            self.serv_id,
+            crate::addr::to_alloy(self.receiver)
+        )
+    }
+}
+
+impl std::error::Error for SettledExceedsAuthorized {}
+
+/// 返回第一个结算金额超过授权金额的收据，没有 authorized_amount 的收据两者相同
+pub fn check_partial_settlement(receipts: &[PaymentSettledByProxy]) -> Result<(), WithContext<SettledExceedsAuthorized>> {
+    match receipts.iter().enumerate().find(|(_, receipt)| receipt.amount > receipt.authorized()) {
+        Some((index, receipt)) => Err(SettledExceedsAuthorized {
+            pay_id: receipt.pay_id,
+            serv_id: receipt.serv_id,
+            receiver: receipt.receiver,
+            authorized: receipt.authorized(),
+            settled: receipt.amount,
+        })
+        .ctx(index, receipt),
+        None => Ok(()),
+    }
+}
+
+/// 返回第一个在 current_time 已过期的收据，没有 valid_until 的收据不受限制
+pub fn check_receipt_expiry(receipts: &[PaymentSettledByProxy], current_time: u64) -> Result<(), WithContext<ReceiptExpired>> {
+    match receipts.iter().enumerate().find(|(_, receipt)| !receipt.is_valid_at(current_time)) {
+        Some((index, receipt)) => Err(ReceiptExpired {
+            pay_id: receipt.pay_id,
+            serv_id: receipt.serv_id,
+            receiver: receipt.receiver,
+            valid_until: receipt.valid_until.unwrap_or_default(),
+            current_time,
+        })
+        .ctx(index, receipt),
+        None => Ok(()),
+    }
+}
+
+/// 两个收据的 key 相同：first 与 second 为它们按 canonical_receipt_order 排列的 hash()
+///
+/// first == second 时是同一收据的重复；不同时是 key 冲突，只在以 key 建树的嵌套分组中报告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateReceipt {
+    pub key: B256,
+    pub first: B256,
+    pub second: B256,
+}
+
+impl std::fmt::Display for DuplicateReceipt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.first == self.second {
+            write!(f, "Duplicate receipt with key {} (hash {})", self.key, self.first)
+        } else {
+            write!(
+                f,
+                "Receipts {} and {} collide on key {} (same pay_id, serv_id, receiver and nonce)",
+                self.first, self.second, self.key
+            )
+        }
+    }
+}
+
+impl std::error::Error for DuplicateReceipt {}
+
+/// 收据的规范全序：先比较 to_key()，key 相同时比较 hash()
+///
+/// 所有对收据排序的地方都使用这个顺序，结果与输入顺序无关。两个收据比较为 Equal
+/// 说明内容完全相同，排序的调用方返回 DuplicateReceipt
+pub fn canonical_receipt_order(a: &PaymentSettledByProxy, b: &PaymentSettledByProxy) -> std::cmp::Ordering {
+    a.to_key().cmp(&b.to_key()).then_with(|| a.hash().cmp(&b.hash()))
+}
+
+/// 按 canonical_receipt_order 排序的 (to_key(), hash())，每个收据只计算一次
+pub(crate) fn canonical_entries<'a>(
+    receipts: impl IntoIterator<Item = &'a PaymentSettledByProxy>
+) -> Result<Vec<(B256, B256)>, DuplicateReceipt> {
+    let mut entries: Vec<(B256, B256)> = receipts
+        .into_iter()
+        .map(|receipt| (receipt.to_key(), receipt.hash()))
+        .collect();
+    // (key, hash) 的字典序即 canonical_receipt_order
+    entries.sort_unstable();
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(DuplicateReceipt { key: pair[0].0, first: pair[0].1, second: pair[1].1 });
+    }
+    Ok(entries)
+}
+
+/// canonical_entries 的结果中没有 key 相同的收据，以 key 建树之前检查
+pub(crate) fn check_unique_keys(entries: &[(B256, B256)]) -> Result<(), DuplicateReceipt> {
+    match entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        Some(pair) => Err(DuplicateReceipt { key: pair[0].0, first: pair[0].1, second: pair[1].1 }),
+        None => Ok(()),
+    }
+}
+
+/// 收据的唯一性键：keccak256(pay_id(32) ‖ serv_id(4) ‖ receiver(20) [‖ amount(32)] [‖ nonce(8)])
+///
+/// new 不包含金额，与 to_key() 相同，同一 (pay_id, serv_id, receiver, nonce) 只允许一张收据；
+/// including_amount 与 to_unique_key() 相同，用于允许同一组合下有多张金额不同的收据的流程
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UniquenessKey(B256);
+
+impl UniquenessKey {
+    pub fn new(pay_id: U256, serv_id: u32, receiver: &EthAddress, nonce: Option<u64>) -> Self {
+        Self::hash(pay_id, serv_id, receiver, None, nonce)
+    }
+
+    pub fn including_amount(pay_id: U256, serv_id: u32, receiver: &EthAddress, amount: U256, nonce: Option<u64>) -> Self {
+        Self::hash(pay_id, serv_id, receiver, Some(amount), nonce)
+    }
+
+    pub fn as_b256(&self) -> B256 {
+        self.0
+    }
+
+    fn hash(pay_id: U256, serv_id: u32, receiver: &EthAddress, amount: Option<U256>, nonce: Option<u64>) -> Self {
+        count_op!(Keccak);
+        let mut hasher = Keccak::v256();
+        let mut output = [0u8; 32];
+        hasher.update(&pay_id.to_be_bytes::<32>());
+        hasher.update(&serv_id.to_be_bytes());
+        hasher.update(receiver);
+        if let Some(amount) = amount {
+            hasher.update(&amount.to_be_bytes::<32>());
+        }
+        if let Some(nonce) = nonce {
+            hasher.update(&nonce.to_be_bytes());
+        }
+        hasher.finalize(&mut output);
+        Self(output.into())
+    }
+}
+
+impl From<UniquenessKey> for B256 {
+    fn from(key: UniquenessKey) -> Self {
+        key.0
+    }
+}
 
 // 为外部类型创建新的包装类型
 #[derive(Debug, Clone, PartialEq)]
@@ -24,43 +299,172 @@ pub struct RlpAddress(EthAddress);
 #[derive(Debug, Clone, PartialEq)]
 pub struct RlpU256(U256);
 
-#[derive(Debug,Clone,Serialize,Deserialize)]
-pub struct ReceiverProof {
-    pub receiver: EthAddress,
-    pub proof: MerkleProof // 实际使用时替换为具体的证明类型
-}
+// 定义在 crate 根，验证子集（no_std / wasm）也需要
+pub use crate::ReceiverProof;
 
 
 
 #[derive(Debug, Clone,Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Payment {
+    #[serde(with = "crate::serde_u256_dec")]
     pay_id: U256,
     serv_id: u32,
+    #[serde(with = "crate::serde_u256_dec")]
     pub amount: U256,     // 新增字段
+    #[serde(with = "crate::serde_hex")]
     receiver: EthAddress,
-    #[serde(with = "signature_serde")]
+    #[serde(with = "crate::serde_hex::signature")]
     sig_sender: EthSignature,
+    #[serde(default)]
+    pub nonce: Option<u64>, // 防重放序号，None 时使用旧版签名/哈希布局
+    #[serde(default)]
+    pub valid_until: Option<u64>, // 过期时间（含），None 时永不过期
+    #[serde(default, with = "crate::serde_hex")]
+    pub token: EthAddress, // 结算的代币，零地址（NATIVE_TOKEN）为原生资产，旧数据没有该字段
+}
+
+/// 带 nonce 的载荷版本号
+pub const PAYLOAD_VERSION_NONCE: u8 = 1;
+
+/// 带 valid_until 的载荷版本位，与 nonce 同时存在时版本字节为 3
+pub const PAYLOAD_VERSION_VALID_UNTIL: u8 = 2;
+
+/// 非原生代币的载荷版本位
+pub const PAYLOAD_VERSION_TOKEN: u8 = 4;
+
+/// 部分结算（带 authorized_amount）的载荷版本位，只出现在代理签名和收据哈希中
+pub const PAYLOAD_VERSION_PARTIAL: u8 = 8;
+
+/// 版本化载荷布局：没有可选字段且为原生代币时保持旧版布局不变，
+/// 否则在开头加版本字节（存在的可选字段对应的位），末尾依次追加存在的 nonce、valid_until（大端序）、
+/// token（20 字节）和 authorized_amount（32 字节大端序）
+fn versioned_payload(
+    legacy: Vec<u8>,
+    nonce: Option<u64>,
+    valid_until: Option<u64>,
+    token: &EthAddress,
+    authorized_amount: Option<U256>,
+) -> Vec<u8> {
+    let is_token = *token != NATIVE_TOKEN;
+    let version = nonce.map_or(0, |_| PAYLOAD_VERSION_NONCE)
+        | valid_until.map_or(0, |_| PAYLOAD_VERSION_VALID_UNTIL)
+        | if is_token { PAYLOAD_VERSION_TOKEN } else { 0 }
+        | authorized_amount.map_or(0, |_| PAYLOAD_VERSION_PARTIAL);
+    if version == 0 {
+        return legacy;
+    }
+    let mut packed = Vec::with_capacity(legacy.len() + 69);
+    packed.push(version);
+    packed.extend_from_slice(&legacy);
+    for value in [nonce, valid_until].into_iter().flatten() {
+        packed.extend_from_slice(&value.to_be_bytes());
+    }
+    if is_token {
+        packed.extend_from_slice(token);
+    }
+    if let Some(authorized_amount) = authorized_amount {
+        packed.extend_from_slice(&authorized_amount.to_be_bytes::<32>());
+    }
+    packed
+}
+
+/// 签名域，防止测试网签名的收据在主网上同样有效
+///
+/// 带域的签名消息布局（紧密打包）：
+/// chain_id (8 字节大端序) | contract (20 字节) | 原签名载荷
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningDomain {
+    pub chain_id: u64,
+    pub contract: EthAddress,
+}
+
+impl SigningDomain {
+    pub fn new(chain_id: u64, contract: EthAddress) -> Self {
+        Self { chain_id, contract }
+    }
+
+    /// 在载荷前加上域分隔符
+    pub fn wrap(&self, payload: &[u8]) -> Vec<u8> {
+        let mut packed = Vec::with_capacity(28 + payload.len());
+        packed.extend_from_slice(&self.chain_id.to_be_bytes());
+        packed.extend_from_slice(&self.contract);
+        packed.extend_from_slice(payload);
+        packed
+    }
+}
+
+/// 没有域时保持原载荷，兼容旧的无域签名
+fn domain_payload(payload: Vec<u8>, domain: Option<&SigningDomain>) -> Vec<u8> {
+    match domain {
+        Some(domain) => domain.wrap(&payload),
+        None => payload,
+    }
 }
+
 impl Payment {
+    /// 创建未签名的原生代币收据，nonce 和 valid_until 为 None；新增字段时只需修改这里
+    pub fn new(pay_id: U256, serv_id: u32, amount: U256, receiver: EthAddress) -> Self {
+        Self {
+            pay_id,
+            serv_id,
+            amount,
+            receiver,
+            sig_sender: [0u8; 65],
+            nonce: None,
+            valid_until: None,
+            token: NATIVE_TOKEN,
+        }
+    }
+
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// 设置过期时间，需要在签名之前设置
+    pub fn with_valid_until(mut self, valid_until: u64) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    /// 设置结算的代币，需要在签名之前设置
+    pub fn with_token(mut self, token: EthAddress) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// 直接设置发送者签名，通常用 sign 生成
+    pub fn with_sig_sender(mut self, sig_sender: EthSignature) -> Self {
+        self.sig_sender = sig_sender;
+        self
+    }
+
     // 已有的方法保持不变...
 
+    /// 发送者签名的载荷：pay_id | serv_id | amount | receiver，按 nonce、valid_until 和 token 版本化
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut packed = Vec::new();
+        packed.extend_from_slice(&self.pay_id.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.serv_id.to_be_bytes());
+        packed.extend_from_slice(&self.amount.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.receiver);
+        versioned_payload(packed, self.nonce, self.valid_until, &self.token, None)
+    }
+
     // 添加新的签名方法
     pub fn sign(&mut self, secret_key: &SecretKey) -> Result<(), DecoderError> {
+        self.sign_in(secret_key, None)
+    }
+
+    /// 在指定签名域下签名
+    pub fn sign_with_domain(&mut self, secret_key: &SecretKey, domain: &SigningDomain) -> Result<(), DecoderError> {
+        self.sign_in(secret_key, Some(domain))
+    }
+
+    pub(crate) fn sign_in(&mut self, secret_key: &SecretKey, domain: Option<&SigningDomain>) -> Result<(), DecoderError> {
         // 1. 将字段紧密打包
-        let mut packed = Vec::new();
-        
-        // 添加pay_id (转换为固定长度的字节数组)
-        let pay_id_bytes:[u8;32] = self.pay_id.to_be_bytes();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        // 添加serv_id (转换为固定长度的字节数组)
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-          // 添加 amount
-          let amount_bytes: [u8; 32] = self.amount.to_be_bytes();
-          packed.extend_from_slice(&amount_bytes);
-        // 添加receiver地址
-        packed.extend_from_slice(&self.receiver);
+        let packed = domain_payload(self.signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -85,19 +489,17 @@ impl Payment {
 
     // 验证签名
     pub fn verify(&self, public_key: &PublicKey) -> Result<bool, DecoderError> {
+        self.verify_in(public_key, None)
+    }
+
+    /// 在指定签名域下验证签名
+    pub fn verify_with_domain(&self, public_key: &PublicKey, domain: &SigningDomain) -> Result<bool, DecoderError> {
+        self.verify_in(public_key, Some(domain))
+    }
+
+    pub(crate) fn verify_in(&self, public_key: &PublicKey, domain: Option<&SigningDomain>) -> Result<bool, DecoderError> {
         // 1. 重新构建消息
-        let mut packed = Vec::new();
-        
-        let pay_id_bytes:[u8;32] = self.pay_id.to_be_bytes();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-       
-       let amount_bytes: [u8; 32] = self.amount.to_be_bytes();
-        packed.extend_from_slice(&amount_bytes);
-        
-        packed.extend_from_slice(&self.receiver);
+        let packed = domain_payload(self.signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -115,16 +517,17 @@ impl Payment {
 
     // 从签名恢复公钥
     pub fn recover_signer(&self) -> Result<PublicKey, DecoderError> {
+        self.recover_signer_in(None)
+    }
+
+    /// 在指定签名域下恢复公钥
+    pub fn recover_signer_with_domain(&self, domain: &SigningDomain) -> Result<PublicKey, DecoderError> {
+        self.recover_signer_in(Some(domain))
+    }
+
+    pub(crate) fn recover_signer_in(&self, domain: Option<&SigningDomain>) -> Result<PublicKey, DecoderError> {
         // 1. 重新构建消息
-        let mut packed = Vec::new();
-        
-        let pay_id_bytes:[u8;32] = self.pay_id.to_be_bytes();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-        
-        packed.extend_from_slice(&self.receiver);
+        let packed = domain_payload(self.signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -140,74 +543,308 @@ impl Payment {
             .map_err(|_| DecoderError::Custom("Failed to parse message"))?;
             
         // 4. 恢复公钥
+        count_op!(SignatureRecovery);
         recover(&msg, &sig, &recovery_id)
             .map_err(|_| DecoderError::Custom("Failed to recover public key"))
     }
         /// 获取签名者的以太坊地址
         pub fn get_signer_address(&self) -> Result<EthAddress, DecoderError> {
+            self.get_signer_address_in(None)
+        }
+
+        /// 获取指定签名域下签名者的以太坊地址
+        pub fn get_signer_address_with_domain(&self, domain: &SigningDomain) -> Result<EthAddress, DecoderError> {
+            self.get_signer_address_in(Some(domain))
+        }
+
+        pub(crate) fn get_signer_address_in(&self, domain: Option<&SigningDomain>) -> Result<EthAddress, DecoderError> {
             // 1. 首先恢复公钥
-            let public_key = self.recover_signer()?;
+            let public_key = self.recover_signer_in(domain)?;
             
             // 2. 将公钥转换为以太坊地址
             Ok(super::get_ethereum_address(&public_key))
         }
 }
 #[derive(Debug, Clone,Serialize, Deserialize)]
+#[serde(from = "PaymentSettledByProxyFields")]
+#[non_exhaustive]
 pub struct PaymentSettledByProxy {
+    #[serde(with = "crate::serde_u256_dec")]
     pub pay_id: U256,
     pub serv_id: u32,
-    pub amount: U256,
+    #[serde(with = "crate::serde_u256_dec")]
+    pub amount: U256, // 结算金额，代理签名覆盖该金额
+    #[serde(with = "crate::serde_hex")]
     pub receiver: EthAddress,
-    #[serde(with = "signature_serde")]
+    #[serde(with = "crate::serde_hex::signature")]
     pub sig_sender: EthSignature,
     pub settled: bool,
-    #[serde(with = "signature_serde")]
+    #[serde(with = "crate::serde_hex::signature")]
     pub sig_proxy: EthSignature,
+    #[serde(default)]
+    pub nonce: Option<u64>, // 与 Payment.nonce 相同
+    #[serde(default)]
+    pub valid_until: Option<u64>, // 与 Payment.valid_until 相同
+    #[serde(default, with = "crate::serde_hex")]
+    pub token: EthAddress, // 与 Payment.token 相同，必须等于对应 PayIdInfo.token
+    #[serde(default, with = "crate::serde_u256_dec::option")]
+    pub authorized_amount: Option<U256>, // 发送者签名授权的金额，None 时与 amount 相同（全额结算，旧数据）
 }
 
-// 为 PaymentSettledByProxy 实现读取方法
-impl PaymentSettledByProxy {
-  pub   fn read_from_stdin() -> Self {
+// 与 PaymentSettledByProxy 相同的 JSON 字段，反序列化后规范化 authorized_amount
+#[derive(Deserialize)]
+struct PaymentSettledByProxyFields {
+    #[serde(with = "crate::serde_u256_dec")]
+    pay_id: U256,
+    serv_id: u32,
+    #[serde(with = "crate::serde_u256_dec")]
+    amount: U256,
+    #[serde(with = "crate::serde_hex")]
+    receiver: EthAddress,
+    #[serde(with = "crate::serde_hex::signature")]
+    sig_sender: EthSignature,
+    settled: bool,
+    #[serde(with = "crate::serde_hex::signature")]
+    sig_proxy: EthSignature,
+    #[serde(default)]
+    nonce: Option<u64>,
+    #[serde(default)]
+    valid_until: Option<u64>,
+    #[serde(default, with = "crate::serde_hex")]
+    token: EthAddress,
+    #[serde(default, with = "crate::serde_u256_dec::option")]
+    authorized_amount: Option<U256>,
+}
+
+impl From<PaymentSettledByProxyFields> for PaymentSettledByProxy {
+    fn from(fields: PaymentSettledByProxyFields) -> Self {
         Self {
-            pay_id: spio::read::<U256>(),
-            serv_id: spio::read::<u32>(),
-            amount: spio::read::<U256>(),
-            receiver: spio::read::<EthAddress>(),
-            sig_sender:read_eth_signature(), 
-            settled: spio::read::<bool>(),
-            sig_proxy: read_eth_signature(), 
+            pay_id: fields.pay_id,
+            serv_id: fields.serv_id,
+            amount: fields.amount,
+            receiver: fields.receiver,
+            sig_sender: fields.sig_sender,
+            settled: fields.settled,
+            sig_proxy: fields.sig_proxy,
+            nonce: fields.nonce,
+            valid_until: fields.valid_until,
+            token: fields.token,
+            authorized_amount: partial_authorized_amount(fields.amount, fields.authorized_amount),
+        }
+    }
+}
+
+/// 解码出的授权金额等于结算金额时为全额结算，规范化为 None，与 with_authorized_amount 相同；
+/// 否则 Some(amount) 和 None 表示同一张收据却有不同的哈希和签名载荷
+pub(crate) fn partial_authorized_amount(amount: U256, authorized_amount: Option<U256>) -> Option<U256> {
+    authorized_amount.filter(|authorized_amount| *authorized_amount != amount)
+}
+
+/// guest stdin 中每张收据在最初 7 个字段之后是否还有新增的字段，host 和 guest 必须使用同一布局
+///
+/// 旧的 host 只写入最初的字段，对应 LEGACY；新增的字段按结构体中的顺序追加在后面。
+/// 布局由 guest 程序决定，需要时由 host 在所有收据之前写入 bits()（一个 u8），guest 用 read_from_stdin 读取；
+/// 每张收据的字节布局见 wire 模块
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ReceiptStdinLayout(u8);
+
+impl ReceiptStdinLayout {
+    /// 只有最初的字段，新增的字段读取为默认值
+    pub const LEGACY: Self = Self(0);
+    const TOKEN: u8 = 1 << 0;
+    const NONCE: u8 = 1 << 1;
+    const VALID_UNTIL: u8 = 1 << 2;
+    const AUTHORIZED_AMOUNT: u8 = 1 << 3;
+    const KNOWN: u8 = Self::TOKEN | Self::NONCE | Self::VALID_UNTIL | Self::AUTHORIZED_AMOUNT;
+
+    /// 未知的位返回 None
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        (bits & !Self::KNOWN == 0).then_some(Self(bits))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// 包含 token
+    pub fn with_token(self) -> Self {
+        Self(self.0 | Self::TOKEN)
+    }
+
+    pub fn has_token(self) -> bool {
+        self.0 & Self::TOKEN != 0
+    }
+
+    /// 包含 nonce
+    pub fn with_nonce(self) -> Self {
+        Self(self.0 | Self::NONCE)
+    }
+
+    pub fn has_nonce(self) -> bool {
+        self.0 & Self::NONCE != 0
+    }
+
+    /// 包含 valid_until
+    pub fn with_valid_until(self) -> Self {
+        Self(self.0 | Self::VALID_UNTIL)
+    }
+
+    pub fn has_valid_until(self) -> bool {
+        self.0 & Self::VALID_UNTIL != 0
+    }
+
+    /// 包含 authorized_amount
+    pub fn with_authorized_amount(self) -> Self {
+        Self(self.0 | Self::AUTHORIZED_AMOUNT)
+    }
+
+    pub fn has_authorized_amount(self) -> bool {
+        self.0 & Self::AUTHORIZED_AMOUNT != 0
+    }
+
+    /// 能完整写入这组收据的最小布局：只有原生代币时不包含 token，都没有 nonce、valid_until、
+    /// authorized_amount（都是全额结算）时不包含对应字段
+    pub fn for_receipts(receipts: &[PaymentSettledByProxy]) -> Self {
+        let mut layout = Self::LEGACY;
+        if receipts.iter().any(|receipt| receipt.token != NATIVE_TOKEN) {
+            layout = layout.with_token();
         }
+        if receipts.iter().any(|receipt| receipt.nonce.is_some()) {
+            layout = layout.with_nonce();
+        }
+        if receipts.iter().any(|receipt| receipt.valid_until.is_some()) {
+            layout = layout.with_valid_until();
+        }
+        if receipts.iter().any(|receipt| receipt.authorized_amount.is_some()) {
+            layout = layout.with_authorized_amount();
+        }
+        layout
+    }
+
+    /// 读取 host 写入的布局，未知的位返回 None
+    #[cfg(feature = "zkvm")]
+    pub fn read_from_stdin() -> Option<Self> {
+        Self::from_bits(spio::read::<u8>())
+    }
+}
+
+// 为 PaymentSettledByProxy 实现读取方法
+#[cfg(feature = "zkvm")]
+impl PaymentSettledByProxy {
+    /// 按 LEGACY 布局读取，与只写入最初字段的 host 兼容
+    pub fn read_from_stdin() -> Result<Self, WireError> {
+        Self::read_from_stdin_with(ReceiptStdinLayout::LEGACY)
+    }
+
+    /// 读取 host 用 to_stdin_bytes(layout) 写入的一帧，布局中没有的字段为默认值
+    pub fn read_from_stdin_with(layout: ReceiptStdinLayout) -> Result<Self, WireError> {
+        Self::from_stdin_bytes(layout, &spio::read_vec())
     }
 }
 // 在PaymentSettledByProxy实现块中添加新方法
 impl PaymentSettledByProxy {
+    /// 创建未结算、未签名的原生代币收据，nonce 和 valid_until 为 None；新增字段时只需修改这里
+    pub fn new(pay_id: U256, serv_id: u32, amount: U256, receiver: EthAddress) -> Self {
+        Self {
+            pay_id,
+            serv_id,
+            amount,
+            receiver,
+            sig_sender: [0u8; 65],
+            settled: false,
+            sig_proxy: [0u8; 65],
+            nonce: None,
+            valid_until: None,
+            token: NATIVE_TOKEN,
+            authorized_amount: None,
+        }
+    }
+
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    pub fn with_valid_until(mut self, valid_until: u64) -> Self {
+        self.valid_until = Some(valid_until);
+        self
+    }
+
+    pub fn with_token(mut self, token: EthAddress) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// 发送者授权的金额与 amount 不同（部分结算）时设置，与 amount 相同时等价于不设置
+    pub fn with_authorized_amount(mut self, authorized_amount: U256) -> Self {
+        self.authorized_amount = (authorized_amount != self.amount).then_some(authorized_amount);
+        self
+    }
+
+    /// 发送者签名授权的金额
+    pub fn authorized(&self) -> U256 {
+        self.authorized_amount.unwrap_or(self.amount)
+    }
+
+    /// 代理结算的金额，overpay 检查和利润计算使用该金额
+    pub fn settled_amount(&self) -> U256 {
+        self.amount
+    }
+
+    /// 结算金额小于授权金额
+    pub fn is_partial(&self) -> bool {
+        self.amount < self.authorized()
+    }
+
+    /// current_time 不晚于 valid_until 时有效（边界时刻仍有效），没有 valid_until 时始终有效
+    pub fn is_valid_at(&self, current_time: u64) -> bool {
+        self.valid_until.is_none_or(|valid_until| current_time <= valid_until)
+    }
+
+    pub fn with_settled(mut self, settled: bool) -> Self {
+        self.settled = settled;
+        self
+    }
+
+    /// 直接设置发送者签名，通常由 Payment::sign 生成后经 From 转换带入
+    pub fn with_sig_sender(mut self, sig_sender: EthSignature) -> Self {
+        self.sig_sender = sig_sender;
+        self
+    }
+
+    /// 直接设置代理签名，通常用 sign_by_proxy 生成
+    pub fn with_sig_proxy(mut self, sig_proxy: EthSignature) -> Self {
+        self.sig_proxy = sig_proxy;
+        self
+    }
+
     // 已有的方法保持不变...
 
-    // 代理签名方法
-    pub fn sign_by_proxy(&mut self, secret_key: &SecretKey) -> Result<(), DecoderError> {
-        // 1. 将字段紧密打包
+    /// 代理签名的载荷：pay_id | serv_id | amount | receiver | sig_sender | settled，按 nonce、valid_until、token 和 authorized_amount 版本化
+    pub fn proxy_signing_payload(&self) -> Vec<u8> {
         let mut packed = Vec::new();
-        
-        // 添加pay_id (转换为固定长度的字节数组)
-        let  pay_id_bytes:[u8;32] = self.pay_id.to_be_bytes::<32>();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        // 添加serv_id
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-        
-        // 添加amount
-        let  amount_bytes:[u8;32]  = self.amount.to_be_bytes::<32>();
-        packed.extend_from_slice(&amount_bytes);
-        
-        // 添加receiver地址
+        packed.extend_from_slice(&self.pay_id.to_be_bytes::<32>());
+        packed.extend_from_slice(&self.serv_id.to_be_bytes());
+        packed.extend_from_slice(&self.amount.to_be_bytes::<32>());
         packed.extend_from_slice(&self.receiver);
-        
-        // 添加sender的签名
         packed.extend_from_slice(&self.sig_sender);
-        
-        // 添加settled状态
-        packed.extend_from_slice(&[self.settled as u8]);
+        packed.push(self.settled as u8);
+        versioned_payload(packed, self.nonce, self.valid_until, &self.token, self.authorized_amount)
+    }
+
+    // 代理签名方法
+    pub fn sign_by_proxy(&mut self, secret_key: &SecretKey) -> Result<(), DecoderError> {
+        self.sign_by_proxy_in(secret_key, None)
+    }
+
+    /// 在指定签名域下代理签名
+    pub fn sign_by_proxy_with_domain(&mut self, secret_key: &SecretKey, domain: &SigningDomain) -> Result<(), DecoderError> {
+        self.sign_by_proxy_in(secret_key, Some(domain))
+    }
+
+    pub(crate) fn sign_by_proxy_in(&mut self, secret_key: &SecretKey, domain: Option<&SigningDomain>) -> Result<(), DecoderError> {
+        // 1. 将字段紧密打包
+        let packed = domain_payload(self.proxy_signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -232,21 +869,17 @@ impl PaymentSettledByProxy {
 
     // 验证代理签名
     pub fn verify_proxy_signature(&self, public_key: &PublicKey) -> Result<bool, DecoderError> {
+        self.verify_proxy_signature_in(public_key, None)
+    }
+
+    /// 在指定签名域下验证代理签名
+    pub fn verify_proxy_signature_with_domain(&self, public_key: &PublicKey, domain: &SigningDomain) -> Result<bool, DecoderError> {
+        self.verify_proxy_signature_in(public_key, Some(domain))
+    }
+
+    pub(crate) fn verify_proxy_signature_in(&self, public_key: &PublicKey, domain: Option<&SigningDomain>) -> Result<bool, DecoderError> {
         // 1. 重新构建消息
-        let mut packed = Vec::new();
-        
-        let  pay_id_bytes = self.pay_id.to_be_bytes::<32>();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-        
-        let  amount_bytes =    self.amount.to_be_bytes::<32>();
-        packed.extend_from_slice(&amount_bytes);
-        
-        packed.extend_from_slice(&self.receiver);
-        packed.extend_from_slice(&self.sig_sender);
-        packed.extend_from_slice(&[self.settled as u8]);
+        let packed = domain_payload(self.proxy_signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -265,21 +898,17 @@ impl PaymentSettledByProxy {
 
     // 继续完成recover_proxy_signer方法
     pub fn recover_proxy_signer(&self) -> Result<PublicKey, DecoderError> {
+        self.recover_proxy_signer_in(None)
+    }
+
+    /// 在指定签名域下恢复代理公钥
+    pub fn recover_proxy_signer_with_domain(&self, domain: &SigningDomain) -> Result<PublicKey, DecoderError> {
+        self.recover_proxy_signer_in(Some(domain))
+    }
+
+    pub(crate) fn recover_proxy_signer_in(&self, domain: Option<&SigningDomain>) -> Result<PublicKey, DecoderError> {
         // 1. 重新构建消息
-        let mut packed = Vec::new();
-        
-        let  pay_id_bytes:[u8;32] = self.pay_id.to_be_bytes();
-        packed.extend_from_slice(&pay_id_bytes);
-        
-        let serv_id_bytes = self.serv_id.to_be_bytes();
-        packed.extend_from_slice(&serv_id_bytes);
-        
-        let  amount_bytes:[u8;32] = self.amount.to_be_bytes();
-        packed.extend_from_slice(&amount_bytes);
-        
-        packed.extend_from_slice(&self.receiver);
-        packed.extend_from_slice(&self.sig_sender);
-        packed.extend_from_slice(&[self.settled as u8]);
+        let packed = domain_payload(self.proxy_signing_payload(), domain);
         
         // 2. 计算消息哈希
         let message_hash = keccak256(&packed);
@@ -295,19 +924,33 @@ impl PaymentSettledByProxy {
             .map_err(|_| DecoderError::Custom("Failed to parse message"))?;
             
         // 4. 恢复公钥
+        count_op!(SignatureRecovery);
         recover(&msg, &sig, &recovery_id)
             .map_err(|_| DecoderError::Custom("Failed to recover public key"))
     }
 
     // 便利方法：设置金额和结算状态
+    //
+    // 金额与发送者授权的金额不同时记录 authorized_amount，发送者签名仍按授权金额验证
     pub fn set_settlement(&mut self, amount: U256, settled: bool) {
+        let authorized = self.authorized();
+        self.authorized_amount = (amount != authorized).then_some(authorized);
         self.amount = amount;
         self.settled = settled;
     }
     /// 获取代理签名者的以太坊地址
     pub fn get_proxy_address(&self) -> Result<EthAddress, DecoderError> {
+        self.get_proxy_address_in(None)
+    }
+
+    /// 获取指定签名域下代理签名者的以太坊地址
+    pub fn get_proxy_address_with_domain(&self, domain: &SigningDomain) -> Result<EthAddress, DecoderError> {
+        self.get_proxy_address_in(Some(domain))
+    }
+
+    pub(crate) fn get_proxy_address_in(&self, domain: Option<&SigningDomain>) -> Result<EthAddress, DecoderError> {
         // 1. 首先恢复公钥
-        let public_key = self.recover_proxy_signer()?;
+        let public_key = self.recover_proxy_signer_in(domain)?;
         
         // 2. 将公钥转换为以太坊地址
         Ok(super::get_ethereum_address(&public_key))
@@ -315,17 +958,29 @@ impl PaymentSettledByProxy {
 
     /// 获取原始签名者的以太坊地址
     pub fn get_sender_address(&self) -> Result<EthAddress, DecoderError> {
+        self.get_sender_address_in(None)
+    }
+
+    /// 获取指定签名域下原始签名者的以太坊地址
+    pub fn get_sender_address_with_domain(&self, domain: &SigningDomain) -> Result<EthAddress, DecoderError> {
+        self.get_sender_address_in(Some(domain))
+    }
+
+    pub(crate) fn get_sender_address_in(&self, domain: Option<&SigningDomain>) -> Result<EthAddress, DecoderError> {
         // 1. 创建临时Payment对象用于恢复原始签名者
         let temp_payment = Payment {
             pay_id: self.pay_id,
             serv_id: self.serv_id,
             receiver: self.receiver,
             sig_sender: self.sig_sender,
-            amount:self.amount,
+            amount: self.authorized(), // 发送者签名覆盖授权金额
+            nonce: self.nonce,
+            valid_until: self.valid_until,
+            token: self.token,
         };
         
         // 2. 使用Payment的方法获取签名者地址
-        temp_payment.get_signer_address()
+        temp_payment.get_signer_address_in(domain)
     }
 }
 // 为PaymentSettledByProxy实现From<Payment> trait
@@ -334,11 +989,15 @@ impl From<Payment> for PaymentSettledByProxy {
         PaymentSettledByProxy {
             pay_id: payment.pay_id,
             serv_id: payment.serv_id,
-            amount: payment.amount, // 默认金额设为0
+            amount: payment.amount, // 沿用收据金额
             receiver: payment.receiver,
             sig_sender: payment.sig_sender,
             settled: false,       // 默认未结算
             sig_proxy: [0u8; 65], // 默认签名
+            nonce: payment.nonce,
+            valid_until: payment.valid_until,
+            token: payment.token,
+            authorized_amount: None, // 全额结算前与 amount 相同
         }
     }
 }
@@ -453,22 +1112,98 @@ impl Decodable for RlpSignature {
     }
 }
 
+// 可选的尾部字段（nonce、valid_until）：只编码到最后一个存在的字段为止，
+// 排在它前面但缺省的字段编码为空列表占位，与 0 区分
+fn optional_len(fields: &[Option<u64>]) -> usize {
+    fields.iter().rposition(Option::is_some).map_or(0, |index| index + 1)
+}
+
+fn append_optional(stream: &mut RlpStream, fields: &[Option<u64>]) {
+    for field in &fields[..optional_len(fields)] {
+        match field {
+            Some(value) => stream.append(value),
+            None => stream.begin_list(0),
+        };
+    }
+}
+
+// 非原生代币时 token 跟在 nonce、valid_until 之后，缺省的 nonce、valid_until 用空列表占位；
+// 原生代币不编码，保持之前的布局
+fn trailing_len(fields: &[Option<u64>], token: &EthAddress) -> usize {
+    if *token == NATIVE_TOKEN {
+        optional_len(fields)
+    } else {
+        fields.len() + 1
+    }
+}
+
+fn append_trailing(stream: &mut RlpStream, fields: &[Option<u64>], token: &EthAddress) {
+    if *token == NATIVE_TOKEN {
+        append_optional(stream, fields);
+        return;
+    }
+    append_all_trailing(stream, fields, token);
+}
+
+// 编码全部的 nonce、valid_until 和 token，缺省的 nonce、valid_until 用空列表占位
+fn append_all_trailing(stream: &mut RlpStream, fields: &[Option<u64>], token: &EthAddress) {
+    for field in fields {
+        match field {
+            Some(value) => stream.append(value),
+            None => stream.begin_list(0),
+        };
+    }
+    stream.append(&RlpAddress(*token));
+}
+
+fn u256_at(rlp: &Rlp, index: usize) -> Result<Option<U256>, DecoderError> {
+    if index >= rlp.item_count()? {
+        return Ok(None);
+    }
+    RlpU256::decode(&rlp.at(index)?).map(|value| Some(value.into()))
+}
+
+fn token_at(rlp: &Rlp, index: usize) -> Result<EthAddress, DecoderError> {
+    if index >= rlp.item_count()? {
+        return Ok(NATIVE_TOKEN);
+    }
+    RlpAddress::decode(&rlp.at(index)?).map(Into::into)
+}
+
+fn optional_at(rlp: &Rlp, index: usize) -> Result<Option<u64>, DecoderError> {
+    if index >= rlp.item_count()? {
+        return Ok(None);
+    }
+    let item = rlp.at(index)?;
+    if item.is_list() {
+        return match item.item_count()? {
+            0 => Ok(None),
+            _ => Err(DecoderError::RlpExpectedToBeData),
+        };
+    }
+    item.as_val().map(Some)
+}
+
 // 为 Payment 实现序列化
 impl Encodable for Payment {
     fn rlp_append(&self, stream: &mut RlpStream) {
-        stream.begin_list(4);
+        // 没有 nonce、valid_until 且为原生代币时保持旧版的 5 个字段
+        let optional = [self.nonce, self.valid_until];
+        stream.begin_list(5 + trailing_len(&optional, &self.token));
         stream.append(&RlpU256(self.pay_id));
         stream.append(&self.serv_id);
         stream.append(&RlpU256(self.amount));  // 新增字段
  
         stream.append(&RlpAddress(self.receiver));
         stream.append(&RlpSignature(self.sig_sender));
+        append_trailing(stream, &optional, &self.token);
     }
 }
 
 impl Decodable for Payment {
     fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
-        if rlp.item_count()? != 5 {  // 修改为5个字段
+        let item_count = rlp.item_count()?;
+        if !(5..=8).contains(&item_count) {  // 5个字段为旧版编码，之后依次为 nonce、valid_until、token
             return Err(DecoderError::RlpIncorrectListLen);
         }
 
@@ -478,6 +1213,9 @@ impl Decodable for Payment {
             amount: RlpU256::decode(&rlp.at(2)?)?.into(),  // 新增字段
             receiver: RlpAddress::decode(&rlp.at(3)?)?.into(),
             sig_sender: RlpSignature::decode(&rlp.at(4)?)?.into(),
+            nonce: optional_at(rlp, 5)?,
+            valid_until: optional_at(rlp, 6)?,
+            token: token_at(rlp, 7)?,
         })
     }
 }
@@ -485,7 +1223,14 @@ impl Decodable for Payment {
 // 为 PaymentSettledByProxy 实现序列化
 impl Encodable for PaymentSettledByProxy {
     fn rlp_append(&self, stream: &mut RlpStream) {
-        stream.begin_list(7);
+        // 没有 nonce、valid_until 且为原生代币时保持旧版的 7 个字段；
+        // 部分结算时编码全部 11 个字段，authorized_amount 在最后
+        let optional = [self.nonce, self.valid_until];
+        let trailing = match self.authorized_amount {
+            Some(_) => optional.len() + 2,
+            None => trailing_len(&optional, &self.token),
+        };
+        stream.begin_list(7 + trailing);
         stream.append(&RlpU256(self.pay_id));
         stream.append(&self.serv_id);
         stream.append(&RlpU256(self.amount));
@@ -493,23 +1238,36 @@ impl Encodable for PaymentSettledByProxy {
         stream.append(&RlpSignature(self.sig_sender));
         stream.append(&self.settled);
         stream.append(&RlpSignature(self.sig_proxy));
+        match self.authorized_amount {
+            Some(authorized_amount) => {
+                append_all_trailing(stream, &optional, &self.token);
+                stream.append(&RlpU256(authorized_amount));
+            }
+            None => append_trailing(stream, &optional, &self.token),
+        }
     }
 }
 
 impl Decodable for PaymentSettledByProxy {
     fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
-        if rlp.item_count()? != 7 {
+        let item_count = rlp.item_count()?;
+        if !(7..=11).contains(&item_count) {  // 7个字段为旧版编码，之后依次为 nonce、valid_until、token、authorized_amount
             return Err(DecoderError::RlpIncorrectListLen);
         }
 
+        let amount = RlpU256::decode(&rlp.at(2)?)?.into();
         Ok(PaymentSettledByProxy {
             pay_id: RlpU256::decode(&rlp.at(0)?)?.into(),
             serv_id: rlp.val_at(1)?,
-            amount: RlpU256::decode(&rlp.at(2)?)?.into(),
+            amount,
             receiver: RlpAddress::decode(&rlp.at(3)?)?.into(),
             sig_sender: RlpSignature::decode(&rlp.at(4)?)?.into(),
             settled: rlp.val_at(5)?,
             sig_proxy: RlpSignature::decode(&rlp.at(6)?)?.into(),
+            nonce: optional_at(rlp, 7)?,
+            valid_until: optional_at(rlp, 8)?,
+            token: token_at(rlp, 9)?,
+            authorized_amount: partial_authorized_amount(amount, u256_at(rlp, 10)?),
         })
     }
 }
@@ -522,9 +1280,25 @@ impl Payment {
         stream.out().to_vec()
     }
 
+    /// 等价于 decode_checked，只保留底层错误
     pub fn rlp_decode(bytes: &[u8]) -> Result<Self, DecoderError> {
-        let rlp = Rlp::new(bytes);
-        Self::decode(&rlp)
+        Self::decode_checked(bytes).map_err(|e| e.error)
+    }
+
+    /// 带检查的解码：整个输入必须恰好是一个列表，每个字段必须是数据且长度不超过上限，
+    /// 错误中带有出错的字段名
+    pub fn decode_checked(bytes: &[u8]) -> Result<Self, RlpDecodeError> {
+        let items = checked_list(bytes, "payment", &PAYMENT_RLP_FIELDS, 5)?;
+        Ok(Payment {
+            pay_id: decode_field::<RlpU256>(&items[0], "pay_id")?.into(),
+            serv_id: decode_field(&items[1], "serv_id")?,
+            amount: decode_field::<RlpU256>(&items[2], "amount")?.into(),
+            receiver: decode_field::<RlpAddress>(&items[3], "receiver")?.into(),
+            sig_sender: decode_field::<RlpSignature>(&items[4], "sig_sender")?.into(),
+            nonce: decode_optional(items.get(5), "nonce")?,
+            valid_until: decode_optional(items.get(6), "valid_until")?,
+            token: decode_token(items.get(7))?,
+        })
     }
 }
 
@@ -535,9 +1309,139 @@ impl PaymentSettledByProxy {
         stream.out().to_vec()
     }
 
+    /// 等价于 decode_checked，只保留底层错误
     pub fn rlp_decode(bytes: &[u8]) -> Result<Self, DecoderError> {
-        let rlp = Rlp::new(bytes);
-        Self::decode(&rlp)
+        Self::decode_checked(bytes).map_err(|e| e.error)
+    }
+
+    /// 带检查的解码，规则与 Payment::decode_checked 相同
+    pub fn decode_checked(bytes: &[u8]) -> Result<Self, RlpDecodeError> {
+        let items = checked_list(bytes, "payment_settled_by_proxy", &SETTLED_RLP_FIELDS, 7)?;
+        let amount = decode_field::<RlpU256>(&items[2], "amount")?.into();
+        Ok(PaymentSettledByProxy {
+            pay_id: decode_field::<RlpU256>(&items[0], "pay_id")?.into(),
+            serv_id: decode_field(&items[1], "serv_id")?,
+            amount,
+            receiver: decode_field::<RlpAddress>(&items[3], "receiver")?.into(),
+            sig_sender: decode_field::<RlpSignature>(&items[4], "sig_sender")?.into(),
+            settled: decode_field(&items[5], "settled")?,
+            sig_proxy: decode_field::<RlpSignature>(&items[6], "sig_proxy")?.into(),
+            nonce: decode_optional(items.get(7), "nonce")?,
+            valid_until: decode_optional(items.get(8), "valid_until")?,
+            token: decode_token(items.get(9))?,
+            authorized_amount: partial_authorized_amount(
+                amount,
+                items
+                    .get(10)
+                    .map(|item| decode_field::<RlpU256>(item, "authorized_amount").map(Into::into))
+                    .transpose()?,
+            ),
+        })
+    }
+}
+
+/// RLP 解码失败，field 为出错的字段名，整个列表出错时为列表名
+#[derive(Debug, Clone, PartialEq)]
+pub struct RlpDecodeError {
+    pub field: &'static str,
+    pub error: DecoderError,
+}
+
+impl std::fmt::Display for RlpDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid RLP field {}: {}", self.field, self.error)
+    }
+}
+
+impl std::error::Error for RlpDecodeError {}
+
+// (字段名, 数据最大字节数)，可选的 nonce、valid_until、token（收据还有 authorized_amount）在最后
+const PAYMENT_RLP_FIELDS: [(&str, usize); 8] = [
+    ("pay_id", 32),
+    ("serv_id", 4),
+    ("amount", 32),
+    ("receiver", 20),
+    ("sig_sender", 65),
+    ("nonce", 8),
+    ("valid_until", 8),
+    ("token", 20),
+];
+
+const SETTLED_RLP_FIELDS: [(&str, usize); 11] = [
+    ("pay_id", 32),
+    ("serv_id", 4),
+    ("amount", 32),
+    ("receiver", 20),
+    ("sig_sender", 65),
+    ("settled", 1),
+    ("sig_proxy", 65),
+    ("nonce", 8),
+    ("valid_until", 8),
+    ("token", 20),
+    ("authorized_amount", 32),
+];
+
+/// 检查列表结构后返回各字段，复制数据之前先检查长度
+fn checked_list<'a>(
+    bytes: &'a [u8],
+    list: &'static str,
+    fields: &[(&'static str, usize)],
+    required: usize,
+) -> Result<Vec<Rlp<'a>>, RlpDecodeError> {
+    let list_error = |error| RlpDecodeError { field: list, error };
+
+    let rlp = Rlp::new(bytes);
+    if !rlp.is_list() {
+        return Err(list_error(DecoderError::RlpExpectedToBeList));
+    }
+    // 声明的长度必须与输入一致，截断和超长的长度前缀都在这里被拒绝
+    let payload = rlp.payload_info().map_err(list_error)?;
+    if payload.header_len.checked_add(payload.value_len) != Some(bytes.len()) {
+        return Err(list_error(DecoderError::RlpInconsistentLengthAndData));
+    }
+
+    let item_count = rlp.item_count().map_err(list_error)?;
+    if item_count < required || item_count > fields.len() {
+        return Err(list_error(DecoderError::RlpIncorrectListLen));
+    }
+
+    let mut items = Vec::with_capacity(item_count);
+    for (index, &(field, max_len)) in fields.iter().take(item_count).enumerate() {
+        let field_error = |error| RlpDecodeError { field, error };
+        let item = rlp.at(index).map_err(field_error)?;
+        // 可选字段可以是空列表占位
+        if index >= required && item.is_list() && item.item_count() == Ok(0) {
+            items.push(item);
+            continue;
+        }
+        if !item.is_data() {
+            return Err(field_error(DecoderError::RlpExpectedToBeData));
+        }
+        if item.data().map_err(field_error)?.len() > max_len {
+            return Err(field_error(DecoderError::RlpIsTooBig));
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn decode_field<T: Decodable>(item: &Rlp, field: &'static str) -> Result<T, RlpDecodeError> {
+    T::decode(item).map_err(|error| RlpDecodeError { field, error })
+}
+
+// 缺少时为原生代币
+fn decode_token(item: Option<&Rlp>) -> Result<EthAddress, RlpDecodeError> {
+    match item {
+        Some(item) => decode_field::<RlpAddress>(item, "token").map(Into::into),
+        None => Ok(NATIVE_TOKEN),
+    }
+}
+
+// 缺少或为空列表占位时为 None
+fn decode_optional(item: Option<&Rlp>, field: &'static str) -> Result<Option<u64>, RlpDecodeError> {
+    match item {
+        Some(item) if !item.is_list() => decode_field(item, field).map(Some),
+        _ => Ok(None),
     }
 }
 impl Payment {
@@ -560,7 +1464,7 @@ impl Payment {
         packed.extend_from_slice(&self.sig_sender);
         
         // 计算哈希
-        B256::from_slice(&keccak256(&packed))
+        hash_with_domain(HashDomain::PaymentLeaf, &[&versioned_payload(packed, self.nonce, self.valid_until, &self.token, None)])
     }
 }
 
@@ -591,34 +1495,186 @@ impl PaymentSettledByProxy {
         packed.extend_from_slice(&self.sig_proxy);
         
         // 计算哈希
-        B256::from_slice(&keccak256(&packed))
+        hash_with_domain(HashDomain::PaymentLeaf, &[&versioned_payload(packed, self.nonce, self.valid_until, &self.token, self.authorized_amount)])
     }
 
     // hash_for_signing 方法也需要更新
     pub fn hash_for_signing(&self) -> B256 {
-        let mut packed = Vec::new();
-        
-        packed.extend_from_slice(&self.pay_id.to_be_bytes::<32>());
-        packed.extend_from_slice(&self.serv_id.to_be_bytes());
-        packed.extend_from_slice(&self.amount.to_be_bytes::<32>());
-        packed.extend_from_slice(&self.receiver);
-        packed.extend_from_slice(&self.sig_sender);
-        packed.push(self.settled as u8);
-        
-        B256::from_slice(&keccak256(&packed))
+        B256::from_slice(&keccak256(&self.proxy_signing_payload()))
     }
     // 辅助函数：将payment转换为key
+    //
+    // 不包含金额：同一 (pay_id, serv_id, receiver, nonce) 下金额不同的收据 key 相同，
+    // overpay 检查和嵌套分组把它们当作冲突拒绝，见 UniquenessKey
     pub fn to_key(&self) ->B256{
-        let mut hasher = Keccak::v256();
-        let mut output = [0u8; 32];
-        hasher.update(&self.pay_id.to_be_bytes::<32>());
-        hasher.update(&self.serv_id.to_be_bytes());
-        hasher.update(&self.receiver);
-        hasher.finalize(&mut output);
-        output.into()
+        UniquenessKey::new(self.pay_id, self.serv_id, &self.receiver, self.nonce).into()
+    }
+
+    /// 包含金额的唯一性键，同一 (pay_id, serv_id, receiver, nonce) 允许多张收据时使用
+    pub fn to_unique_key(&self) -> B256 {
+        UniquenessKey::including_amount(self.pay_id, self.serv_id, &self.receiver, self.amount, self.nonce).into()
+    }
+}
+
+
+/// 同一 (pay_id, serv_id, receiver, nonce) 出现了内容不同的收据，需要人工确认；与 to_key() 相同，nonce 不同的收据不冲突
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptConflict {
+    pub pay_id: U256,
+    pub serv_id: u32,
+    pub receiver: EthAddress,
+    pub nonce: Option<u64>,
+    pub amounts: Vec<U256>, // 每个不同版本的金额，按首次出现的顺序
+}
+
+/// dedupe_receipts 的处理报告
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DedupeReport {
+    /// 每个 (pay_id, receiver) 丢弃的完全重复收据数量，按 (pay_id, receiver) 升序
+    pub dropped: Vec<((U256, EthAddress), usize)>,
+    /// 按 (pay_id, serv_id, receiver, nonce) 升序
+    pub conflicts: Vec<ReceiptConflict>,
+}
+
+impl DedupeReport {
+    pub fn dropped_total(&self) -> usize {
+        self.dropped.iter().map(|(_, count)| count).sum()
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+}
+
+/// 去掉 hash() 完全相同的重复收据，保留每个收据第一次出现的位置，其余顺序不变
+///
+/// 同一 (pay_id, serv_id, receiver, nonce) 下内容不同的收据不做取舍，全部保留并记入 conflicts，
+/// 之后由 overpay 检查的唯一性校验拒绝，避免悄悄丢掉其中一个
+pub fn dedupe_receipts(receipts: Vec<PaymentSettledByProxy>) -> (Vec<PaymentSettledByProxy>, DedupeReport) {
+    let (kept, _, report) = split_duplicates(receipts);
+    (kept, report)
+}
+
+/// 与 dedupe_receipts 相同，同时返回去掉的重复收据，按输入顺序
+pub(crate) fn split_duplicates(
+    receipts: Vec<PaymentSettledByProxy>,
+) -> (Vec<PaymentSettledByProxy>, Vec<PaymentSettledByProxy>, DedupeReport) {
+    let mut seen = std::collections::HashSet::new();
+    let mut dropped: std::collections::BTreeMap<(U256, EthAddress), usize> = Default::default();
+    let mut variants: std::collections::BTreeMap<(U256, u32, EthAddress, Option<u64>), Vec<U256>> = Default::default();
+
+    let mut kept = Vec::with_capacity(receipts.len());
+    let mut duplicates = Vec::new();
+    for receipt in receipts {
+        if !seen.insert(receipt.hash()) {
+            *dropped.entry((receipt.pay_id, receipt.receiver)).or_default() += 1;
+            duplicates.push(receipt);
+            continue;
+        }
+        variants
+            .entry((receipt.pay_id, receipt.serv_id, receipt.receiver, receipt.nonce))
+            .or_default()
+            .push(receipt.amount);
+        kept.push(receipt);
     }
+
+    let conflicts = variants
+        .into_iter()
+        .filter(|(_, amounts)| amounts.len() > 1)
+        .map(|((pay_id, serv_id, receiver, nonce), amounts)| ReceiptConflict { pay_id, serv_id, receiver, nonce, amounts })
+        .collect();
+
+    (kept, duplicates, DedupeReport { dropped: dropped.into_iter().collect(), conflicts })
 }
 
+#[cfg(test)]
+mod dedupe_tests {
+    use super::*;
+
+    fn receipt(pay_id: u64, serv_id: u32, amount: u64, receiver: u8) -> PaymentSettledByProxy {
+        PaymentSettledByProxy::new(U256::from(pay_id), serv_id, U256::from(amount), [receiver; 20])
+            .with_settled(true)
+            .with_sig_sender([1u8; 65])
+            .with_sig_proxy([2u8; 65])
+    }
+
+    fn hashes(receipts: &[PaymentSettledByProxy]) -> Vec<B256> {
+        receipts.iter().map(|r| r.hash()).collect()
+    }
+
+    #[test]
+    fn test_exact_duplicates_removed() {
+        let input = vec![
+            receipt(1, 1, 100, 1),
+            receipt(1, 2, 200, 1),
+            receipt(1, 1, 100, 1),
+            receipt(2, 1, 300, 2),
+            receipt(1, 1, 100, 1),
+            receipt(2, 1, 300, 2),
+        ];
+        let (kept, report) = dedupe_receipts(input.clone());
+
+        assert_eq!(hashes(&kept), hashes(&[input[0].clone(), input[1].clone(), input[3].clone()]));
+        assert_eq!(
+            report.dropped,
+            vec![((U256::from(1), [1u8; 20]), 2), ((U256::from(2), [2u8; 20]), 1)]
+        );
+        assert_eq!(report.dropped_total(), 3);
+        assert!(!report.has_conflicts());
+
+        // 没有重复时原样返回
+        let (unchanged, report) = dedupe_receipts(kept.clone());
+        assert_eq!(hashes(&unchanged), hashes(&kept));
+        assert_eq!(report, DedupeReport::default());
+    }
+
+    #[test]
+    fn test_conflicting_amounts_surfaced() {
+        let input = vec![
+            receipt(1, 1, 100, 1),
+            receipt(1, 1, 150, 1),
+            receipt(1, 1, 100, 1),
+            receipt(1, 2, 100, 1),
+        ];
+        let (kept, report) = dedupe_receipts(input);
+
+        // 冲突的两个版本都保留
+        assert_eq!(kept.len(), 3);
+        assert_eq!(report.dropped_total(), 1);
+        assert_eq!(
+            report.conflicts,
+            vec![ReceiptConflict {
+                pay_id: U256::from(1),
+                serv_id: 1,
+                receiver: [1u8; 20],
+                nonce: None,
+                amounts: vec![U256::from(100), U256::from(150)],
+            }]
+        );
+
+        // nonce 不同的收据不冲突
+        let (kept, report) = dedupe_receipts(vec![receipt(1, 1, 100, 1).with_nonce(1), receipt(1, 1, 150, 1).with_nonce(2)]);
+        assert_eq!(kept.len(), 2);
+        assert!(!report.has_conflicts());
+    }
+
+    #[test]
+    fn test_output_order_deterministic() {
+        let input: Vec<PaymentSettledByProxy> = (0..20u64)
+            .map(|i| receipt(i % 3, (i % 5) as u32, 100, (i % 4) as u8))
+            .collect();
+        let mut doubled = input.clone();
+        doubled.extend(input.iter().rev().cloned());
+
+        let (first, first_report) = dedupe_receipts(doubled.clone());
+        let (second, second_report) = dedupe_receipts(doubled);
+        assert_eq!(hashes(&first), hashes(&second));
+        assert_eq!(first_report, second_report);
+        // 保留第一次出现的顺序
+        assert_eq!(hashes(&first), hashes(&input));
+        assert_eq!(first_report.dropped_total(), 20);
+    }
+}
 
 // 添加测试
 #[cfg(test)]
@@ -627,12 +1683,8 @@ mod hash_tests {
 
     #[test]
     fn test_payment_hash() {
-        let payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-        };
+        let payment = Payment::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_sig_sender(EthSignature::from([1u8; 65]));
 
         let hash1 = payment.hash();
         let hash2 = payment.hash();
@@ -646,17 +1698,27 @@ mod hash_tests {
         assert_ne!(payment.hash(), payment2.hash());
     }
 
+    #[test]
+    fn test_payment_hash_covers_amount() {
+        let payment = Payment::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]));
+
+        let mut payment2 = payment.clone();
+        payment2.amount = U256::from(101);
+        assert_ne!(payment.hash(), payment2.hash());
+
+        // 结算后的收据同样覆盖 amount
+        let settled: PaymentSettledByProxy = payment.into();
+        let settled2: PaymentSettledByProxy = payment2.into();
+        assert_ne!(settled.hash(), settled2.hash());
+        assert_ne!(settled.hash_for_signing(), settled2.hash_for_signing());
+    }
+
     #[test]
     fn test_payment_settled_hash() {
-        let payment = PaymentSettledByProxy {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-            settled: true,
-            sig_proxy: EthSignature::from([2u8; 65]),
-        };
+        let payment = PaymentSettledByProxy::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_settled(true)
+            .with_sig_sender(EthSignature::from([1u8; 65]))
+            .with_sig_proxy(EthSignature::from([2u8; 65]));
 
         let hash1 = payment.hash();
         let hash2 = payment.hash();
@@ -682,16 +1744,18 @@ mod hash_tests {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::DeterministicRng;
+    use rand::SeedableRng;
+
+    // 可复现的测试私钥，不同的 seed 得到不同的私钥
+    fn test_key(seed: u64) -> SecretKey {
+        SecretKey::random(&mut DeterministicRng::seed_from_u64(seed))
+    }
 
     #[test]
     fn test_payment_rlp() {
-        let payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),  // 添加 amount
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-        };
+        let payment = Payment::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_sig_sender(EthSignature::from([1u8; 65]));
 
         let encoded = payment.rlp_encode();
         let decoded = Payment::rlp_decode(&encoded).unwrap();
@@ -704,15 +1768,10 @@ mod tests {
 
     #[test]
     fn test_payment_settled_rlp() {
-        let payment_settled = PaymentSettledByProxy {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-            settled: true,
-            sig_proxy: EthSignature::from([2u8; 65]),
-        };
+        let payment_settled = PaymentSettledByProxy::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_settled(true)
+            .with_sig_sender(EthSignature::from([1u8; 65]))
+            .with_sig_proxy(EthSignature::from([2u8; 65]));
 
         let encoded = payment_settled.rlp_encode();
         let decoded = PaymentSettledByProxy::rlp_decode(&encoded).unwrap();
@@ -729,25 +1788,15 @@ mod tests {
 
     // 辅助函数：创建测试数据
     fn create_test_payment() -> Payment {
-        Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),  // 添加 amount
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-        }
+        Payment::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_sig_sender(EthSignature::from([1u8; 65]))
     }
 
     fn create_test_payment_settled() -> PaymentSettledByProxy {
-        PaymentSettledByProxy {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),
-            receiver: EthAddress::from([1u8; 20]),
-            sig_sender: EthSignature::from([1u8; 65]),
-            settled: true,
-            sig_proxy: EthSignature::from([2u8; 65]),
-        }
+        PaymentSettledByProxy::new(U256::from(1), 1, U256::from(100), EthAddress::from([1u8; 20]))
+            .with_settled(true)
+            .with_sig_sender(EthSignature::from([1u8; 65]))
+            .with_sig_proxy(EthSignature::from([2u8; 65]))
     }
 
     // U256 编码测试
@@ -846,13 +1895,7 @@ mod tests {
 
     #[test]
     fn test_payment_rlp_zero_values() {
-        let payment = Payment {
-            pay_id: U256::default(),
-            serv_id: 0,
-            amount: U256::from(100),  // 添加 amount
-            receiver: EthAddress::from([0u8; 20]),
-            sig_sender: EthSignature::from([0u8; 65]),
-        };
+        let payment = Payment::new(U256::default(), 0, U256::from(100), EthAddress::from([0u8; 20]));
         let encoded = payment.rlp_encode();
         let decoded = Payment::rlp_decode(&encoded).unwrap();
         assert_eq!(payment.pay_id, decoded.pay_id);
@@ -876,6 +1919,344 @@ mod tests {
         assert_eq!(payment.sig_proxy, decoded.sig_proxy);
     }
 
+    #[test]
+    fn test_payment_settled_rlp_legacy_layout() {
+        // 手工构造旧版 7 字段编码
+        let legacy = create_test_payment_settled();
+        let mut stream = RlpStream::new();
+        stream.begin_list(7);
+        stream.append(&RlpU256(legacy.pay_id));
+        stream.append(&legacy.serv_id);
+        stream.append(&RlpU256(legacy.amount));
+        stream.append(&RlpAddress(legacy.receiver));
+        stream.append(&RlpSignature(legacy.sig_sender));
+        stream.append(&legacy.settled);
+        stream.append(&RlpSignature(legacy.sig_proxy));
+        let encoded = stream.out().to_vec();
+
+        let decoded = PaymentSettledByProxy::rlp_decode(&encoded).unwrap();
+        assert_eq!(decoded.nonce, None);
+        assert_eq!(decoded.hash(), legacy.hash());
+        // 没有 nonce 时编码与旧版完全一致
+        assert_eq!(legacy.rlp_encode(), encoded);
+
+        // 带 nonce 时为 8 个字段
+        let mut with_nonce = legacy.clone();
+        with_nonce.nonce = Some(7);
+        let encoded = with_nonce.rlp_encode();
+        assert_eq!(Rlp::new(&encoded).item_count().unwrap(), 8);
+        let decoded = PaymentSettledByProxy::rlp_decode(&encoded).unwrap();
+        assert_eq!(decoded.nonce, Some(7));
+        assert_eq!(decoded.hash(), with_nonce.hash());
+
+        let mut payment = create_test_payment();
+        payment.nonce = Some(7);
+        let decoded = Payment::rlp_decode(&payment.rlp_encode()).unwrap();
+        assert_eq!(decoded.nonce, Some(7));
+        assert_eq!(decoded.hash(), payment.hash());
+    }
+
+    #[test]
+    fn test_valid_until_layouts() {
+        let legacy = create_test_payment_settled();
+        let decoded = PaymentSettledByProxy::rlp_decode(&legacy.rlp_encode()).unwrap();
+        assert_eq!(decoded.valid_until, None);
+        assert_eq!(PaymentSettledByProxy::decode_checked(&legacy.rlp_encode()).unwrap().valid_until, None);
+
+        // 只有 valid_until 时 nonce 位置为空列表占位，与 nonce = 0 区分
+        let expiring = legacy.clone().with_valid_until(1000);
+        let encoded = expiring.rlp_encode();
+        assert_eq!(Rlp::new(&encoded).item_count().unwrap(), 9);
+        for decoded in [
+            PaymentSettledByProxy::rlp_decode(&encoded).unwrap(),
+            PaymentSettledByProxy::decode_checked(&encoded).unwrap(),
+        ] {
+            assert_eq!(decoded.nonce, None);
+            assert_eq!(decoded.valid_until, Some(1000));
+            assert_eq!(decoded.hash(), expiring.hash());
+        }
+        let zero_nonce = expiring.clone().with_nonce(0);
+        assert_ne!(zero_nonce.rlp_encode(), encoded);
+        assert_ne!(zero_nonce.hash(), expiring.hash());
+        assert_eq!(PaymentSettledByProxy::rlp_decode(&zero_nonce.rlp_encode()).unwrap().nonce, Some(0));
+
+        let payment = create_test_payment().with_nonce(3).with_valid_until(1000);
+        let decoded = Payment::decode_checked(&payment.rlp_encode()).unwrap();
+        assert_eq!((decoded.nonce, decoded.valid_until), (Some(3), Some(1000)));
+        assert_eq!(decoded.hash(), payment.hash());
+        assert_eq!(Payment::rlp_decode(&create_test_payment().rlp_encode()).unwrap().valid_until, None);
+
+        // valid_until 参与签名载荷
+        let sender_key = test_key(1);
+        let sender_address = crate::get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
+        let mut payment = create_test_payment().with_valid_until(1000);
+        payment.sign(&sender_key).unwrap();
+        assert_eq!(payment.get_signer_address().unwrap(), sender_address);
+        let settled: PaymentSettledByProxy = payment.clone().into();
+        assert_eq!(settled.valid_until, Some(1000));
+        assert_eq!(settled.get_sender_address().unwrap(), sender_address);
+        let extended = payment.with_valid_until(2000);
+        assert_ne!(extended.get_signer_address().ok(), Some(sender_address));
+
+        // stdin 布局只在有 valid_until 的收据时包含该字段
+        assert!(!ReceiptStdinLayout::for_receipts(&[create_test_payment_settled()]).has_valid_until());
+        let layout = ReceiptStdinLayout::for_receipts(&[create_test_payment_settled(), settled]);
+        assert!(layout.has_valid_until() && !layout.has_nonce());
+        assert_eq!(ReceiptStdinLayout::from_bits(layout.bits()), Some(layout));
+    }
+
+    #[test]
+    fn test_token_layouts() {
+        let token = [0xaau8; 20];
+        let legacy = create_test_payment_settled();
+        assert_eq!(legacy.token, NATIVE_TOKEN);
+
+        // 只有 token 时 nonce、valid_until 位置为空列表占位
+        let with_token = legacy.clone().with_token(token);
+        let encoded = with_token.rlp_encode();
+        assert_eq!(Rlp::new(&encoded).item_count().unwrap(), 10);
+        for decoded in [
+            PaymentSettledByProxy::rlp_decode(&encoded).unwrap(),
+            PaymentSettledByProxy::decode_checked(&encoded).unwrap(),
+        ] {
+            assert_eq!((decoded.nonce, decoded.valid_until, decoded.token), (None, None, token));
+            assert_eq!(decoded.hash(), with_token.hash());
+        }
+        assert_ne!(with_token.hash(), legacy.hash());
+        assert_ne!(with_token.proxy_signing_payload(), legacy.proxy_signing_payload());
+        assert_eq!(with_token.proxy_signing_payload()[0], PAYLOAD_VERSION_TOKEN);
+
+        let payment = create_test_payment().with_nonce(3).with_token(token);
+        let decoded = Payment::decode_checked(&payment.rlp_encode()).unwrap();
+        assert_eq!((decoded.nonce, decoded.token), (Some(3), token));
+        assert_eq!(decoded.hash(), payment.hash());
+        assert_eq!(payment.signing_payload()[0], PAYLOAD_VERSION_NONCE | PAYLOAD_VERSION_TOKEN);
+
+        // 旧的 JSON 没有 token 字段时为原生代币
+        let json = serde_json::to_value(&legacy).unwrap();
+        let mut object = json.as_object().unwrap().clone();
+        object.remove("token");
+        let decoded: PaymentSettledByProxy = serde_json::from_value(object.into()).unwrap();
+        assert_eq!(decoded.token, NATIVE_TOKEN);
+        assert_eq!(decoded.hash(), legacy.hash());
+
+        // token 参与发送者签名
+        let sender_key = test_key(2);
+        let sender_address = crate::get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
+        let mut payment = create_test_payment().with_token(token);
+        payment.sign(&sender_key).unwrap();
+        let settled: PaymentSettledByProxy = payment.clone().into();
+        assert_eq!(settled.token, token);
+        assert_eq!(settled.get_sender_address().unwrap(), sender_address);
+        assert_ne!(payment.with_token([0xbbu8; 20]).get_signer_address().ok(), Some(sender_address));
+
+        // stdin 布局只在有非原生代币时包含 token，未知的位被拒绝
+        assert_eq!(ReceiptStdinLayout::for_receipts(&[legacy.clone()]), ReceiptStdinLayout::LEGACY);
+        let layout = ReceiptStdinLayout::for_receipts(&[legacy, with_token]);
+        assert!(layout.has_token());
+        assert_eq!(ReceiptStdinLayout::from_bits(layout.bits()), Some(layout));
+        assert_eq!(ReceiptStdinLayout::from_bits(0x80), None);
+    }
+
+    #[test]
+    fn test_partial_settlement_layouts() {
+        let sender_key = test_key(3);
+        let proxy_key = test_key(4);
+        let sender_address = crate::get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
+        let mut payment = create_test_payment();
+        payment.sign(&sender_key).unwrap();
+
+        // 代理只结算 60，发送者签名仍按授权的 100 验证
+        let mut partial = PaymentSettledByProxy::from(payment.clone());
+        partial.set_settlement(U256::from(60), true);
+        partial.sign_by_proxy(&proxy_key).unwrap();
+        assert_eq!(partial.authorized_amount, Some(U256::from(100)));
+        assert_eq!((partial.authorized(), partial.settled_amount()), (U256::from(100), U256::from(60)));
+        assert!(partial.is_partial());
+        assert_eq!(partial.get_sender_address().unwrap(), sender_address);
+        assert!(partial.verify_proxy_signature(&PublicKey::from_secret_key(&proxy_key)).unwrap());
+        assert_eq!(partial.proxy_signing_payload()[0], PAYLOAD_VERSION_PARTIAL);
+        assert!(check_partial_settlement(&[partial.clone()]).is_ok());
+
+        // 全额结算时不记录 authorized_amount，布局与之前相同
+        let mut full = PaymentSettledByProxy::from(payment);
+        full.set_settlement(U256::from(100), true);
+        assert_eq!(full.authorized_amount, None);
+        assert!(!full.is_partial());
+        assert_ne!(full.hash(), partial.clone().with_authorized_amount(U256::from(60)).hash());
+        assert_eq!(partial.clone().with_authorized_amount(U256::from(60)).authorized_amount, None);
+
+        // 部分结算编码全部 11 个字段
+        let encoded = partial.rlp_encode();
+        assert_eq!(Rlp::new(&encoded).item_count().unwrap(), 11);
+        for decoded in [
+            PaymentSettledByProxy::rlp_decode(&encoded).unwrap(),
+            PaymentSettledByProxy::decode_checked(&encoded).unwrap(),
+        ] {
+            assert_eq!(decoded.authorized_amount, Some(U256::from(100)));
+            assert_eq!((decoded.nonce, decoded.valid_until, decoded.token), (None, None, NATIVE_TOKEN));
+            assert_eq!(decoded.hash(), partial.hash());
+        }
+
+        // 旧的编码和 JSON 没有 authorized_amount，两个金额相同
+        let legacy = create_test_payment_settled();
+        assert_eq!(Rlp::new(&legacy.rlp_encode()).item_count().unwrap(), 7);
+        assert_eq!(PaymentSettledByProxy::decode_checked(&legacy.rlp_encode()).unwrap().authorized(), legacy.amount);
+        let mut object = serde_json::to_value(&legacy).unwrap().as_object().unwrap().clone();
+        object.remove("authorized_amount");
+        let decoded: PaymentSettledByProxy = serde_json::from_value(object.into()).unwrap();
+        assert_eq!((decoded.authorized(), decoded.settled_amount()), (legacy.amount, legacy.amount));
+        assert_eq!(decoded.hash(), legacy.hash());
+
+        // 解码出等于结算金额的 authorized_amount 时规范化为 None，与旧数据的哈希相同
+        let mut explicit = legacy.clone();
+        explicit.authorized_amount = Some(legacy.amount);
+        let encoded = explicit.rlp_encode();
+        for decoded in [
+            PaymentSettledByProxy::rlp_decode(&encoded).unwrap(),
+            PaymentSettledByProxy::decode_checked(&encoded).unwrap(),
+            serde_json::from_value(serde_json::to_value(&explicit).unwrap()).unwrap(),
+        ] {
+            assert_eq!(decoded.authorized_amount, None);
+            assert_eq!(decoded.hash(), legacy.hash());
+        }
+        assert_eq!(partial_authorized_amount(U256::from(60), Some(U256::from(100))), Some(U256::from(100)));
+
+        // 只有部分结算的收据需要在 stdin 布局中包含 authorized_amount
+        assert!(!ReceiptStdinLayout::for_receipts(&[legacy.clone()]).has_authorized_amount());
+        let layout = ReceiptStdinLayout::for_receipts(&[legacy, partial.clone()]);
+        assert!(layout.has_authorized_amount());
+        assert_eq!(ReceiptStdinLayout::from_bits(layout.bits()), Some(layout));
+
+        // 结算金额超过授权金额
+        let over = create_test_payment_settled().with_authorized_amount(U256::from(99));
+        let err = check_partial_settlement(&[partial, over]).unwrap_err();
+        assert_eq!((err.index, err.inner.authorized, err.inner.settled), (Some(1), U256::from(99), U256::from(100)));
+    }
+
+    #[test]
+    fn test_receipt_expiry() {
+        let receipts = vec![
+            create_test_payment_settled(),
+            create_test_payment_settled().with_valid_until(1000),
+        ];
+        assert!(receipts[0].is_valid_at(u64::MAX));
+        assert!(check_receipt_expiry(&receipts, 1000).is_ok());
+
+        let err = check_receipt_expiry(&receipts, 1001).unwrap_err();
+        assert_eq!((err.inner.valid_until, err.inner.current_time), (1000, 1001));
+        assert_eq!((err.index, err.inner.pay_id), (Some(1), U256::from(1)));
+    }
+
+    #[test]
+    fn test_canonical_receipt_order() {
+        use std::cmp::Ordering;
+
+        let a = create_test_payment_settled();
+        let mut b = a.clone();
+        b.amount += U256::from(1);
+        assert_eq!(a.to_key(), b.to_key());
+
+        // key 相同时按 hash() 决定，与参数顺序一致
+        let expected = a.hash().cmp(&b.hash());
+        assert_ne!(expected, Ordering::Equal);
+        assert_eq!(canonical_receipt_order(&a, &b), expected);
+        assert_eq!(canonical_receipt_order(&b, &a), expected.reverse());
+        assert_eq!(canonical_receipt_order(&a, &a.clone()), Ordering::Equal);
+
+        let entries = canonical_entries([&b, &a]).unwrap();
+        assert_eq!(entries, canonical_entries([&a, &b]).unwrap());
+        assert_eq!(
+            canonical_entries([&a, &b, &a]).unwrap_err(),
+            DuplicateReceipt { key: a.to_key(), first: a.hash(), second: a.hash() }
+        );
+
+        // key 冲突只在 check_unique_keys 中报告，按 hash() 顺序给出两个收据
+        let (low, high) = if expected == Ordering::Less { (&a, &b) } else { (&b, &a) };
+        assert_eq!(
+            check_unique_keys(&entries).unwrap_err(),
+            DuplicateReceipt { key: a.to_key(), first: low.hash(), second: high.hash() }
+        );
+
+        // 包含金额的键区分两个收据，也不与 to_key() 相同
+        assert_ne!(a.to_unique_key(), b.to_unique_key());
+        assert_ne!(a.to_unique_key(), a.to_key());
+        assert_eq!(
+            UniquenessKey::new(a.pay_id, a.serv_id, &a.receiver, a.nonce).as_b256(),
+            a.to_key()
+        );
+    }
+
+    #[test]
+    fn test_nonce_in_payloads() {
+        let sender_key = test_key(5);
+        let proxy_key = test_key(6);
+        let sender_address = crate::get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
+        let proxy_public_key = PublicKey::from_secret_key(&proxy_key);
+
+        let mut payment = create_test_payment();
+        payment.nonce = Some(1);
+        payment.sign(&sender_key).unwrap();
+        assert_eq!(payment.get_signer_address().unwrap(), sender_address);
+
+        let mut settled: PaymentSettledByProxy = payment.clone().into();
+        assert_eq!(settled.nonce, Some(1));
+        settled.set_settlement(U256::from(100), true);
+        settled.sign_by_proxy(&proxy_key).unwrap();
+        assert_eq!(settled.get_sender_address().unwrap(), sender_address);
+        assert!(settled.verify_proxy_signature(&proxy_public_key).unwrap());
+
+        // 修改 nonce 后两个签名都失效
+        let mut replayed = settled.clone();
+        replayed.nonce = Some(2);
+        assert_ne!(replayed.get_sender_address().ok(), Some(sender_address));
+        assert!(!replayed.verify_proxy_signature(&proxy_public_key).unwrap());
+
+        // nonce 参与 hash 和 to_key
+        assert_ne!(replayed.hash(), settled.hash());
+        assert_ne!(replayed.to_key(), settled.to_key());
+        let mut legacy = settled.clone();
+        legacy.nonce = None;
+        assert_ne!(legacy.hash_for_signing(), settled.hash_for_signing());
+
+        // 有 nonce 的收据需要包含 nonce 的 stdin 布局，旧的 host 写入的收据按 LEGACY 读取为 None
+        assert!(!ReceiptStdinLayout::for_receipts(&[create_test_payment_settled()]).has_nonce());
+        let layout = ReceiptStdinLayout::for_receipts(&[create_test_payment_settled().with_nonce(1)]);
+        assert!(layout.has_nonce() && !layout.has_token());
+        assert_eq!(ReceiptStdinLayout::from_bits(layout.with_token().bits()), Some(layout.with_token()));
+    }
+
+    #[test]
+    fn test_signing_domain() {
+        let sender_key = test_key(7);
+        let proxy_key = test_key(8);
+        let sender_public_key = PublicKey::from_secret_key(&sender_key);
+        let proxy_public_key = PublicKey::from_secret_key(&proxy_key);
+        let mainnet = SigningDomain::new(1, [9u8; 20]);
+        let goerli = SigningDomain::new(5, [9u8; 20]);
+
+        let mut payment = create_test_payment();
+        payment.sign_with_domain(&sender_key, &mainnet).unwrap();
+        assert!(payment.verify_with_domain(&sender_public_key, &mainnet).unwrap());
+        assert!(!payment.verify_with_domain(&sender_public_key, &goerli).unwrap());
+        assert!(!payment.verify(&sender_public_key).unwrap());
+
+        let mut settled: PaymentSettledByProxy = payment.into();
+        settled.sign_by_proxy_with_domain(&proxy_key, &mainnet).unwrap();
+        assert!(settled.verify_proxy_signature_with_domain(&proxy_public_key, &mainnet).unwrap());
+        assert!(!settled.verify_proxy_signature_with_domain(&proxy_public_key, &goerli).unwrap());
+
+        let sender_address = crate::get_ethereum_address(&sender_public_key);
+        let proxy_address = crate::get_ethereum_address(&proxy_public_key);
+        assert_eq!(settled.get_sender_address_with_domain(&mainnet).unwrap(), sender_address);
+        assert_eq!(settled.get_proxy_address_with_domain(&mainnet).unwrap(), proxy_address);
+        assert_ne!(settled.get_proxy_address_with_domain(&goerli).ok(), Some(proxy_address));
+
+        // 合约地址同样参与域分隔
+        let other_contract = SigningDomain::new(1, [8u8; 20]);
+        assert!(!settled.verify_proxy_signature_with_domain(&proxy_public_key, &other_contract).unwrap());
+    }
+
     // 错误情况测试
     #[test]
     fn test_invalid_address_length() {
@@ -955,7 +2336,7 @@ mod tests {
         assert_eq!(payment.serv_id, settled.serv_id);
         assert_eq!(payment.receiver, settled.receiver);
         assert_eq!(payment.sig_sender, settled.sig_sender);
-        assert_eq!(settled.amount, U256::default());
+        assert_eq!(settled.amount, payment.amount);
         assert!(!settled.settled);
         assert_eq!(settled.sig_proxy, EthSignature::from([0u8; 65]));
     }
@@ -963,13 +2344,8 @@ mod tests {
     // 边界值测试
     #[test]
     fn test_boundary_values() {
-        let payment = Payment {
-            pay_id: U256::MAX,
-            serv_id: u32::MAX,
-            amount: U256::MAX,  // 添加 amount
-            receiver: EthAddress::from([0xFFu8; 20]),
-            sig_sender: EthSignature::from([0xFFu8; 65]),
-        };
+        let payment = Payment::new(U256::MAX, u32::MAX, U256::MAX, EthAddress::from([0xFFu8; 20]))
+            .with_sig_sender(EthSignature::from([0xFFu8; 65]));
 
         let encoded = payment.rlp_encode();
         let decoded = Payment::rlp_decode(&encoded).unwrap();
@@ -982,18 +2358,12 @@ mod tests {
     #[test]
     fn test_payment_settled_sign_and_verify() {
         // 1. 创建私钥
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key = SecretKey::random(&mut rand::thread_rng());
+        let sender_key = test_key(9);
+        let proxy_key = test_key(10);
         let proxy_public_key = PublicKey::from_secret_key(&proxy_key);
         
         // 2. 创建初始Payment并签名
-        let mut payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount:U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65],
-        };
+        let mut payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]);
         payment.sign(&sender_key).unwrap();
         
         // 3. 转换为PaymentSettledByProxy
@@ -1016,19 +2386,13 @@ mod tests {
     #[test]
     fn test_payment_settled_invalid_proxy_signature() {
         // 1. 创建私钥
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key1 = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key2 = SecretKey::random(&mut rand::thread_rng());
+        let sender_key = test_key(11);
+        let proxy_key1 = test_key(12);
+        let proxy_key2 = test_key(13);
         let proxy_public_key2 = PublicKey::from_secret_key(&proxy_key2);
         
         // 2. 创建初始Payment并签名
-        let mut payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount:U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65],
-        };
+        let mut payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]);
         payment.sign(&sender_key).unwrap();
         
         // 3. 转换为PaymentSettledByProxy
@@ -1045,20 +2409,13 @@ mod tests {
     #[test]
     fn test_payment_settled_data_integrity() {
         // 1. 创建私钥
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key = SecretKey::random(&mut rand::thread_rng());
+        let sender_key = test_key(14);
+        let proxy_key = test_key(15);
         let proxy_public_key = PublicKey::from_secret_key(&proxy_key);
         
         // 2. 创建并签名PaymentSettledByProxy
-        let mut payment_settled = PaymentSettledByProxy {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount: U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65],
-            settled: true,
-            sig_proxy: [0u8; 65],
-        };
+        let mut payment_settled = PaymentSettledByProxy::new(U256::from(1), 1, U256::from(100), [1u8; 20])
+            .with_settled(true);
         
         // 3. 签名
         payment_settled.sign_by_proxy(&proxy_key).unwrap();
@@ -1073,17 +2430,11 @@ mod tests {
     #[test]
     fn test_payment_get_signer_address() {
         // 1. 创建私钥和对应的公钥
-        let secret_key = SecretKey::random(&mut rand::thread_rng());
+        let secret_key = test_key(16);
         let public_key = PublicKey::from_secret_key(&secret_key);
         
         // 2. 创建支付对象
-        let mut payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount:U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65],
-        };
+        let mut payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]);
         
         // 3. 签名
         payment.sign(&secret_key).unwrap();
@@ -1099,34 +2450,51 @@ mod tests {
     #[test]
     fn test_payment_get_signer_address_with_invalid_signature() {
         // 1. 创建无效签名的支付对象
-        let payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount:U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65], // 无效签名
-        };
+        let payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]); // 无效签名
         
         // 2. 尝试获取签名者地址，应该失败
         assert!(payment.get_signer_address().is_err());
     }
 
+    #[test]
+    fn test_recover_signer_payload() {
+        // 发送者签名的载荷固定为 pay_id(32) ‖ serv_id(4) ‖ amount(32) ‖ receiver(20)，改变布局会让已签名的收据全部失效
+        let payment = Payment::new(U256::from(1), 7, U256::from(1000), [0x11u8; 20]);
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&U256::from(1).to_be_bytes::<32>());
+        expected.extend_from_slice(&[0, 0, 0, 7]);
+        expected.extend_from_slice(&U256::from(1000).to_be_bytes::<32>());
+        expected.extend_from_slice(&[0x11u8; 20]);
+        assert_eq!(expected.len(), 88);
+        assert_eq!(payment.signing_payload(), expected);
+
+        // 直接对该载荷的 keccak 签名，recover_signer 恢复出签名者
+        let secret_key = test_key(21);
+        let public_key = PublicKey::from_secret_key(&secret_key);
+        let message = Message::parse_slice(&keccak256(&expected)).unwrap();
+        let (signature, recovery_id) = sign(&message, &secret_key);
+        let mut sig_sender = [0u8; 65];
+        sig_sender[..64].copy_from_slice(&signature.serialize());
+        sig_sender[64] = recovery_id.serialize();
+        let signed = payment.with_sig_sender(sig_sender);
+        assert_eq!(signed.recover_signer().unwrap(), public_key);
+
+        // 金额在载荷中，只改金额时恢复出的不再是签名者
+        let mut changed = signed.clone();
+        changed.amount = U256::from(1001);
+        assert_ne!(changed.recover_signer().ok(), Some(public_key));
+    }
+
     #[test]
     fn test_payment_signer_address_consistency() {
         // 1. 创建私钥
-        let secret_key = SecretKey::random(&mut rand::thread_rng());
+        let secret_key = test_key(17);
         let public_key = PublicKey::from_secret_key(&secret_key);
         
         // 2. 创建多个不同的支付对象
         let mut payments = vec![];
         for i in 0..3 {
-            let mut payment = Payment {
-                pay_id: U256::from(i),
-                serv_id: i as u32,
-                amount:U256::from(i),
-                receiver: [1u8; 20],
-                sig_sender: [0u8; 65],
-            };
+            let mut payment = Payment::new(U256::from(i), i as u32, U256::from(i), [1u8; 20]);
             payment.sign(&secret_key).unwrap();
             payments.push(payment);
         }
@@ -1144,20 +2512,14 @@ mod tests {
     #[test]
     fn test_payment_settled_get_signer_addresses() {
         // 1. 创建发送者和代理的私钥
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key = SecretKey::random(&mut rand::thread_rng());
+        let sender_key = test_key(18);
+        let proxy_key = test_key(19);
         
         let sender_public_key = PublicKey::from_secret_key(&sender_key);
         let proxy_public_key = PublicKey::from_secret_key(&proxy_key);
         
         // 2. 创建初始Payment并签名
-        let mut payment = Payment {
-            pay_id: U256::from(1),
-            serv_id: 1,
-            amount:U256::from(100),
-            receiver: [1u8; 20],
-            sig_sender: [0u8; 65],
-        };
+        let mut payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]);
         payment.sign(&sender_key).unwrap();
         
         // 3. 转换为PaymentSettledByProxy并添加代理签名
@@ -1176,3 +2538,131 @@ mod tests {
         assert_eq!(proxy_address, expected_proxy_address);
     }
 }
+
+#[cfg(test)]
+mod test_rlp_checked {
+    use super::*;
+
+    fn payment() -> Payment {
+        Payment::new(U256::from(7), 3, U256::from(1000), [9u8; 20])
+            .with_nonce(4)
+            .with_sig_sender([1u8; 65])
+    }
+
+    fn settled() -> PaymentSettledByProxy {
+        PaymentSettledByProxy::from(payment())
+            .with_settled(true)
+            .with_sig_proxy([2u8; 65])
+    }
+
+    // 按字段替换后重新编码，每个替换值为原始 RLP 字节
+    fn replace_field(encoded: &[u8], index: usize, raw: &[u8]) -> Vec<u8> {
+        let rlp = Rlp::new(encoded);
+        let count = rlp.item_count().unwrap();
+        let mut stream = RlpStream::new_list(count);
+        for i in 0..count {
+            if i == index {
+                stream.append_raw(raw, 1);
+            } else {
+                stream.append_raw(rlp.at(i).unwrap().as_raw(), 1);
+            }
+        }
+        stream.out().to_vec()
+    }
+
+    fn payment_error(bytes: &[u8]) -> RlpDecodeError {
+        let err = Payment::decode_checked(bytes).unwrap_err();
+        // 不带检查的入口返回同样的底层错误
+        assert_eq!(Payment::rlp_decode(bytes).unwrap_err(), err.error);
+        err
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let decoded = Payment::decode_checked(&payment().rlp_encode()).unwrap();
+        assert_eq!(decoded.rlp_encode(), payment().rlp_encode());
+        assert_eq!(decoded.nonce, Some(4));
+        let legacy = Payment::new(U256::from(7), 3, U256::from(1000), [9u8; 20]);
+        assert_eq!(Payment::decode_checked(&legacy.rlp_encode()).unwrap().nonce, None);
+
+        let decoded = PaymentSettledByProxy::decode_checked(&settled().rlp_encode()).unwrap();
+        assert_eq!(decoded.sig_proxy, [2u8; 65]);
+        assert_eq!(decoded.nonce, Some(4));
+    }
+
+    #[test]
+    fn test_malformed_list_corpus() {
+        let encoded = payment().rlp_encode();
+
+        // 截断
+        for len in [0, 1, encoded.len() / 2, encoded.len() - 1] {
+            assert_eq!(payment_error(&encoded[..len]).field, "payment");
+        }
+        // 多余的尾部字节
+        let mut trailing = encoded.clone();
+        trailing.push(0x80);
+        assert_eq!(payment_error(&trailing).error, DecoderError::RlpInconsistentLengthAndData);
+        // 不是列表
+        assert_eq!(payment_error(&[0x83, 1, 2, 3]).error, DecoderError::RlpExpectedToBeList);
+        // 4 GB 的长度前缀：列表和字符串
+        assert_eq!(payment_error(&[0xfb, 0xff, 0xff, 0xff, 0xff, 0xc0]).field, "payment");
+        let mut huge_item = vec![0xf8, 0x05, 0xbb, 0xff, 0xff, 0xff, 0xff];
+        assert!(Payment::decode_checked(&huge_item).is_err());
+        huge_item[1] = 0x06;
+        huge_item.push(0);
+        assert!(Payment::decode_checked(&huge_item).is_err());
+        // 字段数量不对
+        let mut stream = RlpStream::new_list(4);
+        for _ in 0..4 {
+            stream.append(&1u8);
+        }
+        assert_eq!(payment_error(&stream.out()).error, DecoderError::RlpIncorrectListLen);
+    }
+
+    #[test]
+    fn test_malformed_field_corpus() {
+        let encoded = payment().rlp_encode();
+        let nested_list = {
+            // 字段本身是一个深层嵌套的列表
+            let mut raw = vec![0xc0];
+            for _ in 0..30 {
+                let mut stream = RlpStream::new_list(1);
+                stream.append_raw(&raw, 1);
+                raw = stream.out().to_vec();
+            }
+            raw
+        };
+        let too_long = |len: usize| {
+            let mut stream = RlpStream::new();
+            stream.append(&vec![0xAAu8; len].as_slice());
+            stream.out().to_vec()
+        };
+
+        let cases: Vec<(usize, Vec<u8>, &str, DecoderError)> = vec![
+            (2, nested_list.clone(), "amount", DecoderError::RlpExpectedToBeData),
+            (0, too_long(33), "pay_id", DecoderError::RlpIsTooBig),
+            (1, too_long(5), "serv_id", DecoderError::RlpIsTooBig),
+            (3, too_long(21), "receiver", DecoderError::RlpIsTooBig),
+            (4, too_long(66), "sig_sender", DecoderError::RlpIsTooBig),
+            (5, too_long(9), "nonce", DecoderError::RlpIsTooBig),
+        ];
+        for (index, raw, field, error) in cases {
+            let err = payment_error(&replace_field(&encoded, index, &raw));
+            assert_eq!((err.field, err.error), (field, error));
+        }
+
+        // 长度在上限内但不符合字段类型
+        let err = payment_error(&replace_field(&encoded, 3, &too_long(19)));
+        assert_eq!(err.field, "receiver");
+        assert!(err.to_string().contains("receiver"));
+
+        // PaymentSettledByProxy 的额外字段
+        let encoded = settled().rlp_encode();
+        let err = PaymentSettledByProxy::decode_checked(&replace_field(&encoded, 6, &too_long(64))).unwrap_err();
+        assert_eq!(err.field, "sig_proxy");
+        let err = PaymentSettledByProxy::decode_checked(&replace_field(&encoded, 5, &nested_list)).unwrap_err();
+        assert_eq!((err.field, err.error), ("settled", DecoderError::RlpExpectedToBeData));
+        let err = PaymentSettledByProxy::decode_checked(&replace_field(&encoded, 2, &too_long(40))).unwrap_err();
+        assert_eq!(err.field, "amount");
+    }
+}
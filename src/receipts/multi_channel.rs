@@ -0,0 +1,221 @@
+use alloy_primitives::U256;
+use std::collections::{BTreeMap, HashMap};
+
+use super::overpay_checker::{OverpayCheckResult, OverpayError, ReceiptsOverpayChecker};
+use super::{DuplicatePayIdInfo, EthAddress, PaymentSettledByProxy, SigningDomain};
+use crate::models::PayIdInfo;
+
+/**
+ * 一次检查多个代理的混合收据
+ *
+ * 1. PayIdInfo 按 proxy 分组，同一个 pay_id 只能属于一个代理
+ * 2. 每个收据归入其 pay_id 所属的代理，pay_id 没有对应的 PayIdInfo 时拒绝整批
+ * 3. 每组用 ReceiptsOverpayChecker 独立检查，channel 为该组的代理
+ *
+ * 结果按代理地址升序排列，guest 中的输出顺序与输入顺序无关。
+ * 没有收据的代理不产生结果。
+ */
+#[derive(Debug, Clone, Default)]
+pub struct MultiChannelOverpayChecker {
+    epoch: u64,
+    verify_proxies: bool, // 代理签名按各自的代理验证
+    verify_senders: bool,
+    signing_domain: Option<SigningDomain>,
+    current_time: Option<u64>,
+}
+
+impl MultiChannelOverpayChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 所有代理使用同一个结算轮次
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 与 ReceiptsOverpayChecker::with_proxy_signature_check 相同，代理签名按各自的代理验证
+    pub fn with_proxy_signature_check(mut self) -> Self {
+        self.verify_proxies = true;
+        self
+    }
+
+    /// 与 ReceiptsOverpayChecker::with_sender_signature_check 相同
+    pub fn with_sender_signature_check(mut self) -> Self {
+        self.verify_senders = true;
+        self
+    }
+
+    pub fn with_signing_domain(mut self, domain: SigningDomain) -> Self {
+        self.signing_domain = Some(domain);
+        self
+    }
+
+    pub fn with_current_time(mut self, current_time: u64) -> Self {
+        self.current_time = Some(current_time);
+        self
+    }
+
+    /// 按代理分组检查，返回按代理地址升序排列的 (proxy, OverpayCheckResult)
+    pub fn process(
+        &self,
+        pay_id_infos: Vec<PayIdInfo>,
+        payments: Vec<PaymentSettledByProxy>,
+    ) -> Result<Vec<(EthAddress, OverpayCheckResult)>, OverpayError> {
+        // 1. PayIdInfo 按代理分组
+        let mut owners: HashMap<U256, EthAddress> = HashMap::new();
+        let mut channels: BTreeMap<EthAddress, (Vec<PayIdInfo>, Vec<PaymentSettledByProxy>)> = BTreeMap::new();
+        for info in pay_id_infos {
+            match owners.insert(info.id, info.proxy) {
+                Some(proxy) if proxy != info.proxy => {
+                    return Err(OverpayError::InvalidInputs(Box::new(DuplicatePayIdInfo { id: info.id })));
+                }
+                _ => {}
+            }
+            channels.entry(info.proxy).or_default().0.push(info);
+        }
+
+        // 2. 收据归入 pay_id 所属的代理
+        for payment in payments {
+            let proxy = owners.get(&payment.pay_id).ok_or(OverpayError::UnknownPayId(payment.pay_id))?;
+            channels.get_mut(proxy).expect("owner has a channel").1.push(payment);
+        }
+
+        // 3. 每个代理独立检查
+        let mut results = Vec::with_capacity(channels.len());
+        for (proxy, (infos, receipts)) in channels {
+            if receipts.is_empty() {
+                continue;
+            }
+            let result = self
+                .channel_checker(proxy, infos, receipts)
+                .process()
+                .map_err(|error| OverpayError::ChannelRejected { proxy, error })?;
+            results.push((proxy, result));
+        }
+        Ok(results)
+    }
+
+    fn channel_checker(
+        &self,
+        proxy: EthAddress,
+        pay_id_infos: Vec<PayIdInfo>,
+        payments: Vec<PaymentSettledByProxy>,
+    ) -> ReceiptsOverpayChecker {
+        let mut checker = ReceiptsOverpayChecker::new(proxy, pay_id_infos, payments).with_epoch(self.epoch);
+        if self.verify_proxies {
+            checker = checker.with_proxy_signature_check();
+        }
+        if self.verify_senders {
+            checker = checker.with_sender_signature_check();
+        }
+        if let Some(domain) = self.signing_domain {
+            checker = checker.with_signing_domain(domain);
+        }
+        if let Some(current_time) = self.current_time {
+            checker = checker.with_current_time(current_time);
+        }
+        checker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethaddr_gen::keypair_from_seed;
+    use crate::fixtures::{Scenario, ScenarioBuilder};
+    use crate::get_ethereum_address;
+    use crate::guest_checks::{classify, ErrorCode};
+    use crate::receipts::SettledReceiptBuilder;
+    use crate::BoxError;
+
+    // 代理 A 来自 ScenarioBuilder；代理 B 的 pay_id 100 与 A 的 pay_id 1 属于同一个发送者
+    fn two_channels() -> Result<(Scenario, EthAddress, PayIdInfo, PaymentSettledByProxy), BoxError> {
+        let scenario = ScenarioBuilder::new(1898)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(2, 1, 1, 300)
+            .build()?;
+        let (proxy_key, proxy_public) = keypair_from_seed(18980);
+        let proxy = get_ethereum_address(&proxy_public);
+
+        let info = PayIdInfo { id: U256::from(100), proxy, ..scenario.pay_id_infos[0].clone() };
+        let receipt = SettledReceiptBuilder::new(info.id, 1, U256::from(200), scenario.receiver(0))
+            .sign_sender(&scenario.sender_keys[0])
+            .settle(U256::from(200), true)
+            .sign_proxy(&proxy_key)
+            .build()?;
+        Ok((scenario, proxy, info, receipt))
+    }
+
+    #[test]
+    fn test_multi_channel_matches_single_channel() -> Result<(), BoxError> {
+        let (scenario, proxy, info, receipt) = two_channels()?;
+        assert_eq!(info.sender, scenario.pay_id_infos[0].sender);
+
+        let mut pay_id_infos = scenario.pay_id_infos.clone();
+        pay_id_infos.insert(0, info.clone());
+        let mut payments = scenario.receipts.clone();
+        payments.insert(1, receipt.clone());
+
+        let checker = MultiChannelOverpayChecker::new()
+            .with_epoch(scenario.epoch)
+            .with_proxy_signature_check()
+            .with_sender_signature_check();
+        let results = checker.process(pay_id_infos.clone(), payments.clone())?;
+        assert_eq!(results.len(), 2);
+        assert!(results[0].0 < results[1].0);
+
+        for (channel, result) in &results {
+            let expected = if *channel == proxy {
+                ReceiptsOverpayChecker::new(proxy, vec![info.clone()], vec![receipt.clone()])
+                    .with_epoch(scenario.epoch)
+                    .process()?
+            } else {
+                assert_eq!(*channel, scenario.proxy);
+                scenario.overpay_checker().process()?
+            };
+            assert_eq!(*result, expected);
+        }
+
+        // 输入顺序不影响结果
+        pay_id_infos.reverse();
+        payments.reverse();
+        assert_eq!(checker.process(pay_id_infos, payments)?, results);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_channel_rejections() -> Result<(), BoxError> {
+        let (scenario, proxy, info, receipt) = two_channels()?;
+        let mut pay_id_infos = scenario.pay_id_infos.clone();
+        pay_id_infos.push(info.clone());
+
+        // pay_id 没有 PayIdInfo 的收据
+        let mut orphan = receipt.clone();
+        orphan.pay_id = U256::from(999);
+        let mut payments = scenario.receipts.clone();
+        payments.push(orphan);
+        let err = MultiChannelOverpayChecker::new().process(pay_id_infos.clone(), payments).unwrap_err();
+        assert!(matches!(err, OverpayError::UnknownPayId(pay_id) if pay_id == U256::from(999)));
+        assert_eq!(classify(&err), ErrorCode::InvalidInput);
+
+        // 同一个 pay_id 属于两个代理
+        let mut ambiguous = pay_id_infos.clone();
+        ambiguous.push(PayIdInfo { proxy, ..scenario.pay_id_infos[0].clone() });
+        let err = MultiChannelOverpayChecker::new().process(ambiguous, scenario.receipts.clone()).unwrap_err();
+        assert_eq!(classify(&err), ErrorCode::Duplicate);
+
+        // 代理 B 的收据超付，错误带上代理
+        let mut overpaid = receipt;
+        overpaid.amount = info.amount + U256::from(1);
+        overpaid.authorized_amount = None;
+        let mut payments = scenario.receipts.clone();
+        payments.push(overpaid);
+        let err = MultiChannelOverpayChecker::new().process(pay_id_infos, payments).unwrap_err();
+        assert!(matches!(err, OverpayError::ChannelRejected { proxy: rejected, .. } if rejected == proxy));
+        assert_eq!(classify(&err), ErrorCode::Overpay);
+        Ok(())
+    }
+}
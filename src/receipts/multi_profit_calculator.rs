@@ -0,0 +1,337 @@
+use alloy_primitives::{B256, U256};
+use std::collections::{HashMap, HashSet};
+
+use super::profit_calculator::{
+    calculate_receipt_profits, calculate_serv_ids_root, calculate_token_profits, fee_config_map, pay_id_senders,
+    validate_pay_id_proxies, validate_receipt_signatures, validate_receipts_proof,
+    validate_receivers,
+};
+use super::overpay_checker::OverpayCheckResult;
+use super::sealed::SealedSigners;
+use super::{check_partial_settlement, DustPolicy, EthAddress, PayIdsProcessor, PaymentSettledByProxy, SigningDomain};
+use crate::models::{PayIdInfo, ServiceFeeConfig, TreeHashAlgorithm};
+use crate::{BoxError, ProfitResult};
+
+/**
+ * 一次处理一个代理下的所有接收者
+ *
+ * 与逐个运行 ReceiptsProfitCalculator 的结果完全一致，但共享部分只计算一次：
+ * 1. PayIdInfos 的代理校验、pay_ids_root 和 serv_ids_root
+ * 2. PayId 到发送者的映射以及服务费率查找表
+ *
+ * 每个接收者的默克尔证明从 OverpayCheckResult 中取得，并要求其根等于 payments_root。
+ */
+pub struct MultiReceiverProfitCalculator {
+    vks_hash: B256,
+    proxy: EthAddress,
+    overpay_result: OverpayCheckResult,
+    receipts_by_receiver: Vec<(EthAddress, Vec<PaymentSettledByProxy>)>,
+    pay_id_infos: Vec<PayIdInfo>,
+    service_configs: Vec<ServiceFeeConfig>,
+    dust_policy: DustPolicy,
+    signing_domain: Option<SigningDomain>,
+}
+
+/// 多接收者计算结果
+#[derive(Debug, Clone)]
+pub struct MultiProfitResult {
+    pub profit_results: Vec<ProfitResult>,
+    pub system_profit: U256,
+    pub proxy_profit: U256,
+    pub receiver_profit: U256,
+}
+
+impl MultiReceiverProfitCalculator {
+    /// receipts_by_receiver 通常来自 PaymentsGrouper::group_payments
+    pub fn new(
+        vks_hash: B256,
+        proxy: EthAddress,
+        overpay_result: OverpayCheckResult,
+        receipts_by_receiver: Vec<(EthAddress, Vec<PaymentSettledByProxy>)>,
+        pay_id_infos: Vec<PayIdInfo>,
+        service_configs: Vec<ServiceFeeConfig>,
+    ) -> Self {
+        Self {
+            vks_hash,
+            proxy,
+            overpay_result,
+            receipts_by_receiver,
+            pay_id_infos,
+            service_configs,
+            dust_policy: DustPolicy::default(),
+            signing_domain: None,
+        }
+    }
+
+    /// 设置签名域，与 ReceiptsProfitCalculator::with_signing_domain 相同
+    pub fn with_signing_domain(mut self, domain: SigningDomain) -> Self {
+        self.signing_domain = Some(domain);
+        self
+    }
+
+    /// 设置 dust 策略，Skip 后没有剩余收据的接收者会被移除
+    pub fn with_dust_policy(mut self, dust_policy: DustPolicy) -> Self {
+        for (_, receipts) in self.receipts_by_receiver.iter_mut() {
+            dust_policy.filter(receipts);
+        }
+        self.receipts_by_receiver.retain(|(_, receipts)| !receipts.is_empty());
+        self.dust_policy = dust_policy;
+        self
+    }
+
+    pub fn calculate(&self) -> Result<MultiProfitResult, BoxError> {
+        // 1. 共享部分的验证，只做一次
+        validate_pay_id_proxies(&self.pay_id_infos, self.proxy)?;
+
+        let pay_ids_root = PayIdsProcessor::get_root_hash(&self.pay_id_infos)?;
+        if pay_ids_root != self.overpay_result.pay_ids_root {
+            return Err("pay_ids_root does not match OverpayCheckResult".into());
+        }
+        let serv_ids_root = calculate_serv_ids_root(&self.service_configs)?;
+
+        let senders = pay_id_senders(&self.pay_id_infos);
+        let fee_configs = fee_config_map(&self.service_configs)?;
+
+        // 2. 逐个接收者验证并计算
+        let mut seen = HashSet::new();
+        let mut result = MultiProfitResult {
+            profit_results: Vec::with_capacity(self.receipts_by_receiver.len()),
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::ZERO,
+        };
+
+        for (receiver, receipts) in &self.receipts_by_receiver {
+            if !seen.insert(*receiver) {
+                return Err(format!("Duplicate receiver {:?}", receiver).into());
+            }
+
+            let profit_result = self
+                .calculate_receiver(*receiver, receipts, pay_ids_root, serv_ids_root, &senders, &fee_configs)
+                .map_err(|e| format!("Receiver {:?}: {}", receiver, e))?;
+
+            result.system_profit = result
+                .system_profit
+                .checked_add(profit_result.system_profit)
+                .ok_or("Addition overflow")?;
+            result.proxy_profit = result
+                .proxy_profit
+                .checked_add(profit_result.proxy_profit)
+                .ok_or("Addition overflow")?;
+            result.receiver_profit = result
+                .receiver_profit
+                .checked_add(profit_result.receiver_profit)
+                .ok_or("Addition overflow")?;
+            result.profit_results.push(profit_result);
+        }
+
+        Ok(result)
+    }
+
+    fn calculate_receiver(
+        &self,
+        receiver: EthAddress,
+        receipts: &[PaymentSettledByProxy],
+        pay_ids_root: B256,
+        serv_ids_root: B256,
+        senders: &HashMap<U256, EthAddress>,
+        fee_configs: &HashMap<u32, &ServiceFeeConfig>,
+    ) -> Result<ProfitResult, BoxError> {
+        let merkle_proof = self.overpay_result.get_merkle_proof(receiver)?;
+        if merkle_proof.root_hash != self.overpay_result.payments_root {
+            return Err("Merkle proof root does not match payments_root".into());
+        }
+
+        check_partial_settlement(receipts)?;
+        self.dust_policy.check(receipts)?;
+        validate_receipts_proof(receipts, &merkle_proof, TreeHashAlgorithm::Keccak)?;
+        validate_receivers(receipts, receiver)?;
+        validate_receipt_signatures(receipts, self.proxy, senders, self.signing_domain.as_ref(), &SealedSigners::default())?;
+        let (system_profit, proxy_profit, receiver_profit) =
+            calculate_receipt_profits(receipts, fee_configs)?;
+        let token_totals = calculate_token_profits(receipts, fee_configs)?;
+
+        Ok(ProfitResult {
+            vks_hash: self.vks_hash,
+            receiver,
+            proxy: self.proxy,
+            receipts_root: merkle_proof.root_hash,
+            pay_ids_root,
+            serv_ids_root,
+            system_profit,
+            proxy_profit,
+            receiver_profit,
+            // 轮次沿用 overpay_result
+            epoch: self.overpay_result.epoch,
+            token_totals,
+            // 根与 overpay_result 一起计算，版本沿用它
+            commitment_version: self.overpay_result.commitment_version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethaddr_gen::EthAddressGen;
+    use crate::get_ethereum_address;
+    use crate::receipts::overpay_checker::ReceiptsOverpayChecker;
+    use crate::receipts::profit_calculator::ReceiptsProfitCalculator;
+    use crate::receipts::{PaymentsGrouper, SettledReceiptBuilder};
+    use crate::DeterministicRng;
+    use libsecp256k1::{PublicKey, SecretKey};
+    use rand::SeedableRng;
+
+    fn create_test_payment(
+        pay_id: u64,
+        serv_id: u32,
+        amount: u64,
+        receiver: EthAddress,
+        sender_key: &SecretKey,
+        proxy_key: &SecretKey,
+    ) -> Result<PaymentSettledByProxy, BoxError> {
+        Ok(SettledReceiptBuilder::new(U256::from(pay_id), serv_id, U256::from(amount), receiver)
+            .sign_sender(sender_key)
+            .settle(U256::from(amount), true)
+            .sign_proxy(proxy_key)
+            .build()?)
+    }
+
+    struct TestData {
+        proxy: EthAddress,
+        payments: Vec<PaymentSettledByProxy>,
+        pay_id_infos: Vec<PayIdInfo>,
+        service_configs: Vec<ServiceFeeConfig>,
+    }
+
+    fn create_test_data() -> Result<TestData, BoxError> {
+        let sender_key = SecretKey::random(&mut DeterministicRng::seed_from_u64(1));
+        let proxy_key = SecretKey::random(&mut DeterministicRng::seed_from_u64(2));
+        let sender = get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
+        let proxy = get_ethereum_address(&PublicKey::from_secret_key(&proxy_key));
+        let receivers = EthAddressGen::generate_batch_with(3, &mut DeterministicRng::seed_from_u64(3));
+
+        let pay_id_infos = (1..=2)
+            .map(|id| PayIdInfo {
+                id: U256::from(id),
+                amount: U256::from(10000),
+                sender,
+                proxy,
+                state: 1,
+                created_at: 0,
+                closing_time: 0,
+                token: [0u8; 20],
+                sig_sender: None,
+            })
+            .collect();
+
+        let service_configs = vec![
+            ServiceFeeConfig {
+                serv_id: 1,
+                system_fee_rate: 500,
+                proxy_fee_rate: 1000,
+                tiers: Vec::new(),
+            },
+            ServiceFeeConfig {
+                serv_id: 2,
+                system_fee_rate: 300,
+                proxy_fee_rate: 700,
+                tiers: Vec::new(),
+            },
+        ];
+
+        let payments = vec![
+            create_test_payment(1, 1, 1000, receivers[0], &sender_key, &proxy_key)?,
+            create_test_payment(2, 2, 2000, receivers[0], &sender_key, &proxy_key)?,
+            create_test_payment(1, 2, 1500, receivers[1], &sender_key, &proxy_key)?,
+            create_test_payment(2, 1, 333, receivers[2], &sender_key, &proxy_key)?,
+        ];
+
+        Ok(TestData {
+            proxy,
+            payments,
+            pay_id_infos,
+            service_configs,
+        })
+    }
+
+    #[test]
+    fn test_equivalent_to_single_calculator() -> Result<(), BoxError> {
+        let data = create_test_data()?;
+        let overpay_result =
+            ReceiptsOverpayChecker::new(data.proxy, data.pay_id_infos.clone(), data.payments.clone())
+                .process()?;
+        let groups = PaymentsGrouper::group_payments(&data.payments);
+
+        let multi = MultiReceiverProfitCalculator::new(
+            B256::ZERO,
+            data.proxy,
+            overpay_result,
+            groups.clone(),
+            data.pay_id_infos.clone(),
+            data.service_configs.clone(),
+        )
+        .calculate()?;
+
+        let overpay_result =
+            ReceiptsOverpayChecker::new(data.proxy, data.pay_id_infos.clone(), data.payments.clone())
+                .process()?;
+        assert_eq!(multi.profit_results.len(), groups.len());
+
+        let mut total = U256::ZERO;
+        for ((receiver, receipts), multi_result) in groups.into_iter().zip(&multi.profit_results) {
+            let single = ReceiptsProfitCalculator::new(
+                B256::ZERO,
+                receiver,
+                data.proxy,
+                receipts,
+                overpay_result.get_merkle_proof(receiver)?,
+                data.pay_id_infos.clone(),
+                data.service_configs.clone(),
+            )
+            .calculate()?;
+
+            assert_eq!(single.receiver, multi_result.receiver);
+            assert_eq!(single.receipts_root, multi_result.receipts_root);
+            assert_eq!(single.pay_ids_root, multi_result.pay_ids_root);
+            assert_eq!(single.serv_ids_root, multi_result.serv_ids_root);
+            assert_eq!(single.system_profit, multi_result.system_profit);
+            assert_eq!(single.proxy_profit, multi_result.proxy_profit);
+            assert_eq!(single.receiver_profit, multi_result.receiver_profit);
+            total += single.system_profit + single.proxy_profit + single.receiver_profit;
+        }
+
+        assert_eq!(total, multi.system_profit + multi.proxy_profit + multi.receiver_profit);
+        assert_eq!(total, U256::from(1000 + 2000 + 1500 + 333));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_receiver_proof_is_identified() -> Result<(), BoxError> {
+        let data = create_test_data()?;
+        let mut overpay_result =
+            ReceiptsOverpayChecker::new(data.proxy, data.pay_id_infos.clone(), data.payments.clone())
+                .process()?;
+        let groups = PaymentsGrouper::group_payments(&data.payments);
+
+        // 篡改第二个接收者的证明
+        let bad_receiver = overpay_result.receiver_proofs[1].receiver;
+        overpay_result.receiver_proofs[1].proof.value_proof.value = B256::repeat_byte(0xAA);
+
+        let result = MultiReceiverProfitCalculator::new(
+            B256::ZERO,
+            data.proxy,
+            overpay_result,
+            groups,
+            data.pay_id_infos,
+            data.service_configs,
+        )
+        .calculate();
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains(&format!("{:?}", bad_receiver)));
+
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 use alloy_primitives::{B256, U256};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use crate::{models::segment_vc::MerkleProof, BoxError};
-use super::{EthAddress, PayIdsProcessor, PaymentsGrouper, PaymentSettledByProxy, ReceiverProof};
-use crate:: models::{pay_id_infos::PayIdInfo,segment_vc::SegmentVC};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use crate::{format_eth_address, models::segment_vc::MerkleProof, BoxError, CommitmentVersion, HashDomain, HashScheme, VersionedRoot};
+use tiny_keccak::Hasher;
+use crate::metrics::{measure, record, Metrics, NoopMetrics, PIPELINE_OPS, RECEIPTS_PROCESSED};
+use super::{check_partial_settlement, check_receipt_expiry, split_duplicates, AmountOverflow, DedupeReport, InvalidReceiptSignature, WithContext, DustPolicy, EthAddress, SigningDomain, PayIdsProcessor, PaymentsGrouper, PaymentSettledByProxy, ReceiverProof};
+use super::sealed::{SealedReceipt, SealedSigners};
+use super::settlement_filter::{SettlementFilter, UnsettledReport};
+use crate:: models::{pay_id_infos::PayIdInfo,segment_vc::SegmentVC,u256_to_key,TreeHashAlgorithm};
 /**
  * 
  *   @pay_id_infos.rs
@@ -39,31 +44,397 @@ use crate:: models::{pay_id_infos::PayIdInfo,segment_vc::SegmentVC};
  */
 // PaymentSettledByProxy 结构体定义
 
+#[derive(Clone)]
 pub struct ReceiptsOverpayChecker {
     channel: EthAddress,
     pay_id_infos: Vec<PayIdInfo>,
     settled_payments: Vec<PaymentSettledByProxy>,
+    dust_policy: DustPolicy,
+    verify_proxies: bool, // 验证每个收据的代理签名来自 channel
+    verify_senders: bool, // 验证每个收据的发送者签名与 PayIdInfo.sender 一致
+    nonce_marks: Option<HashMap<(U256, EthAddress), u64>>, // 上一轮结算中每个 (pay_id, receiver) 的最大 nonce
+    signing_domain: Option<SigningDomain>, // 签名验证和 PayIdInfo 授权检查使用的签名域
+    epoch: u64,                            // 结算轮次，写入结果防止跨轮重放
+    dedupe_report: Option<DedupeReport>,   // with_receipt_dedupe 的处理报告
+    current_time: Option<u64>,             // 设置后拒绝在该时刻已过期的收据
+    max_receipts_per_page: Option<usize>,  // 设置后每个receiver的值为分页的组哈希链
+    sealed: SealedSigners,                 // from_sealed 时封存的签名者，验证签名时不再恢复
+    excluded: Vec<PaymentSettledByProxy>,  // 去重、dust 过滤去掉的收据，承诺在 excluded_root 中
+    require_pay_id_authorization: bool,    // 要求每个 PayIdInfo 带有 sender 的签名
 }
 
-#[derive(Debug,Serialize,Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct OverpayCheckResult {
     pub payments_root: B256,
     pub receiver_proofs: Vec<ReceiverProof>,
     pub pay_ids_root: B256,
+    #[serde(default)]
+    pub epoch: u64, // 结算轮次，旧数据没有该字段时为 0
+    /// payments_root 和 pay_ids_root 的承诺方案版本，旧数据没有该字段时为 Legacy
+    #[serde(default)]
+    pub commitment_version: CommitmentVersion,
+    /// 被排除在根之外的收据（重复、Skip 的 dust、未结算）的承诺，见 excluded_receipts_root；
+    /// 没有收据被排除时为 None，旧数据没有该字段时也为 None
+    #[serde(default)]
+    pub excluded_root: Option<B256>,
+    /// 收据引用的 pay_id 的承诺，见 referenced_pay_ids_root；verify_cross_roots 用它确认见证中的 pay_id 列表完整。
+    /// 没有收据时为 None，旧数据没有该字段时也为 None
+    #[serde(default)]
+    pub referenced_pay_ids_root: Option<B256>,
 }
 
+/// 被排除收据的承诺：各收据 hash() 升序排列（重复的保留）后拼接，按 HashDomain::ExcludedReceipts 计算 keccak，
+/// 与排除前的顺序无关。scheme 取结果的 hash_scheme()，excluded 为空时返回 None
+pub fn excluded_receipts_root(scheme: HashScheme, excluded: &[PaymentSettledByProxy]) -> Option<B256> {
+    if excluded.is_empty() {
+        return None;
+    }
+    let mut hashes: Vec<B256> = excluded.iter().map(PaymentSettledByProxy::hash).collect();
+    hashes.sort_unstable();
+
+    let mut hasher = scheme.hasher(HashDomain::ExcludedReceipts);
+    for hash in &hashes {
+        hasher.update(hash.as_slice());
+    }
+    let mut root = B256::ZERO;
+    hasher.finalize(root.as_mut_slice());
+    Some(root)
+}
+
+/// 收据引用的 pay_id 的承诺：不同的 pay_id 升序排列，各自按 u256_to_key 编码后拼接，按 HashDomain::ReferencedPayIds 计算 keccak，
+/// 与顺序和重复无关。pay_ids 为空时返回 None
+pub fn referenced_pay_ids_root(scheme: HashScheme, pay_ids: impl IntoIterator<Item = U256>) -> Option<B256> {
+    let pay_ids: BTreeSet<U256> = pay_ids.into_iter().collect();
+    if pay_ids.is_empty() {
+        return None;
+    }
+    let mut hasher = scheme.hasher(HashDomain::ReferencedPayIds);
+    for pay_id in &pay_ids {
+        hasher.update(u256_to_key(*pay_id).as_slice());
+    }
+    let mut root = B256::ZERO;
+    hasher.finalize(root.as_mut_slice());
+    Some(root)
+}
+
+/// receiver_proofs 的规范顺序：按 receiver 地址的字节序逐字节比较升序排列
+/// （即 `[u8; 20]` 的字典序，等价于把地址当作 uint160 比较），且不允许重复。
+/// Solidity 端依赖该顺序做二分查找。
 impl OverpayCheckResult {
+    /// 创建结果，按 receiver 排序并拒绝重复的 receiver
+    pub fn new(
+        payments_root: B256,
+        receiver_proofs: Vec<ReceiverProof>,
+        pay_ids_root: B256,
+    ) -> Result<Self, BoxError> {
+        let mut result = Self {
+            payments_root,
+            receiver_proofs,
+            pay_ids_root,
+            epoch: 0,
+            commitment_version: CommitmentVersion::CURRENT,
+            excluded_root: None,
+            referenced_pay_ids_root: None,
+        };
+        result.canonicalize();
+
+        if let Some(pair) = result
+            .receiver_proofs
+            .windows(2)
+            .find(|pair| pair[0].receiver == pair[1].receiver)
+        {
+            return Err(format!("Duplicate receiver in receiver_proofs: {:?}", pair[0].receiver).into());
+        }
+
+        Ok(result)
+    }
+
+    /// 设置结算轮次
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 设置根的承诺方案版本，new 默认为当前版本
+    pub fn with_commitment_version(mut self, version: CommitmentVersion) -> Self {
+        self.commitment_version = version;
+        self
+    }
+
+    /// excluded 是否正是生成结果时被排除的收据（顺序无关）
+    pub fn verify_excluded(&self, excluded: &[PaymentSettledByProxy]) -> bool {
+        self.excluded_root == excluded_receipts_root(self.hash_scheme(), excluded)
+    }
+
+    /// 计算该结果的承诺使用的哈希方案，见 CommitmentVersion::hash_scheme
+    pub fn hash_scheme(&self) -> HashScheme {
+        self.commitment_version.hash_scheme()
+    }
+
+    pub fn versioned_payments_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.payments_root)
+    }
+
+    pub fn versioned_pay_ids_root(&self) -> VersionedRoot {
+        VersionedRoot::new(self.commitment_version, self.pay_ids_root)
+    }
+
+    /// receiver_proofs 是否严格按 receiver 升序且无重复
+    pub fn is_canonical(&self) -> bool {
+        self.receiver_proofs
+            .windows(2)
+            .all(|pair| pair[0].receiver < pair[1].receiver)
+    }
+
+    /// 按 receiver 重新排序（稳定排序，不会去重）
+    pub fn canonicalize(&mut self) {
+        self.receiver_proofs.sort_by(|a, b| a.receiver.cmp(&b.receiver));
+    }
+
+    /// 查找 receiver 在 receiver_proofs 中的位置，规范顺序下使用二分查找
+    pub fn receiver_index(&self, receiver: &EthAddress) -> Option<usize> {
+        if self.is_canonical() {
+            self.receiver_proofs
+                .binary_search_by(|proof| proof.receiver.cmp(receiver))
+                .ok()
+        } else {
+            self.receiver_proofs
+                .iter()
+                .position(|proof| &proof.receiver == receiver)
+        }
+    }
+
+    /// 用原始输入重新验证结果，不修改结果本身
+    ///
+    /// 重新运行与 process 相同的输入检查和超付检查（不验证签名，与 process 的默认行为一致），
+    /// 再比较 pay_ids_root、payments_root、接收者集合以及每个接收者的证明。
+    /// 结果由开启了分页、去重或 dust 策略的检查器生成时用 verify_with_checker
+    pub fn verify_against(
+        &self,
+        channel: EthAddress,
+        pay_id_infos: &[PayIdInfo],
+        payments: &[PaymentSettledByProxy],
+    ) -> Result<(), OverpayError> {
+        self.verify_with_checker(&ReceiptsOverpayChecker::new(channel, pay_id_infos.to_vec(), payments.to_vec()))
+    }
+
+    /// 与 verify_against 相同，但用调用方配置的检查器重新计算，with_max_receipts_per_page 等影响根的选项
+    /// 须与生成结果时相同；检查器开启的签名验证等检查同样会运行
+    pub fn verify_with_checker(&self, checker: &ReceiptsOverpayChecker) -> Result<(), OverpayError> {
+        let (payments_root, receiver_proofs, pay_ids_root) =
+            checker.commitments().map_err(OverpayError::InvalidInputs)?;
+
+        if self.pay_ids_root != pay_ids_root {
+            return Err(OverpayError::PayIdsRootMismatch { expected: pay_ids_root, actual: self.pay_ids_root });
+        }
+        if self.payments_root != payments_root {
+            return Err(OverpayError::PaymentsRootMismatch { expected: payments_root, actual: self.payments_root });
+        }
+
+        // 接收者集合必须与收据中的接收者完全一致，重复的接收者算作多余
+        let expected: BTreeSet<EthAddress> = receiver_proofs.iter().map(|proof| proof.receiver).collect();
+        let mut seen = BTreeSet::new();
+        let mut extra = Vec::new();
+        for proof in &self.receiver_proofs {
+            if !expected.contains(&proof.receiver) || !seen.insert(proof.receiver) {
+                extra.push(proof.receiver);
+            }
+        }
+        let missing: Vec<EthAddress> = expected.difference(&seen).copied().collect();
+        if !missing.is_empty() || !extra.is_empty() {
+            return Err(OverpayError::ReceiverMismatch { missing, extra });
+        }
+
+        // 每个证明都必须有效，且证明的值等于重新计算的收据组哈希
+        for proof in &self.receiver_proofs {
+            let recomputed = receiver_proofs
+                .iter()
+                .find(|candidate| candidate.receiver == proof.receiver)
+                .ok_or(OverpayError::InvalidReceiverProof(proof.receiver))?;
+            let valid = proof.verify(self.payments_root).unwrap_or(false)
+                && proof.proof.value_proof.value == recomputed.proof.value_proof.value;
+            if !valid {
+                return Err(OverpayError::InvalidReceiverProof(proof.receiver));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 用 CrossRootWitness 验证收据引用的每个 pay_id 都包含在 pay_ids_root 中，不需要原始输入
+    ///
+    /// witness.pay_ids 必须与结果中的 referenced_pay_ids_root 一致，不能由证明方删减；其中每个 pay_id
+    /// 都必须恰好有一个证明，证明的 PayIdInfo.id 等于该 pay_id，且 PayIdsProcessor::verify_pay_id
+    /// 在 pay_ids_root 下通过；多余的证明同样拒绝。没有 referenced_pay_ids_root 的旧结果只接受空的见证
+    pub fn verify_cross_roots(&self, witness: &CrossRootWitness) -> Result<(), OverpayError> {
+        let referenced: BTreeSet<U256> = witness.pay_ids.iter().copied().collect();
+        let committed = referenced_pay_ids_root(self.hash_scheme(), referenced.iter().copied());
+        if committed != self.referenced_pay_ids_root {
+            return Err(OverpayError::ReferencedPayIdsMismatch {
+                expected: self.referenced_pay_ids_root,
+                actual: committed,
+            });
+        }
+        let mut proven = BTreeSet::new();
+        for (info, proof) in &witness.proofs {
+            if !referenced.contains(&info.id) || !proven.insert(info.id) {
+                return Err(OverpayError::InvalidPayIdProof(info.id));
+            }
+            let valid = PayIdsProcessor::verify_pay_id(self.pay_ids_root, info, proof).unwrap_or(false);
+            if !valid {
+                return Err(OverpayError::InvalidPayIdProof(info.id));
+            }
+        }
+        match referenced.difference(&proven).next() {
+            Some(&pay_id) => Err(OverpayError::MissingPayIdProof(pay_id)),
+            None => Ok(()),
+        }
+    }
+
     /// 根据接收者地址获取对应的默克尔证明
     pub fn get_merkle_proof(&self, receiver: EthAddress) -> Result<MerkleProof, BoxError> {
         // 从 receiver_proofs 中查找对应接收者的证明
-        self.receiver_proofs
-            .iter()
-            .find(|proof| proof.receiver == receiver)
-            .map(|proof| proof.proof.clone())
+        self.receiver_index(&receiver)
+            .map(|index| self.receiver_proofs[index].proof.clone())
             .ok_or_else(|| "Merkle proof not found for receiver".into())
     }
 }
 
+/// 证明内容较长，只输出根和接收者列表
+impl fmt::Display for OverpayCheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let receivers = self
+            .receiver_proofs
+            .iter()
+            .map(|proof| format_eth_address(&proof.receiver))
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(
+            f,
+            "OverpayCheckResult payments_root={} pay_ids_root={} receivers=[{}] epoch={}",
+            self.payments_root, self.pay_ids_root, receivers, self.epoch
+        )?;
+        if let Some(excluded_root) = self.excluded_root {
+            write!(f, " excluded_root={}", excluded_root)?;
+        }
+        Ok(())
+    }
+}
+
+/// 收据引用的 pay_id 与 pay_ids_root 之间的对应关系，由 ReceiptsOverpayChecker::process_with_witness 生成
+///
+/// pay_ids 为收据中出现的不同 pay_id（升序），proofs 为每个 pay_id 的 PayIdInfo 及其在 pay_ids_root 下的包含证明
+#[derive(Debug, Clone)]
+pub struct CrossRootWitness {
+    pub pay_ids: Vec<U256>,
+    pub proofs: Vec<(PayIdInfo, MerkleProof)>,
+}
+
+/// pay_id 下收据的总额超过 PayIdInfo 中的存款
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverpayDetected {
+    pub pay_id: U256,
+    pub total: U256,
+    pub deposit: U256,
+}
+
+impl fmt::Display for OverpayDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Overpayment detected for pay_id {}: total {} exceeds deposit {}",
+            self.pay_id, self.total, self.deposit
+        )
+    }
+}
+
+impl std::error::Error for OverpayDetected {}
+
+/// 收据的代币与对应 PayIdInfo 的代币不同
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMismatch {
+    pub pay_id: U256,
+    pub expected: EthAddress, // PayIdInfo.token
+    pub actual: EthAddress,   // 收据的 token
+}
+
+impl fmt::Display for TokenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Token mismatch for pay_id {}: PayIdInfo token {}, receipt token {}",
+            self.pay_id,
+            format_eth_address(&self.expected),
+            format_eth_address(&self.actual)
+        )
+    }
+}
+
+impl std::error::Error for TokenMismatch {}
+
+/// OverpayCheckResult::verify_against 的失败原因
+#[derive(Debug)]
+pub enum OverpayError {
+    /// 原始输入本身不能通过检查（channel、重复收据、超付等）
+    InvalidInputs(BoxError),
+    PayIdsRootMismatch { expected: B256, actual: B256 },
+    PaymentsRootMismatch { expected: B256, actual: B256 },
+    /// missing：收据中有但结果中没有；extra：结果中多余或重复的接收者
+    ReceiverMismatch { missing: Vec<EthAddress>, extra: Vec<EthAddress> },
+    InvalidReceiverProof(EthAddress),
+    /// CrossRootWitness 中缺少收据引用的 pay_id 的证明
+    MissingPayIdProof(U256),
+    /// pay_id 的证明无效、重复或不属于收据引用的 pay_id
+    InvalidPayIdProof(U256),
+    /// CrossRootWitness.pay_ids 与结果承诺的 referenced_pay_ids_root 不一致，例如删去了某个 pay_id
+    ReferencedPayIdsMismatch { expected: Option<B256>, actual: Option<B256> },
+    /// MultiChannelOverpayChecker：收据的 pay_id 没有对应的 PayIdInfo，无法归入代理
+    UnknownPayId(U256),
+    /// MultiChannelOverpayChecker：某个代理的收据不能通过检查
+    ChannelRejected { proxy: EthAddress, error: BoxError },
+}
+
+impl fmt::Display for OverpayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverpayError::InvalidInputs(e) => write!(f, "Overpay inputs rejected: {}", e),
+            OverpayError::PayIdsRootMismatch { expected, actual } => {
+                write!(f, "pay_ids_root mismatch: expected {}, got {}", expected, actual)
+            }
+            OverpayError::PaymentsRootMismatch { expected, actual } => {
+                write!(f, "payments_root mismatch: expected {}, got {}", expected, actual)
+            }
+            OverpayError::ReceiverMismatch { missing, extra } => write!(
+                f,
+                "Receiver set mismatch: missing [{}], extra [{}]",
+                missing.iter().map(format_eth_address).collect::<Vec<_>>().join(", "),
+                extra.iter().map(format_eth_address).collect::<Vec<_>>().join(", ")
+            ),
+            OverpayError::InvalidReceiverProof(receiver) => {
+                write!(f, "Invalid receiver proof for {}", format_eth_address(receiver))
+            }
+            OverpayError::MissingPayIdProof(pay_id) => write!(f, "Missing pay_ids_root proof for pay_id {}", pay_id),
+            OverpayError::InvalidPayIdProof(pay_id) => write!(f, "Invalid pay_ids_root proof for pay_id {}", pay_id),
+            OverpayError::ReferencedPayIdsMismatch { expected, actual } => {
+                write!(f, "Referenced pay_ids mismatch: expected {:?}, got {:?}", expected, actual)
+            }
+            OverpayError::UnknownPayId(pay_id) => write!(f, "No PayIdInfo for receipt pay_id {}", pay_id),
+            OverpayError::ChannelRejected { proxy, error } => {
+                write!(f, "Channel {} rejected: {}", format_eth_address(proxy), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OverpayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OverpayError::InvalidInputs(e) | OverpayError::ChannelRejected { error: e, .. } => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 impl ReceiptsOverpayChecker {
     pub fn new(
         channel: EthAddress,
@@ -74,10 +445,182 @@ impl ReceiptsOverpayChecker {
             channel,
             pay_id_infos,
             settled_payments,
+            dust_policy: DustPolicy::default(),
+            verify_proxies: false,
+            verify_senders: false,
+            nonce_marks: None,
+            signing_domain: None,
+            epoch: 0,
+            dedupe_report: None,
+            current_time: None,
+            max_receipts_per_page: None,
+            sealed: SealedSigners::default(),
+            excluded: Vec::new(),
+            require_pay_id_authorization: false,
+        }
+    }
+
+    /// 从已封存的收据构造，验证签名时信任封存时恢复的签名者，只比较地址
+    pub fn from_sealed(channel: EthAddress, pay_id_infos: Vec<PayIdInfo>, sealed: &[SealedReceipt]) -> Self {
+        let receipts = sealed.iter().map(|sealed| sealed.receipt().clone()).collect();
+        Self {
+            sealed: SealedSigners::new(sealed),
+            ..Self::new(channel, pay_id_infos, receipts)
+        }
+    }
+
+    /// 每页最多 max_receipts_per_page 个收据，receiver 的值按 PaymentsGrouper::group_by_receiver_paged 分页计算，
+    /// 每页分别用 ReceiptsProfitCalculator::calculate_page 计算。收据不超过一页的receiver不受影响
+    pub fn with_max_receipts_per_page(mut self, max_receipts_per_page: usize) -> Self {
+        self.max_receipts_per_page = Some(max_receipts_per_page);
+        self
+    }
+
+    /// 设置结算轮次，默认为 0；结果以及后续的 ProfitResult、settlement_id 都携带该轮次
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 设置签名域，签名验证和 PayIdInfo 授权检查都使用带域的恢复
+    pub fn with_signing_domain(mut self, domain: SigningDomain) -> Self {
+        self.signing_domain = Some(domain);
+        self
+    }
+
+    /// 开启防重放检查：每个收据必须带 nonce，且同一 (pay_id, receiver) 的 nonce
+    /// 严格递增并大于上一轮的最大值，没有记录的 (pay_id, receiver) 不限制起始值
+    pub fn with_nonce_high_water_marks(mut self, marks: HashMap<(U256, EthAddress), u64>) -> Self {
+        self.nonce_marks = Some(marks);
+        self
+    }
+
+    /// 本批收据处理后每个 (pay_id, receiver) 的最大 nonce，作为下一轮的输入
+    pub fn nonce_high_water_marks(&self) -> HashMap<(U256, EthAddress), u64> {
+        let mut marks = self.nonce_marks.clone().unwrap_or_default();
+        for payment in &self.settled_payments {
+            if let Some(nonce) = payment.nonce {
+                let mark = marks.entry((payment.pay_id, payment.receiver)).or_insert(nonce);
+                *mark = (*mark).max(nonce);
+            }
         }
+        marks
+    }
+
+    /// 设置结算时间，valid_until 早于该时间的收据被拒绝（ReceiptExpired），
+    /// 没有 valid_until 的收据不受影响
+    pub fn with_current_time(mut self, current_time: u64) -> Self {
+        self.current_time = Some(current_time);
+        self
+    }
+
+    /// 恢复每个收据的代理地址并要求等于 channel
+    pub fn with_proxy_signature_check(mut self) -> Self {
+        self.verify_proxies = true;
+        self
+    }
+
+    /// 恢复每个收据的发送者地址并要求等于对应 PayIdInfo.sender
+    pub fn with_sender_signature_check(mut self) -> Self {
+        self.verify_senders = true;
+        self
+    }
+
+    /// 要求每个 PayIdInfo 带有 sender 对通道参数的签名（PayIdInfo::sign），否则返回 UnauthorizedPayIdInfo；
+    /// 设置了签名域时签名须由 PayIdInfo::sign_with_domain 在同一域下生成。默认不检查，PayIdInfo 作为可信数据
+    pub fn with_pay_id_authorization(mut self) -> Self {
+        self.require_pay_id_authorization = true;
+        self
+    }
+
+    /// 先用 dedupe_receipts 去掉完全重复的收据；存在内容冲突的收据时 process 返回错误并列出冲突
+    pub fn with_receipt_dedupe(mut self) -> Self {
+        let (receipts, duplicates, report) = split_duplicates(std::mem::take(&mut self.settled_payments));
+        self.settled_payments = receipts;
+        self.excluded.extend(duplicates);
+        self.dedupe_report = Some(report);
+        self
+    }
+
+    pub fn dedupe_report(&self) -> Option<&DedupeReport> {
+        self.dedupe_report.as_ref()
+    }
+
+    /// 设置 dust 策略，Skip 模式下 dust 收据在分组之前即被移除
+    pub fn with_dust_policy(mut self, dust_policy: DustPolicy) -> Self {
+        let dust = dust_policy.take_dust(&mut self.settled_payments);
+        self.excluded.extend(dust);
+        self.dust_policy = dust_policy;
+        self
+    }
+
+    /// with_receipt_dedupe、with_dust_policy 去掉的收据，按去掉的先后顺序
+    pub fn excluded_receipts(&self) -> &[PaymentSettledByProxy] {
+        &self.excluded
     }
 
     pub fn process(&self) -> Result<OverpayCheckResult, BoxError> {
+        self.process_with_metrics(&NoopMetrics)
+    }
+
+    /// 与 process 相同，开启 profiling 时把收据数、签名恢复、keccak 调用和树节点哈希次数写入 metrics
+    pub fn process_with_metrics(&self, metrics: &dyn Metrics) -> Result<OverpayCheckResult, BoxError> {
+        let result = measure(metrics, &PIPELINE_OPS, || {
+            let (payments_root, receiver_proofs, pay_ids_root) = self.commitments()?;
+            let mut result = OverpayCheckResult::new(payments_root, receiver_proofs, pay_ids_root)?.with_epoch(self.epoch);
+            result.excluded_root = excluded_receipts_root(result.hash_scheme(), &self.excluded);
+            result.referenced_pay_ids_root =
+                referenced_pay_ids_root(result.hash_scheme(), self.settled_payments.iter().map(|payment| payment.pay_id));
+            Ok(result)
+        });
+        record(metrics, RECEIPTS_PROCESSED, self.settled_payments.len() as u64);
+        result
+    }
+
+    /// 与 process 相同，但未结算的收据不报错：分出后只用已结算的收据计算根和超付，
+    /// 未结算的收据汇总在 UnsettledReport 中，并与其他被排除的收据一起承诺在 excluded_root 中
+    pub fn process_ignoring_unsettled(&self) -> Result<(OverpayCheckResult, UnsettledReport), BoxError> {
+        let (settled, unsettled) = SettlementFilter::partition_by_settled(self.settled_payments.clone());
+        let report = UnsettledReport::from_receipts(&unsettled)?;
+        let mut checker = Self { settled_payments: settled, ..self.clone() };
+        checker.excluded.extend(unsettled);
+        Ok((checker.process()?, report))
+    }
+
+    /// 与 process 相同，同时生成 CrossRootWitness，供只持有结果的审计方调用 verify_cross_roots
+    pub fn process_with_witness(&self) -> Result<(OverpayCheckResult, CrossRootWitness), BoxError> {
+        let result = self.process()?;
+
+        let pay_ids: Vec<U256> = self
+            .settled_payments
+            .iter()
+            .map(|payment| payment.pay_id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let (root, proofs) = PayIdsProcessor::create_with_proofs(&self.pay_id_infos)?;
+        debug_assert_eq!(root, result.pay_ids_root);
+
+        let mut witness_proofs: Vec<(PayIdInfo, MerkleProof)> = Vec::with_capacity(pay_ids.len());
+        // 同一 id 有多个 PayIdInfo 时只取第一个
+        for (id, proof) in proofs {
+            let proven = witness_proofs.last().is_some_and(|(info, _)| info.id == id);
+            if proven || pay_ids.binary_search(&id).is_err() {
+                continue;
+            }
+            let info = self
+                .pay_id_infos
+                .iter()
+                .find(|info| info.id == id && info.hash() == proof.value_proof.value)
+                .ok_or_else(|| format!("PayId {} not found in PayIdInfos", id))?;
+            witness_proofs.push((info.clone(), proof));
+        }
+
+        Ok((result, CrossRootWitness { pay_ids, proofs: witness_proofs }))
+    }
+
+    // process 与 OverpayCheckResult::verify_against 共用：验证输入并计算 (payments_root, receiver_proofs, pay_ids_root)
+    fn commitments(&self) -> Result<(B256, Vec<ReceiverProof>, B256), BoxError> {
         // 1. 预处理验证
         self.validate_prerequisites()?;
 
@@ -90,11 +633,7 @@ impl ReceiptsOverpayChecker {
         // 4. 创建PayIdInfo的segment_vc
         let pay_ids_root = self.create_pay_ids_vc()?;
 
-        Ok(OverpayCheckResult {
-            payments_root,
-            receiver_proofs,
-            pay_ids_root,
-        })
+        Ok((payments_root, receiver_proofs, pay_ids_root))
     }
 
     fn validate_prerequisites(&self) -> Result<(), BoxError> {
@@ -103,21 +642,121 @@ impl ReceiptsOverpayChecker {
             if info.proxy != self.channel {
                 return Err("Invalid channel in PayIdInfo".into());
             }
+            if self.require_pay_id_authorization {
+                info.check_authorized_in(self.signing_domain.as_ref())?;
+            }
         }
 
-        // 2. 验证settled状态
-        for payment in &self.settled_payments {
+        // 2. 验证settled状态，结算金额不能超过发送者授权的金额
+        for (index, payment) in self.settled_payments.iter().enumerate() {
             if !payment.settled {
-                return Err("Found unsettled payment".into());
+                return Err(WithContext::at_receipt(index, payment, "Found unsettled payment").to_string().into());
             }
         }
+        check_partial_settlement(&self.settled_payments)?;
 
-        // 3. 验证唯一性
+        // 3. 验证收据的代币与 PayIdInfo 一致，PayIdInfo 缺失时由超付检查报告
+        let tokens: HashMap<U256, EthAddress> = self.pay_id_infos.iter().map(|info| (info.id, info.token)).collect();
+        for (index, payment) in self.settled_payments.iter().enumerate() {
+            if let Some(&expected) = tokens.get(&payment.pay_id) {
+                if payment.token != expected {
+                    let mismatch = TokenMismatch { pay_id: payment.pay_id, expected, actual: payment.token };
+                    return Err(WithContext::at_receipt(index, payment, mismatch).into());
+                }
+            }
+        }
+
+        // 4. 验证 dust 策略
+        self.dust_policy.check(&self.settled_payments)?;
+
+        // 5. 验证唯一性，开启去重时先报告内容冲突的收据
+        if let Some(conflict) = self.dedupe_report.as_ref().and_then(|report| report.conflicts.first()) {
+            return Err(format!(
+                "Conflicting receipts for (pay_id {}, serv_id {}, receiver {:?}): amounts {:?}",
+                conflict.pay_id, conflict.serv_id, conflict.receiver, conflict.amounts
+            )
+            .into());
+        }
+        // 与 to_key() 相同：nonce 不同的收据是不同的收费，可以出现在同一批中
         let mut seen = HashMap::new();
-        for payment in &self.settled_payments {
-            let key = (payment.pay_id, payment.serv_id, payment.receiver);
-            if seen.insert(key, true).is_some() {
-                return Err("Duplicate payment found".into());
+        for (index, payment) in self.settled_payments.iter().enumerate() {
+            if seen.insert(payment.to_key(), true).is_some() {
+                return Err(WithContext::at_receipt(index, payment, "Duplicate payment found").to_string().into());
+            }
+        }
+
+        // 6. 验证签名
+        if self.verify_proxies || self.verify_senders {
+            self.validate_signatures()?;
+        }
+
+        // 7. 验证 nonce 防重放
+        if let Some(marks) = &self.nonce_marks {
+            Self::validate_nonces(&self.settled_payments, marks)?;
+        }
+
+        // 8. 验证收据未过期
+        if let Some(current_time) = self.current_time {
+            check_receipt_expiry(&self.settled_payments, current_time)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_nonces(
+        payments: &[PaymentSettledByProxy],
+        marks: &HashMap<(U256, EthAddress), u64>,
+    ) -> Result<(), BoxError> {
+        let mut nonces: HashMap<(U256, EthAddress), Vec<(u64, usize)>> = HashMap::new();
+        for (index, payment) in payments.iter().enumerate() {
+            let nonce = payment.nonce.ok_or_else(|| {
+                let missing = format!("Missing nonce for receipt (serv_id {})", payment.serv_id);
+                WithContext::at_receipt(index, payment, missing).to_string()
+            })?;
+            nonces.entry((payment.pay_id, payment.receiver)).or_default().push((nonce, index));
+        }
+
+        // 与收据顺序无关：排序后要求严格递增且大于上一轮的最大值，报告排在后面的收据
+        for (key, mut values) in nonces {
+            values.sort_unstable();
+            let mut previous = marks.get(&key).copied();
+            for (nonce, index) in values {
+                if previous.is_some_and(|previous| nonce <= previous) {
+                    let replayed = format!("Replayed nonce {}", nonce);
+                    return Err(WithContext::at_receipt(index, &payments[index], replayed).to_string().into());
+                }
+                previous = Some(nonce);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_signatures(&self) -> Result<(), BoxError> {
+        let senders: HashMap<U256, EthAddress> = self.pay_id_infos
+            .iter()
+            .map(|info| (info.id, info.sender))
+            .collect();
+
+        let domain = self.signing_domain.as_ref();
+        for (index, payment) in self.settled_payments.iter().enumerate() {
+            if self.verify_proxies {
+                let proxy = self.sealed.proxy_address(payment, domain);
+                if proxy != Some(self.channel) {
+                    let invalid = InvalidReceiptSignature::new("proxy", payment, self.channel, proxy);
+                    return Err(WithContext::at_receipt(index, payment, invalid).into());
+                }
+            }
+
+            if self.verify_senders {
+                let expected = *senders.get(&payment.pay_id).ok_or_else(|| {
+                    WithContext::at_receipt(index, payment, "PayId not found in PayIdInfos").to_string()
+                })?;
+                let sender = self.sealed.sender_address(payment, domain);
+                if sender != Some(expected) {
+                    let invalid = InvalidReceiptSignature::new("sender", payment, expected, sender);
+                    return Err(WithContext::at_receipt(index, payment, invalid).into());
+                }
             }
         }
 
@@ -128,7 +767,10 @@ impl ReceiptsOverpayChecker {
         // 1. 统计每个pay_id的总额
         let mut pay_id_totals: HashMap<U256, U256> = HashMap::new();
         for payment in &self.settled_payments {
-            *pay_id_totals.entry(payment.pay_id).or_default() += payment.amount;
+            let total = pay_id_totals.entry(payment.pay_id).or_default();
+            *total = total
+                .checked_add(payment.amount)
+                .ok_or(AmountOverflow::PayId(payment.pay_id))?;
         }
 
         //2. 统计每个pid的允许总额
@@ -139,10 +781,9 @@ impl ReceiptsOverpayChecker {
         
         // 3. 验证不超过PayIdInfo中的amount
         for (pay_id, total) in pay_id_totals {
-            // let pay_id_bytes = B256::from_uint(&pay_id);
             if let Some(&max_amount) = pay_id_info_map.get(&pay_id) {
                 if total > max_amount {
-                    return Err(format!("Overpayment detected for pay_id {}", pay_id).into());
+                    return Err(OverpayDetected { pay_id, total, deposit: max_amount }.into());
                 }
             } else {
                 return Err(format!("PayId {} not found in PayIdInfos", pay_id).into());
@@ -152,7 +793,11 @@ impl ReceiptsOverpayChecker {
         Ok(())
     }
     fn create_payments_vc(&self) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
-        PaymentsGrouper::group_by_receiver(&self.settled_payments)
+        PaymentsGrouper::group_by_receiver_paged(
+            &self.settled_payments,
+            TreeHashAlgorithm::Keccak,
+            self.max_receipts_per_page,
+        )
     }
 
     fn create_pay_ids_vc(&self) -> Result<B256, BoxError> {
@@ -163,87 +808,815 @@ impl ReceiptsOverpayChecker {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ethaddr_gen::keypair_from_seed;
+    use crate::fixtures::{signed_receipt, Scenario, ScenarioBuilder, Violation};
+    use crate::models::UnauthorizedPayIdInfo;
+    use crate::receipts::{SettledExceedsAuthorized, SettledReceiptBuilder};
 
-    fn create_test_pay_id_info(id: u64, amount: u64, channel: EthAddress) -> PayIdInfo {
-        PayIdInfo {
-            id: U256::from(id),
-            amount: U256::from(amount),
-            sender: [0u8; 20].into(),
-            proxy: channel,
-            state: 1,
-            created_at: 1000,
-            closing_time: 2000,
-        }
-    }
-
+    // 未签名的收据，只用于不验证签名的 nonce 检查
     fn create_test_payment(
         pay_id: u64,
         serv_id: u32,
         receiver: EthAddress,
         amount: u64,
     ) -> PaymentSettledByProxy {
-        PaymentSettledByProxy {
-            pay_id: U256::from(pay_id),
-            serv_id,
-            receiver,
-            amount: U256::from(amount),
-            settled: true,
-            sig_sender: [1u8;65],
-            sig_proxy: [2u8;65],
-        }
+        PaymentSettledByProxy::new(U256::from(pay_id), serv_id, U256::from(amount), receiver)
+            .with_settled(true)
+            .with_sig_sender([1u8;65])
+            .with_sig_proxy([2u8;65])
     }
 
     #[test]
     fn test_prerequisites_validation() -> Result<(), BoxError> {
-        let channel = [1u8;20];
-        let receiver = [21u8;20];
+        let scenario = ScenarioBuilder::new(1)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(1, 2, 0, 400)
+            .with_payment(2, 1, 0, 1000)
+            .with_deposit(1, U256::from(1000))
+            .build()?;
+        scenario.overpay_checker().validate_prerequisites()?;
 
-        let pay_id_infos = vec![
-            create_test_pay_id_info(1, 1000, channel),
-            create_test_pay_id_info(2, 2000, channel),
-        ];
+        // PayIdInfo 属于其他代理
+        let mut pay_id_infos = scenario.pay_id_infos.clone();
+        pay_id_infos[1].proxy = [9u8;20];
+        let sorter = ReceiptsOverpayChecker::new(scenario.proxy, pay_id_infos, scenario.receipts.clone());
+        assert!(sorter.validate_prerequisites().is_err());
 
-        let settled_payments = vec![
-            create_test_payment(1, 1, receiver, 500),
-            create_test_payment(1, 2, receiver, 400),
-            create_test_payment(2, 1, receiver, 1000),
-        ];
+        Ok(())
+    }
+
+    #[test]
+    fn test_overpayment_validation() -> Result<(), BoxError> {
+        let builder = || {
+            ScenarioBuilder::new(2)
+                .with_payment(1, 1, 0, 600)
+                .with_payment(1, 2, 0, 400)
+                .with_payment(2, 1, 0, 1000)
+        };
+
+        // 正常支付场景：存款与收据总额相同
+        let scenario = builder().build()?;
+        assert!(scenario.overpay_checker().validate_overpayment().is_ok());
 
-        let sorter = ReceiptsOverpayChecker::new(channel, pay_id_infos, settled_payments);
-        sorter.validate_prerequisites()?;
+        // 超付场景：pay_id 1 的总额超过存款
+        let overpaid = builder().with_violation(Violation::Overpay { pay_id: 1 }).build()?;
+        let err = overpaid.overpay_checker().validate_overpayment().unwrap_err();
+        assert!(err.to_string().contains("Overpayment detected for pay_id 1"));
 
         Ok(())
     }
 
     #[test]
-    fn test_overpayment_validation() -> Result<(), BoxError> {
-        let channel = [1u8;20];
-        let receiver = [2u8;20];
+    fn test_amount_overflow() -> Result<(), BoxError> {
+        let mut scenario = ScenarioBuilder::new(3)
+            .with_payment(1, 1, 0, 0)
+            .with_payment(1, 2, 0, 0)
+            .with_deposit(1, U256::MAX)
+            .build()?;
+        for payment in scenario.receipts.iter_mut() {
+            payment.amount = U256::MAX;
+        }
 
-        let pay_id_infos = vec![
-            create_test_pay_id_info(1, 1000, channel),
-            create_test_pay_id_info(2, 2000, channel),
-        ];
+        let sorter = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos, scenario.receipts);
+        let err = sorter.validate_overpayment().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AmountOverflow>(),
+            Some(&AmountOverflow::PayId(U256::from(1)))
+        );
 
-        // 正常支付场景
-        let valid_payments = vec![
-            create_test_payment(1, 1, receiver, 500),
-            create_test_payment(1, 2, receiver, 400),
-            create_test_payment(2, 1, receiver, 1000),
-        ];
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_verification() -> Result<(), BoxError> {
+        let builder = || {
+            ScenarioBuilder::new(4)
+                .with_payment(1, 1, 0, 300)
+                .with_payment(2, 2, 0, 400)
+        };
+        let (other_key, _) = keypair_from_seed(12345);
+
+        // 合法收据通过验证
+        let scenario = builder().build()?;
+        scenario.overpay_checker().process()?;
+
+        // 伪造的代理签名被拒绝，错误中包含收据标识
+        let forged = builder().with_violation(Violation::WrongProxy { pay_id: 2 }).build()?;
+        let proxy_only = |scenario: &Scenario| {
+            ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), scenario.receipts.clone())
+                .with_proxy_signature_check()
+        };
+        let err = proxy_only(&forged).process().unwrap_err();
+        assert!(err.to_string().contains("Invalid proxy signature"));
+        assert!(err.to_string().contains("serv_id 2"));
+
+        // 只检查发送者时不恢复代理签名
+        ReceiptsOverpayChecker::new(forged.proxy, forged.pay_id_infos.clone(), forged.receipts.clone())
+            .with_sender_signature_check()
+            .process()?;
+
+        // 未开启验证时保持原有行为
+        ReceiptsOverpayChecker::new(forged.proxy, forged.pay_id_infos, forged.receipts).process()?;
+
+        // 发送者与 PayIdInfo.sender 不一致被拒绝
+        let mut wrong_sender = builder().build()?;
+        wrong_sender.receipts[0] =
+            signed_receipt(1, 1, 300, wrong_sender.receiver(0), &other_key, &wrong_sender.proxy_key)?;
+        proxy_only(&wrong_sender).process()?;
+        let err = wrong_sender.overpay_checker().process().unwrap_err();
+        assert!(err.to_string().contains("Invalid sender signature"));
+        assert!(err.to_string().contains("serv_id 1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signing_domain_verification() -> Result<(), BoxError> {
+        let mut scenario = ScenarioBuilder::new(5).with_payment(1, 1, 0, 100).build()?;
+        let mainnet = SigningDomain::new(1, [9u8;20]);
+        let goerli = SigningDomain::new(5, [9u8;20]);
+
+        let mut payment = super::super::Payment::new(U256::from(1), 1, U256::from(100), scenario.receiver(0));
+        payment.sign_with_domain(&scenario.sender_keys[0], &mainnet)?;
+        let mut settled = PaymentSettledByProxy::from(payment);
+        settled.set_settlement(U256::from(100), true);
+        settled.sign_by_proxy_with_domain(&scenario.proxy_key, &mainnet)?;
+        scenario.receipts = vec![settled];
+
+        scenario.overpay_checker().with_signing_domain(mainnet).process()?;
+        assert!(scenario.overpay_checker().with_signing_domain(goerli).process().is_err());
+        // 无域验证同样失败
+        assert!(scenario.overpay_checker().process().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nonce_replay() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(6).with_deposit(1, U256::from(1000)).build()?;
+        let channel = scenario.proxy;
+        let receiver = scenario.receiver(0);
+        let pay_id_infos = scenario.pay_id_infos;
+
+        let with_nonce = |serv_id: u32, nonce: u64| {
+            let mut payment = create_test_payment(1, serv_id, receiver, 100);
+            payment.nonce = Some(nonce);
+            payment
+        };
+
+        // 第一轮：没有历史记录
+        let first_round = vec![with_nonce(1, 1), with_nonce(2, 2)];
+        let checker = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), first_round.clone())
+            .with_nonce_high_water_marks(HashMap::new());
+        checker.process()?;
+        let marks = checker.nonce_high_water_marks();
+        assert_eq!(marks.get(&(U256::from(1), receiver)), Some(&2));
+
+        // 第二轮重放同样的收据被拒绝
+        let err = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), first_round)
+            .with_nonce_high_water_marks(marks.clone())
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().contains("Replayed nonce"));
+
+        // 新的 nonce 通过
+        ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), vec![with_nonce(1, 3)])
+            .with_nonce_high_water_marks(marks.clone())
+            .process()?;
 
-        let sorter = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), valid_payments);
-        assert!(sorter.validate_overpayment().is_ok());
+        // 同一批内 nonce 重复被拒绝
+        assert!(ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), vec![with_nonce(1, 5), with_nonce(2, 5)])
+            .with_nonce_high_water_marks(marks.clone())
+            .process()
+            .is_err());
 
-        // 超付场景
-        let overpaid_payments = vec![
-            create_test_payment(1, 1, receiver, 600),
-            create_test_payment(1, 2, receiver, 500), // 总额超过1000
-            create_test_payment(2, 1, receiver, 1000),
+        // 唯一性键包含 nonce：同一 (pay_id, serv_id, receiver) 下 nonce 不同的收据是两笔收费
+        let result = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), vec![with_nonce(1, 6), with_nonce(1, 7)])
+            .with_nonce_high_water_marks(marks.clone())
+            .process()?;
+        assert_eq!(result.receiver_proofs.len(), 1);
+        let err = ReceiptsOverpayChecker::new(channel, pay_id_infos.clone(), vec![with_nonce(1, 6), with_nonce(1, 6)])
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().ends_with("Duplicate payment found"));
+
+        // 开启检查后缺少 nonce 的收据被拒绝
+        assert!(ReceiptsOverpayChecker::new(
+            channel,
+            pay_id_infos,
+            vec![create_test_payment(1, 1, receiver, 100)],
+        )
+        .with_nonce_high_water_marks(marks)
+        .process()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receipt_expiry() -> Result<(), BoxError> {
+        use crate::receipts::ReceiptExpired;
+
+        let scenario = ScenarioBuilder::new(6).with_deposit(1, U256::from(1000)).build()?;
+        let receiver = scenario.receiver(0);
+        let receipts = vec![
+            create_test_payment(1, 1, receiver, 100).with_valid_until(1000),
+            create_test_payment(1, 2, receiver, 100),
         ];
+        let checker = |current_time: u64| {
+            ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), receipts.clone())
+                .with_current_time(current_time)
+        };
+
+        // 边界时刻仍然有效，结果与不检查过期时相同
+        let result = checker(1000).process()?;
+        let unchecked = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), receipts.clone())
+            .process()?;
+        assert_eq!(result, unchecked);
+
+        let err = checker(1001).process().unwrap_err();
+        let expired = err.downcast_ref::<WithContext<ReceiptExpired>>().ok_or("Expected ReceiptExpired")?;
+        assert_eq!((expired.inner.serv_id, expired.inner.valid_until, expired.inner.current_time), (1, 1000, 1001));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dust_policy() -> Result<(), BoxError> {
+        use crate::receipts::DustAction;
+
+        let scenario = ScenarioBuilder::new(7)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(1, 2, 0, 0)
+            .with_deposit(1, U256::from(1000))
+            .build()?;
+
+        // 默认策略保持原有行为
+        scenario.overpay_checker().process()?;
+
+        // Reject: 报告出错的收据
+        let err = scenario
+            .overpay_checker()
+            .with_dust_policy(DustPolicy::new(U256::from(1), DustAction::Reject))
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().contains("serv_id 2"));
+
+        // Skip: 结果与不包含 dust 收据时相同
+        let skipped = scenario
+            .overpay_checker()
+            .with_dust_policy(DustPolicy::new(U256::from(1), DustAction::Skip))
+            .process()?;
+        let mut without_dust = scenario.clone();
+        without_dust.receipts.truncate(1);
+        let expected = without_dust.overpay_checker().process()?;
+        assert_eq!(skipped.payments_root, expected.payments_root);
+
+        // 边界值：amount == min_amount 不被拒绝
+        without_dust
+            .overpay_checker()
+            .with_dust_policy(DustPolicy::new(U256::from(500), DustAction::Reject))
+            .process()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_receipt_dedupe() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(11)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(1, 2, 0, 400)
+            .build()?;
+        let mut resent = scenario.receipts.clone();
+        resent.push(scenario.receipts[0].clone());
+
+        // 默认严格拒绝重复收据
+        let checker = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), resent.clone());
+        assert!(checker.process().unwrap_err().to_string().contains("Duplicate payment"));
+
+        // 开启去重后与没有重发时结果相同
+        let checker = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), resent.clone())
+            .with_proxy_signature_check()
+            .with_sender_signature_check()
+            .with_receipt_dedupe();
+        assert_eq!(checker.dedupe_report().map(|report| report.dropped_total()), Some(1));
+        assert_eq!(checker.process()?.payments_root, scenario.overpay_checker().process()?.payments_root);
+
+        // 金额不同的同一收据报告为冲突
+        let mut conflicting = resent;
+        conflicting.push(signed_receipt(1, 2, 350, scenario.receiver(0), &scenario.sender_keys[0], &scenario.proxy_key)?);
+        let err = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos, conflicting)
+            .with_receipt_dedupe()
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().contains("Conflicting receipts"));
+        assert!(err.to_string().contains("serv_id 2"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_excluded_root() -> Result<(), BoxError> {
+        use crate::receipts::DustAction;
+
+        let scenario = ScenarioBuilder::new(18)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(1, 2, 0, 400)
+            .with_payment(1, 3, 0, 0)
+            .build()?;
+
+        // 没有收据被排除
+        let result = scenario.overpay_checker().process()?;
+        assert_eq!(result.excluded_root, None);
+        assert!(result.verify_excluded(&[]));
+        assert_eq!(crate::OverpayCheckResultStruct::from(result).excluded_root, B256::ZERO);
+
+        // 重发的收据和 dust 收据都承诺在 excluded_root 中，与顺序无关
+        let mut resent = scenario.receipts.clone();
+        resent.push(scenario.receipts[1].clone());
+        let checker = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), resent)
+            .with_proxy_signature_check()
+            .with_sender_signature_check()
+            .with_receipt_dedupe()
+            .with_dust_policy(DustPolicy::new(U256::from(1), DustAction::Skip));
+        let excluded = vec![scenario.receipts[1].clone(), scenario.receipts[2].clone()];
+        assert_eq!(checker.excluded_receipts(), excluded.as_slice());
+        let result = checker.process()?;
+        assert!(result.excluded_root.is_some());
+        assert!(result.verify_excluded(&excluded));
+        assert!(result.verify_excluded(&[excluded[1].clone(), excluded[0].clone()]));
+        assert!(result.to_string().ends_with(&format!("excluded_root={}", result.excluded_root.unwrap())));
+
+        // 排除集合不同时承诺不同
+        assert!(!result.verify_excluded(&excluded[..1]));
+        assert!(!result.verify_excluded(&[excluded[0].clone(), excluded[0].clone()]));
+        assert!(!result.verify_excluded(&[]));
+        assert_ne!(excluded_receipts_root(result.hash_scheme(), &excluded[..1]), result.excluded_root);
+
+        // 按结果记录的版本选择哈希方案：Legacy 的结果按 V1 计算，与编译时的 v2-hashing 无关
+        let v1_root = excluded_receipts_root(HashScheme::V1, &excluded);
+        assert_ne!(v1_root, excluded_receipts_root(HashScheme::V2, &excluded));
+        let mut legacy = result.clone().with_commitment_version(CommitmentVersion::Legacy);
+        legacy.excluded_root = v1_root;
+        assert!(legacy.verify_excluded(&excluded));
+        assert!(!legacy.clone().with_commitment_version(CommitmentVersion::V2).verify_excluded(&excluded));
+
+        // excluded_root 随 sol 结构和 public values 往返
+        let restored = OverpayCheckResult::try_from(crate::OverpayCheckResultStruct::from(result.clone()))?;
+        assert_eq!(restored.excluded_root, result.excluded_root);
+        assert_eq!(OverpayCheckResult::from_public_values(&result.to_public_values())?.excluded_root, result.excluded_root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_against() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(12)
+            .with_receivers(3)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(1, 2, 1, 400)
+            .with_payment(2, 1, 2, 500)
+            .build()?;
+        let result = scenario.overpay_checker().process()?;
+        let verify = |result: &OverpayCheckResult, pay_id_infos: &[PayIdInfo], receipts: &[PaymentSettledByProxy]| {
+            result.verify_against(scenario.proxy, pay_id_infos, receipts)
+        };
+        verify(&result, &scenario.pay_id_infos, &scenario.receipts).map_err(|e| e.to_string())?;
+
+        // 篡改证明的兄弟节点
+        let mut tampered = result.clone();
+        tampered.receiver_proofs[1].proof.segment_proof.siblings[0] = B256::repeat_byte(0xee);
+        let receiver = tampered.receiver_proofs[1].receiver;
+        assert!(matches!(
+            verify(&tampered, &scenario.pay_id_infos, &scenario.receipts),
+            Err(OverpayError::InvalidReceiverProof(r)) if r == receiver
+        ));
+
+        // 篡改证明的值
+        let mut tampered = result.clone();
+        tampered.receiver_proofs[0].proof.value_proof.value = B256::repeat_byte(0xee);
+        assert!(matches!(
+            verify(&tampered, &scenario.pay_id_infos, &scenario.receipts),
+            Err(OverpayError::InvalidReceiverProof(_))
+        ));
+
+        // 篡改 payments_root
+        let mut tampered = result.clone();
+        tampered.payments_root = B256::repeat_byte(0xee);
+        assert!(matches!(
+            verify(&tampered, &scenario.pay_id_infos, &scenario.receipts),
+            Err(OverpayError::PaymentsRootMismatch { .. })
+        ));
+
+        // 输入中某个收据的金额被改动
+        let mut receipts = scenario.receipts.clone();
+        receipts[0].amount = U256::from(299);
+        assert!(matches!(
+            verify(&result, &scenario.pay_id_infos, &receipts),
+            Err(OverpayError::PaymentsRootMismatch { .. })
+        ));
+
+        // 缺少一个接收者的证明
+        let mut tampered = result.clone();
+        let removed = tampered.receiver_proofs.remove(2).receiver;
+        match verify(&tampered, &scenario.pay_id_infos, &scenario.receipts) {
+            Err(OverpayError::ReceiverMismatch { missing, extra }) => {
+                assert_eq!(missing, vec![removed]);
+                assert!(extra.is_empty());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // PayIdInfo 与结果不一致
+        let mut pay_id_infos = scenario.pay_id_infos.clone();
+        pay_id_infos[0].amount += U256::from(1);
+        assert!(matches!(
+            verify(&result, &pay_id_infos, &scenario.receipts),
+            Err(OverpayError::PayIdsRootMismatch { .. })
+        ));
+
+        // 输入本身超付
+        let mut receipts = scenario.receipts.clone();
+        receipts[0].amount = U256::from(10_000);
+        assert!(matches!(
+            verify(&result, &scenario.pay_id_infos, &receipts),
+            Err(OverpayError::InvalidInputs(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_paged_result() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(13)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 100)
+            .with_payment(1, 2, 0, 200)
+            .with_payment(1, 3, 0, 300)
+            .with_payment(2, 1, 1, 400)
+            .build()?;
+        let paged = || {
+            ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), scenario.receipts.clone())
+                .with_max_receipts_per_page(2)
+        };
+        let result = paged().process()?;
+
+        // 不分页的检查器得到不同的 payments_root
+        assert!(matches!(
+            result.verify_against(scenario.proxy, &scenario.pay_id_infos, &scenario.receipts),
+            Err(OverpayError::PaymentsRootMismatch { .. })
+        ));
+
+        // 按相同的页大小重新验证
+        result.verify_with_checker(&paged()).map_err(|e| e.to_string())?;
+        let mut tampered = result.clone();
+        tampered.receiver_proofs[0].proof.value_proof.value = B256::repeat_byte(0xee);
+        assert!(matches!(tampered.verify_with_checker(&paged()), Err(OverpayError::InvalidReceiverProof(_))));
+        assert!(matches!(
+            result.verify_with_checker(&paged().with_max_receipts_per_page(3)),
+            Err(OverpayError::PaymentsRootMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cross_root_witness() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(13)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(2, 1, 1, 400)
+            .with_payment(3, 2, 0, 500)
+            .build()?;
+        let (result, witness) = scenario.overpay_checker().process_with_witness()?;
+        assert_eq!(result, scenario.overpay_checker().process()?);
+        assert_eq!(witness.pay_ids, vec![U256::from(1), U256::from(2), U256::from(3)]);
+        result.verify_cross_roots(&witness).map_err(|e| e.to_string())?;
+
+        // 缺少一个 pay_id 的证明
+        let mut dropped = witness.clone();
+        dropped.proofs.remove(1);
+        assert!(matches!(
+            result.verify_cross_roots(&dropped),
+            Err(OverpayError::MissingPayIdProof(id)) if id == U256::from(2)
+        ));
+
+        // 换成内容不同的 PayIdInfo
+        let mut substituted = witness.clone();
+        substituted.proofs[0].0.amount += U256::from(1);
+        assert!(matches!(
+            result.verify_cross_roots(&substituted),
+            Err(OverpayError::InvalidPayIdProof(id)) if id == U256::from(1)
+        ));
+
+        // 用另一个 pay_id 的证明顶替
+        let mut substituted = witness.clone();
+        substituted.proofs[2] = substituted.proofs[0].clone();
+        assert!(matches!(
+            result.verify_cross_roots(&substituted),
+            Err(OverpayError::InvalidPayIdProof(id)) if id == U256::from(1)
+        ));
+
+        // 证明不属于该结果的 pay_ids_root
+        let mut other = result.clone();
+        other.pay_ids_root = B256::repeat_byte(0xee);
+        assert!(matches!(other.verify_cross_roots(&witness), Err(OverpayError::InvalidPayIdProof(_))));
+
+        // 同时删去 pay_id 和它的证明，与结果承诺的列表不一致
+        let mut truncated = witness.clone();
+        truncated.pay_ids.remove(1);
+        truncated.proofs.remove(1);
+        assert!(matches!(
+            result.verify_cross_roots(&truncated),
+            Err(OverpayError::ReferencedPayIdsMismatch { expected, .. }) if expected == result.referenced_pay_ids_root
+        ));
+        let mut legacy = result.clone();
+        legacy.referenced_pay_ids_root = None;
+        assert!(matches!(legacy.verify_cross_roots(&witness), Err(OverpayError::ReferencedPayIdsMismatch { .. })));
+
+        // 承诺随 public values 往返
+        let restored = OverpayCheckResult::from_public_values(&result.to_public_values())?;
+        assert_eq!(restored.referenced_pay_ids_root, result.referenced_pay_ids_root);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_mismatch_rejected() -> Result<(), BoxError> {
+        let token = [0xaau8; 20];
+        let scenario = ScenarioBuilder::new(14)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(2, 1, 0, 400)
+            .with_token(2, token)
+            .build()?;
+        scenario.overpay_checker().process()?;
+
+        // PayIdInfo 的代币与收据不同
+        let mut mismatched = scenario.clone();
+        mismatched.pay_id_infos[1].token = [0xbbu8; 20];
+        let err = mismatched.overpay_checker().process().unwrap_err();
+        let mismatch = err.downcast_ref::<WithContext<TokenMismatch>>().ok_or("Expected TokenMismatch")?;
+        assert_eq!(mismatch.index, Some(1));
+        assert_eq!(mismatch.inner, TokenMismatch { pay_id: U256::from(2), expected: [0xbbu8; 20], actual: token });
+
+        // 原生代币的 PayIdInfo 不接受代币收据
+        let mut native = scenario.clone();
+        native.pay_id_infos[1].token = crate::NATIVE_TOKEN;
+        assert!(native.overpay_checker().process().unwrap_err().is::<WithContext<TokenMismatch>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_id_authorization() -> Result<(), BoxError> {
+        let mut scenario = ScenarioBuilder::new(19)
+            .with_senders(2)
+            .with_payment(1, 1, 0, 300)
+            .with_payment(2, 1, 0, 400)
+            .build()?;
+        let unsigned = scenario.overpay_checker().process()?;
+
+        // 开启后未签名的 PayIdInfo 被拒绝
+        let err = scenario.overpay_checker().with_pay_id_authorization().process().unwrap_err();
+        let unauthorized = err.downcast_ref::<UnauthorizedPayIdInfo>().ok_or("Expected UnauthorizedPayIdInfo")?;
+        assert_eq!((unauthorized.id, unauthorized.signer), (U256::from(1), None));
+
+        // 签名不改变承诺
+        scenario.sign_pay_id_infos()?;
+        let signed = scenario.overpay_checker().with_pay_id_authorization().process()?;
+        assert_eq!((signed.payments_root, signed.pay_ids_root), (unsigned.payments_root, unsigned.pay_ids_root));
+
+        // 由另一个发送者签名
+        let mut forged = scenario.clone();
+        forged.pay_id_infos[1].sign(&forged.sender_keys[1])?;
+        let err = forged.overpay_checker().with_pay_id_authorization().process().unwrap_err();
+        let unauthorized = err.downcast_ref::<UnauthorizedPayIdInfo>().ok_or("Expected UnauthorizedPayIdInfo")?;
+        assert_eq!(unauthorized.signer, Some(forged.senders[1]));
+
+        // 配置了签名域时，不带域的授权不能在这个部署上使用；收据签名不在此验证
+        let domain = SigningDomain::new(1, [0x77; 20]);
+        let scoped = |infos: &[PayIdInfo]| {
+            ReceiptsOverpayChecker::new(scenario.proxy, infos.to_vec(), scenario.receipts.clone())
+                .with_epoch(scenario.epoch)
+                .with_signing_domain(domain)
+                .with_pay_id_authorization()
+                .process()
+        };
+        let err = scoped(&scenario.pay_id_infos).unwrap_err();
+        assert!(err.is::<UnauthorizedPayIdInfo>());
+        let mut infos = scenario.pay_id_infos.clone();
+        for info in &mut infos {
+            let index = scenario.senders.iter().position(|sender| *sender == info.sender).ok_or("Unknown sender")?;
+            info.sign_with_domain(&scenario.sender_keys[index], &domain)?;
+        }
+        assert_eq!(scoped(&infos)?.pay_ids_root, unsigned.pay_ids_root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_settlement() -> Result<(), BoxError> {
+        let mut scenario = ScenarioBuilder::new(15)
+            .with_payment(1, 1, 0, 1000)
+            .with_deposit(1, U256::from(600))
+            .build()?;
+        let sender = scenario.senders.iter().position(|sender| *sender == scenario.pay_id_infos[0].sender).unwrap();
+
+        // 发送者授权 1000，代理只结算 600，按结算金额检查存款
+        scenario.receipts[0] = SettledReceiptBuilder::new(U256::from(1), 1, U256::from(1000), scenario.receiver(0))
+            .sign_sender(&scenario.sender_keys[sender])
+            .settle(U256::from(600), true)
+            .allow_amount_change()
+            .sign_proxy(&scenario.proxy_key)
+            .build()?;
+        let result = scenario.overpay_checker().process()?;
+        let receiver = scenario.receiver(0);
+        let profit = scenario.profit_calculator(receiver, result.get_merkle_proof(receiver)?).calculate()?;
+        assert_eq!(profit.system_profit + profit.proxy_profit + profit.receiver_profit, U256::from(600));
+
+        // 结算金额超过授权金额被拒绝
+        let mut over = scenario.clone();
+        over.receipts[0].amount = U256::from(1001);
+        let err = over.overpay_checker().process().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WithContext<SettledExceedsAuthorized>>().map(|err| (err.inner.authorized, err.inner.settled)),
+            Some((U256::from(1000), U256::from(1001)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_ignoring_unsettled() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(16)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(2, 1, 1, 300)
+            .with_deposit(1, U256::from(500))
+            .build()?;
+        let settled_only = scenario.overpay_checker().process()?;
+
+        // 未结算的收据：计入时 pay_id 1 会超付
+        let mut payments = scenario.receipts.clone();
+        let unsettled = [(0, 2, 400u64), (1, 3, 50)].map(|(index, serv_id, amount)| {
+            let mut payment = scenario.receipts[index].clone();
+            payment.serv_id = serv_id;
+            payment.amount = U256::from(amount);
+            payment.settled = false;
+            payment
+        });
+        payments.insert(1, unsettled[0].clone());
+        payments.push(unsettled[1].clone());
+        let checker = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), payments.clone())
+            .with_epoch(scenario.epoch)
+            .with_proxy_signature_check()
+            .with_sender_signature_check();
+        assert!(checker.process().unwrap_err().to_string().contains("unsettled"));
+
+        // 根只覆盖已结算的收据，未结算的收据承诺在 excluded_root 中
+        let (result, report) = checker.process_ignoring_unsettled()?;
+        assert!(result.verify_excluded(&unsettled));
+        assert_eq!(OverpayCheckResult { excluded_root: None, ..result }, settled_only);
+
+        // 报告与输入总额对账
+        assert_eq!(report.count, 2);
+        assert_eq!(report.total_amount, U256::from(450));
+        assert_eq!(
+            report.by_receiver.iter().map(|(_, amount)| *amount).fold(U256::ZERO, |a, b| a + b),
+            report.total_amount
+        );
+        let input_total = payments.iter().fold(U256::ZERO, |total, payment| total + payment.amount);
+        let settled_total = scenario.receipts.iter().fold(U256::ZERO, |total, payment| total + payment.amount);
+        assert_eq!(settled_total + report.total_amount, input_total);
+        let receiver_0 = report.by_receiver.iter().find(|(receiver, _)| *receiver == scenario.receiver(0));
+        assert_eq!(receiver_0.map(|(_, amount)| *amount), Some(U256::from(400)));
+
+        // 没有未结算的收据时与 process 相同
+        let (result, report) = scenario.overpay_checker().process_ignoring_unsettled()?;
+        assert_eq!(result, settled_only);
+        assert!(report.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors_report_receipt_index() -> Result<(), BoxError> {
+        use crate::guest_checks::{classify, ErrorCode};
+        use crate::receipts::ReceiptExpired;
+
+        let builder = || (1..=5).fold(ScenarioBuilder::new(17), |builder, pay_id| builder.with_payment(pay_id, 1, 0, 100));
+
+        // 第 4 个收据（下标 3）的代理签名是伪造的
+        let forged = builder().with_violation(Violation::WrongProxy { pay_id: 4 }).build()?;
+        let err = forged.overpay_checker().process().unwrap_err();
+        let invalid = err
+            .downcast_ref::<WithContext<InvalidReceiptSignature>>()
+            .ok_or("Expected InvalidReceiptSignature")?;
+        assert_eq!((invalid.index, invalid.pay_id, invalid.inner.signer), (Some(3), Some(U256::from(4)), "proxy"));
+        assert!(err.to_string().starts_with("receipt #3 (receiver "));
+        assert_eq!(classify(err.as_ref()), ErrorCode::SignatureInvalid);
+
+        // 字符串错误同样带有下标前缀
+        let mut scenario = builder().build()?;
+        scenario.receipts[2].settled = false;
+        let err = scenario.overpay_checker().process().unwrap_err();
+        assert!(err.to_string().starts_with("receipt #2 "), "{}", err);
+
+        let mut scenario = builder().build()?;
+        scenario.receipts.push(scenario.receipts[1].clone());
+        let err = scenario.overpay_checker().process().unwrap_err();
+        assert!(err.to_string().starts_with("receipt #5 ") && err.to_string().ends_with("Duplicate payment found"));
+
+        // 修改 valid_until 后签名不再有效，这里不验证签名
+        let mut scenario = builder().build()?;
+        scenario.receipts[4].valid_until = Some(1);
+        let err = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos, scenario.receipts)
+            .with_current_time(2)
+            .process()
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<WithContext<ReceiptExpired>>().and_then(|err| err.index), Some(4));
+        assert_eq!(classify(err.as_ref()), ErrorCode::Expired);
+        Ok(())
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_process_metrics() -> Result<(), BoxError> {
+        use crate::metrics::*;
+
+        let scenario = ScenarioBuilder::new(13)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(2, 1, 1, 300)
+            .build()?;
+        let metrics = InMemoryMetrics::new();
+        scenario.overpay_checker().process_with_metrics(&metrics)?;
+
+        assert_eq!(metrics.get(RECEIPTS_PROCESSED), 2);
+        // 每个收据恢复代理和发送者各一次
+        assert_eq!(metrics.get(SIGNATURES_RECOVERED), 4);
+        // 2 个分组值 + 两棵各 2 个元素的 hash-only 树（每次插入 3 次哈希，单段没有上层）
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 14);
+        assert!(metrics.get(KECCAK_INVOCATIONS) >= metrics.get(SIGNATURES_RECOVERED));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_receiver_rejected() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(8).with_payment(1, 1, 0, 100).build()?;
+        let result = scenario.overpay_checker().process()?;
+
+        let proof = result.receiver_proofs[0].clone();
+        let duplicated = OverpayCheckResult::new(
+            result.payments_root,
+            vec![proof.clone(), proof],
+            result.pay_ids_root,
+        );
+        assert!(duplicated.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_epoch_propagates() -> Result<(), BoxError> {
+        let builder = || ScenarioBuilder::new(9).with_payment(1, 1, 0, 100);
+
+        let round_0 = builder().build()?.overpay_checker().process()?;
+        let round_3 = builder().with_epoch(3).build()?.overpay_checker().process()?;
+        assert_eq!(round_0.epoch, 0);
+        assert_eq!(round_3.epoch, 3);
+        // 轮次不影响承诺本身
+        assert_eq!(round_3.payments_root, round_0.payments_root);
+
+        let sol = crate::OverpayCheckResultStruct::from(round_3);
+        assert_eq!(sol.epoch, 3);
+        assert_eq!(OverpayCheckResult::try_from(sol)?.epoch, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sol_round_trip_preserves_order() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(10)
+            .with_receivers(3)
+            .with_payment(1, 0, 0, 100)
+            .with_payment(1, 1, 1, 100)
+            .with_payment(1, 2, 2, 100)
+            .build()?;
+        let mut sorted = scenario.receivers.clone();
+        sorted.sort();
+
+        let result = scenario.overpay_checker().process()?;
+        assert!(result.is_canonical());
+
+        // 打乱 sol 结构中的顺序，转换回来后应重新规范化
+        let commitment = super::super::ReceiverSetCommitment::from_overpay_result(&result);
+        let mut sol_result: crate::OverpayCheckResultStruct = result.into();
+        assert_eq!(sol_result.receivers_root, commitment.root());
+        assert_eq!(commitment.receivers(), sorted.as_slice());
+        sol_result.receiver_proofs.reverse();
+        let restored = sol_result.to_result()?;
 
-        let sorter = ReceiptsOverpayChecker::new(channel, pay_id_infos, overpaid_payments);
-        assert!(sorter.validate_overpayment().is_err());
+        assert!(restored.is_canonical());
+        let order: Vec<EthAddress> = restored.receiver_proofs.iter().map(|p| p.receiver).collect();
+        assert_eq!(order, sorted);
+        assert_eq!(restored.receiver_index(&sorted[1]), Some(1));
+        assert!(restored.get_merkle_proof([4u8;20]).is_err());
 
         Ok(())
     }
@@ -1,35 +1,121 @@
 use alloy_primitives::{B256, U256};
 use crate::BoxError;
-use crate::models::{PayIdInfo, segment_vc::SegmentVC};
+use crate::models::{u256_to_key, PayIdInfo, TreeHashAlgorithm, segment_vc::{HistoryMode, MerkleProof, SegmentVC}};
 
 pub struct PayIdsProcessor;
 
 impl PayIdsProcessor {
     /// 将PayIdInfo数组转换为SegmentVC并返回根哈希
     /// PayIdInfo按id从小到大排序，以id为key，PayIdInfo的哈希为值创建SegmentVC
+    /// SegmentVC 为 hash-only 模式，可用 lookup_matches 检查某个 PayIdInfo 的哈希
     pub fn create_segment_vc(pay_ids: &[PayIdInfo]) -> Result<(SegmentVC, B256), BoxError> {
-        // 1. 克隆并排序PayIdInfo数组
-        let mut sorted_pay_ids = pay_ids.to_vec();
-        sorted_pay_ids.sort_by(|a, b| a.id.cmp(&b.id));
-
-        // 2. 准备批量插入数据
-        let entries: Vec<(B256, B256)> = sorted_pay_ids
-            .iter()
-            .map(|pay_id| (pay_id.id.into(), pay_id.hash()))
-            .collect();
+        Self::create_segment_vc_with(pay_ids, TreeHashAlgorithm::Keccak)
+    }
 
-        // 3. 创建SegmentVC并批量插入
-        let mut vc = SegmentVC::new(entries.len());
-        let root = vc.insert_batch(entries)?;
+    /// 与 create_segment_vc 相同，但树使用 hasher；叶子仍为 PayIdInfo::hash()
+    pub fn create_segment_vc_with(
+        pay_ids: &[PayIdInfo],
+        hasher: TreeHashAlgorithm,
+    ) -> Result<(SegmentVC, B256), BoxError> {
+        // 1. 按 (id, hash) 排序
+        let sorted_pay_ids = canonical_pay_ids(pay_ids)?;
 
-        Ok((vc, root))
+        // 2. 创建SegmentVC并批量插入
+        build_segment_vc(&sorted_pay_ids, hasher)
     }
 
     /// 只获取根哈希
     pub fn get_root_hash(pay_ids: &[PayIdInfo]) -> Result<B256, BoxError> {
-        let (_, root) = Self::create_segment_vc(pay_ids)?;
+        Self::get_root_hash_with(pay_ids, TreeHashAlgorithm::Keccak)
+    }
+
+    pub fn get_root_hash_with(pay_ids: &[PayIdInfo], hasher: TreeHashAlgorithm) -> Result<B256, BoxError> {
+        let (_, root) = Self::create_segment_vc_with(pay_ids, hasher)?;
         Ok(root)
     }
+
+    /// 创建SegmentVC并为每个PayId生成包含证明，结果按id从小到大排序
+    /// 证明的 value 为 PayIdInfo 的哈希，根与 get_root_hash 相同
+    pub fn create_with_proofs(pay_ids: &[PayIdInfo]) -> Result<(B256, Vec<(U256, MerkleProof)>), BoxError> {
+        Self::create_with_proofs_with(pay_ids, TreeHashAlgorithm::Keccak)
+    }
+
+    pub fn create_with_proofs_with(
+        pay_ids: &[PayIdInfo],
+        hasher: TreeHashAlgorithm,
+    ) -> Result<(B256, Vec<(U256, MerkleProof)>), BoxError> {
+        let sorted_pay_ids = canonical_pay_ids(pay_ids)?;
+        let (vc, root) = build_segment_vc(&sorted_pay_ids, hasher)?;
+
+        // 证明按插入顺序产生，与排序后的 pay_ids 一一对应
+        let mut proofs = Vec::with_capacity(sorted_pay_ids.len());
+        for (&(id, hash), (key, proof_ref)) in sorted_pay_ids.iter().zip(vc.generate_proofs_for_all()?) {
+            if key != u256_to_key(id) {
+                return Err(format!("Proof key {} does not match pay_id {}", key, id).into());
+            }
+            let mut proof = proof_ref.to_owned();
+            proof.value_proof.value = hash;
+            proofs.push((id, proof));
+        }
+        if proofs.len() != sorted_pay_ids.len() {
+            return Err(format!("Generated {} proofs for {} pay_ids", proofs.len(), sorted_pay_ids.len()).into());
+        }
+
+        Ok((root, proofs))
+    }
+
+    /// 验证 PayIdInfo 包含在 pay_ids_root 中：重新计算 info.hash() 并按 Keccak 验证
+    pub fn verify_pay_id(root: B256, info: &PayIdInfo, proof: &MerkleProof) -> Result<bool, BoxError> {
+        Self::verify_pay_id_with(root, info, proof, TreeHashAlgorithm::Keccak)
+    }
+
+    /// 与 verify_pay_id 相同，按 create_with_proofs_with 使用的 hasher 验证，不使用证明中记录的算法
+    pub fn verify_pay_id_with(
+        root: B256,
+        info: &PayIdInfo,
+        proof: &MerkleProof,
+        hasher: TreeHashAlgorithm,
+    ) -> Result<bool, BoxError> {
+        if proof.root_hash != root || proof.value_proof.value != info.hash() {
+            return Ok(false);
+        }
+        proof.verify_with(hasher, false)
+    }
+}
+
+/// 两个 PayIdInfo 的 id 和 hash() 都相同，是同一记录的重复
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatePayIdInfo {
+    pub id: U256,
+}
+
+impl std::fmt::Display for DuplicatePayIdInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Duplicate PayIdInfo with id {}", self.id)
+    }
+}
+
+impl std::error::Error for DuplicatePayIdInfo {}
+
+// 与 canonical_receipt_order 相同的规则：按 id 排序，id 相同时按 hash() 排序，两者都相同为重复。
+// id 相同但内容不同的记录由 SegmentVC 的重复键检查拒绝
+fn canonical_pay_ids(pay_ids: &[PayIdInfo]) -> Result<Vec<(U256, B256)>, DuplicatePayIdInfo> {
+    let mut entries: Vec<(U256, B256)> = pay_ids.iter().map(|info| (info.id, info.hash())).collect();
+    entries.sort_unstable();
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(DuplicatePayIdInfo { id: pair[0].0 });
+    }
+    Ok(entries)
+}
+
+fn build_segment_vc(sorted_pay_ids: &[(U256, B256)], hasher: TreeHashAlgorithm) -> Result<(SegmentVC, B256), BoxError> {
+    let entries: Vec<(B256, B256)> = sorted_pay_ids
+        .iter()
+        .map(|&(id, hash)| (u256_to_key(id), hash))
+        .collect();
+    let mut vc = SegmentVC::builder().hash_only().with_history_mode(HistoryMode::Disabled).with_hasher(hasher).build();
+    let root = vc.insert_batch(entries)?;
+    Ok((vc, root))
 }
 
 #[cfg(test)]
@@ -47,6 +133,8 @@ mod tests {
             state: 1,
             created_at: 1000,
             closing_time: 2000,
+            token: [0u8; 20],
+            sig_sender: None,
         }
     }
 
@@ -62,11 +150,11 @@ mod tests {
         // 创建SegmentVC
         let (vc, root1) = PayIdsProcessor::create_segment_vc(&pay_ids)?;
 
-        // 验证所有PayId都能在VC中找到
+        // 验证所有PayId都能在VC中找到，hash-only 模式下不保留值
         for pay_id in &pay_ids {
-            let h_pay_id = B256::from(pay_id.id.to_be_bytes());
-            let value = vc.get_value(h_pay_id)?;
-            assert_eq!(value, pay_id.hash());
+            let h_pay_id = u256_to_key(pay_id.id);
+            assert!(vc.lookup_matches(h_pay_id, pay_id.hash())?);
+            assert!(vc.get_value(h_pay_id).is_err());
         }
 
         // 验证只获取根哈希的方法
@@ -76,6 +164,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_pay_id_proofs() -> Result<(), BoxError> {
+        let pay_ids: Vec<PayIdInfo> = (1..=20).rev().map(|id| create_test_pay_id(id, id as u64 * 100)).collect();
+
+        let (root, proofs) = PayIdsProcessor::create_with_proofs(&pay_ids)?;
+        assert_eq!(root, PayIdsProcessor::get_root_hash(&pay_ids)?);
+        assert_eq!(proofs.len(), pay_ids.len());
+
+        // 每个证明都能验证，结果按 id 排序
+        for (i, (id, proof)) in proofs.iter().enumerate() {
+            assert_eq!(*id, U256::from(i + 1));
+            let info = pay_ids.iter().find(|info| info.id == *id).unwrap();
+            assert!(PayIdsProcessor::verify_pay_id(root, info, proof)?);
+        }
+
+        // 篡改金额后验证失败
+        let (id, proof) = &proofs[4];
+        let mut tampered = pay_ids.iter().find(|info| info.id == *id).unwrap().clone();
+        tampered.amount += U256::from(1);
+        assert!(!PayIdsProcessor::verify_pay_id(root, &tampered, proof)?);
+
+        // 其他根下的证明不能用于本根
+        let other_pay_ids = vec![create_test_pay_id(5, 500), create_test_pay_id(6, 600)];
+        let (other_root, other_proofs) = PayIdsProcessor::create_with_proofs(&other_pay_ids)?;
+        assert_ne!(other_root, root);
+        let info = &other_pay_ids[0];
+        assert!(PayIdsProcessor::verify_pay_id(other_root, info, &other_proofs[0].1)?);
+        assert!(!PayIdsProcessor::verify_pay_id(root, info, &other_proofs[0].1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_id_proofs_with_sha256() -> Result<(), BoxError> {
+        let pay_ids: Vec<PayIdInfo> = (1..=20).map(|id| create_test_pay_id(id, id as u64 * 100)).collect();
+
+        let (root, proofs) = PayIdsProcessor::create_with_proofs_with(&pay_ids, TreeHashAlgorithm::Sha256)?;
+        assert_eq!(root, PayIdsProcessor::get_root_hash_with(&pay_ids, TreeHashAlgorithm::Sha256)?);
+        assert_ne!(root, PayIdsProcessor::get_root_hash(&pay_ids)?);
+
+        for ((_, proof), info) in proofs.iter().zip(&pay_ids) {
+            assert!(PayIdsProcessor::verify_pay_id_with(root, info, proof, TreeHashAlgorithm::Sha256)?);
+            assert!(!PayIdsProcessor::verify_pay_id(root, info, proof)?);
+            assert!(!proof.verify_with(TreeHashAlgorithm::Keccak, false)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_id_key_byte_order() -> Result<(), BoxError> {
+        // 键为大端序，pay_id 1 对应 0x00..01
+        let pay_ids = vec![create_test_pay_id(1, 100)];
+        let (vc, _) = PayIdsProcessor::create_segment_vc(&pay_ids)?;
+        let mut key = [0u8; 32];
+        key[31] = 1;
+        assert!(vc.lookup_matches(B256::from(key), pay_ids[0].hash())?);
+        Ok(())
+    }
+
     #[test]
     fn test_pay_ids_order() -> Result<(), BoxError> {
         // 创建两组顺序不同但内容相同的数据
@@ -98,4 +246,18 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_duplicate_pay_ids_rejected() {
+        let mut pay_ids = vec![create_test_pay_id(1, 100), create_test_pay_id(2, 200)];
+        pay_ids.push(pay_ids[0].clone());
+        let err = PayIdsProcessor::get_root_hash(&pay_ids).unwrap_err();
+        assert_eq!(err.downcast_ref::<DuplicatePayIdInfo>(), Some(&DuplicatePayIdInfo { id: U256::from(1) }));
+        assert!(PayIdsProcessor::create_with_proofs(&pay_ids).is_err());
+
+        // id 相同但内容不同由 SegmentVC 拒绝
+        pay_ids[2].amount = U256::from(101);
+        let err = PayIdsProcessor::get_root_hash(&pay_ids).unwrap_err();
+        assert!(err.downcast_ref::<DuplicatePayIdInfo>().is_none());
+    }
 }
\ No newline at end of file
@@ -1,22 +1,21 @@
-use alloy_primitives::B256;
-use tiny_keccak::{Keccak,Hasher};
+use alloy_primitives::{B256, U256};
 use std::collections::HashMap;
 use crate::models::segment_vc::MerkleProof;
-use crate::{eth_address_to_B256, BoxError};
+use crate::models::TreeHashAlgorithm;
+use crate::{eth_address_to_B256, BoxError, HashDomain, HashScheme};
 use crate::{
     EthAddress,
-    models::segment_vc::SegmentVC,
+    models::segment_vc::{HistoryMode, SegmentVC},
 };
-use super::{PaymentSettledByProxy, ReceiverProof};
+use super::{canonical_entries, check_unique_keys, AmountOverflow, DuplicateReceipt, PaymentSettledByProxy, ReceiverProof, WithContext};
 
 pub struct PaymentsGrouper;
 
 impl PaymentsGrouper {
-    /// 按receiver分类处理支付记录，创建SegmentVC并返回根哈希和每个receiver的证明
-    pub fn group_by_receiver(
+    /// 按receiver分组，结果按receiver地址排序
+    pub fn group_payments(
         payments: &[PaymentSettledByProxy]
-    ) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
-        // 1. 按receiver分组
+    ) -> Vec<(EthAddress, Vec<PaymentSettledByProxy>)> {
         let mut receiver_groups: HashMap<EthAddress, Vec<PaymentSettledByProxy>> = HashMap::new();
         for payment in payments {
             receiver_groups
@@ -25,63 +24,336 @@ impl PaymentsGrouper {
                 .push(payment.clone());
         }
 
-        // 2. 为每个receiver创建Vec<PaymentSettledByProxy>
-        let mut all_entries = Vec::new();
-        let mut receiver_proofs = Vec::new();
+        let mut groups: Vec<(EthAddress, Vec<PaymentSettledByProxy>)> = receiver_groups.into_iter().collect();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        groups
+    }
 
-        // 按receiver地址排序，确保确定性
-        let mut receivers: Vec<EthAddress> = receiver_groups.keys().cloned().collect();
-        receivers.sort();
+    /// 统计每个receiver的支付总额，结果按receiver地址排序
+    pub fn receiver_totals(
+        payments: &[PaymentSettledByProxy]
+    ) -> Result<Vec<(EthAddress, U256)>, BoxError> {
+        let mut totals = Vec::new();
+        for (receiver, payments) in Self::group_payments(payments) {
+            let mut total = U256::ZERO;
+            for payment in &payments {
+                total = total
+                    .checked_add(payment.amount)
+                    .ok_or(AmountOverflow::Receiver(receiver))?;
+            }
+            totals.push((receiver, total));
+        }
+        Ok(totals)
+    }
 
-        for receiver in &receivers {
-            let payments = &receiver_groups[receiver];
-            
-            // 为每个payment创建key-value对
-            let mut entries: Vec<(B256, B256)> = payments
-                .iter()
-                .map(|payment| {
-                    let key = payment.to_key();
-                    let value = payment_to_hash(payment);
-                    (key, value)
-                })
-                .collect();
-            
-            // 排序确保确定性
-            entries.sort_by(|a, b| a.0.cmp(&b.0));
-            
-            //创建Vec<PaymentSettledByProxy>的哈希
-            let mut hasher = Keccak::v256();
-            for (key,hash_of_payment) in &entries {
-                hasher.update(&hash_of_payment.as_slice());
-               
+    /// 按receiver分类处理支付记录，创建SegmentVC并返回根哈希和每个receiver的证明
+    ///
+    /// 每个receiver的值为 keccak256(按 canonical_receipt_order 排序后各收据 hash() 的拼接)，v2-hashing 下以 GroupHash 标签开头。
+    /// 只对输入的下标排序，不复制收据，也不保存中间的 (key, hash) 对。内容完全相同的收据返回 DuplicateReceipt
+    pub fn group_by_receiver(
+        payments: &[PaymentSettledByProxy]
+    ) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
+        Self::group_by_receiver_with(payments, TreeHashAlgorithm::Keccak)
+    }
+
+    /// 与 group_by_receiver 相同，但分组值和外层树都使用 hasher；收据自身的 hash() 仍为 keccak256
+    pub fn group_by_receiver_with(
+        payments: &[PaymentSettledByProxy],
+        hasher: TreeHashAlgorithm,
+    ) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
+        Self::group_by_receiver_paged(payments, hasher, None)
+    }
+
+    /// 与 group_by_receiver_with 相同；max_receipts_per_page 为 Some 时，每个receiver排序后的收据
+    /// 每 max_receipts_per_page 个分为一页，值为各页组哈希的链（见 paged_group_hash）。
+    /// 收据不超过一页的receiver的值与不分页时相同
+    pub fn group_by_receiver_paged(
+        payments: &[PaymentSettledByProxy],
+        hasher: TreeHashAlgorithm,
+        max_receipts_per_page: Option<usize>,
+    ) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
+        if max_receipts_per_page == Some(0) {
+            return Err("max_receipts_per_page must be positive".into());
+        }
+
+        // 1. 按 receiver 和 canonical_receipt_order 排序下标
+        let order = receiver_order(payments)?;
+
+        // 2. 同一receiver的下标连续，按排序后的顺序逐页流式计算哈希
+        let mut receivers = Vec::new();
+        let mut values = Vec::new();
+        for run in receiver_runs(payments, &order) {
+            let mut value = None;
+            for page in run.chunks(max_receipts_per_page.unwrap_or(run.len())) {
+                let page_hash = page_group_hash(hasher, page.iter().map(|&index| payment_to_hash(&payments[index])));
+                value = Some(chain_page_hash(hasher, value, page_hash));
             }
-            let mut output = [0u8;32];
-            hasher.finalize(&mut output[..]);
-            
 
-            
-            // 添加到总的entries中
-            all_entries.push((eth_address_to_B256(receiver), B256::from_slice(&output)));
+            receivers.push(payments[run[0]].receiver);
+            values.push(value.unwrap_or_default());
+        }
+        drop(order);
+
+        commit_receivers(receivers, values, hasher)
+    }
+
+    /// 把一个receiver的收据按 canonical_receipt_order 排序后每 max_receipts_per_page 个分为一页，
+    /// 与 group_by_receiver_paged 的分页相同，每页分别交给 ReceiptsProfitCalculator::calculate_page
+    pub fn receipt_pages(
+        payments: &[PaymentSettledByProxy],
+        max_receipts_per_page: usize,
+    ) -> Result<Vec<Vec<PaymentSettledByProxy>>, BoxError> {
+        if max_receipts_per_page == 0 {
+            return Err("max_receipts_per_page must be positive".into());
+        }
+        if let Some((index, payment)) = payments.iter().enumerate().find(|(_, payment)| payment.receiver != payments[0].receiver) {
+            let mixed = format!("Receipt pages must belong to one receiver, found {:?}", payment.receiver);
+            return Err(WithContext::at_receipt(index, payment, mixed).to_string().into());
+        }
+
+        let order = receiver_order(payments)?;
+        Ok(order
+            .chunks(max_receipts_per_page)
+            .map(|page| page.iter().map(|&index| payments[index].clone()).collect())
+            .collect())
+    }
+
+    /// 嵌套分组：每个receiver的收据放入自己的 SegmentVC（键为 to_key()，值为 hash()），
+    /// 子树的根作为该receiver在外层树中的值
+    ///
+    /// 与 group_by_receiver 的外层布局相同，只是值不同，因此两种根不能混用。
+    /// 同一receiver下 to_key() 重复的收据在建树之前返回 DuplicateReceipt，给出两个收据的 hash()
+    pub fn group_by_receiver_nested(
+        payments: &[PaymentSettledByProxy]
+    ) -> Result<NestedPaymentGroups, BoxError> {
+        Self::group_by_receiver_nested_with(payments, TreeHashAlgorithm::Keccak)
+    }
+
+    /// 与 group_by_receiver_nested 相同，但子树和外层树都使用 hasher
+    pub fn group_by_receiver_nested_with(
+        payments: &[PaymentSettledByProxy],
+        hasher: TreeHashAlgorithm,
+    ) -> Result<NestedPaymentGroups, BoxError> {
+        let order = receiver_order(payments)?;
+
+        let mut receivers = Vec::new();
+        let mut values = Vec::new();
+        let mut subtrees = HashMap::new();
+        for run in receiver_runs(payments, &order) {
+            let receiver = payments[run[0]].receiver;
+            let subtree = receiver_subtree(run.iter().map(|&index| &payments[index]), hasher)?;
+
+            receivers.push(receiver);
+            values.push(subtree.get_root_hash()?);
+            subtrees.insert(receiver, subtree);
+        }
+
+        let (root, receiver_proofs) = commit_receivers(receivers, values, hasher)?;
+        Ok(NestedPaymentGroups {
+            root,
+            receiver_proofs,
+            subtrees,
+            hasher,
+        })
+    }
+}
+
+/// group_by_receiver_nested 的结果，保存每个receiver的收据子树以便之后生成单个收据的证明
+#[derive(Debug)]
+pub struct NestedPaymentGroups {
+    pub root: B256,
+    pub receiver_proofs: Vec<ReceiverProof>,
+    subtrees: HashMap<EthAddress, SegmentVC>,
+    hasher: TreeHashAlgorithm, // 子树和外层树的树哈希算法
+}
+
+impl NestedPaymentGroups {
+    pub fn receiver_proof(&self, receiver: &EthAddress) -> Option<&ReceiverProof> {
+        self.receiver_proofs.iter().find(|proof| proof.receiver == *receiver)
+    }
+
+    /// 单个收据在receiver子树中的证明，proof.root_hash 即外层证明中的值
+    ///
+    /// 不需要公开该receiver的其他收据，配合 receiver_proof 用 verify_payment_inclusion 验证
+    pub fn payment_inclusion_proof(
+        &self,
+        receiver: &EthAddress,
+        payment: &PaymentSettledByProxy,
+    ) -> Result<MerkleProof, BoxError> {
+        if payment.receiver != *receiver {
+            return Err(format!("Payment receiver {:?} does not match {:?}", payment.receiver, receiver).into());
+        }
+        let subtree = self
+            .subtrees
+            .get(receiver)
+            .ok_or_else(|| format!("Receiver {:?} not in payment groups", receiver))?;
+
+        let mut proof = subtree.generate_proof(payment.to_key())?;
+        proof.value_proof.value = payment_to_hash(payment);
+        // key 相同但内容不同的收据不在子树中
+        if !proof.verify_with(self.hasher, false)? {
+            return Err("Payment not included in receiver subtree".into());
         }
+        Ok(proof)
+    }
+}
+
+/// 用两段证明验证单个收据包含在 payments_root 中：
+/// payment_proof 证明收据在receiver子树中，receiver_proof 证明子树的根在外层树中
+pub fn verify_payment_inclusion(
+    payments_root: B256,
+    receiver_proof: &ReceiverProof,
+    payment_proof: &MerkleProof,
+    payment: &PaymentSettledByProxy,
+) -> Result<bool, BoxError> {
+    verify_payment_inclusion_with(payments_root, receiver_proof, payment_proof, payment, TreeHashAlgorithm::Keccak)
+}
+
+/// 与 verify_payment_inclusion 相同，两段证明都按 group_by_receiver_nested_with 使用的 hasher 验证
+pub fn verify_payment_inclusion_with(
+    payments_root: B256,
+    receiver_proof: &ReceiverProof,
+    payment_proof: &MerkleProof,
+    payment: &PaymentSettledByProxy,
+    hasher: TreeHashAlgorithm,
+) -> Result<bool, BoxError> {
+    if payment.receiver != receiver_proof.receiver
+        || payment_proof.value_proof.value != payment_to_hash(payment)
+        || payment_proof.root_hash != receiver_proof.proof.value_proof.value
+        || receiver_proof.proof.root_hash != payments_root
+    {
+        return Ok(false);
+    }
+    Ok(payment_proof.verify_with(hasher)? && receiver_proof.verify_with(payments_root, hasher)?)
+}
 
-        // 3. 创建总的SegmentVC
-        let mut vc = SegmentVC::new(all_entries.len());
-        let root = vc.insert_batch(all_entries)?;
+/// 一个receiver的收据子树，按 canonical_receipt_order 排序插入使用 hasher 的 hash-only SegmentVC
+pub(crate) fn receiver_subtree<'a>(
+    payments: impl IntoIterator<Item = &'a PaymentSettledByProxy>,
+    hasher: TreeHashAlgorithm,
+) -> Result<SegmentVC, BoxError> {
+    let entries = canonical_entries(payments)?;
+    // key 冲突在插入前报告，避免 insert_batch 返回没有上下文的 KeyExists
+    check_unique_keys(&entries)?;
 
-        // 4. 为每个receiver创建证明
-        for receiver in receivers {
-            let receiver_hash = eth_address_to_B256(&receiver);
-            let proof = vc.generate_proof(receiver_hash)?;
-            receiver_proofs.push(ReceiverProof {
-                receiver,
-                proof,
-            });
+    let mut vc = SegmentVC::builder().hash_only().with_history_mode(HistoryMode::Disabled).with_hasher(hasher).build();
+    vc.insert_batch(entries)?;
+    Ok(vc)
+}
+
+// 每个收据的 key 只计算一次，按 receiver 再按 canonical_receipt_order 排序下标；
+// hash() 只在 key 相同时计算，相同时为重复收据，报告两者中在输入里靠后的下标
+fn receiver_order(payments: &[PaymentSettledByProxy]) -> Result<Vec<usize>, WithContext<DuplicateReceipt>> {
+    let keys: Vec<B256> = payments.iter().map(|payment| payment.to_key()).collect();
+    let mut order: Vec<usize> = (0..payments.len()).collect();
+    order.sort_unstable_by(|&a, &b| {
+        payments[a]
+            .receiver
+            .cmp(&payments[b].receiver)
+            .then_with(|| keys[a].cmp(&keys[b]))
+            .then_with(|| payment_to_hash(&payments[a]).cmp(&payment_to_hash(&payments[b])))
+    });
+
+    for pair in order.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if payments[a].receiver == payments[b].receiver
+            && keys[a] == keys[b]
+            && payment_to_hash(&payments[a]) == payment_to_hash(&payments[b])
+        {
+            let hash = payment_to_hash(&payments[a]);
+            let index = a.max(b);
+            return Err(WithContext::at_receipt(index, &payments[index], DuplicateReceipt { key: keys[a], first: hash, second: hash }));
         }
+    }
+    Ok(order)
+}
+
+// 排序后同一receiver的下标连续，逐段返回
+fn receiver_runs<'a>(
+    payments: &'a [PaymentSettledByProxy],
+    order: &'a [usize],
+) -> impl Iterator<Item = &'a [usize]> + 'a {
+    order.chunk_by(move |&a, &b| payments[a].receiver == payments[b].receiver)
+}
 
-        Ok((root, receiver_proofs.clone()))
+/// 分页的receiver组值：每页按 canonical_receipt_order 排序后计算与 group_by_receiver 相同的组哈希 h_i，
+/// 再按页的顺序链接：value = h_0，value = keccak256(value ‖ h_i)
+///
+/// 只有一页时等于不分页的组值。不检查重复收据，重复由 PaymentsGrouper 和 ReceiptsProfitCalculator 拒绝
+pub fn paged_group_hash(pages: &[&[PaymentSettledByProxy]]) -> B256 {
+    let hasher = TreeHashAlgorithm::Keccak;
+    let mut value = None;
+    for page in pages {
+        let mut entries: Vec<(B256, B256)> = page
+            .iter()
+            .map(|payment| (payment.to_key(), payment_to_hash(payment)))
+            .collect();
+        entries.sort_unstable();
+        let page_hash = page_group_hash(hasher, entries.into_iter().map(|(_, hash)| hash));
+        value = Some(chain_page_hash(hasher, value, page_hash));
     }
+    value.unwrap_or_else(|| page_group_hash(hasher, core::iter::empty()))
 }
 
+// 一页收据的组哈希，hashes 已按 canonical_receipt_order 排列；v2-hashing 下以 GroupHash 标签开头
+pub(crate) fn page_group_hash(hasher: TreeHashAlgorithm, hashes: impl IntoIterator<Item = B256>) -> B256 {
+    let mut state = hasher.start();
+    if let Some(tag) = HashScheme::ACTIVE.tag(HashDomain::GroupHash) {
+        state.update(&[tag]);
+    }
+    for hash in hashes {
+        state.update(hash.as_slice());
+    }
+    state.finalize()
+}
+
+// 页哈希链的一步，第一页的链值就是它的页哈希
+pub(crate) fn chain_page_hash(hasher: TreeHashAlgorithm, previous: Option<B256>, page_hash: B256) -> B256 {
+    match previous {
+        None => page_hash,
+        Some(previous) => {
+            let mut state = hasher.start();
+            state.update(previous.as_slice());
+            state.update(page_hash.as_slice());
+            state.finalize()
+        }
+    }
+}
+
+// 创建总的SegmentVC，只保留哈希；证明按插入顺序产生，与receivers一一对应
+fn commit_receivers(
+    receivers: Vec<EthAddress>,
+    values: Vec<B256>,
+    hasher: TreeHashAlgorithm,
+) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
+    let all_entries: Vec<(B256, B256)> = receivers
+        .iter()
+        .map(eth_address_to_B256)
+        .zip(values.iter().copied())
+        .collect();
+    let mut vc = SegmentVC::builder().hash_only().with_history_mode(HistoryMode::Disabled).with_hasher(hasher).build();
+    let root = vc.insert_batch(all_entries)?;
+
+    let expected = receivers.len();
+    let mut receiver_proofs = Vec::with_capacity(expected);
+    let proofs = vc.generate_proofs_for_all()?;
+    for ((receiver, value), (key, proof_ref)) in receivers.into_iter().zip(values).zip(proofs) {
+        if key != eth_address_to_B256(&receiver) {
+            return Err(format!("Proof key {} does not match receiver {:?}", key, receiver).into());
+        }
+        let mut proof = proof_ref.to_owned();
+        proof.value_proof.value = value;
+        receiver_proofs.push(ReceiverProof {
+            receiver,
+            proof,
+        });
+    }
+    if receiver_proofs.len() != expected {
+        return Err(format!("Generated {} receiver proofs for {} receivers", receiver_proofs.len(), expected).into());
+    }
+
+    Ok((root, receiver_proofs))
+}
 
 
 // 辅助函数：将payment转换为hash值
@@ -94,7 +366,8 @@ fn payment_to_hash(payment: &PaymentSettledByProxy) -> B256 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use alloy_primitives::{B256, U256};
+    use crate::receipts::profit_calculator::validate_receipts_proof;
+    use tiny_keccak::Hasher;
 
     fn create_test_payment(
         pay_id: u64,
@@ -102,15 +375,10 @@ mod tests {
         receiver: EthAddress,
         amount: u64,
     ) -> PaymentSettledByProxy {
-        PaymentSettledByProxy {
-            pay_id: U256::from(pay_id),
-            serv_id,
-            receiver,
-            amount: U256::from(amount),
-            settled: true,
-            sig_sender: [1u8;65],
-            sig_proxy: [2u8;65],
-        }
+        PaymentSettledByProxy::new(U256::from(pay_id), serv_id, U256::from(amount), receiver)
+            .with_settled(true)
+            .with_sig_sender([1u8;65])
+            .with_sig_proxy([2u8;65])
     }
 
     #[test]
@@ -167,4 +435,424 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_receiver_totals_overflow() {
+        let receiver1 = [1u8;20];
+        let receiver2 = [2u8;20];
+
+        let mut payments = vec![
+            create_test_payment(1, 1, receiver1, 100),
+            create_test_payment(1, 1, receiver2, 0),
+            create_test_payment(2, 1, receiver2, 0),
+        ];
+        payments[1].amount = U256::MAX;
+        payments[2].amount = U256::MAX;
+
+        let err = PaymentsGrouper::receiver_totals(&payments).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AmountOverflow>(),
+            Some(&AmountOverflow::Receiver(receiver2))
+        );
+    }
+
+    #[test]
+    fn test_receiver_totals_never_wrap() {
+        // 简单的线性同余生成器，保证用例可复现
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            seed
+        };
+
+        for _ in 0..200 {
+            let count = (next() % 6) as usize + 1;
+            let payments: Vec<PaymentSettledByProxy> = (0..count)
+                .map(|i| {
+                    let receiver = [(next() % 3) as u8; 20];
+                    let mut payment = create_test_payment(i as u64, 1, receiver, 0);
+                    // 金额分布在 U256::MAX 附近和小额之间
+                    payment.amount = if next() % 2 == 0 {
+                        U256::MAX >> (next() % 4) as usize
+                    } else {
+                        U256::from(next())
+                    };
+                    payment
+                })
+                .collect();
+
+            // 参考实现：用 overflowing_add 判断每个receiver是否溢出
+            let mut overflowed = false;
+            let mut expected = Vec::new();
+            for (receiver, group) in PaymentsGrouper::group_payments(&payments) {
+                let mut total = U256::ZERO;
+                for payment in &group {
+                    let (sum, carry) = total.overflowing_add(payment.amount);
+                    total = sum;
+                    overflowed |= carry;
+                }
+                expected.push((receiver, total));
+            }
+
+            match PaymentsGrouper::receiver_totals(&payments) {
+                Ok(totals) => {
+                    assert!(!overflowed);
+                    assert_eq!(totals, expected);
+                }
+                Err(err) => {
+                    assert!(overflowed);
+                    assert!(err.downcast_ref::<AmountOverflow>().is_some());
+                }
+            }
+        }
+    }
+
+    // 改为下标排序之前的实现，用于锁定根和证明不变
+    fn legacy_group_by_receiver(
+        payments: &[PaymentSettledByProxy]
+    ) -> Result<(B256, Vec<ReceiverProof>), BoxError> {
+        let receiver_groups = PaymentsGrouper::group_payments(payments);
+        let mut all_entries = Vec::new();
+        for (receiver, payments) in &receiver_groups {
+            let mut entries: Vec<(B256, B256)> = payments
+                .iter()
+                .map(|payment| (payment.to_key(), payment_to_hash(payment)))
+                .collect();
+            // key 相同时按 hash() 排序，与 canonical_receipt_order 一致
+            entries.sort();
+            let mut hasher = HashScheme::ACTIVE.hasher(HashDomain::GroupHash);
+            for (_, hash_of_payment) in &entries {
+                hasher.update(hash_of_payment.as_slice());
+            }
+            let mut output = [0u8; 32];
+            hasher.finalize(&mut output[..]);
+            all_entries.push((eth_address_to_B256(receiver), B256::from_slice(&output)));
+        }
+
+        let mut vc = SegmentVC::builder().hash_only().with_history_mode(HistoryMode::Disabled).build();
+        let root = vc.insert_batch(all_entries.clone())?;
+        let mut receiver_proofs = Vec::new();
+        for (((receiver, _), (_, value)), (_, proof_ref)) in
+            receiver_groups.into_iter().zip(all_entries).zip(vc.generate_proofs_for_all()?)
+        {
+            let mut proof = proof_ref.to_owned();
+            proof.value_proof.value = value;
+            receiver_proofs.push(ReceiverProof { receiver, proof });
+        }
+        Ok((root, receiver_proofs))
+    }
+
+    #[test]
+    fn test_equivalent_to_legacy_grouping() -> Result<(), BoxError> {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            seed >> 16
+        };
+
+        for _ in 0..100 {
+            let count = (next() % 60) as usize + 1;
+            let payments: Vec<PaymentSettledByProxy> = (0..count)
+                .map(|i| {
+                    let receiver = [(next() % 20) as u8; 20];
+                    // pay_id 和 serv_id 取值较少，会出现 key 相同的收据；金额包含下标，不会出现完全相同的收据
+                    let amount = next() % 1000 * 100 + i as u64;
+                    let mut payment = create_test_payment(next() % 4, (next() % 3) as u32, receiver, amount);
+                    if next() % 3 == 0 {
+                        payment.nonce = Some(next() % 5);
+                    }
+                    payment
+                })
+                .collect();
+
+            assert_eq!(
+                PaymentsGrouper::group_by_receiver(&payments)?,
+                legacy_group_by_receiver(&payments)?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_grouping() -> Result<(), BoxError> {
+        let payments: Vec<PaymentSettledByProxy> = (0..40u64)
+            .map(|i| create_test_payment(i, 1, [(i % 20) as u8 + 1; 20], 100 + i))
+            .collect();
+
+        let (keccak_root, keccak_proofs) = PaymentsGrouper::group_by_receiver(&payments)?;
+        let (sha_root, sha_proofs) = PaymentsGrouper::group_by_receiver_with(&payments, TreeHashAlgorithm::Sha256)?;
+        assert_ne!(sha_root, keccak_root);
+        assert_eq!(
+            PaymentsGrouper::group_by_receiver_with(&payments, TreeHashAlgorithm::Keccak)?.0,
+            keccak_root
+        );
+
+        for (keccak_proof, sha_proof) in keccak_proofs.iter().zip(&sha_proofs) {
+            assert_eq!(sha_proof.proof.hasher, TreeHashAlgorithm::Sha256);
+            assert!(sha_proof.verify_with(sha_root, TreeHashAlgorithm::Sha256)?);
+            assert!(!sha_proof.verify(sha_root)?);
+            assert!(!sha_proof.proof.verify_with(TreeHashAlgorithm::Keccak, false)?);
+            assert!(!keccak_proof.proof.verify_with(TreeHashAlgorithm::Sha256, false)?);
+
+            // 组值和路径都按验证方给出的 hasher 计算
+            let group: Vec<PaymentSettledByProxy> =
+                payments.iter().filter(|payment| payment.receiver == sha_proof.receiver).cloned().collect();
+            assert!(validate_receipts_proof(&group, &sha_proof.proof, TreeHashAlgorithm::Sha256).is_ok());
+            assert!(validate_receipts_proof(&group, &sha_proof.proof, TreeHashAlgorithm::Keccak).is_err());
+        }
+
+        // 嵌套分组的子树和外层树同样使用 hasher
+        let nested = PaymentsGrouper::group_by_receiver_nested_with(&payments, TreeHashAlgorithm::Sha256)?;
+        assert_ne!(nested.root, PaymentsGrouper::group_by_receiver_nested(&payments)?.root);
+        let receiver_proof = nested.receiver_proof(&payments[0].receiver).ok_or("missing receiver")?;
+        let payment_proof = nested.payment_inclusion_proof(&payments[0].receiver, &payments[0])?;
+        assert!(verify_payment_inclusion_with(
+            nested.root,
+            receiver_proof,
+            &payment_proof,
+            &payments[0],
+            TreeHashAlgorithm::Sha256
+        )?);
+        assert!(!verify_payment_inclusion(nested.root, receiver_proof, &payment_proof, &payments[0])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_paged_grouping() -> Result<(), BoxError> {
+        let receiver1 = [1u8; 20];
+        let receiver2 = [2u8; 20];
+        let mut payments: Vec<PaymentSettledByProxy> = (0..7u64)
+            .map(|i| create_test_payment(i, 1, receiver1, 100 + i))
+            .collect();
+        payments.push(create_test_payment(1, 2, receiver2, 50));
+        payments.push(create_test_payment(2, 2, receiver2, 60));
+
+        // 页足够大时与不分页相同
+        let unpaged = PaymentsGrouper::group_by_receiver(&payments)?;
+        assert_eq!(
+            PaymentsGrouper::group_by_receiver_paged(&payments, TreeHashAlgorithm::Keccak, Some(7))?,
+            unpaged
+        );
+
+        // 每页 3 个：receiver1 分为 3 页，receiver2 只有一页，值不变
+        let (root, proofs) = PaymentsGrouper::group_by_receiver_paged(&payments, TreeHashAlgorithm::Keccak, Some(3))?;
+        assert_ne!(root, unpaged.0);
+        let receiver1_receipts: Vec<PaymentSettledByProxy> =
+            payments.iter().filter(|p| p.receiver == receiver1).cloned().collect();
+        let pages = PaymentsGrouper::receipt_pages(&receiver1_receipts, 3)?;
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 3, 1]);
+        let page_refs: Vec<&[PaymentSettledByProxy]> = pages.iter().map(Vec::as_slice).collect();
+        assert_eq!(proofs[0].proof.value_proof.value, paged_group_hash(&page_refs));
+        assert_eq!(proofs[1].proof.value_proof.value, unpaged.1[1].proof.value_proof.value);
+        assert_eq!(paged_group_hash(&[&receiver1_receipts]), unpaged.1[0].proof.value_proof.value);
+        for proof in &proofs {
+            assert!(proof.verify(root)?);
+        }
+
+        assert!(PaymentsGrouper::group_by_receiver_paged(&payments, TreeHashAlgorithm::Keccak, Some(0)).is_err());
+        assert!(PaymentsGrouper::receipt_pages(&payments, 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_grouping_allocations() -> Result<(), BoxError> {
+        let receivers = 10u64;
+        let payments: Vec<PaymentSettledByProxy> = (0..100_000u64)
+            .map(|i| create_test_payment(i / receivers, 1, [(i % receivers) as u8 + 1; 20], i))
+            .collect();
+
+        // 基准：每个收据计算一次 hash() 本身的分配
+        let before = crate::alloc_counter::allocations();
+        for payment in &payments {
+            payment.hash();
+        }
+        let hashing = crate::alloc_counter::allocations() - before;
+
+        // 分组只在哈希之外增加与receiver数量相关的常数次分配
+        let before = crate::alloc_counter::allocations();
+        let (_, proofs) = PaymentsGrouper::group_by_receiver(&payments)?;
+        let grouping = crate::alloc_counter::allocations() - before;
+
+        assert_eq!(proofs.len(), receivers as usize);
+        assert!(
+            grouping <= hashing + 64 * receivers as usize,
+            "grouping allocated {} times, hashing alone {}",
+            grouping,
+            hashing
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_payment_chained_proofs() -> Result<(), BoxError> {
+        let receiver1 = [1u8;20];
+        let receiver2 = [2u8;20];
+        let payments = vec![
+            create_test_payment(1, 1, receiver1, 100),
+            create_test_payment(2, 1, receiver2, 300),
+            create_test_payment(1, 2, receiver1, 200),
+            create_test_payment(3, 1, receiver1, 50),
+            create_test_payment(2, 2, receiver2, 400),
+        ];
+
+        let groups = PaymentsGrouper::group_by_receiver_nested(&payments)?;
+        assert_eq!(groups.receiver_proofs.len(), 2);
+
+        // 每个收据都可以只凭自己和两段证明验证包含在总根中
+        for payment in &payments {
+            let receiver_proof = groups.receiver_proof(&payment.receiver).ok_or("Receiver proof not found")?;
+            let payment_proof = groups.payment_inclusion_proof(&payment.receiver, payment)?;
+            assert_eq!(payment_proof.root_hash, receiver_proof.proof.value_proof.value);
+            assert!(verify_payment_inclusion(groups.root, receiver_proof, &payment_proof, payment)?);
+        }
+
+        // 子树的根与收据的输入顺序无关
+        let mut shuffled = payments.clone();
+        shuffled.reverse();
+        assert_eq!(PaymentsGrouper::group_by_receiver_nested(&shuffled)?.root, groups.root);
+
+        // 外层布局与扁平分组相同但值不同，两种根不会相等
+        let (flat_root, _) = PaymentsGrouper::group_by_receiver(&payments)?;
+        assert_ne!(flat_root, groups.root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chained_proofs_reject_tampering() -> Result<(), BoxError> {
+        let receiver1 = [1u8;20];
+        let receiver2 = [2u8;20];
+        let payments = vec![
+            create_test_payment(1, 1, receiver1, 100),
+            create_test_payment(1, 2, receiver1, 200),
+            create_test_payment(2, 1, receiver2, 300),
+        ];
+        let groups = PaymentsGrouper::group_by_receiver_nested(&payments)?;
+        let receiver_proof = groups.receiver_proof(&receiver1).ok_or("Receiver proof not found")?;
+        let payment_proof = groups.payment_inclusion_proof(&receiver1, &payments[0])?;
+
+        // 修改金额后哈希不同
+        let mut altered = payments[0].clone();
+        altered.amount = U256::from(101);
+        assert!(!verify_payment_inclusion(groups.root, receiver_proof, &payment_proof, &altered)?);
+        assert!(groups.payment_inclusion_proof(&receiver1, &altered).is_err());
+
+        // 用其他receiver的外层证明
+        let other_proof = groups.receiver_proof(&receiver2).ok_or("Receiver proof not found")?;
+        assert!(!verify_payment_inclusion(groups.root, other_proof, &payment_proof, &payments[0])?);
+        assert!(groups.payment_inclusion_proof(&receiver2, &payments[0]).is_err());
+
+        // 错误的总根
+        assert!(!verify_payment_inclusion(B256::repeat_byte(1), receiver_proof, &payment_proof, &payments[0])?);
+
+        // 不在分组中的收据
+        let outsider = create_test_payment(9, 1, receiver1, 100);
+        assert!(groups.payment_inclusion_proof(&receiver1, &outsider).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_duplicate_keys_rejected() {
+        let receiver1 = [1u8;20];
+        let payments = vec![
+            create_test_payment(1, 1, receiver1, 100),
+            create_test_payment(1, 1, receiver1, 200),
+        ];
+        let err = PaymentsGrouper::group_by_receiver_nested(&payments).unwrap_err();
+        let mut hashes = [payments[0].hash(), payments[1].hash()];
+        hashes.sort();
+        let expected = DuplicateReceipt { key: payments[0].to_key(), first: hashes[0], second: hashes[1] };
+        assert_eq!(err.downcast_ref::<DuplicateReceipt>(), Some(&expected));
+        assert!(err.to_string().contains(&hashes[0].to_string()) && err.to_string().contains(&hashes[1].to_string()));
+        // 扁平分组允许 key 相同的收据
+        assert!(PaymentsGrouper::group_by_receiver(&payments).is_ok());
+    }
+
+    #[test]
+    fn test_key_ties_broken_by_hash() -> Result<(), BoxError> {
+        let receiver1 = [1u8;20];
+        let mut payments = vec![
+            create_test_payment(1, 1, receiver1, 100),
+            create_test_payment(1, 1, receiver1, 200),
+            create_test_payment(2, 1, receiver1, 300),
+        ];
+        let (root, _) = PaymentsGrouper::group_by_receiver(&payments)?;
+        payments.swap(0, 1);
+        assert_eq!(PaymentsGrouper::group_by_receiver(&payments)?.0, root);
+
+        // 完全相同的收据在两种分组中都报告为 DuplicateReceipt
+        payments.push(payments[2].clone());
+        let hash = payments[2].hash();
+        let expected = DuplicateReceipt { key: payments[2].to_key(), first: hash, second: hash };
+        for err in [
+            PaymentsGrouper::group_by_receiver(&payments).unwrap_err(),
+            PaymentsGrouper::group_by_receiver_nested(&payments).unwrap_err(),
+        ] {
+            let duplicate = err.downcast_ref::<WithContext<DuplicateReceipt>>().ok_or("Expected DuplicateReceipt")?;
+            assert_eq!((duplicate.index, &duplicate.inner), (Some(3), &expected));
+            assert!(err.to_string().starts_with("receipt #3 "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use crate::receipts::profit_calculator::validate_receipts_proof;
+    use proptest::prelude::*;
+
+    // receiver、pay_id、serv_id 取值较少，会出现同一receiver下 key 相同的收据；
+    // 下标作为金额的一部分，保证没有完全相同的收据
+    fn payments_strategy() -> impl Strategy<Value = Vec<PaymentSettledByProxy>> {
+        proptest::collection::vec((0u8..4, 0u64..4, 0u32..3, 0u64..1000, proptest::option::of(0u64..3)), 1..40)
+            .prop_map(|specs| {
+                specs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (receiver, pay_id, serv_id, amount, nonce))| {
+                        let mut payment = PaymentSettledByProxy::new(
+                            U256::from(pay_id),
+                            serv_id,
+                            U256::from(amount * 100 + i as u64),
+                            [receiver + 1; 20],
+                        )
+                        .with_settled(true);
+                        payment.nonce = nonce;
+                        payment
+                    })
+                    .collect()
+            })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn shuffling_never_changes_roots(
+            (payments, shuffled) in payments_strategy()
+                .prop_flat_map(|payments| (Just(payments.clone()), Just(payments).prop_shuffle()))
+        ) {
+            let (root, proofs) = PaymentsGrouper::group_by_receiver(&payments).unwrap();
+            prop_assert_eq!(PaymentsGrouper::group_by_receiver(&shuffled).unwrap(), (root, proofs.clone()));
+            prop_assert_eq!(
+                PaymentsGrouper::group_by_receiver_with(&payments, TreeHashAlgorithm::Sha256).unwrap().0,
+                PaymentsGrouper::group_by_receiver_with(&shuffled, TreeHashAlgorithm::Sha256).unwrap().0
+            );
+            // 嵌套分组在 key 重复时报错，两种顺序的结果一致
+            match (
+                PaymentsGrouper::group_by_receiver_nested(&payments),
+                PaymentsGrouper::group_by_receiver_nested(&shuffled),
+            ) {
+                (Ok(a), Ok(b)) => prop_assert_eq!(a.root, b.root),
+                (a, b) => prop_assert!(a.is_err() && b.is_err()),
+            }
+
+            // 打乱后的收据仍能通过原顺序生成的证明
+            for proof in &proofs {
+                let group: Vec<PaymentSettledByProxy> =
+                    shuffled.iter().filter(|p| p.receiver == proof.receiver).cloned().collect();
+                prop_assert!(validate_receipts_proof(&group, &proof.proof, TreeHashAlgorithm::Keccak).is_ok());
+            }
+        }
+    }
+}
@@ -1,10 +1,12 @@
-use super::{EthAddress, PaymentSettledByProxy};
-use crate::ethaddr_gen::EthAddressGen;
+use super::payment_grouper::{chain_page_hash, page_group_hash, receiver_subtree};
+use super::{canonical_entries, check_partial_settlement, check_receipt_expiry, DustPolicy, InvalidReceiptSignature, WithContext, EthAddress, PayIdsProcessor, PaymentSettledByProxy, SigningDomain};
 use crate::{
-    get_ethereum_address,
-    models::{segment_vc::MerkleProof, PayIdInfo, ServiceFeeConfig},
+    models::{segment_vc::MerkleProof, PayIdInfo, ServiceFeeConfig, ServiceFeeRegistry, TreeHashAlgorithm},
     BoxError,
 };
+use crate::metrics::{measure, record, Metrics, NoopMetrics, PIPELINE_OPS, RECEIPTS_PROCESSED};
+use super::sealed::{SealedReceipt, SealedSigners};
+use super::disclosure::DisclosureProof;
 /**
  * @fileoverview added by tsickle
  * @promotion
@@ -18,6 +20,7 @@ use crate::{
  *  所有的收据以to_key()的结果排序
  *  对所有的哈希全部再哈希一次，得到hash_of_all_payment
  *  hash_of_all_payment必须能够通过默克尔证明
+ *  嵌套模式（with_nested_receipts）下改为所有收据以to_key()为键、hash()为值构建子树，子树的根必须能够通过默克尔证明
  * 3. 收据中所有的接收者都是自己
  * 4. 针对每个收据，验证sig_sender,sig_proxy的有效性，以及sig_proxy必须由代理地址签发，sig_sender必须与PayIdInfos中的Sender一致
  *
@@ -35,22 +38,32 @@ use crate::{
  *
  */
 use alloy_primitives::{B256, U256};
-use sha3::{Digest, Keccak256};
 use std::collections::HashMap;
 
-use crate::ProfitResult;
+use crate::{CommitmentVersion, ProfitResult, TokenSubtotal, NATIVE_TOKEN};
 
 pub struct ReceiptsProfitCalculator {
+    vks_hash: B256,
     receiver: EthAddress,
     proxy: EthAddress,
     receipts: Vec<PaymentSettledByProxy>,
     merkle_proof: MerkleProof,
     pay_id_infos: Vec<PayIdInfo>,
     service_configs: Vec<ServiceFeeConfig>,
+    dust_policy: DustPolicy,
+    signing_domain: Option<SigningDomain>,
+    epoch: u64,
+    nested_receipts: bool,
+    current_time: Option<u64>,
+    sealed: SealedSigners, // from_sealed 时封存的签名者
+    require_pay_id_authorization: bool,
+    tree_hasher: TreeHashAlgorithm, // 生成 merkle_proof 的树使用的算法
 }
 
 impl ReceiptsProfitCalculator {
+    /// vks_hash 为 guest 程序的验证密钥哈希，测试中可传入 B256::ZERO
     pub fn new(
+        vks_hash: B256,
         receiver: EthAddress,
         proxy: EthAddress,
         receipts: Vec<PaymentSettledByProxy>,
@@ -59,28 +72,147 @@ impl ReceiptsProfitCalculator {
         service_configs: Vec<ServiceFeeConfig>,
     ) -> Self {
         Self {
+            vks_hash,
             receiver,
             proxy,
             receipts,
             merkle_proof,
             pay_id_infos,
             service_configs,
+            dust_policy: DustPolicy::default(),
+            signing_domain: None,
+            epoch: 0,
+            nested_receipts: false,
+            current_time: None,
+            sealed: SealedSigners::default(),
+            require_pay_id_authorization: false,
+            tree_hasher: TreeHashAlgorithm::Keccak,
         }
     }
 
+    /// 从已封存的收据构造，验证签名时信任封存时恢复的签名者，只比较地址
+    pub fn from_sealed(
+        vks_hash: B256,
+        receiver: EthAddress,
+        proxy: EthAddress,
+        receipts: &[SealedReceipt],
+        merkle_proof: MerkleProof,
+        pay_id_infos: Vec<PayIdInfo>,
+        service_configs: Vec<ServiceFeeConfig>,
+    ) -> Self {
+        let plain = receipts.iter().map(|sealed| sealed.receipt().clone()).collect();
+        Self {
+            sealed: SealedSigners::new(receipts),
+            ..Self::new(vks_hash, receiver, proxy, plain, merkle_proof, pay_id_infos, service_configs)
+        }
+    }
+
+    /// 设置结算轮次，必须与 ReceiptsOverpayChecker 使用的轮次一致，默认为 0
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// 证明来自 PaymentsGrouper::group_by_receiver_nested：
+    /// 证明中的值为收据子树的根，而不是所有收据哈希的拼接哈希
+    pub fn with_nested_receipts(mut self) -> Self {
+        self.nested_receipts = true;
+        self
+    }
+
+    /// 设置分组时使用的树哈希算法，验证 merkle_proof 和计算收据的组值时使用，默认为 Keccak；
+    /// 不使用证明中记录的 hasher
+    pub fn with_tree_hasher(mut self, hasher: TreeHashAlgorithm) -> Self {
+        self.tree_hasher = hasher;
+        self
+    }
+
+    /// 设置结算时间，存在已过期的收据时拒绝计算（ReceiptExpired），
+    /// 应与 ReceiptsOverpayChecker 使用的时间一致
+    pub fn with_current_time(mut self, current_time: u64) -> Self {
+        self.current_time = Some(current_time);
+        self
+    }
+
+    /// 设置签名域，验证收据签名和 PayIdInfo 授权时使用带域的恢复
+    pub fn with_signing_domain(mut self, domain: SigningDomain) -> Self {
+        self.signing_domain = Some(domain);
+        self
+    }
+
+    /// 要求每个 PayIdInfo 带有 sender 的签名，与 ReceiptsOverpayChecker::with_pay_id_authorization 相同
+    pub fn with_pay_id_authorization(mut self) -> Self {
+        self.require_pay_id_authorization = true;
+        self
+    }
+
+    /// 设置 dust 策略，必须与生成默克尔证明时 ReceiptsOverpayChecker 使用的策略一致
+    pub fn with_dust_policy(mut self, dust_policy: DustPolicy) -> Self {
+        dust_policy.filter(&mut self.receipts);
+        self.dust_policy = dust_policy;
+        self
+    }
+
     pub fn calculate(&self) -> Result<ProfitResult, BoxError> {
+        self.calculate_with_metrics(&NoopMetrics)
+    }
+
+    /// 计算结果并附上接收者的默克尔证明，用于不交出收据时出示利润总额；利润数额的可信度见 DisclosureProof
+    pub fn disclosure_proof(&self) -> Result<DisclosureProof, BoxError> {
+        Ok(DisclosureProof::new(self.calculate()?, self.merkle_proof.clone()))
+    }
+
+    /// 与 calculate 相同，开启 profiling 时把收据数、签名恢复、keccak 调用和树节点哈希次数写入 metrics
+    pub fn calculate_with_metrics(&self, metrics: &dyn Metrics) -> Result<ProfitResult, BoxError> {
+        let result = measure(metrics, &PIPELINE_OPS, || self.compute());
+        record(metrics, RECEIPTS_PROCESSED, self.receipts.len() as u64);
+        result
+    }
+
+    /// 分页模式：self 的收据是 PaymentsGrouper::receipt_pages 的一页，merkle_proof 来自
+    /// group_by_receiver_paged，previous_hash 为前一页结果的 running_hash（第一页为 None）
+    ///
+    /// 只验证这一页的收据和证明路径；证明中的值在 combine_partial_results 中与最后一页的链值比较
+    pub fn calculate_page(&self, previous_hash: Option<B256>) -> Result<PartialProfitResult, BoxError> {
+        if self.nested_receipts {
+            return Err("Nested receipts proofs cannot be paged".into());
+        }
+
+        let mut running_hash = B256::ZERO;
+        self.validate_prerequisites(|| {
+            running_hash = validate_receipt_page(&self.receipts, &self.merkle_proof, previous_hash, self.tree_hasher)?;
+            Ok(())
+        })?;
+
+        Ok(PartialProfitResult {
+            result: self.profit_result()?,
+            previous_hash,
+            running_hash,
+            group_value: self.merkle_proof.value_proof.value,
+        })
+    }
+
+    fn compute(&self) -> Result<ProfitResult, BoxError> {
         // 1. 预验证
-        self.validate_prerequisites()?;
+        self.validate_prerequisites(|| self.validate_merkle_proof())?;
+
+        // 2. 计算利润和各种根哈希
+        self.profit_result()
+    }
 
-        // 2. 计算利润
-        let (system_profit, proxy_profit, receiver_profit) = self.calculate_profits()?;
+    fn profit_result(&self) -> Result<ProfitResult, BoxError> {
+        // 1. 计算利润
+        let fee_configs = fee_config_map(&self.service_configs)?;
+        let (system_profit, proxy_profit, receiver_profit) = calculate_receipt_profits(&self.receipts, &fee_configs)?;
+        let token_totals = calculate_token_profits(&self.receipts, &fee_configs)?;
 
-        // 3. 计算各种根哈希
+        // 2. 计算各种根哈希
         let receipts_root = self.merkle_proof.root_hash;
         let pay_ids_root = self.calculate_pay_ids_root()?;
         let serv_ids_root = self.calculate_serv_ids_root()?;
 
         Ok(ProfitResult {
+            vks_hash: self.vks_hash,
             receiver: self.receiver,
             proxy: self.proxy,
             receipts_root,
@@ -89,314 +221,417 @@ impl ReceiptsProfitCalculator {
             system_profit,
             proxy_profit,
             receiver_profit,
+            epoch: self.epoch,
+            token_totals,
+            commitment_version: CommitmentVersion::CURRENT,
         })
     }
 
-    fn validate_prerequisites(&self) -> Result<(), BoxError> {
+    // validate_proof 验证收据与默克尔证明，分页模式下只验证一页
+    fn validate_prerequisites(
+        &self,
+        validate_proof: impl FnOnce() -> Result<(), BoxError>,
+    ) -> Result<(), BoxError> {
         // 1. 验证PayIdInfos的代理地址
-        for info in &self.pay_id_infos {
-            if info.proxy != self.proxy {
-                return Err(format!(
-                    "Invalid proxy in PayIdInfo. Expected: {:?}, Got: {:?}",
-                    self.proxy, info.proxy
-                )
-                .into());
+        validate_pay_id_proxies(&self.pay_id_infos, self.proxy)?;
+        if self.require_pay_id_authorization {
+            for info in &self.pay_id_infos {
+                info.check_authorized_in(self.signing_domain.as_ref())?;
             }
         }
+        check_partial_settlement(&self.receipts)?;
+        self.dust_policy.check(&self.receipts)?;
 
         // 2. 验证默克尔证明
-        self.validate_merkle_proof()?;
+        validate_proof()?;
 
         // 3. 验证接收者地址
-        for receipt in &self.receipts {
-            if receipt.receiver != self.receiver {
-                return Err(format!(
-                    "Invalid receiver in receipt. Expected: {:?}, Got: {:?}",
-                    self.receiver, receipt.receiver
-                )
-                .into());
-            }
-        }
+        validate_receivers(&self.receipts, self.receiver)?;
 
         // 4. 验证签名
         self.validate_signatures()?;
 
+        // 5. 过期的收据不计入利润
+        if let Some(current_time) = self.current_time {
+            check_receipt_expiry(&self.receipts, current_time)?;
+        }
+
         Ok(())
     }
 
     fn validate_merkle_proof(&self) -> Result<(), BoxError> {
-        // 1. 对收据排序
-        let mut sorted_receipts = self.receipts.clone();
-        sorted_receipts.sort_by(|a, b| a.to_key().cmp(&b.to_key()));
-
-        // 2. 计算所有收据的组合哈希
-        let mut hasher = Keccak256::new();
-        for receipt in &sorted_receipts {
-            let receipt_hash = receipt.hash();
-            hasher.update(receipt_hash.as_slice());
+        if self.nested_receipts {
+            validate_nested_receipts_proof(&self.receipts, &self.merkle_proof, self.tree_hasher)
+        } else {
+            validate_receipts_proof(&self.receipts, &self.merkle_proof, self.tree_hasher)
         }
-        let hash_of_all_payments = B256::from_slice(&hasher.finalize());
+    }
 
-        // 3. 验证组合哈希是否与证明中的值相等
-        if self.merkle_proof.value_proof.value != hash_of_all_payments {
-            return Err("Invalid Merkle proof and hash of receipts".into());
+    fn validate_signatures(&self) -> Result<(), BoxError> {
+        validate_receipt_signatures(
+            &self.receipts,
+            self.proxy,
+            &pay_id_senders(&self.pay_id_infos),
+            self.signing_domain.as_ref(),
+            &self.sealed,
+        )
+    }
+
+    fn calculate_pay_ids_root(&self) -> Result<B256, BoxError> {
+        // 与ReceiptsOverpayChecker使用相同的SegmentVC承诺，聚合时才能比较
+        PayIdsProcessor::get_root_hash(&self.pay_id_infos)
+    }
+
+    fn calculate_serv_ids_root(&self) -> Result<B256, BoxError> {
+        calculate_serv_ids_root(&self.service_configs)
+    }
+}
+
+// 以下辅助函数由单接收者和多接收者计算器共用
+
+/// 验证所有PayIdInfo都属于同一个代理
+pub(crate) fn validate_pay_id_proxies(
+    pay_id_infos: &[PayIdInfo],
+    proxy: EthAddress,
+) -> Result<(), BoxError> {
+    for info in pay_id_infos {
+        if info.proxy != proxy {
+            return Err(format!(
+                "Invalid proxy in PayIdInfo. Expected: {:?}, Got: {:?}",
+                proxy, info.proxy
+            )
+            .into());
         }
-        // 4. 验证默克尔证明
-        if !self.merkle_proof.verify()? {
-            return Err("Invalid Merkle proof for receipts".into());
+    }
+    Ok(())
+}
+
+/// 验证收据中所有的接收者都是指定接收者
+pub(crate) fn validate_receivers(
+    receipts: &[PaymentSettledByProxy],
+    receiver: EthAddress,
+) -> Result<(), BoxError> {
+    for (index, receipt) in receipts.iter().enumerate() {
+        if receipt.receiver != receiver {
+            let invalid = format!("Invalid receiver in receipt. Expected: {:?}, Got: {:?}", receiver, receipt.receiver);
+            return Err(WithContext::at_receipt(index, receipt, invalid).to_string().into());
         }
+    }
+    Ok(())
+}
 
-        Ok(())
+/// 验证收据的组合哈希与默克尔证明一致且证明有效，组合哈希和证明都按分组时的 hasher 计算
+pub(crate) fn validate_receipts_proof(
+    receipts: &[PaymentSettledByProxy],
+    merkle_proof: &MerkleProof,
+    hasher: TreeHashAlgorithm,
+) -> Result<(), BoxError> {
+    // 1. 按 canonical_receipt_order 对收据排序，重复的收据返回 DuplicateReceipt
+    let entries = canonical_entries(receipts)?;
+
+    // 2. 计算所有收据的组合哈希，与 group_by_receiver_with 的组值相同
+    let hash_of_all_payments = page_group_hash(hasher, entries.into_iter().map(|(_, hash)| hash));
+
+    // 3. 验证组合哈希是否与证明中的值相等
+    if merkle_proof.value_proof.value != hash_of_all_payments {
+        return Err("Invalid Merkle proof and hash of receipts".into());
+    }
+    // 4. 验证默克尔证明
+    if !merkle_proof.verify_with(hasher, false)? {
+        return Err("Invalid Merkle proof for receipts".into());
     }
 
-    fn validate_signatures(&self) -> Result<(), BoxError> {
-        // 创建PayId到发送者的映射
-        let pay_id_senders: HashMap<U256, EthAddress> = self
-            .pay_id_infos
-            .iter()
-            .map(|info| (info.id, info.sender))
-            .collect();
-
-        for receipt in &self.receipts {
-            // 获取对应的发送者
-            let sender = pay_id_senders
-                .get(&receipt.pay_id)
-                .ok_or_else(|| format!("PayId {} not found in PayIdInfos", receipt.pay_id))?;
-
-            // 验证发送者地址
-            let recovered_sender = receipt.get_sender_address()?;
-            if &recovered_sender != sender {
-                return Err(format!(
-                    "Invalid sender signature. Expected: {:?}, Got: {:?}",
-                    sender, recovered_sender
-                )
-                .into());
-            }
+    Ok(())
+}
 
-            // 验证代理地址
-            let recovered_proxy = receipt.get_proxy_address()?;
-            if recovered_proxy != self.proxy {
-                return Err(format!(
-                    "Invalid proxy signature. Expected: {:?}, Got: {:?}",
-                    self.proxy, recovered_proxy
-                )
-                .into());
-            }
-        }
+/// 验证一页收据：计算这一页的组哈希并接到 previous_hash 之后，证明路径必须有效，返回新的链值
+pub(crate) fn validate_receipt_page(
+    receipts: &[PaymentSettledByProxy],
+    merkle_proof: &MerkleProof,
+    previous_hash: Option<B256>,
+    hasher: TreeHashAlgorithm,
+) -> Result<B256, BoxError> {
+    let entries = canonical_entries(receipts)?;
+    let page_hash = page_group_hash(hasher, entries.into_iter().map(|(_, hash)| hash));
 
-        Ok(())
+    if !merkle_proof.verify_with(hasher, false)? {
+        return Err("Invalid Merkle proof for receipts".into());
     }
+    Ok(chain_page_hash(hasher, previous_hash, page_hash))
+}
 
-    fn calculate_profits(&self) -> Result<(U256, U256, U256), BoxError> {
-        let mut total_system_profit = U256::default();
-        let mut total_proxy_profit = U256::default();
-        let mut total_receiver_profit = U256::default();
-
-        // 创建服务费率查找表
-        let fee_configs: HashMap<u32, &ServiceFeeConfig> = self
-            .service_configs
-            .iter()
-            .map(|config| (config.serv_id, config))
-            .collect();
-
-        let base_rate = U256::from(10000); // 费率基数
-
-        for receipt in &self.receipts {
-            let config = fee_configs.get(&receipt.serv_id).ok_or_else(|| {
-                format!("Service config not found for serv_id: {}", receipt.serv_id)
-            })?;
-            let system_fee_rate = U256::from(config.system_fee_rate);
-            let proxy_fee_rate = U256::from(config.proxy_fee_rate);
-            // 计算系统分成
-            let system_fee = receipt
-                .amount
-                .checked_mul(system_fee_rate)
-                .ok_or("Multiplication overflow")?
-                .checked_div(base_rate)
-                .ok_or("Division by zero")?;
-            total_system_profit = total_system_profit
-                .checked_add(system_fee)
-                .ok_or("Addition overflow")?;
+/// ReceiptsProfitCalculator::calculate_page 的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialProfitResult {
+    /// 只包含这一页收据的利润
+    pub result: ProfitResult,
+    /// 之前各页的链值，第一页为 None
+    pub previous_hash: Option<B256>,
+    /// 接上这一页之后的链值
+    pub running_hash: B256,
+    /// 默克尔证明中该receiver的值，最后一页的 running_hash 必须与它相等
+    pub group_value: B256,
+}
 
-            // 计算代理分成
-            let proxy_fee = receipt
-                .amount
-                .checked_mul(proxy_fee_rate)
-                .ok_or("Multiplication overflow")?
-                .checked_div(base_rate)
-                .ok_or("Division by zero")?;
-            total_proxy_profit = total_proxy_profit
-                .checked_add(proxy_fee)
-                .ok_or("Addition overflow")?;
+/// 合并同一receiver按顺序排列的各页结果
+///
+/// 各页必须首尾相接（previous_hash 等于前一页的 running_hash，第一页为 None），
+/// 除利润以外的字段相同，最后一页的链值等于证明中的值；利润逐项相加
+pub fn combine_partial_results(partials: &[PartialProfitResult]) -> Result<ProfitResult, BoxError> {
+    let (first, last) = match (partials.first(), partials.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err("No partial results to combine".into()),
+    };
 
-            // 计算接收者收入
-            let receiver_fee = receipt
-                .amount
-                .checked_sub(system_fee)
-                .ok_or("Subtraction overflow")?
-                .checked_sub(proxy_fee)
-                .ok_or("Subtraction overflow")?;
-            total_receiver_profit = total_receiver_profit
-                .checked_add(receiver_fee)
+    let mut combined = first.result.clone();
+    let mut token_totals = first.result.token_subtotals();
+    let mut previous_hash = None;
+    for (index, partial) in partials.iter().enumerate() {
+        if partial.previous_hash != previous_hash {
+            return Err(format!("Partial result {} does not continue the previous page", index).into());
+        }
+        let result = &partial.result;
+        if partial.group_value != first.group_value
+            || result.vks_hash != combined.vks_hash
+            || result.receiver != combined.receiver
+            || result.proxy != combined.proxy
+            || result.receipts_root != combined.receipts_root
+            || result.pay_ids_root != combined.pay_ids_root
+            || result.serv_ids_root != combined.serv_ids_root
+            || result.epoch != combined.epoch
+            || result.commitment_version != combined.commitment_version
+        {
+            return Err(format!("Partial result {} belongs to a different settlement", index).into());
+        }
+        if index > 0 {
+            combined.system_profit = combined
+                .system_profit
+                .checked_add(result.system_profit)
+                .ok_or("Addition overflow")?;
+            combined.proxy_profit = combined
+                .proxy_profit
+                .checked_add(result.proxy_profit)
                 .ok_or("Addition overflow")?;
+            combined.receiver_profit = combined
+                .receiver_profit
+                .checked_add(result.receiver_profit)
+                .ok_or("Addition overflow")?;
+            for subtotal in &result.token_subtotals() {
+                TokenSubtotal::accumulate(&mut token_totals, subtotal).map_err(|_| "Addition overflow")?;
+            }
         }
+        previous_hash = Some(partial.running_hash);
+    }
 
-        Ok((
-            total_system_profit,
-            total_proxy_profit,
-            total_receiver_profit,
-        ))
+    if last.running_hash != last.group_value {
+        return Err("Invalid Merkle proof and hash of receipt pages".into());
     }
+    TokenSubtotal::normalize(&mut token_totals);
+    combined.token_totals = token_totals;
+    Ok(combined)
+}
 
-    fn calculate_pay_ids_root(&self) -> Result<B256, BoxError> {
-        // 对PayIdInfo排序并计算哈希
-        let mut sorted_pay_ids = self.pay_id_infos.clone();
-        sorted_pay_ids.sort_by(|a, b| a.id.cmp(&b.id));
+/// 验证收据子树的根与默克尔证明一致且证明有效，用于嵌套分组
+pub(crate) fn validate_nested_receipts_proof(
+    receipts: &[PaymentSettledByProxy],
+    merkle_proof: &MerkleProof,
+    hasher: TreeHashAlgorithm,
+) -> Result<(), BoxError> {
+    let subtree = receiver_subtree(receipts, hasher)?;
+    if merkle_proof.value_proof.value != subtree.get_root_hash()? {
+        return Err("Invalid Merkle proof and root of receipts subtree".into());
+    }
+    if !merkle_proof.verify_with(hasher, false)? {
+        return Err("Invalid Merkle proof for receipts".into());
+    }
 
-        let mut hasher = Keccak256::new();
-        for pay_id_info in &sorted_pay_ids {
-            hasher.update(pay_id_info.hash().as_slice());
+    Ok(())
+}
+
+/// 创建PayId到发送者的映射
+pub(crate) fn pay_id_senders(pay_id_infos: &[PayIdInfo]) -> HashMap<U256, EthAddress> {
+    pay_id_infos
+        .iter()
+        .map(|info| (info.id, info.sender))
+        .collect()
+}
+
+/// 验证每个收据的发送者签名和代理签名
+pub(crate) fn validate_receipt_signatures(
+    receipts: &[PaymentSettledByProxy],
+    proxy: EthAddress,
+    pay_id_senders: &HashMap<U256, EthAddress>,
+    domain: Option<&SigningDomain>,
+    sealed: &SealedSigners,
+) -> Result<(), BoxError> {
+    for (index, receipt) in receipts.iter().enumerate() {
+        // 获取对应的发送者
+        let sender = pay_id_senders.get(&receipt.pay_id).ok_or_else(|| {
+            WithContext::at_receipt(index, receipt, "PayId not found in PayIdInfos").to_string()
+        })?;
+
+        // 验证发送者地址
+        let recovered_sender = sealed.sender_address(receipt, domain);
+        if recovered_sender != Some(*sender) {
+            let invalid = InvalidReceiptSignature::new("sender", receipt, *sender, recovered_sender);
+            return Err(WithContext::at_receipt(index, receipt, invalid).into());
         }
 
-        Ok(B256::from_slice(&hasher.finalize()))
+        // 验证代理地址
+        let recovered_proxy = sealed.proxy_address(receipt, domain);
+        if recovered_proxy != Some(proxy) {
+            let invalid = InvalidReceiptSignature::new("proxy", receipt, proxy, recovered_proxy);
+            return Err(WithContext::at_receipt(index, receipt, invalid).into());
+        }
     }
 
-    fn calculate_serv_ids_root(&self) -> Result<B256, BoxError> {
-        // 对ServiceFeeConfig排序并计算哈希
-        let mut sorted_configs = self.service_configs.clone();
-        sorted_configs.sort_by(|a, b| a.serv_id.cmp(&b.serv_id));
-
-        let mut hasher = Keccak256::new();
-        for config in &sorted_configs {
-            // 打包服务配置数据
-            hasher.update(&config.serv_id.to_be_bytes());
-            hasher.update(&config.system_fee_rate.to_be_bytes());
-            hasher.update(&config.proxy_fee_rate.to_be_bytes());
-        }
+    Ok(())
+}
+
+/// 创建服务费率查找表，费率或分级无效的配置返回错误
+pub(crate) fn fee_config_map(service_configs: &[ServiceFeeConfig]) -> Result<HashMap<u32, &ServiceFeeConfig>, BoxError> {
+    service_configs
+        .iter()
+        .map(|config| {
+            config.validate()?;
+            Ok((config.serv_id, config))
+        })
+        .collect()
+}
+
+/// 计算一组收据的 (system_profit, proxy_profit, receiver_profit)，只累计原生代币的收据，
+/// 其他代币的金额见 calculate_token_profits
+pub(crate) fn calculate_receipt_profits(
+    receipts: &[PaymentSettledByProxy],
+    fee_configs: &HashMap<u32, &ServiceFeeConfig>,
+) -> Result<(U256, U256, U256), BoxError> {
+    let mut total_system_profit = U256::default();
+    let mut total_proxy_profit = U256::default();
+    let mut total_receiver_profit = U256::default();
 
-        Ok(B256::from_slice(&hasher.finalize()))
+    for receipt in receipts.iter().filter(|receipt| receipt.token == NATIVE_TOKEN) {
+        let (system_fee, proxy_fee, receiver_fee) = receipt_profit(receipt, fee_configs)?;
+        total_system_profit = total_system_profit
+            .checked_add(system_fee)
+            .ok_or("Addition overflow")?;
+        total_proxy_profit = total_proxy_profit
+            .checked_add(proxy_fee)
+            .ok_or("Addition overflow")?;
+        total_receiver_profit = total_receiver_profit
+            .checked_add(receiver_fee)
+            .ok_or("Addition overflow")?;
     }
+
+    Ok((
+        total_system_profit,
+        total_proxy_profit,
+        total_receiver_profit,
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::receipts::overpay_checker::ReceiptsOverpayChecker;
-    use libsecp256k1::{PublicKey, SecretKey}; // 添加这行
+/// 按代币分别累计利润，所有收据都是原生代币时为空，见 ProfitResult.token_totals
+pub(crate) fn calculate_token_profits(
+    receipts: &[PaymentSettledByProxy],
+    fee_configs: &HashMap<u32, &ServiceFeeConfig>,
+) -> Result<Vec<TokenSubtotal>, BoxError> {
+    if receipts.iter().all(|receipt| receipt.token == NATIVE_TOKEN) {
+        return Ok(Vec::new());
+    }
+    let mut totals = Vec::new();
+    for receipt in receipts {
+        let (system_profit, proxy_profit, receiver_profit) = receipt_profit(receipt, fee_configs)?;
+        let subtotal = TokenSubtotal { token: receipt.token, system_profit, proxy_profit, receiver_profit };
+        TokenSubtotal::accumulate(&mut totals, &subtotal).map_err(|_| "Addition overflow")?;
+    }
+    Ok(totals)
+}
 
-    fn create_test_payment(
-        pay_id: u64,
-        serv_id: u32,
-        amount: u64,
-        receiver: EthAddress,
-        sender_key: &SecretKey,
-        proxy_key: &SecretKey,
-    ) -> Result<PaymentSettledByProxy, BoxError> {
-        // 1. 创建并签名Payment
-        let mut payment = super::super::Payment {
-            pay_id: U256::from(pay_id),
-            serv_id,
-            receiver,
-            sig_sender: [0u8; 65],
-        };
-        payment.sign(sender_key)?;
+// 一张收据的 (系统分成, 代理分成, 接收者收入)
+fn receipt_profit(
+    receipt: &PaymentSettledByProxy,
+    fee_configs: &HashMap<u32, &ServiceFeeConfig>,
+) -> Result<(U256, U256, U256), BoxError> {
+    let base_rate = U256::from(10000); // 费率基数
+
+    let config = fee_configs.get(&receipt.serv_id).ok_or_else(|| {
+        format!("Service config not found for serv_id: {}", receipt.serv_id)
+    })?;
+    // 按金额选择费率，没有分级时为配置的基础费率
+    let (system_fee_rate, proxy_fee_rate) = config.rates_for(receipt.amount);
+    let system_fee_rate = U256::from(system_fee_rate);
+    let proxy_fee_rate = U256::from(proxy_fee_rate);
+    // 计算系统分成和代理分成
+    let system_fee = fee_share(receipt.amount, system_fee_rate, base_rate)?;
+    let proxy_fee = fee_share(receipt.amount, proxy_fee_rate, base_rate)?;
+
+    // 计算接收者收入
+    let receiver_fee = receipt
+        .amount
+        .checked_sub(system_fee)
+        .ok_or("Subtraction overflow")?
+        .checked_sub(proxy_fee)
+        .ok_or("Subtraction overflow")?;
+    Ok((system_fee, proxy_fee, receiver_fee))
+}
 
-        // 2. 转换为PaymentSettledByProxy并签名
-        let mut settled = PaymentSettledByProxy::from(payment);
-        settled.set_settlement(U256::from(amount), true);
-        settled.sign_by_proxy(proxy_key)?;
+// ⌊amount × rate / base⌋：先把 amount 拆成 quotient × base + remainder，
+// rate 不超过 base 时 quotient × rate 不超过 amount，接近 U256::MAX 的金额也不会溢出
+fn fee_share(amount: U256, rate: U256, base: U256) -> Result<U256, BoxError> {
+    let (quotient, remainder) = (amount / base, amount % base);
+    let whole = quotient.checked_mul(rate).ok_or("Multiplication overflow")?;
+    // remainder < base，rate 不超过 u16::MAX，乘积不会溢出
+    let part = remainder * rate / base;
+    Ok(whole.checked_add(part).ok_or("Addition overflow")?)
+}
+
+/// 一组费率配置的 serv_ids_root，先按 serv_id 排序，与计算器写入 ProfitResult 的值相同
+pub fn serv_ids_root(service_configs: &[ServiceFeeConfig]) -> B256 {
+    ServiceFeeRegistry::root_of(service_configs)
+}
 
-        Ok(settled)
+/// 对ServiceFeeConfig排序并计算哈希，哈希方式由 ServiceFeeRegistry 定义
+pub(crate) fn calculate_serv_ids_root(service_configs: &[ServiceFeeConfig]) -> Result<B256, BoxError> {
+    Ok(serv_ids_root(service_configs))
+}
+
+/// 用原始数据重新计算 ProfitResult 中的承诺，检查证明使用的费率和 pay_id 集合
+impl ProfitResult {
+    /// 费率配置重新计算的 serv_ids_root 与结果中的一致，配置的顺序不影响结果
+    pub fn verify_fee_schedule(&self, configs: &[ServiceFeeConfig]) -> bool {
+        serv_ids_root(configs) == self.serv_ids_root
+    }
+
+    /// pay_id 列表重新计算的 pay_ids_root 与结果中的一致，列表无法构建承诺（如 id 重复）时返回 false
+    pub fn verify_pay_ids(&self, infos: &[PayIdInfo]) -> bool {
+        PayIdsProcessor::get_root_hash(infos).is_ok_and(|root| root == self.pay_ids_root)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethaddr_gen::EthAddressGen;
+    use crate::fixtures::{ScenarioBuilder, Violation};
+    use crate::receipts::overpay_checker::ReceiptsOverpayChecker;
 
     #[test]
     fn test_complete_calculation() -> Result<(), BoxError> {
-        // 1. 创建密钥对
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key = SecretKey::random(&mut rand::thread_rng());
-
-        let sender = get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
-        let proxy = get_ethereum_address(&PublicKey::from_secret_key(&proxy_key));
-        let receiver = EthAddressGen::random();
-
-        // 2. 创建测试数据
-        let pay_id_infos = vec![
-            PayIdInfo {
-                id: U256::from(1),
-                amount: U256::from(1000),
-                sender,
-                proxy,
-                state: 1,
-                created_at: 0,
-                closing_time: 0,
-            },
-            PayIdInfo {
-                id: U256::from(2),
-                amount: U256::from(2000),
-                sender,
-                proxy,
-                state: 1,
-                created_at: 0,
-                closing_time: 0,
-            },
-        ];
-
-        let service_configs = vec![
-            ServiceFeeConfig {
-                serv_id: 1,
-                system_fee_rate: 500, // 5%
-                proxy_fee_rate: 1000, // 10%
-            },
-            ServiceFeeConfig {
-                serv_id: 2,
-                system_fee_rate: 300, // 3%
-                proxy_fee_rate: 700,  // 7%
-            },
-        ];
-
-        // 3. 创建收据
-        let receipts = vec![
-            create_test_payment(1, 1, 1000, receiver, &sender_key, &proxy_key)?,
-            create_test_payment(2, 2, 2000, receiver, &sender_key, &proxy_key)?,
-        ];
-        // 4. 使用ReceiptsSorter生成MerkleProof
-        let sorter = ReceiptsOverpayChecker::new(proxy, pay_id_infos.clone(), receipts.clone());
-        let sort_result = sorter.process()?;
-        // 找到对应receiver的proof
-        let receiver_proof = sort_result
-            .receiver_proofs
-            .into_iter()
-            .find(|p| p.receiver == receiver)
-            .ok_or("Receiver proof not found")?;
-
-        let service_configs = vec![
-            ServiceFeeConfig {
-                serv_id: 1,
-                system_fee_rate: 500, // 5%
-                proxy_fee_rate: 1000, // 10%
-            },
-            ServiceFeeConfig {
-                serv_id: 2,
-                system_fee_rate: 300, // 3%
-                proxy_fee_rate: 700,  // 7%
-            },
-        ];
-        // 4. 创建计算器实例
-        let calculator = ReceiptsProfitCalculator::new(
-            receiver,
-            proxy,
-            receipts,
-            receiver_proof.proof, // 使用生成的proof
-            pay_id_infos,
-            service_configs,
-        );
+        let scenario = ScenarioBuilder::new(1)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 2, 0, 2000)
+            .with_fee_config(1, 500, 1000) // 5%, 10%
+            .with_fee_config(2, 300, 700) // 3%, 7%
+            .build()?;
+        let receiver = scenario.receiver(0);
+
+        // 使用 overpay 检查生成 MerkleProof
+        let sort_result = scenario.overpay_checker().process()?;
+        let proof = sort_result.get_merkle_proof(receiver)?;
 
-        // 5. 执行计算
-        let result = calculator.calculate()?;
+        let result = scenario.profit_calculator(receiver, proof).calculate()?;
 
-        // 6. 验证结果
         assert_eq!(result.receiver, receiver);
-        assert_eq!(result.proxy, proxy);
+        assert_eq!(result.proxy, scenario.proxy);
 
         // 验证利润计算
         // 第一笔交易: 1000 * (5% + 10%) = 150
@@ -409,77 +644,427 @@ mod tests {
         let total = result.system_profit + result.proxy_profit + result.receiver_profit;
         assert_eq!(total, U256::from(3000)); // 1000 + 2000
 
+        // pay_ids_root 与 overpay 检查的承诺一致
+        assert_eq!(result.pay_ids_root, sort_result.pay_ids_root);
+
         Ok(())
     }
 
     #[test]
-    fn test_invalid_proxy() -> Result<(), BoxError> {
-        let sender_key = SecretKey::random(&mut rand::thread_rng());
-        let proxy_key = SecretKey::random(&mut rand::thread_rng());
-        let wrong_proxy = EthAddressGen::random();
-        let receiver = EthAddressGen::random();
-
-        let sender = get_ethereum_address(&PublicKey::from_secret_key(&sender_key));
-        let proxy = get_ethereum_address(&PublicKey::from_secret_key(&proxy_key));
-
-        // 创建收据
-        let receipts = vec![create_test_payment(
-            1,
-            1,
-            1000,
-            receiver,
-            &sender_key,
-            &proxy_key,
-        )?];
-
-        // 使用ReceiptsSorter生成MerkleProof
-        let sorter = ReceiptsOverpayChecker::new(
-            proxy, // 使用正确的代理地址生成proof
-            vec![PayIdInfo {
-                id: U256::from(1),
-                amount: U256::from(1000),
-                sender,
-                proxy,
-                state: 1,
-                created_at: 0,
-                closing_time: 0,
-            }],
-            receipts.clone(),
+    fn test_verify_fee_schedule_and_pay_ids() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 2, 0, 2000)
+            .with_fee_config(1, 500, 1000)
+            .with_fee_config(2, 300, 700)
+            .build()?;
+        let receiver = scenario.receiver(0);
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+        let result = scenario.profit_calculator(receiver, proof).calculate()?;
+
+        assert_eq!(serv_ids_root(&scenario.service_configs), result.serv_ids_root);
+        assert!(result.verify_fee_schedule(&scenario.service_configs));
+        assert!(result.verify_pay_ids(&scenario.pay_id_infos));
+
+        // 配置的顺序不影响结果
+        let mut reversed = scenario.service_configs.clone();
+        reversed.reverse();
+        assert!(result.verify_fee_schedule(&reversed));
+
+        // 修改一个费率即不匹配
+        let mut changed = scenario.service_configs.clone();
+        changed[1].proxy_fee_rate += 1;
+        assert!(!result.verify_fee_schedule(&changed));
+        assert!(!result.verify_fee_schedule(&scenario.service_configs[..1]));
+
+        // 缺少或重复 pay_id 都不匹配
+        assert!(!result.verify_pay_ids(&scenario.pay_id_infos[..1]));
+        let mut duplicated = scenario.pay_id_infos.clone();
+        duplicated.push(duplicated[0].clone());
+        assert!(!result.verify_pay_ids(&duplicated));
+        Ok(())
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_calculate_metrics() -> Result<(), BoxError> {
+        use crate::metrics::*;
+
+        let scenario = ScenarioBuilder::new(13)
+            .with_payment(1, 1, 0, 500)
+            .with_payment(2, 1, 1, 300)
+            .build()?;
+        let receiver = scenario.receiver(0);
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+
+        let metrics = InMemoryMetrics::new();
+        scenario.profit_calculator(receiver, proof).calculate_with_metrics(&metrics)?;
+
+        assert_eq!(metrics.get(RECEIPTS_PROCESSED), 1);
+        assert_eq!(metrics.get(SIGNATURES_RECOVERED), 2);
+        // 证明验证 2 次（chunk hash 和段根）+ pay_ids 树 6 次
+        assert_eq!(metrics.get(TREE_NODES_HASHED), 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_receipts_mode() -> Result<(), BoxError> {
+        use crate::receipts::PaymentsGrouper;
+
+        let scenario = ScenarioBuilder::new(2)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(1, 2, 0, 2000)
+            .with_payment(1, 1, 1, 500)
+            .with_deposit(1, U256::from(5000))
+            .build()?;
+        let receiver = scenario.receiver(0);
+
+        let groups = PaymentsGrouper::group_by_receiver_nested(&scenario.receipts)?;
+        let nested_proof = groups.receiver_proof(&receiver).ok_or("Receiver proof not found")?.proof.clone();
+        let calculator = |proof: MerkleProof| scenario.profit_calculator(receiver, proof);
+
+        let result = calculator(nested_proof.clone()).with_nested_receipts().calculate()?;
+        assert_eq!(result.receipts_root, groups.root);
+        assert_eq!(result.system_profit + result.proxy_profit + result.receiver_profit, U256::from(3000));
+
+        // 两种模式的证明不能混用
+        let (_, flat_proofs) = PaymentsGrouper::group_by_receiver(&scenario.receipts)?;
+        let flat_proof = flat_proofs.into_iter().find(|p| p.receiver == receiver).ok_or("Receiver proof not found")?.proof;
+        assert!(calculator(flat_proof.clone()).calculate().is_ok());
+        assert!(calculator(flat_proof).with_nested_receipts().calculate().is_err());
+        assert!(calculator(nested_proof).calculate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_paged_calculation() -> Result<(), BoxError> {
+        use crate::receipts::PaymentsGrouper;
+
+        let scenario = ScenarioBuilder::new(5)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 2, 0, 2000)
+            .with_payment(3, 1, 0, 700)
+            .with_payment(4, 2, 0, 300)
+            .with_payment(5, 1, 0, 4500)
+            .with_fee_config(1, 500, 1000)
+            .with_fee_config(2, 300, 700)
+            .build()?;
+        let receiver = scenario.receiver(0);
+        let single_pass = scenario
+            .profit_calculator(receiver, scenario.overpay_checker().process()?.get_merkle_proof(receiver)?)
+            .calculate()?;
+
+        // 一页：证明与不分页相同，合并结果与 calculate 相同
+        let proof = scenario.overpay_checker().with_max_receipts_per_page(5).process()?.get_merkle_proof(receiver)?;
+        let calculator = scenario.profit_calculator(receiver, proof);
+        let page = calculator.calculate_page(None)?;
+        assert_eq!(page.running_hash, page.group_value);
+        assert_eq!(combine_partial_results(&[page])?, single_pass);
+        assert_eq!(calculator.calculate()?, single_pass);
+
+        // 两页：每页单独计算，合并后的利润与一次计算相同
+        let proof = scenario.overpay_checker().with_max_receipts_per_page(3).process()?.get_merkle_proof(receiver)?;
+        let pages = PaymentsGrouper::receipt_pages(&scenario.receipts_for(&receiver), 3)?;
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2]);
+        let page_calculator = |receipts: &Vec<PaymentSettledByProxy>| {
+            ReceiptsProfitCalculator::new(
+                B256::ZERO,
+                receiver,
+                scenario.proxy,
+                receipts.clone(),
+                proof.clone(),
+                scenario.pay_id_infos.clone(),
+                scenario.service_configs.clone(),
+            )
+        };
+        let first = page_calculator(&pages[0]).calculate_page(None)?;
+        let second = page_calculator(&pages[1]).calculate_page(Some(first.running_hash))?;
+        let combined = combine_partial_results(&[first.clone(), second.clone()])?;
+        assert_eq!(combined.receipts_root, proof.root_hash);
+        assert_eq!(
+            (combined.system_profit, combined.proxy_profit, combined.receiver_profit),
+            (single_pass.system_profit, single_pass.proxy_profit, single_pass.receiver_profit)
         );
-        let sort_result = sorter.process()?;
 
-        // 找到对应receiver的proof
-        let receiver_proof = sort_result
-            .receiver_proofs
-            .into_iter()
-            .find(|p| p.receiver == receiver)
-            .ok_or("Receiver proof not found")?;
+        // 缺页、乱序、对整页收据使用分页的证明都被拒绝
+        assert!(combine_partial_results(&[first.clone()]).is_err());
+        assert!(combine_partial_results(&[second.clone(), first.clone()]).is_err());
+        assert!(combine_partial_results(&[]).is_err());
+        assert!(scenario.profit_calculator(receiver, proof.clone()).calculate().is_err());
+        let reordered = page_calculator(&pages[1]).calculate_page(None)?;
+        let tail = page_calculator(&pages[0]).calculate_page(Some(reordered.running_hash))?;
+        assert!(combine_partial_results(&[reordered, tail]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_tiers() -> Result<(), BoxError> {
+        use crate::models::FeeTier;
+
+        let tier = |threshold: u64, system_fee_rate, proxy_fee_rate| FeeTier {
+            threshold: U256::from(threshold),
+            system_fee_rate,
+            proxy_fee_rate,
+        };
+        let profits = |config: &ServiceFeeConfig, amount: u64| {
+            let receipts = [PaymentSettledByProxy::new(U256::from(1), 1, U256::from(amount), [1u8; 20])];
+            let configs = [config.clone()];
+            let fee_configs = fee_config_map(&configs)?;
+            calculate_receipt_profits(&receipts, &fee_configs)
+        };
+        let expected = |system: u64, proxy: u64, receiver: u64| (U256::from(system), U256::from(proxy), U256::from(receiver));
+
+        // 1000 以上代理费率降为 5%，5000 以上为 0
+        let tiered = ServiceFeeConfig::flat(1, 500, 1000).with_tiers(vec![tier(1000, 500, 500), tier(5000, 500, 0)]);
+        assert_eq!(profits(&tiered, 999)?, expected(49, 99, 851));
+        assert_eq!(profits(&tiered, 1000)?, expected(50, 50, 900));
+        assert_eq!(profits(&tiered, 4999)?, expected(249, 249, 4501));
+        assert_eq!(profits(&tiered, 5000)?, expected(250, 0, 4750));
+
+        // 从 0 开始、费率相同的一级与单一费率相同
+        let flat = ServiceFeeConfig::flat(1, 500, 1000);
+        let single = flat.clone().with_tiers(vec![tier(0, 500, 1000)]);
+        for amount in [0, 1, 999, 1000, 123_456] {
+            assert_eq!(profits(&single, amount)?, profits(&flat, amount)?);
+        }
+
+        // 无效的分级在计算前被拒绝
+        let unsorted = flat.with_tiers(vec![tier(5000, 500, 0), tier(1000, 500, 500)]);
+        assert!(profits(&unsorted, 1000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dust_policy_skip() -> Result<(), BoxError> {
+        use crate::receipts::{DustAction, DustPolicy};
+
+        let scenario = ScenarioBuilder::new(3)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(1, 2, 0, 0)
+            .build()?;
+        let receiver = scenario.receiver(0);
+        let policy = DustPolicy::new(U256::from(1), DustAction::Skip);
+
+        // 证明由同样跳过 dust 收据的 overpay 检查生成
+        let sort_result = scenario.overpay_checker().with_dust_policy(policy).process()?;
+        let proof = sort_result.get_merkle_proof(receiver)?;
+
+        let result = scenario
+            .profit_calculator(receiver, proof.clone())
+            .with_dust_policy(policy)
+            .calculate()?;
+        assert_eq!(result.system_profit + result.proxy_profit + result.receiver_profit, U256::from(1000));
+
+        // Reject 模式下同样的收据失败
+        let rejected = scenario
+            .profit_calculator(receiver, proof)
+            .with_dust_policy(DustPolicy::new(U256::from(1), DustAction::Reject))
+            .calculate();
+        assert!(rejected.is_err());
 
-        // 创建测试数据，但使用错误的代理地址
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_receipts_not_counted() -> Result<(), BoxError> {
+        use crate::receipts::{ReceiptExpired, SettledReceiptBuilder};
+
+        let mut scenario = ScenarioBuilder::new(5).with_payment(1, 1, 0, 1000).build()?;
+        let receiver = scenario.receiver(0);
+
+        // 重新签发带 valid_until 的收据
+        let receipt = SettledReceiptBuilder::new(U256::from(1), 1, U256::from(1000), receiver)
+            .with_valid_until(1000)
+            .sign_sender(&scenario.sender_keys[0])
+            .settle(U256::from(1000), true)
+            .sign_proxy(&scenario.proxy_key)
+            .build()?;
+        scenario.receipts = vec![receipt];
+
+        let proof = scenario.overpay_checker().with_current_time(1000).process()?.get_merkle_proof(receiver)?;
+        let result = scenario.profit_calculator(receiver, proof.clone()).with_current_time(1000).calculate()?;
+        assert_eq!(result.system_profit + result.proxy_profit + result.receiver_profit, U256::from(1000));
+
+        let err = scenario
+            .profit_calculator(receiver, proof)
+            .with_current_time(1001)
+            .calculate()
+            .unwrap_err();
+        assert!(err.downcast_ref::<WithContext<ReceiptExpired>>().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_proxy() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(4).with_payment(1, 1, 0, 1000).build()?;
+        let receiver = scenario.receiver(0);
+        let wrong_proxy = EthAddressGen::from_seed(99);
+
+        // 使用正确的代理地址生成proof
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+
+        // 使用错误的代理地址，验证应该失败
         let calculator = ReceiptsProfitCalculator::new(
+            B256::ZERO,
             receiver,
-            wrong_proxy, // 使用错误的代理地址
-            receipts,
-            receiver_proof.proof, // 使用生成的proof
-            vec![PayIdInfo {
-                id: U256::from(1),
-                amount: U256::from(1000),
-                sender,
-                proxy,
-                state: 1,
-                created_at: 0,
-                closing_time: 0,
-            }],
-            vec![ServiceFeeConfig {
-                serv_id: 1,
-                system_fee_rate: 500,
-                proxy_fee_rate: 1000,
-            }],
+            wrong_proxy,
+            scenario.receipts.clone(),
+            proof,
+            scenario.pay_id_infos.clone(),
+            scenario.service_configs.clone(),
         );
-
-        // 验证应该失败
         assert!(calculator.calculate().is_err());
 
+        // 收据由其他代理签名：不验证签名的 overpay 检查能生成证明，利润计算拒绝
+        let scenario = ScenarioBuilder::new(4)
+            .with_payment(1, 1, 0, 1000)
+            .with_violation(Violation::WrongProxy { pay_id: 1 })
+            .build()?;
+        let proof = ReceiptsOverpayChecker::new(scenario.proxy, scenario.pay_id_infos.clone(), scenario.receipts.clone())
+            .process()?
+            .get_merkle_proof(receiver)?;
+        assert!(scenario.profit_calculator(receiver, proof).calculate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pay_id_authorization() -> Result<(), BoxError> {
+        let mut scenario = ScenarioBuilder::new(5).with_payment(1, 1, 0, 1000).build()?;
+        let receiver = scenario.receiver(0);
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+        let unsigned = scenario.profit_calculator(receiver, proof.clone()).calculate()?;
+
+        let err = scenario.profit_calculator(receiver, proof.clone()).with_pay_id_authorization().calculate().unwrap_err();
+        assert!(err.is::<crate::models::UnauthorizedPayIdInfo>());
+
+        // 签名不改变 pay_ids_root，原有证明仍然有效
+        scenario.sign_pay_id_infos()?;
+        let signed = scenario.profit_calculator(receiver, proof).with_pay_id_authorization().calculate()?;
+        assert_eq!(signed, unsigned);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_errors_report_receipt_index() -> Result<(), BoxError> {
+        let scenario = (1..=4)
+            .fold(ScenarioBuilder::new(5), |builder, pay_id| builder.with_payment(pay_id, 1, 0, 100))
+            .with_violation(Violation::WrongProxy { pay_id: 3 })
+            .build()?;
+        let senders = pay_id_senders(&scenario.pay_id_infos);
+        let validate = |receipts: &[PaymentSettledByProxy]| {
+            validate_receipt_signatures(receipts, scenario.proxy, &senders, None, &SealedSigners::default())
+        };
+
+        let err = validate(&scenario.receipts).unwrap_err();
+        let invalid = err
+            .downcast_ref::<WithContext<InvalidReceiptSignature>>()
+            .ok_or("Expected InvalidReceiptSignature")?;
+        assert_eq!((invalid.index, invalid.receiver, invalid.inner.signer), (Some(2), Some(scenario.receiver(0)), "proxy"));
+        assert!(err.to_string().starts_with("receipt #2 "));
+
+        // 下标是传入切片中的位置
+        let err = validate(&scenario.receipts[1..]).unwrap_err();
+        assert_eq!(err.downcast_ref::<WithContext<InvalidReceiptSignature>>().and_then(|err| err.index), Some(1));
         Ok(())
     }
 }
+
+// 费率计算与精确有理数结果的比较：参考实现用 512 位的中间值计算 amount × rate / 10000
+#[cfg(test)]
+mod fee_proptests {
+    use super::*;
+    use crate::models::FEE_RATE_BASE;
+    use alloy_primitives::U512;
+    use proptest::prelude::*;
+
+    fn widen(value: U256) -> U512 {
+        let mut limbs = [0u64; 8];
+        limbs[..4].copy_from_slice(value.as_limbs());
+        U512::from_limbs(limbs)
+    }
+
+    fn narrow(value: U512) -> U256 {
+        let limbs = value.into_limbs();
+        assert!(limbs[4..].iter().all(|limb| *limb == 0), "Reference value exceeds U256");
+        U256::from_limbs(limbs[..4].try_into().unwrap())
+    }
+
+    // 精确值 amount × rate / 10000 向下取整
+    fn reference_fee(amount: U256, rate: u16) -> U256 {
+        narrow(widen(amount) * U512::from(rate) / U512::from(FEE_RATE_BASE))
+    }
+
+    // (system_fee, proxy_fee, receiver_fee)
+    fn fees(amount: U256, system_fee_rate: u16, proxy_fee_rate: u16) -> Result<(U256, U256, U256), BoxError> {
+        let config = ServiceFeeConfig::flat(1, system_fee_rate, proxy_fee_rate);
+        let configs = HashMap::from([(1u32, &config)]);
+        receipt_profit(&PaymentSettledByProxy::new(U256::from(1), 1, amount, [1u8; 20]), &configs)
+    }
+
+    // 计算值不超过精确值，且与精确值相差不到一个单位
+    fn assert_floor(amount: U256, rate: u16, fee: U256) {
+        let exact = widen(amount) * U512::from(rate);
+        let scaled = widen(fee) * U512::from(FEE_RATE_BASE);
+        assert!(scaled <= exact, "fee {} above exact value for amount {} rate {}", fee, amount, rate);
+        assert!(exact - scaled < U512::from(FEE_RATE_BASE), "fee {} more than one unit below exact value", fee);
+        assert_eq!(fee, reference_fee(amount, rate));
+    }
+
+    fn amount_strategy() -> impl Strategy<Value = U256> {
+        prop_oneof![
+            any::<u64>().prop_map(U256::from),
+            any::<[u8; 32]>().prop_map(U256::from_be_bytes),
+            any::<u64>().prop_map(|below| U256::MAX - U256::from(below)),
+            (0u64..20_000).prop_map(U256::from),
+        ]
+    }
+
+    fn rate_strategy() -> impl Strategy<Value = u16> {
+        prop_oneof![Just(0u16), Just(1), Just(9999), Just(FEE_RATE_BASE), 0..=FEE_RATE_BASE]
+    }
+
+    // 有效配置的两个费率之和不超过 FEE_RATE_BASE
+    fn rates_strategy() -> impl Strategy<Value = (u16, u16)> {
+        (rate_strategy(), rate_strategy()).prop_map(|(system, proxy)| (system, proxy.min(FEE_RATE_BASE - system)))
+    }
+
+    #[test]
+    fn test_regression_amounts_near_u256_max() -> Result<(), BoxError> {
+        // amount × rate 超过 U256 时曾返回 Multiplication overflow
+        for (amount, rates) in [
+            (U256::MAX, (FEE_RATE_BASE, 0)),
+            (U256::MAX, (1, 9999)),
+            (U256::MAX - U256::from(1), (5000, 5000)),
+            (U256::MAX / U256::from(FEE_RATE_BASE) + U256::from(1), (9999, 1)),
+        ] {
+            let (system_fee, proxy_fee, receiver_fee) = fees(amount, rates.0, rates.1)?;
+            assert_floor(amount, rates.0, system_fee);
+            assert_floor(amount, rates.1, proxy_fee);
+            assert_eq!(system_fee + proxy_fee + receiver_fee, amount);
+        }
+        assert_eq!(fees(U256::MAX, FEE_RATE_BASE, 0)?, (U256::MAX, U256::ZERO, U256::ZERO));
+
+        // 费率之和超过基数的配置没有剩余给接收者
+        assert!(fees(U256::from(10000), 9999, 9999).is_err());
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(20_000))]
+
+        #[test]
+        fn fees_match_rational_reference(amount in amount_strategy(), (system_fee_rate, proxy_fee_rate) in rates_strategy()) {
+            let (system_fee, proxy_fee, receiver_fee) = fees(amount, system_fee_rate, proxy_fee_rate).unwrap();
+            assert_floor(amount, system_fee_rate, system_fee);
+            assert_floor(amount, proxy_fee_rate, proxy_fee);
+            // 三部分之和等于金额，接收者得到取整的余数
+            prop_assert_eq!(system_fee.checked_add(proxy_fee).and_then(|fees| fees.checked_add(receiver_fee)), Some(amount));
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! 按顺序完成发送者签名、代理结算和代理签名的收据构建器
+//!
+//! 手工构建时需要 Payment::sign → From 转换 → set_settlement → sign_by_proxy，
+//! 漏掉 set_settlement 时代理签名的是未结算的收据。构建器在 build 时按固定顺序签名，
+//! 并拒绝缺少签名、结算金额与签名金额不同或未结算的收据。部分结算需要显式调用 allow_amount_change。
+use alloy_primitives::U256;
+use libsecp256k1::SecretKey;
+use rlp::DecoderError;
+
+use super::{Payment, PaymentSettledByProxy, SigningDomain};
+use crate::EthAddress;
+
+/// SettledReceiptBuilder::build 拒绝构建的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    MissingSenderSignature,
+    MissingProxySignature,
+    /// 结算金额与发送者签名的金额不同且没有调用 allow_amount_change，或结算金额超过签名的金额
+    AmountMismatch { signed: U256, settled: U256 },
+    /// 代理签名时收据未结算（没有调用 settle 或 settled 为 false）
+    NotSettled,
+    Signing(DecoderError),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::MissingSenderSignature => write!(f, "Receipt is missing the sender signature"),
+            BuildError::MissingProxySignature => write!(f, "Receipt is missing the proxy signature"),
+            BuildError::AmountMismatch { signed, settled } => write!(
+                f,
+                "Settled amount {} differs from sender-signed amount {}",
+                settled, signed
+            ),
+            BuildError::NotSettled => write!(f, "Proxy cannot sign an unsettled receipt"),
+            BuildError::Signing(err) => write!(f, "Failed to sign receipt: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<DecoderError> for BuildError {
+    fn from(err: DecoderError) -> Self {
+        BuildError::Signing(err)
+    }
+}
+
+/// 已结算、带两个签名的收据的构建器，签名在 build 时进行，调用顺序不影响结果
+///
+/// ```ignore
+/// let receipt = SettledReceiptBuilder::new(pay_id, serv_id, amount, receiver)
+///     .sign_sender(&sender_key)
+///     .settle(amount, true)
+///     .sign_proxy(&proxy_key)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct SettledReceiptBuilder {
+    payment: Payment,
+    sender_key: Option<SecretKey>,
+    proxy_key: Option<SecretKey>,
+    settlement: Option<(U256, bool)>,
+    allow_amount_change: bool,
+    domain: Option<SigningDomain>,
+}
+
+impl SettledReceiptBuilder {
+    pub fn new(pay_id: U256, serv_id: u32, amount: U256, receiver: EthAddress) -> Self {
+        Self {
+            payment: Payment::new(pay_id, serv_id, amount, receiver),
+            sender_key: None,
+            proxy_key: None,
+            settlement: None,
+            allow_amount_change: false,
+            domain: None,
+        }
+    }
+
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.payment = self.payment.with_nonce(nonce);
+        self
+    }
+
+    pub fn with_valid_until(mut self, valid_until: u64) -> Self {
+        self.payment = self.payment.with_valid_until(valid_until);
+        self
+    }
+
+    pub fn with_token(mut self, token: EthAddress) -> Self {
+        self.payment = self.payment.with_token(token);
+        self
+    }
+
+    /// 两个签名都在该签名域下进行
+    pub fn with_domain(mut self, domain: SigningDomain) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// 发送者对 new 中的金额签名
+    pub fn sign_sender(mut self, secret_key: &SecretKey) -> Self {
+        self.sender_key = Some(*secret_key);
+        self
+    }
+
+    /// 代理结算的金额和状态，代理签名覆盖这里的值
+    pub fn settle(mut self, amount: U256, settled: bool) -> Self {
+        self.settlement = Some((amount, settled));
+        self
+    }
+
+    /// 允许部分结算：结算金额小于发送者签名的金额，收据记录 authorized_amount
+    pub fn allow_amount_change(mut self) -> Self {
+        self.allow_amount_change = true;
+        self
+    }
+
+    /// 代理对结算后的收据签名
+    pub fn sign_proxy(mut self, secret_key: &SecretKey) -> Self {
+        self.proxy_key = Some(*secret_key);
+        self
+    }
+
+    /// 检查后依次完成发送者签名、结算和代理签名
+    pub fn build(self) -> Result<PaymentSettledByProxy, BuildError> {
+        let sender_key = self.sender_key.ok_or(BuildError::MissingSenderSignature)?;
+        let proxy_key = self.proxy_key.ok_or(BuildError::MissingProxySignature)?;
+        let (amount, settled) = self.settlement.ok_or(BuildError::NotSettled)?;
+        if !settled {
+            return Err(BuildError::NotSettled);
+        }
+        if amount > self.payment.amount || (amount != self.payment.amount && !self.allow_amount_change) {
+            return Err(BuildError::AmountMismatch { signed: self.payment.amount, settled: amount });
+        }
+
+        let mut payment = self.payment;
+        payment.sign_in(&sender_key, self.domain.as_ref())?;
+
+        let mut receipt = PaymentSettledByProxy::from(payment);
+        receipt.set_settlement(amount, settled);
+        receipt.sign_by_proxy_in(&proxy_key, self.domain.as_ref())?;
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethaddr_gen::keypair_from_seed;
+    use crate::get_ethereum_address;
+
+    fn builder() -> (SettledReceiptBuilder, SecretKey, SecretKey) {
+        let (sender_key, _) = keypair_from_seed(1);
+        let (proxy_key, _) = keypair_from_seed(2);
+        let builder = SettledReceiptBuilder::new(U256::from(7u32), 3, U256::from(100u32), [9u8; 20]);
+        (builder, sender_key, proxy_key)
+    }
+
+    #[test]
+    fn test_build_signed_receipt() -> Result<(), BuildError> {
+        let (builder, sender_key, proxy_key) = builder();
+        let (_, sender_public) = keypair_from_seed(1);
+        let (_, proxy_public) = keypair_from_seed(2);
+
+        // sign_proxy 在 settle 之前调用也按结算后的收据签名
+        let receipt = builder
+            .sign_proxy(&proxy_key)
+            .sign_sender(&sender_key)
+            .settle(U256::from(100u32), true)
+            .build()?;
+        assert!(receipt.settled);
+        assert_eq!(receipt.amount, U256::from(100u32));
+        assert_eq!(receipt.get_sender_address()?, get_ethereum_address(&sender_public));
+        assert_eq!(receipt.get_proxy_address()?, get_ethereum_address(&proxy_public));
+
+        // 与手工构建的收据相同
+        let mut payment = Payment::new(U256::from(7u32), 3, U256::from(100u32), [9u8; 20]);
+        payment.sign(&sender_key)?;
+        let mut manual = PaymentSettledByProxy::from(payment);
+        manual.set_settlement(U256::from(100u32), true);
+        manual.sign_by_proxy(&proxy_key)?;
+        assert_eq!(receipt.sig_sender, manual.sig_sender);
+        assert_eq!(receipt.sig_proxy, manual.sig_proxy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_with_domain() -> Result<(), BuildError> {
+        let (builder, sender_key, proxy_key) = builder();
+        let (_, proxy_public) = keypair_from_seed(2);
+        let domain = SigningDomain::new(1, [5u8; 20]);
+        let receipt = builder
+            .with_domain(domain)
+            .with_nonce(4)
+            .sign_sender(&sender_key)
+            .settle(U256::from(100u32), true)
+            .sign_proxy(&proxy_key)
+            .build()?;
+        assert_eq!(receipt.nonce, Some(4));
+        assert!(receipt.verify_proxy_signature_with_domain(&proxy_public, &domain)?);
+        assert!(!receipt.verify_proxy_signature(&proxy_public)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_refusals() {
+        let (builder, sender_key, proxy_key) = builder();
+        let amount = U256::from(100u32);
+
+        let missing_sender = builder.clone().settle(amount, true).sign_proxy(&proxy_key).build();
+        assert_eq!(missing_sender.unwrap_err(), BuildError::MissingSenderSignature);
+
+        let missing_proxy = builder.clone().sign_sender(&sender_key).settle(amount, true).build();
+        assert_eq!(missing_proxy.unwrap_err(), BuildError::MissingProxySignature);
+
+        // 忘记 settle
+        let unsettled = builder.clone().sign_sender(&sender_key).sign_proxy(&proxy_key).build();
+        assert_eq!(unsettled.unwrap_err(), BuildError::NotSettled);
+
+        let not_settled = builder.clone().sign_sender(&sender_key).settle(amount, false).sign_proxy(&proxy_key).build();
+        assert_eq!(not_settled.unwrap_err(), BuildError::NotSettled);
+
+        let changed = builder.clone().sign_sender(&sender_key).settle(U256::from(60u32), true).sign_proxy(&proxy_key);
+        assert_eq!(
+            changed.clone().build().unwrap_err(),
+            BuildError::AmountMismatch { signed: amount, settled: U256::from(60u32) }
+        );
+
+        // 显式允许后可以部分结算，发送者签名按授权金额验证
+        let partial = changed.allow_amount_change().build().unwrap();
+        assert_eq!(partial.amount, U256::from(60u32));
+        assert_eq!(partial.authorized_amount, Some(amount));
+        let (_, sender_public) = keypair_from_seed(1);
+        assert_eq!(partial.get_sender_address().unwrap(), get_ethereum_address(&sender_public));
+
+        // 结算金额不能超过授权金额
+        let exceeded = builder
+            .sign_sender(&sender_key)
+            .settle(U256::from(101u32), true)
+            .allow_amount_change()
+            .sign_proxy(&proxy_key)
+            .build();
+        assert_eq!(
+            exceeded.unwrap_err(),
+            BuildError::AmountMismatch { signed: amount, settled: U256::from(101u32) }
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use alloy_primitives::B256;
+use crate::{hash_with_domain, keccak256, BoxError, HashDomain};
+use super::overpay_checker::OverpayCheckResult;
+use super::EthAddress;
+
+/// 一轮代理结算中接收者集合的承诺
+///
+/// 布局（Solidity 端可直接照搬，与 OpenZeppelin MerkleProof.verify 兼容）：
+/// 1. 接收者按地址字节序升序排列并去重
+/// 2. 叶子 = keccak256(abi.encodePacked(receiver))，即 20 字节地址的哈希；
+///    v2-hashing 下为 keccak256(abi.encodePacked(uint8(0x05), receiver))
+/// 3. 每层相邻两个节点合并为 keccak256(min(a, b) ‖ max(a, b))，
+///    奇数个节点时最后一个节点原样上移，树深 ⌈log2 n⌉
+/// 4. 只有一个接收者时根就是叶子，空集合的根为 0
+///
+/// 因为合并时按大小排序，证明只需要兄弟节点列表，不需要左右方向
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiverSetCommitment {
+    receivers: Vec<EthAddress>,
+    levels: Vec<Vec<B256>>, // levels[0] 为叶子，最后一层只有根
+}
+
+impl ReceiverSetCommitment {
+    pub fn new(receivers: &[EthAddress]) -> Self {
+        let mut receivers = receivers.to_vec();
+        receivers.sort();
+        receivers.dedup();
+
+        let mut levels = vec![receivers.iter().map(leaf_hash).collect::<Vec<B256>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let next = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_pair(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { receivers, levels }
+    }
+
+    /// 从 OverpayCheckResult 的 receiver_proofs 构建
+    pub fn from_overpay_result(result: &OverpayCheckResult) -> Self {
+        let receivers: Vec<EthAddress> = result.receiver_proofs.iter().map(|proof| proof.receiver).collect();
+        Self::new(&receivers)
+    }
+
+    /// 排序去重后的接收者
+    pub fn receivers(&self) -> &[EthAddress] {
+        &self.receivers
+    }
+
+    pub fn root(&self) -> B256 {
+        self.levels[self.levels.len() - 1]
+            .first()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// 从叶子到根的兄弟节点，原样上移的层没有兄弟节点
+    pub fn inclusion_proof(&self, receiver: &EthAddress) -> Result<Vec<B256>, BoxError> {
+        let mut index = self
+            .receivers
+            .binary_search(receiver)
+            .map_err(|_| format!("Receiver {:?} not in receiver set", receiver))?;
+
+        let mut proof = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            }
+            index /= 2;
+        }
+        Ok(proof)
+    }
+
+    pub fn verify_inclusion(root: B256, receiver: &EthAddress, proof: &[B256]) -> bool {
+        let computed = proof
+            .iter()
+            .fold(leaf_hash(receiver), |current, sibling| hash_pair(&current, sibling));
+        computed == root
+    }
+}
+
+fn leaf_hash(receiver: &EthAddress) -> B256 {
+    hash_with_domain(HashDomain::ReceiverSet, &[receiver])
+}
+
+// 按大小排序后拼接，Solidity 端无需知道左右方向
+fn hash_pair(a: &B256, b: &B256) -> B256 {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    let mut packed = [0u8; 64];
+    packed[..32].copy_from_slice(low.as_slice());
+    packed[32..].copy_from_slice(high.as_slice());
+    B256::from(keccak256(&packed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receivers(n: u8) -> Vec<EthAddress> {
+        // 故意乱序，构建时应排序
+        (1..=n).rev().map(|i| [i; 20]).collect()
+    }
+
+    fn assert_all_included(set: &ReceiverSetCommitment) -> Result<(), BoxError> {
+        for receiver in set.receivers() {
+            let proof = set.inclusion_proof(receiver)?;
+            assert!(ReceiverSetCommitment::verify_inclusion(set.root(), receiver, &proof));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_receiver() -> Result<(), BoxError> {
+        let set = ReceiverSetCommitment::new(&receivers(1));
+        assert_eq!(set.root(), leaf_hash(&[1u8; 20]));
+        assert!(set.inclusion_proof(&[1u8; 20])?.is_empty());
+        assert_all_included(&set)
+    }
+
+    #[test]
+    fn test_two_receivers() -> Result<(), BoxError> {
+        let set = ReceiverSetCommitment::new(&receivers(2));
+        let expected = hash_pair(&leaf_hash(&[1u8; 20]), &leaf_hash(&[2u8; 20]));
+        assert_eq!(set.root(), expected);
+        assert_eq!(set.inclusion_proof(&[1u8; 20])?, vec![leaf_hash(&[2u8; 20])]);
+        assert_all_included(&set)
+    }
+
+    #[test]
+    fn test_odd_receivers() -> Result<(), BoxError> {
+        for n in [3u8, 5, 7, 9] {
+            let set = ReceiverSetCommitment::new(&receivers(n));
+            assert_eq!(set.receivers().len(), n as usize);
+            assert!(set.receivers().windows(2).all(|pair| pair[0] < pair[1]));
+            assert_all_included(&set)?;
+        }
+
+        // 3 个接收者：最后一个叶子在第一层原样上移，证明只有一个兄弟节点
+        let set = ReceiverSetCommitment::new(&receivers(3));
+        let left = hash_pair(&leaf_hash(&[1u8; 20]), &leaf_hash(&[2u8; 20]));
+        assert_eq!(set.root(), hash_pair(&left, &leaf_hash(&[3u8; 20])));
+        assert_eq!(set.inclusion_proof(&[3u8; 20])?, vec![left]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_and_duplicates_do_not_matter() {
+        let mut shuffled = receivers(5);
+        shuffled.swap(0, 3);
+        shuffled.push([2u8; 20]);
+        assert_eq!(
+            ReceiverSetCommitment::new(&shuffled).root(),
+            ReceiverSetCommitment::new(&receivers(5)).root()
+        );
+    }
+
+    #[test]
+    fn test_non_member_rejected() -> Result<(), BoxError> {
+        let set = ReceiverSetCommitment::new(&receivers(5));
+        let outsider = [0xAAu8; 20];
+        assert!(set.inclusion_proof(&outsider).is_err());
+
+        // 借用成员的证明也不能证明非成员
+        let proof = set.inclusion_proof(&[3u8; 20])?;
+        assert!(!ReceiverSetCommitment::verify_inclusion(set.root(), &outsider, &proof));
+        // 错误的根
+        assert!(!ReceiverSetCommitment::verify_inclusion(B256::repeat_byte(1), &[3u8; 20], &proof));
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_set() {
+        let set = ReceiverSetCommitment::new(&[]);
+        assert_eq!(set.root(), B256::ZERO);
+        assert!(set.inclusion_proof(&[1u8; 20]).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! 签名验证后不可修改的收据
+//!
+//! PaymentSettledByProxy 的字段都是公开的，签名后修改金额会得到签名不再匹配的收据。
+//! SealedReceipt 在构造时恢复两个签名者、与期望的发送者和代理比较并保存，之后只能读取；
+//! 检查器从 SealedReceipt 构造时直接使用保存的签名者，不再重复恢复签名。
+use alloy_primitives::B256;
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use super::{PaymentSettledByProxy, SigningDomain};
+use crate::{EthAddress, SignatureError};
+
+/// 两个签名都能恢复出签名者的收据，只提供读取访问
+#[derive(Debug, Clone)]
+pub struct SealedReceipt {
+    receipt: PaymentSettledByProxy,
+    sender: EthAddress,
+    proxy: EthAddress,
+    domain: Option<SigningDomain>,
+}
+
+impl PaymentSettledByProxy {
+    /// 恢复发送者和代理签名者并封存收据：任一签名无法恢复时返回 UnrecoverableSignature，
+    /// 签名者不是 expected_sender（对应 PayIdInfo.sender）或 expected_proxy 时返回 UnexpectedSigner，
+    /// 因此签名后被修改的收据无法封存
+    pub fn seal(self, expected_sender: EthAddress, expected_proxy: EthAddress) -> Result<SealedReceipt, SignatureError> {
+        SealedReceipt::new(self, expected_sender, expected_proxy, None)
+    }
+
+    /// 在指定签名域下封存
+    pub fn seal_with_domain(
+        self,
+        expected_sender: EthAddress,
+        expected_proxy: EthAddress,
+        domain: &SigningDomain,
+    ) -> Result<SealedReceipt, SignatureError> {
+        SealedReceipt::new(self, expected_sender, expected_proxy, Some(*domain))
+    }
+}
+
+impl SealedReceipt {
+    fn new(
+        receipt: PaymentSettledByProxy,
+        expected_sender: EthAddress,
+        expected_proxy: EthAddress,
+        domain: Option<SigningDomain>,
+    ) -> Result<Self, SignatureError> {
+        let sender = receipt
+            .get_sender_address_in(domain.as_ref())
+            .map_err(|_| SignatureError::UnrecoverableSignature("sender"))?;
+        check_signer("sender", expected_sender, sender)?;
+        let proxy = receipt
+            .get_proxy_address_in(domain.as_ref())
+            .map_err(|_| SignatureError::UnrecoverableSignature("proxy"))?;
+        check_signer("proxy", expected_proxy, proxy)?;
+        Ok(Self { receipt, sender, proxy, domain })
+    }
+
+    pub fn receipt(&self) -> &PaymentSettledByProxy {
+        &self.receipt
+    }
+
+    /// 发送者签名恢复出的地址
+    pub fn sender(&self) -> EthAddress {
+        self.sender
+    }
+
+    /// 代理签名恢复出的地址
+    pub fn proxy(&self) -> EthAddress {
+        self.proxy
+    }
+
+    /// 封存时使用的签名域
+    pub fn domain(&self) -> Option<&SigningDomain> {
+        self.domain.as_ref()
+    }
+
+    pub fn into_inner(self) -> PaymentSettledByProxy {
+        self.receipt
+    }
+}
+
+fn check_signer(signer: &'static str, expected: EthAddress, actual: EthAddress) -> Result<(), SignatureError> {
+    if expected != actual {
+        return Err(SignatureError::UnexpectedSigner { signer, expected, actual });
+    }
+    Ok(())
+}
+
+impl Deref for SealedReceipt {
+    type Target = PaymentSettledByProxy;
+
+    fn deref(&self) -> &PaymentSettledByProxy {
+        &self.receipt
+    }
+}
+
+/// 按收据哈希查找封存时恢复的签名者，签名域不同或没有封存的收据仍然恢复签名
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SealedSigners {
+    signers: HashMap<B256, (EthAddress, EthAddress, Option<SigningDomain>)>, // (sender, proxy, domain)
+}
+
+impl SealedSigners {
+    pub(crate) fn new(sealed: &[SealedReceipt]) -> Self {
+        let signers = sealed
+            .iter()
+            .map(|sealed| (sealed.receipt.hash(), (sealed.sender, sealed.proxy, sealed.domain)))
+            .collect();
+        Self { signers }
+    }
+
+    fn lookup(&self, receipt: &PaymentSettledByProxy, domain: Option<&SigningDomain>) -> Option<(EthAddress, EthAddress)> {
+        if self.signers.is_empty() {
+            return None;
+        }
+        match self.signers.get(&receipt.hash()) {
+            Some((sender, proxy, sealed_domain)) if sealed_domain.as_ref() == domain => Some((*sender, *proxy)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn sender_address(&self, receipt: &PaymentSettledByProxy, domain: Option<&SigningDomain>) -> Option<EthAddress> {
+        match self.lookup(receipt, domain) {
+            Some((sender, _)) => Some(sender),
+            None => receipt.get_sender_address_in(domain).ok(),
+        }
+    }
+
+    pub(crate) fn proxy_address(&self, receipt: &PaymentSettledByProxy, domain: Option<&SigningDomain>) -> Option<EthAddress> {
+        match self.lookup(receipt, domain) {
+            Some((_, proxy)) => Some(proxy),
+            None => receipt.get_proxy_address_in(domain).ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{Scenario, ScenarioBuilder};
+    use crate::receipts::profit_calculator::ReceiptsProfitCalculator;
+    use crate::{BoxError, ReceiptsOverpayChecker};
+    use alloy_primitives::U256;
+
+    // 按收据的 pay_id 找到发送者，代理为场景的 channel
+    fn seal(scenario: &Scenario, receipt: PaymentSettledByProxy) -> Result<SealedReceipt, SignatureError> {
+        let sender = scenario.pay_id_infos.iter().find(|info| info.id == receipt.pay_id).expect("known pay_id").sender;
+        receipt.seal(sender, scenario.proxy)
+    }
+
+    #[test]
+    fn test_seal() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(41).with_payment(1, 1, 0, 1000).build()?;
+        let receipt = scenario.receipts[0].clone();
+        let sender = scenario.pay_id_infos[0].sender;
+
+        let sealed = seal(&scenario, receipt.clone())?;
+        assert_eq!(sealed.proxy(), scenario.proxy);
+        assert_eq!(sealed.sender(), sender);
+        assert_eq!(sealed.amount, receipt.amount);
+        assert_eq!(sealed.into_inner().hash(), receipt.hash());
+
+        // 签名被篡改，无法恢复签名者
+        let mut tampered = receipt.clone();
+        tampered.sig_proxy[64] = 7;
+        assert_eq!(seal(&scenario, tampered).unwrap_err(), SignatureError::UnrecoverableSignature("proxy"));
+        let mut tampered = receipt.clone();
+        tampered.sig_sender = [0u8; 65];
+        assert_eq!(seal(&scenario, tampered).unwrap_err(), SignatureError::UnrecoverableSignature("sender"));
+
+        // 签名后修改金额：签名仍能恢复，但恢复出的不是期望的发送者
+        let mut tampered = receipt.clone();
+        tampered.amount = U256::from(999);
+        let err = seal(&scenario, tampered).unwrap_err();
+        assert!(matches!(err, SignatureError::UnexpectedSigner { signer: "sender", expected, .. } if expected == sender));
+
+        // 期望的代理不同
+        let err = receipt.clone().seal(sender, [7u8; 20]).unwrap_err();
+        assert_eq!(
+            err,
+            SignatureError::UnexpectedSigner { signer: "proxy", expected: [7u8; 20], actual: scenario.proxy }
+        );
+        assert!(err.to_string().starts_with("Unexpected proxy signer"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sealed_pipelines_match() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(42)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 1000)
+            .with_payment(2, 2, 1, 500)
+            .with_payment(3, 1, 0, 700)
+            .build()?;
+        let sealed: Vec<SealedReceipt> =
+            scenario.receipts.iter().cloned().map(|receipt| seal(&scenario, receipt)).collect::<Result<_, _>>()?;
+
+        let overpay = scenario.overpay_checker().process()?;
+        let sealed_overpay = ReceiptsOverpayChecker::from_sealed(scenario.proxy, scenario.pay_id_infos.clone(), &sealed)
+            .with_proxy_signature_check()
+            .with_sender_signature_check()
+            .process()?;
+        assert_eq!(sealed_overpay, overpay);
+
+        for receiver in scenario.receivers.clone() {
+            let proof = overpay.get_merkle_proof(receiver)?;
+            let expected = scenario.profit_calculator(receiver, proof.clone()).calculate()?;
+            let receipts: Vec<SealedReceipt> = sealed.iter().filter(|sealed| sealed.receiver == receiver).cloned().collect();
+            let actual = ReceiptsProfitCalculator::from_sealed(
+                B256::ZERO,
+                receiver,
+                scenario.proxy,
+                &receipts,
+                proof,
+                scenario.pay_id_infos.clone(),
+                scenario.service_configs.clone(),
+            )
+            .with_epoch(scenario.epoch)
+            .calculate()?;
+            assert_eq!(actual, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sealed_signers_still_checked() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(43).with_payment(1, 1, 0, 1000).build()?;
+
+        // 签名后修改金额，按恢复出的签名者封存：检查器仍拒绝不是 channel 的代理
+        let mut modified = scenario.receipts[0].clone();
+        modified.amount = U256::from(999);
+        let sender = modified.get_sender_address_in(None)?;
+        let proxy = modified.get_proxy_address_in(None)?;
+        let sealed = vec![modified.seal(sender, proxy)?];
+        assert_ne!(sealed[0].proxy(), scenario.proxy);
+        let mut pay_id_infos = scenario.pay_id_infos.clone();
+        pay_id_infos[0].amount = U256::from(999);
+        let err = ReceiptsOverpayChecker::from_sealed(scenario.proxy, pay_id_infos, &sealed)
+            .with_proxy_signature_check()
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid proxy signature"));
+
+        // 封存时的签名域与检查器不同，按检查器的签名域重新恢复
+        let domain = SigningDomain::new(1, [5u8; 20]);
+        let sealed = vec![seal(&scenario, scenario.receipts[0].clone())?];
+        let err = ReceiptsOverpayChecker::from_sealed(scenario.proxy, scenario.pay_id_infos.clone(), &sealed)
+            .with_signing_domain(domain)
+            .with_proxy_signature_check()
+            .process()
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid proxy signature"));
+        Ok(())
+    }
+}
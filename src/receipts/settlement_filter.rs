@@ -0,0 +1,80 @@
+use alloy_primitives::U256;
+use std::collections::BTreeMap;
+
+use super::{AmountOverflow, EthAddress, PaymentSettledByProxy};
+
+/// 把未结算（settled = false）的收据从批次中分出
+///
+/// 代理在分组和报表中保留未结算的收据，但只有已结算的收据进入承诺的根和超付统计。
+/// ReceiptsOverpayChecker::process_ignoring_unsettled 使用该阶段，其他入口仍然拒绝未结算的收据。
+pub struct SettlementFilter;
+
+impl SettlementFilter {
+    /// (已结算, 未结算)，两部分都保持输入顺序
+    pub fn partition_by_settled(
+        payments: Vec<PaymentSettledByProxy>,
+    ) -> (Vec<PaymentSettledByProxy>, Vec<PaymentSettledByProxy>) {
+        payments.into_iter().partition(|payment| payment.settled)
+    }
+}
+
+/// 未结算收据的汇总
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnsettledReport {
+    pub count: usize,
+    pub total_amount: U256,
+    /// 每个接收者的未结算金额，按 receiver 升序
+    pub by_receiver: Vec<(EthAddress, U256)>,
+}
+
+impl UnsettledReport {
+    /// 汇总 unsettled 中的收据，不检查 settled 标志
+    pub fn from_receipts(unsettled: &[PaymentSettledByProxy]) -> Result<Self, AmountOverflow> {
+        let mut by_receiver: BTreeMap<EthAddress, U256> = BTreeMap::new();
+        let mut total_amount = U256::ZERO;
+        for payment in unsettled {
+            let overflow = || AmountOverflow::Receiver(payment.receiver);
+            let amount = by_receiver.entry(payment.receiver).or_default();
+            *amount = amount.checked_add(payment.amount).ok_or_else(overflow)?;
+            total_amount = total_amount.checked_add(payment.amount).ok_or_else(overflow)?;
+        }
+        Ok(Self { count: unsettled.len(), total_amount, by_receiver: by_receiver.into_iter().collect() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_payment(pay_id: u64, receiver: u8, amount: u64, settled: bool) -> PaymentSettledByProxy {
+        PaymentSettledByProxy::new(U256::from(pay_id), 1, U256::from(amount), [receiver; 20]).with_settled(settled)
+    }
+
+    #[test]
+    fn test_partition_and_report() {
+        let payments = vec![
+            create_test_payment(1, 2, 100, true),
+            create_test_payment(2, 2, 30, false),
+            create_test_payment(3, 1, 50, false),
+            create_test_payment(4, 2, 20, false),
+            create_test_payment(5, 1, 70, true),
+        ];
+        let (settled, unsettled) = SettlementFilter::partition_by_settled(payments);
+        assert_eq!(settled.iter().map(|p| p.pay_id.to::<u64>()).collect::<Vec<_>>(), vec![1, 5]);
+        assert_eq!(unsettled.iter().map(|p| p.pay_id.to::<u64>()).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let report = UnsettledReport::from_receipts(&unsettled).unwrap();
+        assert_eq!(report.count, 3);
+        assert_eq!(report.total_amount, U256::from(100));
+        assert_eq!(report.by_receiver, vec![([1u8; 20], U256::from(50)), ([2u8; 20], U256::from(50))]);
+        assert!(UnsettledReport::from_receipts(&[]).unwrap().is_empty());
+
+        let overflow = [create_test_payment(1, 3, 1, false), create_test_payment(2, 3, 0, false)]
+            .map(|payment| PaymentSettledByProxy { amount: U256::MAX, ..payment });
+        assert_eq!(UnsettledReport::from_receipts(&overflow), Err(AmountOverflow::Receiver([3u8; 20])));
+    }
+}
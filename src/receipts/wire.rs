@@ -0,0 +1,244 @@
+//! guest stdin 中收据的字节布局，host（Rust、Go）按此写入，guest 的 read_from_stdin_with 按此解析
+//!
+//! host 先写入 ReceiptStdinLayout::bits()（一个 u8 帧），之后每张收据作为一个 stdin 帧写入。
+//! 整数为小端序，U256 为 32 字节大端序，地址和签名为原始字节，bool 为一个字节（0 或 1），
+//! Option 为标记字节（0 为 None，1 为 Some）之后是值。字段依次为：
+//!
+//! | 字段              | 类型                  | 条件                           |
+//! |-------------------|-----------------------|--------------------------------|
+//! | pay_id            | 32 字节               |                                |
+//! | serv_id           | u32                   |                                |
+//! | amount            | 32 字节               |                                |
+//! | receiver          | 20 字节               |                                |
+//! | sig_sender        | 65 字节               |                                |
+//! | settled           | bool                  |                                |
+//! | sig_proxy         | 65 字节               |                                |
+//! | nonce             | 标记 + u64            | layout.has_nonce()             |
+//! | valid_until       | 标记 + u64            | layout.has_valid_until()       |
+//! | token             | 20 字节               | layout.has_token()             |
+//! | authorized_amount | 标记 + 32 字节        | layout.has_authorized_amount() |
+//!
+//! LEGACY 布局的收据为 219 字节。布局中没有的字段解码为默认值；编码时这些字段有值则返回
+//! WireError::NotInLayout，而不是悄悄丢弃。解码出等于 amount 的 authorized_amount 规范化为 None。
+//! 布局变化时必须同步更新 tests/fixtures/receipt_*.bin，测试与这些文件逐字节比较
+use alloy_primitives::U256;
+
+use super::{partial_authorized_amount, PaymentSettledByProxy, ReceiptStdinLayout};
+use crate::models::wire::{decode_frame, read_array, read_bool, read_option, read_u32, read_u64, write_option, WireError};
+use crate::NATIVE_TOKEN;
+
+fn read_u256(input: &mut &[u8], field: &'static str) -> Result<U256, WireError> {
+    Ok(U256::from_be_bytes(read_array::<32>(input, field)?))
+}
+
+fn write_u256(out: &mut Vec<u8>, value: U256) {
+    out.extend_from_slice(&value.to_be_bytes::<32>());
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+impl PaymentSettledByProxy {
+    /// 按 layout 编码，布局中没有的字段有值时返回 WireError::NotInLayout
+    pub fn encode_stdin(&self, layout: ReceiptStdinLayout, out: &mut Vec<u8>) -> Result<(), WireError> {
+        let missing = [
+            ("nonce", self.nonce.is_some() && !layout.has_nonce()),
+            ("valid_until", self.valid_until.is_some() && !layout.has_valid_until()),
+            ("token", self.token != NATIVE_TOKEN && !layout.has_token()),
+            ("authorized_amount", self.authorized_amount.is_some() && !layout.has_authorized_amount()),
+        ];
+        if let Some(&(field, _)) = missing.iter().find(|(_, missing)| *missing) {
+            return Err(WireError::NotInLayout { field });
+        }
+
+        write_u256(out, self.pay_id);
+        out.extend_from_slice(&self.serv_id.to_le_bytes());
+        write_u256(out, self.amount);
+        out.extend_from_slice(&self.receiver);
+        out.extend_from_slice(&self.sig_sender);
+        out.push(u8::from(self.settled));
+        out.extend_from_slice(&self.sig_proxy);
+        if layout.has_nonce() {
+            write_option(out, self.nonce, write_u64);
+        }
+        if layout.has_valid_until() {
+            write_option(out, self.valid_until, write_u64);
+        }
+        if layout.has_token() {
+            out.extend_from_slice(&self.token);
+        }
+        if layout.has_authorized_amount() {
+            write_option(out, self.authorized_amount, write_u256);
+        }
+        Ok(())
+    }
+
+    /// 从 input 开头按 layout 解码一张收据并前移 input
+    pub fn decode_stdin(layout: ReceiptStdinLayout, input: &mut &[u8]) -> Result<Self, WireError> {
+        let pay_id = read_u256(input, "pay id")?;
+        let serv_id = read_u32(input, "serv id")?;
+        let amount = read_u256(input, "amount")?;
+        Ok(Self {
+            pay_id,
+            serv_id,
+            amount,
+            receiver: read_array(input, "receiver")?,
+            sig_sender: read_array(input, "sender signature")?,
+            settled: read_bool(input, "settled")?,
+            sig_proxy: read_array(input, "proxy signature")?,
+            nonce: if layout.has_nonce() { read_option(input, "nonce", read_u64)? } else { None },
+            valid_until: if layout.has_valid_until() { read_option(input, "valid until", read_u64)? } else { None },
+            token: if layout.has_token() { read_array(input, "token")? } else { NATIVE_TOKEN },
+            authorized_amount: if layout.has_authorized_amount() {
+                partial_authorized_amount(amount, read_option(input, "authorized amount", read_u256)?)
+            } else {
+                None
+            },
+        })
+    }
+
+    /// host 写入 stdin 的一帧，guest 用 read_from_stdin_with(layout) 读取
+    pub fn to_stdin_bytes(&self, layout: ReceiptStdinLayout) -> Result<Vec<u8>, WireError> {
+        let mut out = Vec::new();
+        self.encode_stdin(layout, &mut out)?;
+        Ok(out)
+    }
+
+    /// 解码 to_stdin_bytes 的输出，之后有多余字节时返回 WireError::TrailingBytes
+    pub fn from_stdin_bytes(layout: ReceiptStdinLayout, bytes: &[u8]) -> Result<Self, WireError> {
+        decode_frame(bytes, |input| Self::decode_stdin(layout, input))
+    }
+}
+
+/// host 依次写入的 stdin 帧：for_receipts 选出的布局（一个字节），之后每张收据一帧
+pub fn receipt_stdin_frames(receipts: &[PaymentSettledByProxy]) -> Result<Vec<Vec<u8>>, WireError> {
+    let layout = ReceiptStdinLayout::for_receipts(receipts);
+    let mut frames = Vec::with_capacity(receipts.len() + 1);
+    frames.push(vec![layout.bits()]);
+    for receipt in receipts {
+        frames.push(receipt.to_stdin_bytes(layout)?);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ScenarioBuilder;
+    use crate::BoxError;
+
+    // 布局变化时重新生成这些文件，审查时可以看到
+    const LEGACY_BIN: &[u8] = include_bytes!("../../tests/fixtures/receipt_legacy.bin");
+    const FULL_BIN: &[u8] = include_bytes!("../../tests/fixtures/receipt_full.bin");
+
+    fn legacy_receipt() -> PaymentSettledByProxy {
+        PaymentSettledByProxy {
+            pay_id: U256::from(1),
+            serv_id: 7,
+            amount: U256::from(1000),
+            receiver: [0x11; 20],
+            sig_sender: [0x22; 65],
+            settled: true,
+            sig_proxy: [0x33; 65],
+            nonce: None,
+            valid_until: None,
+            token: NATIVE_TOKEN,
+            authorized_amount: None,
+        }
+    }
+
+    fn full_receipt() -> PaymentSettledByProxy {
+        PaymentSettledByProxy {
+            nonce: Some(5),
+            token: [0x44; 20],
+            authorized_amount: Some(U256::from(1500)),
+            ..legacy_receipt()
+        }
+    }
+
+    fn full_layout() -> ReceiptStdinLayout {
+        ReceiptStdinLayout::LEGACY.with_token().with_nonce().with_valid_until().with_authorized_amount()
+    }
+
+    #[test]
+    fn test_fixtures() -> Result<(), BoxError> {
+        for (bytes, layout, receipt) in
+            [(LEGACY_BIN, ReceiptStdinLayout::LEGACY, legacy_receipt()), (FULL_BIN, full_layout(), full_receipt())]
+        {
+            let decoded = PaymentSettledByProxy::from_stdin_bytes(layout, bytes)?;
+            assert_eq!(decoded.hash(), receipt.hash());
+            assert_eq!((decoded.nonce, decoded.valid_until, decoded.token), (receipt.nonce, receipt.valid_until, receipt.token));
+            assert_eq!(decoded.authorized_amount, receipt.authorized_amount);
+            assert_eq!(receipt.to_stdin_bytes(layout)?, bytes);
+        }
+
+        // 固定字段的位置：serv_id 紧跟 pay_id，settled 在两个签名之间，新增字段追加在最后
+        assert_eq!(LEGACY_BIN.len(), 219);
+        assert_eq!(&LEGACY_BIN[32..36], &[7, 0, 0, 0]);
+        assert_eq!(LEGACY_BIN[153], 1);
+        assert_eq!(&FULL_BIN[..219], LEGACY_BIN);
+        assert_eq!(&FULL_BIN[219..229], &[1, 5, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(FULL_BIN.len(), 219 + 9 + 1 + 20 + 33);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scenario_round_trip() -> Result<(), BoxError> {
+        let scenario = ScenarioBuilder::new(3)
+            .with_receivers(2)
+            .with_payment(1, 1, 0, 400)
+            .with_payment(2, 1, 1, 700)
+            .with_token(2, [0x44; 20])
+            .with_fee_config(1, 500, 1000)
+            .build()?;
+        let receipts = &scenario.receipts;
+        let frames = receipt_stdin_frames(receipts)?;
+        assert_eq!(frames.len(), receipts.len() + 1);
+        let layout = ReceiptStdinLayout::from_bits(frames[0][0]).ok_or("unknown layout")?;
+        assert_eq!(layout, ReceiptStdinLayout::LEGACY.with_token());
+        for (frame, receipt) in frames[1..].iter().zip(receipts) {
+            let decoded = PaymentSettledByProxy::from_stdin_bytes(layout, frame)?;
+            assert_eq!(decoded.hash(), receipt.hash());
+            assert_eq!(decoded.sig_sender, receipt.sig_sender);
+            assert_eq!(decoded.sig_proxy, receipt.sig_proxy);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        // 布局中没有的字段不能悄悄丢弃
+        let err = full_receipt().to_stdin_bytes(ReceiptStdinLayout::LEGACY.with_token()).unwrap_err();
+        assert_eq!(err, WireError::NotInLayout { field: "nonce" });
+        let layout = ReceiptStdinLayout::LEGACY.with_nonce().with_valid_until().with_authorized_amount();
+        assert_eq!(full_receipt().to_stdin_bytes(layout).unwrap_err(), WireError::NotInLayout { field: "token" });
+
+        // 截断和多余的字节
+        for len in [0, 31, 36, 218] {
+            let err = PaymentSettledByProxy::from_stdin_bytes(ReceiptStdinLayout::LEGACY, &LEGACY_BIN[..len]).unwrap_err();
+            assert!(matches!(err, WireError::UnexpectedEnd { .. }), "{}: {:?}", len, err);
+        }
+        assert_eq!(
+            PaymentSettledByProxy::from_stdin_bytes(ReceiptStdinLayout::LEGACY, FULL_BIN).unwrap_err(),
+            WireError::TrailingBytes(FULL_BIN.len() - 219)
+        );
+
+        // bool 和 Option 的标记只能是 0 或 1
+        let mut bytes = LEGACY_BIN.to_vec();
+        bytes[153] = 2;
+        let err = PaymentSettledByProxy::from_stdin_bytes(ReceiptStdinLayout::LEGACY, &bytes).unwrap_err();
+        assert_eq!(err, WireError::InvalidValue { field: "settled" });
+        let mut bytes = FULL_BIN.to_vec();
+        bytes[219] = 2;
+        let err = PaymentSettledByProxy::from_stdin_bytes(full_layout(), &bytes).unwrap_err();
+        assert_eq!(err, WireError::InvalidValue { field: "nonce" });
+
+        // 等于结算金额的授权金额规范化为 None
+        let mut bytes = LEGACY_BIN.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&U256::from(1000).to_be_bytes::<32>());
+        let layout = ReceiptStdinLayout::LEGACY.with_authorized_amount();
+        assert_eq!(PaymentSettledByProxy::from_stdin_bytes(layout, &bytes).unwrap().authorized_amount, None);
+    }
+}
@@ -7,38 +7,194 @@
  * 2. ProfitResult
  * 
  * 
+ * 3. 该代理结算的 settlement_id
+ * 
  * 处理过程如下：
  * 1. 验证Vec<PaymentSettledByProxy>的哈希根与ProfitResult.receipts_root 一致
  * 2. 累计所有的ProfitResult中的receiver_profit得到结果
+ * 3. 按处理顺序链接 settlement_id 得到 settlement_root
+ * 4. 提供了费率配置或代理的 PayIdInfo 时，重新计算 serv_ids_root / pay_ids_root 并与 ProfitResult 比较
+ * 5. 提供了 accepted_roots 时，receipts_root 必须在近期发布的根中，或能用历史证明证明；
+ *    settlement_id 由调用方提供，与 ProfitResult 没有绑定，不能作为锚点
  * 
- * 返回累计的结果
+ * 返回累计的结果 ReceiverSettleResult
  */
 
  use alloy_primitives::{Address, B256, U256};
+use std::collections::{BTreeMap, HashMap};
+use crate::addr::AlloyAddressExt;
+use crate::models::{CircularHashStore, PayIdInfo, ServiceFeeConfig};
+use crate::receipts::AmountOverflow;
 use crate::{
-    keccak256, keccak256_more, BoxError, PaymentSettledByProxy, ProfitResult
+    keccak256_chain, keccak256_concat, settlement_history_step, BoxError, EthAddress, PaymentSettledByProxy, ProfitResult,
+    ReceiverSettleResult, TokenSubtotal,
 };
 
+/// ProfitResult 没有锚定在近期发布的结算根上：receipts_root 不在 accepted_roots 中，
+/// 也不能用提供的历史证明证明
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleSettlement {
+    pub receipts_root: B256,
+}
+
+impl std::fmt::Display for StaleSettlement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Stale settlement: receipts_root {} is not in the accepted roots",
+            self.receipts_root
+        )
+    }
+}
+
+impl std::error::Error for StaleSettlement {}
+
+/// process_many 的一项：一个代理结算的数据
+#[derive(Debug, Clone)]
+pub struct ProxySettlementInput {
+    pub payments: Vec<PaymentSettledByProxy>,
+    pub profit_result: ProfitResult,
+    pub settlement_id: B256,
+    pub history_proof: Vec<B256>, // 根已被挤出 accepted_roots 时的历史证明，通常为空
+}
+
+impl ProxySettlementInput {
+    pub fn new(payments: Vec<PaymentSettledByProxy>, profit_result: ProfitResult, settlement_id: B256) -> Self {
+        Self { payments, profit_result, settlement_id, history_proof: Vec::new() }
+    }
+
+    pub fn with_history_proof(mut self, history_proof: Vec<B256>) -> Self {
+        self.history_proof = history_proof;
+        self
+    }
+}
+
+/// process_many 已应用的结算
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReceiverBatchReport {
+    pub applied: usize,
+    pub proxy_subtotals: BTreeMap<EthAddress, U256>, // 本批次每个代理的 receiver_profit 之和
+    pub batch_total: U256,                           // 本批次的 receiver_profit 之和
+    pub total_profit: U256,                          // 结算器累计的原生代币总利润，包括之前处理的结算
+    pub receipts_roots: Vec<B256>,                   // 已接受的 receipts_root，按处理顺序
+}
+
+impl ReceiverBatchReport {
+    // 不会溢出：每一项都已经计入了 checked_add 过的 total_profit
+    fn record(&mut self, profit_result: &ProfitResult) {
+        self.applied += 1;
+        *self.proxy_subtotals.entry(profit_result.proxy).or_default() += profit_result.receiver_profit;
+        self.batch_total += profit_result.receiver_profit;
+        self.receipts_roots.push(profit_result.receipts_root);
+    }
+}
+
+/// process_many 在第 index 项失败，之前的结算已经应用并记录在 report 中，之后的没有处理
+#[derive(Debug)]
+pub struct ReceiverBatchAborted {
+    pub index: usize,
+    pub report: ReceiverBatchReport,
+    pub source: BoxError,
+}
+
+impl std::fmt::Display for ReceiverBatchAborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Proxy settlement #{} failed after {} applied: {}",
+            self.index, self.report.applied, self.source
+        )
+    }
+}
+
+impl std::error::Error for ReceiverBatchAborted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
 /// 接收者结算器
 pub struct ReceiverSettler {
-    receiver: Address,
-    total_profit: U256,
+    receiver: EthAddress,     // 与 ProfitResult.receiver 相同的表示
+    total_profit: U256,       // 原生代币的 receiver_profit 之和，与 ProfitResult 的总额一样不包括其他代币
+    token_totals: Vec<TokenSubtotal>, // 按代币累计的小计，不同代币的金额分开
+    vks_hash: Option<B256>,   // 所有 ProfitResult 必须来自同一个 guest 程序
+    settlement_root: B256,    // 已处理的 settlement_id 链，从 B256::ZERO 开始
+    fee_configs: Option<Vec<ServiceFeeConfig>>,        // 提供时检查 serv_ids_root
+    pay_id_infos: HashMap<EthAddress, Vec<PayIdInfo>>, // 按代理提供时检查 pay_ids_root
+    accepted_roots: Option<CircularHashStore>,         // 提供时只接受近期发布的 receipts_root / settlement_id
 }
 
 impl ReceiverSettler {
     /// 创建新的接收者结算器
     pub fn new(receiver: Address) -> Self {
         Self {
-            receiver,
+            receiver: receiver.to_eth(),
+            total_profit: U256::ZERO,
+            token_totals: Vec::new(),
+            vks_hash: None,
+            settlement_root: B256::ZERO,
+            fee_configs: None,
+            pay_id_infos: HashMap::new(),
+            accepted_roots: None,
+        }
+    }
+
+    /// 创建只接受指定 vks_hash 的接收者结算器
+    pub fn with_vks_hash(receiver: Address, vks_hash: B256) -> Self {
+        Self {
+            receiver: receiver.to_eth(),
             total_profit: U256::ZERO,
+            token_totals: Vec::new(),
+            vks_hash: Some(vks_hash),
+            settlement_root: B256::ZERO,
+            fee_configs: None,
+            pay_id_infos: HashMap::new(),
+            accepted_roots: None,
+        }
+    }
+
+    /// 创建只接受近期结算根的接收者结算器，store 由调用方写入最近发布的 receipts_root
+    pub fn with_accepted_roots(receiver: Address, store: CircularHashStore) -> Self {
+        Self {
+            accepted_roots: Some(store),
+            ..Self::new(receiver)
         }
     }
 
-    /// 处理来自一个代理的结算数据
+    /// 提供费率配置后，每个 ProfitResult 的 serv_ids_root 必须由这组配置计算得到
+    pub fn with_fee_configs(mut self, configs: Vec<ServiceFeeConfig>) -> Self {
+        self.fee_configs = Some(configs);
+        self
+    }
+
+    /// 提供某个代理的 PayIdInfo 后，该代理的 ProfitResult 的 pay_ids_root 必须由这组 PayIdInfo 计算得到
+    pub fn with_pay_id_infos(mut self, proxy: Address, infos: Vec<PayIdInfo>) -> Self {
+        self.pay_id_infos.insert(proxy.to_eth(), infos);
+        self
+    }
+
+    /// 处理来自一个代理的结算数据，settlement_id 为该代理结算（ProxySettlementResult）的 id
+    ///
+    /// 成功后 settlement_root = keccak256(settlement_root ‖ settlement_id)，失败时不链接。
+    /// 链与处理顺序有关，合约按同样的顺序重放 settlement_id 才能检查跨代理的重复领取
     pub fn process_proxy_settlement(
         &mut self,
         payments: &[PaymentSettledByProxy],
         profit_result: &ProfitResult,
+        settlement_id: B256,
+    ) -> Result<(), BoxError> {
+        self.process_proxy_settlement_with_history(payments, profit_result, settlement_id, &[])
+    }
+
+    /// 与 process_proxy_settlement 相同，根已被挤出 accepted_roots 时用 history_proof 证明
+    /// （CircularHashStore::check_hash 的历史证明）
+    pub fn process_proxy_settlement_with_history(
+        &mut self,
+        payments: &[PaymentSettledByProxy],
+        profit_result: &ProfitResult,
+        settlement_id: B256,
+        history_proof: &[B256],
     ) -> Result<(), BoxError> {
         // 1. 验证支付列表的哈希根与 ProfitResult 中的 receipts_root 一致
         let calculated_root = self.calculate_payments_root(payments);
@@ -47,51 +203,145 @@ impl ReceiverSettler {
         }
 
         // 2. 验证接收者地址匹配
-        if self.receiver != Address::from_slice(&profit_result.receiver) {
+        if self.receiver != profit_result.receiver {
             return Err("Receiver mismatch".into());
         }
 
-        // 3. 累加接收者利润
-        self.total_profit = self.total_profit
+        // 3. 结算必须锚定在近期发布的根上；只检查结果自身的 receipts_root，
+        //    调用方提供的 settlement_id 可以与任意旧结果配对，不能用于判断新旧
+        if let Some(store) = &self.accepted_roots {
+            if !store.check_hash(profit_result.receipts_root, history_proof) {
+                return Err(StaleSettlement { receipts_root: profit_result.receipts_root }.into());
+            }
+        }
+
+        // 4. 有原始数据时检查费率和 pay_id 的承诺
+        if let Some(configs) = &self.fee_configs {
+            if !profit_result.verify_fee_schedule(configs) {
+                return Err("serv_ids_root mismatch".into());
+            }
+        }
+        if let Some(infos) = self.pay_id_infos.get(&profit_result.proxy) {
+            if !profit_result.verify_pay_ids(infos) {
+                return Err("pay_ids_root mismatch".into());
+            }
+        }
+
+        // 5. 验证 vks_hash 一致，未指定时以第一个被接受的结果为准
+        if self.vks_hash.is_some_and(|vks_hash| vks_hash != profit_result.vks_hash) {
+            return Err("vks_hash mismatch".into());
+        }
+
+        // 6. 累加接收者利润，两项都不溢出时才更新
+        let total_profit = self.total_profit
             .checked_add(profit_result.receiver_profit)
-            .ok_or("Profit overflow")?;
+            .ok_or(AmountOverflow::Receiver(profit_result.receiver))?;
+        let mut token_totals = self.token_totals.clone();
+        for subtotal in &profit_result.token_subtotals() {
+            TokenSubtotal::accumulate(&mut token_totals, subtotal)
+                .map_err(|_| AmountOverflow::Receiver(profit_result.receiver))?;
+        }
+
+        // 7. 所有检查都通过后一起提交 vks_hash、利润和 settlement_id 的链接，失败的结果不改变状态
+        self.vks_hash = Some(profit_result.vks_hash);
+        self.total_profit = total_profit;
+        self.token_totals = token_totals;
+        self.settlement_root = settlement_history_step(self.settlement_root, settlement_id);
 
         Ok(())
     }
 
+    /// 依次处理多个代理结算，遇到第一个错误时停止
+    ///
+    /// 失败时返回 ReceiverBatchAborted，其中的 report 记录已应用的结算；失败的一项与
+    /// process_proxy_settlement 一样不计入利润、不链接 settlement_id，之后的项不处理
+    pub fn process_many(
+        &mut self,
+        items: impl IntoIterator<Item = ProxySettlementInput>,
+    ) -> Result<ReceiverBatchReport, BoxError> {
+        let mut report = ReceiverBatchReport::default();
+        for (index, item) in items.into_iter().enumerate() {
+            let processed = self.process_proxy_settlement_with_history(
+                &item.payments,
+                &item.profit_result,
+                item.settlement_id,
+                &item.history_proof,
+            );
+            if let Err(source) = processed {
+                report.total_profit = self.total_profit;
+                return Err(ReceiverBatchAborted { index, report, source }.into());
+            }
+            report.record(&item.profit_result);
+        }
+        report.total_profit = self.total_profit;
+        Ok(report)
+    }
+
     /// 计算支付列表的哈希根
     fn calculate_payments_root(&self, payments: &[PaymentSettledByProxy]) -> B256 {
         let mut current_hash = B256::ZERO;
         
         for payment in payments {
-            // 序列化支付数据
-            let mut data = Vec::new();
-            data.extend_from_slice(&payment.pay_id.to_be_bytes::<32>());
-            data.extend_from_slice(&payment.serv_id.to_be_bytes());
-            data.extend_from_slice(&payment.amount.to_be_bytes::<32>());
-            data.extend_from_slice(&payment.receiver);
-            data.extend_from_slice(&payment.sig_sender);
-            data.extend_from_slice(&[payment.settled as u8]);
-            data.extend_from_slice(&payment.sig_proxy);
-
-            // 计算当前支付的哈希，并更新累积哈希
-            current_hash = B256::from_slice(
-                &keccak256_more(&current_hash, &keccak256(&data))
-            );
+            // 支付数据各字段依次哈希，再更新累积哈希
+            let payment_hash = keccak256_concat(&[
+                &payment.pay_id.to_be_bytes::<32>(),
+                &payment.serv_id.to_be_bytes(),
+                &payment.amount.to_be_bytes::<32>(),
+                &payment.receiver,
+                &payment.sig_sender,
+                &[payment.settled as u8],
+                &payment.sig_proxy,
+            ]);
+            current_hash = B256::from(keccak256_chain(&current_hash, &[&payment_hash]));
         }
 
         current_hash
     }
 
-    /// 获取累计的总利润
+    /// 获取累计的原生代币总利润
     pub fn total_profit(&self) -> U256 {
         self.total_profit
     }
+
+    /// 按代币累计的小计，按 token 升序；只处理过原生代币的结算时只有一项原生代币的小计
+    pub fn token_totals(&self) -> &[TokenSubtotal] {
+        &self.token_totals
+    }
+
+    /// 获取已绑定的 vks_hash
+    pub fn vks_hash(&self) -> Option<B256> {
+        self.vks_hash
+    }
+
+    pub fn accepted_roots(&self) -> Option<&CircularHashStore> {
+        self.accepted_roots.as_ref()
+    }
+
+    /// 接受的结算根，调用方在新的结算发布后写入
+    pub fn accepted_roots_mut(&mut self) -> Option<&mut CircularHashStore> {
+        self.accepted_roots.as_mut()
+    }
+
+    /// 已处理的 settlement_id 链的当前值
+    pub fn settlement_root(&self) -> B256 {
+        self.settlement_root
+    }
+
+    /// 结束结算，生成 ReceiverSettleResult；没有处理过任何结算且未指定 vks_hash 时 vk_hash 为零
+    pub fn finalize(self) -> ReceiverSettleResult {
+        ReceiverSettleResult {
+            vk_hash: self.vks_hash.unwrap_or_default(),
+            settlement_root: self.settlement_root,
+            receiver: self.receiver,
+            profit: self.total_profit,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::CommitmentVersion;
 
     #[test]
     fn test_receiver_settler() {
@@ -101,15 +351,8 @@ mod tests {
 
         // 创建测试支付列表
         let payments = vec![
-            PaymentSettledByProxy {
-                pay_id: U256::from(1u32),
-                serv_id: 0xFFFFFFF,
-                amount: U256::from(100u32),
-                receiver: receiver.into(),
-                sig_sender: [0u8; 65],
-                settled: true,
-                sig_proxy: [0u8; 65],
-            }
+            PaymentSettledByProxy::new(U256::from(1u32), 0xFFFFFFF, U256::from(100u32), receiver.to_eth())
+                .with_settled(true)
         ];
 
         // 计算支付列表的哈希根
@@ -117,7 +360,8 @@ mod tests {
 
         // 创建利润结果
         let profit_result = ProfitResult {
-            receiver: receiver.into(),
+            vks_hash: B256::ZERO,
+            receiver: receiver.to_eth(),
             proxy: [0u8; 20],
             receipts_root,
             pay_ids_root: B256::ZERO,
@@ -125,10 +369,13 @@ mod tests {
             system_profit: U256::from(10u32),
             proxy_profit: U256::from(20u32),
             receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
         };
 
         // 处理结算
-        settler.process_proxy_settlement(&payments, &profit_result)
+        settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11))
             .expect("Processing should succeed");
 
         // 验证总利润
@@ -137,15 +384,389 @@ mod tests {
         // 测试错误情况：错误的 receipts_root
         let invalid_profit_result = ProfitResult {
             receipts_root: B256::ZERO,
-            ..profit_result
+            ..profit_result.clone()
         };
-        assert!(settler.process_proxy_settlement(&payments, &invalid_profit_result).is_err());
+        assert!(settler.process_proxy_settlement(&payments, &invalid_profit_result, B256::repeat_byte(0x11)).is_err());
 
         // 测试错误情况：错误的接收者
         let invalid_profit_result = ProfitResult {
-            receiver: Address::new([3u8;20]).into(),
+            receiver: [3u8;20],
+            ..profit_result.clone()
+        };
+        assert!(settler.process_proxy_settlement(&payments, &invalid_profit_result, B256::repeat_byte(0x11)).is_err());
+
+        // 测试错误情况：vks_hash 与第一个结果不一致
+        let invalid_profit_result = ProfitResult {
+            vks_hash: B256::repeat_byte(1),
+            ..profit_result.clone()
+        };
+        assert!(settler.process_proxy_settlement(&payments, &invalid_profit_result, B256::repeat_byte(0x11)).is_err());
+        assert_eq!(settler.vks_hash(), Some(B256::ZERO));
+
+        // 其他代币的利润只计入按代币的小计，不计入总利润
+        let token = [0xaau8; 20];
+        let subtotal = |token, receiver_profit: u32| TokenSubtotal {
+            token,
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::from(receiver_profit),
+        };
+        let token_result = ProfitResult {
+            receiver_profit: U256::ZERO,
+            token_totals: vec![subtotal(crate::NATIVE_TOKEN, 0), subtotal(token, 500)],
+            ..profit_result.clone()
+        };
+        settler.process_proxy_settlement(&payments, &token_result, B256::repeat_byte(0x12)).unwrap();
+        assert_eq!(settler.total_profit(), U256::from(70u32));
+        assert_eq!(settler.token_totals(), &[subtotal(crate::NATIVE_TOKEN, 70), subtotal(token, 500)]);
+    }
+
+    #[test]
+    fn test_raw_data_checks() -> Result<(), BoxError> {
+        let scenario = crate::fixtures::ScenarioBuilder::new(3)
+            .with_payment(1, 1, 0, 1000)
+            .with_fee_config(1, 500, 1000)
+            .build()?;
+        let receiver = scenario.receiver(0);
+        let proof = scenario.overpay_checker().process()?.get_merkle_proof(receiver)?;
+        let payments = scenario.receipts_for(&receiver);
+        let profit_result = scenario.profit_calculator(receiver, proof).calculate()?;
+        // receipts_root 换成结算器的支付链哈希，只检查两个根
+        let profit_result = ProfitResult {
+            receipts_root: ReceiverSettler::new(Address::new(receiver)).calculate_payments_root(&payments),
             ..profit_result
         };
-        assert!(settler.process_proxy_settlement(&payments, &invalid_profit_result).is_err());
+        let receiver = Address::new(receiver);
+        let proxy = Address::new(scenario.proxy);
+
+        let mut settler = ReceiverSettler::new(receiver)
+            .with_fee_configs(scenario.service_configs.clone())
+            .with_pay_id_infos(proxy, scenario.pay_id_infos.clone());
+        settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11))?;
+        assert_eq!(settler.total_profit(), profit_result.receiver_profit);
+
+        // 费率与证明不一致
+        let mut changed = scenario.service_configs.clone();
+        changed[0].system_fee_rate += 1;
+        let mut settler = ReceiverSettler::new(receiver).with_fee_configs(changed);
+        assert!(settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11)).is_err());
+
+        // pay_id 集合与证明不一致，未链接
+        let mut settler = ReceiverSettler::new(receiver).with_pay_id_infos(proxy, Vec::new());
+        assert!(settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11)).is_err());
+        assert_eq!(settler.settlement_root(), B256::ZERO);
+        assert_eq!(settler.vks_hash(), None);
+
+        // 其他代理的 PayIdInfo 不参与检查
+        let mut settler = ReceiverSettler::new(receiver).with_pay_id_infos(Address::repeat_byte(0xee), Vec::new());
+        settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_accepted_roots() {
+        let receiver = Address::new([1u8;20]);
+        let payments = vec![
+            PaymentSettledByProxy::new(U256::from(1u32), 1, U256::from(100u32), receiver.to_eth())
+                .with_settled(true)
+        ];
+        let receipts_root = ReceiverSettler::new(receiver).calculate_payments_root(&payments);
+        let profit_result = ProfitResult {
+            vks_hash: B256::ZERO,
+            receiver: receiver.to_eth(),
+            proxy: [0u8; 20],
+            receipts_root,
+            pay_ids_root: B256::ZERO,
+            serv_ids_root: B256::ZERO,
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+        let settlement_id = B256::repeat_byte(0x11);
+
+        // 根在窗口中
+        let mut store = CircularHashStore::new(2);
+        store.add_hash(receipts_root).unwrap();
+        let mut settler = ReceiverSettler::with_accepted_roots(receiver, store);
+        settler.process_proxy_settlement(&payments, &profit_result, settlement_id).unwrap();
+        assert_eq!(settler.total_profit(), U256::from(70u32));
+
+        // 只有 settlement_id 在窗口中：旧结果配上新发布的 id 不能绕过检查
+        let mut store = CircularHashStore::new(2);
+        store.add_hash(settlement_id).unwrap();
+        let mut settler = ReceiverSettler::with_accepted_roots(receiver, store);
+        let err = settler.process_proxy_settlement(&payments, &profit_result, settlement_id).unwrap_err();
+        assert_eq!(err.downcast_ref::<StaleSettlement>(), Some(&StaleSettlement { receipts_root }));
+        assert_eq!(settler.total_profit(), U256::ZERO);
+
+        // 未发布的根被拒绝，状态不变
+        let mut store = CircularHashStore::new(2);
+        store.add_hash(B256::repeat_byte(0xaa)).unwrap();
+        let mut settler = ReceiverSettler::with_accepted_roots(receiver, store);
+        let err = settler.process_proxy_settlement(&payments, &profit_result, settlement_id).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StaleSettlement>(),
+            Some(&StaleSettlement { receipts_root })
+        );
+        assert_eq!(settler.total_profit(), U256::ZERO);
+        assert_eq!(settler.settlement_root(), B256::ZERO);
+        assert_eq!(settler.vks_hash(), None);
+
+        // 调用方写入新发布的根后接受
+        settler.accepted_roots_mut().unwrap().add_hash(receipts_root).unwrap();
+        settler.process_proxy_settlement(&payments, &profit_result, settlement_id).unwrap();
+
+        // 被挤出窗口的根：history = keccak(receipts_root ‖ a)，需要历史证明 [a]
+        let (a, b, c) = (B256::repeat_byte(0xa1), B256::repeat_byte(0xb1), B256::repeat_byte(0xc1));
+        let mut store = CircularHashStore::new(2);
+        for hash in [receipts_root, a, b, c] {
+            store.add_hash(hash).unwrap();
+        }
+        let mut settler = ReceiverSettler::with_accepted_roots(receiver, store);
+        assert!(settler.process_proxy_settlement(&payments, &profit_result, settlement_id).is_err());
+        assert!(settler.process_proxy_settlement_with_history(&payments, &profit_result, settlement_id, &[b]).is_err());
+        settler.process_proxy_settlement_with_history(&payments, &profit_result, settlement_id, &[a]).unwrap();
+        assert_eq!(settler.total_profit(), U256::from(70u32));
+
+        // 未设置 accepted_roots 时不检查
+        let mut settler = ReceiverSettler::new(receiver);
+        settler.process_proxy_settlement(&payments, &profit_result, settlement_id).unwrap();
+    }
+
+    #[test]
+    fn test_payments_root_unchanged() {
+        // 引入 keccak256_concat 之前拼接 Vec 的实现
+        fn payments_root_with_vec(payments: &[PaymentSettledByProxy]) -> B256 {
+            let mut current_hash = B256::ZERO;
+            for payment in payments {
+                let mut data = Vec::new();
+                data.extend_from_slice(&payment.pay_id.to_be_bytes::<32>());
+                data.extend_from_slice(&payment.serv_id.to_be_bytes());
+                data.extend_from_slice(&payment.amount.to_be_bytes::<32>());
+                data.extend_from_slice(&payment.receiver);
+                data.extend_from_slice(&payment.sig_sender);
+                data.extend_from_slice(&[payment.settled as u8]);
+                data.extend_from_slice(&payment.sig_proxy);
+                current_hash = B256::from(crate::keccak256_more(&current_hash, &crate::keccak256(&data)));
+            }
+            current_hash
+        }
+
+        let receiver = Address::new([1u8;20]);
+        let payments: Vec<PaymentSettledByProxy> = (0..5u32)
+            .map(|i| {
+                PaymentSettledByProxy::new(U256::from(i), i, U256::from(100 * i), [i as u8; 20])
+                    .with_settled(i % 2 == 0)
+                    .with_sig_sender([i as u8; 65])
+                    .with_sig_proxy([0xf0 | i as u8; 65])
+            })
+            .collect();
+        let settler = ReceiverSettler::new(receiver);
+        for len in 0..=payments.len() {
+            assert_eq!(settler.calculate_payments_root(&payments[..len]), payments_root_with_vec(&payments[..len]));
+        }
+
+        let before = crate::alloc_counter::allocations();
+        settler.calculate_payments_root(&payments);
+        assert_eq!(crate::alloc_counter::allocations() - before, 0);
+    }
+
+    #[test]
+    fn test_profit_overflow() {
+        let receiver = Address::new([1u8;20]);
+        let mut settler = ReceiverSettler::new(receiver);
+
+        let payments = Vec::new();
+        let profit_result = ProfitResult {
+            vks_hash: B256::ZERO,
+            receiver: receiver.to_eth(),
+            proxy: [0u8; 20],
+            receipts_root: settler.calculate_payments_root(&payments),
+            pay_ids_root: B256::ZERO,
+            serv_ids_root: B256::ZERO,
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::MAX,
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+
+        settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11))
+            .expect("Processing should succeed");
+        let err = settler.process_proxy_settlement(&payments, &profit_result, B256::repeat_byte(0x11)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<AmountOverflow>(),
+            Some(&AmountOverflow::Receiver(receiver.into()))
+        );
+        assert_eq!(settler.total_profit(), U256::MAX);
+        // 溢出的结算没有链接
+        assert_eq!(settler.settlement_root(), crate::settlement_chain_root(B256::ZERO, &[B256::repeat_byte(0x11)]));
+    }
+
+    #[test]
+    fn test_rejected_result_does_not_pin_vks_hash() -> Result<(), BoxError> {
+        let receiver = Address::new([1u8;20]);
+        let mut settler = ReceiverSettler::new(receiver);
+        let payments = Vec::new();
+        let accepted = ProfitResult {
+            vks_hash: B256::repeat_byte(8),
+            receiver: receiver.to_eth(),
+            proxy: [0u8; 20],
+            receipts_root: settler.calculate_payments_root(&payments),
+            pay_ids_root: B256::ZERO,
+            serv_ids_root: B256::ZERO,
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::from(5),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+
+        // 第一个结果的代币小计在自身内部溢出，被拒绝后不留下任何状态
+        let subtotal = TokenSubtotal { receiver_profit: U256::MAX, ..TokenSubtotal::new([0x44; 20]) };
+        let overflowing = ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            token_totals: vec![subtotal, subtotal],
+            ..accepted.clone()
+        };
+        let err = settler.process_proxy_settlement(&payments, &overflowing, B256::repeat_byte(0x11)).unwrap_err();
+        assert!(err.is::<AmountOverflow>());
+        assert_eq!(settler.vks_hash(), None);
+        assert_eq!(settler.total_profit(), U256::ZERO);
+        assert_eq!(settler.settlement_root(), B256::ZERO);
+
+        // 之后 vks_hash 不同的合法结果仍然被接受
+        settler.process_proxy_settlement(&payments, &accepted, B256::repeat_byte(0x22))?;
+        assert_eq!(settler.vks_hash(), Some(B256::repeat_byte(8)));
+        assert_eq!(settler.total_profit(), U256::from(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_settlement_root_chain() {
+        let receiver = Address::new([1u8;20]);
+        let payments = vec![
+            PaymentSettledByProxy::new(U256::from(1u32), 1, U256::from(100u32), receiver.to_eth())
+                .with_settled(true)
+        ];
+        let profit_result = |proxy: u8, receiver_profit: u32| ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            receiver: receiver.to_eth(),
+            proxy: [proxy; 20],
+            receipts_root: ReceiverSettler::new(receiver).calculate_payments_root(&payments),
+            pay_ids_root: B256::ZERO,
+            serv_ids_root: B256::ZERO,
+            system_profit: U256::ZERO,
+            proxy_profit: U256::ZERO,
+            receiver_profit: U256::from(receiver_profit),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        };
+        let settlements = [
+            (profit_result(2, 30), B256::repeat_byte(0xa1)),
+            (profit_result(3, 40), B256::repeat_byte(0xb2)),
+        ];
+
+        let settle = |order: [usize; 2]| {
+            let mut settler = ReceiverSettler::new(receiver);
+            for index in order {
+                let (result, settlement_id) = &settlements[index];
+                settler.process_proxy_settlement(&payments, result, *settlement_id).unwrap();
+            }
+            settler.finalize()
+        };
+
+        // 利润与顺序无关，settlement_root 与顺序有关：合约必须按相同顺序重放 settlement_id
+        let forward = settle([0, 1]);
+        let backward = settle([1, 0]);
+        assert_eq!(forward.profit, U256::from(70u32));
+        assert_eq!(backward.profit, forward.profit);
+        assert_eq!(forward.vk_hash, B256::repeat_byte(7));
+        assert_eq!(forward.receiver, receiver.to_eth());
+        assert_ne!(forward.settlement_root, backward.settlement_root);
+
+        let ids = [settlements[0].1, settlements[1].1];
+        assert!(crate::verify_settlement_chain(B256::ZERO, &ids, forward.settlement_root));
+        assert!(!crate::verify_settlement_chain(B256::ZERO, &ids, backward.settlement_root));
+        assert!(crate::verify_settlement_chain(B256::ZERO, &[ids[1], ids[0]], backward.settlement_root));
+        assert!(!crate::verify_settlement_chain(B256::ZERO, &ids[..1], forward.settlement_root));
+
+        // 没有处理任何结算时为起点
+        let empty = ReceiverSettler::new(receiver).finalize();
+        assert_eq!(empty.settlement_root, B256::ZERO);
+        assert!(crate::verify_settlement_chain(B256::ZERO, &[], empty.settlement_root));
+    }
+
+    #[test]
+    fn test_process_many() -> Result<(), BoxError> {
+        let receiver = Address::new([1u8;20]);
+        let payments = vec![
+            PaymentSettledByProxy::new(U256::from(1u32), 1, U256::from(100u32), receiver.to_eth())
+                .with_settled(true)
+        ];
+        let receipts_root = ReceiverSettler::new(receiver).calculate_payments_root(&payments);
+        let input = |proxy: u8, receiver_profit: u32, settlement_id: u8| {
+            let profit_result = ProfitResult {
+                vks_hash: B256::repeat_byte(7),
+                receiver: receiver.to_eth(),
+                proxy: [proxy; 20],
+                receipts_root,
+                pay_ids_root: B256::ZERO,
+                serv_ids_root: B256::ZERO,
+                system_profit: U256::ZERO,
+                proxy_profit: U256::ZERO,
+                receiver_profit: U256::from(receiver_profit),
+                epoch: 0,
+                token_totals: Vec::new(),
+                commitment_version: CommitmentVersion::Legacy,
+            };
+            ProxySettlementInput::new(payments.clone(), profit_result, B256::repeat_byte(settlement_id))
+        };
+
+        // 空批次
+        let mut settler = ReceiverSettler::new(receiver);
+        let report = settler.process_many(Vec::new())?;
+        assert_eq!(report, ReceiverBatchReport::default());
+
+        let mut settler = ReceiverSettler::new(receiver);
+        settler.process_proxy_settlement(&payments, &input(9, 5, 0x09).profit_result, B256::repeat_byte(0x09))?;
+        let report = settler.process_many(vec![input(2, 30, 0xa1), input(3, 40, 0xb2), input(2, 10, 0xc3)])?;
+        assert_eq!(report.applied, 3);
+        assert_eq!(report.proxy_subtotals, BTreeMap::from([([2u8; 20], U256::from(40)), ([3u8; 20], U256::from(40))]));
+        assert_eq!(report.batch_total, U256::from(80));
+        assert_eq!(report.total_profit, U256::from(85));
+        assert_eq!(report.receipts_roots, vec![receipts_root; 3]);
+
+        // 与逐个处理得到相同的结果
+        let mut one_by_one = ReceiverSettler::new(receiver);
+        for item in [input(9, 5, 0x09), input(2, 30, 0xa1), input(3, 40, 0xb2), input(2, 10, 0xc3)] {
+            one_by_one.process_proxy_settlement(&item.payments, &item.profit_result, item.settlement_id)?;
+        }
+        assert_eq!(settler.settlement_root(), one_by_one.settlement_root());
+
+        // 第二项的接收者不同：第一项已应用，第三项没有处理
+        let mut settler = ReceiverSettler::new(receiver);
+        let mut wrong = input(3, 40, 0xb2);
+        wrong.profit_result.receiver = [0xeeu8; 20];
+        let err = settler.process_many(vec![input(2, 30, 0xa1), wrong, input(4, 50, 0xd4)]).unwrap_err();
+        let aborted = err.downcast_ref::<ReceiverBatchAborted>().ok_or("Expected ReceiverBatchAborted")?;
+        assert_eq!(aborted.index, 1);
+        assert_eq!(aborted.report.applied, 1);
+        assert_eq!(aborted.report.proxy_subtotals, BTreeMap::from([([2u8; 20], U256::from(30))]));
+        assert_eq!(aborted.report.total_profit, U256::from(30));
+        assert_eq!(aborted.report.receipts_roots, vec![receipts_root]);
+        assert!(aborted.to_string().contains("Receiver mismatch"));
+        assert_eq!(settler.total_profit(), U256::from(30));
+
+        let mut first_only = ReceiverSettler::new(receiver);
+        first_only.process_proxy_settlement(&payments, &input(2, 30, 0xa1).profit_result, B256::repeat_byte(0xa1))?;
+        assert_eq!(settler.settlement_root(), first_only.settlement_root());
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,344 @@
+//! 0x 十六进制的 serde 表示
+//!
+//! 人类可读的格式（JSON）使用 "0x..." 字符串，方便与 TypeScript 工具交互；
+//! 非人类可读的格式（bincode）保持原有的紧凑表示：定长数组按元组编码，签名按 Vec<u8> 编码。
+//!
+//! 定长数组（EthAddress、[u8; 32]）直接使用 `#[serde(with = "crate::serde_hex")]`，
+//! 签名使用 `#[serde(with = "crate::serde_hex::signature")]`，`Option<EthSignature>` 使用 `crate::serde_hex::option_signature`。
+
+use alloc::{format, string::String};
+use alloy_primitives::hex;
+use core::fmt;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserializer, Serializer};
+
+pub fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&hex::encode_prefixed(bytes))
+    } else {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for byte in bytes {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(HexVisitor::<N>)
+    } else {
+        deserializer.deserialize_tuple(N, ArrayVisitor::<N>)
+    }
+}
+
+/// 解析十六进制字符串，0x 前缀可选，大小写不敏感（兼容 EIP-55 校验和地址）
+pub fn decode_fixed<const N: usize>(value: &str) -> Result<[u8; N], String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    let bytes = hex::decode(digits).map_err(|e| format!("Invalid hex: {}", e))?;
+    <[u8; N]>::try_from(bytes.as_slice())
+        .map_err(|_| format!("Expected {} bytes, got {}", N, bytes.len()))
+}
+
+fn decode_hex<E: de::Error, const N: usize>(value: &str) -> Result<[u8; N], E> {
+    decode_fixed(value).map_err(E::custom)
+}
+
+struct HexVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for HexVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a 0x-prefixed hex string of {} bytes", N)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        decode_hex(value)
+    }
+}
+
+struct ArrayVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for ArrayVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an array of {} bytes", N)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// EthSignature（65 字节）的表示，非人类可读格式下与原 signature_serde 一致
+pub mod signature {
+    use super::HexVisitor;
+    use crate::EthSignature;
+    use alloc::{format, vec::Vec};
+    use alloy_primitives::hex;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(signature: &EthSignature, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode_prefixed(signature))
+        } else {
+            signature.to_vec().serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<EthSignature, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(HexVisitor::<65>)
+        } else {
+            let bytes: Vec<u8> = Vec::deserialize(deserializer)?;
+            EthSignature::try_from(&bytes[..])
+                .map_err(|e| serde::de::Error::custom(format!("Invalid signature: {}", e)))
+        }
+    }
+}
+
+/// Option<EthSignature> 的表示，与 `#[serde(default)]` 一起使用，旧数据没有该字段时为 None
+pub mod option_signature {
+    use crate::EthSignature;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Signature(#[serde(with = "super::signature")] EthSignature);
+
+    pub fn serialize<S>(signature: &Option<EthSignature>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        signature.map(Signature).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<EthSignature>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<Signature>::deserialize(deserializer)?.map(|Signature(signature)| signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::segment_vc::{MerkleProof, SegmentProof, ValueProof};
+    use crate::{
+        CommitmentVersion, EthAddress, OverpayCheckResult, PayIdInfo, PaymentSettledByProxy, ProfitResult,
+        ReceiverProof, ReceiverSettleResult,
+    };
+    use alloy_primitives::{B256, U256};
+    use serde::{Deserialize, Serialize};
+
+    fn create_test_payment() -> PaymentSettledByProxy {
+        PaymentSettledByProxy::new(U256::from(1), 1, U256::from(100), [0xABu8; 20])
+            .with_settled(true)
+            .with_sig_sender([0x11u8; 65])
+            .with_sig_proxy([0x22u8; 65])
+            .with_nonce(3)
+    }
+
+    fn create_test_profit_result() -> ProfitResult {
+        ProfitResult {
+            vks_hash: B256::repeat_byte(7),
+            receiver: [1u8; 20],
+            proxy: [2u8; 20],
+            receipts_root: B256::repeat_byte(3),
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(10u32),
+            proxy_profit: U256::from(20u32),
+            receiver_profit: U256::from(70u32),
+            epoch: 0,
+            token_totals: Vec::new(),
+            commitment_version: CommitmentVersion::Legacy,
+        }
+    }
+
+    fn create_test_overpay_result() -> OverpayCheckResult {
+        OverpayCheckResult {
+            payments_root: B256::repeat_byte(2),
+            receiver_proofs: vec![ReceiverProof {
+                receiver: [5u8; 20],
+                proof: MerkleProof {
+                    value_proof: ValueProof {
+                        value: B256::repeat_byte(9),
+                        chunk_hash: B256::ZERO,
+                    },
+                    segment_proof: SegmentProof {
+                        chunk_index: 0,
+                        siblings: vec![B256::repeat_byte(1)],
+                    },
+                    level_proofs: vec![],
+                    root_hash: B256::repeat_byte(2),
+                    hasher: Default::default(),
+                    padded: false,
+                },
+            }],
+            pay_ids_root: B256::repeat_byte(6),
+            epoch: 0,
+            commitment_version: CommitmentVersion::Legacy,
+            excluded_root: None,
+            referenced_pay_ids_root: None,
+        }
+    }
+
+    fn assert_payment_eq(a: &PaymentSettledByProxy, b: &PaymentSettledByProxy) {
+        assert_eq!(a.pay_id, b.pay_id);
+        assert_eq!(a.serv_id, b.serv_id);
+        assert_eq!(a.amount, b.amount);
+        assert_eq!(a.receiver, b.receiver);
+        assert_eq!(a.sig_sender, b.sig_sender);
+        assert_eq!(a.settled, b.settled);
+        assert_eq!(a.sig_proxy, b.sig_proxy);
+        assert_eq!(a.nonce, b.nonce);
+    }
+
+    #[test]
+    fn test_json_uses_hex() {
+        let payment = create_test_payment();
+        let value = serde_json::to_value(&payment).unwrap();
+        assert_eq!(value["receiver"], format!("0x{}", "ab".repeat(20)));
+        assert_eq!(value["sig_sender"], format!("0x{}", "11".repeat(65)));
+        assert_eq!(value["sig_proxy"], format!("0x{}", "22".repeat(65)));
+
+        let profit_result = create_test_profit_result();
+        let value = serde_json::to_value(&profit_result).unwrap();
+        assert_eq!(value["receiver"], format!("0x{}", "01".repeat(20)));
+        assert_eq!(value["proxy"], format!("0x{}", "02".repeat(20)));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let payment = create_test_payment();
+        let json = serde_json::to_string(&payment).unwrap();
+        assert_payment_eq(&payment, &serde_json::from_str(&json).unwrap());
+
+        let info = PayIdInfo {
+            id: U256::from(1),
+            amount: U256::from(1000),
+            sender: [3u8; 20],
+            proxy: [4u8; 20],
+            state: 1,
+            created_at: 1000,
+            closing_time: 2000,
+            token: [0u8; 20],
+            sig_sender: None,
+        };
+        let decoded: PayIdInfo = serde_json::from_str(&serde_json::to_string(&info).unwrap()).unwrap();
+        assert_eq!(decoded.sender, info.sender);
+        assert_eq!(decoded.proxy, info.proxy);
+        assert_eq!(decoded.hash(), info.hash());
+
+        let profit_result = create_test_profit_result();
+        let decoded: ProfitResult =
+            serde_json::from_str(&serde_json::to_string(&profit_result).unwrap()).unwrap();
+        assert_eq!(decoded.receiver, profit_result.receiver);
+        assert_eq!(decoded.proxy, profit_result.proxy);
+        assert_eq!(decoded.receiver_profit, profit_result.receiver_profit);
+
+        let settle_result = ReceiverSettleResult {
+            vk_hash: B256::repeat_byte(1),
+            settlement_root: B256::repeat_byte(2),
+            receiver: [8u8; 20],
+            profit: U256::from(100u32),
+        };
+        let decoded: ReceiverSettleResult =
+            serde_json::from_str(&serde_json::to_string(&settle_result).unwrap()).unwrap();
+        assert_eq!(decoded.receiver, settle_result.receiver);
+
+        let overpay_result = create_test_overpay_result();
+        let json = serde_json::to_string(&overpay_result).unwrap();
+        assert!(json.contains(&format!("0x{}", "05".repeat(20))));
+        let decoded: OverpayCheckResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.receiver_proofs[0].receiver, overpay_result.receiver_proofs[0].receiver);
+        assert_eq!(decoded.payments_root, overpay_result.payments_root);
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let payment = create_test_payment();
+        let bytes = bincode::serialize(&payment).unwrap();
+        assert_payment_eq(&payment, &bincode::deserialize(&bytes).unwrap());
+
+        let profit_result = create_test_profit_result();
+        let decoded: ProfitResult =
+            bincode::deserialize(&bincode::serialize(&profit_result).unwrap()).unwrap();
+        assert_eq!(decoded.receiver, profit_result.receiver);
+        assert_eq!(decoded.proxy, profit_result.proxy);
+
+        let overpay_result = create_test_overpay_result();
+        let decoded: OverpayCheckResult =
+            bincode::deserialize(&bincode::serialize(&overpay_result).unwrap()).unwrap();
+        assert_eq!(decoded.receiver_proofs[0].receiver, overpay_result.receiver_proofs[0].receiver);
+    }
+
+    #[test]
+    fn test_bincode_layout_unchanged() {
+        #[derive(Serialize, Deserialize)]
+        struct Hex(#[serde(with = "crate::serde_hex")] [u8; 20]);
+        #[derive(Serialize, Deserialize)]
+        struct HexSignature(#[serde(with = "crate::serde_hex::signature")] [u8; 65]);
+
+        // 定长数组与原来的 [u8; N] 编码一致，签名与原来的 Vec<u8> 编码一致
+        let address = [7u8; 20];
+        assert_eq!(bincode::serialize(&Hex(address)).unwrap(), bincode::serialize(&address).unwrap());
+        let signature = [9u8; 65];
+        assert_eq!(
+            bincode::serialize(&HexSignature(signature)).unwrap(),
+            bincode::serialize(&signature.to_vec()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sdk_style_json() {
+        // 按 TS SDK 的输出格式手写的 JSON：EIP-55 校验和地址、0x 小写十六进制签名、0x 十六进制金额。
+        // 不是 SDK 生成的跨实现向量，只检查解析器接受这种格式
+        let fixture = r#"{
+            "pay_id": "0x1",
+            "serv_id": 2,
+            "amount": "0x3e8",
+            "receiver": "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "sig_sender": "0x1111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111",
+            "settled": true,
+            "sig_proxy": "0x2222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222222"
+        }"#;
+
+        let payment: PaymentSettledByProxy = serde_json::from_str(fixture).unwrap();
+        assert_eq!(payment.pay_id, U256::from(1));
+        assert_eq!(payment.serv_id, 2);
+        assert_eq!(payment.amount, U256::from(1000));
+        assert_eq!(
+            payment.receiver,
+            alloy_primitives::address!("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").into_array()
+        );
+        assert_eq!(payment.sig_sender, [0x11u8; 65]);
+        assert_eq!(payment.sig_proxy, [0x22u8; 65]);
+        assert_eq!(payment.nonce, None);
+
+        // 长度错误的地址被拒绝
+        let invalid = fixture.replace("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", "0x5aAeb6");
+        assert!(serde_json::from_str::<PaymentSettledByProxy>(&invalid).is_err());
+    }
+}
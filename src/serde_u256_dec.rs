@@ -0,0 +1,242 @@
+//! U256 的十进制字符串 serde 表示
+//!
+//! alloy 的 U256 在 JSON 中是 "0x..." 字符串，面板等 JS 消费方常常把它当作数字处理，超过 2^53 后丢失精度。
+//! 人类可读的格式输出十进制字符串，读取时接受十进制字符串、0x 十六进制字符串（旧数据）和 JSON 整数；
+//! 非人类可读的格式（bincode）与 U256 自身的编码相同。
+//!
+//! 字段使用 `#[serde(with = "crate::serde_u256_dec")]`，`Option<U256>` 使用 `crate::serde_u256_dec::option`。
+
+use alloc::string::ToString;
+use alloy_primitives::U256;
+use core::fmt;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub fn serialize<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&value.to_string())
+    } else {
+        value.serialize(serializer)
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(DecimalVisitor)
+    } else {
+        U256::deserialize(deserializer)
+    }
+}
+
+/// 十进制，或带 0x 前缀的十六进制
+fn parse<E: de::Error>(value: &str) -> Result<U256, E> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(digits) => U256::from_str_radix(digits, 16),
+        None => U256::from_str_radix(value, 10),
+    };
+    parsed.map_err(|err| E::custom(alloc::format!("Invalid U256 {:?}: {}", value, err)))
+}
+
+struct DecimalVisitor;
+
+impl<'de> Visitor<'de> for DecimalVisitor {
+    type Value = U256;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a decimal or 0x-prefixed hex string, or a non-negative integer")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        parse(value)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        Ok(U256::from(value))
+    }
+
+    fn visit_u128<E: de::Error>(self, value: u128) -> Result<Self::Value, E> {
+        Ok(U256::from(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        u64::try_from(value)
+            .map(U256::from)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+}
+
+// Option<U256> 的每个元素按上面的规则编码
+struct Decimal(U256);
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(Decimal)
+    }
+}
+
+/// `Option<U256>` 的表示，None 与原来相同
+pub mod option {
+    use super::Decimal;
+    use alloy_primitives::U256;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(value: &Option<U256>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.map(Decimal).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<Decimal>::deserialize(deserializer)?.map(|Decimal(value)| value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::receipts::{Payment, PaymentSettledByProxy};
+    use crate::{PayIdInfo, ProfitResult, ProxySettlementResult, ReceiverSettleResult, TokenSubtotal};
+    use alloy_primitives::{B256, U256};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Amounts {
+        #[serde(with = "crate::serde_u256_dec")]
+        amount: U256,
+        #[serde(default, with = "crate::serde_u256_dec::option")]
+        authorized: Option<U256>,
+    }
+
+    fn profit_result(receiver_profit: U256) -> ProfitResult {
+        ProfitResult {
+            vks_hash: B256::repeat_byte(1),
+            receiver: [1u8; 20],
+            proxy: [2u8; 20],
+            receipts_root: B256::repeat_byte(3),
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profit: U256::from(1u64 << 53) + U256::from(1),
+            proxy_profit: U256::ZERO,
+            receiver_profit,
+            epoch: 2,
+            token_totals: vec![TokenSubtotal {
+                token: [7u8; 20],
+                system_profit: U256::ZERO,
+                proxy_profit: U256::ZERO,
+                receiver_profit,
+            }],
+            commitment_version: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_json_decimal_strings() {
+        let above = U256::from(1u64 << 53) + U256::from(1);
+        let amounts = Amounts { amount: above, authorized: Some(U256::MAX) };
+        let value = serde_json::to_value(&amounts).unwrap();
+        assert_eq!(value["amount"], "9007199254740993");
+        assert_eq!(value["authorized"], U256::MAX.to_string());
+        assert_eq!(serde_json::from_value::<Amounts>(value).unwrap(), amounts);
+
+        let result = profit_result(U256::MAX);
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(value["system_profit"], "9007199254740993");
+        assert_eq!(value["receiver_profit"], U256::MAX.to_string());
+        assert_eq!(value["token_totals"][0]["receiver_profit"], U256::MAX.to_string());
+        assert_eq!(serde_json::from_value::<ProfitResult>(value).unwrap(), result);
+
+        let settlement = ProxySettlementResult {
+            vks_hash: B256::repeat_byte(1),
+            settlement_id: B256::repeat_byte(2),
+            proxy: [3u8; 20],
+            pay_ids_root: B256::repeat_byte(4),
+            serv_ids_root: B256::repeat_byte(5),
+            system_profits: above,
+            proxy_profits: U256::ZERO,
+            amount: U256::MAX,
+            epoch: 1,
+            token_totals: Vec::new(),
+            commitment_version: Default::default(),
+        };
+        let value = serde_json::to_value(&settlement).unwrap();
+        assert_eq!(value["amount"], U256::MAX.to_string());
+        assert_eq!(serde_json::from_value::<ProxySettlementResult>(value).unwrap(), settlement);
+
+        let settle_result = ReceiverSettleResult {
+            vk_hash: B256::ZERO,
+            settlement_root: B256::ZERO,
+            receiver: [8u8; 20],
+            profit: U256::MAX,
+        };
+        let value = serde_json::to_value(&settle_result).unwrap();
+        assert_eq!(value["profit"], U256::MAX.to_string());
+        assert_eq!(serde_json::from_value::<ReceiverSettleResult>(value).unwrap().profit, U256::MAX);
+
+        let payment = Payment::new(U256::MAX, 1, above, [9u8; 20]);
+        let value = serde_json::to_value(&payment).unwrap();
+        assert_eq!((value["pay_id"].as_str(), value["amount"].as_str()), (Some(&*U256::MAX.to_string()), Some("9007199254740993")));
+
+        let receipt = PaymentSettledByProxy::from(payment).with_authorized_amount(U256::MAX);
+        let value = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(value["authorized_amount"], U256::MAX.to_string());
+        let decoded: PaymentSettledByProxy = serde_json::from_value(value).unwrap();
+        assert_eq!(decoded.hash(), receipt.hash());
+    }
+
+    #[test]
+    fn test_json_accepts_legacy_forms() {
+        // 旧的 0x 十六进制字符串和 JSON 整数同样可以读取
+        for json in [
+            r#"{"amount":"0x3e8","authorized":null}"#,
+            r#"{"amount":1000}"#,
+            r#"{"amount":"1000"}"#,
+        ] {
+            assert_eq!(serde_json::from_str::<Amounts>(json).unwrap().amount, U256::from(1000));
+        }
+        let json = r#"{"amount":"0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff","authorized":"0x1"}"#;
+        let amounts: Amounts = serde_json::from_str(json).unwrap();
+        assert_eq!((amounts.amount, amounts.authorized), (U256::MAX, Some(U256::from(1))));
+
+        for invalid in [r#"{"amount":-1}"#, r#"{"amount":"12ab"}"#, r#"{"amount":1.5}"#] {
+            assert!(serde_json::from_str::<Amounts>(invalid).is_err(), "{}", invalid);
+        }
+
+        let info = r#"{"id":"0x2a","amount":5000,"sender":"0x0303030303030303030303030303030303030303",
+            "proxy":"0x0404040404040404040404040404040404040404","state":1,"created_at":0,"closing_time":0}"#;
+        let info: PayIdInfo = serde_json::from_str(info).unwrap();
+        assert_eq!((info.id, info.amount), (U256::from(42), U256::from(5000)));
+        assert_eq!(serde_json::to_value(&info).unwrap()["id"], "42");
+    }
+
+    #[test]
+    fn test_bincode_layout_unchanged() {
+        #[derive(Serialize)]
+        struct Plain {
+            amount: U256,
+            authorized: Option<U256>,
+        }
+        let amounts = Amounts { amount: U256::MAX, authorized: Some(U256::from(1u64 << 60)) };
+        let bytes = bincode::serialize(&amounts).unwrap();
+        let plain = Plain { amount: amounts.amount, authorized: amounts.authorized };
+        assert_eq!(bytes, bincode::serialize(&plain).unwrap());
+        assert_eq!(bincode::deserialize::<Amounts>(&bytes).unwrap(), amounts);
+
+        let result = profit_result(U256::MAX);
+        assert_eq!(bincode::deserialize::<ProfitResult>(&bincode::serialize(&result).unwrap()).unwrap(), result);
+    }
+}
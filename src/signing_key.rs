@@ -0,0 +1,164 @@
+//! 释放时清零的私钥
+//!
+//! libsecp256k1 的 SecretKey 是普通的值类型，随栈帧和堆内存留下副本。SigningKey 包装一个 SecretKey，
+//! drop 时用 volatile 写覆盖为固定的标量 1，长时间运行的服务应使用它而不是直接持有 SecretKey
+use core::fmt;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use libsecp256k1::SecretKey;
+
+use crate::SignatureError;
+
+/// 签名函数接受的私钥：SigningKey 或 SecretKey
+pub trait AsSecretKey {
+    fn secret_key(&self) -> &SecretKey;
+}
+
+impl AsSecretKey for SecretKey {
+    fn secret_key(&self) -> &SecretKey {
+        self
+    }
+}
+
+impl AsSecretKey for SigningKey {
+    fn secret_key(&self) -> &SecretKey {
+        &self.secret
+    }
+}
+
+#[derive(Clone)]
+pub struct SigningKey {
+    secret: SecretKey,
+}
+
+impl SigningKey {
+    #[cfg(feature = "std")]
+    pub fn random() -> Self {
+        Self::random_with(&mut rand::thread_rng())
+    }
+
+    /// 由 rng 生成，测试中传入 DeterministicRng 得到可复现的私钥
+    #[cfg(feature = "std")]
+    pub fn random_with<R: rand::Rng>(rng: &mut R) -> Self {
+        Self { secret: SecretKey::random(rng) }
+    }
+
+    /// 大端序标量，必须在 [1, n) 内
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, SignatureError> {
+        SecretKey::parse(bytes)
+            .map(|secret| Self { secret })
+            .map_err(|_| SignatureError::InvalidSecretKey)
+    }
+
+    /// 对应的以太坊地址
+    pub fn address(&self) -> crate::EthAddress {
+        crate::get_ethereum_address(&crate::get_public_key(self))
+    }
+
+    /// 发送者签名收据，等价于 payment.sign(secret_key)
+    #[cfg(feature = "std")]
+    pub fn sign_payment(&self, payment: &mut crate::receipts::Payment) -> Result<(), rlp::DecoderError> {
+        payment.sign(&self.secret)
+    }
+
+    /// 代理签名结算后的收据，等价于 settlement.sign_by_proxy(secret_key)
+    #[cfg(feature = "std")]
+    pub fn sign_settlement(&self, settlement: &mut crate::receipts::PaymentSettledByProxy) -> Result<(), rlp::DecoderError> {
+        settlement.sign_by_proxy(&self.secret)
+    }
+
+    // 覆盖私钥；write_volatile 和 fence 防止编译器把这次写入当作无用的存储优化掉
+    fn wipe(&mut self) {
+        // SAFETY: self.secret 是有效的、已初始化的 SecretKey，覆盖为另一个有效值
+        unsafe { core::ptr::write_volatile(&mut self.secret, SecretKey::default()) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+// 不输出私钥
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SigningKey(..)")
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::receipts::{Payment, PaymentSettledByProxy};
+    use alloy_primitives::U256;
+
+    // secp256k1 的阶 n
+    const ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+        0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+    ];
+
+    #[test]
+    fn test_from_bytes_range() {
+        let mut max = ORDER;
+        max[31] -= 1;
+        assert!(SigningKey::from_bytes(&max).is_ok());
+        assert!(SigningKey::from_bytes(&[7u8; 32]).is_ok());
+
+        for invalid in [[0u8; 32], ORDER, [0xffu8; 32]] {
+            assert_eq!(SigningKey::from_bytes(&invalid).unwrap_err(), SignatureError::InvalidSecretKey);
+        }
+        assert_eq!(format!("{:?}", SigningKey::from_bytes(&max).unwrap()), "SigningKey(..)");
+    }
+
+    #[test]
+    fn test_signing_parity() -> Result<(), crate::BoxError> {
+        let bytes = [7u8; 32];
+        let key = SigningKey::from_bytes(&bytes)?;
+        let raw = SecretKey::parse(&bytes).unwrap();
+
+        assert_eq!(crate::sign_message(&key, b"message")?, crate::sign_message(&raw, b"message")?);
+        assert_eq!(crate::get_public_key(&key), crate::get_public_key(&raw));
+        assert_eq!(key.address(), crate::get_ethereum_address(&crate::get_public_key(&raw)));
+
+        let payment = Payment::new(U256::from(1), 1, U256::from(100), [1u8; 20]);
+        let (mut with_key, mut with_raw) = (payment.clone(), payment);
+        key.sign_payment(&mut with_key)?;
+        with_raw.sign(&raw)?;
+        assert_eq!(with_key.hash(), with_raw.hash());
+        assert_eq!(with_key.get_signer_address()?, key.address());
+
+        let mut settled = PaymentSettledByProxy::from(with_key).with_settled(true);
+        let mut settled_raw = settled.clone();
+        key.sign_settlement(&mut settled)?;
+        settled_raw.sign_by_proxy(&raw)?;
+        assert_eq!(settled.sig_proxy, settled_raw.sig_proxy);
+        assert_eq!(settled.get_proxy_address()?, key.address());
+
+        let random = SigningKey::random();
+        assert_ne!(random.address(), key.address());
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_with_seeded_rng() {
+        use crate::DeterministicRng;
+        use rand::SeedableRng;
+
+        let first = crate::generate_private_key_with(&mut DeterministicRng::seed_from_u64(3));
+        let second = SigningKey::random_with(&mut DeterministicRng::seed_from_u64(3));
+        assert_eq!(first.address(), second.address());
+        assert_ne!(SigningKey::random_with(&mut DeterministicRng::seed_from_u64(4)).address(), first.address());
+    }
+
+    #[test]
+    fn test_wipe() {
+        let mut key = SigningKey::from_bytes(&[7u8; 32]).unwrap();
+        let copy = key.clone();
+        key.wipe();
+        assert_eq!(key.secret, SecretKey::default());
+        assert_ne!(copy.secret, SecretKey::default());
+    }
+}
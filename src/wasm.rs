@@ -0,0 +1,35 @@
+//! 浏览器端使用的验证接口
+//!
+//! 与 --no-default-features 一起开启，只依赖验证子集，不链接 rand、SystemTime 和 sp1_zkvm。
+//! 证明和结果以 JSON 传入，格式与 serde 序列化结果相同，方便 JS 侧直接传递后端返回的数据
+
+use alloc::string::String;
+use alloy_primitives::B256;
+
+pub use crate::models::segment_vc::MerkleProof;
+pub use crate::{get_ethereum_address, recover_public_key, ReceiverProof, SettlementProof};
+use crate::{BoxError, EthAddress, EthSignature};
+
+/// 验证 JSON 格式的 ReceiverProof 属于 payments_root
+pub fn verify_receiver_proof_json(payments_root: B256, proof_json: &str) -> Result<bool, BoxError> {
+    let proof: ReceiverProof = serde_json::from_str(proof_json).map_err(json_error)?;
+    proof.verify(payments_root)
+}
+
+/// 验证 JSON 格式的 SettlementProof
+pub fn verify_settlement_proof_json(proof_json: &str) -> Result<bool, BoxError> {
+    let proof: SettlementProof = serde_json::from_str(proof_json).map_err(json_error)?;
+    proof.verify()
+}
+
+/// 从签名恢复签名者地址，message 为签名前的原始消息
+pub fn recover_signer(signature: &EthSignature, message: &[u8]) -> Result<EthAddress, BoxError> {
+    let public_key = recover_public_key(signature, message)?;
+    Ok(get_ethereum_address(&public_key))
+}
+
+// serde_json::Error 只在 std 下实现 Error
+fn json_error(error: serde_json::Error) -> BoxError {
+    let message: String = alloc::format!("Invalid JSON: {}", error);
+    message.into()
+}
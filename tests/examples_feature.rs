@@ -0,0 +1,60 @@
+//! examples 特性：默认特性不公开 SP1 模板的 fibonacci 示例模块，crate 根的弃用旧导出总是存在
+//!
+//! 构建检查用单独的 target 目录重新编译整个 crate，耗时较长，默认忽略：
+//! cargo test --test examples_feature -- --ignored
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+#[ignore = "rebuilds the crate with default features"]
+fn core_builds_without_examples() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("examples_feature");
+
+    // 默认特性不公开 examples 模块，先确认特性列表中没有它
+    let default_features = std::fs::read_to_string(&manifest)
+        .expect("failed to read Cargo.toml")
+        .lines()
+        .find(|line| line.starts_with("default ="))
+        .map(str::to_owned)
+        .expect("no default features in Cargo.toml");
+    assert!(!default_features.contains("examples"), "examples is a default feature: {}", default_features);
+
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--manifest-path"])
+        .arg(&manifest)
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "crate does not build with default features");
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_exports_without_feature() {
+    use alloy_sol_types::SolType;
+
+    // 旧的 guest 不开启 examples 特性也能使用 crate 根的导出
+    assert_eq!(zkpay_lib::fibonacci(10), (55, 89));
+    let encoded = zkpay_lib::PublicValuesStruct::abi_encode(&zkpay_lib::PublicValuesStruct { n: 10, a: 55, b: 89 });
+    let decoded = zkpay_lib::PublicValuesStruct::abi_decode(&encoded, true).unwrap();
+    assert_eq!((decoded.n, decoded.a, decoded.b), (10, 55, 89));
+}
+
+#[cfg(feature = "examples")]
+#[test]
+#[allow(deprecated)]
+fn deprecated_exports_match_examples() {
+    use alloy_sol_types::SolType;
+    use zkpay_lib::examples;
+
+    assert_eq!(zkpay_lib::fibonacci(30), examples::fibonacci(30));
+
+    // 旧路径的类型与 examples::PublicValuesStruct 相同，编码可以互通
+    let (a, b) = examples::fibonacci(12);
+    let legacy = zkpay_lib::PublicValuesStruct { n: 12, a, b };
+    let encoded = zkpay_lib::PublicValuesStruct::abi_encode(&legacy);
+    let decoded = examples::PublicValuesStruct::abi_decode(&encoded, true).unwrap();
+    assert_eq!((decoded.n, decoded.a, decoded.b), (12, 144, 233));
+}
@@ -0,0 +1,22 @@
+//! 验证子集在关闭默认特性（no_std + alloc）时可以编译
+//!
+//! 用单独的 target 目录重新编译整个 crate，耗时较长，默认忽略：
+//! cargo test --test no_std_build -- --ignored
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+#[ignore = "rebuilds the crate without default features"]
+fn verification_subset_builds_without_std() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std_build");
+
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--no-default-features", "--manifest-path"])
+        .arg(&manifest)
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "crate does not build with --no-default-features");
+}
@@ -0,0 +1,77 @@
+//! 浏览器端验证本机生成的证明 fixture
+//!
+//! 本机：cargo test --test wasm_verify 检查 fixture 与 SegmentVC 的输出一致并能验证
+//! wasm32：wasm-pack test --node -- --no-default-features --features wasm
+use alloy_primitives::B256;
+use serde::Deserialize;
+use zkpay_lib::ReceiverProof;
+
+const FIXTURE: &str = include_str!("fixtures/receiver_proof.json");
+
+#[derive(Deserialize)]
+struct Fixture {
+    payments_root: B256,
+    receiver_proof: ReceiverProof,
+}
+
+fn fixture() -> Fixture {
+    serde_json::from_str(FIXTURE).expect("invalid fixture")
+}
+
+fn check_fixture() {
+    let fixture = fixture();
+    assert!(fixture.receiver_proof.verify(fixture.payments_root).unwrap());
+
+    // 其他根或篡改后的值都不能通过
+    assert!(!fixture.receiver_proof.verify(B256::repeat_byte(1)).unwrap());
+    let mut tampered = fixture.receiver_proof.clone();
+    tampered.proof.value_proof.value = B256::repeat_byte(0x23);
+    assert!(!tampered.verify(fixture.payments_root).unwrap());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn native_fixture_verifies() {
+    check_fixture();
+}
+
+/// fixture 由 3 个接收者的 hash-only SegmentVC 生成，证明的是第 2 个接收者
+#[cfg(all(not(target_arch = "wasm32"), feature = "std"))]
+#[test]
+fn fixture_matches_native_tree() {
+    use zkpay_lib::{eth_address_to_B256, SegmentVC};
+
+    let entries: Vec<([u8; 20], B256)> = (1..=3u8)
+        .map(|i| ([i; 20], B256::repeat_byte(i * 0x11)))
+        .collect();
+    let mut vc = SegmentVC::new_hash_only(entries.len());
+    let root = vc
+        .insert_batch(entries.iter().map(|(receiver, value)| (eth_address_to_B256(receiver), *value)).collect())
+        .unwrap();
+
+    let (receiver, value) = entries[1];
+    let mut proof = vc.generate_proof(eth_address_to_B256(&receiver)).unwrap();
+    proof.value_proof.value = value;
+
+    let fixture = fixture();
+    assert_eq!(root, fixture.payments_root);
+    assert_eq!(fixture.receiver_proof, ReceiverProof { receiver, proof });
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn wasm_fixture_verifies() {
+        super::check_fixture();
+    }
+
+    #[cfg(feature = "wasm")]
+    #[wasm_bindgen_test]
+    fn wasm_json_entry_point() {
+        let fixture = super::fixture();
+        let proof_json = serde_json::to_string(&fixture.receiver_proof).unwrap();
+        assert!(zkpay_lib::wasm::verify_receiver_proof_json(fixture.payments_root, &proof_json).unwrap());
+    }
+}